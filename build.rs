@@ -0,0 +1,8 @@
+// Generates the gRPC service from the in-tree .proto. protox compiles it in
+// Rust, so building needs no protoc.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/fedis.proto");
+    let descriptors = protox::compile(["proto/fedis.proto"], ["proto"])?;
+    tonic_prost_build::configure().compile_fds(descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+/// Heap bytes the allocator handed out and that are still in use; known for
+/// glibc's malloc only.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub fn allocated() -> Option<usize> {
+    // SAFETY: mallinfo2 only reads the allocator's statistics.
+    let info = unsafe { libc::mallinfo2() };
+    Some(info.uordblks + info.hblkhd)
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub fn allocated() -> Option<usize> {
+    None
+}
+
+/// The resident set size of the process; known on Linux only.
+#[cfg(target_os = "linux")]
+pub fn resident() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system constant.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * usize::try_from(page_size).ok()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn resident() -> Option<usize> {
+    None
+}
+
+/// Hands freed heap pages back to the system where the allocator supports
+/// it; a no-op elsewhere, as `MEMORY PURGE` is in Redis without jemalloc.
+pub fn trim() {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    // SAFETY: malloc_trim only releases free memory at the top of the heap
+    // and free pages inside it; it is safe to call at any time.
+    unsafe {
+        libc::malloc_trim(0);
+    }
+}
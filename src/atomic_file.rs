@@ -0,0 +1,66 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes written between fsyncs, like Redis' `rdb-save-incremental-fsync`, so a
+/// large file does not reach the disk in one burst at the end.
+const INCREMENTAL_FSYNC_BYTES: usize = 4 * 1024 * 1024;
+
+/// Streams a file into a temporary sibling and renames it over `path` on
+/// `commit`; readers never see a partial file. Dropping it uncommitted removes
+/// the temporary file.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    out: BufWriter<File>,
+    unsynced: usize,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// `tmp_extension` replaces the extension of `path` for the temporary file.
+    pub fn create(path: &Path, tmp_extension: &str) -> std::io::Result<Self> {
+        let tmp = path.with_extension(tmp_extension);
+        let out = BufWriter::new(File::create(&tmp)?);
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp,
+            out,
+            unsynced: 0,
+            committed: false,
+        })
+    }
+
+    pub fn commit(mut self) -> std::io::Result<()> {
+        self.out.flush()?;
+        self.out.get_ref().sync_all()?;
+        std::fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.unsynced += written;
+        if self.unsynced >= INCREMENTAL_FSYNC_BYTES {
+            self.out.flush()?;
+            self.out.get_ref().sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.tmp);
+        }
+    }
+}
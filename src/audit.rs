@@ -0,0 +1,118 @@
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+/// Security audit trail. Events always go to the `audit` tracing target and, when
+/// configured, are appended as JSON lines to a dedicated file that does not depend
+/// on the regular log level. The file is written on its own thread, so a slow
+/// disk never stalls the command being audited.
+pub struct AuditLog {
+    tx: Option<mpsc::Sender<AuditMsg>>,
+}
+
+enum AuditMsg {
+    Line(String),
+    /// Answered once every line queued before it is in the file.
+    Drained(oneshot::Sender<()>),
+}
+
+pub struct AuditEvent<'a> {
+    pub event: &'a str,
+    pub user: &'a str,
+    pub peer: &'a str,
+    pub outcome: &'a str,
+    pub detail: &'a str,
+}
+
+impl AuditLog {
+    pub fn open(path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(path) = path else {
+            return Ok(Self { tx: None });
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("fedis-audit".to_string())
+            .spawn(move || write_lines(file, rx))?;
+        Ok(Self { tx: Some(tx) })
+    }
+
+    pub fn record(&self, event: AuditEvent<'_>) {
+        info!(
+            target: "audit",
+            event = event.event,
+            user = event.user,
+            peer = event.peer,
+            outcome = event.outcome,
+            detail = event.detail,
+            "audit"
+        );
+
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let line = serde_json::json!({
+            "ts_ms": ts_ms,
+            "event": event.event,
+            "user": event.user,
+            "peer": event.peer,
+            "outcome": event.outcome,
+            "detail": event.detail,
+        });
+        let _ = tx.send(AuditMsg::Line(line.to_string()));
+    }
+
+    /// Waits until every event recorded so far is in the file, at shutdown.
+    pub async fn drain(&self) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let (done, drained) = oneshot::channel();
+        if tx.send(AuditMsg::Drained(done)).is_ok() {
+            let _ = drained.await;
+        }
+    }
+}
+
+/// The writer thread: appends lines as they come, flushing whenever the
+/// queue runs empty. It exits once the log is dropped.
+fn write_lines(file: std::fs::File, rx: mpsc::Receiver<AuditMsg>) {
+    let mut file = BufWriter::new(file);
+    let mut next = rx.recv().ok();
+    while let Some(msg) = next {
+        match msg {
+            AuditMsg::Line(line) => {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    warn!(error = %e, "audit log write failed");
+                }
+            }
+            AuditMsg::Drained(done) => {
+                let _ = file.flush();
+                let _ = done.send(());
+            }
+        }
+        next = match rx.try_recv() {
+            Ok(msg) => Some(msg),
+            Err(_) => {
+                if let Err(e) = file.flush() {
+                    warn!(error = %e, "audit log write failed");
+                }
+                rx.recv().ok()
+            }
+        };
+    }
+}
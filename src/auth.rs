@@ -1,40 +1,209 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::command::category_commands;
+use crate::jwt::JwtVerifier;
+use crate::lockout::{AuthLockout, LockoutPolicy};
+use crate::store::glob_match;
 
 #[derive(Clone)]
 pub struct Auth {
+    state: Arc<RwLock<AuthState>>,
+    acl_file: Option<PathBuf>,
+    log: Arc<Mutex<AclLog>>,
+    lockout: Arc<AuthLockout>,
+    tokens: Option<Arc<JwtVerifier>>,
+    /// Bumped on every change to the user table so sessions know to re-validate.
+    generation: Arc<AtomicU64>,
+    kill_deleted_sessions: bool,
+}
+
+const ACL_LOG_MAX_LEN: usize = 128;
+
+#[derive(Default)]
+struct AclLog {
+    entries: VecDeque<AclLogEntry>,
+    next_id: u64,
+}
+
+#[derive(Clone)]
+pub struct AclLogEntry {
+    pub entry_id: u64,
+    pub count: u64,
+    pub reason: &'static str,
+    pub object: String,
+    pub username: String,
+    pub client_info: String,
+    pub created_ms: u64,
+    pub updated_ms: u64,
+}
+
+struct AuthState {
     users: HashMap<String, User>,
     default_user: String,
 }
 
 #[derive(Clone)]
 pub struct User {
-    password: String,
+    /// SHA-256 of the password, if the user has one.
+    password_hash: Option<[u8; 32]>,
+    /// `nopass`: any password is accepted, as in Redis.
+    nopass: bool,
     enabled: bool,
+    /// Set by the fedis-specific `readonly` rule: write commands are refused with
+    /// `READONLY` even though the user may otherwise run them.
+    read_only: bool,
     permissions: Permissions,
+    /// Root key patterns. `None` means the user never had a key rule and keeps
+    /// unrestricted key access, which is what fedis did before key rules existed.
+    keys: Option<Vec<KeyPattern>>,
+    selectors: Vec<Selector>,
 }
 
 #[derive(Clone)]
 pub enum Permissions {
     All,
+    /// `+@all` followed by `-command` or `-@category` rules: every command
+    /// but these.
+    AllExcept(HashSet<String>),
     Commands(HashSet<String>),
 }
 
+#[derive(Clone, PartialEq, Eq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+/// A Redis 7 selector: an extra command + key rule set written as `(...)`. A command
+/// is allowed when the root rules or any one selector allows it with all its keys.
+#[derive(Clone)]
+struct Selector {
+    permissions: Permissions,
+    keys: Vec<KeyPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessDenied {
+    Command,
+    Key(Vec<u8>),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AuthError {
     NoPasswordConfigured,
     InvalidCredentials,
+    /// Too many recent failures from this client or for this user; the attempt
+    /// was refused without checking the password.
+    LockedOut(Duration),
+}
+
+/// Outcome of re-checking a session after the user table changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionCheck {
+    Valid,
+    /// The user was disabled or removed; the session is logged out.
+    Revoked,
+    /// The user was removed and the connection should be closed.
+    Kill,
 }
 
 impl Auth {
-    pub fn new(users: HashMap<String, User>, default_user: String) -> Self {
+    pub fn new(
+        users: HashMap<String, User>,
+        default_user: String,
+        acl_file: Option<PathBuf>,
+    ) -> Self {
         Self {
-            users,
-            default_user,
+            state: Arc::new(RwLock::new(AuthState {
+                users,
+                default_user,
+            })),
+            acl_file,
+            log: Arc::new(Mutex::new(AclLog::default())),
+            lockout: Arc::new(AuthLockout::new(None)),
+            tokens: None,
+            generation: Arc::new(AtomicU64::new(0)),
+            kill_deleted_sessions: false,
         }
     }
 
+    /// Closes connections whose user is deleted instead of only logging them out.
+    pub fn with_kill_deleted_sessions(mut self, enabled: bool) -> Self {
+        self.kill_deleted_sessions = enabled;
+        self
+    }
+
+    /// Lets AUTH accept signed bearer tokens in place of passwords.
+    pub fn with_token_verifier(mut self, verifier: Option<JwtVerifier>) -> Self {
+        self.tokens = verifier.filter(JwtVerifier::is_configured).map(Arc::new);
+        self
+    }
+
+    pub fn accepts_tokens(&self) -> bool {
+        self.tokens.is_some()
+    }
+
+    pub fn with_lockout(mut self, policy: Option<LockoutPolicy>) -> Self {
+        self.lockout = Arc::new(AuthLockout::new(policy));
+        self
+    }
+
+    pub fn lockout(&self) -> &AuthLockout {
+        &self.lockout
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, AuthState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, AuthState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn bump_generation(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Re-checks a logged-in session against the current user table. Sessions only
+    /// pay for the lookup when users changed since they were last checked.
+    pub fn revalidate(&self, session: &mut SessionAuth) -> SessionCheck {
+        let generation = self.generation.load(Ordering::Acquire);
+        if session.auth_generation == generation {
+            return SessionCheck::Valid;
+        }
+        session.auth_generation = generation;
+        // Tokens that carry their own rules do not depend on the ACL user.
+        if session.token.as_ref().is_some_and(|t| t.rules.is_some()) {
+            return SessionCheck::Valid;
+        }
+        let Some(user) = session.user.as_deref() else {
+            return SessionCheck::Valid;
+        };
+        let check = match self.read().users.get(user) {
+            Some(entry) if entry.enabled => return SessionCheck::Valid,
+            Some(_) => SessionCheck::Revoked,
+            None if self.kill_deleted_sessions => SessionCheck::Kill,
+            None => SessionCheck::Revoked,
+        };
+        session.user = None;
+        session.token = None;
+        check
+    }
+
     pub fn requires_auth(&self) -> bool {
-        self.users.values().any(|v| !v.password.is_empty())
+        self.tokens.is_some()
+            || self
+                .read()
+                .users
+                .values()
+                .any(|v| v.password_hash.is_some())
     }
 
     pub fn authenticate(
@@ -46,8 +215,9 @@ impl Auth {
             return Err(AuthError::NoPasswordConfigured);
         }
 
-        let user = username.unwrap_or(&self.default_user);
-        let Some(entry) = self.users.get(user) else {
+        let state = self.read();
+        let user = username.unwrap_or(&state.default_user);
+        let Some(entry) = state.users.get(user) else {
             return Err(AuthError::InvalidCredentials);
         };
 
@@ -55,61 +225,699 @@ impl Auth {
             return Err(AuthError::InvalidCredentials);
         }
 
-        if entry.password == password {
+        if entry.nopass
+            || entry
+                .password_hash
+                .is_some_and(|hash| constant_time_eq(&hash, &hash_password(password)))
+        {
             return Ok(user.to_string());
         }
 
         Err(AuthError::InvalidCredentials)
     }
 
-    pub fn can_execute(&self, user: Option<&str>, command: &str) -> bool {
-        if self.users.is_empty() {
-            return true;
+    /// Verifies a bearer token. Its `sub` claim names the user; an `acl` claim
+    /// carries the permissions, otherwise the subject must be an enabled ACL user.
+    pub fn authenticate_token(
+        &self,
+        username: Option<&str>,
+        token: &str,
+    ) -> Result<TokenGrant, AuthError> {
+        let verifier = self.tokens.as_ref().ok_or(AuthError::InvalidCredentials)?;
+        let claims = verifier
+            .verify(token, now_ms())
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        if username.is_some_and(|u| u != claims.subject) {
+            return Err(AuthError::InvalidCredentials);
         }
-
-        let subject = user.unwrap_or(&self.default_user);
-        let Some(entry) = self.users.get(subject) else {
-            return false;
+        let rules = match claims.acl {
+            Some(rules) => {
+                let mut user = User::reset();
+                user.enabled = true;
+                for rule in merge_selector_rules(rules.iter().map(String::as_str))
+                    .map_err(|_| AuthError::InvalidCredentials)?
+                {
+                    user.apply_rule(&rule)
+                        .map_err(|_| AuthError::InvalidCredentials)?;
+                }
+                Some(user)
+            }
+            None => {
+                let state = self.read();
+                if !state.users.get(&claims.subject).is_some_and(|u| u.enabled) {
+                    return Err(AuthError::InvalidCredentials);
+                }
+                None
+            }
         };
+        Ok(TokenGrant {
+            user: claims.subject,
+            expires_at_ms: claims.expires_at_ms,
+            rules,
+        })
+    }
 
-        if !entry.enabled {
-            return false;
+    /// Picks the first enabled ACL user among names taken from a verified client
+    /// certificate; the certificate stands in for the password.
+    pub fn authenticate_certificate(&self, names: &[String]) -> Option<String> {
+        let state = self.read();
+        names
+            .iter()
+            .find(|name| state.users.get(*name).is_some_and(|user| user.enabled))
+            .cloned()
+    }
+
+    /// Checks `command` and the keys it touches against the session user's root
+    /// rules and selectors. `write` selects whether keys need `%W` or `%R` access.
+    pub fn check_access(
+        &self,
+        session: &SessionAuth,
+        command: &str,
+        keys: &[&[u8]],
+        write: bool,
+    ) -> Result<(), AccessDenied> {
+        if let Some(user) = session.token.as_ref().and_then(|t| t.rules.as_ref()) {
+            return user.check_access(command, keys, write);
         }
 
-        match &entry.permissions {
-            Permissions::All => true,
-            Permissions::Commands(commands) => commands.contains(command),
+        let state = self.read();
+        if state.users.is_empty() {
+            return Ok(());
+        }
+
+        let subject = session.user.as_deref().unwrap_or(&state.default_user);
+        let Some(entry) = state.users.get(subject) else {
+            return Err(AccessDenied::Command);
+        };
+        entry.check_access(command, keys, write)
+    }
+
+    pub fn is_read_only(&self, session: &SessionAuth) -> bool {
+        if let Some(user) = session.token.as_ref().and_then(|t| t.rules.as_ref()) {
+            return user.read_only;
         }
+        let state = self.read();
+        let subject = session.user.as_deref().unwrap_or(&state.default_user);
+        state.users.get(subject).is_some_and(|u| u.read_only)
     }
 
-    pub fn default_user(&self) -> &str {
-        &self.default_user
+    pub fn default_user(&self) -> String {
+        self.read().default_user.clone()
     }
 
-    pub fn list_users(&self) -> Vec<&str> {
-        let mut out: Vec<&str> = self.users.keys().map(String::as_str).collect();
+    pub fn list_users(&self) -> Vec<String> {
+        let mut out: Vec<String> = self.read().users.keys().cloned().collect();
         out.sort_unstable();
         out
     }
+
+    /// Returns `user <name> <rules>` lines in the same shape `ACL LIST` and the ACL file use.
+    pub fn describe_users(&self) -> Vec<String> {
+        let state = self.read();
+        let mut names: Vec<&String> = state.users.keys().collect();
+        names.sort_unstable();
+        names
+            .into_iter()
+            .map(|name| format!("user {} {}", name, state.users[name].rules()))
+            .collect()
+    }
+
+    /// Returns the `ACL GETUSER` view of a user; passwords are only ever exposed as hashes.
+    pub fn user_info(&self, name: &str) -> Option<UserInfo> {
+        let state = self.read();
+        let user = state.users.get(name)?;
+        let mut flags = vec![if user.enabled { "on" } else { "off" }.to_string()];
+        if user.nopass {
+            flags.push("nopass".to_string());
+        }
+        if user.read_only {
+            flags.push("readonly".to_string());
+        }
+        Some(UserInfo {
+            flags,
+            passwords: user.password_hash.iter().map(|h| to_hex(h)).collect(),
+            commands: user.permissions.rules(),
+            keys: match &user.keys {
+                None => "~*".to_string(),
+                Some(keys) => key_rules(keys),
+            },
+            selectors: user
+                .selectors
+                .iter()
+                .map(|s| (s.permissions.rules(), key_rules(&s.keys)))
+                .collect(),
+        })
+    }
+
+    /// Applies ACL rules to `name`, creating the user (disabled, no commands) if missing.
+    /// Rules are validated before anything is changed.
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut state = self.write();
+        let mut user = state.users.get(name).cloned().unwrap_or_else(User::reset);
+        for rule in merge_selector_rules(rules.iter().map(String::as_str))? {
+            user.apply_rule(&rule)?;
+        }
+        state.users.insert(name.to_string(), user);
+        self.bump_generation();
+        Ok(())
+    }
+
+    pub fn delete_user(&self, name: &str) -> Result<bool, String> {
+        let mut state = self.write();
+        if name == state.default_user {
+            return Err(format!("The '{}' user cannot be removed", name));
+        }
+        let removed = state.users.remove(name).is_some();
+        if removed {
+            self.bump_generation();
+        }
+        Ok(removed)
+    }
+
+    /// Replaces the whole user table with the contents of the configured ACL file.
+    /// The current users are kept untouched if the file cannot be parsed.
+    pub fn load_acl_file(&self) -> Result<(), String> {
+        let Some(path) = &self.acl_file else {
+            return Err("This instance is not configured to use an ACL file".to_string());
+        };
+        let users = read_acl_file(path)?;
+        self.write().users = users;
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Records an auth or permission failure for `ACL LOG`. Repeats of the same
+    /// failure from the same client bump the existing entry instead of adding one.
+    pub fn log_failure(
+        &self,
+        reason: &'static str,
+        object: &str,
+        username: &str,
+        client_info: &str,
+    ) {
+        let now = now_ms();
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = log.entries.iter_mut().find(|e| {
+            e.reason == reason
+                && e.object == object
+                && e.username == username
+                && e.client_info == client_info
+        }) {
+            entry.count += 1;
+            entry.updated_ms = now;
+            return;
+        }
+        let entry_id = log.next_id;
+        log.next_id += 1;
+        log.entries.push_front(AclLogEntry {
+            entry_id,
+            count: 1,
+            reason,
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info: client_info.to_string(),
+            created_ms: now,
+            updated_ms: now,
+        });
+        log.entries.truncate(ACL_LOG_MAX_LEN);
+    }
+
+    /// Most recent entries first.
+    pub fn log_entries(&self, count: usize) -> Vec<AclLogEntry> {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn reset_log(&self) {
+        self.log
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .clear();
+    }
+
+    pub fn save_acl_file(&self) -> Result<(), String> {
+        let Some(path) = &self.acl_file else {
+            return Err("This instance is not configured to use an ACL file".to_string());
+        };
+        let mut contents = self.describe_users().join("\n");
+        contents.push('\n');
+        let tmp = path.with_extension("acl.tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("There was an error trying to save the ACLs: {}", e))
+    }
+}
+
+pub struct UserInfo {
+    pub flags: Vec<String>,
+    pub passwords: Vec<String>,
+    pub commands: String,
+    pub keys: String,
+    /// `(commands, keys)` for each selector.
+    pub selectors: Vec<(String, String)>,
 }
 
 impl User {
+    /// Builds a user from a plaintext password, which is hashed immediately; an empty
+    /// password means `nopass`.
     pub fn new(password: String, enabled: bool, permissions: Permissions) -> Self {
         Self {
-            password,
+            password_hash: (!password.is_empty()).then(|| hash_password(&password)),
+            nopass: password.is_empty(),
             enabled,
+            read_only: false,
             permissions,
+            keys: None,
+            selectors: Vec::new(),
+        }
+    }
+
+    /// A user as `ACL SETUSER` creates one: off, with no password and no
+    /// permissions, so nobody can log in as it yet.
+    fn reset() -> Self {
+        Self {
+            nopass: false,
+            ..Self::new(String::new(), false, Permissions::Commands(HashSet::new()))
+        }
+    }
+
+    fn check_access(&self, command: &str, keys: &[&[u8]], write: bool) -> Result<(), AccessDenied> {
+        if !self.enabled {
+            return Err(AccessDenied::Command);
+        }
+
+        let root = self.permissions.allows(command).then(|| match &self.keys {
+            None => None,
+            Some(patterns) => denied_key(patterns, keys, write),
+        });
+        let mut denied = match root {
+            Some(None) => return Ok(()),
+            Some(Some(key)) => Some(key),
+            None => None,
+        };
+        for selector in &self.selectors {
+            if !selector.permissions.allows(command) {
+                continue;
+            }
+            match denied_key(&selector.keys, keys, write) {
+                None => return Ok(()),
+                Some(key) => {
+                    denied.get_or_insert(key);
+                }
+            }
+        }
+        Err(denied.map_or(AccessDenied::Command, AccessDenied::Key))
+    }
+
+    /// Applies a single Redis-style ACL rule (`on`, `>pass`, `+get`, `~key:*`, `(...)`, ...).
+    pub fn apply_rule(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_ascii_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "readonly" => self.read_only = true,
+            "readwrite" => self.read_only = false,
+            "nopass" => {
+                self.password_hash = None;
+                self.nopass = true;
+            }
+            "resetpass" => {
+                self.password_hash = None;
+                self.nopass = false;
+            }
+            "clearselectors" => self.selectors.clear(),
+            "reset" => *self = Self::reset(),
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    self.password_hash = Some(hash_password(password));
+                    self.nopass = false;
+                } else if let Some(hex) = rule.strip_prefix('#') {
+                    let Some(hash) = parse_hash(hex) else {
+                        return Err(
+                            "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"
+                                .to_string(),
+                        );
+                    };
+                    self.password_hash = Some(hash);
+                    self.nopass = false;
+                } else if let Some(inner) = rule.strip_prefix('(') {
+                    let inner = inner.strip_suffix(')').ok_or_else(|| {
+                        format!(
+                            "Unmatched parenthesis in acl selector starting at '{}'",
+                            rule
+                        )
+                    })?;
+                    let mut selector = Selector {
+                        permissions: Permissions::Commands(HashSet::new()),
+                        keys: Vec::new(),
+                    };
+                    for selector_rule in inner.split_whitespace() {
+                        if !selector.permissions.apply_rule(selector_rule)?
+                            && !apply_key_rule(&mut selector.keys, selector_rule)?
+                        {
+                            return Err(format!(
+                                "Error in ACL SETUSER modifier '{}'",
+                                selector_rule
+                            ));
+                        }
+                    }
+                    self.selectors.push(selector);
+                } else if lower == "resetkeys" || lower == "allkeys" || rule.starts_with(['~', '%'])
+                {
+                    apply_key_rule(self.keys.get_or_insert_with(Vec::new), rule)?;
+                } else if !self.permissions.apply_rule(rule)? {
+                    return Err(format!("Error in ACL SETUSER modifier '{}'", rule));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rules(&self) -> String {
+        let mut out = vec![if self.enabled { "on" } else { "off" }.to_string()];
+        if self.nopass {
+            out.push("nopass".to_string());
+        } else if let Some(hash) = &self.password_hash {
+            out.push(format!("#{}", to_hex(hash)));
+        }
+        if self.read_only {
+            out.push("readonly".to_string());
+        }
+        out.push(self.permissions.rules());
+        if let Some(keys) = &self.keys {
+            out.push(key_rules(keys));
+        }
+        for selector in &self.selectors {
+            out.push(format!(
+                "({} {})",
+                key_rules(&selector.keys),
+                selector.permissions.rules()
+            ));
+        }
+        out.join(" ")
+    }
+}
+
+impl Permissions {
+    fn allows(&self, command: &str) -> bool {
+        match self {
+            Permissions::All => true,
+            Permissions::AllExcept(excluded) => !excluded.contains(command),
+            Permissions::Commands(commands) => commands.contains(command),
+        }
+    }
+
+    /// Applies a command rule, returning false when `rule` is not one.
+    fn apply_rule(&mut self, rule: &str) -> Result<bool, String> {
+        match rule.to_ascii_lowercase().as_str() {
+            "+@all" | "allcommands" => *self = Permissions::All,
+            "-@all" | "nocommands" => *self = Permissions::Commands(HashSet::new()),
+            "&*" | "allchannels" | "resetchannels" => {}
+            _ => {
+                if let Some(command) = rule.strip_prefix('+') {
+                    let names = command_names(command)?;
+                    match self {
+                        Permissions::All => {}
+                        Permissions::AllExcept(excluded) => {
+                            for name in &names {
+                                excluded.remove(name);
+                            }
+                            if excluded.is_empty() {
+                                *self = Permissions::All;
+                            }
+                        }
+                        Permissions::Commands(commands) => commands.extend(names),
+                    }
+                } else if let Some(command) = rule.strip_prefix('-') {
+                    let names = command_names(command)?;
+                    match self {
+                        Permissions::All => {
+                            *self = Permissions::AllExcept(names.into_iter().collect())
+                        }
+                        Permissions::AllExcept(excluded) => excluded.extend(names),
+                        Permissions::Commands(commands) => {
+                            for name in &names {
+                                commands.remove(name);
+                            }
+                        }
+                    }
+                } else {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn rules(&self) -> String {
+        match self {
+            Permissions::All => "+@all".to_string(),
+            Permissions::AllExcept(excluded) => {
+                let mut sorted: Vec<&String> = excluded.iter().collect();
+                sorted.sort_unstable();
+                let mut out = vec!["+@all".to_string()];
+                out.extend(sorted.into_iter().map(|c| format!("-{}", c.to_lowercase())));
+                out.join(" ")
+            }
+            Permissions::Commands(commands) => {
+                let mut sorted: Vec<&String> = commands.iter().collect();
+                sorted.sort_unstable();
+                let mut out = vec!["-@all".to_string()];
+                out.extend(sorted.into_iter().map(|c| format!("+{}", c.to_lowercase())));
+                out.join(" ")
+            }
+        }
+    }
+}
+
+/// The commands a `+`/`-` rule names: one command, or every command in an
+/// `@category`.
+fn command_names(rule: &str) -> Result<Vec<String>, String> {
+    let Some(category) = rule.strip_prefix('@') else {
+        return Ok(vec![rule.to_ascii_uppercase()]);
+    };
+    category_commands(&category.to_ascii_lowercase())
+        .map(|names| names.into_iter().map(str::to_string).collect())
+        .ok_or_else(|| format!("Unknown command category '{}'", rule))
+}
+
+/// Applies `~pattern`, `%R~pattern`, `%W~pattern`, `%RW~pattern`, `allkeys` or
+/// `resetkeys`, returning false when `rule` is not a key rule.
+fn apply_key_rule(keys: &mut Vec<KeyPattern>, rule: &str) -> Result<bool, String> {
+    if rule.eq_ignore_ascii_case("resetkeys") {
+        keys.clear();
+        return Ok(true);
+    }
+    let (read, write, pattern) = if rule.eq_ignore_ascii_case("allkeys") {
+        (true, true, "*")
+    } else if let Some(pattern) = rule.strip_prefix('~') {
+        (true, true, pattern)
+    } else if let Some(rest) = rule.strip_prefix('%') {
+        let (flags, pattern) = rest
+            .split_once('~')
+            .ok_or_else(|| format!("Error in ACL SETUSER modifier '{}'", rule))?;
+        let flags = flags.to_ascii_uppercase();
+        if flags.is_empty() || !flags.chars().all(|c| c == 'R' || c == 'W') {
+            return Err(format!("Error in ACL SETUSER modifier '{}'", rule));
         }
+        (flags.contains('R'), flags.contains('W'), pattern)
+    } else {
+        return Ok(false);
+    };
+    let pattern = KeyPattern {
+        pattern: pattern.to_string(),
+        read,
+        write,
+    };
+    if !keys.contains(&pattern) {
+        keys.push(pattern);
     }
+    Ok(true)
+}
+
+fn key_rules(keys: &[KeyPattern]) -> String {
+    if keys.is_empty() {
+        return "resetkeys".to_string();
+    }
+    keys.iter()
+        .map(|k| match (k.read, k.write) {
+            (true, true) => format!("~{}", k.pattern),
+            (true, false) => format!("%R~{}", k.pattern),
+            _ => format!("%W~{}", k.pattern),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the first key the patterns do not grant the needed access to.
+fn denied_key(patterns: &[KeyPattern], keys: &[&[u8]], write: bool) -> Option<Vec<u8>> {
+    keys.iter()
+        .find(|key| {
+            !patterns.iter().any(|p| {
+                (if write { p.write } else { p.read }) && glob_match(p.pattern.as_bytes(), key)
+            })
+        })
+        .map(|key| key.to_vec())
+}
+
+/// Joins rules split on whitespace back into whole `(...)` selectors, as Redis
+/// does for both `ACL SETUSER` arguments and ACL file lines.
+fn merge_selector_rules<'a>(rules: impl Iterator<Item = &'a str>) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    let mut open: Option<String> = None;
+    for rule in rules {
+        match open.as_mut() {
+            Some(selector) => {
+                selector.push(' ');
+                selector.push_str(rule);
+                if rule.ends_with(')') {
+                    out.extend(open.take());
+                }
+            }
+            None if rule.starts_with('(') && !rule.ends_with(')') => open = Some(rule.to_string()),
+            None => out.push(rule.to_string()),
+        }
+    }
+    if let Some(selector) = open {
+        return Err(format!(
+            "Unmatched parenthesis in acl selector starting at '{}'",
+            selector
+        ));
+    }
+    Ok(out)
+}
+
+/// Returns `bits` of OS randomness as lowercase hex, as `ACL GENPASS` does.
+pub fn generate_password(bits: usize) -> Result<String, String> {
+    let mut buf = vec![0_u8; bits.div_ceil(8)];
+    getrandom::fill(&mut buf).map_err(|e| e.to_string())?;
+    let mut hex = to_hex(&buf);
+    hex.truncate(bits.div_ceil(4));
+    Ok(hex)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn hash_password(password: &str) -> [u8; 32] {
+    Sha256::digest(password.as_bytes()).into()
+}
+
+fn parse_hash(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut out = [0_u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compares a presented secret with the configured one without leaking, by
+/// timing, how much of it matched.
+pub(crate) fn secret_matches(expected: &str, presented: &str) -> bool {
+    constant_time_eq(&hash_password(expected), &hash_password(presented))
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .fold(0_u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// Parses an ACL file made of `user <name> <rules...>` lines; blank lines and `#` comments are skipped.
+pub fn read_acl_file(path: &Path) -> Result<HashMap<String, User>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Error loading ACLs, opening file '{}': {}",
+            path.display(),
+            e
+        )
+    })?;
+    let mut users = HashMap::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some("user") {
+            return Err(format!("line {}: should start with user keyword", idx + 1));
+        }
+        let Some(name) = parts.next() else {
+            return Err(format!("line {}: missing user name", idx + 1));
+        };
+        let mut user = User::reset();
+        let rules = merge_selector_rules(parts).map_err(|e| format!("line {}: {}", idx + 1, e))?;
+        for rule in rules {
+            user.apply_rule(&rule)
+                .map_err(|e| format!("line {}: {}", idx + 1, e))?;
+        }
+        users.insert(name.to_string(), user);
+    }
+    Ok(users)
 }
 
 #[derive(Default, Clone)]
 pub struct SessionAuth {
     pub user: Option<String>,
     pub client_name: Option<String>,
+    pub connection_id: u64,
+    pub peer_addr: Option<String>,
+    /// Set when the session authenticated with a bearer token.
+    pub token: Option<TokenGrant>,
+    /// User-table generation this session was last validated against.
+    pub auth_generation: u64,
+    /// Port a replica announced with `REPLCONF listening-port` before PSYNC.
+    pub replica_port: Option<u16>,
+    /// Set by `ASKING`, for the next command only.
+    pub asking: bool,
+}
+
+#[derive(Clone)]
+pub struct TokenGrant {
+    pub user: String,
+    pub expires_at_ms: Option<u64>,
+    /// Permissions from the token's `acl` claim; `None` defers to the ACL user.
+    rules: Option<User>,
 }
 
 impl SessionAuth {
+    pub fn client_info(&self) -> String {
+        format!(
+            "id={} addr={} name={} user={}",
+            self.connection_id,
+            self.peer_addr.as_deref().unwrap_or("?"),
+            self.client_name.as_deref().unwrap_or(""),
+            self.user.as_deref().unwrap_or("default")
+        )
+    }
+
+    /// Drops a token login once the token has expired, so the client must AUTH again.
+    pub fn expire_token(&mut self) -> bool {
+        let expired = self
+            .token
+            .as_ref()
+            .and_then(|t| t.expires_at_ms)
+            .is_some_and(|exp| exp <= now_ms());
+        if expired {
+            self.token = None;
+            self.user = None;
+        }
+        expired
+    }
+
     pub fn is_authenticated(&self, auth: &Auth) -> bool {
         !auth.requires_auth() || self.user.is_some()
     }
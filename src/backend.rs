@@ -0,0 +1,711 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use serde_json::Value as JsonValue;
+
+use crate::encoding::ValueType;
+use crate::search::{IndexDefinition, Query, ShardIndex, ShardSearch};
+use crate::tier::{DEFAULT_HOT_BYTES, DEFAULT_VALUE_THRESHOLD, TierConfig};
+
+/// Which engine holds the keyspace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StorageEngine {
+    /// Everything in memory, made durable by the AOF and snapshots.
+    #[default]
+    Memory,
+    /// An embedded sled (LSM) database at the given directory; data may exceed RAM
+    /// and the database is its own persistence.
+    Sled(PathBuf),
+    /// In memory like `Memory`, but large and cold values are spilled to segment
+    /// files, so the dataset may exceed RAM while the AOF stays its persistence.
+    Tiered(TierConfig),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueEntry {
+    /// Shared with replies, the AOF and snapshots instead of copied into each.
+    /// A JSON key keeps its document's compact text here, which its AOF record
+    /// needed anyway, so whole-document reads and persistence never serialize.
+    /// A time series keeps its samples here too, laid out so that they are
+    /// read and appended in place (see `timeseries`).
+    pub value: Bytes,
+    pub kind: ValueType,
+    /// The parsed document of a JSON key; `None` for other kinds.
+    pub json: Option<Arc<JsonValue>>,
+    pub expires_at: Option<u64>,
+    /// Raised by every write to the key, for `OBJECT VERSION`; see
+    /// `IndexedShard`, which sets it. 0 until the entry is stored.
+    pub version: u64,
+}
+
+impl ValueEntry {
+    pub fn string(value: Bytes, expires_at: Option<u64>) -> Self {
+        Self {
+            value,
+            kind: ValueType::String,
+            json: None,
+            expires_at,
+            version: 0,
+        }
+    }
+
+    pub fn json(doc: JsonValue, expires_at: Option<u64>) -> Self {
+        let text = serde_json::to_vec(&doc).expect("JSON values always serialize");
+        Self {
+            value: text.into(),
+            kind: ValueType::Json,
+            json: Some(Arc::new(doc)),
+            expires_at,
+            version: 0,
+        }
+    }
+
+    pub fn series(value: Bytes, expires_at: Option<u64>) -> Self {
+        Self {
+            value,
+            kind: ValueType::TimeSeries,
+            json: None,
+            expires_at,
+            version: 0,
+        }
+    }
+
+    /// An entry from the kind and payload persistence stores for it. A JSON
+    /// payload is parsed here, once, when it is loaded.
+    pub fn decode(kind: ValueType, value: Bytes, expires_at: Option<u64>) -> Result<Self, String> {
+        match kind {
+            ValueType::String => Ok(Self::string(value, expires_at)),
+            ValueType::Json => {
+                let doc = serde_json::from_slice(&value)
+                    .map_err(|e| format!("invalid JSON document: {}", e))?;
+                Ok(Self {
+                    value,
+                    kind,
+                    json: Some(Arc::new(doc)),
+                    expires_at,
+                    version: 0,
+                })
+            }
+            ValueType::TimeSeries => {
+                crate::timeseries::validate(&value)?;
+                Ok(Self::series(value, expires_at))
+            }
+            other => Err(format!(
+                "{} values are not supported by this build",
+                other.name()
+            )),
+        }
+    }
+
+    pub fn kind(&self) -> ValueType {
+        self.kind
+    }
+}
+
+/// Persistent maps: cloning one is O(1) and shares structure until either side
+/// is written, so snapshots can serialize a frozen view while writes continue.
+pub type ShardMap = imbl::HashMap<Vec<u8>, ValueEntry>;
+
+/// Removed data handed to the lazyfree thread; dropping it frees the memory.
+pub type Garbage = Box<dyn Send>;
+
+/// One shard of the keyspace. The store serializes access to each shard with a
+/// lock and keeps expiry, conditional writes and logging above this layer.
+pub trait ShardBackend: Send + Sync {
+    /// Borrowed where the backend can lend the entry, owned where it decodes it.
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, ValueEntry>>;
+    /// Returns the entry the key held before, if any.
+    fn insert(&mut self, key: Vec<u8>, entry: ValueEntry) -> Option<ValueEntry>;
+    fn remove(&mut self, key: &[u8]) -> Option<ValueEntry>;
+    /// Changes the expiry and version of an existing key; false when the key
+    /// is missing.
+    fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>, version: u64) -> bool;
+    fn len(&self) -> usize;
+    fn clear(&mut self);
+    /// Empties the shard and returns what it held, so the caller can free it
+    /// elsewhere. A backend that does not hold its entries in memory clears
+    /// itself and returns nothing worth freeing.
+    fn detach(&mut self) -> Garbage;
+    fn for_each(&self, visit: &mut dyn FnMut(&[u8], &ValueEntry));
+    /// A point-in-time copy for snapshots and AOF rewrites. O(1) in memory; an
+    /// on-disk backend has to read every entry.
+    fn freeze(&self) -> ShardMap;
+}
+
+impl ShardBackend for ShardMap {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, ValueEntry>> {
+        imbl::HashMap::get(self, key).map(Cow::Borrowed)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: ValueEntry) -> Option<ValueEntry> {
+        imbl::HashMap::insert(self, key, entry)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<ValueEntry> {
+        imbl::HashMap::remove(self, key)
+    }
+
+    fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>, version: u64) -> bool {
+        match self.get_mut(key) {
+            Some(entry) => {
+                entry.expires_at = expires_at;
+                entry.version = version;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        imbl::HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        imbl::HashMap::clear(self);
+    }
+
+    fn detach(&mut self) -> Garbage {
+        Box::new(std::mem::take(self))
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&[u8], &ValueEntry)) {
+        for (key, entry) in self.iter() {
+            visit(key, entry);
+        }
+    }
+
+    fn freeze(&self) -> ShardMap {
+        self.clone()
+    }
+}
+
+/// A shard stored in its own sled tree as `expires_at i64 BE (-1: none) | value`.
+/// JSON documents mark the expiry instead: -2 when they have none, bit 62 set
+/// when they do, so trees written before JSON keys existed read unchanged.
+/// Entries written since keys have versions are prefixed with
+/// `SLED_VERSIONED | version u64 BE`; no expiry field starts with that byte, so
+/// older entries read as version 0.
+/// sled errors are I/O failures of the database itself; like a failed AOF write
+/// they are not recoverable here, so they abort.
+pub struct SledShard {
+    tree: sled::Tree,
+    /// `sled::Tree::len` walks the whole tree, so the count is kept here.
+    len: usize,
+}
+
+/// The expiry field of a JSON document without an expiry.
+const SLED_JSON_PERSISTENT: i64 = -2;
+/// Set in the expiry field of a JSON document with one.
+const SLED_JSON_FLAG: i64 = 1 << 62;
+/// The same two for time series.
+const SLED_SERIES_PERSISTENT: i64 = -3;
+const SLED_SERIES_FLAG: i64 = 1 << 61;
+/// The first byte of an entry that starts with its version.
+const SLED_VERSIONED: u8 = 0x01;
+
+impl SledShard {
+    pub fn open(db: &sled::Db, idx: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let tree = db.open_tree(format!("shard-{}", idx))?;
+        let len = tree.len();
+        Ok(Self { tree, len })
+    }
+
+    fn decode(bytes: &[u8]) -> ValueEntry {
+        let (version, bytes) = match bytes.split_first() {
+            Some((&SLED_VERSIONED, rest)) => {
+                let (version, rest) = rest.split_at(8);
+                let version = u64::from_be_bytes(version.try_into().expect("8-byte version"));
+                (version, rest)
+            }
+            _ => (0, bytes),
+        };
+        let (exp, value) = bytes.split_at(8);
+        let exp = i64::from_be_bytes(exp.try_into().expect("8-byte expiry"));
+        let (kind, expires_at) = match exp {
+            SLED_JSON_PERSISTENT => (ValueType::Json, None),
+            SLED_SERIES_PERSISTENT => (ValueType::TimeSeries, None),
+            exp if exp >= 0 && exp & SLED_JSON_FLAG != 0 => {
+                (ValueType::Json, Some((exp & !SLED_JSON_FLAG) as u64))
+            }
+            exp if exp >= 0 && exp & SLED_SERIES_FLAG != 0 => (
+                ValueType::TimeSeries,
+                Some((exp & !SLED_SERIES_FLAG) as u64),
+            ),
+            exp => (ValueType::String, (exp >= 0).then_some(exp as u64)),
+        };
+        let mut entry = ValueEntry::decode(kind, Bytes::copy_from_slice(value), expires_at)
+            .expect("sled holds a corrupt value");
+        entry.version = version;
+        entry
+    }
+
+    fn encode(entry: &ValueEntry) -> Vec<u8> {
+        let exp = match (entry.kind(), entry.expires_at) {
+            (ValueType::Json, None) => SLED_JSON_PERSISTENT,
+            (ValueType::Json, Some(at)) => at as i64 | SLED_JSON_FLAG,
+            (ValueType::TimeSeries, None) => SLED_SERIES_PERSISTENT,
+            (ValueType::TimeSeries, Some(at)) => at as i64 | SLED_SERIES_FLAG,
+            (_, expires_at) => expires_at.map(|v| v as i64).unwrap_or(-1),
+        };
+        let mut out = Vec::with_capacity(17 + entry.value.len());
+        out.push(SLED_VERSIONED);
+        out.extend_from_slice(&entry.version.to_be_bytes());
+        out.extend_from_slice(&exp.to_be_bytes());
+        out.extend_from_slice(&entry.value);
+        out
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (sled::IVec, ValueEntry)> + '_ {
+        self.tree
+            .iter()
+            .map(|item| item.expect("sled read failed"))
+            .map(|(key, bytes)| (key, Self::decode(&bytes)))
+    }
+}
+
+impl ShardBackend for SledShard {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, ValueEntry>> {
+        let bytes = self.tree.get(key).expect("sled read failed")?;
+        Some(Cow::Owned(Self::decode(&bytes)))
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: ValueEntry) -> Option<ValueEntry> {
+        let previous = self
+            .tree
+            .insert(key, Self::encode(&entry))
+            .expect("sled write failed");
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous.map(|bytes| Self::decode(&bytes))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<ValueEntry> {
+        let previous = self.tree.remove(key).expect("sled write failed")?;
+        self.len -= 1;
+        Some(Self::decode(&previous))
+    }
+
+    fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>, version: u64) -> bool {
+        let Some(mut entry) = ShardBackend::get(self, key).map(Cow::into_owned) else {
+            return false;
+        };
+        entry.expires_at = expires_at;
+        entry.version = version;
+        self.tree
+            .insert(key, Self::encode(&entry))
+            .expect("sled write failed");
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.tree.clear().expect("sled write failed");
+        self.len = 0;
+    }
+
+    fn detach(&mut self) -> Garbage {
+        self.clear();
+        Box::new(())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&[u8], &ValueEntry)) {
+        for (key, entry) in self.entries() {
+            visit(&key, &entry);
+        }
+    }
+
+    fn freeze(&self) -> ShardMap {
+        self.entries()
+            .map(|(key, entry)| (key.to_vec(), entry))
+            .collect()
+    }
+}
+
+/// Bookkeeping per key besides its bytes: the map entry holding the key and
+/// value handles, and the map's slot and hash for it.
+const ENTRY_OVERHEAD: usize =
+    std::mem::size_of::<(Vec<u8>, ValueEntry)>() + 2 * std::mem::size_of::<usize>();
+/// Bookkeeping per key with an expiry besides its two key copies: the
+/// deadline queue element, the lookup entry and its hash table slot.
+const EXPIRY_OVERHEAD: usize = std::mem::size_of::<(u64, Vec<u8>)>()
+    + std::mem::size_of::<(Vec<u8>, u64)>()
+    + std::mem::size_of::<usize>();
+
+/// What keys cost in memory, kept up to date on every write instead of
+/// recounted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Key and value bytes, rounded up to what the allocator hands out.
+    pub dataset: usize,
+    /// Map entries around them.
+    pub overhead: usize,
+    /// The expiry index.
+    pub expires: usize,
+}
+
+impl MemoryUsage {
+    /// The cost of one key, as charged by `IndexedShard`.
+    pub fn of(key: &[u8], entry: &ValueEntry) -> Self {
+        Self::sized(key.len(), entry)
+    }
+
+    fn sized(key_len: usize, entry: &ValueEntry) -> Self {
+        // A parsed JSON document is charged as much again as its text; walking
+        // it on every write to count its nodes would cost more than it tells.
+        let copies = if entry.json.is_some() { 2 } else { 1 };
+        Self {
+            dataset: allocated(key_len) + copies * allocated(entry.value.len()),
+            overhead: ENTRY_OVERHEAD,
+            expires: if entry.expires_at.is_some() {
+                expiry_cost(key_len)
+            } else {
+                0
+            },
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.dataset + self.overhead + self.expires
+    }
+
+    fn add(&mut self, other: Self) {
+        self.dataset += other.dataset;
+        self.overhead += other.overhead;
+        self.expires += other.expires;
+    }
+
+    fn sub(&mut self, other: Self) {
+        self.dataset -= other.dataset;
+        self.overhead -= other.overhead;
+        self.expires -= other.expires;
+    }
+}
+
+impl std::iter::Sum for MemoryUsage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |mut total, usage| {
+            total.add(usage);
+            total
+        })
+    }
+}
+
+fn expiry_cost(key_len: usize) -> usize {
+    2 * allocated(key_len) + EXPIRY_OVERHEAD
+}
+
+/// Allocators hand out memory in size classes; 16 bytes is the smallest step
+/// of glibc's malloc and jemalloc alike.
+fn allocated(len: usize) -> usize {
+    len.next_multiple_of(16)
+}
+
+/// The memory used by all shards of a store, and the most it ever was.
+#[derive(Debug, Default)]
+pub struct MemoryCounter {
+    used: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MemoryCounter {
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, bytes: usize) {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(used, Ordering::Relaxed);
+    }
+
+    fn sub(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A shard that also indexes its keys by expiry time, so active expiration
+/// pops only the keys that are due instead of walking or sampling the shard,
+/// accounts for the memory its keys take and keeps its part of the search
+/// indexes current. It also versions every write: inserts and expiry changes
+/// stamp the key with the next tick of the shard's clock, replacing whatever
+/// version the caller passed.
+pub struct IndexedShard {
+    backend: Box<dyn ShardBackend>,
+    volatile: ExpiryIndex,
+    usage: MemoryUsage,
+    counter: Arc<MemoryCounter>,
+    search: ShardSearch,
+    /// The last version handed out. Ticks follow the wall clock in
+    /// microseconds, so a key deleted and written again, or written again
+    /// after a restart that lost its version, still only moves forward.
+    clock: u64,
+}
+
+/// Keys with an expiry, ordered by deadline. `deadlines` finds a key's entry
+/// in `queue` when its expiry changes or it is removed.
+#[derive(Default)]
+struct ExpiryIndex {
+    queue: BTreeSet<(u64, Vec<u8>)>,
+    deadlines: HashMap<Vec<u8>, u64>,
+}
+
+impl ExpiryIndex {
+    fn set(&mut self, key: &[u8], expires_at: Option<u64>) {
+        let Some(at) = expires_at else {
+            self.remove(key);
+            return;
+        };
+        match self.deadlines.insert(key.to_vec(), at) {
+            Some(previous) if previous == at => return,
+            Some(previous) => {
+                self.queue.remove(&(previous, key.to_vec()));
+            }
+            None => {}
+        }
+        self.queue.insert((at, key.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(at) = self.deadlines.remove(key) {
+            self.queue.remove(&(at, key.to_vec()));
+        }
+    }
+
+    /// The key with the earliest deadline, if that deadline is `now` or before.
+    fn first_due(&self, now: u64) -> Option<&[u8]> {
+        self.queue
+            .first()
+            .filter(|(at, _)| *at <= now)
+            .map(|(_, key)| key.as_slice())
+    }
+}
+
+impl IndexedShard {
+    /// Indexes the backend's existing keys: free for a new map, one pass over
+    /// an opened sled tree. Their memory is added to `counter`, which the
+    /// store's shards share.
+    pub fn new(backend: Box<dyn ShardBackend>, counter: Arc<MemoryCounter>) -> Self {
+        let mut volatile = ExpiryIndex::default();
+        let mut usage = MemoryUsage::default();
+        let mut clock = 0;
+        backend.for_each(&mut |key, entry| {
+            volatile.set(key, entry.expires_at);
+            usage.add(MemoryUsage::of(key, entry));
+            clock = clock.max(entry.version);
+        });
+        counter.add(usage.total());
+        Self {
+            backend,
+            volatile,
+            usage,
+            counter,
+            search: ShardSearch::default(),
+            clock,
+        }
+    }
+
+    /// A number above every version this shard handed out so far; the next
+    /// write gets it or, if the clock moved on, a higher one.
+    pub fn next_version(&self) -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        (self.clock + 1).max(now)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock = self.next_version();
+        self.clock
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    fn charge(&mut self, usage: MemoryUsage) {
+        self.usage.add(usage);
+        self.counter.add(usage.total());
+    }
+
+    fn release(&mut self, usage: MemoryUsage) {
+        self.usage.sub(usage);
+        self.counter.sub(usage.total());
+    }
+
+    /// Removes up to `limit` keys whose expiry is `now` or earlier, earliest
+    /// first, and moves their values to `expired` so they can be freed after
+    /// the shard lock is released. Returns whether more keys are due.
+    pub fn expire_due(
+        &mut self,
+        now: u64,
+        limit: usize,
+        expired: &mut Vec<(Vec<u8>, Bytes)>,
+    ) -> bool {
+        let mut removed = 0;
+        while let Some(key) = self.volatile.first_due(now) {
+            if removed == limit {
+                return true;
+            }
+            let key = key.to_vec();
+            if let Some(entry) = self.remove(&key) {
+                expired.push((key, entry.value));
+            }
+            removed += 1;
+        }
+        false
+    }
+
+    /// Changes the expiry of an existing key and gives it a new version;
+    /// false when the key is missing.
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>) -> bool {
+        let version = self.tick();
+        if !self.backend.set_expiry(key, expires_at, version) {
+            return false;
+        }
+        let indexed = self.volatile.deadlines.contains_key(key);
+        self.volatile.set(key, expires_at);
+        let expires = MemoryUsage {
+            expires: expiry_cost(key.len()),
+            ..MemoryUsage::default()
+        };
+        match (indexed, expires_at.is_some()) {
+            (false, true) => self.charge(expires),
+            (true, false) => self.release(expires),
+            _ => {}
+        }
+        true
+    }
+
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.deadlines.len()
+    }
+
+    /// Files the documents already in the shard; writes keep it current.
+    pub fn add_search_index(&mut self, definition: Arc<IndexDefinition>) {
+        let mut index = ShardIndex::new(definition);
+        self.backend
+            .for_each(&mut |key, entry| index.insert(key, entry.json.as_deref()));
+        self.search.add(index);
+    }
+
+    pub fn drop_search_index(&mut self, name: &str) -> bool {
+        self.search.drop_index(name)
+    }
+
+    pub fn search_index(&self, name: &str) -> Option<Arc<IndexDefinition>> {
+        self.search.definition(name)
+    }
+
+    pub fn search_index_names(&self) -> Vec<String> {
+        self.search.names()
+    }
+
+    /// The documents of index `name` matching `query`, with their text;
+    /// documents past their expiry at `now` are left out.
+    pub fn search(&self, name: &str, query: &Query, now: u64) -> Vec<(Vec<u8>, Bytes)> {
+        let Some(keys) = self.search.matches(name, query) else {
+            return Vec::new();
+        };
+        keys.into_iter()
+            .filter_map(|key| {
+                let entry = self.backend.get(key)?;
+                entry
+                    .expires_at
+                    .is_none_or(|at| at > now)
+                    .then(|| (key.to_vec(), entry.value.clone()))
+            })
+            .collect()
+    }
+}
+
+impl ShardBackend for IndexedShard {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, ValueEntry>> {
+        self.backend.get(key)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, mut entry: ValueEntry) -> Option<ValueEntry> {
+        entry.version = self.tick();
+        self.volatile.set(&key, entry.expires_at);
+        self.search.insert(&key, entry.json.as_deref());
+        let key_len = key.len();
+        let usage = MemoryUsage::sized(key_len, &entry);
+        let previous = self.backend.insert(key, entry);
+        if let Some(previous) = &previous {
+            self.release(MemoryUsage::sized(key_len, previous));
+        }
+        self.charge(usage);
+        previous
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<ValueEntry> {
+        self.volatile.remove(key);
+        self.search.remove(key);
+        let previous = self.backend.remove(key)?;
+        self.release(MemoryUsage::of(key, &previous));
+        Some(previous)
+    }
+
+    fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>, _: u64) -> bool {
+        IndexedShard::set_expiry(self, key, expires_at)
+    }
+
+    fn len(&self) -> usize {
+        self.backend.len()
+    }
+
+    fn clear(&mut self) {
+        self.volatile = ExpiryIndex::default();
+        self.search.take();
+        self.backend.clear();
+        self.release(self.usage);
+    }
+
+    fn detach(&mut self) -> Garbage {
+        let volatile = std::mem::take(&mut self.volatile);
+        let search = self.search.take();
+        let entries = self.backend.detach();
+        self.release(self.usage);
+        Box::new((entries, volatile, search))
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&[u8], &ValueEntry)) {
+        self.backend.for_each(visit);
+    }
+
+    fn freeze(&self) -> ShardMap {
+        self.backend.freeze()
+    }
+}
+
+/// `path` overrides where an on-disk engine keeps its files, by default next to
+/// the AOF in `data_path`.
+pub fn parse_storage_engine(
+    value: Option<&str>,
+    path: Option<PathBuf>,
+    data_path: &Path,
+) -> Result<StorageEngine, Box<dyn std::error::Error>> {
+    match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+        None | Some("memory") => Ok(StorageEngine::Memory),
+        Some("sled") | Some("lsm") => Ok(StorageEngine::Sled(
+            path.unwrap_or_else(|| data_path.join("fedis.sled")),
+        )),
+        Some("tiered") => Ok(StorageEngine::Tiered(TierConfig {
+            path: path.unwrap_or_else(|| data_path.join("fedis.tier")),
+            value_threshold: DEFAULT_VALUE_THRESHOLD,
+            hot_bytes: DEFAULT_HOT_BYTES,
+        })),
+        _ => Err("FEDIS_STORAGE_ENGINE must be one of: memory, sled, tiered".into()),
+    }
+}
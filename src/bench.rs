@@ -0,0 +1,333 @@
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::protocol::{FrameReader, ReadLimits, RespValue, encode};
+
+const REPLY_LIMITS: ReadLimits = ReadLimits {
+    max_bulk_bytes: 512 * 1024 * 1024,
+    max_array_len: 1024 * 1024,
+    max_line_bytes: 64 * 1024,
+};
+
+const USAGE: &str = "usage: fedis bench [--addr host:port] [--clients n] [--requests n] \
+     [--pipeline n] [--value-size bytes] [--keyspace n] [--tests set,get,incr] \
+     [--user name] [--password secret]";
+
+/// A load run requested with `fedis bench` instead of starting the server,
+/// like `redis-benchmark`: every workload sends `requests` commands over
+/// `clients` connections, `pipeline` at a time, and reports throughput and
+/// latency percentiles.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Bench {
+    addr: String,
+    clients: usize,
+    requests: u64,
+    pipeline: usize,
+    value_size: usize,
+    keyspace: u64,
+    workloads: Vec<Workload>,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Workload {
+    Set,
+    Get,
+    Incr,
+}
+
+impl Workload {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Set => "SET",
+            Self::Get => "GET",
+            Self::Incr => "INCR",
+        }
+    }
+
+    fn encode_into(self, out: &mut Vec<u8>, key: u64, value: &Bytes) {
+        let key = format!("bench:{key}");
+        let args = match self {
+            Self::Set => vec![Bytes::from_static(b"SET"), key.into(), value.clone()],
+            Self::Get => vec![Bytes::from_static(b"GET"), key.into()],
+            // Counters get their own keys so INCR never hits a SET payload.
+            Self::Incr => vec![Bytes::from_static(b"INCR"), Bytes::from(format!("{key}:n"))],
+        };
+        out.extend(encode(RespValue::Array(
+            args.into_iter()
+                .map(|arg| RespValue::Bulk(Some(arg)))
+                .collect(),
+        )));
+    }
+}
+
+/// What one workload measured: each request's latency is the round trip of
+/// the pipeline batch it was sent in, as `redis-benchmark` counts it.
+struct Report {
+    workload: Workload,
+    elapsed: Duration,
+    latencies_us: Vec<u64>,
+    errors: u64,
+}
+
+impl Report {
+    fn percentile(&self, pct: f64) -> f64 {
+        if self.latencies_us.is_empty() {
+            return 0.0;
+        }
+        let rank = (pct / 100.0 * self.latencies_us.len() as f64).ceil() as usize;
+        self.latencies_us[rank.clamp(1, self.latencies_us.len()) - 1] as f64 / 1000.0
+    }
+
+    fn print(&self) {
+        let requests = self.latencies_us.len();
+        println!(
+            "{}: {} requests in {:.2}s, {:.0} requests/s, {} errors",
+            self.workload.name(),
+            requests,
+            self.elapsed.as_secs_f64(),
+            requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON),
+            self.errors
+        );
+        println!(
+            "  latency ms: p50 {:.3}  p95 {:.3}  p99 {:.3}  p99.9 {:.3}  max {:.3}",
+            self.percentile(50.0),
+            self.percentile(95.0),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.percentile(100.0)
+        );
+    }
+}
+
+/// What one connection measured.
+struct ClientRun {
+    latencies_us: Vec<u64>,
+    errors: u64,
+}
+
+impl Bench {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if args.first().map(String::as_str) != Some("bench") {
+            return Ok(None);
+        }
+        let mut bench = Self {
+            addr: "127.0.0.1:6379".to_string(),
+            clients: 50,
+            requests: 100_000,
+            pipeline: 1,
+            value_size: 3,
+            keyspace: 100_000,
+            workloads: vec![Workload::Set, Workload::Get, Workload::Incr],
+            user: None,
+            password: None,
+        };
+        let mut rest = args[1..].iter();
+        while let Some(flag) = rest.next() {
+            let value = rest.next().ok_or(USAGE)?;
+            let count = || match value.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(format!("{} needs a positive number", flag)),
+            };
+            match flag.as_str() {
+                "--addr" => bench.addr = value.clone(),
+                "--clients" => bench.clients = count()? as usize,
+                "--requests" => bench.requests = count()?,
+                "--pipeline" => bench.pipeline = count()? as usize,
+                "--value-size" => bench.value_size = count()? as usize,
+                "--keyspace" => bench.keyspace = count()?,
+                "--tests" => {
+                    bench.workloads = value
+                        .split(',')
+                        .map(|name| match name.to_ascii_lowercase().as_str() {
+                            "set" => Ok(Workload::Set),
+                            "get" => Ok(Workload::Get),
+                            "incr" => Ok(Workload::Incr),
+                            other => Err(format!("unknown bench test '{}'", other)),
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                "--user" => bench.user = Some(value.clone()),
+                "--password" => bench.password = Some(value.clone()),
+                _ => return Err(USAGE.into()),
+            }
+        }
+        Ok(Some(bench))
+    }
+
+    /// Runs every workload in turn and prints a report for each.
+    pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        println!(
+            "benchmarking {} with {} clients, pipeline {}, {}-byte values, {} keys",
+            self.addr, self.clients, self.pipeline, self.value_size, self.keyspace
+        );
+        for &workload in &self.workloads {
+            self.run_workload(workload).await?.print();
+        }
+        Ok(())
+    }
+
+    async fn run_workload(&self, workload: Workload) -> Result<Report, Box<dyn std::error::Error>> {
+        let value = Bytes::from(vec![b'x'; self.value_size]);
+        let clients = self.clients.min(self.requests as usize);
+        let mut connections = Vec::with_capacity(clients);
+        for _ in 0..clients {
+            connections.push(self.connect().await?);
+        }
+
+        let started = Instant::now();
+        let mut tasks = Vec::with_capacity(clients);
+        for (idx, (reader, writer)) in connections.into_iter().enumerate() {
+            // The first `requests % clients` connections send one extra.
+            let share = self.requests / clients as u64
+                + u64::from((idx as u64) < self.requests % clients as u64);
+            let first_key = idx as u64 * share;
+            let job = Job {
+                workload,
+                requests: share,
+                pipeline: self.pipeline as u64,
+                first_key,
+                keyspace: self.keyspace,
+                value: value.clone(),
+            };
+            tasks.push(tokio::spawn(job.drive(reader, writer)));
+        }
+        let mut report = Report {
+            workload,
+            elapsed: Duration::ZERO,
+            latencies_us: Vec::with_capacity(self.requests as usize),
+            errors: 0,
+        };
+        for task in tasks {
+            let run = task.await??;
+            report.latencies_us.extend(run.latencies_us);
+            report.errors += run.errors;
+        }
+        report.elapsed = started.elapsed();
+        report.latencies_us.sort_unstable();
+        Ok(report)
+    }
+
+    async fn connect(&self) -> Result<Connection, Box<dyn std::error::Error>> {
+        let stream = TcpStream::connect(&self.addr)
+            .await
+            .map_err(|e| format!("connecting to {}: {}", self.addr, e))?;
+        stream.set_nodelay(true)?;
+        let (read, mut write) = stream.into_split();
+        let mut reader = FrameReader::new(read, REPLY_LIMITS);
+        if let Some(password) = &self.password {
+            let mut args = vec![Bytes::from_static(b"AUTH")];
+            args.extend(self.user.clone().map(Bytes::from));
+            args.push(Bytes::from(password.clone()));
+            let auth =
+                RespValue::Array(args.into_iter().map(|a| RespValue::Bulk(Some(a))).collect());
+            write.write_all(&encode(auth)).await?;
+            match reader.read_frame().await? {
+                Some(RespValue::Simple(_)) => {}
+                Some(RespValue::Error(e)) => return Err(format!("AUTH failed: {}", e).into()),
+                _ => return Err("unexpected AUTH reply".into()),
+            }
+        }
+        Ok((reader, write))
+    }
+}
+
+type Connection = (FrameReader<OwnedReadHalf>, OwnedWriteHalf);
+
+/// One connection's share of a workload.
+struct Job {
+    workload: Workload,
+    requests: u64,
+    pipeline: u64,
+    first_key: u64,
+    keyspace: u64,
+    value: Bytes,
+}
+
+impl Job {
+    /// Sends the commands in pipelined batches and times each batch.
+    async fn drive(
+        self,
+        mut reader: FrameReader<OwnedReadHalf>,
+        mut writer: OwnedWriteHalf,
+    ) -> Result<ClientRun, String> {
+        let mut run = ClientRun {
+            latencies_us: Vec::with_capacity(self.requests as usize),
+            errors: 0,
+        };
+        let mut batch = Vec::new();
+        let mut sent = 0;
+        while sent < self.requests {
+            let size = (self.requests - sent).min(self.pipeline);
+            batch.clear();
+            for i in 0..size {
+                let key = (self.first_key + sent + i) % self.keyspace;
+                self.workload.encode_into(&mut batch, key, &self.value);
+            }
+            let started = Instant::now();
+            writer.write_all(&batch).await.map_err(|e| e.to_string())?;
+            for _ in 0..size {
+                match reader.read_frame().await.map_err(|e| e.to_string())? {
+                    Some(RespValue::Error(_)) => run.errors += 1,
+                    Some(_) => {}
+                    None => return Err("server closed the connection".to_string()),
+                }
+            }
+            let elapsed = started.elapsed().as_micros() as u64;
+            run.latencies_us
+                .extend(std::iter::repeat_n(elapsed, size as usize));
+            sent += size;
+        }
+        Ok(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_bench_options() {
+        assert_eq!(Bench::from_args(&args(&["127.0.0.1:6380"])).unwrap(), None);
+        let bench = Bench::from_args(&args(&[
+            "bench",
+            "--clients",
+            "4",
+            "--pipeline",
+            "16",
+            "--tests",
+            "get,INCR",
+        ]))
+        .unwrap()
+        .expect("bench mode");
+        assert_eq!(bench.clients, 4);
+        assert_eq!(bench.pipeline, 16);
+        assert_eq!(bench.workloads, vec![Workload::Get, Workload::Incr]);
+        assert!(Bench::from_args(&args(&["bench", "--clients", "0"])).is_err());
+        assert!(Bench::from_args(&args(&["bench", "--tests", "lpush"])).is_err());
+        assert!(Bench::from_args(&args(&["bench", "--pipeline"])).is_err());
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let report = Report {
+            workload: Workload::Get,
+            elapsed: Duration::from_secs(1),
+            latencies_us: (1..=100).map(|ms| ms * 1000).collect(),
+            errors: 0,
+        };
+        assert_eq!(report.percentile(50.0), 50.0);
+        assert_eq!(report.percentile(99.0), 99.0);
+        assert_eq!(report.percentile(100.0), 100.0);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+
+use crate::auth::{Auth, SessionAuth, generate_password};
+use crate::persistence::LogRecord;
+use crate::protocol::{FrameReader, RespValue, encode, frame_to_args};
+use crate::store::glob_match;
+
+/// Changes kept so that a follower that lost its connection can resume.
+const BACKLOG: usize = 65_536;
+/// Changes a follower may fall behind by before it is disconnected.
+const FOLLOWER_QUEUE: usize = 4096;
+
+/// One change to the keyspace, as `FOLLOW` pushes it.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ChangeEvent {
+    /// Position in the feed; the resume token names it.
+    seq: u64,
+    /// `set`, `del`, `expire`, `persist`, `ts.add`, `expired` or `flushall`.
+    op: &'static str,
+    /// Empty for `flushall`.
+    key: Bytes,
+    /// The key's TTL after the change in ms, as `PTTL` replies: -1 without
+    /// an expiry, -2 once the key is gone. `None` when the change leaves it
+    /// as it was.
+    ttl_ms: Option<i64>,
+}
+
+/// The keyspace changes of this node, in commit order, for `FOLLOW` in
+/// non_redis_mode: a push stream of what changed, without the values, for
+/// invalidating caches.
+#[derive(Clone)]
+pub(crate) struct ChangeFeed {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// Part of every resume token; a new one is chosen on every start, so
+    /// tokens from before a restart are refused rather than misread.
+    id: String,
+    /// Off until the first follower, so a server nobody follows pays nothing.
+    active: AtomicBool,
+    backlog: std::sync::Mutex<Backlog>,
+    tx: broadcast::Sender<Arc<ChangeEvent>>,
+}
+
+struct Backlog {
+    /// The `seq` of the latest change; 0 before the first.
+    last: u64,
+    events: VecDeque<Arc<ChangeEvent>>,
+}
+
+/// A `FOLLOW` a client sent, already following; the connection is handed
+/// to [`serve_follower`].
+pub struct FollowRequest {
+    pub(crate) pattern: Vec<u8>,
+    pub(crate) feed: ChangeFeed,
+    pub(crate) following: Following,
+    /// Changes to keys the user may not read are left out.
+    pub(crate) auth: Auth,
+    pub(crate) session: SessionAuth,
+}
+
+/// Where a follower starts: the change it resumes after, those it missed
+/// since, and the receiver for the rest.
+pub(crate) struct Following {
+    start: u64,
+    missed: Vec<Arc<ChangeEvent>>,
+    rx: broadcast::Receiver<Arc<ChangeEvent>>,
+}
+
+impl ChangeFeed {
+    pub(crate) fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            inner: Arc::new(Inner {
+                id: generate_password(64)?,
+                active: AtomicBool::new(false),
+                backlog: std::sync::Mutex::new(Backlog {
+                    last: 0,
+                    events: VecDeque::new(),
+                }),
+                tx: broadcast::channel(FOLLOWER_QUEUE).0,
+            }),
+        })
+    }
+
+    pub(crate) fn record(&self, record: &LogRecord) {
+        if !self.inner.active.load(Ordering::SeqCst) {
+            return;
+        }
+        let ttl = |expires_at: Option<u64>| match expires_at {
+            Some(at) => at.saturating_sub(now_ms()) as i64,
+            None => -1,
+        };
+        let (op, key, ttl_ms) = match record {
+            LogRecord::Set {
+                key, expires_at, ..
+            } => ("set", key, Some(ttl(*expires_at))),
+            LogRecord::Del { key } => ("del", key, Some(-2)),
+            LogRecord::Expire { key, expires_at } => ("expire", key, Some(ttl(Some(*expires_at)))),
+            LogRecord::Persist { key } => ("persist", key, Some(-1)),
+            LogRecord::TsAdd { key, .. } => ("ts.add", key, None),
+        };
+        self.append(op, key, ttl_ms);
+    }
+
+    /// A key removed because it expired; expiries are not log records.
+    pub(crate) fn expired(&self, key: &[u8]) {
+        if self.inner.active.load(Ordering::SeqCst) {
+            self.append("expired", key, Some(-2));
+        }
+    }
+
+    pub(crate) fn flushall(&self) {
+        if self.inner.active.load(Ordering::SeqCst) {
+            self.append("flushall", b"", Some(-2));
+        }
+    }
+
+    fn append(&self, op: &'static str, key: &[u8], ttl_ms: Option<i64>) {
+        let mut backlog = self.lock();
+        backlog.last += 1;
+        let event = Arc::new(ChangeEvent {
+            seq: backlog.last,
+            op,
+            key: Bytes::copy_from_slice(key),
+            ttl_ms,
+        });
+        if backlog.events.len() == BACKLOG {
+            backlog.events.pop_front();
+        }
+        backlog.events.push_back(event.clone());
+        // Sent under the lock so followers see changes in order.
+        let _ = self.inner.tx.send(event);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Backlog> {
+        self.inner.backlog.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn token(&self, seq: u64) -> String {
+        format!("{}-{}", self.inner.id, seq)
+    }
+
+    /// The change a token of this feed names; `None` for tokens of another
+    /// feed, such as one from before a restart.
+    pub(crate) fn parse_token(&self, token: &[u8]) -> Option<u64> {
+        let token = std::str::from_utf8(token).ok()?;
+        let (id, seq) = token.rsplit_once('-')?;
+        if id != self.inner.id {
+            return None;
+        }
+        seq.parse().ok()
+    }
+
+    /// Starts following after `from`, or after the latest change; `None`
+    /// when the backlog no longer reaches back to `from`.
+    pub(crate) fn follow(&self, from: Option<u64>) -> Option<Following> {
+        self.inner.active.store(true, Ordering::SeqCst);
+        let backlog = self.lock();
+        let start = from.unwrap_or(backlog.last);
+        let oldest = backlog
+            .events
+            .front()
+            .map_or(backlog.last + 1, |event| event.seq);
+        if start > backlog.last || start + 1 < oldest {
+            return None;
+        }
+        let missed = backlog
+            .events
+            .iter()
+            .filter(|event| event.seq > start)
+            .cloned()
+            .collect();
+        Some(Following {
+            start,
+            missed,
+            rx: self.inner.tx.subscribe(),
+        })
+    }
+}
+
+/// Runs a connection as a follower until the client goes away or sends
+/// QUIT: a `["follow", pattern, token]` confirmation, then a
+/// `["change", token, op, key, ttl]` push for every change to a key
+/// matching the pattern, starting with those after the `FROM` token.
+/// Falling too far behind ends it with an error naming the token to resume
+/// from.
+/// Only `PING` and `QUIT` are accepted meanwhile.
+pub async fn serve_follower<R, W>(
+    request: Box<FollowRequest>,
+    reader: &mut FrameReader<R>,
+    writer: &mut W,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let FollowRequest {
+        pattern,
+        feed,
+        following: Following {
+            start,
+            missed,
+            mut rx,
+        },
+        auth,
+        session,
+    } = *request;
+    let confirmation = RespValue::Array(vec![
+        RespValue::Bulk(Some(Bytes::from_static(b"follow"))),
+        RespValue::Bulk(Some(Bytes::from(pattern.clone()))),
+        RespValue::Bulk(Some(Bytes::from(feed.token(start)))),
+    ]);
+    writer.write_all(&encode(confirmation)).await?;
+    let wanted = |event: &ChangeEvent| {
+        event.op == "flushall"
+            || (glob_match(&pattern, &event.key)
+                && auth
+                    .check_access(&session, "FOLLOW", &[&event.key], false)
+                    .is_ok())
+    };
+    let mut last = start;
+    for event in missed {
+        last = event.seq;
+        if wanted(&event) {
+            writer.write_all(&encode(push(&feed, &event))).await?;
+        }
+    }
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(event) => {
+                    last = event.seq;
+                    if wanted(&event) {
+                        writer.write_all(&encode(push(&feed, &event))).await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let reply = RespValue::Error(format!(
+                        "ERR follower fell too far behind; resume from {}",
+                        feed.token(last)
+                    ));
+                    writer.write_all(&encode(reply)).await?;
+                    return Ok(());
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            // Cancel safe, as in serve_subscriber.
+            frame = async { reader.read_frame().await.map_err(|e| e.to_string()) } => {
+                let Some(frame) = frame? else {
+                    return Ok(());
+                };
+                let args = frame_to_args(frame)?;
+                let name = args
+                    .first()
+                    .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase())
+                    .unwrap_or_default();
+                let reply = match name.as_str() {
+                    "PING" => RespValue::Array(vec![
+                        RespValue::Bulk(Some(Bytes::from_static(b"pong"))),
+                        RespValue::Bulk(Some(args.get(1).cloned().unwrap_or_default().into())),
+                    ]),
+                    "QUIT" => {
+                        writer
+                            .write_all(&encode(RespValue::Simple("OK".to_string())))
+                            .await?;
+                        return Ok(());
+                    }
+                    _ => RespValue::Error(format!(
+                        "ERR Can't execute '{}': only PING / QUIT are allowed while following",
+                        name.to_lowercase()
+                    )),
+                };
+                writer.write_all(&encode(reply)).await?;
+            }
+        }
+    }
+}
+
+fn push(feed: &ChangeFeed, event: &ChangeEvent) -> RespValue {
+    RespValue::Array(vec![
+        RespValue::Bulk(Some(Bytes::from_static(b"change"))),
+        RespValue::Bulk(Some(Bytes::from(feed.token(event.seq)))),
+        RespValue::Bulk(Some(Bytes::from_static(event.op.as_bytes()))),
+        RespValue::Bulk(Some(event.key.clone())),
+        match event.ttl_ms {
+            Some(ttl) => RespValue::Integer(ttl),
+            None => RespValue::Bulk(None),
+        },
+    ])
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
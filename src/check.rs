@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+
+use crate::encryption::Keyring;
+use crate::persistence::Aof;
+use crate::store::check_snapshot;
+
+/// An offline inspection requested on the command line instead of starting the
+/// server, like `redis-check-aof` and `redis-check-rdb`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Check {
+    /// `--check-aof <path> [--fix]`
+    Aof { path: PathBuf, fix: bool },
+    /// `--check-snapshot <path>`
+    Snapshot { path: PathBuf },
+}
+
+impl Check {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let Some(mode) = args.first().filter(|arg| arg.starts_with("--check-")) else {
+            return Ok(None);
+        };
+        let path = args
+            .get(1)
+            .map(PathBuf::from)
+            .ok_or_else(|| format!("{} needs a file path", mode))?;
+        let rest = &args[2..];
+        let check = match mode.as_str() {
+            "--check-aof" => match rest {
+                [] => Self::Aof { path, fix: false },
+                [flag] if flag == "--fix" => Self::Aof { path, fix: true },
+                _ => return Err("usage: fedis --check-aof <path> [--fix]".into()),
+            },
+            "--check-snapshot" if rest.is_empty() => Self::Snapshot { path },
+            "--check-snapshot" => return Err("usage: fedis --check-snapshot <path>".into()),
+            other => return Err(format!("unknown option '{}'", other).into()),
+        };
+        Ok(Some(check))
+    }
+
+    /// Prints a report and returns the process exit code: 0 when the file is
+    /// valid (or was fixed), 1 when it is not.
+    pub fn run(&self, keyring: Option<&Keyring>) -> i32 {
+        let result = match self {
+            Self::Aof { path, fix } => check_aof(path, *fix, keyring),
+            Self::Snapshot { path } => check_snapshot(path, keyring).map(|report| {
+                println!(
+                    "snapshot {}: {} bytes, {} keys ({} with an expiry)",
+                    path.display(),
+                    report.file_len,
+                    report.entries,
+                    report.expiring
+                );
+                println!("snapshot is valid");
+                true
+            }),
+        };
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    }
+}
+
+fn check_aof(
+    path: &Path,
+    fix: bool,
+    keyring: Option<&Keyring>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let report = Aof::check(path, keyring)?;
+    println!(
+        "AOF {}: {} bytes, {} valid records, {} timestamp annotations",
+        path.display(),
+        report.file_len,
+        report.records,
+        report.timestamps
+    );
+    let Some((offset, reason)) = report.first_bad else {
+        println!("AOF is valid");
+        return Ok(true);
+    };
+    println!(
+        "first invalid record at offset {} ({}); {} bytes after it would be lost by truncation",
+        offset,
+        reason,
+        report.file_len - offset as u64
+    );
+    if !fix {
+        println!("run with --fix to truncate the file to its last valid record");
+        return Ok(false);
+    }
+    let backup = path.with_extension("aof.bak");
+    std::fs::copy(path, &backup)?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(offset as u64)?;
+    println!(
+        "truncated to {} bytes; the original was copied to {}",
+        offset,
+        backup.display()
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_check_modes() {
+        assert_eq!(Check::from_args(&args(&["127.0.0.1:6380"])).unwrap(), None);
+        assert_eq!(
+            Check::from_args(&args(&["--check-aof", "a.aof", "--fix"])).unwrap(),
+            Some(Check::Aof {
+                path: PathBuf::from("a.aof"),
+                fix: true
+            })
+        );
+        assert_eq!(
+            Check::from_args(&args(&["--check-snapshot", "s.snapshot"])).unwrap(),
+            Some(Check::Snapshot {
+                path: PathBuf::from("s.snapshot")
+            })
+        );
+        assert!(Check::from_args(&args(&["--check-aof"])).is_err());
+        assert!(Check::from_args(&args(&["--check-snapshot", "s", "--fix"])).is_err());
+    }
+}
@@ -0,0 +1,473 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+
+use crate::auth::Auth;
+use crate::backend::StorageEngine;
+use crate::config::Config;
+use crate::persistence::{AofFormat, AofFsync};
+use crate::runtime::RuntimeFlavor;
+use crate::tls::build_acceptor;
+use crate::write_behind::SinkTarget;
+
+/// Written in place of passwords and password hashes.
+const MASK: &str = "****";
+
+/// A configuration check requested with `fedis check-config` instead of
+/// starting the server, for deploy pipelines: the configuration is loaded
+/// as the server would load it, checked beyond what loading catches and
+/// printed with secrets masked.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheckConfig {
+    /// The listen address or `redis://` URL the server would be given.
+    args: Vec<String>,
+}
+
+/// What the checks found: problems fail the check, warnings do not.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Findings {
+    problems: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl CheckConfig {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if args.first().map(String::as_str) != Some("check-config") {
+            return Ok(None);
+        }
+        if args.len() > 2 {
+            return Err("usage: fedis check-config [address | redis://url]".into());
+        }
+        Ok(Some(Self {
+            args: args[1..].to_vec(),
+        }))
+    }
+
+    /// Prints the effective configuration and what is wrong with it, and
+    /// returns the process exit code: 0 when nothing is, 1 otherwise.
+    pub fn run(&self) -> i32 {
+        let config = match Config::from_env_with_args(&self.args) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("error: {}", e);
+                println!("configuration is invalid");
+                return 1;
+            }
+        };
+        for (name, value) in effective(&config) {
+            println!("{:<20} {}", name, value);
+        }
+        let findings = check(&config);
+        for warning in &findings.warnings {
+            println!("warning: {}", warning);
+        }
+        for problem in &findings.problems {
+            println!("error: {}", problem);
+        }
+        if findings.problems.is_empty() {
+            println!("configuration is valid");
+            0
+        } else {
+            println!("configuration is invalid");
+            1
+        }
+    }
+}
+
+/// The settings worth seeing before a rollout, resolved, as name and value.
+fn effective(config: &Config) -> Vec<(&'static str, String)> {
+    let off = || "off".to_string();
+    let path = |path: &Path| path.display().to_string();
+    let yes_no = |on: bool| if on { "yes" } else { "no" }.to_string();
+    let mut out = vec![
+        ("listen", config.listen_addr.clone()),
+        ("grpc", config.grpc_addr.clone().unwrap_or_else(off)),
+        ("metrics", config.metrics_addr.clone().unwrap_or_else(off)),
+        (
+            "tls",
+            config
+                .tls
+                .as_ref()
+                .map_or_else(off, |tls| path(&tls.cert_file)),
+        ),
+        (
+            "storage-engine",
+            match &config.storage_engine {
+                StorageEngine::Memory => "memory".to_string(),
+                StorageEngine::Sled(dir) => format!("sled {}", dir.display()),
+                StorageEngine::Tiered(tier) => format!("tiered {}", tier.path.display()),
+            },
+        ),
+        ("aof", path(&config.aof_path)),
+        (
+            "aof-fsync",
+            match config.aof_fsync {
+                AofFsync::Always => "always",
+                AofFsync::EverySec => "everysec",
+                AofFsync::No => "no",
+            }
+            .to_string(),
+        ),
+        (
+            "aof-format",
+            match config.aof_format {
+                AofFormat::Fedis => "fedis",
+                AofFormat::Resp => "redis",
+            }
+            .to_string(),
+        ),
+        (
+            "snapshot",
+            config.snapshot_path.as_deref().map_or_else(off, path),
+        ),
+        (
+            "rdb-export",
+            config.rdb_export_path.as_deref().map_or_else(off, path),
+        ),
+        ("export-dir", path(&config.export_dir)),
+        ("encryption", yes_no(config.encryption.is_some())),
+        ("read-only", yes_no(config.read_only)),
+        (
+            "replica-of",
+            config
+                .replica_of
+                .as_ref()
+                .map_or_else(off, |(host, port)| format!("{}:{}", host, port)),
+        ),
+        (
+            "master-auth",
+            config
+                .master_auth
+                .as_ref()
+                .map_or_else(off, |_| MASK.to_string()),
+        ),
+        (
+            "upstream",
+            config
+                .upstream
+                .as_ref()
+                .map_or_else(off, |upstream| upstream.source.addr.clone()),
+        ),
+        (
+            "write-behind",
+            match config.write_behind.as_ref().map(|sink| &sink.target) {
+                None => off(),
+                Some(SinkTarget::Postgres { target, table }) => {
+                    format!(
+                        "postgres {}/{} table {}",
+                        target.addr, target.database, table
+                    )
+                }
+                Some(SinkTarget::Kafka {
+                    bootstrap, topic, ..
+                }) => format!("kafka {} topic {}", bootstrap.join(","), topic),
+            },
+        ),
+        (
+            "expiry-webhook",
+            config.expiry_webhook.as_ref().map_or_else(off, |webhook| {
+                // The query may carry a token.
+                format!(
+                    "{}{} match {}",
+                    webhook.url.origin().ascii_serialization(),
+                    webhook.url.path(),
+                    webhook.pattern.as_deref().unwrap_or("*")
+                )
+            }),
+        ),
+        (
+            "soft-delete",
+            config.soft_delete_retention.map_or_else(off, |retention| {
+                format!("tombstones kept {}s", retention.as_secs())
+            }),
+        ),
+        (
+            "maxmemory",
+            config
+                .max_memory_bytes
+                .map_or_else(|| "unlimited".to_string(), |bytes| bytes.to_string()),
+        ),
+        ("maxclients", config.max_connections.to_string()),
+        (
+            "runtime",
+            match config.runtime.flavor {
+                RuntimeFlavor::MultiThread => {
+                    format!("multi_thread, {} workers", config.runtime.worker_threads)
+                }
+                RuntimeFlavor::CurrentThread => "current_thread".to_string(),
+            },
+        ),
+        (
+            "log-file",
+            config
+                .log_file
+                .as_ref()
+                .map_or_else(|| "stdout".to_string(), |log| path(&log.path)),
+        ),
+        ("default-user", config.default_user.clone()),
+    ];
+    let auth = Auth::new(
+        config.users.clone(),
+        config.default_user.clone(),
+        config.acl_file.clone(),
+    );
+    for user in auth.describe_users() {
+        out.push(("acl", mask_hashes(&user)));
+    }
+    out
+}
+
+/// ACL rules with each `#<sha256>` password hash masked.
+fn mask_hashes(rules: &str) -> String {
+    rules
+        .split(' ')
+        .map(|rule| {
+            if rule.starts_with('#') {
+                format!("#{}", MASK)
+            } else {
+                rule.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What loading the configuration does not catch: addresses, files and
+/// directories the server would fail on at startup, and settings that
+/// contradict each other.
+fn check(config: &Config) -> Findings {
+    let mut findings = Findings::default();
+
+    let mut listeners: Vec<(&str, SocketAddr)> = Vec::new();
+    for (name, addr) in [
+        ("FEDIS_LISTEN", Some(&config.listen_addr)),
+        ("FEDIS_GRPC_ADDR", config.grpc_addr.as_ref()),
+        ("FEDIS_METRICS_ADDR", config.metrics_addr.as_ref()),
+    ] {
+        let Some(addr) = addr else {
+            continue;
+        };
+        match addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(resolved)) => {
+                let clash = listeners.iter().find(|(_, other)| {
+                    other.port() == resolved.port()
+                        && resolved.port() != 0
+                        && (other.ip() == resolved.ip()
+                            || other.ip().is_unspecified()
+                            || resolved.ip().is_unspecified())
+                });
+                if let Some((other_name, _)) = clash {
+                    findings.problems.push(format!(
+                        "{} and {} both listen on port {}",
+                        other_name,
+                        name,
+                        resolved.port()
+                    ));
+                }
+                listeners.push((name, resolved));
+            }
+            _ => findings
+                .problems
+                .push(format!("{}: '{}' is not a host:port address", name, addr)),
+        }
+    }
+
+    let mut dirs = vec![
+        ("the AOF", config.aof_path.parent()),
+        ("FEDIS_EXPORT_DIR", Some(config.export_dir.as_path())),
+        (
+            "FEDIS_SNAPSHOT_PATH",
+            config.snapshot_path.as_deref().and_then(Path::parent),
+        ),
+        (
+            "FEDIS_RDB_EXPORT_PATH",
+            config.rdb_export_path.as_deref().and_then(Path::parent),
+        ),
+        (
+            "FEDIS_AUDIT_LOG",
+            config.audit_log_path.as_deref().and_then(Path::parent),
+        ),
+        (
+            "FEDIS_ACL_FILE",
+            config.acl_file.as_deref().and_then(Path::parent),
+        ),
+        (
+            "FEDIS_LOG_FILE",
+            config.log_file.as_ref().and_then(|log| log.path.parent()),
+        ),
+        ("FEDIS_WASM_PATH", config.wasm.path.as_deref()),
+    ];
+    match &config.storage_engine {
+        StorageEngine::Memory => {}
+        StorageEngine::Sled(dir) => dirs.push(("FEDIS_STORAGE_PATH", Some(dir.as_path()))),
+        StorageEngine::Tiered(tier) => {
+            dirs.push(("FEDIS_STORAGE_PATH", Some(tier.path.as_path())));
+        }
+    }
+    for (name, dir) in dirs {
+        let Some(dir) = dir else {
+            continue;
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        if let Err(e) = check_writable(dir) {
+            findings.problems.push(format!("{}: {}", name, e));
+        }
+    }
+
+    let files = [
+        ("the AOF", Some(config.aof_path.as_path())),
+        ("FEDIS_SNAPSHOT_PATH", config.snapshot_path.as_deref()),
+        ("FEDIS_RDB_EXPORT_PATH", config.rdb_export_path.as_deref()),
+    ];
+    for (idx, (name, file)) in files.iter().enumerate() {
+        for (other_name, other) in &files[idx + 1..] {
+            if file.is_some() && file == other {
+                findings
+                    .problems
+                    .push(format!("{} and {} are the same file", name, other_name));
+            }
+        }
+    }
+
+    for (name, tls) in [
+        ("FEDIS_TLS_CERT_FILE", &config.tls),
+        ("FEDIS_METRICS_TLS_CERT_FILE", &config.metrics_tls),
+    ] {
+        if let Some(Err(e)) = tls.as_ref().map(build_acceptor) {
+            findings.problems.push(format!("{}: {}", name, e));
+        }
+    }
+
+    if config.aof_format == AofFormat::Resp && config.encryption.is_some() {
+        findings.problems.push(
+            "FEDIS_AOF_FORMAT=redis cannot be combined with FEDIS_ENCRYPTION_KEY_FILE".to_string(),
+        );
+    }
+    if config.metrics_tls.is_some() && config.metrics_addr.is_none() {
+        findings
+            .warnings
+            .push("metrics TLS is configured but FEDIS_METRICS_ADDR is not set".to_string());
+    }
+    if config.jwt.is_some() && !config.non_redis_mode {
+        findings.warnings.push(
+            "JWT verification is configured but tokens are only accepted with \
+             FEDIS_NON_REDIS_MODE on"
+                .to_string(),
+        );
+    }
+    if config.expiry_webhook.is_some() && !config.non_redis_mode {
+        findings.warnings.push(
+            "the expiry webhook is configured but only posts with FEDIS_NON_REDIS_MODE on"
+                .to_string(),
+        );
+    }
+    if config.soft_delete_retention.is_some() && !config.non_redis_mode {
+        findings.warnings.push(
+            "soft delete is configured but DEL only keeps tombstones with FEDIS_NON_REDIS_MODE on"
+                .to_string(),
+        );
+    }
+
+    let auth = Auth::new(
+        config.users.clone(),
+        config.default_user.clone(),
+        config.acl_file.clone(),
+    );
+    if auth.requires_auth() {
+        for name in auth.list_users() {
+            let flags = auth.user_info(&name).map(|info| info.flags);
+            let flags = flags.unwrap_or_default();
+            if flags.iter().any(|f| f == "on") && flags.iter().any(|f| f == "nopass") {
+                findings
+                    .warnings
+                    .push(format!("user '{}' is enabled without a password", name));
+            }
+        }
+    } else if listeners
+        .first()
+        .is_some_and(|(_, addr)| !addr.ip().is_loopback())
+    {
+        findings.warnings.push(format!(
+            "no password is set and the server listens on {}",
+            config.listen_addr
+        ));
+    }
+    findings
+}
+
+/// Whether files can be created in `dir`, or in the closest existing
+/// directory above it that the server would create it in.
+fn check_writable(dir: &Path) -> Result<(), String> {
+    let existing = dir
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("."));
+    if !existing.is_dir() {
+        return Err(format!("{} is not a directory", existing.display()));
+    }
+    let probe = existing.join(format!(".fedis-check-config-{}", std::process::id()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(format!("{} is not writable: {}", existing.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_clashes_and_masks_secrets() {
+        let dir = std::env::temp_dir().join(format!("fedis-check-config-{}", std::process::id()));
+        let config = Config::builder()
+            .listen_addr("0.0.0.0:7379")
+            .data_path(&dir)
+            .password("secret")
+            .set("FEDIS_GRPC_ADDR", "not-an-address")
+            .set("FEDIS_METRICS_ADDR", "7379")
+            .set("FEDIS_MASTERAUTH", "upstream")
+            .set("FEDIS_USERS", "reader::+@read")
+            .build()
+            .expect("config");
+        let findings = check(&config);
+        assert_eq!(
+            findings.problems,
+            [
+                "FEDIS_GRPC_ADDR: 'not-an-address' is not a host:port address",
+                "FEDIS_LISTEN and FEDIS_METRICS_ADDR both listen on port 7379",
+            ]
+        );
+        assert_eq!(
+            findings.warnings,
+            ["user 'reader' is enabled without a password"]
+        );
+
+        let settings = effective(&config);
+        let value = |name: &str| {
+            settings
+                .iter()
+                .filter(|(setting, _)| *setting == name)
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(value("master-auth"), ["****"]);
+        assert!(value("acl")[0].starts_with("user default on #**** "));
+        let printed: String = settings.iter().map(|(_, value)| value.clone()).collect();
+        assert!(!printed.contains("secret") && !printed.contains("upstream"));
+
+        let config = Config::builder()
+            .listen_addr("127.0.0.1:7379")
+            .data_path(&dir)
+            .build()
+            .expect("config");
+        assert_eq!(check(&config), Findings::default());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
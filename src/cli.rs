@@ -0,0 +1,617 @@
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::config::Config;
+use crate::protocol::{RespValue, encode};
+use crate::server::{Server, ServerHandle};
+
+const USAGE: &str = "usage: fedis cli [--addr host:port | --in-process <data-dir>] \
+     [--user name] [--password secret] [command [arg ...]]";
+
+/// A prompt requested with `fedis cli` instead of starting the server, like
+/// `redis-cli`: commands are read with line editing and history, sent to a
+/// server over TCP or to one opened in-process on a data directory, and
+/// replies are printed the way `redis-cli` prints them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cli {
+    target: Target,
+    user: Option<String>,
+    password: Option<String>,
+    /// Run this one command and exit instead of prompting.
+    command: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Target {
+    Addr(String),
+    /// A server started in this process on the directory; nothing else may
+    /// have it open.
+    InProcess(PathBuf),
+}
+
+impl Cli {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if args.first().map(String::as_str) != Some("cli") {
+            return Ok(None);
+        }
+        let mut cli = Self {
+            target: Target::Addr("127.0.0.1:6379".to_string()),
+            user: None,
+            password: None,
+            command: Vec::new(),
+        };
+        let mut rest = args[1..].iter();
+        while let Some(flag) = rest.next() {
+            if !flag.starts_with("--") {
+                cli.command.push(flag.clone());
+                cli.command.extend(rest.cloned());
+                break;
+            }
+            let value = rest.next().ok_or(USAGE)?;
+            match flag.as_str() {
+                "--addr" => cli.target = Target::Addr(value.clone()),
+                "--in-process" => cli.target = Target::InProcess(PathBuf::from(value)),
+                "--user" => cli.user = Some(value.clone()),
+                "--password" => cli.password = Some(value.clone()),
+                _ => return Err(USAGE.into()),
+            }
+        }
+        Ok(Some(cli))
+    }
+
+    /// Runs the command given on the command line, or prompts until EOF or
+    /// `quit`.
+    pub fn run(&self, runtime: &Runtime) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut connection, server) = runtime.block_on(connect(
+            &self.target,
+            self.user.as_deref(),
+            self.password.as_deref(),
+        ))?;
+        let result = if self.command.is_empty() {
+            self.prompt(runtime, &mut connection)
+        } else {
+            let args: Vec<Vec<u8>> = self
+                .command
+                .iter()
+                .map(|a| a.clone().into_bytes())
+                .collect();
+            runtime
+                .block_on(execute(&mut connection, &args))
+                .map(|_| ())
+        };
+        if let Some(server) = server {
+            runtime.block_on(server.shutdown())?;
+        }
+        result
+    }
+
+    fn prompt(
+        &self,
+        runtime: &Runtime,
+        connection: &mut Connection,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let prompt = match &self.target {
+            Target::Addr(addr) => format!("{}> ", addr),
+            Target::InProcess(path) => format!("{}> ", path.display()),
+        };
+        let mut editor = DefaultEditor::new()?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // Missing on first use.
+            let _ = editor.load_history(path);
+        }
+        let result = loop {
+            let line = match editor.readline(&prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break Ok(()),
+                Err(e) => break Err(e.into()),
+            };
+            let args = match split_args(&line) {
+                Ok(args) if args.is_empty() => continue,
+                Ok(args) => args,
+                Err(e) => {
+                    println!("(error) {}", e);
+                    continue;
+                }
+            };
+            let _ = editor.add_history_entry(line.as_str());
+            let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+            if name == "quit" || name == "exit" {
+                break Ok(());
+            }
+            match runtime.block_on(execute(connection, &args)) {
+                Ok(false) => {}
+                Ok(true) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        if let Some(path) = &history {
+            let _ = editor.save_history(path);
+        }
+        result
+    }
+}
+
+/// Connects to `target` and logs in when given a password. An in-process
+/// server is returned with the connection, to be shut down when done.
+pub(crate) async fn connect(
+    target: &Target,
+    user: Option<&str>,
+    password: Option<&str>,
+) -> Result<(Connection, Option<ServerHandle>), Box<dyn std::error::Error>> {
+    let (mut connection, server) = match target {
+        Target::Addr(addr) => (Connection::open(addr).await?, None),
+        Target::InProcess(path) => {
+            // The environment configures the server as it would the binary,
+            // apart from the directory.
+            let builder = std::env::vars()
+                .filter(|(name, _)| name.starts_with("FEDIS_"))
+                .fold(Config::builder(), |builder, (name, value)| {
+                    builder.set(&name, value)
+                });
+            let config = builder.data_path(path).build()?;
+            let server = Server::new(config).await?.start_in_process();
+            (Connection::new(server.connect()), Some(server))
+        }
+    };
+    if let Some(password) = password {
+        connection.auth(user, password).await?;
+    }
+    Ok((connection, server))
+}
+
+/// Sends one command and prints its reply; after a subscription or
+/// `MONITOR`, keeps printing what arrives until Ctrl-C, and returns `true`
+/// as the connection is no use for other commands any more.
+async fn execute(
+    connection: &mut Connection,
+    args: &[Vec<u8>],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let reply = connection.call(args).await?;
+    print!("{}", format_reply(&reply));
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let streams = matches!(
+        name.as_str(),
+        "subscribe" | "psubscribe" | "ssubscribe" | "monitor"
+    );
+    if !streams || matches!(reply, Reply::Error(_)) {
+        return Ok(false);
+    }
+    println!("Reading messages... (press Ctrl-C to quit)");
+    loop {
+        tokio::select! {
+            reply = connection.read_reply() => print!("{}", format_reply(&reply?)),
+            _ = tokio::signal::ctrl_c() => return Ok(true),
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    match std::env::var_os("FEDIS_CLI_HISTFILE") {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".fedis_cli_history")),
+    }
+}
+
+/// One connection, over TCP or in-memory to an in-process server.
+pub(crate) struct Connection {
+    reader: Box<dyn AsyncRead + Unpin + Send>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    pub(crate) fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            buf: Vec::new(),
+        }
+    }
+
+    pub(crate) async fn open(addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| format!("connecting to {}: {}", addr, e))?;
+        Ok(Self::new(stream))
+    }
+
+    pub(crate) async fn auth(
+        &mut self,
+        user: Option<&str>,
+        password: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut args = vec![b"AUTH".to_vec()];
+        args.extend(user.map(|user| user.as_bytes().to_vec()));
+        args.push(password.as_bytes().to_vec());
+        match self.call(&args).await? {
+            Reply::Error(e) => Err(format!("AUTH failed: {}", e).into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) async fn call(
+        &mut self,
+        args: &[Vec<u8>],
+    ) -> Result<Reply, Box<dyn std::error::Error>> {
+        self.writer.write_all(&command(args)).await?;
+        self.read_reply().await
+    }
+
+    /// Sends the commands in one write and reads their replies in order.
+    pub(crate) async fn pipeline(
+        &mut self,
+        commands: &[Vec<Vec<u8>>],
+    ) -> Result<Vec<Reply>, Box<dyn std::error::Error>> {
+        let out: Vec<u8> = commands.iter().flat_map(|args| command(args)).collect();
+        self.writer.write_all(&out).await?;
+        let mut replies = Vec::with_capacity(commands.len());
+        for _ in commands {
+            replies.push(self.read_reply().await?);
+        }
+        Ok(replies)
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply, Box<dyn std::error::Error>> {
+        loop {
+            if let Some((reply, end)) = parse_reply(&self.buf, 0)? {
+                self.buf.drain(..end);
+                return Ok(reply);
+            }
+            let mut chunk = [0_u8; 4096];
+            let read = self.reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Err("server closed the connection".into());
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+fn command(args: &[Vec<u8>]) -> Vec<u8> {
+    encode(RespValue::Array(
+        args.iter()
+            .map(|arg| RespValue::Bulk(Some(arg.clone().into())))
+            .collect(),
+    ))
+}
+
+/// A reply in any RESP2 or RESP3 type, to print whatever server is on the
+/// other end.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Double(String),
+    Boolean(bool),
+    BigNumber(String),
+    Bulk(Vec<u8>),
+    Verbatim(String),
+    Null,
+    Array(Vec<Reply>),
+    Set(Vec<Reply>),
+    Push(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>),
+}
+
+/// The reply starting at `pos` and the position after it, or `None` while
+/// it is incomplete. Attributes are read and dropped.
+fn parse_reply(buf: &[u8], pos: usize) -> Result<Option<(Reply, usize)>, String> {
+    let Some(&kind) = buf.get(pos) else {
+        return Ok(None);
+    };
+    let Some(newline) = buf[pos..].windows(2).position(|w| w == b"\r\n") else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(&buf[pos + 1..pos + newline]).into_owned();
+    let mut next = pos + newline + 2;
+    let length = || {
+        line.parse::<i64>()
+            .map_err(|_| format!("invalid length '{}'", line))
+    };
+    let reply = match kind {
+        b'+' => Reply::Simple(line.clone()),
+        b'-' => Reply::Error(line.clone()),
+        b':' => Reply::Integer(length()?),
+        b',' => Reply::Double(line.clone()),
+        b'#' => Reply::Boolean(line == "t"),
+        b'(' => Reply::BigNumber(line.clone()),
+        b'_' => Reply::Null,
+        b'$' | b'=' | b'!' => match usize::try_from(length()?) {
+            Err(_) => Reply::Null,
+            Ok(len) => {
+                let Some(value) = buf.get(next..next + len + 2) else {
+                    return Ok(None);
+                };
+                if !value.ends_with(b"\r\n") {
+                    return Err("invalid bulk string ending".to_string());
+                }
+                next += len + 2;
+                let value = value[..len].to_vec();
+                match kind {
+                    b'$' => Reply::Bulk(value),
+                    b'!' => Reply::Error(String::from_utf8_lossy(&value).into_owned()),
+                    // Verbatim strings start with their format, e.g. `txt:`.
+                    _ => Reply::Verbatim(
+                        String::from_utf8_lossy(value.get(4..).unwrap_or_default()).into_owned(),
+                    ),
+                }
+            }
+        },
+        b'*' | b'~' | b'>' | b'%' | b'|' => {
+            let Ok(count) = usize::try_from(length()?) else {
+                return Ok(Some((Reply::Null, next)));
+            };
+            let elements = if matches!(kind, b'%' | b'|') {
+                count * 2
+            } else {
+                count
+            };
+            let mut items = Vec::with_capacity(elements);
+            for _ in 0..elements {
+                let Some((item, after)) = parse_reply(buf, next)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                next = after;
+            }
+            match kind {
+                b'*' => Reply::Array(items),
+                b'~' => Reply::Set(items),
+                b'>' => Reply::Push(items),
+                b'%' => {
+                    let mut items = items.into_iter();
+                    let mut entries = Vec::with_capacity(count);
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        entries.push((key, value));
+                    }
+                    Reply::Map(entries)
+                }
+                // An attribute annotates the reply that follows it.
+                _ => return parse_reply(buf, next),
+            }
+        }
+        other => return Err(format!("unknown reply type '{}'", other as char)),
+    };
+    Ok(Some((reply, next)))
+}
+
+/// Formats a reply like `redis-cli` on a terminal, one line per value with
+/// numbered, indented aggregates.
+fn format_reply(reply: &Reply) -> String {
+    let mut out = String::new();
+    write_reply(&mut out, reply, 0);
+    out
+}
+
+fn write_reply(out: &mut String, reply: &Reply, indent: usize) {
+    match reply {
+        Reply::Simple(text) | Reply::Verbatim(text) => out.push_str(text),
+        Reply::Error(e) => out.push_str(&format!("(error) {}", e)),
+        Reply::Integer(n) => out.push_str(&format!("(integer) {}", n)),
+        Reply::Double(d) => out.push_str(&format!("(double) {}", d)),
+        Reply::Boolean(b) => out.push_str(if *b { "(true)" } else { "(false)" }),
+        Reply::BigNumber(n) => out.push_str(&format!("(big number) {}", n)),
+        Reply::Bulk(value) => out.push_str(&quote(value)),
+        Reply::Null => out.push_str("(nil)"),
+        Reply::Array(items) | Reply::Set(items) | Reply::Push(items) => {
+            if items.is_empty() {
+                out.push_str(match reply {
+                    Reply::Set(_) => "(empty set)\n",
+                    _ => "(empty array)\n",
+                });
+            }
+            let marker = if matches!(reply, Reply::Set(_)) {
+                '~'
+            } else {
+                ')'
+            };
+            let width = items.len().to_string().len();
+            for (idx, item) in items.iter().enumerate() {
+                let prefix = format!("{:>width$}{} ", idx + 1, marker);
+                if idx > 0 {
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str(&prefix);
+                write_reply(out, item, indent + prefix.len());
+            }
+            return;
+        }
+        Reply::Map(entries) => {
+            if entries.is_empty() {
+                out.push_str("(empty hash)\n");
+            }
+            let width = entries.len().to_string().len();
+            for (idx, (key, value)) in entries.iter().enumerate() {
+                let prefix = format!("{:>width$}# ", idx + 1);
+                if idx > 0 {
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str(&prefix);
+                out.push_str(format_reply(key).trim_end());
+                out.push_str(" => ");
+                write_reply(out, value, indent + prefix.len());
+            }
+            return;
+        }
+    }
+    out.push('\n');
+}
+
+/// A bulk string in double quotes with `redis-cli`'s escapes.
+fn quote(value: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in value {
+        match byte {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Splits a line into arguments as `redis-cli` does: on whitespace, with
+/// double quotes that take `\n`, `\xff`-style escapes and single quotes that
+/// take none but `\'`.
+fn split_args(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    const UNBALANCED: &str = "Invalid argument(s)";
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+        let mut arg = Vec::new();
+        let mut utf8 = [0_u8; 4];
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => loop {
+                    match chars.next().ok_or(UNBALANCED)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or(UNBALANCED)? {
+                            'n' => arg.push(b'\n'),
+                            'r' => arg.push(b'\r'),
+                            't' => arg.push(b'\t'),
+                            'a' => arg.push(0x07),
+                            'b' => arg.push(0x08),
+                            'x' => {
+                                let hex: String = chars.by_ref().take(2).collect();
+                                let byte = u8::from_str_radix(&hex, 16)
+                                    .map_err(|_| UNBALANCED.to_string())?;
+                                arg.push(byte);
+                            }
+                            other => arg.extend(other.encode_utf8(&mut utf8).as_bytes()),
+                        },
+                        other => arg.extend(other.encode_utf8(&mut utf8).as_bytes()),
+                    }
+                },
+                '\'' => loop {
+                    match chars.next().ok_or(UNBALANCED)? {
+                        '\'' => break,
+                        '\\' if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            arg.push(b'\'');
+                        }
+                        other => arg.extend(other.encode_utf8(&mut utf8).as_bytes()),
+                    }
+                },
+                c if c.is_whitespace() => break,
+                other => {
+                    arg.extend(other.encode_utf8(&mut utf8).as_bytes());
+                    continue;
+                }
+            }
+            // A closing quote must end the argument.
+            if matches!(c, '"' | '\'') && chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err(UNBALANCED.to_string());
+            }
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_cli_options() {
+        assert_eq!(Cli::from_args(&args(&["bench"])).unwrap(), None);
+        let cli = Cli::from_args(&args(&["cli", "--password", "secret", "GET", "--addr"]))
+            .unwrap()
+            .expect("cli mode");
+        assert_eq!(cli.target, Target::Addr("127.0.0.1:6379".to_string()));
+        assert_eq!(cli.password.as_deref(), Some("secret"));
+        assert_eq!(cli.command, args(&["GET", "--addr"]));
+        let cli = Cli::from_args(&args(&["cli", "--in-process", "/var/lib/fedis"]))
+            .unwrap()
+            .expect("cli mode");
+        assert_eq!(
+            cli.target,
+            Target::InProcess(PathBuf::from("/var/lib/fedis"))
+        );
+        assert!(cli.command.is_empty());
+        assert!(Cli::from_args(&args(&["cli", "--addr"])).is_err());
+        assert!(Cli::from_args(&args(&["cli", "--port", "1"])).is_err());
+    }
+
+    #[test]
+    fn splits_lines_like_redis_cli() {
+        let split = |line: &str| split_args(line).unwrap();
+        assert_eq!(
+            split("  set  k v "),
+            vec![b"set".to_vec(), b"k".to_vec(), b"v".to_vec()]
+        );
+        assert_eq!(
+            split(r#"SET "a b" "\x00\n\"" 'it\'s'"#),
+            vec![
+                b"SET".to_vec(),
+                b"a b".to_vec(),
+                b"\x00\n\"".to_vec(),
+                b"it's".to_vec()
+            ]
+        );
+        assert_eq!(split(r#"GET """#), vec![b"GET".to_vec(), Vec::new()]);
+        assert!(split("").is_empty());
+        assert!(split_args(r#"GET "open"#).is_err());
+        assert!(split_args(r#"GET "a"b"#).is_err());
+    }
+
+    #[test]
+    fn parses_and_formats_resp3_replies() {
+        let reply = |raw: &[u8]| parse_reply(raw, 0).unwrap().expect("complete").0;
+        assert_eq!(parse_reply(b"$5\r\nhel", 0).unwrap(), None);
+        assert_eq!(format_reply(&reply(b"+OK\r\n")), "OK\n");
+        assert_eq!(format_reply(&reply(b"-ERR no\r\n")), "(error) ERR no\n");
+        assert_eq!(format_reply(&reply(b":7\r\n")), "(integer) 7\n");
+        assert_eq!(format_reply(&reply(b"$-1\r\n")), "(nil)\n");
+        assert_eq!(format_reply(&reply(b"_\r\n")), "(nil)\n");
+        assert_eq!(format_reply(&reply(b",1.5\r\n")), "(double) 1.5\n");
+        assert_eq!(format_reply(&reply(b"#t\r\n")), "(true)\n");
+        assert_eq!(
+            format_reply(&reply(b"$3\r\na\xff\"\r\n")),
+            "\"a\\xff\\\"\"\n"
+        );
+        assert_eq!(format_reply(&reply(b"=8\r\ntxt:line\r\n")), "line\n");
+        assert_eq!(format_reply(&reply(b"*0\r\n")), "(empty array)\n");
+        assert_eq!(
+            format_reply(&reply(b"*2\r\n$1\r\na\r\n*2\r\n:1\r\n:2\r\n")),
+            "1) \"a\"\n2) 1) (integer) 1\n   2) (integer) 2\n"
+        );
+        assert_eq!(
+            format_reply(&reply(b"%2\r\n$5\r\nproto\r\n:3\r\n$2\r\nid\r\n:9\r\n")),
+            "1# \"proto\" => (integer) 3\n2# \"id\" => (integer) 9\n"
+        );
+        assert_eq!(
+            format_reply(&reply(b"|1\r\n+ttl\r\n:1\r\n~1\r\n+x\r\n")),
+            "1~ x\n"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+/// The connections being served, so `CLIENT KILL` can find and close them.
+#[derive(Default)]
+pub(crate) struct Clients {
+    connected: Mutex<HashMap<u64, Connected>>,
+}
+
+struct Connected {
+    addr: Option<String>,
+    /// The ACL user the connection is logged in as; `None` for the default.
+    user: Option<String>,
+    kill: Arc<Notify>,
+}
+
+/// Picks the connections `CLIENT KILL` closes; every filter given must match.
+#[derive(Debug, Default)]
+pub(crate) struct KillFilter {
+    pub(crate) id: Option<u64>,
+    pub(crate) addr: Option<String>,
+    pub(crate) user: Option<String>,
+    /// The connection sending the command, unless it may kill itself.
+    pub(crate) skip: Option<u64>,
+}
+
+/// A connection's entry in `Clients`, removed when dropped.
+pub(crate) struct Registration<'a> {
+    clients: &'a Clients,
+    id: u64,
+    kill: Arc<Notify>,
+}
+
+impl Clients {
+    pub(crate) fn register(&self, id: u64, addr: Option<String>) -> Registration<'_> {
+        let kill = Arc::new(Notify::new());
+        self.lock().insert(
+            id,
+            Connected {
+                addr,
+                user: None,
+                kill: kill.clone(),
+            },
+        );
+        Registration {
+            clients: self,
+            id,
+            kill,
+        }
+    }
+
+    /// Closes the connections `filter` matches; returns how many.
+    pub(crate) fn kill(&self, filter: &KillFilter, default_user: &str) -> usize {
+        let connected = self.lock();
+        let mut killed = 0;
+        for (id, client) in connected.iter() {
+            let user = client.user.as_deref().unwrap_or(default_user);
+            if filter.skip == Some(*id)
+                || filter.id.is_some_and(|wanted| wanted != *id)
+                || filter
+                    .addr
+                    .as_ref()
+                    .is_some_and(|wanted| client.addr.as_ref() != Some(wanted))
+                || filter.user.as_deref().is_some_and(|wanted| wanted != user)
+            {
+                continue;
+            }
+            client.kill.notify_one();
+            killed += 1;
+        }
+        killed
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Connected>> {
+        self.connected.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Registration<'_> {
+    /// Records the user the connection logged in as, for `CLIENT KILL USER`.
+    pub(crate) fn set_user(&self, user: Option<&str>) {
+        if let Some(client) = self.clients.lock().get_mut(&self.id) {
+            client.user = user.map(str::to_string);
+        }
+    }
+
+    /// Resolves once `CLIENT KILL` picked this connection, even if that was
+    /// before the call.
+    pub(crate) async fn killed(&self) {
+        self.kill.notified().await;
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.clients.lock().remove(&self.id);
+    }
+}
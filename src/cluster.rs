@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+use std::sync::RwLock;
+
+use sha2::{Digest, Sha256};
+
+/// Hash slots the keyspace is split into, as in Redis Cluster.
+pub const SLOTS: u16 = 16384;
+
+/// A node as configured with `FEDIS_CLUSTER_NODES`: its address and the slots
+/// it starts out owning.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticNode {
+    pub host: String,
+    pub port: u16,
+    pub slots: Vec<RangeInclusive<u16>>,
+}
+
+/// Parses `host:port [slot|start-end ...]` entries separated by `;`, e.g.
+/// `10.0.0.1:6379 0-8191;10.0.0.2:6379 8192-16383`.
+pub fn parse_cluster_nodes(value: &str) -> Result<Vec<StaticNode>, Box<dyn std::error::Error>> {
+    let mut nodes = Vec::new();
+    for entry in value.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split_whitespace();
+        let address = parts.next().unwrap_or_default();
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| format!("cluster node '{address}' must be '<host>:<port>'"))?;
+        let port = port
+            .parse()
+            .map_err(|_| format!("invalid cluster node port '{port}'"))?;
+        let slots = parts
+            .map(|range| {
+                parse_slot_range(range).ok_or_else(|| format!("invalid slot range '{range}'"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        nodes.push(StaticNode {
+            host: host.to_string(),
+            port,
+            slots,
+        });
+    }
+    Ok(nodes)
+}
+
+pub fn parse_slot_range(range: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (parse_slot(start.as_bytes())?, parse_slot(end.as_bytes())?);
+    (start <= end).then_some(start..=end)
+}
+
+pub fn parse_slot(value: &[u8]) -> Option<u16> {
+    std::str::from_utf8(value)
+        .ok()?
+        .parse::<u16>()
+        .ok()
+        .filter(|slot| *slot < SLOTS)
+}
+
+/// The slot a key hashes to. Only the part inside the first non-empty `{...}`
+/// counts, so related keys can be kept on one node.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tagged = key.iter().position(|b| *b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        let close = rest.iter().position(|b| *b == b'}')?;
+        (close > 0).then(|| &rest[..close])
+    });
+    crc16(tagged.unwrap_or(key)) % SLOTS
+}
+
+/// CRC16/XMODEM, the checksum Redis Cluster hashes keys with.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0_u16;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Node IDs are derived from the announced address, so every node names its
+/// peers the same way without having to exchange IDs.
+pub fn node_id(host: &str, port: u16) -> String {
+    let digest = Sha256::digest(format!("{host}:{port}").as_bytes());
+    digest[..20].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClusterNode {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl ClusterNode {
+    fn new(host: &str, port: u16) -> Self {
+        Self {
+            id: node_id(host, port),
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Where a keyed command has to run.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Route {
+    Local,
+    /// The slot is owned by another node: `MOVED`.
+    Moved(u16, String),
+    /// The slot is moving to this node's `MIGRATING` target and the keys may
+    /// already be there: `ASK`.
+    Ask(u16, String),
+    CrossSlot,
+    Unassigned(u16),
+}
+
+/// The slot ownership table of a cluster-mode node. Membership is static: each
+/// node is configured with the same table, and `CLUSTER MEET`/`FORGET` and
+/// slot changes are applied to every node by the operator or cluster tool,
+/// since nodes do not gossip.
+pub struct Cluster {
+    myself: ClusterNode,
+    state: RwLock<State>,
+}
+
+struct State {
+    nodes: BTreeMap<String, ClusterNode>,
+    owners: Vec<Option<String>>,
+    /// Slots this node is handing to another node, by slot.
+    migrating: HashMap<u16, String>,
+    /// Slots this node is taking from another node, by slot.
+    importing: HashMap<u16, String>,
+}
+
+/// A slot change refused by [`Cluster`]; the message is sent as is.
+pub type ClusterError = String;
+
+impl Cluster {
+    /// `myself` is the address this node announces; it joins the configured
+    /// nodes if they do not list it.
+    pub fn new(myself: (String, u16), nodes: &[StaticNode]) -> Result<Self, ClusterError> {
+        let myself = ClusterNode::new(&myself.0, myself.1);
+        let mut state = State {
+            nodes: BTreeMap::from([(myself.id.clone(), myself.clone())]),
+            owners: vec![None; usize::from(SLOTS)],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        };
+        for node in nodes {
+            let member = ClusterNode::new(&node.host, node.port);
+            for slot in node.slots.iter().flat_map(Clone::clone) {
+                if state.owners[usize::from(slot)].is_some() {
+                    return Err(format!("slot {slot} is assigned to more than one node"));
+                }
+                state.owners[usize::from(slot)] = Some(member.id.clone());
+            }
+            state.nodes.insert(member.id.clone(), member);
+        }
+        Ok(Self {
+            myself,
+            state: RwLock::new(state),
+        })
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.myself
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, State> {
+        self.state.read().expect("cluster lock")
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, State> {
+        self.state.write().expect("cluster lock")
+    }
+
+    /// Routes a command by its keys. `missing` says whether any of them is
+    /// absent here, which only matters while the slot is migrating (see
+    /// [`Cluster::is_migrating`]); `asking` is set when the client sent
+    /// `ASKING` right before.
+    pub fn route(&self, keys: &[&[u8]], asking: bool, missing: bool) -> Route {
+        let Some(first) = keys.first() else {
+            return Route::Local;
+        };
+        let slot = key_slot(first);
+        if keys.iter().any(|key| key_slot(key) != slot) {
+            return Route::CrossSlot;
+        }
+        let state = self.read();
+        match state.owners[usize::from(slot)].as_deref() {
+            Some(owner) if owner == self.myself.id => match state.migrating.get(&slot) {
+                Some(target) if missing => Route::Ask(slot, state.nodes[target].address()),
+                _ => Route::Local,
+            },
+            _ if asking && state.importing.contains_key(&slot) => Route::Local,
+            Some(owner) => Route::Moved(slot, state.nodes[owner].address()),
+            None => Route::Unassigned(slot),
+        }
+    }
+
+    /// Whether the slot is migrating away; keyed commands then need to know
+    /// whether their keys are still here.
+    pub fn is_migrating(&self, slot: u16) -> bool {
+        self.read().migrating.contains_key(&slot)
+    }
+
+    pub fn nodes(&self) -> Vec<ClusterNode> {
+        self.read().nodes.values().cloned().collect()
+    }
+
+    /// Contiguous slot ranges and their owner, in slot order.
+    pub fn slot_ranges(&self) -> Vec<(u16, u16, ClusterNode)> {
+        let state = self.read();
+        let mut ranges: Vec<(u16, u16, ClusterNode)> = Vec::new();
+        for (slot, owner) in state.owners.iter().enumerate() {
+            let Some(owner) = owner else {
+                continue;
+            };
+            let slot = slot as u16;
+            match ranges.last_mut() {
+                Some((_, end, node)) if *end + 1 == slot && node.id == *owner => *end = slot,
+                _ => ranges.push((slot, slot, state.nodes[owner].clone())),
+            }
+        }
+        ranges
+    }
+
+    pub fn assigned_slots(&self) -> usize {
+        self.read().owners.iter().flatten().count()
+    }
+
+    /// `[slot->-id]` and `[slot-<-id]` markers for `CLUSTER NODES`.
+    pub fn open_slots(&self) -> Vec<String> {
+        let state = self.read();
+        let mut out: Vec<(u16, String)> = state
+            .migrating
+            .iter()
+            .map(|(slot, id)| (*slot, format!("[{slot}->-{id}]")))
+            .chain(
+                state
+                    .importing
+                    .iter()
+                    .map(|(slot, id)| (*slot, format!("[{slot}-<-{id}]"))),
+            )
+            .collect();
+        out.sort();
+        out.into_iter().map(|(_, marker)| marker).collect()
+    }
+
+    /// `CLUSTER MEET`: adds a node, without slots.
+    pub fn meet(&self, host: &str, port: u16) {
+        let node = ClusterNode::new(host, port);
+        self.write().nodes.insert(node.id.clone(), node);
+    }
+
+    /// `CLUSTER FORGET`: drops a node and unassigns its slots.
+    pub fn forget(&self, id: &str) -> Result<(), ClusterError> {
+        if id == self.myself.id {
+            return Err("ERR I tried hard but I can't forget myself...".to_string());
+        }
+        let mut state = self.write();
+        if state.nodes.remove(id).is_none() {
+            return Err(format!("ERR Unknown node {id}"));
+        }
+        for owner in &mut state.owners {
+            if owner.as_deref() == Some(id) {
+                *owner = None;
+            }
+        }
+        state.migrating.retain(|_, target| target != id);
+        state.importing.retain(|_, source| source != id);
+        Ok(())
+    }
+
+    /// `CLUSTER ADDSLOTS`: claims unassigned slots for this node, all or none.
+    pub fn add_slots(&self, slots: &[u16]) -> Result<(), ClusterError> {
+        let mut state = self.write();
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| state.owners[usize::from(**slot)].is_some())
+        {
+            return Err(format!("ERR Slot {slot} is already busy"));
+        }
+        for slot in slots {
+            state.owners[usize::from(*slot)] = Some(self.myself.id.clone());
+            state.importing.remove(slot);
+        }
+        Ok(())
+    }
+
+    /// `CLUSTER DELSLOTS`: unassigns slots, all or none.
+    pub fn del_slots(&self, slots: &[u16]) -> Result<(), ClusterError> {
+        let mut state = self.write();
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| state.owners[usize::from(**slot)].is_none())
+        {
+            return Err(format!("ERR Slot {slot} is already unassigned"));
+        }
+        for slot in slots {
+            state.owners[usize::from(*slot)] = None;
+            state.migrating.remove(slot);
+            state.importing.remove(slot);
+        }
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT <slot> MIGRATING <id>`.
+    pub fn set_migrating(&self, slot: u16, target: &str) -> Result<(), ClusterError> {
+        let mut state = self.write();
+        if state.owners[usize::from(slot)].as_deref() != Some(self.myself.id.as_str()) {
+            return Err(format!("ERR I'm not the owner of hash slot {slot}"));
+        }
+        state.known(target)?;
+        state.migrating.insert(slot, target.to_string());
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT <slot> IMPORTING <id>`.
+    pub fn set_importing(&self, slot: u16, source: &str) -> Result<(), ClusterError> {
+        let mut state = self.write();
+        if state.owners[usize::from(slot)].as_deref() == Some(self.myself.id.as_str()) {
+            return Err(format!("ERR I'm already the owner of hash slot {slot}"));
+        }
+        state.known(source)?;
+        state.importing.insert(slot, source.to_string());
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT <slot> STABLE`.
+    pub fn set_stable(&self, slot: u16) {
+        let mut state = self.write();
+        state.migrating.remove(&slot);
+        state.importing.remove(&slot);
+    }
+
+    /// `CLUSTER SETSLOT <slot> NODE <id>`: the end of a migration, sent to
+    /// every node.
+    pub fn set_owner(&self, slot: u16, owner: &str) -> Result<(), ClusterError> {
+        let mut state = self.write();
+        state.known(owner)?;
+        state.owners[usize::from(slot)] = Some(owner.to_string());
+        state.migrating.remove(&slot);
+        state.importing.remove(&slot);
+        Ok(())
+    }
+}
+
+impl State {
+    fn known(&self, id: &str) -> Result<(), ClusterError> {
+        if self.nodes.contains_key(id) {
+            Ok(())
+        } else {
+            Err(format!("ERR I don't know about node {id}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_hash_to_redis_cluster_slots() {
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"123456789"), 0x31c3 % SLOTS);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+    }
+
+    #[test]
+    fn routes_follow_slot_ownership_and_migrations() {
+        let nodes =
+            parse_cluster_nodes("127.0.0.1:7000 0-8191;127.0.0.1:7001 8192-16383").expect("nodes");
+        let cluster = Cluster::new(("127.0.0.1".to_string(), 7000), &nodes).expect("cluster");
+        let other = node_id("127.0.0.1", 7001);
+        let foo: &[u8] = b"foo"; // slot 12182
+        let bar: &[u8] = b"bar"; // slot 5061
+
+        assert_eq!(cluster.route(&[bar], false, true), Route::Local);
+        assert_eq!(
+            cluster.route(&[foo], false, true),
+            Route::Moved(12182, "127.0.0.1:7001".to_string())
+        );
+        assert_eq!(cluster.route(&[foo, bar], false, true), Route::CrossSlot);
+
+        cluster.set_migrating(5061, &other).expect("migrating");
+        assert_eq!(cluster.route(&[bar], false, false), Route::Local);
+        assert_eq!(
+            cluster.route(&[bar], false, true),
+            Route::Ask(5061, "127.0.0.1:7001".to_string())
+        );
+        assert!(cluster.set_importing(5061, &other).is_err());
+        cluster.set_owner(5061, &other).expect("hand over");
+        assert_eq!(
+            cluster.route(&[bar], false, false),
+            Route::Moved(5061, "127.0.0.1:7001".to_string())
+        );
+
+        cluster.set_importing(12182, &other).expect("importing");
+        assert_eq!(cluster.route(&[foo], true, true), Route::Local);
+        assert!(matches!(
+            cluster.route(&[foo], false, true),
+            Route::Moved(..)
+        ));
+
+        cluster.forget(&other).expect("forget");
+        assert_eq!(cluster.route(&[foo], false, true), Route::Unassigned(12182));
+        cluster.add_slots(&[12182]).expect("add");
+        assert_eq!(cluster.route(&[foo], false, true), Route::Local);
+        assert_eq!(
+            cluster.add_slots(&[12182]),
+            Err("ERR Slot 12182 is already busy".to_string())
+        );
+    }
+}
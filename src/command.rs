@@ -1,18 +1,50 @@
 mod auth_compat;
+mod cluster;
 mod expiry;
+pub mod extension;
+mod follow;
 mod info;
 mod json;
 mod keyspace;
+mod lock;
+mod memory;
+mod pubsub;
+mod registry;
+mod replication;
+mod search;
 mod strings;
+mod throttle;
+mod timeseries;
+mod upstream;
+mod wasm;
 
 #[cfg(test)]
 mod tests;
 
-use crate::auth::{Auth, SessionAuth};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::auth::{AccessDenied, Auth, SessionAuth, SessionCheck};
+use crate::changefeed::FollowRequest;
+use crate::clients::Clients;
+use crate::cluster::Cluster;
+use crate::migration::SlotMigrator;
+use crate::otel::Telemetry;
 use crate::protocol::RespValue;
+use crate::pubsub::PubSub;
+use crate::ratelimit::RateLimiter;
+use crate::replication::{PsyncRequest, ReplicationRole};
+use crate::runtime::RuntimeConfig;
+use crate::slowlog::SlowLog;
 use crate::stats::ServerStats;
 use crate::store::Store;
+use crate::upstream::{Upstream, UpstreamConfig};
+use crate::wasm::WasmRuntime;
+use bytes::Bytes;
+pub(crate) use registry::category_commands;
+use registry::{CommandSpec, NonRedis};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::sync::watch;
 
 pub struct CommandExecutor {
     auth: Auth,
@@ -20,11 +52,46 @@ pub struct CommandExecutor {
     stats: Arc<ServerStats>,
     listen_addr: String,
     max_memory_bytes: Option<u64>,
+    rate_limiter: RateLimiter,
+    audit: AuditLog,
+    read_only: AtomicBool,
+    /// `replica-read-only`: refuse client writes while following a master.
+    replica_read_only: AtomicBool,
+    /// Set while FAILOVER waits for its target: client writes wait for it to clear.
+    write_pause: watch::Sender<bool>,
+    replication: Option<ReplicationRole>,
+    pubsub: PubSub,
+    /// Set in cluster mode: keyed commands for other nodes' slots are redirected.
+    cluster: Option<Arc<Cluster>>,
+    migrator: SlotMigrator,
+    /// How the runtime serving commands was built, for `INFO server`.
+    runtime: RuntimeConfig,
+    slowlog: SlowLog,
+    /// OTLP export of a span per command, when configured.
+    telemetry: Option<Telemetry>,
+    /// `FEDIS_NON_REDIS_MODE`: enables commands plain Redis does not have.
+    non_redis_mode: bool,
+    /// `FEDIS_ADVERTISE_MODULES`: `MODULE LIST` shows the built-in modules.
+    advertise_modules: bool,
+    /// Loaded WASM modules, when scripting is set up.
+    wasm: Option<Arc<WasmRuntime>>,
+    /// `FEDIS_UPSTREAM_URL`: the Redis this server reads through and writes
+    /// through to.
+    upstream: Option<Upstream>,
+    /// The connections being served, for `CLIENT KILL`.
+    clients: Clients,
 }
 
 pub enum SessionAction {
     Continue,
     Close,
+    /// The client sent PSYNC: the connection becomes a replication link.
+    Replicate(Box<PsyncRequest>),
+    /// The client sent SUBSCRIBE or UNSUBSCRIBE (the command, as sent): the
+    /// connection runs in subscriber mode.
+    Subscribe(Vec<Vec<u8>>),
+    /// The client sent FOLLOW: the connection becomes a change feed.
+    Follow(Box<FollowRequest>),
 }
 
 impl CommandExecutor {
@@ -34,6 +101,8 @@ impl CommandExecutor {
         stats: Arc<ServerStats>,
         listen_addr: String,
         max_memory_bytes: Option<u64>,
+        rate_limiter: RateLimiter,
+        audit: AuditLog,
     ) -> Self {
         Self {
             auth,
@@ -41,9 +110,120 @@ impl CommandExecutor {
             stats,
             listen_addr,
             max_memory_bytes,
+            rate_limiter,
+            audit,
+            read_only: AtomicBool::new(false),
+            replica_read_only: AtomicBool::new(true),
+            write_pause: watch::Sender::new(false),
+            replication: None,
+            pubsub: PubSub::new(),
+            cluster: None,
+            migrator: SlotMigrator::default(),
+            runtime: RuntimeConfig::default(),
+            slowlog: SlowLog::default(),
+            telemetry: None,
+            non_redis_mode: false,
+            advertise_modules: false,
+            wasm: None,
+            upstream: None,
+            clients: Clients::default(),
         }
     }
 
+    /// Whether a replica refuses client writes (the default, like Redis'
+    /// `replica-read-only yes`); also `CONFIG SET replica-read-only`.
+    pub fn set_replica_read_only(&self, enabled: bool) {
+        self.replica_read_only.store(enabled, Ordering::Relaxed);
+    }
+
+    fn refuses_writes(&self, session: &SessionAuth) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+            || self.auth.is_read_only(session)
+            || (self.replica_read_only.load(Ordering::Relaxed)
+                && self
+                    .replication_role()
+                    .is_some_and(ReplicationRole::is_replica))
+    }
+
+    pub fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    pub(crate) fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    pub fn pause_writes(&self, paused: bool) {
+        self.write_pause.send_replace(paused);
+    }
+
+    /// Enables `REPLICAOF`; the role needs a handle back to this executor.
+    pub fn with_replication_role(mut self, role: ReplicationRole) -> Self {
+        self.replication = Some(role);
+        self
+    }
+
+    pub fn with_runtime(mut self, runtime: RuntimeConfig) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    pub fn with_slowlog(mut self, slowlog: SlowLog) -> Self {
+        self.slowlog = slowlog;
+        self
+    }
+
+    pub fn slow_log(&self) -> &SlowLog {
+        &self.slowlog
+    }
+
+    pub fn with_telemetry(mut self, telemetry: Option<Telemetry>) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    pub fn with_non_redis_mode(mut self, enabled: bool) -> Self {
+        self.non_redis_mode = enabled;
+        self
+    }
+
+    pub fn with_advertised_modules(mut self, enabled: bool) -> Self {
+        self.advertise_modules = enabled;
+        self
+    }
+
+    pub(crate) fn with_wasm(mut self, runtime: WasmRuntime) -> Self {
+        self.wasm = Some(Arc::new(runtime));
+        self
+    }
+
+    pub(crate) fn with_upstream(mut self, upstream: Option<UpstreamConfig>) -> Self {
+        self.upstream = upstream.map(Upstream::new);
+        self
+    }
+
+    pub fn telemetry(&self) -> Option<&Telemetry> {
+        self.telemetry.as_ref()
+    }
+
+    pub fn with_cluster(mut self, cluster: Cluster) -> Self {
+        self.cluster = Some(Arc::new(cluster));
+        self
+    }
+
+    pub fn replication_role(&self) -> Option<&ReplicationRole> {
+        self.replication.as_ref()
+    }
+
+    /// Server-wide read-only mode; also toggled at runtime with `CONFIG SET read-only`.
+    pub fn set_read_only(&self, enabled: bool) {
+        self.read_only.store(enabled, Ordering::Relaxed);
+    }
+
     pub async fn execute(
         &self,
         args: Vec<Vec<u8>>,
@@ -57,36 +237,112 @@ impl CommandExecutor {
         }
 
         let cmd = upper(&args[0]);
-        if cmd != "AUTH"
-            && cmd != "PING"
-            && cmd != "QUIT"
-            && cmd != "HELLO"
-            && !session.is_authenticated(&self.auth)
+        session.expire_token();
+        if self.auth.revalidate(session) == SessionCheck::Kill {
+            return (
+                RespValue::Error("ERR the ACL user of this connection was deleted".to_string()),
+                SessionAction::Close,
+            );
+        }
+        let Some(spec) = registry::lookup(&cmd) else {
+            return (
+                RespValue::Error(format!("ERR unknown command '{}'", cmd.to_lowercase())),
+                SessionAction::Continue,
+            );
+        };
+        if !self.non_redis_mode
+            && let Some(error) = spec.needs_non_redis(&args)
         {
+            return (error, SessionAction::Continue);
+        }
+        if !spec.accepts(args.len()) {
+            return (wrong_arity(&cmd), SessionAction::Continue);
+        }
+        let exempt = spec.has_flag("no_auth");
+        if !exempt && !session.is_authenticated(&self.auth) {
             return (
                 RespValue::Error("NOAUTH Authentication required.".to_string()),
                 SessionAction::Continue,
             );
         }
 
-        if cmd != "AUTH"
-            && cmd != "PING"
-            && cmd != "QUIT"
-            && cmd != "HELLO"
-            && !self.auth.can_execute(session.user.as_deref(), &cmd)
-        {
+        if !exempt {
+            let keys = spec.keys(&args);
+            if let Err(denied) = self
+                .auth
+                .check_access(session, &cmd, &keys, spec.is_write())
+            {
+                let username = session
+                    .user
+                    .clone()
+                    .unwrap_or_else(|| self.auth.default_user());
+                let (reason, object, message) = match denied {
+                    AccessDenied::Command => (
+                        "command",
+                        cmd.to_lowercase(),
+                        format!(
+                            "NOPERM this user has no permissions to run the '{}' command",
+                            cmd.to_lowercase()
+                        ),
+                    ),
+                    AccessDenied::Key(key) => (
+                        "key",
+                        String::from_utf8_lossy(&key).to_string(),
+                        "NOPERM No permissions to access a key".to_string(),
+                    ),
+                };
+                self.auth
+                    .log_failure(reason, &object, &username, &session.client_info());
+                let resp = RespValue::Error(message);
+                self.audit(&cmd, &args, session, &resp);
+                return (resp, SessionAction::Continue);
+            }
+        }
+
+        // `ASKING` only holds for the command right after it.
+        let asking = std::mem::take(&mut session.asking);
+        if let Some(redirect) = self.cluster_redirect(spec, &args, asking).await {
+            return (redirect, SessionAction::Continue);
+        }
+
+        if spec.is_write() && *self.write_pause.borrow() {
+            // Released once the failover is done or abandoned; by then this node
+            // may be a replica, which the check below answers for.
+            let _ = self
+                .write_pause
+                .subscribe()
+                .wait_for(|paused| !paused)
+                .await;
+        }
+
+        // Writes from the master arrive through `apply_replicated`, not here.
+        if spec.is_write() && self.refuses_writes(session) {
             return (
-                RespValue::Error(format!(
-                    "NOPERM this user has no permissions to run the '{}' command",
-                    cmd.to_lowercase()
-                )),
+                RespValue::Error(
+                    "READONLY You can't write against a read only replica.".to_string(),
+                ),
                 SessionAction::Continue,
             );
         }
 
-        if self.max_memory_bytes.is_some() && is_memory_growing_command(&cmd) {
+        if !exempt {
+            let user = session
+                .user
+                .clone()
+                .unwrap_or_else(|| self.auth.default_user());
+            let request_bytes = args.iter().map(Vec::len).sum();
+            if !self.rate_limiter.try_acquire(&user, request_bytes) {
+                self.stats.record_rate_limited(&user);
+                return (
+                    RespValue::Error(format!("THROTTLED rate limit exceeded for user '{}'", user)),
+                    SessionAction::Continue,
+                );
+            }
+        }
+
+        if self.max_memory_bytes.is_some() && spec.has_flag("denyoom") {
             let limit = self.max_memory_bytes.unwrap_or(u64::MAX) as usize;
-            let used = self.store.metrics().await.approx_memory_bytes;
+            let used = self.store.used_memory();
             if used >= limit {
                 return (
                     RespValue::Error(
@@ -97,97 +353,157 @@ impl CommandExecutor {
             }
         }
 
-        match cmd.as_str() {
-            "PING" => self.ping(&args),
-            "ECHO" => self.echo(&args),
-            "TIME" => self.time(&args),
-            "AUTH" => self.auth_cmd(&args, session),
-            "HELLO" => self.hello(&args, session),
-            "CLIENT" => self.client(&args, session).await,
-            "ACL" => self.acl(&args, session),
-            "MODULE" => self.module_cmd(&args),
-            "COMMAND" => self.command_meta(&args),
-            "CONFIG" => self.config_cmd(&args),
-            "LATENCY" => self.latency(&args),
-            "SLOWLOG" => self.slowlog(&args),
-            "BGSAVE" => self.bgsave(&args).await,
-            "SAVE" => self.save(&args).await,
-            "LASTSAVE" => self.lastsave(&args),
-            "BGREWRITEAOF" => self.bgrewriteaof(&args).await,
-            "GET" => self.get(&args).await,
-            "JSON.SET" => self.json_set(&args).await,
-            "JSON.GET" => self.json_get(&args).await,
-            "JSON.DEL" => self.json_del(&args).await,
-            "JSON.TYPE" => self.json_type(&args).await,
-            "GETDEL" => self.getdel(&args).await,
-            "GETEX" => self.getex(&args).await,
-            "GETSET" => self.getset(&args).await,
-            "MGET" => self.mget(&args).await,
-            "GETRANGE" => self.getrange(&args).await,
-            "SET" => self.set(&args).await,
-            "SETRANGE" => self.setrange(&args).await,
-            "SETNX" => self.setnx(&args).await,
-            "SETEX" => self.setex(&args).await,
-            "PSETEX" => self.psetex(&args).await,
-            "UPDATE" => self.update(&args).await,
-            "MSET" => self.mset(&args).await,
-            "MSETNX" => self.msetnx(&args).await,
-            "INCR" => self.incr(&args).await,
-            "DECR" => self.decr(&args).await,
-            "INCRBY" => self.incrby(&args).await,
-            "DECRBY" => self.decrby(&args).await,
-            "DEL" => self.del(&args).await,
-            "UNLINK" => self.unlink(&args).await,
-            "DBSIZE" => self.dbsize(&args).await,
-            "KEYS" => self.keys(&args).await,
-            "SCAN" => self.scan(&args).await,
-            "TYPE" => self.key_type(&args).await,
-            "EXISTS" => self.exists(&args).await,
-            "EXPIRE" => self.expire(&args).await,
-            "PEXPIRE" => self.pexpire(&args).await,
-            "EXPIREAT" => self.expireat(&args).await,
-            "PEXPIREAT" => self.pexpireat(&args).await,
-            "PERSIST" => self.persist(&args).await,
-            "TTL" => self.ttl(&args).await,
-            "PTTL" => self.pttl(&args).await,
-            "MEMORY" => self.memory(&args).await,
-            "OBJECT" => self.object(&args).await,
-            "INFO" => self.info(&args).await,
-            "SELECT" => self.select(&args),
-            "QUIT" => (RespValue::Simple("OK".to_string()), SessionAction::Close),
-            "STRLEN" => self.strlen(&args).await,
-            "APPEND" => self.append(&args).await,
-            _ => (
-                RespValue::Error(format!("ERR unknown command '{}'", cmd.to_lowercase())),
-                SessionAction::Continue,
+        let started = Instant::now();
+        let result = match self.through_upstream(spec, &args).await {
+            Some(reply) => (reply, SessionAction::Continue),
+            None => (spec.handler)(self, &args, session).await,
+        };
+        let elapsed = started.elapsed();
+        if self.slowlog.is_slow(elapsed) {
+            let user = session
+                .user
+                .clone()
+                .unwrap_or_else(|| self.auth.default_user());
+            self.slowlog.record(
+                &args,
+                elapsed,
+                session.peer_addr.as_deref().unwrap_or(""),
+                session.client_name.as_deref().unwrap_or(""),
+                &user,
+            );
+        }
+        self.audit(&cmd, &args, session, &result.0);
+        result
+    }
+
+    /// Writes security-relevant commands (auth, ACL changes, config and destructive
+    /// admin commands) to the audit log. Secrets such as passwords are never included.
+    fn audit(&self, cmd: &str, args: &[Vec<u8>], session: &SessionAuth, response: &RespValue) {
+        let sub = args.get(1).map(|v| upper(v)).unwrap_or_default();
+        let (event, detail) = match cmd {
+            "AUTH" => (
+                "auth",
+                if args.len() == 3 {
+                    format!("user={}", String::from_utf8_lossy(&args[1]))
+                } else {
+                    String::new()
+                },
+            ),
+            "HELLO" if args.iter().any(|v| upper(v) == "AUTH") => ("auth", "hello".to_string()),
+            "ACL" if matches!(sub.as_str(), "SETUSER" | "DELUSER" | "LOAD" | "SAVE") => (
+                "acl",
+                args.get(2)
+                    .map(|v| format!("{} {}", sub.to_lowercase(), String::from_utf8_lossy(v)))
+                    .unwrap_or_else(|| sub.to_lowercase()),
+            ),
+            "CONFIG" if sub == "SET" => (
+                "config_set",
+                args.get(2)
+                    .map(|v| String::from_utf8_lossy(v).to_string())
+                    .unwrap_or_default(),
             ),
+            "CLIENT" if sub == "KILL" => (
+                "client_kill",
+                args[2..]
+                    .iter()
+                    .map(|v| String::from_utf8_lossy(v))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "FLUSHALL" | "FLUSHDB" | "SHUTDOWN" => ("admin", cmd.to_lowercase()),
+            _ => return,
+        };
+        let outcome = match response {
+            RespValue::Error(e) => e.split_whitespace().next().unwrap_or("ERR"),
+            _ => "ok",
+        };
+        let user = session
+            .user
+            .clone()
+            .unwrap_or_else(|| self.auth.default_user());
+        self.audit.record(AuditEvent {
+            event,
+            user: &user,
+            peer: session.peer_addr.as_deref().unwrap_or("-"),
+            outcome,
+            detail: &detail,
+        });
+    }
+
+    /// Logs the session in as the ACL user named by its verified client
+    /// certificate. Returns false when no candidate name is an enabled user, in
+    /// which case the client still has to AUTH.
+    pub fn authenticate_certificate(&self, names: &[String], session: &mut SessionAuth) -> bool {
+        let Some(user) = self.auth.authenticate_certificate(names) else {
+            return false;
+        };
+        self.audit.record(AuditEvent {
+            event: "auth",
+            user: &user,
+            peer: session.peer_addr.as_deref().unwrap_or("-"),
+            outcome: "ok",
+            detail: "tls-client-cert",
+        });
+        session.user = Some(user);
+        true
+    }
+
+    /// The commands `COMMAND` reports: the fedis ones only in non_redis_mode.
+    fn served_commands(&self) -> Vec<&'static CommandSpec> {
+        registry::commands()
+            .into_iter()
+            .filter(|spec| self.serves(spec))
+            .collect()
+    }
+
+    fn serves(&self, spec: &CommandSpec) -> bool {
+        self.non_redis_mode || spec.non_redis != NonRedis::Command
+    }
+
+    /// Applies a write received from a master. The master already checked it, so
+    /// auth, ACLs, rate limits and read-only mode do not apply.
+    pub async fn apply_replicated(&self, args: &[Vec<u8>]) -> RespValue {
+        let cmd = upper(&args[0]);
+        let Some(spec) = registry::lookup(&cmd) else {
+            return RespValue::Error(format!("ERR unknown command '{}'", cmd.to_lowercase()));
+        };
+        if !spec.accepts(args.len()) {
+            return wrong_arity(&cmd);
         }
+        let mut session = SessionAuth::default();
+        (spec.handler)(self, args, &mut session).await.0
     }
 
     pub fn record_command_stats(&self, command: &str, elapsed_usec: u64) {
         self.stats.record_command(command, elapsed_usec);
     }
+
+    /// Labels the call with the session's user and its outcome for the metrics.
+    pub fn record_command_outcome(
+        &self,
+        command: &str,
+        session: &SessionAuth,
+        error: bool,
+        elapsed_usec: u64,
+    ) {
+        let user = session
+            .user
+            .clone()
+            .unwrap_or_else(|| self.auth.default_user());
+        self.stats
+            .record_command_outcome(command, &user, error, elapsed_usec);
+    }
+
+    pub fn record_error_reply(&self, command: &str, message: &str) {
+        self.stats.record_error_reply(command, message);
+    }
 }
 
-fn is_memory_growing_command(cmd: &str) -> bool {
-    matches!(
-        cmd,
-        "SET"
-            | "SETEX"
-            | "PSETEX"
-            | "SETNX"
-            | "MSET"
-            | "MSETNX"
-            | "APPEND"
-            | "SETRANGE"
-            | "GETSET"
-            | "UPDATE"
-            | "INCR"
-            | "INCRBY"
-            | "DECR"
-            | "DECRBY"
-            | "JSON.SET"
-    )
+fn wrong_arity(cmd: &str) -> RespValue {
+    RespValue::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        cmd.to_lowercase()
+    ))
 }
 
 pub(super) fn parse_u64(bytes: &[u8]) -> Option<u64> {
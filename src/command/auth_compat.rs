@@ -1,5 +1,36 @@
 use super::*;
-use crate::auth::AuthError;
+use crate::auth::{AuthError, generate_password};
+use crate::clients::KillFilter;
+use crate::export::ExportFormat;
+use crate::jwt::looks_like_token;
+use crate::slowlog::SlowLogFilter;
+
+/// A module whose commands fedis has built in, with the version of the
+/// Redis module they follow.
+struct BuiltinModule {
+    name: &'static str,
+    version: i64,
+    /// Every command of the module starts with it.
+    prefix: &'static str,
+}
+
+const BUILTIN_MODULES: &[BuiltinModule] = &[
+    BuiltinModule {
+        name: "ReJSON",
+        version: 20609,
+        prefix: "JSON.",
+    },
+    BuiltinModule {
+        name: "search",
+        version: 20809,
+        prefix: "FT.",
+    },
+    BuiltinModule {
+        name: "timeseries",
+        version: 11011,
+        prefix: "TS.",
+    },
+];
 
 impl CommandExecutor {
     pub(super) fn ping(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
@@ -11,7 +42,7 @@ impl CommandExecutor {
         }
         if args.len() == 2 {
             return (
-                RespValue::Bulk(Some(args[1].clone())),
+                RespValue::Bulk(Some(args[1].clone().into())),
                 SessionAction::Continue,
             );
         }
@@ -22,32 +53,20 @@ impl CommandExecutor {
     }
 
     pub(super) fn echo(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'echo' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         (
-            RespValue::Bulk(Some(args[1].clone())),
+            RespValue::Bulk(Some(args[1].clone().into())),
             SessionAction::Continue,
         )
     }
 
-    pub(super) fn time(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 1 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'time' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
+    pub(super) fn time(&self) -> (RespValue, SessionAction) {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default();
         (
             RespValue::Array(vec![
-                RespValue::Bulk(Some(now.as_secs().to_string().into_bytes())),
-                RespValue::Bulk(Some(now.subsec_micros().to_string().into_bytes())),
+                RespValue::Bulk(Some(now.as_secs().to_string().into())),
+                RespValue::Bulk(Some(now.subsec_micros().to_string().into())),
             ]),
             SessionAction::Continue,
         )
@@ -90,7 +109,7 @@ impl CommandExecutor {
                     }
                     let user = String::from_utf8_lossy(&args[idx + 1]);
                     let pass = String::from_utf8_lossy(&args[idx + 2]);
-                    match self.auth.authenticate(Some(&user), &pass) {
+                    match self.authenticate_session(Some(&user), &pass, session) {
                         Ok(u) => session.user = Some(u),
                         Err(AuthError::NoPasswordConfigured) => {
                             return (
@@ -110,6 +129,9 @@ impl CommandExecutor {
                                 SessionAction::Continue,
                             )
                         }
+                        Err(AuthError::LockedOut(remaining)) => {
+                            return (locked_out_error(remaining), SessionAction::Continue)
+                        }
                     }
                     idx += 3;
                 }
@@ -136,29 +158,41 @@ impl CommandExecutor {
 
         let fields = vec![
             (
-                RespValue::Bulk(Some(b"server".to_vec())),
-                RespValue::Bulk(Some(b"redis".to_vec())),
+                RespValue::Bulk(Some(Bytes::from_static(b"server"))),
+                RespValue::Bulk(Some(Bytes::from_static(b"redis"))),
             ),
             (
-                RespValue::Bulk(Some(b"version".to_vec())),
-                RespValue::Bulk(Some(b"7.2.0-fedis".to_vec())),
+                RespValue::Bulk(Some(Bytes::from_static(b"version"))),
+                RespValue::Bulk(Some(Bytes::from_static(b"7.2.0-fedis"))),
             ),
             (
-                RespValue::Bulk(Some(b"proto".to_vec())),
+                RespValue::Bulk(Some(Bytes::from_static(b"proto"))),
                 RespValue::Integer(proto),
             ),
-            (RespValue::Bulk(Some(b"id".to_vec())), RespValue::Integer(0)),
             (
-                RespValue::Bulk(Some(b"mode".to_vec())),
-                RespValue::Bulk(Some(b"standalone".to_vec())),
+                RespValue::Bulk(Some(Bytes::from_static(b"id"))),
+                RespValue::Integer(0),
             ),
             (
-                RespValue::Bulk(Some(b"role".to_vec())),
-                RespValue::Bulk(Some(b"master".to_vec())),
+                RespValue::Bulk(Some(Bytes::from_static(b"mode"))),
+                RespValue::Bulk(Some(Bytes::from_static(b"standalone"))),
+            ),
+            (
+                RespValue::Bulk(Some(Bytes::from_static(b"role"))),
+                RespValue::Bulk(Some(
+                    if self
+                        .replication_role()
+                        .is_some_and(ReplicationRole::is_replica)
+                    {
+                        Bytes::from_static(b"replica")
+                    } else {
+                        Bytes::from_static(b"master")
+                    },
+                )),
             ),
             (
-                RespValue::Bulk(Some(b"modules".to_vec())),
-                RespValue::Array(Vec::new()),
+                RespValue::Bulk(Some(Bytes::from_static(b"modules"))),
+                self.module_list(proto),
             ),
         ];
 
@@ -179,13 +213,6 @@ impl CommandExecutor {
         args: &[Vec<u8>],
         session: &mut SessionAuth,
     ) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'client' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let sub = upper(&args[1]);
         match sub.as_str() {
             "SETINFO" => {
@@ -224,14 +251,18 @@ impl CommandExecutor {
                     );
                 }
                 (
-                    RespValue::Bulk(session.client_name.clone().map(|v| v.into_bytes())),
+                    RespValue::Bulk(session.client_name.clone().map(Bytes::from)),
                     SessionAction::Continue,
                 )
             }
-            "ID" => (RespValue::Integer(0), SessionAction::Continue),
+            "ID" => (
+                RespValue::Integer(session.connection_id as i64),
+                SessionAction::Continue,
+            ),
+            "KILL" => (self.client_kill(args, session), SessionAction::Continue),
             "GETREDIR" => (RespValue::Integer(-1), SessionAction::Continue),
             "LIST" => (
-                RespValue::Bulk(Some(b"id=0 addr=127.0.0.1:0 fd=0 name= age=0 idle=0 flags=N db=0 sub=0 psub=0 ssub=0 multi=-1 qbuf=0 qbuf-free=0 argv-mem=0 obl=0 oll=0 omem=0 tot-mem=0 events=r cmd=client user=default redir=-1 resp=2".to_vec())),
+                RespValue::Bulk(Some(Bytes::from_static(b"id=0 addr=127.0.0.1:0 fd=0 name= age=0 idle=0 flags=N db=0 sub=0 psub=0 ssub=0 multi=-1 qbuf=0 qbuf-free=0 argv-mem=0 obl=0 oll=0 omem=0 tot-mem=0 events=r cmd=client user=default redir=-1 resp=2"))),
                 SessionAction::Continue,
             ),
             "INFO" => (
@@ -241,7 +272,8 @@ impl CommandExecutor {
                         session.client_name.as_deref().unwrap_or(""),
                         session.user.as_deref().unwrap_or("default")
                     )
-                    .into_bytes(),
+                    .into_bytes()
+                    .into(),
                 )),
                 SessionAction::Continue,
             ),
@@ -256,18 +288,56 @@ impl CommandExecutor {
         }
     }
 
+    /// `CLIENT KILL addr`, replying OK, or `CLIENT KILL` with `ID`, `ADDR`,
+    /// `USER` and `SKIPME` filters, replying how many connections closed.
+    fn client_kill(&self, args: &[Vec<u8>], session: &SessionAuth) -> RespValue {
+        let default_user = self.auth.default_user();
+        if args.len() == 3 {
+            let filter = KillFilter {
+                addr: Some(String::from_utf8_lossy(&args[2]).to_string()),
+                ..KillFilter::default()
+            };
+            return match self.clients().kill(&filter, &default_user) {
+                0 => RespValue::Error("ERR No such client".to_string()),
+                _ => RespValue::Simple("OK".to_string()),
+            };
+        }
+        if args.len() < 4 || !args.len().is_multiple_of(2) {
+            return RespValue::Error("ERR syntax error".to_string());
+        }
+        let mut filter = KillFilter {
+            skip: Some(session.connection_id),
+            ..KillFilter::default()
+        };
+        for pair in args[2..].chunks(2) {
+            let value = String::from_utf8_lossy(&pair[1]).to_string();
+            match upper(&pair[0]).as_str() {
+                "ID" => match value.parse() {
+                    Ok(id) if id > 0 => filter.id = Some(id),
+                    _ => {
+                        return RespValue::Error(
+                            "ERR client-id should be greater than 0".to_string(),
+                        );
+                    }
+                },
+                "ADDR" => filter.addr = Some(value),
+                "USER" => filter.user = Some(value),
+                "SKIPME" => match value.to_ascii_lowercase().as_str() {
+                    "yes" => filter.skip = Some(session.connection_id),
+                    "no" => filter.skip = None,
+                    _ => return RespValue::Error("ERR syntax error".to_string()),
+                },
+                _ => return RespValue::Error("ERR syntax error".to_string()),
+            }
+        }
+        RespValue::Integer(self.clients().kill(&filter, &default_user) as i64)
+    }
+
     pub(super) fn acl(
         &self,
         args: &[Vec<u8>],
         session: &SessionAuth,
     ) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'acl' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let sub = upper(&args[1]);
         match sub.as_str() {
             "WHOAMI" => (
@@ -275,20 +345,251 @@ impl CommandExecutor {
                     session
                         .user
                         .clone()
-                        .unwrap_or_else(|| self.auth.default_user().to_string())
-                        .into_bytes(),
+                        .unwrap_or_else(|| self.auth.default_user())
+                        .into_bytes()
+                        .into(),
                 )),
                 SessionAction::Continue,
             ),
             "LIST" => {
+                let users = self
+                    .auth
+                    .describe_users()
+                    .into_iter()
+                    .map(|u| RespValue::Bulk(Some(u.into())))
+                    .collect();
+                (RespValue::Array(users), SessionAction::Continue)
+            }
+            "USERS" => {
                 let users = self
                     .auth
                     .list_users()
                     .into_iter()
-                    .map(|u| RespValue::Bulk(Some(format!("user {} on", u).into_bytes())))
+                    .map(|u| RespValue::Bulk(Some(u.into())))
                     .collect();
                 (RespValue::Array(users), SessionAction::Continue)
             }
+            "GETUSER" => {
+                if args.len() != 3 {
+                    return (
+                        RespValue::Error(
+                            "ERR wrong number of arguments for 'acl|getuser' command".to_string(),
+                        ),
+                        SessionAction::Continue,
+                    );
+                }
+                let Some(info) = self.auth.user_info(&String::from_utf8_lossy(&args[2])) else {
+                    return (RespValue::Bulk(None), SessionAction::Continue);
+                };
+                let bulk_list = |items: Vec<String>| {
+                    RespValue::Array(
+                        items
+                            .into_iter()
+                            .map(|v| RespValue::Bulk(Some(v.into())))
+                            .collect(),
+                    )
+                };
+                (
+                    RespValue::Array(vec![
+                        RespValue::Bulk(Some(Bytes::from_static(b"flags"))),
+                        bulk_list(info.flags),
+                        RespValue::Bulk(Some(Bytes::from_static(b"passwords"))),
+                        bulk_list(info.passwords),
+                        RespValue::Bulk(Some(Bytes::from_static(b"commands"))),
+                        RespValue::Bulk(Some(info.commands.into())),
+                        RespValue::Bulk(Some(Bytes::from_static(b"keys"))),
+                        RespValue::Bulk(Some(info.keys.into())),
+                        RespValue::Bulk(Some(Bytes::from_static(b"channels"))),
+                        RespValue::Bulk(Some(Bytes::from_static(b"&*"))),
+                        RespValue::Bulk(Some(Bytes::from_static(b"selectors"))),
+                        RespValue::Array(
+                            info.selectors
+                                .into_iter()
+                                .map(|(commands, keys)| {
+                                    RespValue::Array(vec![
+                                        RespValue::Bulk(Some(Bytes::from_static(b"commands"))),
+                                        RespValue::Bulk(Some(commands.into())),
+                                        RespValue::Bulk(Some(Bytes::from_static(b"keys"))),
+                                        RespValue::Bulk(Some(keys.into())),
+                                        RespValue::Bulk(Some(Bytes::from_static(b"channels"))),
+                                        RespValue::Bulk(Some(Bytes::from_static(b"&*"))),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ]),
+                    SessionAction::Continue,
+                )
+            }
+            "SETUSER" => {
+                if args.len() < 3 {
+                    return (
+                        RespValue::Error(
+                            "ERR wrong number of arguments for 'acl|setuser' command".to_string(),
+                        ),
+                        SessionAction::Continue,
+                    );
+                }
+                let name = String::from_utf8_lossy(&args[2]).to_string();
+                let rules: Vec<String> = args[3..]
+                    .iter()
+                    .map(|v| String::from_utf8_lossy(v).to_string())
+                    .collect();
+                match self.auth.set_user(&name, &rules) {
+                    Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+                    Err(e) => (
+                        RespValue::Error(format!("ERR {}", e)),
+                        SessionAction::Continue,
+                    ),
+                }
+            }
+            "DELUSER" => {
+                if args.len() < 3 {
+                    return (
+                        RespValue::Error(
+                            "ERR wrong number of arguments for 'acl|deluser' command".to_string(),
+                        ),
+                        SessionAction::Continue,
+                    );
+                }
+                let mut removed = 0_i64;
+                for name in &args[2..] {
+                    match self.auth.delete_user(&String::from_utf8_lossy(name)) {
+                        Ok(true) => removed += 1,
+                        Ok(false) => {}
+                        Err(e) => {
+                            return (
+                                RespValue::Error(format!("ERR {}", e)),
+                                SessionAction::Continue,
+                            );
+                        }
+                    }
+                }
+                (RespValue::Integer(removed), SessionAction::Continue)
+            }
+            "GENPASS" => {
+                let bits = match args.get(2) {
+                    Some(raw) => match parse_u64(raw) {
+                        Some(v) if (1..=4096).contains(&v) => v as usize,
+                        _ => {
+                            return (
+                                RespValue::Error(
+                                    "ERR ACL GENPASS argument must be the number of bits for the output password, a positive number up to 4096"
+                                        .to_string(),
+                                ),
+                                SessionAction::Continue,
+                            );
+                        }
+                    },
+                    None => 256,
+                };
+                match generate_password(bits) {
+                    Ok(pass) => (RespValue::Bulk(Some(pass.into())), SessionAction::Continue),
+                    Err(e) => (
+                        RespValue::Error(format!("ERR internal: {}", e)),
+                        SessionAction::Continue,
+                    ),
+                }
+            }
+            "CAT" => {
+                let table = registry::commands();
+                let Some(category) = args.get(2) else {
+                    let mut categories: Vec<&str> =
+                        table.iter().flat_map(|spec| spec.categories()).collect();
+                    categories.sort_unstable();
+                    categories.dedup();
+                    return (
+                        RespValue::Array(
+                            categories
+                                .into_iter()
+                                .map(|c| {
+                                    RespValue::Bulk(Some(Bytes::copy_from_slice(c.as_bytes())))
+                                })
+                                .collect(),
+                        ),
+                        SessionAction::Continue,
+                    );
+                };
+                let category = String::from_utf8_lossy(category).to_ascii_lowercase();
+                let commands: Vec<RespValue> = table
+                    .iter()
+                    .filter(|spec| spec.categories().contains(&category.as_str()))
+                    .map(|spec| RespValue::Bulk(Some(spec.name.to_ascii_lowercase().into())))
+                    .collect();
+                if commands.is_empty() {
+                    return (
+                        RespValue::Error(format!("ERR Unknown category '{}'", category)),
+                        SessionAction::Continue,
+                    );
+                }
+                (RespValue::Array(commands), SessionAction::Continue)
+            }
+            "LOG" => {
+                let count = match args.get(2) {
+                    Some(raw) if upper(raw) == "RESET" => {
+                        self.auth.reset_log();
+                        return (RespValue::Simple("OK".to_string()), SessionAction::Continue);
+                    }
+                    Some(raw) => match parse_u64(raw) {
+                        Some(v) => v as usize,
+                        None => {
+                            return (
+                                RespValue::Error(
+                                    "ERR value is not an integer or out of range".to_string(),
+                                ),
+                                SessionAction::Continue,
+                            );
+                        }
+                    },
+                    None => 10,
+                };
+                let now = now_ms();
+                let entries = self
+                    .auth
+                    .log_entries(count)
+                    .into_iter()
+                    .map(|e| {
+                        let age = now.saturating_sub(e.created_ms) as f64 / 1000.0;
+                        RespValue::Array(vec![
+                            RespValue::Bulk(Some(Bytes::from_static(b"count"))),
+                            RespValue::Integer(e.count as i64),
+                            RespValue::Bulk(Some(Bytes::from_static(b"reason"))),
+                            RespValue::Bulk(Some(Bytes::copy_from_slice(e.reason.as_bytes()))),
+                            RespValue::Bulk(Some(Bytes::from_static(b"context"))),
+                            RespValue::Bulk(Some(Bytes::from_static(b"toplevel"))),
+                            RespValue::Bulk(Some(Bytes::from_static(b"object"))),
+                            RespValue::Bulk(Some(e.object.into())),
+                            RespValue::Bulk(Some(Bytes::from_static(b"username"))),
+                            RespValue::Bulk(Some(e.username.into())),
+                            RespValue::Bulk(Some(Bytes::from_static(b"age-seconds"))),
+                            RespValue::Bulk(Some(format!("{:.3}", age).into())),
+                            RespValue::Bulk(Some(Bytes::from_static(b"client-info"))),
+                            RespValue::Bulk(Some(e.client_info.into())),
+                            RespValue::Bulk(Some(Bytes::from_static(b"entry-id"))),
+                            RespValue::Integer(e.entry_id as i64),
+                            RespValue::Bulk(Some(Bytes::from_static(b"timestamp-created"))),
+                            RespValue::Integer(e.created_ms as i64),
+                            RespValue::Bulk(Some(Bytes::from_static(b"timestamp-last-updated"))),
+                            RespValue::Integer(e.updated_ms as i64),
+                        ])
+                    })
+                    .collect();
+                (RespValue::Array(entries), SessionAction::Continue)
+            }
+            "LOAD" => match self.auth.load_acl_file() {
+                Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+                Err(e) => (
+                    RespValue::Error(format!("ERR {}", e)),
+                    SessionAction::Continue,
+                ),
+            },
+            "SAVE" => match self.auth.save_acl_file() {
+                Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+                Err(e) => (
+                    RespValue::Error(format!("ERR {}", e)),
+                    SessionAction::Continue,
+                ),
+            },
             _ => (
                 RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
                 SessionAction::Continue,
@@ -297,15 +598,9 @@ impl CommandExecutor {
     }
 
     pub(super) fn module_cmd(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'module' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         let sub = upper(&args[1]);
         match sub.as_str() {
-            "LIST" => (RespValue::Array(Vec::new()), SessionAction::Continue),
+            "LIST" if args.len() == 2 => (self.module_list(2), SessionAction::Continue),
             _ => (
                 RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
                 SessionAction::Continue,
@@ -313,12 +608,50 @@ impl CommandExecutor {
         }
     }
 
+    /// The modules whose commands fedis has built in, as Redis lists loaded
+    /// modules, when `FEDIS_ADVERTISE_MODULES` is on; clients such as
+    /// redis-om and RedisInsight look here before using `JSON.*` or `FT.*`.
+    fn module_list(&self, proto: i64) -> RespValue {
+        if !self.advertise_modules {
+            return RespValue::Array(Vec::new());
+        }
+        let modules = BUILTIN_MODULES
+            .iter()
+            .map(|module| {
+                let fields = vec![
+                    (
+                        RespValue::Bulk(Some(Bytes::from_static(b"name"))),
+                        RespValue::Bulk(Some(Bytes::from_static(module.name.as_bytes()))),
+                    ),
+                    (
+                        RespValue::Bulk(Some(Bytes::from_static(b"ver"))),
+                        RespValue::Integer(module.version),
+                    ),
+                    (
+                        RespValue::Bulk(Some(Bytes::from_static(b"path"))),
+                        RespValue::Bulk(Some(Bytes::from_static(b"builtin"))),
+                    ),
+                    (
+                        RespValue::Bulk(Some(Bytes::from_static(b"args"))),
+                        RespValue::Array(Vec::new()),
+                    ),
+                ];
+                if proto == 3 {
+                    RespValue::Map(fields)
+                } else {
+                    RespValue::Array(fields.into_iter().flat_map(|(k, v)| [k, v]).collect())
+                }
+            })
+            .collect();
+        RespValue::Array(modules)
+    }
+
     pub(super) fn command_meta(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        let table = command_table();
+        let table = self.served_commands();
         if args.len() == 1 {
             let payload = table
                 .iter()
-                .map(command_meta_entry)
+                .map(|spec| spec.meta())
                 .collect::<Vec<RespValue>>();
             return (RespValue::Array(payload), SessionAction::Continue);
         }
@@ -333,8 +666,8 @@ impl CommandExecutor {
                 let mut out = Vec::new();
                 for name in args.iter().skip(2) {
                     let needle = String::from_utf8_lossy(name).to_ascii_uppercase();
-                    if let Some(spec) = table.iter().find(|spec| spec.name == needle) {
-                        out.push(command_meta_entry(spec));
+                    if let Some(spec) = registry::lookup(&needle).filter(|spec| self.serves(spec)) {
+                        out.push(spec.meta());
                     } else {
                         out.push(RespValue::Bulk(None));
                     }
@@ -342,6 +675,11 @@ impl CommandExecutor {
                 (RespValue::Array(out), SessionAction::Continue)
             }
             "DOCS" => (RespValue::Array(Vec::new()), SessionAction::Continue),
+            "LIST" => (self.command_list(&args[2..]), SessionAction::Continue),
+            "GETKEYS" | "GETKEYSANDFLAGS" => (
+                self.command_getkeys(&sub, &args[2..]),
+                SessionAction::Continue,
+            ),
             _ => (
                 RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
                 SessionAction::Continue,
@@ -349,14 +687,90 @@ impl CommandExecutor {
         }
     }
 
-    pub(super) fn config_cmd(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'config' command".to_string()),
-                SessionAction::Continue,
+    /// `COMMAND LIST [FILTERBY MODULE name|ACLCAT category|PATTERN pattern]`:
+    /// names from the registry. Only advertised built-in modules have commands.
+    fn command_list(&self, filter: &[Vec<u8>]) -> RespValue {
+        let names = self.served_commands().into_iter();
+        let names: Vec<&str> = match filter {
+            [] => names.map(|spec| spec.name).collect(),
+            [by, kind, value] if upper(by) == "FILTERBY" => {
+                let value = String::from_utf8_lossy(value).to_ascii_lowercase();
+                match upper(kind).as_str() {
+                    "MODULE" => match BUILTIN_MODULES
+                        .iter()
+                        .find(|module| module.name.eq_ignore_ascii_case(&value))
+                    {
+                        Some(module) if self.advertise_modules => names
+                            .filter(|spec| spec.name.starts_with(module.prefix))
+                            .map(|spec| spec.name)
+                            .collect(),
+                        _ => Vec::new(),
+                    },
+                    "ACLCAT" => names
+                        .filter(|spec| spec.categories().contains(&value.as_str()))
+                        .map(|spec| spec.name)
+                        .collect(),
+                    "PATTERN" => names
+                        .filter(|spec| glob_match_ascii(&value, &spec.name.to_ascii_lowercase()))
+                        .map(|spec| spec.name)
+                        .collect(),
+                    _ => return RespValue::Error("ERR syntax error".to_string()),
+                }
+            }
+            _ => return RespValue::Error("ERR syntax error".to_string()),
+        };
+        RespValue::Array(
+            names
+                .into_iter()
+                .map(|name| RespValue::Bulk(Some(name.to_ascii_lowercase().into())))
+                .collect(),
+        )
+    }
+
+    /// `COMMAND GETKEYS` and `COMMAND GETKEYSANDFLAGS`: the keys `command`
+    /// would touch, found through its key specs without running it.
+    fn command_getkeys(&self, sub: &str, command: &[Vec<u8>]) -> RespValue {
+        if command.is_empty() {
+            return RespValue::Error(format!(
+                "ERR wrong number of arguments for 'command|{}' command",
+                sub.to_lowercase()
+            ));
+        }
+        let Some(spec) = registry::lookup(&upper(&command[0])).filter(|spec| self.serves(spec))
+        else {
+            return RespValue::Error("ERR Invalid command specified".to_string());
+        };
+        if !spec.accepts(command.len()) {
+            return RespValue::Error(
+                "ERR Invalid number of arguments specified for command".to_string(),
             );
         }
+        let keys = spec.keys_and_flags(command);
+        if keys.is_empty() {
+            return RespValue::Error("ERR The command has no key arguments".to_string());
+        }
+        let bulk = |key: &[u8]| RespValue::Bulk(Some(Bytes::copy_from_slice(key)));
+        RespValue::Array(
+            keys.into_iter()
+                .map(|(key, flags)| {
+                    if sub == "GETKEYS" {
+                        return bulk(key);
+                    }
+                    RespValue::Array(vec![
+                        bulk(key),
+                        RespValue::Array(
+                            flags
+                                .iter()
+                                .map(|flag| RespValue::Simple(flag.to_string()))
+                                .collect(),
+                        ),
+                    ])
+                })
+                .collect(),
+        )
+    }
 
+    pub(super) fn config_cmd(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
         let sub = upper(&args[1]);
         match sub.as_str() {
             "GET" => {
@@ -383,19 +797,110 @@ impl CommandExecutor {
                 if glob_match_ascii(&pattern, "maxmemory") {
                     pairs.push(("maxmemory".to_string(), "0".to_string()));
                 }
+                if glob_match_ascii(&pattern, "read-only") {
+                    let value = if self.read_only.load(Ordering::Relaxed) {
+                        "yes"
+                    } else {
+                        "no"
+                    };
+                    pairs.push(("read-only".to_string(), value.to_string()));
+                }
+                for name in ["replica-read-only", "slave-read-only"] {
+                    if glob_match_ascii(&pattern, name) {
+                        let value = if self.replica_read_only.load(Ordering::Relaxed) {
+                            "yes"
+                        } else {
+                            "no"
+                        };
+                        pairs.push((name.to_string(), value.to_string()));
+                    }
+                }
+                if glob_match_ascii(&pattern, "slowlog-log-slower-than") {
+                    pairs.push((
+                        "slowlog-log-slower-than".to_string(),
+                        self.slowlog.slower_than_usec().to_string(),
+                    ));
+                }
+                if glob_match_ascii(&pattern, "slowlog-max-len") {
+                    pairs.push((
+                        "slowlog-max-len".to_string(),
+                        self.slowlog.max_len().to_string(),
+                    ));
+                }
 
                 let mut out = Vec::new();
                 for (k, v) in pairs {
-                    out.push(RespValue::Bulk(Some(k.into_bytes())));
-                    out.push(RespValue::Bulk(Some(v.into_bytes())));
+                    out.push(RespValue::Bulk(Some(k.into())));
+                    out.push(RespValue::Bulk(Some(v.into())));
                 }
                 (RespValue::Array(out), SessionAction::Continue)
             }
-            "SET" => (
-                RespValue::Error("ERR CONFIG SET is disabled in fedis".to_string()),
+            "SET" => {
+                if args.len() != 4 {
+                    return (
+                        RespValue::Error(
+                            "ERR wrong number of arguments for 'config|set' command".to_string(),
+                        ),
+                        SessionAction::Continue,
+                    );
+                }
+
+                let param = String::from_utf8_lossy(&args[2]).to_ascii_lowercase();
+                let invalid = |reason: &str| {
+                    (
+                        RespValue::Error(format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                            param, reason
+                        )),
+                        SessionAction::Continue,
+                    )
+                };
+                match param.as_str() {
+                    "slowlog-log-slower-than" => {
+                        let Some(usec) = parse_i64(&args[3]) else {
+                            return invalid("argument couldn't be parsed into an integer");
+                        };
+                        self.slowlog.set_slower_than_usec(usec);
+                        return (RespValue::Simple("OK".to_string()), SessionAction::Continue);
+                    }
+                    "slowlog-max-len" => {
+                        let Some(len) = parse_u64(&args[3]) else {
+                            return invalid("argument couldn't be parsed into an integer");
+                        };
+                        self.slowlog.set_max_len(len as usize);
+                        return (RespValue::Simple("OK".to_string()), SessionAction::Continue);
+                    }
+                    _ => {}
+                }
+                // Besides the slowlog, only the read-only switches are runtime-settable.
+                if !matches!(
+                    param.as_str(),
+                    "read-only" | "replica-read-only" | "slave-read-only"
+                ) {
+                    return (
+                        RespValue::Error("ERR CONFIG SET is disabled in fedis".to_string()),
+                        SessionAction::Continue,
+                    );
+                }
+                let enabled = match upper(&args[3]).as_str() {
+                    "YES" => true,
+                    "NO" => false,
+                    _ => return invalid("argument must be 'yes' or 'no'"),
+                };
+                if param == "read-only" {
+                    self.set_read_only(enabled);
+                } else {
+                    self.set_replica_read_only(enabled);
+                }
+                (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+            }
+            "RESETSTAT" => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            // Settings come from the environment; Sentinel sends this after
+            // REPLICAOF and carries on when it fails.
+            "REWRITE" => (
+                RespValue::Error("ERR The server is running without a config file".to_string()),
                 SessionAction::Continue,
             ),
-            "RESETSTAT" => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
             _ => (
                 RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
                 SessionAction::Continue,
@@ -404,16 +909,33 @@ impl CommandExecutor {
     }
 
     pub(super) fn latency(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'latency' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let sub = upper(&args[1]);
         match sub.as_str() {
-            "LATEST" | "DOCTOR" | "HISTOGRAM" | "GRAPH" | "HELP" => {
+            "HISTOGRAM" => {
+                let names: Vec<String> = args[2..]
+                    .iter()
+                    .map(|raw| String::from_utf8_lossy(raw).to_ascii_lowercase())
+                    .collect();
+                let filter = (!names.is_empty()).then_some(names.as_slice());
+                let int = |n: u64| RespValue::Integer(n.min(i64::MAX as u64) as i64);
+                let mut out = Vec::new();
+                for (name, latency) in self.stats.command_latency_snapshot(filter) {
+                    let buckets = latency
+                        .power_of_two_buckets()
+                        .into_iter()
+                        .flat_map(|(bound, below)| [int(bound), int(below)])
+                        .collect();
+                    out.push(RespValue::Bulk(Some(name.into())));
+                    out.push(RespValue::Array(vec![
+                        RespValue::Bulk(Some("calls".into())),
+                        int(latency.count()),
+                        RespValue::Bulk(Some("histogram_usec".into())),
+                        RespValue::Array(buckets),
+                    ]));
+                }
+                (RespValue::Array(out), SessionAction::Continue)
+            }
+            "LATEST" | "DOCTOR" | "GRAPH" | "HELP" => {
                 (RespValue::Array(Vec::new()), SessionAction::Continue)
             }
             _ => (
@@ -423,19 +945,122 @@ impl CommandExecutor {
         }
     }
 
+    /// `SLOWLOG GET [count] [USER name] [CLIENT addr|name] | LEN | RESET |
+    /// HELP`. `GET` returns the newest 10 entries by default and all of them
+    /// for a count of -1; the filters apply before the count.
     pub(super) fn slowlog(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'slowlog' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let sub = upper(&args[1]);
         match sub.as_str() {
-            "GET" => (RespValue::Array(Vec::new()), SessionAction::Continue),
-            "LEN" => (RespValue::Integer(0), SessionAction::Continue),
-            "RESET" => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            "GET" => {
+                let mut rest = &args[2..];
+                let count = match rest.first().filter(|raw| !matches!(upper(raw).as_str(), "USER" | "CLIENT")) {
+                    None => Some(10),
+                    Some(raw) => {
+                        rest = &rest[1..];
+                        match parse_i64(raw) {
+                            Some(-1) => None,
+                            Some(n) if n >= 0 => Some(n as usize),
+                            Some(_) => {
+                                return (
+                                    RespValue::Error(
+                                        "ERR count should be greater than or equal to -1".to_string(),
+                                    ),
+                                    SessionAction::Continue,
+                                );
+                            }
+                            None => {
+                                return (
+                                    RespValue::Error(
+                                        "ERR value is not an integer or out of range".to_string(),
+                                    ),
+                                    SessionAction::Continue,
+                                );
+                            }
+                        }
+                    }
+                };
+                let mut filter = SlowLogFilter::default();
+                for pair in rest.chunks(2) {
+                    let [option, value] = pair else {
+                        return (
+                            RespValue::Error("ERR syntax error".to_string()),
+                            SessionAction::Continue,
+                        );
+                    };
+                    let value = String::from_utf8_lossy(value).to_string();
+                    match upper(option).as_str() {
+                        "USER" => filter.user = Some(value),
+                        "CLIENT" => filter.client = Some(value),
+                        _ => {
+                            return (
+                                RespValue::Error("ERR syntax error".to_string()),
+                                SessionAction::Continue,
+                            );
+                        }
+                    }
+                }
+                let entries = self
+                    .slowlog
+                    .entries(count, &filter)
+                    .into_iter()
+                    .map(|entry| {
+                        RespValue::Array(vec![
+                            RespValue::Integer(entry.id as i64),
+                            RespValue::Integer(entry.timestamp as i64),
+                            RespValue::Integer(entry.duration_usec as i64),
+                            RespValue::Array(
+                                entry
+                                    .args
+                                    .into_iter()
+                                    .map(|arg| RespValue::Bulk(Some(arg.into())))
+                                    .collect(),
+                            ),
+                            RespValue::Bulk(Some(entry.client_addr.into())),
+                            RespValue::Bulk(Some(entry.client_name.into())),
+                            RespValue::Bulk(Some(entry.user.into())),
+                        ])
+                    })
+                    .collect();
+                (RespValue::Array(entries), SessionAction::Continue)
+            }
+            "LEN" if args.len() == 2 => (
+                RespValue::Integer(self.slowlog.len() as i64),
+                SessionAction::Continue,
+            ),
+            "RESET" if args.len() == 2 => {
+                self.slowlog.reset();
+                (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+            }
+            "HELP" if args.len() == 2 => (
+                RespValue::Array(
+                    [
+                        "SLOWLOG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                        "GET [<count>] [USER <username>] [CLIENT <addr>|<name>]",
+                        "    Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+                        "    USER and CLIENT keep only the entries of that user or client.",
+                        "    Entries are made of:",
+                        "    id, timestamp, time in microseconds, arguments array, client IP and port,",
+                        "    client name, user",
+                        "LEN",
+                        "    Return the length of the slowlog.",
+                        "RESET",
+                        "    Reset the slowlog.",
+                        "HELP",
+                        "    Print this help.",
+                    ]
+                    .into_iter()
+                    .map(|line| RespValue::Simple(line.to_string()))
+                    .collect(),
+                ),
+                SessionAction::Continue,
+            ),
+            "LEN" | "RESET" | "HELP" => (
+                RespValue::Error(format!(
+                    "ERR wrong number of arguments for 'slowlog|{}' command",
+                    sub.to_lowercase()
+                )),
+                SessionAction::Continue,
+            ),
             _ => (
                 RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
                 SessionAction::Continue,
@@ -443,16 +1068,7 @@ impl CommandExecutor {
         }
     }
 
-    pub(super) async fn bgrewriteaof(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 1 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'bgrewriteaof' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-
+    pub(super) async fn bgrewriteaof(&self) -> (RespValue, SessionAction) {
         if self.store.bgrewriteaof().await {
             (
                 RespValue::Simple("Background append only file rewriting started".to_string()),
@@ -468,14 +1084,7 @@ impl CommandExecutor {
         }
     }
 
-    pub(super) async fn bgsave(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 1 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'bgsave' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
+    pub(super) async fn bgsave(&self) -> (RespValue, SessionAction) {
         if self.store.bgsave().await {
             (
                 RespValue::Simple("Background saving started".to_string()),
@@ -491,14 +1100,50 @@ impl CommandExecutor {
         }
     }
 
-    pub(super) async fn save(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 1 {
+    /// `EXPORT file [FORMAT NDJSON|CSV] [MATCH pattern]`: writes the keyspace
+    /// to a file in the export directory and replies with the number of keys
+    /// written.
+    pub(super) async fn export(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let name = String::from_utf8_lossy(&args[1]).into_owned();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
             return (
-                RespValue::Error("ERR wrong number of arguments for 'save' command".to_string()),
+                RespValue::Error("ERR the export file must be a plain file name".to_string()),
                 SessionAction::Continue,
             );
         }
+        let mut format = ExportFormat::Ndjson;
+        let mut pattern = None;
+        let mut idx = 2;
+        while idx < args.len() {
+            let Some(value) = args.get(idx + 1) else {
+                return (
+                    RespValue::Error("ERR syntax error".to_string()),
+                    SessionAction::Continue,
+                );
+            };
+            match (upper(&args[idx]).as_str(), upper(value).as_str()) {
+                ("FORMAT", "NDJSON") => format = ExportFormat::Ndjson,
+                ("FORMAT", "CSV") => format = ExportFormat::Csv,
+                ("MATCH", _) => pattern = Some(value.clone()),
+                _ => {
+                    return (
+                        RespValue::Error("ERR syntax error".to_string()),
+                        SessionAction::Continue,
+                    );
+                }
+            }
+            idx += 2;
+        }
+        match self.store.export(&name, format, pattern).await {
+            Ok(written) => (RespValue::Integer(written as i64), SessionAction::Continue),
+            Err(e) => (
+                RespValue::Error(format!("ERR {}", e)),
+                SessionAction::Continue,
+            ),
+        }
+    }
 
+    pub(super) async fn save(&self) -> (RespValue, SessionAction) {
         match self.store.save_snapshot_now().await {
             Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
             Err(_) => (
@@ -508,16 +1153,7 @@ impl CommandExecutor {
         }
     }
 
-    pub(super) fn lastsave(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 1 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'lastsave' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-
+    pub(super) fn lastsave(&self) -> (RespValue, SessionAction) {
         let metrics = self.store.persistence_metrics();
         let ts = if metrics.last_snapshot_epoch_sec > 0 {
             metrics.last_snapshot_epoch_sec as i64
@@ -535,12 +1171,12 @@ impl CommandExecutor {
         let result = match args.len() {
             2 => {
                 let pwd = String::from_utf8_lossy(&args[1]);
-                self.auth.authenticate(None, &pwd)
+                self.authenticate_session(None, &pwd, session)
             }
             3 => {
                 let user = String::from_utf8_lossy(&args[1]);
                 let pwd = String::from_utf8_lossy(&args[2]);
-                self.auth.authenticate(Some(&user), &pwd)
+                self.authenticate_session(Some(&user), &pwd, session)
             }
             _ => {
                 return (
@@ -570,509 +1206,75 @@ impl CommandExecutor {
                 ),
                 SessionAction::Continue,
             ),
+            Err(AuthError::LockedOut(remaining)) => {
+                (locked_out_error(remaining), SessionAction::Continue)
+            }
         }
     }
-}
 
-#[derive(Clone, Copy)]
-struct CommandSpec {
-    name: &'static str,
-    arity: i64,
-    flags: &'static [&'static str],
-    first_key: i64,
-    last_key: i64,
-    step: i64,
+    fn authenticate_session(
+        &self,
+        username: Option<&str>,
+        password: &str,
+        session: &mut SessionAuth,
+    ) -> Result<String, AuthError> {
+        let attempted = username
+            .map(str::to_string)
+            .unwrap_or_else(|| self.auth.default_user());
+        let client_info = session.client_info();
+        let subjects = lockout_subjects(session, &attempted);
+        let lockout = self.auth.lockout();
+        if let Some(remaining) = lockout.check(&subjects) {
+            self.auth
+                .log_failure("auth", "AUTH-LOCKOUT", &attempted, &client_info);
+            return Err(AuthError::LockedOut(remaining));
+        }
+
+        let result = if self.auth.accepts_tokens() && looks_like_token(password) {
+            self.auth
+                .authenticate_token(username, password)
+                .map(|grant| {
+                    let user = grant.user.clone();
+                    session.token = Some(grant);
+                    user
+                })
+        } else {
+            self.auth.authenticate(username, password).inspect(|_| {
+                session.token = None;
+            })
+        };
+        match &result {
+            Ok(_) => lockout.record_success(&subjects),
+            Err(AuthError::InvalidCredentials) => {
+                self.auth
+                    .log_failure("auth", "AUTH", &attempted, &client_info);
+                if lockout.record_failure(&subjects).is_some() {
+                    self.auth
+                        .log_failure("auth", "AUTH-LOCKOUT", &attempted, &client_info);
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
 }
 
-fn command_meta_entry(spec: &CommandSpec) -> RespValue {
-    RespValue::Array(vec![
-        RespValue::Bulk(Some(spec.name.to_ascii_lowercase().into_bytes())),
-        RespValue::Integer(spec.arity),
-        RespValue::Array(
-            spec.flags
-                .iter()
-                .map(|v| RespValue::Bulk(Some(v.as_bytes().to_vec())))
-                .collect(),
-        ),
-        RespValue::Integer(spec.first_key),
-        RespValue::Integer(spec.last_key),
-        RespValue::Integer(spec.step),
-    ])
+/// Failed attempts are counted both per client IP and per attempted user name.
+fn lockout_subjects(session: &SessionAuth, user: &str) -> Vec<String> {
+    let mut subjects = vec![format!("user:{}", user)];
+    if let Some(peer) = session.peer_addr.as_deref() {
+        let ip = peer
+            .parse::<std::net::SocketAddr>()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|_| peer.to_string());
+        subjects.push(format!("ip:{}", ip));
+    }
+    subjects
 }
 
-fn command_table() -> &'static [CommandSpec] {
-    &[
-        CommandSpec {
-            name: "APPEND",
-            arity: 3,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "ACL",
-            arity: -2,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "AUTH",
-            arity: -2,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "BGSAVE",
-            arity: 1,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "BGREWRITEAOF",
-            arity: 1,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "CLIENT",
-            arity: -2,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "COMMAND",
-            arity: -1,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "CONFIG",
-            arity: -2,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "DBSIZE",
-            arity: 1,
-            flags: &["readonly", "fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "DECR",
-            arity: 2,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "DECRBY",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "DEL",
-            arity: -2,
-            flags: &["write"],
-            first_key: 1,
-            last_key: -1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "ECHO",
-            arity: 2,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "EXISTS",
-            arity: -2,
-            flags: &["readonly", "fast"],
-            first_key: 1,
-            last_key: -1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "EXPIRE",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "EXPIREAT",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "GET",
-            arity: 2,
-            flags: &["readonly", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "GETDEL",
-            arity: 2,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "GETEX",
-            arity: -2,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "GETRANGE",
-            arity: 4,
-            flags: &["readonly"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "GETSET",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "HELLO",
-            arity: -1,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "INCR",
-            arity: 2,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "INCRBY",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "INFO",
-            arity: -1,
-            flags: &["readonly"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "JSON.DEL",
-            arity: -2,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "JSON.GET",
-            arity: -2,
-            flags: &["readonly"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "JSON.SET",
-            arity: 4,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "JSON.TYPE",
-            arity: -2,
-            flags: &["readonly"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "KEYS",
-            arity: 2,
-            flags: &["readonly"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "LATENCY",
-            arity: -2,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "LASTSAVE",
-            arity: 1,
-            flags: &["readonly"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "MEMORY",
-            arity: -2,
-            flags: &["readonly"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "MGET",
-            arity: -2,
-            flags: &["readonly"],
-            first_key: 1,
-            last_key: -1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "MSET",
-            arity: -3,
-            flags: &["write"],
-            first_key: 1,
-            last_key: -1,
-            step: 2,
-        },
-        CommandSpec {
-            name: "MSETNX",
-            arity: -3,
-            flags: &["write"],
-            first_key: 1,
-            last_key: -1,
-            step: 2,
-        },
-        CommandSpec {
-            name: "MODULE",
-            arity: -2,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "OBJECT",
-            arity: -3,
-            flags: &["readonly"],
-            first_key: 2,
-            last_key: 2,
-            step: 1,
-        },
-        CommandSpec {
-            name: "PERSIST",
-            arity: 2,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "PEXPIRE",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "PEXPIREAT",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "PING",
-            arity: -1,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "PSETEX",
-            arity: 4,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "PTTL",
-            arity: 2,
-            flags: &["readonly", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "QUIT",
-            arity: 1,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "SCAN",
-            arity: -2,
-            flags: &["readonly"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "SAVE",
-            arity: 1,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "SELECT",
-            arity: 2,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "SET",
-            arity: -3,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "SETEX",
-            arity: 4,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "SETNX",
-            arity: 3,
-            flags: &["write", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "SETRANGE",
-            arity: 4,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "SLOWLOG",
-            arity: -2,
-            flags: &["admin"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "STRLEN",
-            arity: 2,
-            flags: &["readonly", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "TIME",
-            arity: 1,
-            flags: &["fast"],
-            first_key: 0,
-            last_key: 0,
-            step: 0,
-        },
-        CommandSpec {
-            name: "TTL",
-            arity: 2,
-            flags: &["readonly", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "TYPE",
-            arity: 2,
-            flags: &["readonly", "fast"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "UNLINK",
-            arity: -2,
-            flags: &["write"],
-            first_key: 1,
-            last_key: -1,
-            step: 1,
-        },
-        CommandSpec {
-            name: "UPDATE",
-            arity: -3,
-            flags: &["write"],
-            first_key: 1,
-            last_key: 1,
-            step: 1,
-        },
-    ]
+fn locked_out_error(remaining: std::time::Duration) -> RespValue {
+    RespValue::Error(format!(
+        "ERR too many failed authentication attempts, retry in {} seconds",
+        remaining.as_secs_f64().ceil().max(1.0) as u64
+    ))
 }
@@ -0,0 +1,452 @@
+use super::*;
+use crate::cluster::{Cluster, ClusterNode, Route, SLOTS, key_slot, parse_slot, parse_slot_range};
+use crate::migration::{MigrationJob, MigrationProgress, keys_in_slot};
+
+impl CommandExecutor {
+    /// `CLUSTER`. Without cluster mode the answers describe a standalone node
+    /// (no slots, no shards), so cluster-aware clients probing at connect
+    /// time fall back to a single-node connection.
+    pub(super) async fn cluster(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let sub = upper(&args[1]);
+        let Some(cluster) = self.cluster.as_ref() else {
+            return (
+                standalone_reply(&sub, self.stats.run_id()),
+                SessionAction::Continue,
+            );
+        };
+        let reply = match sub.as_str() {
+            "INFO" => cluster_info(cluster),
+            "MYID" => RespValue::Bulk(Some(cluster.myself().id.clone().into())),
+            "NODES" => cluster_nodes(cluster),
+            "SLOTS" => cluster_slots(cluster),
+            "SHARDS" => cluster_shards(cluster),
+            "KEYSLOT" if args.len() == 3 => RespValue::Integer(i64::from(key_slot(&args[2]))),
+            "COUNTKEYSINSLOT" if args.len() == 3 => match parse_slot(&args[2]) {
+                Some(slot) => RespValue::Integer(
+                    keys_in_slot(&self.store, slot, usize::MAX).await.len() as i64,
+                ),
+                None => invalid_slot(),
+            },
+            "GETKEYSINSLOT" if args.len() == 4 => {
+                match (parse_slot(&args[2]), parse_u64(&args[3])) {
+                    (Some(slot), Some(count)) => RespValue::Array(
+                        keys_in_slot(&self.store, slot, count as usize)
+                            .await
+                            .into_iter()
+                            .map(|key| RespValue::Bulk(Some(key.into())))
+                            .collect(),
+                    ),
+                    (None, _) => invalid_slot(),
+                    (_, None) => RespValue::Error("ERR Invalid number of keys".to_string()),
+                }
+            }
+            "ADDSLOTS" | "DELSLOTS" if args.len() > 2 => {
+                match args[2..].iter().map(|slot| parse_slot(slot)).collect() {
+                    Some(slots) => change_slots(cluster, &sub, slots),
+                    None => invalid_slot(),
+                }
+            }
+            "ADDSLOTSRANGE" | "DELSLOTSRANGE" if args.len() > 2 && args.len().is_multiple_of(2) => {
+                let mut slots = Vec::new();
+                for range in args[2..].chunks(2) {
+                    let (Some(start), Some(end)) = (parse_slot(&range[0]), parse_slot(&range[1]))
+                    else {
+                        return (invalid_slot(), SessionAction::Continue);
+                    };
+                    if start > end {
+                        return (
+                            RespValue::Error(format!(
+                                "ERR start slot number {start} is greater than end slot number {end}"
+                            )),
+                            SessionAction::Continue,
+                        );
+                    }
+                    slots.extend(start..=end);
+                }
+                change_slots(cluster, sub.trim_end_matches("RANGE"), slots)
+            }
+            "SETSLOT" if args.len() >= 4 => set_slot(cluster, &args[2..]),
+            "MEET" if args.len() >= 4 => match std::str::from_utf8(&args[3])
+                .ok()
+                .and_then(|port| port.parse::<u16>().ok())
+            {
+                Some(port) => {
+                    cluster.meet(&String::from_utf8_lossy(&args[2]), port);
+                    RespValue::Simple("OK".to_string())
+                }
+                None => RespValue::Error(format!(
+                    "ERR Invalid base port specified: {}",
+                    String::from_utf8_lossy(&args[3])
+                )),
+            },
+            "FORGET" if args.len() == 3 => {
+                match cluster.forget(&String::from_utf8_lossy(&args[2])) {
+                    Ok(()) => RespValue::Simple("OK".to_string()),
+                    Err(e) => RespValue::Error(e),
+                }
+            }
+            "KEYSLOT" | "COUNTKEYSINSLOT" | "GETKEYSINSLOT" | "ADDSLOTS" | "DELSLOTS"
+            | "ADDSLOTSRANGE" | "DELSLOTSRANGE" | "SETSLOT" | "MEET" | "FORGET" => {
+                RespValue::Error(format!(
+                    "ERR wrong number of arguments for 'cluster|{}' command",
+                    sub.to_lowercase()
+                ))
+            }
+            _ => RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `ASKING`: the next command may use a slot this node is importing.
+    pub(super) fn asking(&self, session: &mut SessionAuth) -> (RespValue, SessionAction) {
+        if self.cluster.is_none() {
+            return (
+                RespValue::Error("ERR This instance has cluster support disabled".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        session.asking = true;
+        (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+    }
+
+    /// The redirection for a keyed command this node must not serve, if any.
+    pub(super) async fn cluster_redirect(
+        &self,
+        spec: &CommandSpec,
+        args: &[Vec<u8>],
+        asking: bool,
+    ) -> Option<RespValue> {
+        let cluster = self.cluster.as_ref()?;
+        let keys = spec.keys(args);
+        let missing = match keys.first() {
+            Some(key) if cluster.is_migrating(key_slot(key)) => {
+                let keys: Vec<Vec<u8>> = keys.iter().map(|key| key.to_vec()).collect();
+                (self.store.exists(&keys).await as usize) < keys.len()
+            }
+            _ => false,
+        };
+        let error = match cluster.route(&keys, asking, missing) {
+            Route::Local => return None,
+            Route::Moved(slot, address) => format!("MOVED {slot} {address}"),
+            Route::Ask(slot, address) => format!("ASK {slot} {address}"),
+            Route::CrossSlot => "CROSSSLOT Keys in request don't hash to the same slot".to_string(),
+            Route::Unassigned(_) => "CLUSTERDOWN Hash slot not served".to_string(),
+        };
+        Some(RespValue::Error(error))
+    }
+
+    /// `SLOTMIGRATE <host> <port> <slot|start-end>... [BATCH n] [AUTH password
+    /// | AUTH2 user password]` moves slots to another node in the background;
+    /// `SLOTMIGRATE STATUS` reports progress and `SLOTMIGRATE ABORT` stops it.
+    /// Works in cluster mode and, without redirections, standalone.
+    pub(super) fn slotmigrate(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() == 2 {
+            let reply = match upper(&args[1]).as_str() {
+                "STATUS" => migration_status(self.migrator.progress()),
+                "ABORT" if self.migrator.abort() => RespValue::Simple("OK".to_string()),
+                "ABORT" => RespValue::Error("ERR no slot migration is in progress".to_string()),
+                _ => RespValue::Error("ERR syntax error".to_string()),
+            };
+            return (reply, SessionAction::Continue);
+        }
+        if args.len() < 4 {
+            return (
+                RespValue::Error(
+                    "ERR wrong number of arguments for 'slotmigrate' command".to_string(),
+                ),
+                SessionAction::Continue,
+            );
+        }
+        let Some(port) = std::str::from_utf8(&args[2])
+            .ok()
+            .and_then(|port| port.parse::<u16>().ok())
+        else {
+            return (
+                RespValue::Error("ERR Invalid port".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let mut job = MigrationJob {
+            host: String::from_utf8_lossy(&args[1]).to_string(),
+            port,
+            slots: Vec::new(),
+            batch: 100,
+            user: None,
+            password: None,
+        };
+        let mut idx = 3;
+        while idx < args.len() {
+            let option = upper(&args[idx]);
+            let arg = |offset: usize| {
+                args.get(idx + offset)
+                    .map(|v| String::from_utf8_lossy(v).to_string())
+            };
+            match option.as_str() {
+                "BATCH" => match arg(1).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(batch) if batch > 0 => {
+                        job.batch = batch;
+                        idx += 2;
+                    }
+                    _ => {
+                        return (
+                            RespValue::Error(
+                                "ERR value is not an integer or out of range".to_string(),
+                            ),
+                            SessionAction::Continue,
+                        );
+                    }
+                },
+                "AUTH" if arg(1).is_some() => {
+                    job.password = arg(1);
+                    idx += 2;
+                }
+                "AUTH2" if arg(2).is_some() => {
+                    job.user = arg(1);
+                    job.password = arg(2);
+                    idx += 3;
+                }
+                _ => match parse_slot_range(&String::from_utf8_lossy(&args[idx])) {
+                    Some(range) => {
+                        job.slots.extend(range);
+                        idx += 1;
+                    }
+                    None => return (invalid_slot(), SessionAction::Continue),
+                },
+            }
+        }
+        if job.slots.is_empty() {
+            return (
+                RespValue::Error("ERR syntax error".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        let reply = match self
+            .migrator
+            .start(self.store.clone(), self.cluster.clone(), job)
+        {
+            Ok(()) => RespValue::Simple("OK".to_string()),
+            Err(e) => RespValue::Error(e),
+        };
+        (reply, SessionAction::Continue)
+    }
+}
+
+fn standalone_reply(sub: &str, run_id: &str) -> RespValue {
+    match sub {
+        "INFO" => RespValue::Bulk(Some(
+            "cluster_enabled:0\r\ncluster_state:ok\r\ncluster_slots_assigned:0\r\ncluster_known_nodes:1\r\ncluster_size:0\r\n"
+                .as_bytes()
+                .to_vec()
+            .into(),
+        )),
+        "MYID" => RespValue::Bulk(Some(Bytes::copy_from_slice(run_id.as_bytes()))),
+        "SLOTS" | "SHARDS" => RespValue::Array(Vec::new()),
+        "KEYSLOT" | "COUNTKEYSINSLOT" | "GETKEYSINSLOT" | "NODES" | "ADDSLOTS" | "DELSLOTS"
+        | "ADDSLOTSRANGE" | "DELSLOTSRANGE" | "SETSLOT" | "MEET" | "FORGET" => {
+            RespValue::Error("ERR This instance has cluster support disabled".to_string())
+        }
+        _ => RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
+    }
+}
+
+fn migration_status(progress: Option<MigrationProgress>) -> RespValue {
+    let Some(progress) = progress else {
+        return RespValue::Array(vec![bulk("state"), bulk("none")]);
+    };
+    let mut out = vec![
+        bulk("state"),
+        bulk(progress.state.name()),
+        bulk("target"),
+        RespValue::Bulk(Some(progress.target.into())),
+        bulk("slots"),
+        RespValue::Integer(progress.slots as i64),
+        bulk("slots_done"),
+        RespValue::Integer(progress.slots_done as i64),
+        bulk("keys_moved"),
+        RespValue::Integer(progress.keys_moved as i64),
+        bulk("current_slot"),
+        RespValue::Integer(progress.current_slot.map_or(-1, i64::from)),
+    ];
+    if let Some(error) = progress.error {
+        out.push(bulk("error"));
+        out.push(RespValue::Bulk(Some(error.into())));
+    }
+    RespValue::Array(out)
+}
+
+fn invalid_slot() -> RespValue {
+    RespValue::Error("ERR Invalid or out of range slot".to_string())
+}
+
+fn change_slots(cluster: &Cluster, sub: &str, slots: Vec<u16>) -> RespValue {
+    let result = if sub == "ADDSLOTS" {
+        cluster.add_slots(&slots)
+    } else {
+        cluster.del_slots(&slots)
+    };
+    match result {
+        Ok(()) => RespValue::Simple("OK".to_string()),
+        Err(e) => RespValue::Error(e),
+    }
+}
+
+/// `CLUSTER SETSLOT <slot> IMPORTING <id> | MIGRATING <id> | STABLE | NODE <id>`.
+fn set_slot(cluster: &Cluster, args: &[Vec<u8>]) -> RespValue {
+    let Some(slot) = parse_slot(&args[0]) else {
+        return invalid_slot();
+    };
+    let node = args
+        .get(2)
+        .map(|id| String::from_utf8_lossy(id).to_string());
+    let result = match (upper(&args[1]).as_str(), node) {
+        ("MIGRATING", Some(id)) => cluster.set_migrating(slot, &id),
+        ("IMPORTING", Some(id)) => cluster.set_importing(slot, &id),
+        ("NODE", Some(id)) => cluster.set_owner(slot, &id),
+        ("STABLE", None) => {
+            cluster.set_stable(slot);
+            Ok(())
+        }
+        _ => {
+            return RespValue::Error(
+                "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP"
+                    .to_string(),
+            );
+        }
+    };
+    match result {
+        Ok(()) => RespValue::Simple("OK".to_string()),
+        Err(e) => RespValue::Error(e),
+    }
+}
+
+fn cluster_info(cluster: &Cluster) -> RespValue {
+    let assigned = cluster.assigned_slots();
+    let state = if assigned == usize::from(SLOTS) {
+        "ok"
+    } else {
+        "fail"
+    };
+    let ranges = cluster.slot_ranges();
+    let mut owners: Vec<&str> = ranges.iter().map(|(_, _, node)| node.id.as_str()).collect();
+    owners.sort_unstable();
+    owners.dedup();
+    RespValue::Bulk(Some(
+        format!(
+            "cluster_enabled:1\r\ncluster_state:{state}\r\ncluster_slots_assigned:{assigned}\r\ncluster_slots_ok:{assigned}\r\ncluster_slots_pfail:0\r\ncluster_slots_fail:0\r\ncluster_known_nodes:{}\r\ncluster_size:{}\r\ncluster_current_epoch:0\r\ncluster_my_epoch:0\r\n",
+            cluster.nodes().len(),
+            owners.len()
+        )
+        .into_bytes()
+        .into(),
+    ))
+}
+
+/// `CLUSTER NODES`, one line per node as in Redis' nodes.conf. All nodes are
+/// masters and, without gossip, always reported as connected.
+fn cluster_nodes(cluster: &Cluster) -> RespValue {
+    let ranges = cluster.slot_ranges();
+    let myself = &cluster.myself().id;
+    let mut out = String::new();
+    for node in cluster.nodes() {
+        let flags = if node.id == *myself {
+            "myself,master"
+        } else {
+            "master"
+        };
+        out.push_str(&format!(
+            "{} {}:{}@{} {} - 0 0 0 connected",
+            node.id,
+            node.host,
+            node.port,
+            u32::from(node.port) + 10000,
+            flags
+        ));
+        for (start, end, _) in ranges.iter().filter(|(_, _, owner)| owner.id == node.id) {
+            if start == end {
+                out.push_str(&format!(" {start}"));
+            } else {
+                out.push_str(&format!(" {start}-{end}"));
+            }
+        }
+        if node.id == *myself {
+            for marker in cluster.open_slots() {
+                out.push(' ');
+                out.push_str(&marker);
+            }
+        }
+        out.push('\n');
+    }
+    RespValue::Bulk(Some(out.into()))
+}
+
+fn cluster_slots(cluster: &Cluster) -> RespValue {
+    RespValue::Array(
+        cluster
+            .slot_ranges()
+            .into_iter()
+            .map(|(start, end, node)| {
+                RespValue::Array(vec![
+                    RespValue::Integer(i64::from(start)),
+                    RespValue::Integer(i64::from(end)),
+                    RespValue::Array(vec![
+                        RespValue::Bulk(Some(node.host.into())),
+                        RespValue::Integer(i64::from(node.port)),
+                        RespValue::Bulk(Some(node.id.into())),
+                    ]),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn cluster_shards(cluster: &Cluster) -> RespValue {
+    let ranges = cluster.slot_ranges();
+    let shards = cluster
+        .nodes()
+        .into_iter()
+        .filter_map(|node| {
+            let slots: Vec<RespValue> = ranges
+                .iter()
+                .filter(|(_, _, owner)| owner.id == node.id)
+                .flat_map(|(start, end, _)| {
+                    [
+                        RespValue::Integer(i64::from(*start)),
+                        RespValue::Integer(i64::from(*end)),
+                    ]
+                })
+                .collect();
+            (!slots.is_empty()).then(|| {
+                RespValue::Array(vec![
+                    bulk("slots"),
+                    RespValue::Array(slots),
+                    bulk("nodes"),
+                    RespValue::Array(vec![shard_node(node)]),
+                ])
+            })
+        })
+        .collect();
+    RespValue::Array(shards)
+}
+
+fn shard_node(node: ClusterNode) -> RespValue {
+    RespValue::Array(vec![
+        bulk("id"),
+        RespValue::Bulk(Some(node.id.into())),
+        bulk("port"),
+        RespValue::Integer(i64::from(node.port)),
+        bulk("ip"),
+        RespValue::Bulk(Some(node.host.clone().into())),
+        bulk("endpoint"),
+        RespValue::Bulk(Some(node.host.into())),
+        bulk("role"),
+        bulk("master"),
+        bulk("replication-offset"),
+        RespValue::Integer(0),
+        bulk("health"),
+        bulk("online"),
+    ])
+}
+
+fn bulk(value: &str) -> RespValue {
+    RespValue::Bulk(Some(Bytes::copy_from_slice(value.as_bytes())))
+}
@@ -2,13 +2,6 @@ use super::*;
 
 impl CommandExecutor {
     pub(super) async fn expire(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'expire' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(seconds) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -29,13 +22,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn pexpire(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'pexpire' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(milliseconds) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -56,15 +42,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn pexpireat(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'pexpireat' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(ms_timestamp) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -85,15 +62,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn expireat(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'expireat' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(seconds_timestamp) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -114,13 +82,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn persist(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'persist' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         match self.store.persist(&args[1]).await {
             Ok(v) => (
                 RespValue::Integer(if v { 1 } else { 0 }),
@@ -134,12 +95,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn ttl(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'ttl' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         (
             RespValue::Integer(self.store.ttl(&args[1]).await),
             SessionAction::Continue,
@@ -147,12 +102,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn pttl(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'pttl' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         (
             RespValue::Integer(self.store.pttl(&args[1]).await),
             SessionAction::Continue,
@@ -0,0 +1,170 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+
+use super::registry::{BeginSearch, CommandSpec, FindKeys, KeySpec, NonRedis, Reply};
+use super::*;
+use crate::auth::SessionAuth;
+
+/// What an extension's handler returns.
+pub type ExtensionReply<'a> = Pin<Box<dyn Future<Output = RespValue> + Send + 'a>>;
+
+/// A command compiled into the server from outside `command.rs`. Once
+/// registered it is dispatched like a built-in one: `execute` checks its
+/// arity, authentication, ACLs on the command and its keys, the read-only
+/// and `maxmemory` guards from its flags, and `COMMAND` lists it.
+pub trait CommandExtension: Send + Sync + 'static {
+    /// Matched case-insensitively; must not be a built-in command.
+    fn name(&self) -> &str;
+
+    /// Redis' convention: `n` exactly `n` arguments including the name, `-n`
+    /// at least `n`.
+    fn arity(&self) -> i64;
+
+    /// Redis command flags: `write` makes replicas and read-only mode refuse
+    /// the command, `denyoom` refuses it over `maxmemory`, `readonly`,
+    /// `fast` and the rest only show in `COMMAND` and the ACL categories.
+    fn flags(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The key arguments as `(first, last, step)`, `last` negative to count
+    /// from the end; `(0, 0, 0)`, the default, for a command without keys.
+    fn key_range(&self) -> (i64, i64, i64) {
+        (0, 0, 0)
+    }
+
+    /// Runs the command; `args[0]` is the name as the client sent it.
+    /// Writes through the store are logged to the AOF and replicated.
+    fn call<'a>(&'a self, ctx: ExtensionContext<'a>, args: &'a [Vec<u8>]) -> ExtensionReply<'a>;
+}
+
+/// What an extension's handler may use of the server.
+pub struct ExtensionContext<'a> {
+    store: &'a Store,
+    user: Option<&'a str>,
+}
+
+impl<'a> ExtensionContext<'a> {
+    pub fn store(&self) -> &'a Store {
+        self.store
+    }
+
+    /// The ACL user the connection is logged in as; `None` for the default
+    /// user.
+    pub fn user(&self) -> Option<&'a str> {
+        self.user
+    }
+}
+
+struct Extension {
+    spec: &'static CommandSpec,
+    command: &'static dyn CommandExtension,
+}
+
+/// Registered extensions. They live as long as the process, like the
+/// built-in table, so their specs are leaked once at registration.
+static EXTENSIONS: RwLock<Vec<Extension>> = RwLock::new(Vec::new());
+
+/// Adds `command` to every executor in the process. Call it before the
+/// server starts: ACL rules naming a category list the commands in it when
+/// they are parsed.
+pub fn register_command(command: impl CommandExtension) -> Result<(), String> {
+    let name = command.name().to_ascii_uppercase();
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(format!("invalid command name '{}'", command.name()));
+    }
+    if command.arity() == 0 {
+        return Err(format!("command '{}' needs a non-zero arity", name));
+    }
+    let (first_key, last_key, step) = command.key_range();
+    if first_key < 0 || (first_key > 0 && step <= 0) {
+        return Err(format!("command '{}' has an invalid key range", name));
+    }
+
+    let mut extensions = EXTENSIONS.write().unwrap_or_else(|e| e.into_inner());
+    if registry::builtin(&name).is_some() || extensions.iter().any(|e| e.spec.name == name) {
+        return Err(format!("command '{}' already exists", name));
+    }
+    let flags = command.flags();
+    let key_specs: &'static [KeySpec] = if first_key > 0 {
+        Box::leak(Box::new([KeySpec {
+            begin: BeginSearch::Index(first_key),
+            find: FindKeys::Range {
+                last_key: if last_key < 0 {
+                    last_key
+                } else {
+                    last_key.saturating_sub(first_key).max(0)
+                },
+                step,
+            },
+            flags: if flags.contains(&"write") {
+                &["RW", "access", "update"]
+            } else {
+                &["RO", "access"]
+            },
+        }]))
+    } else {
+        &[]
+    };
+    let spec = Box::leak(Box::new(CommandSpec {
+        name: Box::leak(name.into_boxed_str()),
+        arity: command.arity(),
+        flags,
+        first_key,
+        last_key,
+        step,
+        key_specs,
+        non_redis: NonRedis::No,
+        handler: dispatch,
+    }));
+    extensions.push(Extension {
+        spec,
+        command: Box::leak(Box::new(command)),
+    });
+    Ok(())
+}
+
+/// The spec of a registered extension.
+pub(super) fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    let extensions = EXTENSIONS.read().unwrap_or_else(|e| e.into_inner());
+    extensions
+        .iter()
+        .find(|e| e.spec.name == name)
+        .map(|e| e.spec)
+}
+
+/// Specs of every registered extension, in registration order.
+pub(super) fn specs() -> Vec<&'static CommandSpec> {
+    let extensions = EXTENSIONS.read().unwrap_or_else(|e| e.into_inner());
+    extensions.iter().map(|e| e.spec).collect()
+}
+
+/// The handler of every extension spec: finds the extension by name.
+fn dispatch<'a>(
+    ex: &'a CommandExecutor,
+    args: &'a [Vec<u8>],
+    session: &'a mut SessionAuth,
+) -> Reply<'a> {
+    Box::pin(async move {
+        let name = upper(&args[0]);
+        let command = {
+            let extensions = EXTENSIONS.read().unwrap_or_else(|e| e.into_inner());
+            extensions
+                .iter()
+                .find(|e| e.spec.name == name)
+                .map(|e| e.command)
+        };
+        let Some(command) = command else {
+            return (
+                RespValue::Error(format!("ERR unknown command '{}'", name.to_lowercase())),
+                SessionAction::Continue,
+            );
+        };
+        let ctx = ExtensionContext {
+            store: &ex.store,
+            user: session.user.as_deref(),
+        };
+        (command.call(ctx, args).await, SessionAction::Continue)
+    })
+}
@@ -0,0 +1,52 @@
+use super::*;
+
+impl CommandExecutor {
+    /// `FOLLOW pattern [FROM token]` in non_redis_mode: the connection
+    /// becomes a push stream of the changes to keys matching `pattern`,
+    /// resuming after the change `token` names when given.
+    pub(super) fn follow(
+        &self,
+        args: &[Vec<u8>],
+        session: &SessionAuth,
+    ) -> (RespValue, SessionAction) {
+        let Some(feed) = self.store.change_feed() else {
+            return (
+                RespValue::Error("ERR this server keeps no change feed".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let from = match &args[2..] {
+            [] => None,
+            [keyword, token] if keyword.eq_ignore_ascii_case(b"FROM") => {
+                match feed.parse_token(token) {
+                    Some(seq) => Some(seq),
+                    None => return (stale_token(), SessionAction::Continue),
+                }
+            }
+            _ => {
+                return (
+                    RespValue::Error("ERR syntax error".to_string()),
+                    SessionAction::Continue,
+                );
+            }
+        };
+        let Some(following) = feed.follow(from) else {
+            return (stale_token(), SessionAction::Continue);
+        };
+        // The confirmation is written by the follower itself.
+        (
+            RespValue::Simple(String::new()),
+            SessionAction::Follow(Box::new(FollowRequest {
+                pattern: args[1].clone(),
+                feed: feed.clone(),
+                following,
+                auth: self.auth.clone(),
+                session: session.clone(),
+            })),
+        )
+    }
+}
+
+fn stale_token() -> RespValue {
+    RespValue::Error("ERR resume token is unknown or too old; follow again and resync".to_string())
+}
@@ -1,4 +1,15 @@
 use super::*;
+use crate::allocator;
+use crate::latency::LatencyHistogram;
+use crate::lazyfree::LazyFree;
+use crate::lockout::AuthLockout;
+use crate::replication::{FailoverState, ReplicaStatus, ReplicationFeed};
+use crate::runtime::RuntimeConfig;
+use crate::stats::ServerStats;
+use crate::store::StoreMetrics;
+use crate::tier::TierStats;
+use crate::tombstone::TombstoneMetrics;
+use crate::webhook::ExpiryWebhookMetrics;
 
 impl CommandExecutor {
     pub(super) async fn info(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
@@ -13,59 +24,107 @@ impl CommandExecutor {
             .get(1)
             .map(|s| String::from_utf8_lossy(s).to_ascii_lowercase())
             .unwrap_or_else(|| "default".to_string());
+        match self.info_text(&section).await {
+            Some(text) => (RespValue::Bulk(Some(text.into())), SessionAction::Continue),
+            None => (
+                RespValue::Error("ERR unsupported INFO section".to_string()),
+                SessionAction::Continue,
+            ),
+        }
+    }
 
+    /// The INFO report of a lower-case `section`, or of all of them for
+    /// `default` and `all`; `None` for an unknown section. The metrics
+    /// listener's `/stats` renders the same report as JSON.
+    pub async fn info_text(&self, section: &str) -> Option<String> {
         let metrics = self.store.metrics().await;
         let persistence = self.store.persistence_metrics();
         let commandstats = self.stats.command_stats_snapshot();
+        let latencies = self.stats.command_latency_snapshot(None);
+        let errorstats = self.stats.error_stats_snapshot();
+        let replica = self.replication_role().and_then(|role| role.status());
+        let failover = self
+            .replication_role()
+            .map_or(FailoverState::None, |role| role.failover_state());
+        let feed = self.store.replication_feed();
+        let replica_read_only = self.replica_read_only.load(Ordering::Relaxed);
         let uptime = self.stats.uptime_secs();
-        let lines = match section.as_str() {
+        let rate_limited: u64 = self
+            .stats
+            .rate_limited_snapshot()
+            .iter()
+            .map(|(_, count)| count)
+            .sum();
+        let webhook = self.store.expiry_webhook_metrics();
+        let tombstones = self.store.tombstone_metrics();
+        let lines = match section {
             "default" | "all" => vec![
-                server_section(uptime, &self.listen_addr),
+                server_section(
+                    uptime,
+                    self.stats.run_id(),
+                    &self.listen_addr,
+                    &self.runtime,
+                ),
                 clients_section(self.stats.connected_clients()),
-                memory_section(metrics.approx_memory_bytes),
+                memory_section(
+                    &metrics,
+                    self.max_memory_bytes,
+                    self.store.lazy_free(),
+                    self.store.tier_stats(),
+                    tombstones.as_ref(),
+                ),
                 stats_section(
-                    self.stats.total_connections(),
-                    self.stats.total_commands(),
-                    self.stats.total_command_usec(),
-                    self.stats.instantaneous_ops_per_sec(),
+                    &self.stats,
+                    rate_limited,
+                    self.auth.lockout(),
+                    webhook.as_ref(),
                 ),
                 commandstats_section(&commandstats),
+                errorstats_section(&errorstats),
+                latencystats_section(&latencies),
                 persistence_section(&persistence),
+                replication_section(replica.as_ref(), failover, feed, replica_read_only),
+                cluster_section(self.cluster.is_some()),
                 keyspace_section(metrics.keys, metrics.expiring_keys),
             ],
-            "server" => vec![server_section(uptime, &self.listen_addr)],
+            "server" => vec![server_section(
+                uptime,
+                self.stats.run_id(),
+                &self.listen_addr,
+                &self.runtime,
+            )],
             "clients" => vec![clients_section(self.stats.connected_clients())],
-            "memory" => vec![memory_section(metrics.approx_memory_bytes)],
+            "memory" => vec![memory_section(
+                &metrics,
+                self.max_memory_bytes,
+                self.store.lazy_free(),
+                self.store.tier_stats(),
+                tombstones.as_ref(),
+            )],
             "stats" => vec![stats_section(
-                self.stats.total_connections(),
-                self.stats.total_commands(),
-                self.stats.total_command_usec(),
-                self.stats.instantaneous_ops_per_sec(),
+                &self.stats,
+                rate_limited,
+                self.auth.lockout(),
+                webhook.as_ref(),
             )],
             "commandstats" => vec![commandstats_section(&commandstats)],
+            "errorstats" => vec![errorstats_section(&errorstats)],
+            "latencystats" => vec![latencystats_section(&latencies)],
             "persistence" => vec![persistence_section(&persistence)],
+            "replication" => vec![replication_section(
+                replica.as_ref(),
+                failover,
+                feed,
+                replica_read_only,
+            )],
+            "cluster" => vec![cluster_section(self.cluster.is_some())],
             "keyspace" => vec![keyspace_section(metrics.keys, metrics.expiring_keys)],
-            _ => {
-                return (
-                    RespValue::Error("ERR unsupported INFO section".to_string()),
-                    SessionAction::Continue,
-                );
-            }
+            _ => return None,
         };
-
-        (
-            RespValue::Bulk(Some(lines.join("\n").into_bytes())),
-            SessionAction::Continue,
-        )
+        Some(lines.join("\n"))
     }
 
     pub(super) fn select(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'select' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         let Some(db) = std::str::from_utf8(&args[1])
             .ok()
             .and_then(|v| v.parse::<u64>().ok())
@@ -86,15 +145,21 @@ impl CommandExecutor {
     }
 }
 
-fn server_section(uptime: u64, listen_addr: &str) -> String {
+fn server_section(uptime: u64, run_id: &str, listen_addr: &str, runtime: &RuntimeConfig) -> String {
     let days = uptime / 86_400;
     let port = listen_addr
         .rsplit_once(':')
         .and_then(|(_, p)| p.parse::<u16>().ok())
         .unwrap_or(6379);
     format!(
-        "# Server\nredis_version:7.2.0-fedis\nfedis_version:0.1.0\ntcp_port:{}\nuptime_in_seconds:{}\nuptime_in_days:{}",
-        port, uptime, days
+        "# Server\nredis_version:7.2.0-fedis\nfedis_version:0.1.0\nrun_id:{}\ntcp_port:{}\nuptime_in_seconds:{}\nuptime_in_days:{}\nruntime_flavor:{}\nruntime_worker_threads:{}\nruntime_max_blocking_threads:{}",
+        run_id,
+        port,
+        uptime,
+        days,
+        runtime.flavor_name(),
+        runtime.worker_threads,
+        runtime.max_blocking_threads
     )
 }
 
@@ -102,33 +167,106 @@ fn clients_section(connected_clients: usize) -> String {
     format!("# Clients\nconnected_clients:{}", connected_clients)
 }
 
-fn memory_section(memory_bytes: usize) -> String {
-    format!(
-        "# Memory\nused_memory:{}\nused_memory_human:{}",
-        memory_bytes,
-        human_bytes(memory_bytes)
-    )
+fn memory_section(
+    metrics: &StoreMetrics,
+    max_memory: Option<u64>,
+    lazy_free: &LazyFree,
+    tier: Option<&TierStats>,
+    tombstones: Option<&TombstoneMetrics>,
+) -> String {
+    let used = metrics.approx_memory_bytes;
+    let peak = metrics.peak_memory_bytes.max(used);
+    let max_memory = max_memory.unwrap_or(0);
+    let mut out = format!(
+        "# Memory\nused_memory:{}\nused_memory_human:{}\nused_memory_peak:{}\nused_memory_peak_human:{}\nused_memory_peak_perc:{:.2}%\nused_memory_overhead:{}\nused_memory_dataset:{}\nmaxmemory:{}\nmaxmemory_human:{}",
+        used,
+        human_bytes(used),
+        peak,
+        human_bytes(peak),
+        if peak == 0 {
+            100.0
+        } else {
+            used as f64 * 100.0 / peak as f64
+        },
+        metrics.overhead_bytes,
+        metrics.dataset_bytes,
+        max_memory,
+        human_bytes(max_memory as usize)
+    );
+    if let Some(allocated) = allocator::allocated() {
+        out.push_str(&format!("\nallocator_allocated:{allocated}"));
+    }
+    if let Some(rss) = allocator::resident() {
+        out.push_str(&format!(
+            "\nused_memory_rss:{}\nused_memory_rss_human:{}\nmem_fragmentation_ratio:{:.2}",
+            rss,
+            human_bytes(rss),
+            rss as f64 / used.max(1) as f64
+        ));
+    }
+    out.push_str(&format!(
+        "\nlazyfree_pending_objects:{}\nlazyfreed_objects:{}",
+        lazy_free.pending(),
+        lazy_free.freed()
+    ));
+    if let Some(tier) = tier {
+        out.push_str(&format!(
+            "\ntiered_spilled_keys:{}\ntiered_spilled_bytes:{}\ntiered_disk_bytes:{}\ntiered_disk_reads:{}",
+            tier.spilled_keys(),
+            tier.spilled_bytes(),
+            tier.disk_bytes(),
+            tier.disk_reads()
+        ));
+    }
+    if let Some(tombstones) = tombstones {
+        out.push_str(&format!(
+            "\ntombstone_keys:{}\ntombstone_bytes:{}",
+            tombstones.keys, tombstones.bytes
+        ));
+    }
+    out
 }
 
 fn stats_section(
-    total_connections: u64,
-    total_commands: u64,
-    total_command_usec: u64,
-    instantaneous_ops_per_sec: u64,
+    stats: &ServerStats,
+    rate_limited_commands: u64,
+    lockout: &AuthLockout,
+    webhook: Option<&ExpiryWebhookMetrics>,
 ) -> String {
+    let total_commands = stats.total_commands();
+    let total_command_usec = stats.total_command_usec();
     let usec_per_call = if total_commands == 0 {
         0.0
     } else {
         total_command_usec as f64 / total_commands as f64
     };
-    format!(
-        "# Stats\ntotal_connections_received:{}\ntotal_commands_processed:{}\ntotal_command_usec:{}\ninstantaneous_ops_per_sec:{}\nusec_per_call:{:.2}",
-        total_connections,
+    let mut out = format!(
+        "# Stats\ntotal_connections_received:{}\ntotal_commands_processed:{}\ntotal_command_usec:{}\ninstantaneous_ops_per_sec:{}\nusec_per_call:{:.2}\nrate_limited_commands:{}\ntotal_error_replies:{}\nacl_access_denied_auth:{}\nauth_lockout_rejections:{}",
+        stats.total_connections(),
         total_commands,
         total_command_usec,
-        instantaneous_ops_per_sec,
-        usec_per_call
-    )
+        stats.instantaneous_ops_per_sec(),
+        usec_per_call,
+        rate_limited_commands,
+        stats.total_error_replies(),
+        lockout.failures(),
+        lockout.rejected()
+    );
+    if let Some(webhook) = webhook {
+        out.push_str(&format!(
+            "\nexpiry_webhook_pending:{}\nexpiry_webhook_sent:{}\nexpiry_webhook_dropped:{}\nexpiry_webhook_failures:{}\nexpiry_webhook_last_status:{}",
+            webhook.pending,
+            webhook.sent,
+            webhook.dropped,
+            webhook.failures,
+            if webhook.last_ok { "ok" } else { "err" },
+        ));
+    }
+    out
+}
+
+fn cluster_section(enabled: bool) -> String {
+    format!("# Cluster\ncluster_enabled:{}", u8::from(enabled))
 }
 
 fn keyspace_section(keys: usize, expiring_keys: usize) -> String {
@@ -152,24 +290,120 @@ fn commandstats_section(commandstats: &[(String, u64, u64)]) -> String {
     out
 }
 
+/// `errorstat_<PREFIX>:count=<n>` per error prefix, as Redis 7 reports it.
+fn errorstats_section(errorstats: &[(String, u64)]) -> String {
+    let mut out = String::from("# Errorstats");
+    for (prefix, count) in errorstats {
+        out.push_str(&format!("\nerrorstat_{}:count={}", prefix, count));
+    }
+    out
+}
+
+/// `latency_percentiles_usec_<command>:p50=..,p99=..,p99.9=..` per command,
+/// as Redis 7 reports it.
+fn latencystats_section(latencies: &[(String, LatencyHistogram)]) -> String {
+    let mut out = String::from("# Latencystats");
+    for (command, latency) in latencies {
+        out.push_str(&format!(
+            "\nlatency_percentiles_usec_{}:p50={:.3},p99={:.3},p99.9={:.3}",
+            command,
+            latency.percentile(50.0) as f64,
+            latency.percentile(99.0) as f64,
+            latency.percentile(99.9) as f64
+        ));
+    }
+    out
+}
+
 fn persistence_section(metrics: &crate::store::PersistenceMetrics) -> String {
-    let last_bgsave_status = if metrics.snapshot_fail_count == 0 {
-        "ok"
-    } else {
-        "err"
-    };
-    format!(
-        "# Persistence\naof_enabled:{}\naof_rewrite_in_progress:{}\naof_rewrites:{}\naof_rewrite_failures:{}\naof_last_rewrite_epoch_sec:{}\nrdb_bgsave_in_progress:{}\nrdb_saves:{}\nrdb_last_save_time:{}\nrdb_last_bgsave_status:{}",
+    let status = |ok: bool| if ok { "ok" } else { "err" };
+    let mut out = format!(
+        "# Persistence\naof_enabled:{}\naof_rewrite_in_progress:{}\naof_rewrites:{}\naof_rewrite_failures:{}\naof_last_rewrite_epoch_sec:{}\naof_last_bgrewrite_status:{}\naof_last_write_status:{}\naof_pending_writes:{}\naof_last_fsync_age_ms:{}\nrdb_changes_since_last_save:{}\nrdb_bgsave_in_progress:{}\nrdb_saves:{}\nrdb_last_save_time:{}\nrdb_last_bgsave_status:{}",
         if metrics.aof_enabled { 1 } else { 0 },
         if metrics.rewrite_in_progress { 1 } else { 0 },
         metrics.rewrite_count,
         metrics.rewrite_fail_count,
         metrics.last_rewrite_epoch_sec,
+        status(metrics.last_rewrite_ok),
+        status(metrics.aof_last_write_ok),
+        metrics.aof_pending_writes,
+        metrics.aof_last_fsync_age_ms,
+        metrics.changes_since_last_save,
         if metrics.snapshot_in_progress { 1 } else { 0 },
         metrics.snapshot_count,
         metrics.last_snapshot_epoch_sec,
-        last_bgsave_status,
-    )
+        status(metrics.last_snapshot_ok),
+    );
+    // Like Redis, the sizes are only reported while the AOF is in use.
+    if metrics.aof_enabled {
+        out.push_str(&format!(
+            "\naof_base_size:{}\naof_current_size:{}",
+            metrics.aof_base_size, metrics.aof_current_size
+        ));
+    }
+    if let Some(sink) = &metrics.write_behind {
+        out.push_str(&format!(
+            "\nwrite_behind_pending:{}\nwrite_behind_sent:{}\nwrite_behind_dropped:{}\nwrite_behind_failures:{}\nwrite_behind_last_status:{}",
+            sink.pending,
+            sink.sent,
+            sink.dropped,
+            sink.failures,
+            status(sink.last_ok),
+        ));
+    }
+    out
+}
+
+fn replication_section(
+    replica: Option<&ReplicaStatus>,
+    failover: FailoverState,
+    feed: Option<&ReplicationFeed>,
+    replica_read_only: bool,
+) -> String {
+    let replicas = feed.map(ReplicationFeed::replicas).unwrap_or_default();
+    let mut out = match replica {
+        Some(replica) => {
+            let mut out = format!(
+                "# Replication\nrole:slave\nmaster_host:{}\nmaster_port:{}\nmaster_link_status:{}\nmaster_last_io_seconds_ago:{}\nmaster_sync_in_progress:0\nslave_read_only:{}\nslave_priority:{}\nslave_repl_offset:{}",
+                replica.host,
+                replica.port,
+                if replica.link_up { "up" } else { "down" },
+                if replica.link_up {
+                    replica.last_io_secs as i64
+                } else {
+                    -1
+                },
+                if replica_read_only { 1 } else { 0 },
+                replica.priority,
+                replica.offset,
+            );
+            if let Some(down) = replica.down_secs {
+                out.push_str(&format!("\nmaster_link_down_since_seconds:{}", down));
+            }
+            out.push_str(&format!("\nconnected_slaves:{}", replicas.len()));
+            out
+        }
+        None => format!(
+            "# Replication\nrole:master\nconnected_slaves:{}\nmaster_failover_state:{}",
+            replicas.len(),
+            failover.name()
+        ),
+    };
+    // Sentinel discovers replicas from these lines.
+    for (idx, link) in replicas.iter().enumerate() {
+        out.push_str(&format!(
+            "\nslave{}:ip={},port={},state=online,offset={},lag={}",
+            idx, link.host, link.port, link.acked, link.lag
+        ));
+    }
+    if replica.is_none() {
+        out.push_str(&format!(
+            "\nmaster_replid:{}\nmaster_repl_offset:{}",
+            feed.map(ReplicationFeed::replid).unwrap_or_default(),
+            feed.map_or(0, ReplicationFeed::offset)
+        ));
+    }
+    out
 }
 
 fn human_bytes(bytes: usize) -> String {
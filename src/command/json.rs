@@ -1,103 +1,617 @@
 use super::*;
+use crate::jsonpath::{self, JsonError, JsonFormat, JsonPath};
+use crate::store::SetCondition;
+use serde_json::Value as JsonValue;
 
 impl CommandExecutor {
+    /// `JSON.SET key path value [NX|XX]`. A path that matches replaces every
+    /// match; one that does not may add a member to the objects its parent
+    /// matches. Replies nil when the condition or the path stops the write.
     pub(super) async fn json_set(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 4 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'json.set' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-        if !is_root_path(&args[2]) {
-            return (
-                RespValue::Error("ERR only root path is supported".to_string()),
-                SessionAction::Continue,
-            );
+        let condition = match args.get(4).map(|raw| upper(raw)).as_deref() {
+            _ if args.len() > 5 => None,
+            None => Some(SetCondition::None),
+            Some("NX") => Some(SetCondition::Nx),
+            Some("XX") => Some(SetCondition::Xx),
+            Some(_) => None,
+        };
+        let Some(condition) = condition else {
+            return json_error(JsonError::Reply("ERR syntax error".to_string()));
+        };
+        let path = match JsonPath::parse(&args[2]) {
+            Ok(path) => path,
+            Err(e) => return json_error(e),
+        };
+        let Ok(value) = serde_json::from_slice::<JsonValue>(&args[3]) else {
+            return json_error(JsonError::Reply("ERR invalid JSON".to_string()));
+        };
+
+        let result = self
+            .store
+            .json_update(&args[1], |doc| {
+                let Some(root) = doc else {
+                    if !path.is_root() {
+                        return Err(JsonError::Reply(
+                            "ERR new objects must be created at the root".to_string(),
+                        ));
+                    }
+                    if matches!(condition, SetCondition::Xx) {
+                        return Ok(false);
+                    }
+                    *doc = Some(value);
+                    return Ok(true);
+                };
+                let locations = path.locate(root);
+                if !locations.is_empty() {
+                    if matches!(condition, SetCondition::Nx) {
+                        return Ok(false);
+                    }
+                    for location in &locations {
+                        if let Some(target) = jsonpath::get_mut(root, location) {
+                            *target = value.clone();
+                        }
+                    }
+                    return Ok(true);
+                }
+                if matches!(condition, SetCondition::Xx) {
+                    return Ok(false);
+                }
+                let Some((parent, key)) = path.parent_and_key() else {
+                    return Ok(false);
+                };
+                let mut added = false;
+                for location in parent.locate(root) {
+                    if let Some(JsonValue::Object(map)) = jsonpath::get_mut(root, &location) {
+                        map.insert(key.to_string(), value.clone());
+                        added = true;
+                    }
+                }
+                Ok(added)
+            })
+            .await;
+        match result {
+            Ok(true) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Ok(false) => (RespValue::Bulk(None), SessionAction::Continue),
+            Err(e) => json_error(e),
         }
+    }
 
-        match self.store.json_set_root(args[1].clone(), &args[3]).await {
-            Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
-            Err(_) => (
-                RespValue::Error("ERR invalid JSON".to_string()),
-                SessionAction::Continue,
-            ),
+    /// `JSON.MERGE key path value`: applies `value` as an RFC 7386 merge
+    /// patch to every match, in one logged write. A `null` patch deletes the
+    /// matches; a path that matches nothing may add a member as `JSON.SET`
+    /// does.
+    pub(super) async fn json_merge(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let path = match JsonPath::parse(&args[2]) {
+            Ok(path) => path,
+            Err(e) => return json_error(e),
+        };
+        let Ok(patch) = serde_json::from_slice::<JsonValue>(&args[3]) else {
+            return json_error(JsonError::Reply("ERR invalid JSON".to_string()));
+        };
+
+        let result = self
+            .store
+            .json_update(&args[1], |doc| {
+                let Some(root) = doc else {
+                    if !path.is_root() {
+                        return Err(JsonError::Reply(
+                            "ERR new objects must be created at the root".to_string(),
+                        ));
+                    }
+                    if !patch.is_null() {
+                        let mut created = JsonValue::Null;
+                        jsonpath::merge_patch(&mut created, &patch);
+                        *doc = Some(created);
+                    }
+                    return Ok(true);
+                };
+                if patch.is_null() {
+                    if path.is_root() {
+                        *doc = None;
+                    } else {
+                        let locations = path.locate(root);
+                        jsonpath::remove(root, locations);
+                    }
+                    return Ok(true);
+                }
+                let locations = path.locate(root);
+                if !locations.is_empty() {
+                    for location in &locations {
+                        if let Some(target) = jsonpath::get_mut(root, location) {
+                            jsonpath::merge_patch(target, &patch);
+                        }
+                    }
+                    return Ok(true);
+                }
+                let Some((parent, key)) = path.parent_and_key() else {
+                    return Ok(false);
+                };
+                let mut added = false;
+                for location in parent.locate(root) {
+                    if let Some(JsonValue::Object(map)) = jsonpath::get_mut(root, &location) {
+                        let mut created = JsonValue::Null;
+                        jsonpath::merge_patch(&mut created, &patch);
+                        map.insert(key.to_string(), created);
+                        added = true;
+                    }
+                }
+                Ok(added)
+            })
+            .await;
+        match result {
+            Ok(true) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Ok(false) => (RespValue::Bulk(None), SessionAction::Continue),
+            Err(e) => json_error(e),
         }
     }
 
+    /// `JSON.GET key [INDENT s] [NEWLINE s] [SPACE s] [path ...]`. One path
+    /// replies with its value (legacy paths) or the array of its matches (`$`
+    /// paths); several reply with an object keyed by path. The whole
+    /// document, unformatted, is replied with the text stored next to it,
+    /// without serializing it again.
     pub(super) async fn json_get(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 || args.len() > 3 {
+        let mut layout = JsonFormat::default();
+        let mut raw_paths = Vec::new();
+        let mut idx = 2;
+        while idx < args.len() {
+            let option = match upper(&args[idx]).as_str() {
+                "INDENT" => Some(&mut layout.indent),
+                "NEWLINE" => Some(&mut layout.newline),
+                "SPACE" => Some(&mut layout.space),
+                // RedisJSON never escapes non-ASCII text and ignores it too.
+                "NOESCAPE" => {
+                    idx += 1;
+                    continue;
+                }
+                _ => None,
+            };
+            match option {
+                Some(option) => {
+                    let Some(value) = args.get(idx + 1) else {
+                        return json_error(JsonError::Reply("ERR syntax error".to_string()));
+                    };
+                    *option = String::from_utf8_lossy(value).into_owned();
+                    idx += 2;
+                }
+                None => {
+                    raw_paths.push(args[idx].clone());
+                    idx += 1;
+                }
+            }
+        }
+        let paths = match parse_paths(&raw_paths) {
+            Ok(paths) => paths,
+            Err(e) => return json_error(e),
+        };
+        if let [path] = paths.as_slice()
+            && path.is_legacy()
+            && path.is_root()
+            && layout.is_compact()
+        {
+            return match self.store.json_text(&args[1]).await {
+                Ok(text) => (RespValue::Bulk(text), SessionAction::Continue),
+                Err(e) => json_error(e),
+            };
+        }
+        let doc = match self.store.json_get(&args[1]).await {
+            Ok(Some(doc)) => doc,
+            Ok(None) => return (RespValue::Bulk(None), SessionAction::Continue),
+            Err(e) => return json_error(e),
+        };
+        let reply = if let [path] = paths.as_slice() {
+            match json_matches(path, &doc) {
+                Ok(value) => value,
+                Err(e) => return json_error(e),
+            }
+        } else {
+            let mut out = serde_json::Map::new();
+            for path in &paths {
+                match json_matches(path, &doc) {
+                    Ok(value) => {
+                        out.insert(path.as_str().to_string(), value);
+                    }
+                    Err(e) => return json_error(e),
+                }
+            }
+            JsonValue::Object(out)
+        };
+        (
+            RespValue::Bulk(Some(Bytes::from(jsonpath::format(&reply, &layout)))),
+            SessionAction::Continue,
+        )
+    }
+
+    /// `JSON.RESP key [path]`: the value as RESP instead of text, for
+    /// clients that walk it without a JSON parser. A legacy path replies
+    /// with its first match, a `$` path with an array of every match.
+    pub(super) async fn json_resp(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 3 {
             return (
                 RespValue::Error(
-                    "ERR wrong number of arguments for 'json.get' command".to_string(),
+                    "ERR wrong number of arguments for 'json.resp' command".to_string(),
                 ),
                 SessionAction::Continue,
             );
         }
-        if args.len() == 3 && !is_root_path(&args[2]) {
+        let path = match parse_paths(&args[2..]) {
+            Ok(mut paths) => paths.remove(0),
+            Err(e) => return json_error(e),
+        };
+        let doc = match self.store.json_get(&args[1]).await {
+            Ok(Some(doc)) => doc,
+            Ok(None) => return (RespValue::Bulk(None), SessionAction::Continue),
+            Err(e) => return json_error(e),
+        };
+        let matches = path.select(&doc);
+        let reply = if path.is_legacy() {
+            match matches.first() {
+                Some(value) => resp_value(value),
+                None => return json_error(path.missing()),
+            }
+        } else {
+            RespValue::Array(matches.into_iter().map(resp_value).collect())
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `JSON.DEL key [path]`: how many values went. Deleting the root
+    /// deletes the key.
+    pub(super) async fn json_del(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 3 {
             return (
-                RespValue::Error("ERR only root path is supported".to_string()),
+                RespValue::Error(
+                    "ERR wrong number of arguments for 'json.del' command".to_string(),
+                ),
                 SessionAction::Continue,
             );
         }
-        (
-            RespValue::Bulk(self.store.json_get_root(&args[1]).await),
-            SessionAction::Continue,
-        )
+        let path = match parse_paths(&args[2..]) {
+            Ok(mut paths) => paths.remove(0),
+            Err(e) => return json_error(e),
+        };
+        let result = self
+            .store
+            .json_update(&args[1], |doc| {
+                let Some(root) = doc else {
+                    return Ok(0);
+                };
+                if path.is_root() {
+                    *doc = None;
+                    return Ok(1);
+                }
+                let locations = path.locate(root);
+                Ok(jsonpath::remove(root, locations) as i64)
+            })
+            .await;
+        match result {
+            Ok(n) => (RespValue::Integer(n), SessionAction::Continue),
+            Err(e) => json_error(e),
+        }
     }
 
-    pub(super) async fn json_del(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 || args.len() > 3 {
+    /// `JSON.TYPE key [path]`: the type name for a legacy path, an array of
+    /// them for a `$` path.
+    pub(super) async fn json_type(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 3 {
             return (
                 RespValue::Error(
-                    "ERR wrong number of arguments for 'json.del' command".to_string(),
+                    "ERR wrong number of arguments for 'json.type' command".to_string(),
                 ),
                 SessionAction::Continue,
             );
         }
-        if args.len() == 3 && !is_root_path(&args[2]) {
+        let path = match parse_paths(&args[2..]) {
+            Ok(mut paths) => paths.remove(0),
+            Err(e) => return json_error(e),
+        };
+        let doc = match self.store.json_get(&args[1]).await {
+            Ok(Some(doc)) => doc,
+            Ok(None) => return (RespValue::Bulk(None), SessionAction::Continue),
+            Err(e) => return json_error(e),
+        };
+        let types = path.select(&doc).into_iter().map(|value| {
+            RespValue::Bulk(Some(Bytes::from_static(
+                jsonpath::type_name(value).as_bytes(),
+            )))
+        });
+        let reply = if path.is_legacy() {
+            types.into_iter().next().unwrap_or(RespValue::Bulk(None))
+        } else {
+            RespValue::Array(types.collect())
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `JSON.OBJKEYS key [path]`: the member names of each matched object.
+    pub(super) async fn json_objkeys(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        self.json_inspect(args, "json.objkeys", "object", |value| match value {
+            JsonValue::Object(map) => Ok(RespValue::Array(
+                map.keys()
+                    .map(|key| RespValue::Bulk(Some(Bytes::from(key.clone()))))
+                    .collect(),
+            )),
+            other => Err(jsonpath::type_name(other)),
+        })
+        .await
+    }
+
+    /// `JSON.OBJLEN key [path]`: how many members each matched object has.
+    pub(super) async fn json_objlen(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        self.json_inspect(args, "json.objlen", "object", |value| match value {
+            JsonValue::Object(map) => Ok(RespValue::Integer(map.len() as i64)),
+            other => Err(jsonpath::type_name(other)),
+        })
+        .await
+    }
+
+    /// `JSON.STRLEN key [path]`: the length in bytes of each matched string.
+    pub(super) async fn json_strlen(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        self.json_inspect(args, "json.strlen", "string", |value| match value {
+            JsonValue::String(text) => Ok(RespValue::Integer(text.len() as i64)),
+            other => Err(jsonpath::type_name(other)),
+        })
+        .await
+    }
+
+    /// `JSON.STRAPPEND key [path] value`: appends the JSON string `value` to
+    /// each matched string, replying with the new lengths.
+    pub(super) async fn json_strappend(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let (path, value) = match args {
+            [_, _, value] => (JsonPath::root(), value),
+            [_, _, path, value] => match JsonPath::parse(path) {
+                Ok(path) => (path, value),
+                Err(e) => return json_error(e),
+            },
+            _ => {
+                return (
+                    RespValue::Error(
+                        "ERR wrong number of arguments for 'json.strappend' command".to_string(),
+                    ),
+                    SessionAction::Continue,
+                );
+            }
+        };
+        let Ok(JsonValue::String(suffix)) = serde_json::from_slice::<JsonValue>(value) else {
+            return json_error(JsonError::Reply(
+                "ERR the value to append must be a JSON string".to_string(),
+            ));
+        };
+        let result = self
+            .store
+            .json_update(&args[1], |doc| {
+                let Some(root) = doc else {
+                    return Err(missing_key());
+                };
+                let mut results = Vec::new();
+                for location in path.locate(root) {
+                    results.push(match jsonpath::get_mut(root, &location) {
+                        Some(JsonValue::String(text)) => {
+                            text.push_str(&suffix);
+                            Ok(RespValue::Integer(text.len() as i64))
+                        }
+                        Some(other) => Err(jsonpath::type_name(other)),
+                        None => continue,
+                    });
+                    if path.is_legacy() {
+                        break;
+                    }
+                }
+                Ok(results)
+            })
+            .await;
+        match result {
+            Ok(results) => (per_match(&path, results, "string"), SessionAction::Continue),
+            Err(e) => json_error(e),
+        }
+    }
+
+    /// `JSON.TOGGLE key [path]`: flips each matched boolean. `$` paths
+    /// reply with the new values as 1 or 0, legacy paths with `true` or
+    /// `false`.
+    pub(super) async fn json_toggle(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 3 {
             return (
-                RespValue::Error("ERR only root path is supported".to_string()),
+                RespValue::Error(
+                    "ERR wrong number of arguments for 'json.toggle' command".to_string(),
+                ),
                 SessionAction::Continue,
             );
         }
-        match self.store.json_del_root(&args[1]).await {
-            Ok(v) => (RespValue::Integer(v), SessionAction::Continue),
-            Err(e) => (
-                RespValue::Error(format!("ERR internal: {}", e)),
+        let path = match parse_paths(&args[2..]) {
+            Ok(mut paths) => paths.remove(0),
+            Err(e) => return json_error(e),
+        };
+        let legacy = path.is_legacy();
+        let result = self
+            .store
+            .json_update(&args[1], |doc| {
+                let Some(root) = doc else {
+                    return Err(missing_key());
+                };
+                let mut results = Vec::new();
+                for location in path.locate(root) {
+                    results.push(match jsonpath::get_mut(root, &location) {
+                        Some(JsonValue::Bool(flag)) => {
+                            *flag = !*flag;
+                            Ok(if legacy {
+                                RespValue::Bulk(Some(Bytes::from(flag.to_string())))
+                            } else {
+                                RespValue::Integer(i64::from(*flag))
+                            })
+                        }
+                        Some(other) => Err(jsonpath::type_name(other)),
+                        None => continue,
+                    });
+                    if legacy {
+                        break;
+                    }
+                }
+                Ok(results)
+            })
+            .await;
+        match result {
+            Ok(results) => (
+                per_match(&path, results, "boolean"),
                 SessionAction::Continue,
             ),
+            Err(e) => json_error(e),
         }
     }
 
-    pub(super) async fn json_type(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 || args.len() > 3 {
+    /// `JSON.CLEAR key [path]`: empties each matched array or object and
+    /// zeroes each matched number, replying with how many it cleared.
+    /// Strings, booleans and nulls are left alone.
+    pub(super) async fn json_clear(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 3 {
             return (
                 RespValue::Error(
-                    "ERR wrong number of arguments for 'json.type' command".to_string(),
+                    "ERR wrong number of arguments for 'json.clear' command".to_string(),
                 ),
                 SessionAction::Continue,
             );
         }
-        if args.len() == 3 && !is_root_path(&args[2]) {
+        let path = match parse_paths(&args[2..]) {
+            Ok(mut paths) => paths.remove(0),
+            Err(e) => return json_error(e),
+        };
+        let result = self
+            .store
+            .json_update(&args[1], |doc| {
+                let Some(root) = doc else {
+                    return Err(missing_key());
+                };
+                let mut cleared = 0;
+                for location in path.locate(root) {
+                    match jsonpath::get_mut(root, &location) {
+                        Some(JsonValue::Array(items)) => items.clear(),
+                        Some(JsonValue::Object(map)) => map.clear(),
+                        Some(number @ JsonValue::Number(_)) => *number = JsonValue::from(0),
+                        _ => continue,
+                    }
+                    cleared += 1;
+                }
+                Ok(cleared)
+            })
+            .await;
+        match result {
+            Ok(n) => (RespValue::Integer(n), SessionAction::Continue),
+            Err(e) => json_error(e),
+        }
+    }
+
+    /// Replies with `inspect` of each match of the optional path; nil when
+    /// the key is missing.
+    async fn json_inspect(
+        &self,
+        args: &[Vec<u8>],
+        name: &str,
+        expected: &str,
+        inspect: impl Fn(&JsonValue) -> Result<RespValue, &'static str>,
+    ) -> (RespValue, SessionAction) {
+        if args.len() > 3 {
             return (
-                RespValue::Error("ERR only root path is supported".to_string()),
+                RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    name
+                )),
                 SessionAction::Continue,
             );
         }
-        (
-            RespValue::Bulk(
-                self.store
-                    .json_type_root(&args[1])
-                    .await
-                    .map(|v| v.as_bytes().to_vec()),
-            ),
-            SessionAction::Continue,
-        )
+        let path = match parse_paths(&args[2..]) {
+            Ok(mut paths) => paths.remove(0),
+            Err(e) => return json_error(e),
+        };
+        let doc = match self.store.json_get(&args[1]).await {
+            Ok(Some(doc)) => doc,
+            Ok(None) => return (RespValue::Bulk(None), SessionAction::Continue),
+            Err(e) => return json_error(e),
+        };
+        let results = path.select(&doc).into_iter().map(inspect).collect();
+        (per_match(&path, results, expected), SessionAction::Continue)
+    }
+}
+
+/// The paths in `raw`, or the root when there are none.
+fn parse_paths(raw: &[Vec<u8>]) -> Result<Vec<JsonPath>, JsonError> {
+    if raw.is_empty() {
+        return Ok(vec![JsonPath::root()]);
+    }
+    raw.iter().map(|path| JsonPath::parse(path)).collect()
+}
+
+/// What `JSON.GET` shows for `path`: the first match of a legacy path,
+/// which must match, or the array of all matches.
+fn json_matches(path: &JsonPath, doc: &JsonValue) -> Result<JsonValue, JsonError> {
+    let matches = path.select(doc);
+    if path.is_legacy() {
+        return matches
+            .first()
+            .map(|value| (*value).clone())
+            .ok_or_else(|| path.missing());
+    }
+    Ok(JsonValue::Array(matches.into_iter().cloned().collect()))
+}
+
+/// Folds one result per match into the reply, each an error naming the
+/// type found when the value was not of the `expected` type. `$` paths get
+/// an array with nil for those; legacy paths get the first result, and an
+/// error when it had the wrong type or nothing matched.
+fn per_match(
+    path: &JsonPath,
+    results: Vec<Result<RespValue, &'static str>>,
+    expected: &str,
+) -> RespValue {
+    if !path.is_legacy() {
+        return RespValue::Array(
+            results
+                .into_iter()
+                .map(|result| result.unwrap_or(RespValue::Bulk(None)))
+                .collect(),
+        );
+    }
+    match results.into_iter().next() {
+        Some(Ok(reply)) => reply,
+        Some(Err(found)) => RespValue::Error(format!(
+            "ERR wrong type of path value - expected {} but found {}",
+            expected, found
+        )),
+        None => RespValue::Error(path.missing().reply()),
+    }
+}
+
+fn missing_key() -> JsonError {
+    JsonError::Reply("ERR could not perform this operation on a key that doesn't exist".to_string())
+}
+
+/// A JSON value as RedisJSON's `JSON.RESP` lays it out: arrays lead with
+/// `[` and objects with `{` followed by their members as name/value pairs;
+/// booleans are `true`/`false` simple strings and non-integers bulk strings.
+fn resp_value(value: &JsonValue) -> RespValue {
+    match value {
+        JsonValue::Null => RespValue::Bulk(None),
+        JsonValue::Bool(b) => RespValue::Simple(b.to_string()),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(n) => RespValue::Integer(n),
+            None => RespValue::Bulk(Some(Bytes::from(n.to_string()))),
+        },
+        JsonValue::String(text) => RespValue::Bulk(Some(Bytes::from(text.clone()))),
+        JsonValue::Array(items) => RespValue::Array(
+            std::iter::once(RespValue::Simple("[".to_string()))
+                .chain(items.iter().map(resp_value))
+                .collect(),
+        ),
+        JsonValue::Object(map) => {
+            let mut out = vec![RespValue::Simple("{".to_string())];
+            for (key, member) in map {
+                out.push(RespValue::Bulk(Some(Bytes::from(key.clone()))));
+                out.push(resp_value(member));
+            }
+            RespValue::Array(out)
+        }
     }
 }
 
-fn is_root_path(path: &[u8]) -> bool {
-    path == b"$" || path == b"."
+fn json_error(e: JsonError) -> (RespValue, SessionAction) {
+    (RespValue::Error(e.reply()), SessionAction::Continue)
 }
@@ -1,13 +1,10 @@
 use super::*;
+use crate::encoding::ValueType;
+use crate::rdb::{dump_payload, parse_dump_payload};
+use crate::store::{SetCondition, Undelete, type_name};
 
 impl CommandExecutor {
     pub(super) async fn del(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'del' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         match self.store.del(&args[1..]).await {
             Ok(v) => (RespValue::Integer(v), SessionAction::Continue),
             Err(e) => (
@@ -18,16 +15,40 @@ impl CommandExecutor {
     }
 
     pub(super) async fn unlink(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        self.del(args).await
+        match self.store.unlink(&args[1..]).await {
+            Ok(v) => (RespValue::Integer(v), SessionAction::Continue),
+            Err(e) => (
+                RespValue::Error(format!("ERR internal: {}", e)),
+                SessionAction::Continue,
+            ),
+        }
     }
 
-    pub(super) async fn exists(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
+    /// `FLUSHALL` and `FLUSHDB` (there is only db 0): `ASYNC` frees the old
+    /// keyspace on the lazyfree thread, `SYNC` (the default) before replying.
+    pub(super) async fn flush(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let lazy = match args.get(1).map(|mode| upper(mode)).as_deref() {
+            _ if args.len() > 2 => None,
+            None | Some("SYNC") => Some(false),
+            Some("ASYNC") => Some(true),
+            Some(_) => None,
+        };
+        let Some(lazy) = lazy else {
             return (
-                RespValue::Error("ERR wrong number of arguments for 'exists' command".to_string()),
+                RespValue::Error("ERR syntax error".to_string()),
                 SessionAction::Continue,
             );
+        };
+        match self.store.flush(lazy).await {
+            Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Err(e) => (
+                RespValue::Error(format!("ERR internal: {}", e)),
+                SessionAction::Continue,
+            ),
         }
+    }
+
+    pub(super) async fn exists(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
         (
             RespValue::Integer(self.store.exists(&args[1..]).await),
             SessionAction::Continue,
@@ -35,28 +56,18 @@ impl CommandExecutor {
     }
 
     pub(super) async fn keys(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'keys' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let keys = self.store.keys(&args[1]).await;
         (
-            RespValue::Array(keys.into_iter().map(|k| RespValue::Bulk(Some(k))).collect()),
+            RespValue::Array(
+                keys.into_iter()
+                    .map(|k| RespValue::Bulk(Some(k.into())))
+                    .collect(),
+            ),
             SessionAction::Continue,
         )
     }
 
     pub(super) async fn scan(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'scan' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(cursor) = parse_u64(&args[1]) else {
             return (
                 RespValue::Error("ERR invalid cursor".to_string()),
@@ -110,12 +121,12 @@ impl CommandExecutor {
         let result = self.store.scan(cursor, &pattern, count).await;
         (
             RespValue::Array(vec![
-                RespValue::Bulk(Some(result.next_cursor.to_string().into_bytes())),
+                RespValue::Bulk(Some(result.next_cursor.to_string().into())),
                 RespValue::Array(
                     result
                         .keys
                         .into_iter()
-                        .map(|k| RespValue::Bulk(Some(k)))
+                        .map(|k| RespValue::Bulk(Some(k.into())))
                         .collect(),
                 ),
             ]),
@@ -123,13 +134,7 @@ impl CommandExecutor {
         )
     }
 
-    pub(super) async fn dbsize(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 1 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'dbsize' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
+    pub(super) async fn dbsize(&self) -> (RespValue, SessionAction) {
         (
             RespValue::Integer(self.store.dbsize().await),
             SessionAction::Continue,
@@ -137,15 +142,164 @@ impl CommandExecutor {
     }
 
     pub(super) async fn key_type(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'type' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         (
             RespValue::Simple(self.store.key_type(&args[1]).await.to_string()),
             SessionAction::Continue,
         )
     }
+
+    /// `GETMETA key [key ...]` in non_redis_mode: `GET`, `PTTL` and `TYPE`
+    /// of each key in one map reply, keyed by key, with nil for missing
+    /// keys. The value is a JSON document's text, nil for a time series.
+    pub(super) async fn getmeta(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let mut keys: Vec<&Vec<u8>> = Vec::with_capacity(args.len() - 1);
+        for key in &args[1..] {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            let meta = match self.store.get_with_version(key).await {
+                Some((kind, value, expires_at, version)) => {
+                    let ttl = expires_at.map_or(-1, |at| at.saturating_sub(now_ms()) as i64);
+                    let field = |name: &'static str, value| {
+                        (
+                            RespValue::Bulk(Some(Bytes::from_static(name.as_bytes()))),
+                            value,
+                        )
+                    };
+                    RespValue::Map(vec![
+                        field(
+                            "value",
+                            RespValue::Bulk((kind != ValueType::TimeSeries).then_some(value)),
+                        ),
+                        field("ttl", RespValue::Integer(ttl)),
+                        field(
+                            "type",
+                            RespValue::Bulk(Some(Bytes::from_static(type_name(kind).as_bytes()))),
+                        ),
+                        field("version", RespValue::Integer(version as i64)),
+                    ])
+                }
+                None => RespValue::Bulk(None),
+            };
+            entries.push((RespValue::Bulk(Some(Bytes::from(key.clone()))), meta));
+        }
+        (RespValue::Map(entries), SessionAction::Continue)
+    }
+
+    /// `UNDELETE key` in non_redis_mode with soft delete on: restores the
+    /// key `DEL` or `UNLINK` removed, replying 1, or 0 when it has no
+    /// tombstone left.
+    pub(super) async fn undelete(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if !self.store.soft_delete() {
+            return (
+                RespValue::Error(
+                    "ERR soft delete is off; set FEDIS_SOFT_DELETE_RETENTION_SEC".to_string(),
+                ),
+                SessionAction::Continue,
+            );
+        }
+        let reply = match self.store.undelete(&args[1]).await {
+            Ok(Undelete::Restored) => RespValue::Integer(1),
+            Ok(Undelete::Missing) => RespValue::Integer(0),
+            Ok(Undelete::Exists) => RespValue::Error(
+                "ERR the key exists again; delete or rename it before UNDELETE".to_string(),
+            ),
+            Err(e) => RespValue::Error(format!("ERR internal: {}", e)),
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    pub(super) async fn dump(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let payload = self
+            .store
+            .get_with_expiry(&args[1])
+            .await
+            .map(|(kind, value, _)| Bytes::from(dump_payload(kind, &value)));
+        (RespValue::Bulk(payload), SessionAction::Continue)
+    }
+
+    /// `RESTORE key ttl payload [REPLACE] [ABSTTL] [IDLETIME s] [FREQ f]`.
+    /// Idle time and frequency are accepted and ignored, as fedis keeps no
+    /// eviction statistics.
+    pub(super) async fn restore(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(ttl) = parse_i64(&args[2]) else {
+            return (
+                RespValue::Error("ERR value is not an integer or out of range".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        if ttl < 0 {
+            return (
+                RespValue::Error("ERR Invalid TTL value, must be >= 0".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        let mut replace = false;
+        let mut absolute = false;
+        let mut idx = 4;
+        while idx < args.len() {
+            match upper(&args[idx]).as_str() {
+                "REPLACE" => replace = true,
+                "ABSTTL" => absolute = true,
+                "IDLETIME" | "FREQ" if args.get(idx + 1).and_then(|v| parse_u64(v)).is_some() => {
+                    idx += 1;
+                }
+                _ => {
+                    return (
+                        RespValue::Error("ERR syntax error".to_string()),
+                        SessionAction::Continue,
+                    );
+                }
+            }
+            idx += 1;
+        }
+        let (kind, value) = match parse_dump_payload(&args[3]) {
+            Ok(value) => value,
+            Err(e) => {
+                return (
+                    RespValue::Error(format!("ERR {}", e)),
+                    SessionAction::Continue,
+                );
+            }
+        };
+        let expires_at = match (ttl, absolute) {
+            (0, _) => None,
+            (ttl, true) => Some(ttl as u64),
+            (ttl, false) => Some(now_ms().saturating_add(ttl as u64)),
+        };
+        let key = args[1].clone();
+        let condition = if replace {
+            SetCondition::None
+        } else {
+            SetCondition::Nx
+        };
+        // A deadline already past restores nothing, but still replaces.
+        let result = if expires_at.is_some_and(|at| at <= now_ms()) {
+            if replace {
+                self.store.del(&[key]).await.map(|_| true)
+            } else if self.store.exists(&[key]).await > 0 {
+                Ok(false)
+            } else {
+                Ok(true)
+            }
+        } else {
+            self.store
+                .set_typed(key, kind, value, expires_at, condition)
+                .await
+        };
+        match result {
+            Ok(true) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Ok(false) => (
+                RespValue::Error("BUSYKEY Target key name already exists.".to_string()),
+                SessionAction::Continue,
+            ),
+            Err(e) => (
+                RespValue::Error(format!("ERR internal: {}", e)),
+                SessionAction::Continue,
+            ),
+        }
+    }
 }
@@ -0,0 +1,59 @@
+use super::*;
+
+impl CommandExecutor {
+    /// `LOCK key ttl-ms` in non_redis_mode: takes the lease at `key` for
+    /// `ttl-ms` milliseconds, replying its fencing token, or nil while
+    /// another holder has it.
+    pub(super) async fn lock(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(ttl_ms) = parse_ttl(&args[2]) else {
+            return (invalid_ttl("lock"), SessionAction::Continue);
+        };
+        let reply = match self.store.lock(&args[1], ttl_ms).await {
+            Ok(Some(token)) => RespValue::Integer(token as i64),
+            Ok(None) => RespValue::Bulk(None),
+            Err(e) => RespValue::Error(format!("ERR internal: {}", e)),
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `UNLOCK key token`: releases the lease if `token` still holds it,
+    /// replying 1, or 0 when it expired or was taken since.
+    pub(super) async fn unlock(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(token) = parse_u64(&args[2]) else {
+            return (not_an_integer(), SessionAction::Continue);
+        };
+        let reply = match self.store.unlock(&args[1], token).await {
+            Ok(released) => RespValue::Integer(released as i64),
+            Err(e) => RespValue::Error(format!("ERR internal: {}", e)),
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `EXTEND key token ttl-ms`: makes the lease last `ttl-ms` milliseconds
+    /// from now if `token` still holds it, replying 1, or 0 as `UNLOCK` does.
+    pub(super) async fn extend(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(token) = parse_u64(&args[2]) else {
+            return (not_an_integer(), SessionAction::Continue);
+        };
+        let Some(ttl_ms) = parse_ttl(&args[3]) else {
+            return (invalid_ttl("extend"), SessionAction::Continue);
+        };
+        let reply = match self.store.extend(&args[1], token, ttl_ms).await {
+            Ok(extended) => RespValue::Integer(extended as i64),
+            Err(e) => RespValue::Error(format!("ERR internal: {}", e)),
+        };
+        (reply, SessionAction::Continue)
+    }
+}
+
+fn parse_ttl(raw: &[u8]) -> Option<u64> {
+    parse_u64(raw).filter(|&ttl| ttl > 0)
+}
+
+fn invalid_ttl(command: &str) -> RespValue {
+    RespValue::Error(format!("ERR invalid expire time in '{}' command", command))
+}
+
+fn not_an_integer() -> RespValue {
+    RespValue::Error("ERR value is not an integer or out of range".to_string())
+}
@@ -0,0 +1,169 @@
+use super::*;
+use crate::allocator;
+use crate::store::StoreMetrics;
+
+/// Below this `MEMORY DOCTOR` has too little to go on, as in Redis.
+const DOCTOR_MIN_BYTES: usize = 5 * 1024 * 1024;
+
+impl CommandExecutor {
+    pub(super) async fn memory(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let sub = upper(&args[1]);
+        match sub.as_str() {
+            "USAGE" => {
+                if args.len() < 3 {
+                    return (
+                        RespValue::Error(
+                            "ERR wrong number of arguments for 'memory|usage' command".to_string(),
+                        ),
+                        SessionAction::Continue,
+                    );
+                }
+                (
+                    RespValue::Bulk(
+                        self.store
+                            .memory_usage(&args[2])
+                            .await
+                            .map(|v| Bytes::from(v.to_string())),
+                    ),
+                    SessionAction::Continue,
+                )
+            }
+            "STATS" => {
+                let metrics = self.store.metrics().await;
+                let backlog = self
+                    .store
+                    .replication_feed()
+                    .map_or(0, |feed| feed.backlog_len());
+                (memory_stats(&metrics, backlog), SessionAction::Continue)
+            }
+            "DOCTOR" => {
+                let metrics = self.store.metrics().await;
+                let report = memory_doctor(&metrics, self.max_memory_bytes);
+                (
+                    RespValue::Bulk(Some(report.into())),
+                    SessionAction::Continue,
+                )
+            }
+            "PURGE" => {
+                allocator::trim();
+                (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+            }
+            _ => (
+                RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
+                SessionAction::Continue,
+            ),
+        }
+    }
+}
+
+/// `MEMORY STATS` as Redis lays it out, from what the store accounts for.
+/// fedis only holds strings, so the per-type breakdown has a single entry.
+fn memory_stats(metrics: &StoreMetrics, backlog: usize) -> RespValue {
+    let field = |name: &'static str| RespValue::Bulk(Some(Bytes::from_static(name.as_bytes())));
+    let count = |value: usize| RespValue::Integer(value as i64);
+    let percentage = |part: usize, whole: usize| {
+        let value = if whole == 0 {
+            0.0
+        } else {
+            part as f64 * 100.0 / whole as f64
+        };
+        RespValue::Bulk(Some(format!("{value:.2}").into()))
+    };
+    let used = metrics.approx_memory_bytes;
+    let total = used.saturating_add(backlog);
+    let mut stats = vec![
+        field("peak.allocated"),
+        count(metrics.peak_memory_bytes.max(total)),
+        field("total.allocated"),
+        count(total),
+        field("replication.backlog"),
+        count(backlog),
+        field("overhead.total"),
+        count(metrics.overhead_bytes.saturating_add(backlog)),
+        field("keys.count"),
+        count(metrics.keys),
+        field("keys.bytes-per-key"),
+        count(used.checked_div(metrics.keys).unwrap_or(0)),
+        field("dataset.bytes"),
+        count(metrics.dataset_bytes),
+        field("dataset.percentage"),
+        percentage(metrics.dataset_bytes, total),
+        field("peak.percentage"),
+        percentage(total, metrics.peak_memory_bytes.max(total)),
+        field("db.0"),
+        RespValue::Array(vec![
+            field("overhead.hashtable.main"),
+            count(metrics.overhead_bytes - metrics.expires_overhead_bytes),
+            field("overhead.hashtable.expires"),
+            count(metrics.expires_overhead_bytes),
+        ]),
+        field("types"),
+        RespValue::Array(vec![
+            field("string"),
+            RespValue::Array(vec![
+                field("keys"),
+                count(metrics.keys),
+                field("bytes"),
+                count(metrics.dataset_bytes),
+            ]),
+        ]),
+    ];
+    if let Some(allocated) = allocator::allocated() {
+        stats.extend([field("allocator.allocated"), count(allocated)]);
+    }
+    if let Some(rss) = allocator::resident() {
+        stats.extend([
+            field("allocator.resident"),
+            count(rss),
+            field("fragmentation"),
+            RespValue::Bulk(Some(
+                format!("{:.2}", rss as f64 / total.max(1) as f64).into(),
+            )),
+        ]);
+    }
+    RespValue::Array(stats)
+}
+
+/// `MEMORY DOCTOR`: the Redis heuristics that apply to this store.
+fn memory_doctor(metrics: &StoreMetrics, max_memory: Option<u64>) -> String {
+    let used = metrics.approx_memory_bytes;
+    if used < DOCTOR_MIN_BYTES {
+        return "This instance is empty or is using very little memory, so there is nothing \
+                for me to diagnose yet. Fill it with some data and ask again."
+            .to_string();
+    }
+
+    let mut issues = Vec::new();
+    if metrics.peak_memory_bytes.saturating_mul(2) > used.saturating_mul(3) {
+        issues.push(
+            " * Peak memory: In the past this instance used more than 150% the memory that is \
+             currently using. The allocator is normally not able to release memory after a peak; \
+             MEMORY PURGE asks it to return what it can to the system.",
+        );
+    }
+    if metrics.overhead_bytes > metrics.dataset_bytes {
+        issues.push(
+            " * High overhead: The bookkeeping for keys takes more memory than the keys and \
+             values themselves. Many small keys cost more than fewer larger ones; consider \
+             grouping related small values.",
+        );
+    }
+    if let Some(limit) = max_memory
+        && used as u64 >= limit / 10 * 9
+    {
+        issues.push(
+            " * Near maxmemory: Used memory is above 90% of 'maxmemory'. Commands that grow the \
+             dataset are refused with OOM once the limit is reached.",
+        );
+    }
+
+    if issues.is_empty() {
+        return "I can't find any memory issue in this instance. I can only account for what \
+                the keyspace and the replication backlog hold."
+            .to_string();
+    }
+    format!(
+        "I detected the following memory issues in this instance:\n\n{}\n",
+        issues.join("\n\n")
+    )
+}
@@ -0,0 +1,29 @@
+use super::*;
+
+impl CommandExecutor {
+    /// SUBSCRIBE and UNSUBSCRIBE answer once per channel, so the server runs
+    /// them in subscriber mode, which the connection stays in while it is
+    /// subscribed to anything.
+    pub(super) fn subscriber_command(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() < 2 && args[0].eq_ignore_ascii_case(b"SUBSCRIBE") {
+            return (
+                RespValue::Error(
+                    "ERR wrong number of arguments for 'subscribe' command".to_string(),
+                ),
+                SessionAction::Continue,
+            );
+        }
+        (
+            RespValue::Simple(String::new()),
+            SessionAction::Subscribe(args.to_vec()),
+        )
+    }
+
+    pub(super) fn publish(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let receivers = self.pubsub.publish(&args[1], &args[2]);
+        (
+            RespValue::Integer(receivers as i64),
+            SessionAction::Continue,
+        )
+    }
+}
@@ -0,0 +1,1495 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+use super::*;
+
+pub(super) type Reply<'a> = Pin<Box<dyn Future<Output = (RespValue, SessionAction)> + Send + 'a>>;
+pub(super) type Handler =
+    for<'a> fn(&'a CommandExecutor, &'a [Vec<u8>], &'a mut SessionAuth) -> Reply<'a>;
+
+/// Everything the server knows about a command. `execute` looks commands up
+/// here once and takes dispatch, the arity check, ACL key extraction, the
+/// write and `maxmemory` guards from the entry; `COMMAND` and `ACL CAT`
+/// report from the same table.
+pub(super) struct CommandSpec {
+    pub(super) name: &'static str,
+    /// Redis' convention: `n` exactly `n` arguments including the name, `-n`
+    /// at least `n`.
+    pub(super) arity: i64,
+    pub(super) flags: &'static [&'static str],
+    pub(super) first_key: i64,
+    pub(super) last_key: i64,
+    pub(super) step: i64,
+    /// Where the keys are, as Redis 7 key specs; these drive key extraction.
+    /// `first_key`/`last_key`/`step` stay for the legacy `COMMAND` reply.
+    pub(super) key_specs: &'static [KeySpec],
+    pub(super) non_redis: NonRedis,
+    pub(super) handler: Handler,
+}
+
+/// Whether a command is served only in non_redis_mode. Otherwise `execute`
+/// answers it as unknown and `COMMAND` leaves it out.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum NonRedis {
+    /// A Redis command.
+    No,
+    /// A fedis command.
+    Command,
+    /// A Redis command with these fedis subcommands.
+    Subcommands(&'static [&'static str]),
+}
+
+/// One group of key arguments: where the search starts and how the keys
+/// follow from there, with Redis' key-spec flags (`RO`, `RW`, `OW`, `RM`
+/// and `access`, `update`, `insert`, `delete`).
+pub(super) struct KeySpec {
+    pub(super) begin: BeginSearch,
+    pub(super) find: FindKeys,
+    pub(super) flags: &'static [&'static str],
+}
+
+pub(super) enum BeginSearch {
+    /// The first key is at this argument index.
+    Index(i64),
+    /// The first key follows `keyword`, searched from `start_from` on; from
+    /// the end backwards when negative.
+    Keyword {
+        keyword: &'static str,
+        start_from: i64,
+    },
+}
+
+pub(super) enum FindKeys {
+    /// Keys up to `last_key` relative to the first one (negative counts from
+    /// the end of the arguments), every `step` arguments.
+    Range { last_key: i64, step: i64 },
+    /// A count at `keynum_idx` relative to the first argument searched,
+    /// then that many keys from `first_key` on, every `step` arguments.
+    Keynum {
+        keynum_idx: i64,
+        first_key: i64,
+        step: i64,
+    },
+}
+
+impl KeySpec {
+    /// Indexes of the key arguments in `args`.
+    fn positions(&self, args: &[Vec<u8>]) -> Vec<usize> {
+        let argc = args.len() as i64;
+        let first = match self.begin {
+            BeginSearch::Index(index) => index,
+            BeginSearch::Keyword {
+                keyword,
+                start_from,
+            } => {
+                let matches =
+                    |idx: &i64| args[*idx as usize].eq_ignore_ascii_case(keyword.as_bytes());
+                let found = if start_from >= 0 {
+                    (start_from..argc).find(matches)
+                } else {
+                    (1..=argc + start_from).rev().find(matches)
+                };
+                match found {
+                    Some(idx) => idx + 1,
+                    None => return Vec::new(),
+                }
+            }
+        };
+        if first <= 0 || first >= argc {
+            return Vec::new();
+        }
+        match self.find {
+            FindKeys::Range { last_key, step } => {
+                let last = if last_key < 0 {
+                    argc + last_key
+                } else {
+                    (first + last_key).min(argc - 1)
+                };
+                (first..=last)
+                    .step_by(step.max(1) as usize)
+                    .map(|idx| idx as usize)
+                    .collect()
+            }
+            FindKeys::Keynum {
+                keynum_idx,
+                first_key,
+                step,
+            } => {
+                let Some(count) = args
+                    .get((first + keynum_idx) as usize)
+                    .and_then(|raw| std::str::from_utf8(raw).ok()?.parse::<i64>().ok())
+                    .filter(|count| *count >= 0)
+                else {
+                    return Vec::new();
+                };
+                (0..count)
+                    .map(|n| first + first_key + n * step.max(1))
+                    .take_while(|idx| *idx < argc)
+                    .map(|idx| idx as usize)
+                    .collect()
+            }
+        }
+    }
+}
+
+const RO: &[&str] = &["RO"];
+const RO_ACCESS: &[&str] = &["RO", "access"];
+const RW_INSERT: &[&str] = &["RW", "insert"];
+const RW_UPDATE: &[&str] = &["RW", "access", "update"];
+const RW_DELETE: &[&str] = &["RW", "access", "delete"];
+const RM_DELETE: &[&str] = &["RM", "delete"];
+const OW_INSERT: &[&str] = &["OW", "insert"];
+const OW_UPDATE: &[&str] = &["OW", "update"];
+
+/// The key at `index`.
+const fn single(index: i64, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        begin: BeginSearch::Index(index),
+        find: FindKeys::Range {
+            last_key: 0,
+            step: 1,
+        },
+        flags,
+    }
+}
+
+/// Every `step`-th argument from `index` to the end.
+const fn to_end(index: i64, step: i64, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        begin: BeginSearch::Index(index),
+        find: FindKeys::Range { last_key: -1, step },
+        flags,
+    }
+}
+
+/// As many keys as the count at `index` says, right after it.
+const fn numkeys(index: i64, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        begin: BeginSearch::Index(index),
+        find: FindKeys::Keynum {
+            keynum_idx: 0,
+            first_key: 1,
+            step: 1,
+        },
+        flags,
+    }
+}
+
+impl CommandSpec {
+    pub(super) fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub(super) fn is_write(&self) -> bool {
+        self.has_flag("write")
+    }
+
+    /// The error for `args` when non_redis_mode is off and they need it.
+    pub(super) fn needs_non_redis(&self, args: &[Vec<u8>]) -> Option<RespValue> {
+        let (kind, name) = match self.non_redis {
+            NonRedis::No => return None,
+            NonRedis::Command => ("command", self.name.to_ascii_lowercase()),
+            NonRedis::Subcommands(subcommands) => {
+                let sub = upper(args.get(1)?);
+                if !subcommands.contains(&sub.as_str()) {
+                    return None;
+                }
+                ("subcommand", sub.to_ascii_lowercase())
+            }
+        };
+        Some(RespValue::Error(format!(
+            "ERR unknown {} '{}', it needs FEDIS_NON_REDIS_MODE",
+            kind, name
+        )))
+    }
+
+    pub(super) fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    /// Key arguments per the key specs, for ACL key checks, cluster routing
+    /// and `COMMAND GETKEYS`.
+    pub(super) fn keys<'a>(&self, args: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+        self.keys_and_flags(args)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Key arguments with the flags of the key spec that found them.
+    pub(super) fn keys_and_flags<'a>(
+        &self,
+        args: &'a [Vec<u8>],
+    ) -> Vec<(&'a [u8], &'static [&'static str])> {
+        self.key_specs
+            .iter()
+            .flat_map(|spec| {
+                spec.positions(args)
+                    .into_iter()
+                    .map(|idx| (args[idx].as_slice(), spec.flags))
+            })
+            .collect()
+    }
+
+    /// ACL categories, derived from the flags and the name.
+    pub(super) fn categories(&self) -> Vec<&'static str> {
+        let mut out = Vec::new();
+        if self.has_flag("readonly") {
+            out.push("read");
+        }
+        if self.is_write() {
+            out.push("write");
+        }
+        if self.has_flag("admin") {
+            out.push("admin");
+            out.push("dangerous");
+        }
+        out.push(if self.has_flag("fast") {
+            "fast"
+        } else {
+            "slow"
+        });
+        match self.name {
+            "AUTH" | "ECHO" | "HELLO" | "PING" | "QUIT" | "SELECT" | "CLIENT" => {
+                out.push("connection")
+            }
+            "DEL" | "UNLINK" | "EXISTS" | "EXPIRE" | "EXPIREAT" | "PEXPIRE" | "PEXPIREAT"
+            | "PERSIST" | "TTL" | "PTTL" | "TYPE" | "KEYS" | "SCAN" | "DBSIZE" | "OBJECT"
+            | "GETMETA" | "UNDELETE" => out.push("keyspace"),
+            "FLUSHALL" | "FLUSHDB" => out.extend(["keyspace", "dangerous"]),
+            name if name.starts_with("JSON.") => out.push("json"),
+            name if name.starts_with("FT.") => out.push("search"),
+            name if name.starts_with("TS.") => out.push("timeseries"),
+            name if name.starts_with("WASM.") => out.push("scripting"),
+            _ if self.has_flag("pubsub") => out.push("pubsub"),
+            _ if extension::lookup(self.name).is_some() => {}
+            _ if self.first_key > 0 => out.push("string"),
+            _ => {}
+        }
+        out
+    }
+
+    /// The entry as `COMMAND` and `COMMAND INFO` return it.
+    pub(super) fn meta(&self) -> RespValue {
+        RespValue::Array(vec![
+            RespValue::Bulk(Some(self.name.to_ascii_lowercase().into())),
+            RespValue::Integer(self.arity),
+            RespValue::Array(
+                self.flags
+                    .iter()
+                    .map(|v| RespValue::Bulk(Some(Bytes::from_static(v.as_bytes()))))
+                    .collect(),
+            ),
+            RespValue::Integer(self.first_key),
+            RespValue::Integer(self.last_key),
+            RespValue::Integer(self.step),
+        ])
+    }
+}
+
+/// The command named `name`, upper case.
+/// A built-in command or a registered extension.
+pub(super) fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    builtin(name).or_else(|| extension::lookup(name))
+}
+
+pub(super) fn builtin(name: &str) -> Option<&'static CommandSpec> {
+    static INDEX: OnceLock<HashMap<&'static str, &'static CommandSpec>> = OnceLock::new();
+    INDEX
+        .get_or_init(|| COMMANDS.iter().map(|spec| (spec.name, spec)).collect())
+        .get(name)
+        .copied()
+}
+
+/// The built-in commands, then the registered extensions.
+pub(super) fn commands() -> Vec<&'static CommandSpec> {
+    COMMANDS.iter().chain(extension::specs()).collect()
+}
+
+/// Names of the commands in an ACL category, for `+@category` rules; `None`
+/// for a category no command belongs to.
+pub(crate) fn category_commands(category: &str) -> Option<Vec<&'static str>> {
+    let names: Vec<&'static str> = commands()
+        .into_iter()
+        .filter(|spec| spec.categories().contains(&category))
+        .map(|spec| spec.name)
+        .collect();
+    (!names.is_empty()).then_some(names)
+}
+
+static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "APPEND",
+        arity: 3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.append(args)),
+    },
+    CommandSpec {
+        name: "ACL",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, session| Box::pin(async move { ex.acl(args, session) }),
+    },
+    CommandSpec {
+        name: "ASKING",
+        arity: 1,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, session| Box::pin(async move { ex.asking(session) }),
+    },
+    CommandSpec {
+        name: "AUTH",
+        arity: -2,
+        flags: &["fast", "no_auth"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, session| Box::pin(async move { ex.auth_cmd(args, session) }),
+    },
+    CommandSpec {
+        name: "BGSAVE",
+        arity: 1,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(ex.bgsave()),
+    },
+    CommandSpec {
+        name: "BGREWRITEAOF",
+        arity: 1,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(ex.bgrewriteaof()),
+    },
+    CommandSpec {
+        name: "CLIENT",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, session| Box::pin(ex.client(args, session)),
+    },
+    CommandSpec {
+        name: "CLUSTER",
+        arity: -2,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.cluster(args)),
+    },
+    CommandSpec {
+        name: "COMMAND",
+        arity: -1,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.command_meta(args) }),
+    },
+    CommandSpec {
+        name: "CONFIG",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.config_cmd(args) }),
+    },
+    CommandSpec {
+        name: "DBSIZE",
+        arity: 1,
+        flags: &["readonly", "fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(ex.dbsize()),
+    },
+    CommandSpec {
+        name: "DECR",
+        arity: 2,
+        flags: &["write", "fast", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.decr(args)),
+    },
+    CommandSpec {
+        name: "DECRBY",
+        arity: 3,
+        flags: &["write", "fast", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.decrby(args)),
+    },
+    CommandSpec {
+        name: "DEL",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        key_specs: &[to_end(1, 1, RM_DELETE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.del(args)),
+    },
+    CommandSpec {
+        name: "DUMP",
+        arity: 2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.dump(args)),
+    },
+    CommandSpec {
+        name: "ECHO",
+        arity: 2,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.echo(args) }),
+    },
+    CommandSpec {
+        name: "EXISTS",
+        arity: -2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        key_specs: &[to_end(1, 1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.exists(args)),
+    },
+    CommandSpec {
+        name: "EXPIRE",
+        arity: 3,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.expire(args)),
+    },
+    CommandSpec {
+        name: "EXPIREAT",
+        arity: 3,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.expireat(args)),
+    },
+    CommandSpec {
+        name: "EXPORT",
+        arity: -2,
+        flags: &["admin", "noscript"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.export(args)),
+    },
+    CommandSpec {
+        name: "EXTEND",
+        arity: 4,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, _| Box::pin(ex.extend(args)),
+    },
+    CommandSpec {
+        name: "FAILOVER",
+        arity: -1,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.failover(args) }),
+    },
+    CommandSpec {
+        name: "FLUSHALL",
+        arity: -1,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.flush(args)),
+    },
+    CommandSpec {
+        name: "FLUSHDB",
+        arity: -1,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.flush(args)),
+    },
+    CommandSpec {
+        name: "FOLLOW",
+        arity: -2,
+        flags: &["pubsub"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, session| Box::pin(async move { ex.follow(args, session) }),
+    },
+    CommandSpec {
+        name: "FT.CREATE",
+        arity: -5,
+        flags: &["denyoom"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ft_create(args)),
+    },
+    CommandSpec {
+        name: "FT.DROPINDEX",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ft_dropindex(args)),
+    },
+    CommandSpec {
+        name: "FT.SEARCH",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ft_search(args)),
+    },
+    CommandSpec {
+        name: "FT._LIST",
+        arity: 1,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(ex.ft_list()),
+    },
+    CommandSpec {
+        name: "GET",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.get(args)),
+    },
+    CommandSpec {
+        name: "GETDEL",
+        arity: 2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_DELETE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.getdel(args)),
+    },
+    CommandSpec {
+        name: "GETEX",
+        arity: -2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.getex(args)),
+    },
+    CommandSpec {
+        name: "GETMETA",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        key_specs: &[to_end(1, 1, RO_ACCESS)],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, _| Box::pin(ex.getmeta(args)),
+    },
+    CommandSpec {
+        name: "GETRANGE",
+        arity: 4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.getrange(args)),
+    },
+    CommandSpec {
+        name: "GETSET",
+        arity: 3,
+        flags: &["write", "fast", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.getset(args)),
+    },
+    CommandSpec {
+        name: "HELLO",
+        arity: -1,
+        flags: &["fast", "no_auth"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, session| Box::pin(async move { ex.hello(args, session) }),
+    },
+    CommandSpec {
+        name: "INCR",
+        arity: 2,
+        flags: &["write", "fast", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.incr(args)),
+    },
+    CommandSpec {
+        name: "INCRBY",
+        arity: 3,
+        flags: &["write", "fast", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.incrby(args)),
+    },
+    CommandSpec {
+        name: "INFO",
+        arity: -1,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.info(args)),
+    },
+    CommandSpec {
+        name: "JSON.CLEAR",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_clear(args)),
+    },
+    CommandSpec {
+        name: "JSON.DEL",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_DELETE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_del(args)),
+    },
+    CommandSpec {
+        name: "JSON.GET",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_get(args)),
+    },
+    CommandSpec {
+        name: "JSON.MERGE",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_merge(args)),
+    },
+    CommandSpec {
+        name: "JSON.OBJKEYS",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_objkeys(args)),
+    },
+    CommandSpec {
+        name: "JSON.OBJLEN",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_objlen(args)),
+    },
+    CommandSpec {
+        name: "JSON.RESP",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_resp(args)),
+    },
+    CommandSpec {
+        name: "JSON.SET",
+        arity: -4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_set(args)),
+    },
+    CommandSpec {
+        name: "JSON.STRAPPEND",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_strappend(args)),
+    },
+    CommandSpec {
+        name: "JSON.STRLEN",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_strlen(args)),
+    },
+    CommandSpec {
+        name: "JSON.TOGGLE",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_toggle(args)),
+    },
+    CommandSpec {
+        name: "JSON.TYPE",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.json_type(args)),
+    },
+    CommandSpec {
+        name: "KEYS",
+        arity: 2,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.keys(args)),
+    },
+    CommandSpec {
+        name: "LATENCY",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.latency(args) }),
+    },
+    CommandSpec {
+        name: "LASTSAVE",
+        arity: 1,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(async move { ex.lastsave() }),
+    },
+    CommandSpec {
+        name: "LOCK",
+        arity: 3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_INSERT)],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, _| Box::pin(ex.lock(args)),
+    },
+    CommandSpec {
+        name: "MEMORY",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[KeySpec {
+            begin: BeginSearch::Keyword {
+                keyword: "USAGE",
+                start_from: 1,
+            },
+            find: FindKeys::Range {
+                last_key: 0,
+                step: 1,
+            },
+            flags: RO,
+        }],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.memory(args)),
+    },
+    CommandSpec {
+        name: "MGET",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        key_specs: &[to_end(1, 1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.mget(args)),
+    },
+    CommandSpec {
+        name: "MSET",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: -1,
+        step: 2,
+        key_specs: &[to_end(1, 2, OW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.mset(args)),
+    },
+    CommandSpec {
+        name: "MSETNX",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: -1,
+        step: 2,
+        key_specs: &[to_end(1, 2, OW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.msetnx(args)),
+    },
+    CommandSpec {
+        name: "MODULE",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.module_cmd(args) }),
+    },
+    CommandSpec {
+        name: "OBJECT",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 2,
+        last_key: 2,
+        step: 1,
+        key_specs: &[single(2, RO)],
+        non_redis: NonRedis::Subcommands(&["VERSION"]),
+        handler: |ex, args, _| Box::pin(ex.object(args)),
+    },
+    CommandSpec {
+        name: "PERSIST",
+        arity: 2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.persist(args)),
+    },
+    CommandSpec {
+        name: "PEXPIRE",
+        arity: 3,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.pexpire(args)),
+    },
+    CommandSpec {
+        name: "PEXPIREAT",
+        arity: 3,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.pexpireat(args)),
+    },
+    CommandSpec {
+        name: "PING",
+        arity: -1,
+        flags: &["fast", "no_auth"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.ping(args) }),
+    },
+    CommandSpec {
+        name: "PSETEX",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, OW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.psetex(args)),
+    },
+    CommandSpec {
+        name: "PSYNC",
+        arity: -3,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, session| Box::pin(async move { ex.psync(args, session) }),
+    },
+    CommandSpec {
+        name: "PTTL",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.pttl(args)),
+    },
+    CommandSpec {
+        name: "PUBLISH",
+        arity: 3,
+        flags: &["pubsub", "fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.publish(args) }),
+    },
+    CommandSpec {
+        name: "QUIT",
+        arity: 1,
+        flags: &["fast", "no_auth"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |_, _, _| {
+            Box::pin(async { (RespValue::Simple("OK".to_string()), SessionAction::Close) })
+        },
+    },
+    CommandSpec {
+        name: "REPLCONF",
+        arity: -1,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, session| Box::pin(async move { ex.replconf(args, session) }),
+    },
+    CommandSpec {
+        name: "REPLICAOF",
+        arity: 3,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.replicaof("REPLICAOF", args) }),
+    },
+    CommandSpec {
+        name: "ROLE",
+        arity: 1,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(async move { ex.role() }),
+    },
+    CommandSpec {
+        name: "RESTORE",
+        arity: -4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, OW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.restore(args)),
+    },
+    CommandSpec {
+        name: "SCAN",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.scan(args)),
+    },
+    CommandSpec {
+        name: "SAVE",
+        arity: 1,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(ex.save()),
+    },
+    CommandSpec {
+        name: "SELECT",
+        arity: 2,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.select(args) }),
+    },
+    CommandSpec {
+        name: "SET",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.set(args)),
+    },
+    CommandSpec {
+        name: "SETEX",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, OW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.setex(args)),
+    },
+    CommandSpec {
+        name: "SETNX",
+        arity: 3,
+        flags: &["write", "fast", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, OW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.setnx(args)),
+    },
+    CommandSpec {
+        name: "SETRANGE",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.setrange(args)),
+    },
+    CommandSpec {
+        name: "SLOTMIGRATE",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.slotmigrate(args) }),
+    },
+    CommandSpec {
+        name: "SLOWLOG",
+        arity: -2,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.slowlog(args) }),
+    },
+    CommandSpec {
+        name: "SLAVEOF",
+        arity: 3,
+        flags: &["admin"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.replicaof("SLAVEOF", args) }),
+    },
+    CommandSpec {
+        name: "STRLEN",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.strlen(args)),
+    },
+    CommandSpec {
+        name: "SUBSCRIBE",
+        arity: -2,
+        flags: &["pubsub"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.subscriber_command(args) }),
+    },
+    CommandSpec {
+        name: "THROTTLE",
+        arity: -5,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, _| Box::pin(ex.throttle(args)),
+    },
+    CommandSpec {
+        name: "TIME",
+        arity: 1,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(async move { ex.time() }),
+    },
+    CommandSpec {
+        name: "TS.ADD",
+        arity: -4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ts_add(args)),
+    },
+    CommandSpec {
+        name: "TS.CREATE",
+        arity: -2,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ts_create(args)),
+    },
+    CommandSpec {
+        name: "TS.GET",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ts_get(args)),
+    },
+    CommandSpec {
+        name: "TS.MADD",
+        arity: -4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: -1,
+        step: 3,
+        key_specs: &[to_end(1, 3, RW_INSERT)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ts_madd(args)),
+    },
+    CommandSpec {
+        name: "TS.RANGE",
+        arity: -4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ts_range(args)),
+    },
+    CommandSpec {
+        name: "TTL",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.ttl(args)),
+    },
+    CommandSpec {
+        name: "TYPE",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RO)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.key_type(args)),
+    },
+    CommandSpec {
+        name: "UNDELETE",
+        arity: 2,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, OW_INSERT)],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, _| Box::pin(ex.undelete(args)),
+    },
+    CommandSpec {
+        name: "UNLINK",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        key_specs: &[to_end(1, 1, RM_DELETE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.unlink(args)),
+    },
+    CommandSpec {
+        name: "UNLOCK",
+        arity: 3,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_DELETE)],
+        non_redis: NonRedis::Command,
+        handler: |ex, args, _| Box::pin(ex.unlock(args)),
+    },
+    CommandSpec {
+        name: "UNSUBSCRIBE",
+        arity: -1,
+        flags: &["pubsub"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(async move { ex.subscriber_command(args) }),
+    },
+    CommandSpec {
+        name: "UPDATE",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        key_specs: &[single(1, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.update(args)),
+    },
+    CommandSpec {
+        name: "WASM.CALL",
+        arity: -4,
+        flags: &["write", "denyoom", "noscript", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[numkeys(3, RW_UPDATE)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.wasm_call(args, false)),
+    },
+    CommandSpec {
+        name: "WASM.CALL_RO",
+        arity: -4,
+        flags: &["readonly", "noscript", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[numkeys(3, RO_ACCESS)],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.wasm_call(args, true)),
+    },
+    CommandSpec {
+        name: "WASM.LIST",
+        arity: 1,
+        flags: &["noscript"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, _, _| Box::pin(ex.wasm_list()),
+    },
+    CommandSpec {
+        name: "WASM.LOAD",
+        arity: -3,
+        flags: &["admin", "denyoom", "noscript"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.wasm_load(args)),
+    },
+    CommandSpec {
+        name: "WASM.UNLOAD",
+        arity: 2,
+        flags: &["admin", "noscript"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        key_specs: &[],
+        non_redis: NonRedis::No,
+        handler: |ex, args, _| Box::pin(ex.wasm_unload(args)),
+    },
+];
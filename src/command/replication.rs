@@ -0,0 +1,257 @@
+use super::*;
+use crate::replication::FailoverTarget;
+use std::time::Duration;
+
+impl CommandExecutor {
+    /// Handshake options a replica sends before PSYNC. Only `listening-port`
+    /// matters to fedis, which reports it and fails over to it; Redis replicas
+    /// stop if the others are refused.
+    pub(super) fn replconf(
+        &self,
+        args: &[Vec<u8>],
+        session: &mut SessionAuth,
+    ) -> (RespValue, SessionAction) {
+        if args.len() % 2 != 1 {
+            return (
+                RespValue::Error("ERR syntax error".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        for option in args[1..].chunks(2) {
+            if option[0].eq_ignore_ascii_case(b"listening-port") {
+                let Some(port) = std::str::from_utf8(&option[1])
+                    .ok()
+                    .and_then(|port| port.parse::<u16>().ok())
+                else {
+                    return (
+                        RespValue::Error("ERR value is not an integer or out of range".to_string()),
+                        SessionAction::Continue,
+                    );
+                };
+                session.replica_port = Some(port);
+            }
+        }
+        (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+    }
+
+    pub(super) fn psync(
+        &self,
+        args: &[Vec<u8>],
+        session: &SessionAuth,
+    ) -> (RespValue, SessionAction) {
+        if args.len() != 3 {
+            return (
+                RespValue::Error("ERR wrong number of arguments for 'psync' command".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        let Some(feed) = self.store.replication_feed() else {
+            return (
+                RespValue::Error("ERR replication is not enabled".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let Some(offset) = parse_i64(&args[2]) else {
+            return (
+                RespValue::Error("ERR value is not an integer or out of range".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let (host, peer_port) = session
+            .peer_addr
+            .as_deref()
+            .and_then(|addr| addr.parse::<std::net::SocketAddr>().ok())
+            .map(|addr| (addr.ip().to_string(), addr.port()))
+            .unwrap_or_default();
+        // The reply is written by the replication link itself.
+        (
+            RespValue::Simple(String::new()),
+            SessionAction::Replicate(Box::new(PsyncRequest {
+                replid: String::from_utf8_lossy(&args[1]).to_string(),
+                offset,
+                host,
+                port: session.replica_port.unwrap_or(peer_port),
+                feed: feed.clone(),
+                store: self.store.clone(),
+            })),
+        )
+    }
+
+    /// `REPLICAOF host port` starts following a master; `REPLICAOF NO ONE`
+    /// promotes this node back to a master, keeping the data it has replicated.
+    pub(super) fn replicaof(&self, cmd: &str, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() != 3 {
+            return (
+                RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    cmd.to_lowercase()
+                )),
+                SessionAction::Continue,
+            );
+        }
+        let Some(role) = self.replication_role() else {
+            return (
+                RespValue::Error("ERR replication is not enabled".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        if args[1].eq_ignore_ascii_case(b"NO") && args[2].eq_ignore_ascii_case(b"ONE") {
+            role.promote();
+            return (RespValue::Simple("OK".to_string()), SessionAction::Continue);
+        }
+        let Some(port) = std::str::from_utf8(&args[2])
+            .ok()
+            .and_then(|port| port.parse::<u16>().ok())
+        else {
+            return (
+                RespValue::Error("ERR Invalid master port".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let host = String::from_utf8_lossy(&args[1]).to_string();
+        if !role.follow(host, port) {
+            return (
+                RespValue::Simple("OK Already connected to specified master".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+    }
+
+    /// `FAILOVER [TO host port [FORCE]] [TIMEOUT ms] | FAILOVER ABORT`: hands
+    /// the master role to a replica without losing writes. Like Redis, it
+    /// replies once the failover has started; `INFO replication` reports its
+    /// progress as `master_failover_state`.
+    pub(super) fn failover(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(role) = self.replication_role() else {
+            return (
+                RespValue::Error("ERR replication is not enabled".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let mut to = None;
+        let mut timeout = None;
+        let mut force = false;
+        let mut abort = false;
+        let mut idx = 1;
+        while idx < args.len() {
+            match upper(&args[idx]).as_str() {
+                "TO" if to.is_none() && idx + 2 < args.len() => {
+                    let Some(port) = std::str::from_utf8(&args[idx + 2])
+                        .ok()
+                        .and_then(|port| port.parse::<u16>().ok())
+                    else {
+                        return (
+                            RespValue::Error("ERR Invalid target port".to_string()),
+                            SessionAction::Continue,
+                        );
+                    };
+                    to = Some((String::from_utf8_lossy(&args[idx + 1]).to_string(), port));
+                    idx += 3;
+                }
+                "TIMEOUT" if timeout.is_none() && idx + 1 < args.len() => {
+                    let Some(ms) = parse_u64(&args[idx + 1]).filter(|ms| *ms > 0) else {
+                        return (
+                            RespValue::Error(
+                                "ERR FAILOVER timeout must be greater than 0".to_string(),
+                            ),
+                            SessionAction::Continue,
+                        );
+                    };
+                    timeout = Some(Duration::from_millis(ms));
+                    idx += 2;
+                }
+                "FORCE" if !force => {
+                    force = true;
+                    idx += 1;
+                }
+                "ABORT" if !abort => {
+                    abort = true;
+                    idx += 1;
+                }
+                _ => {
+                    return (
+                        RespValue::Error("ERR syntax error".to_string()),
+                        SessionAction::Continue,
+                    );
+                }
+            }
+        }
+
+        if abort {
+            if to.is_some() || timeout.is_some() || force {
+                return (
+                    RespValue::Error("ERR syntax error".to_string()),
+                    SessionAction::Continue,
+                );
+            }
+            if !role.abort_failover() {
+                return (
+                    RespValue::Error("ERR No failover in progress.".to_string()),
+                    SessionAction::Continue,
+                );
+            }
+            return (RespValue::Simple("OK".to_string()), SessionAction::Continue);
+        }
+        if force && (to.is_none() || timeout.is_none()) {
+            return (
+                RespValue::Error(
+                    "ERR FAILOVER with force option requires both a timeout and target HOST and PORT."
+                        .to_string(),
+                ),
+                SessionAction::Continue,
+            );
+        }
+        let target = FailoverTarget { to, timeout, force };
+        match role.start_failover(target) {
+            Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Err(e) => (
+                RespValue::Error(format!("ERR {}", e)),
+                SessionAction::Continue,
+            ),
+        }
+    }
+
+    /// `ROLE`, which sentinel-aware clients use to check they reached a master.
+    pub(super) fn role(&self) -> (RespValue, SessionAction) {
+        let bulk = |value: String| RespValue::Bulk(Some(value.into()));
+        if let Some(replica) = self.replication_role().and_then(|role| role.status()) {
+            let state = if replica.link_up {
+                "connected"
+            } else {
+                "connect"
+            };
+            return (
+                RespValue::Array(vec![
+                    bulk("slave".to_string()),
+                    bulk(replica.host),
+                    RespValue::Integer(i64::from(replica.port)),
+                    bulk(state.to_string()),
+                    RespValue::Integer(replica.offset as i64),
+                ]),
+                SessionAction::Continue,
+            );
+        }
+        let feed = self.store.replication_feed();
+        let replicas = feed
+            .map(|feed| feed.replicas())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|replica| {
+                RespValue::Array(vec![
+                    bulk(replica.host),
+                    bulk(replica.port.to_string()),
+                    bulk(replica.acked.to_string()),
+                ])
+            })
+            .collect();
+        (
+            RespValue::Array(vec![
+                bulk("master".to_string()),
+                RespValue::Integer(feed.map_or(0, |feed| feed.offset()) as i64),
+                RespValue::Array(replicas),
+            ]),
+            SessionAction::Continue,
+        )
+    }
+}
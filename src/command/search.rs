@@ -0,0 +1,109 @@
+use super::*;
+use crate::search::{IndexDefinition, Query};
+
+impl CommandExecutor {
+    /// `FT.CREATE index [ON JSON] [PREFIX n prefix ...] SCHEMA path [AS name]
+    /// TAG [CASESENSITIVE]|NUMERIC ...`: the documents already stored are
+    /// indexed before the reply.
+    pub(super) async fn ft_create(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let definition = match IndexDefinition::parse(&args[1], &args[2..]) {
+            Ok(definition) => definition,
+            Err(e) => return (RespValue::Error(e), SessionAction::Continue),
+        };
+        if !self.store.create_search_index(definition).await {
+            return (
+                RespValue::Error("ERR Index already exists".to_string()),
+                SessionAction::Continue,
+            );
+        }
+        (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+    }
+
+    /// `FT.SEARCH index query [NOCONTENT] [LIMIT offset num]`: the number of
+    /// matches, then the matching keys in key order, each followed by its
+    /// document as `["$", json]` unless `NOCONTENT`. `LIMIT` defaults to the
+    /// first 10.
+    pub(super) async fn ft_search(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let name = String::from_utf8_lossy(&args[1]);
+        let Some(definition) = self.store.search_index(&name).await else {
+            return no_such_index(&name);
+        };
+        let query = match Query::parse(&definition, &String::from_utf8_lossy(&args[2])) {
+            Ok(query) => query,
+            Err(e) => return (RespValue::Error(e), SessionAction::Continue),
+        };
+        let mut content = true;
+        let (mut offset, mut count) = (0, 10);
+        let mut idx = 3;
+        while idx < args.len() {
+            match upper(&args[idx]).as_str() {
+                "NOCONTENT" => {
+                    content = false;
+                    idx += 1;
+                }
+                "LIMIT" => {
+                    let (Some(from), Some(num)) = (
+                        args.get(idx + 1).and_then(|raw| parse_u64(raw)),
+                        args.get(idx + 2).and_then(|raw| parse_u64(raw)),
+                    ) else {
+                        return syntax_error();
+                    };
+                    offset = from as usize;
+                    count = num as usize;
+                    idx += 3;
+                }
+                _ => return syntax_error(),
+            }
+        }
+
+        let (total, docs) = self.store.search(&name, &query, offset, count).await;
+        let mut reply = vec![RespValue::Integer(total as i64)];
+        for (key, text) in docs {
+            reply.push(RespValue::Bulk(Some(Bytes::from(key))));
+            if content {
+                reply.push(RespValue::Array(vec![
+                    RespValue::Bulk(Some(Bytes::from_static(b"$"))),
+                    RespValue::Bulk(Some(text)),
+                ]));
+            }
+        }
+        (RespValue::Array(reply), SessionAction::Continue)
+    }
+
+    /// `FT.DROPINDEX index`: the indexed documents stay.
+    pub(super) async fn ft_dropindex(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 2 {
+            return syntax_error();
+        }
+        let name = String::from_utf8_lossy(&args[1]);
+        if !self.store.drop_search_index(&name).await {
+            return no_such_index(&name);
+        }
+        (RespValue::Simple("OK".to_string()), SessionAction::Continue)
+    }
+
+    pub(super) async fn ft_list(&self) -> (RespValue, SessionAction) {
+        let names = self
+            .store
+            .search_index_names()
+            .await
+            .into_iter()
+            .map(|name| RespValue::Bulk(Some(Bytes::from(name))))
+            .collect();
+        (RespValue::Array(names), SessionAction::Continue)
+    }
+}
+
+fn no_such_index(name: &str) -> (RespValue, SessionAction) {
+    (
+        RespValue::Error(format!("ERR {}: no such index", name)),
+        SessionAction::Continue,
+    )
+}
+
+fn syntax_error() -> (RespValue, SessionAction) {
+    (
+        RespValue::Error("ERR syntax error".to_string()),
+        SessionAction::Continue,
+    )
+}
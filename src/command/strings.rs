@@ -1,49 +1,25 @@
 use super::*;
-use crate::store::{GetExMode, IncrByError, SetCondition};
+use crate::store::{GetExMode, IncrByError, SetCondition, WrongType};
 
 impl CommandExecutor {
     pub(super) async fn get(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'get' command".to_string()),
-                SessionAction::Continue,
-            );
+        match self.store.get(&args[1]).await {
+            Ok(v) => (RespValue::Bulk(v), SessionAction::Continue),
+            Err(e) => (RespValue::Error(e.to_string()), SessionAction::Continue),
         }
-        (
-            RespValue::Bulk(self.store.get(&args[1]).await),
-            SessionAction::Continue,
-        )
     }
 
     pub(super) async fn getset(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'getset' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         match self.store.getset(args[1].clone(), args[2].clone()).await {
             Ok(v) => (RespValue::Bulk(v), SessionAction::Continue),
-            Err(e) => (
-                RespValue::Error(format!("ERR internal: {}", e)),
-                SessionAction::Continue,
-            ),
+            Err(e) => (write_error(e), SessionAction::Continue),
         }
     }
 
     pub(super) async fn getdel(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'getdel' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         match self.store.getdel(&args[1]).await {
             Ok(v) => (RespValue::Bulk(v), SessionAction::Continue),
-            Err(e) => (
-                RespValue::Error(format!("ERR internal: {}", e)),
-                SessionAction::Continue,
-            ),
+            Err(e) => (write_error(e), SessionAction::Continue),
         }
     }
 
@@ -114,39 +90,19 @@ impl CommandExecutor {
 
         match self.store.getex(&args[1], mode).await {
             Ok(v) => (RespValue::Bulk(v), SessionAction::Continue),
-            Err(e) => (
-                RespValue::Error(format!("ERR internal: {}", e)),
-                SessionAction::Continue,
-            ),
+            Err(e) => (write_error(e), SessionAction::Continue),
         }
     }
 
     pub(super) async fn mget(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'mget' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
-        let mut values = Vec::with_capacity(args.len() - 1);
-        for key in &args[1..] {
-            values.push(RespValue::Bulk(self.store.get(key).await));
-        }
-
-        (RespValue::Array(values), SessionAction::Continue)
+        let values = self.store.mget(&args[1..]).await;
+        (
+            RespValue::Array(values.into_iter().map(RespValue::Bulk).collect()),
+            SessionAction::Continue,
+        )
     }
 
     pub(super) async fn getrange(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 4 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'getrange' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(start) = parse_i64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -160,20 +116,13 @@ impl CommandExecutor {
             );
         };
 
-        (
-            RespValue::Bulk(Some(self.store.getrange(&args[1], start, end).await)),
-            SessionAction::Continue,
-        )
+        match self.store.getrange(&args[1], start, end).await {
+            Ok(v) => (RespValue::Bulk(Some(v)), SessionAction::Continue),
+            Err(e) => (RespValue::Error(e.to_string()), SessionAction::Continue),
+        }
     }
 
     pub(super) async fn set(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'set' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let mut expires_at = None;
         let mut saw_ex = false;
         let mut saw_px = false;
@@ -234,6 +183,38 @@ impl CommandExecutor {
                     expires_at = Some(now_ms().saturating_add(ms));
                     idx += 2;
                 }
+                "EXAT" | "PXAT" => {
+                    if saw_px || saw_ex {
+                        return (
+                            RespValue::Error("ERR syntax error".to_string()),
+                            SessionAction::Continue,
+                        );
+                    }
+                    if idx + 1 >= args.len() {
+                        return (
+                            RespValue::Error("ERR syntax error".to_string()),
+                            SessionAction::Continue,
+                        );
+                    }
+                    let Some(at) = parse_u64(&args[idx + 1]) else {
+                        return (
+                            RespValue::Error(
+                                "ERR value is not an integer or out of range".to_string(),
+                            ),
+                            SessionAction::Continue,
+                        );
+                    };
+                    // Absolute deadlines are what masters propagate, so a
+                    // replica applies the same expiry regardless of lag.
+                    if token == "EXAT" {
+                        saw_ex = true;
+                        expires_at = Some(at.saturating_mul(1000));
+                    } else {
+                        saw_px = true;
+                        expires_at = Some(at);
+                    }
+                    idx += 2;
+                }
                 "NX" => {
                     if saw_nx || saw_xx {
                         return (
@@ -280,15 +261,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn setrange(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 4 {
-            return (
-                RespValue::Error(
-                    "ERR wrong number of arguments for 'setrange' command".to_string(),
-                ),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(offset) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -302,21 +274,11 @@ impl CommandExecutor {
             .await
         {
             Ok(v) => (RespValue::Integer(v), SessionAction::Continue),
-            Err(e) => (
-                RespValue::Error(format!("ERR internal: {}", e)),
-                SessionAction::Continue,
-            ),
+            Err(e) => (write_error(e), SessionAction::Continue),
         }
     }
 
     pub(super) async fn setnx(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'setnx' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         match self
             .store
             .set(args[1].clone(), args[2].clone(), None, SetCondition::Nx)
@@ -332,13 +294,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn setex(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 4 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'setex' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(seconds) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -366,13 +321,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn psetex(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 4 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'psetex' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let Some(milliseconds) = parse_u64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -400,13 +348,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn update(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'update' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let mut expires_at = None;
         let mut saw_ex = false;
         let mut saw_px = false;
@@ -493,31 +434,28 @@ impl CommandExecutor {
     }
 
     pub(super) async fn mset(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 3 || args.len() % 2 == 0 {
+        if args.len() < 3 || args.len().is_multiple_of(2) {
             return (
                 RespValue::Error("ERR wrong number of arguments for 'mset' command".to_string()),
                 SessionAction::Continue,
             );
         }
 
-        let mut idx = 1;
-        while idx < args.len() {
-            let key = args[idx].clone();
-            let value = args[idx + 1].clone();
-            if let Err(e) = self.store.set(key, value, None, SetCondition::None).await {
-                return (
-                    RespValue::Error(format!("ERR internal: {}", e)),
-                    SessionAction::Continue,
-                );
-            }
-            idx += 2;
+        let pairs = args[1..]
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        match self.store.mset(pairs).await {
+            Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Err(e) => (
+                RespValue::Error(format!("ERR internal: {}", e)),
+                SessionAction::Continue,
+            ),
         }
-
-        (RespValue::Simple("OK".to_string()), SessionAction::Continue)
     }
 
     pub(super) async fn msetnx(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 3 || args.len() % 2 == 0 {
+        if args.len() < 3 || args.len().is_multiple_of(2) {
             return (
                 RespValue::Error("ERR wrong number of arguments for 'msetnx' command".to_string()),
                 SessionAction::Continue,
@@ -550,12 +488,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn incrby(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'incrby' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         let Some(by) = parse_i64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -566,12 +498,6 @@ impl CommandExecutor {
     }
 
     pub(super) async fn decrby(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'decrby' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         let Some(by) = parse_i64(&args[2]) else {
             return (
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
@@ -609,66 +535,18 @@ impl CommandExecutor {
                 RespValue::Error("ERR value is not an integer or out of range".to_string()),
                 SessionAction::Continue,
             ),
-            Err(IncrByError::Internal) => (
-                RespValue::Error("ERR internal persistence failure".to_string()),
+            Err(IncrByError::WrongType) => (
+                RespValue::Error(WrongType.to_string()),
                 SessionAction::Continue,
             ),
-        }
-    }
-
-    pub(super) async fn memory(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'memory' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
-        let sub = upper(&args[1]);
-        match sub.as_str() {
-            "USAGE" => {
-                if args.len() < 3 {
-                    return (
-                        RespValue::Error(
-                            "ERR wrong number of arguments for 'memory|usage' command".to_string(),
-                        ),
-                        SessionAction::Continue,
-                    );
-                }
-                (
-                    RespValue::Bulk(
-                        self.store
-                            .memory_usage(&args[2])
-                            .await
-                            .map(|v| v.to_string().into_bytes()),
-                    ),
-                    SessionAction::Continue,
-                )
-            }
-            "STATS" => (
-                RespValue::Array(vec![
-                    RespValue::Bulk(Some(b"peak.allocated".to_vec())),
-                    RespValue::Integer(0),
-                    RespValue::Bulk(Some(b"total.allocated".to_vec())),
-                    RespValue::Integer(0),
-                ]),
-                SessionAction::Continue,
-            ),
-            _ => (
-                RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
+            Err(IncrByError::Internal) => (
+                RespValue::Error("ERR internal persistence failure".to_string()),
                 SessionAction::Continue,
             ),
         }
     }
 
     pub(super) async fn object(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() < 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'object' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
-
         let sub = upper(&args[1]);
         match sub.as_str() {
             "ENCODING" => (
@@ -676,7 +554,7 @@ impl CommandExecutor {
                     self.store
                         .object_encoding(&args[2])
                         .await
-                        .map(|v| v.as_bytes().to_vec()),
+                        .map(|v| Bytes::from_static(v.as_bytes())),
                 ),
                 SessionAction::Continue,
             ),
@@ -687,6 +565,10 @@ impl CommandExecutor {
                 }
                 (RespValue::Integer(0), SessionAction::Continue)
             }
+            "VERSION" => match self.store.version(&args[2]).await {
+                Some(version) => (RespValue::Integer(version as i64), SessionAction::Continue),
+                None => (RespValue::Bulk(None), SessionAction::Continue),
+            },
             _ => (
                 RespValue::Error(format!("ERR unknown subcommand '{}'", sub.to_lowercase())),
                 SessionAction::Continue,
@@ -695,31 +577,25 @@ impl CommandExecutor {
     }
 
     pub(super) async fn strlen(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 2 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'strlen' command".to_string()),
-                SessionAction::Continue,
-            );
+        match self.store.strlen(&args[1]).await {
+            Ok(v) => (RespValue::Integer(v), SessionAction::Continue),
+            Err(e) => (RespValue::Error(e.to_string()), SessionAction::Continue),
         }
-        (
-            RespValue::Integer(self.store.strlen(&args[1]).await),
-            SessionAction::Continue,
-        )
     }
 
     pub(super) async fn append(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
-        if args.len() != 3 {
-            return (
-                RespValue::Error("ERR wrong number of arguments for 'append' command".to_string()),
-                SessionAction::Continue,
-            );
-        }
         match self.store.append(&args[1], &args[2]).await {
             Ok(v) => (RespValue::Integer(v), SessionAction::Continue),
-            Err(e) => (
-                RespValue::Error(format!("ERR internal: {}", e)),
-                SessionAction::Continue,
-            ),
+            Err(e) => (write_error(e), SessionAction::Continue),
         }
     }
 }
+
+/// The reply to a string command that failed: `WRONGTYPE` for a key holding
+/// a JSON document, an internal error otherwise.
+fn write_error(e: Box<dyn std::error::Error>) -> RespValue {
+    match e.downcast_ref::<WrongType>() {
+        Some(wrong) => RespValue::Error(wrong.to_string()),
+        None => RespValue::Error(format!("ERR internal: {}", e)),
+    }
+}
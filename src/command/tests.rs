@@ -1,6 +1,11 @@
 use super::*;
-use crate::auth::User;
-use crate::persistence::{Aof, AofFsync};
+use crate::audit::AuditLog;
+use crate::auth::{Permissions, User};
+use crate::jwt::{JwtVerifier, sign_hs256};
+use crate::lockout::LockoutPolicy;
+use crate::persistence::{Aof, AofFormat, AofFsync};
+use crate::ratelimit::{RateLimit, RateLimiter};
+use crate::wasm::{WasmRuntime, WasmSettings};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -9,20 +14,65 @@ use std::sync::atomic::{AtomicU64, Ordering};
 static TEST_ID: AtomicU64 = AtomicU64::new(1);
 
 async fn make_executor() -> (CommandExecutor, SessionAuth, PathBuf) {
+    TestExecutor::default().build().await
+}
+
+/// Builds an executor over a fresh AOF for tests that need more than
+/// `make_executor` sets up.
+#[derive(Default)]
+struct TestExecutor {
+    auth: Option<Auth>,
+    rate_limits: HashMap<String, RateLimit>,
+    audit_path: Option<PathBuf>,
+}
+
+impl TestExecutor {
+    fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    fn rate_limit(mut self, user: &str, limit: RateLimit) -> Self {
+        self.rate_limits.insert(user.to_string(), limit);
+        self
+    }
+
+    fn audit_log(mut self, path: PathBuf) -> Self {
+        self.audit_path = Some(path);
+        self
+    }
+
+    async fn build(self) -> (CommandExecutor, SessionAuth, PathBuf) {
+        let path = temp_path("aof");
+        let aof = Aof::open(&path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        let auth = self
+            .auth
+            .unwrap_or_else(|| Auth::new(HashMap::new(), "default".to_string(), None));
+        let executor = CommandExecutor::new(
+            auth,
+            store,
+            Arc::new(ServerStats::new()),
+            "127.0.0.1:0".to_string(),
+            None,
+            RateLimiter::new(self.rate_limits),
+            AuditLog::open(self.audit_path.as_deref()).expect("audit log"),
+        );
+        (executor, SessionAuth::default(), path)
+    }
+}
+
+/// A path under the temp dir no other test uses, ending in `extension`.
+fn temp_path(extension: &str) -> PathBuf {
     let id = TEST_ID.fetch_add(1, Ordering::Relaxed);
-    let path = std::env::temp_dir().join(format!("fedis-test-{}-{}.aof", std::process::id(), id));
-    let aof = Aof::open(&path, AofFsync::Always).await.expect("open aof");
-    let store = Store::new(aof, None).await.expect("new store");
-    let users: HashMap<String, User> = HashMap::new();
-    let auth = Auth::new(users, "default".to_string());
-    let executor = CommandExecutor::new(
-        auth,
-        store,
-        Arc::new(ServerStats::new()),
-        "127.0.0.1:0".to_string(),
-        None,
-    );
-    (executor, SessionAuth::default(), path)
+    std::env::temp_dir().join(format!(
+        "fedis-test-{}-{}.{}",
+        std::process::id(),
+        id,
+        extension
+    ))
 }
 
 async fn run(executor: &CommandExecutor, session: &mut SessionAuth, cmd: &[&str]) -> RespValue {
@@ -41,7 +91,7 @@ fn expect_int(value: RespValue) -> i64 {
 
 fn expect_bulk(value: RespValue) -> Option<Vec<u8>> {
     if let RespValue::Bulk(v) = value {
-        v
+        v.map(Vec::from)
     } else {
         panic!("expected bulk response");
     }
@@ -149,6 +199,41 @@ async fn unlink_behaves_like_del() {
     let _ = std::fs::remove_file(path);
 }
 
+#[tokio::test]
+async fn flushall_empties_the_keyspace_sync_or_async() {
+    let (executor, mut session, path) = make_executor().await;
+
+    for mode in [None, Some("ASYNC"), Some("SYNC")] {
+        let _ = run(&executor, &mut session, &["SET", "a", "1"]).await;
+        let _ = run(&executor, &mut session, &["SET", "b", "2"]).await;
+        let mut cmd = vec!["FLUSHALL"];
+        cmd.extend(mode);
+        assert_eq!(
+            expect_simple(run(&executor, &mut session, &cmd).await),
+            "OK"
+        );
+        assert_eq!(
+            expect_int(run(&executor, &mut session, &["DBSIZE"]).await),
+            0
+        );
+    }
+    let _ = run(&executor, &mut session, &["SET", "a", "1"]).await;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["FLUSHDB", "async"]).await),
+        "OK"
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["EXISTS", "a"]).await),
+        0
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["FLUSHALL", "LATER"]).await)
+            .contains("syntax error")
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
 #[tokio::test]
 async fn exists_counts_duplicates_like_redis() {
     let (executor, mut session, path) = make_executor().await;
@@ -172,6 +257,228 @@ async fn exists_returns_arity_error_without_keys() {
     let _ = std::fs::remove_file(path);
 }
 
+#[tokio::test]
+async fn registry_checks_arity_and_categories_before_dispatch() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let err = expect_error(run(&executor, &mut session, &["GET"]).await);
+    assert_eq!(err, "ERR wrong number of arguments for 'get' command");
+    let err = expect_error(run(&executor, &mut session, &["DBSIZE", "extra"]).await);
+    assert_eq!(err, "ERR wrong number of arguments for 'dbsize' command");
+    let err = expect_error(run(&executor, &mut session, &["NOSUCH", "a"]).await);
+    assert_eq!(err, "ERR unknown command 'nosuch'");
+
+    let RespValue::Array(info) = run(&executor, &mut session, &["COMMAND", "INFO", "set"]).await
+    else {
+        panic!("expected array response");
+    };
+    let RespValue::Array(entry) = &info[0] else {
+        panic!("expected entry array");
+    };
+    let RespValue::Array(flags) = &entry[2] else {
+        panic!("expected flag array");
+    };
+    assert!(
+        flags
+            .iter()
+            .any(|flag| matches!(flag, RespValue::Bulk(Some(v)) if &v[..] == b"denyoom"))
+    );
+
+    let _ = run(
+        &executor,
+        &mut session,
+        &["ACL", "SETUSER", "reader", "on", ">pw", "+@read", "-ttl"],
+    )
+    .await;
+    let _ = run(&executor, &mut session, &["AUTH", "reader", "pw"]).await;
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "missing"]).await),
+        None
+    );
+    let err = expect_error(run(&executor, &mut session, &["SET", "a", "1"]).await);
+    assert!(err.starts_with("NOPERM"), "{err}");
+    let err = expect_error(run(&executor, &mut session, &["TTL", "a"]).await);
+    assert!(err.starts_with("NOPERM"), "{err}");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn all_commands_but_a_category_are_allowed() {
+    let (executor, mut admin, path) = make_executor().await;
+
+    let _ = run(
+        &executor,
+        &mut admin,
+        &["ACL", "SETUSER", "admin", "on", ">root", "+@all"],
+    )
+    .await;
+    let _ = run(&executor, &mut admin, &["AUTH", "admin", "root"]).await;
+    let _ = run(
+        &executor,
+        &mut admin,
+        &[
+            "ACL",
+            "SETUSER",
+            "ops",
+            "on",
+            ">pw",
+            "allkeys",
+            "+@all",
+            "-@dangerous",
+            "+info",
+        ],
+    )
+    .await;
+    let mut session = SessionAuth::default();
+    let _ = run(&executor, &mut session, &["AUTH", "ops", "pw"]).await;
+    expect_simple(run(&executor, &mut session, &["SET", "a", "1"]).await);
+    assert!(expect_bulk(run(&executor, &mut session, &["INFO", "server"]).await).is_some());
+    let err = expect_error(run(&executor, &mut session, &["FLUSHALL"]).await);
+    assert!(err.starts_with("NOPERM"), "{err}");
+
+    let RespValue::Array(users) = run(&executor, &mut admin, &["ACL", "LIST"]).await else {
+        panic!("expected array response");
+    };
+    let ops = users
+        .into_iter()
+        .filter_map(expect_bulk)
+        .map(|v| String::from_utf8(v).expect("utf8"))
+        .find(|line| line.starts_with("user ops "))
+        .expect("ops in ACL LIST");
+    assert!(ops.contains(" +@all -"), "{ops}");
+    assert!(ops.contains(" -flushall"), "{ops}");
+    assert!(!ops.contains(" -info"), "{ops}");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn command_list_filters_by_category_and_pattern() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let names = |value: RespValue| -> Vec<String> {
+        let RespValue::Array(items) = value else {
+            panic!("expected array response");
+        };
+        items
+            .into_iter()
+            .filter_map(expect_bulk)
+            .map(|v| String::from_utf8(v).expect("utf8"))
+            .collect()
+    };
+    let all = names(run(&executor, &mut session, &["COMMAND", "LIST"]).await);
+    assert_eq!(
+        all.len() as i64,
+        expect_int(run(&executor, &mut session, &["COMMAND", "COUNT"]).await)
+    );
+    assert!(all.contains(&"get".to_string()));
+
+    let json = names(
+        run(
+            &executor,
+            &mut session,
+            &["COMMAND", "LIST", "FILTERBY", "ACLCAT", "json"],
+        )
+        .await,
+    );
+    assert!(!json.is_empty());
+    assert!(json.iter().all(|name| name.starts_with("json.")));
+
+    let pexp = names(
+        run(
+            &executor,
+            &mut session,
+            &["COMMAND", "LIST", "FILTERBY", "PATTERN", "PEXPIRE*"],
+        )
+        .await,
+    );
+    assert_eq!(pexp, vec!["pexpire".to_string(), "pexpireat".to_string()]);
+
+    let modules = names(
+        run(
+            &executor,
+            &mut session,
+            &["COMMAND", "LIST", "FILTERBY", "MODULE", "json"],
+        )
+        .await,
+    );
+    assert!(modules.is_empty());
+
+    let err = expect_error(
+        run(
+            &executor,
+            &mut session,
+            &["COMMAND", "LIST", "FILTERBY", "X", "y"],
+        )
+        .await,
+    );
+    assert_eq!(err, "ERR syntax error");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn command_getkeys_follows_key_specs() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let keys = |value: RespValue| -> Vec<Vec<u8>> {
+        let RespValue::Array(items) = value else {
+            panic!("expected array response");
+        };
+        items.into_iter().filter_map(expect_bulk).collect()
+    };
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &["COMMAND", "GETKEYS", "MSET", "a", "1", "b", "2"]
+            )
+            .await
+        ),
+        vec![b"a".to_vec(), b"b".to_vec()]
+    );
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &["COMMAND", "GETKEYS", "memory", "usage", "k"]
+            )
+            .await
+        ),
+        vec![b"k".to_vec()]
+    );
+
+    let RespValue::Array(entries) = run(
+        &executor,
+        &mut session,
+        &["COMMAND", "GETKEYSANDFLAGS", "GETDEL", "k"],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    let RespValue::Array(entry) = &entries[0] else {
+        panic!("expected entry array");
+    };
+    assert!(matches!(&entry[0], RespValue::Bulk(Some(v)) if &v[..] == b"k"));
+    let RespValue::Array(flags) = &entry[1] else {
+        panic!("expected flag array");
+    };
+    assert!(matches!(&flags[0], RespValue::Simple(v) if v == "RW"));
+
+    let err = expect_error(run(&executor, &mut session, &["COMMAND", "GETKEYS", "PING"]).await);
+    assert_eq!(err, "ERR The command has no key arguments");
+    let err = expect_error(run(&executor, &mut session, &["COMMAND", "GETKEYS", "GET"]).await);
+    assert_eq!(err, "ERR Invalid number of arguments specified for command");
+    let err = expect_error(run(&executor, &mut session, &["COMMAND", "GETKEYS", "NOSUCH"]).await);
+    assert_eq!(err, "ERR Invalid command specified");
+
+    let _ = std::fs::remove_file(path);
+}
+
 #[tokio::test]
 async fn set_rejects_conflicting_nx_xx_options() {
     let (executor, mut session, path) = make_executor().await;
@@ -182,6 +489,38 @@ async fn set_rejects_conflicting_nx_xx_options() {
     let _ = std::fs::remove_file(path);
 }
 
+#[tokio::test]
+async fn set_accepts_absolute_expiry_deadlines() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let _ = run(
+        &executor,
+        &mut session,
+        &["SET", "a", "1", "PXAT", "4102444800000"],
+    )
+    .await;
+    let ttl = expect_int(run(&executor, &mut session, &["TTL", "a"]).await);
+    assert!(ttl > 0);
+
+    let _ = run(&executor, &mut session, &["SET", "b", "1", "EXAT", "1"]).await;
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "b"]).await),
+        None
+    );
+
+    let err = expect_error(
+        run(
+            &executor,
+            &mut session,
+            &["SET", "c", "1", "EX", "10", "PXAT", "1"],
+        )
+        .await,
+    );
+    assert_eq!(err, "ERR syntax error");
+
+    let _ = std::fs::remove_file(path);
+}
+
 #[tokio::test]
 async fn update_rejects_conflicting_ex_px_options() {
     let (executor, mut session, path) = make_executor().await;
@@ -267,28 +606,2302 @@ async fn json_set_get_type_del_root_path() {
 }
 
 #[tokio::test]
-async fn json_rejects_non_root_path() {
+async fn json_paths_read_write_and_delete_matches() {
     let (executor, mut session, path) = make_executor().await;
+    let doc = r#"{"a":{"b":1},"items":[{"n":1},{"n":5},{"n":9}]}"#;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["JSON.SET", "j", "$", doc]).await),
+        "OK"
+    );
+    let get = |path: &'static str| {
+        let executor = &executor;
+        async move {
+            let mut session = SessionAuth::default();
+            expect_bulk(run(executor, &mut session, &["JSON.GET", "j", path]).await)
+                .map(|v| String::from_utf8(v).unwrap())
+        }
+    };
+
+    assert_eq!(get("$.a.b").await.as_deref(), Some("[1]"));
+    assert_eq!(get(".a.b").await.as_deref(), Some("1"));
+    assert_eq!(get("$.items[?(@.n > 2)].n").await.as_deref(), Some("[5,9]"));
+    assert_eq!(get("$.missing").await.as_deref(), Some("[]"));
+    let err = expect_error(run(&executor, &mut session, &["JSON.GET", "j", ".missing"]).await);
+    assert_eq!(err, "ERR Path '.missing' does not exist");
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.GET", "j", "$.a.b", "$..n"]).await),
+        Some(br#"{"$..n":[1,5,9],"$.a.b":[1]}"#.to_vec())
+    );
+
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["JSON.SET", "j", "$.items[*].n", "0"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    assert_eq!(get("$..n").await.as_deref(), Some("[0,0,0]"));
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["JSON.SET", "j", "$.a.c", "true"]).await),
+        "OK"
+    );
+    assert_eq!(
+        expect_bulk(
+            run(
+                &executor,
+                &mut session,
+                &["JSON.SET", "j", "$.a.c", "1", "NX"]
+            )
+            .await
+        ),
+        None
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.SET", "j", "$.x.y", "1"]).await),
+        None
+    );
+    let err = expect_error(run(&executor, &mut session, &["JSON.SET", "new", "$.a", "1"]).await);
+    assert_eq!(err, "ERR new objects must be created at the root");
+
+    let RespValue::Array(types) = run(&executor, &mut session, &["JSON.TYPE", "j", "$.a.*"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert_eq!(types.len(), 2);
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.TYPE", "j", ".a.c"]).await),
+        Some(b"boolean".to_vec())
+    );
+
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["JSON.DEL", "j", "$.items[0:2]"]).await),
+        2
+    );
+    assert_eq!(get("$.items").await.as_deref(), Some(r#"[[{"n":0}]]"#));
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["JSON.DEL", "j", "$"]).await),
+        1
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["EXISTS", "j"]).await),
+        0
+    );
 
-    let err = expect_error(run(&executor, &mut session, &["JSON.GET", "j", "$.a"]).await);
-    assert_eq!(err, "ERR only root path is supported");
+    let _ = run(&executor, &mut session, &["SET", "plain", "not json"]).await;
+    let err = expect_error(run(&executor, &mut session, &["JSON.GET", "plain"]).await);
+    assert!(err.starts_with("WRONGTYPE"), "{}", err);
 
     let _ = std::fs::remove_file(path);
 }
 
 #[tokio::test]
-async fn acl_whoami_and_module_list_work() {
+async fn json_object_and_string_helpers() {
     let (executor, mut session, path) = make_executor().await;
+    let doc = r#"{"name":"ab","nested":{"name":"xyz","n":1},"n":3}"#;
+    let _ = run(&executor, &mut session, &["JSON.SET", "j", "$", doc]).await;
 
+    let RespValue::Array(keys) = run(&executor, &mut session, &["JSON.OBJKEYS", "j"]).await else {
+        panic!("expected array response");
+    };
+    assert_eq!(keys.len(), 3);
+    let RespValue::Array(lens) =
+        run(&executor, &mut session, &["JSON.OBJLEN", "j", "$..nested"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(lens.as_slice(), [RespValue::Integer(2)]));
+    let err = expect_error(run(&executor, &mut session, &["JSON.OBJLEN", "j", ".n"]).await);
     assert_eq!(
-        expect_bulk(run(&executor, &mut session, &["ACL", "WHOAMI"]).await),
-        Some(b"default".to_vec())
+        err,
+        "ERR wrong type of path value - expected object but found integer"
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.OBJLEN", "missing"]).await),
+        None
+    );
+
+    let RespValue::Array(lens) = run(
+        &executor,
+        &mut session,
+        &["JSON.STRAPPEND", "j", "$..name", "\"cd\""],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        lens.as_slice(),
+        [RespValue::Integer(4), RespValue::Integer(5)]
+    ));
+    let RespValue::Array(lens) = run(&executor, &mut session, &["JSON.STRLEN", "j", "$.*"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        lens.as_slice(),
+        [
+            RespValue::Bulk(None),
+            RespValue::Integer(4),
+            RespValue::Bulk(None)
+        ]
+    ));
+    assert_eq!(
+        expect_int(
+            run(
+                &executor,
+                &mut session,
+                &["JSON.STRLEN", "j", ".nested.name"]
+            )
+            .await
+        ),
+        5
+    );
+    let err = expect_error(
+        run(
+            &executor,
+            &mut session,
+            &["JSON.STRAPPEND", "j", ".name", "cd"],
+        )
+        .await,
+    );
+    assert_eq!(err, "ERR the value to append must be a JSON string");
+    let err = expect_error(
+        run(
+            &executor,
+            &mut session,
+            &["JSON.STRAPPEND", "nope", "\"x\""],
+        )
+        .await,
+    );
+    assert_eq!(
+        err,
+        "ERR could not perform this operation on a key that doesn't exist"
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn json_merge_patches_matches_and_deletes_nulls() {
+    let (executor, mut session, path) = make_executor().await;
+    let patch = r#"{"a":1,"b":{"c":2}}"#;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["JSON.MERGE", "j", "$", patch]).await),
+        "OK"
+    );
+    let patch = r#"{"b":{"c":null,"d":3},"e":[1]}"#;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["JSON.MERGE", "j", "$", patch]).await),
+        "OK"
+    );
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["JSON.MERGE", "j", "$.a", "null"]).await),
+        "OK"
+    );
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["JSON.MERGE", "j", "$.f", "{\"g\":1}"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.GET", "j"]).await),
+        Some(br#"{"b":{"d":3},"e":[1],"f":{"g":1}}"#.to_vec())
+    );
+    let err = expect_error(run(&executor, &mut session, &["JSON.MERGE", "new", "$.a", "1"]).await);
+    assert_eq!(err, "ERR new objects must be created at the root");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn json_toggle_flips_booleans_and_clear_empties_containers() {
+    let (executor, mut session, path) = make_executor().await;
+    let doc = r#"{"on":true,"inner":{"on":false},"list":[1,2],"n":7,"s":"keep"}"#;
+    let _ = run(&executor, &mut session, &["JSON.SET", "j", "$", doc]).await;
+
+    let RespValue::Array(flags) =
+        run(&executor, &mut session, &["JSON.TOGGLE", "j", "$..on"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        flags.as_slice(),
+        [RespValue::Integer(0), RespValue::Integer(1)]
+    ));
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.TOGGLE", "j", ".on"]).await),
+        Some(b"true".to_vec())
+    );
+    let err = expect_error(run(&executor, &mut session, &["JSON.TOGGLE", "j", ".n"]).await);
+    assert_eq!(
+        err,
+        "ERR wrong type of path value - expected boolean but found integer"
+    );
+
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["JSON.CLEAR", "j", "$.*"]).await),
+        3
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.GET", "j"]).await),
+        Some(br#"{"inner":{},"list":[],"n":0,"on":true,"s":"keep"}"#.to_vec())
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["JSON.CLEAR", "j"]).await),
+        1
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["JSON.GET", "j"]).await),
+        Some(b"{}".to_vec())
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn json_get_formats_and_json_resp_nests() {
+    let (executor, mut session, path) = make_executor().await;
+    let doc = r#"{"a":[1,2.5],"b":{"t":true,"n":null},"e":[]}"#;
+    let _ = run(&executor, &mut session, &["JSON.SET", "j", "$", doc]).await;
+
+    assert_eq!(
+        expect_bulk(
+            run(
+                &executor,
+                &mut session,
+                &[
+                    "JSON.GET", "j", "INDENT", "  ", "NEWLINE", "\n", "SPACE", " ", ".b"
+                ],
+            )
+            .await
+        ),
+        Some(b"{\n  \"n\": null,\n  \"t\": true\n}".to_vec())
+    );
+    assert_eq!(
+        expect_bulk(
+            run(
+                &executor,
+                &mut session,
+                &["JSON.GET", "j", "SPACE", " ", "$.e"]
+            )
+            .await
+        ),
+        Some(b"[[]]".to_vec())
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["JSON.GET", "j", "INDENT"]).await)
+            .starts_with("ERR syntax")
+    );
+
+    let RespValue::Array(root) = run(&executor, &mut session, &["JSON.RESP", "j"]).await else {
+        panic!("expected array response");
+    };
+    assert!(matches!(&root[0], RespValue::Simple(s) if s == "{"));
+    assert_eq!(root.len(), 7);
+    let RespValue::Array(list) = &root[2] else {
+        panic!("expected nested array");
+    };
+    assert!(matches!(
+        list.as_slice(),
+        [RespValue::Simple(open), RespValue::Integer(1), RespValue::Bulk(Some(n))]
+            if open == "[" && n.as_ref() == b"2.5"
+    ));
+    let RespValue::Array(matches) =
+        run(&executor, &mut session, &["JSON.RESP", "j", "$.b.t"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(matches.as_slice(), [RespValue::Simple(t)] if t == "true"));
+    assert!(
+        expect_error(run(&executor, &mut session, &["JSON.RESP", "j", ".missing"]).await)
+            .starts_with("ERR")
+    );
+    assert!(matches!(
+        run(&executor, &mut session, &["JSON.RESP", "nokey"]).await,
+        RespValue::Bulk(None)
+    ));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn ft_search_finds_indexed_json_documents() {
+    let (executor, mut session, path) = make_executor().await;
+    let items = [
+        ("item:1", r#"{"name":"Lamp","color":"red","price":12}"#),
+        ("item:2", r#"{"name":"Chair","color":"Red","price":40}"#),
+        ("item:3", r#"{"name":"Table","color":"blue","price":99.5}"#),
+        ("other:1", r#"{"color":"red","price":1}"#),
+    ];
+    for (key, doc) in &items[..2] {
+        run(&executor, &mut session, &["JSON.SET", key, "$", doc]).await;
+    }
+    let create = [
+        "FT.CREATE",
+        "idx",
+        "ON",
+        "JSON",
+        "PREFIX",
+        "1",
+        "item:",
+        "SCHEMA",
+        "$.color",
+        "AS",
+        "color",
+        "TAG",
+        "$.price",
+        "AS",
+        "price",
+        "NUMERIC",
+    ];
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &create).await),
+        "OK"
+    );
+    assert!(expect_error(run(&executor, &mut session, &create).await).contains("already exists"));
+    for (key, doc) in &items[2..] {
+        run(&executor, &mut session, &["JSON.SET", key, "$", doc]).await;
+    }
+    run(&executor, &mut session, &["SET", "item:4", "not json"]).await;
+
+    let search = |query: &'static str, extra: &'static [&'static str]| {
+        let mut cmd = vec!["FT.SEARCH", "idx", query];
+        cmd.extend_from_slice(extra);
+        cmd
+    };
+    let keys = |reply: RespValue| -> (i64, Vec<String>) {
+        let RespValue::Array(items) = reply else {
+            panic!("expected array response");
+        };
+        let RespValue::Integer(total) = items[0] else {
+            panic!("expected total");
+        };
+        let keys = items[1..]
+            .iter()
+            .filter_map(|item| match item {
+                RespValue::Bulk(Some(key)) => Some(String::from_utf8_lossy(key).into_owned()),
+                _ => None,
+            })
+            .collect();
+        (total, keys)
+    };
+
+    let RespValue::Array(full) = run(&executor, &mut session, &search("@color:{blue}", &[])).await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        full.as_slice(),
+        [RespValue::Integer(1), RespValue::Bulk(Some(key)), RespValue::Array(content)]
+            if key.as_ref() == b"item:3" && content.len() == 2
+    ));
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &search("@color:{red}", &["NOCONTENT"])
+            )
+            .await
+        ),
+        (2, vec!["item:1".to_string(), "item:2".to_string()])
+    );
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &search("@color:{bl*}", &["NOCONTENT"])
+            )
+            .await
+        ),
+        (1, vec!["item:3".to_string()])
+    );
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &search("@price:[(12 +inf] @color:{red | blue}", &["NOCONTENT"]),
+            )
+            .await
+        ),
+        (2, vec!["item:2".to_string(), "item:3".to_string()])
+    );
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &search("*", &["NOCONTENT", "LIMIT", "1", "1"]),
+            )
+            .await
+        ),
+        (3, vec!["item:2".to_string()])
+    );
+
+    // Writes keep the index current.
+    run(
+        &executor,
+        &mut session,
+        &["JSON.SET", "item:1", "$.color", r#""green""#],
+    )
+    .await;
+    run(&executor, &mut session, &["DEL", "item:2"]).await;
+    assert_eq!(
+        keys(
+            run(
+                &executor,
+                &mut session,
+                &search("@color:{red}", &["NOCONTENT"])
+            )
+            .await
+        )
+        .0,
+        0
+    );
+    run(&executor, &mut session, &["PEXPIRE", "item:3", "1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    assert_eq!(
+        keys(run(&executor, &mut session, &search("*", &["NOCONTENT"])).await),
+        (1, vec!["item:1".to_string()])
+    );
+
+    assert!(
+        expect_error(run(&executor, &mut session, &search("@size:{x}", &[])).await)
+            .contains("Unknown field")
+    );
+    let RespValue::Array(names) = run(&executor, &mut session, &["FT._LIST"]).await else {
+        panic!("expected array response");
+    };
+    assert_eq!(names.len(), 1);
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["FT.DROPINDEX", "idx"]).await),
+        "OK"
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &search("*", &[])).await)
+            .contains("no such index")
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn time_series_add_range_and_replay() {
+    let (executor, mut session, path) = make_executor().await;
+    let samples = |reply: RespValue| -> Vec<(i64, String)> {
+        let RespValue::Array(items) = reply else {
+            panic!("expected array response");
+        };
+        items
+            .iter()
+            .map(|item| match item {
+                RespValue::Array(pair) => match pair.as_slice() {
+                    [RespValue::Integer(ts), RespValue::Simple(value)] => (*ts, value.clone()),
+                    other => panic!("unexpected sample {:?}", other),
+                },
+                other => panic!("unexpected sample {:?}", other),
+            })
+            .collect()
+    };
+
+    assert!(matches!(
+        run(&executor, &mut session, &["TS.ADD", "t", "1000", "1.5"]).await,
+        RespValue::Integer(1000)
+    ));
+    for (ts, value) in [("1004", "2.5"), ("1012", "4"), ("1002", "-1")] {
+        run(&executor, &mut session, &["TS.ADD", "t", ts, value]).await;
+    }
+    assert!(
+        expect_error(run(&executor, &mut session, &["TS.ADD", "t", "1004", "9"]).await)
+            .contains("DUPLICATE_POLICY")
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["TS.ADD", "t", "now", "9"]).await)
+            .contains("invalid timestamp")
+    );
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["TS.CREATE", "r", "RETENTION", "100"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["TS.CREATE", "r"]).await)
+            .contains("already exists")
+    );
+
+    let RespValue::Array(madd) = run(
+        &executor,
+        &mut session,
+        &[
+            "TS.MADD", "r", "500", "1", "missing", "1", "1", "r", "300", "2",
+        ],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        madd.as_slice(),
+        [RespValue::Integer(500), RespValue::Error(missing), RespValue::Error(old)]
+            if missing.contains("does not exist") && old.contains("retention")
+    ));
+
+    assert_eq!(
+        samples(run(&executor, &mut session, &["TS.RANGE", "t", "-", "+"]).await),
+        vec![
+            (1000, "1.5".to_string()),
+            (1002, "-1".to_string()),
+            (1004, "2.5".to_string()),
+            (1012, "4".to_string()),
+        ]
+    );
+    assert_eq!(
+        samples(
+            run(
+                &executor,
+                &mut session,
+                &["TS.RANGE", "t", "1001", "1004", "COUNT", "1"]
+            )
+            .await
+        ),
+        vec![(1002, "-1".to_string())]
+    );
+    assert_eq!(
+        samples(
+            run(
+                &executor,
+                &mut session,
+                &["TS.RANGE", "t", "-", "+", "AGGREGATION", "avg", "10"]
+            )
+            .await
+        ),
+        vec![(1000, "1".to_string()), (1010, "4".to_string())]
+    );
+    assert!(matches!(
+        run(&executor, &mut session, &["TS.GET", "t"]).await,
+        RespValue::Array(pair) if matches!(pair.as_slice(), [RespValue::Integer(1012), _])
+    ));
+    assert!(
+        expect_error(run(&executor, &mut session, &["TS.GET", "nope"]).await)
+            .contains("does not exist")
+    );
+
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["TYPE", "t"]).await),
+        "TSDB-TYPE"
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["GET", "t"]).await).starts_with("WRONGTYPE")
+    );
+    run(&executor, &mut session, &["SET", "s", "plain"]).await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["TS.ADD", "s", "1", "1"]).await)
+            .starts_with("WRONGTYPE")
+    );
+
+    // The AOF holds the creation and then each sample on its own.
+    let aof = Aof::open(&path, AofFsync::Always, None, AofFormat::Fedis)
+        .await
+        .expect("reopen aof");
+    let store = Store::new(aof, None).await.expect("reopen store");
+    assert_eq!(store.key_type(b"t").await, "TSDB-TYPE");
+    let series = store.ts_series(b"t").await.expect("series");
+    assert_eq!(
+        crate::timeseries::Series::new(&series)
+            .range(0, u64::MAX)
+            .count(),
+        4
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn throttle_limits_with_gcra_in_non_redis_mode() {
+    let (executor, mut session, path) = make_executor().await;
+    let cmd = ["THROTTLE", "user:1", "1", "1", "60"];
+    assert!(
+        expect_error(run(&executor, &mut session, &cmd).await).contains("FEDIS_NON_REDIS_MODE")
+    );
+
+    let executor = executor.with_non_redis_mode(true);
+    let throttle = |reply: RespValue| -> Vec<i64> {
+        let RespValue::Array(items) = reply else {
+            panic!("expected array response");
+        };
+        items.into_iter().map(expect_int).collect()
+    };
+    // One a minute, with a burst of one more.
+    assert_eq!(
+        throttle(run(&executor, &mut session, &cmd).await),
+        vec![0, 2, 1, -1, 60]
+    );
+    assert_eq!(
+        throttle(run(&executor, &mut session, &cmd).await),
+        vec![0, 2, 0, -1, 120]
+    );
+    let refused = throttle(run(&executor, &mut session, &cmd).await);
+    assert_eq!(refused[..3], [1, 2, 0]);
+    assert!((59..=60).contains(&refused[3]), "{:?}", refused);
+    assert!((119..=120).contains(&refused[4]), "{:?}", refused);
+    let ttl = expect_int(run(&executor, &mut session, &["TTL", "user:1"]).await);
+    assert!((119..=120).contains(&ttl));
+
+    assert_eq!(
+        throttle(
+            run(
+                &executor,
+                &mut session,
+                &["THROTTLE", "user:2", "1", "1", "60", "3"]
+            )
+            .await
+        ),
+        vec![1, 2, 2, -1, 0]
+    );
+    assert!(
+        expect_error(
+            run(
+                &executor,
+                &mut session,
+                &["THROTTLE", "user:2", "1", "0", "60"]
+            )
+            .await
+        )
+        .contains("positive")
+    );
+    run(&executor, &mut session, &["SET", "plain", "x"]).await;
+    assert!(
+        expect_error(
+            run(
+                &executor,
+                &mut session,
+                &["THROTTLE", "plain", "1", "1", "60"]
+            )
+            .await
+        )
+        .contains("not an integer")
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+/// A module with a bump allocator and a `copy` handler that sets its second
+/// key to the first one's value, and a `spin` that never returns.
+const DEMO_WAT: &str = r#"(module
+  (import "fedis" "get" (func $get (param i32 i32) (result i64)))
+  (import "fedis" "set" (func $set (param i32 i32 i32 i32)))
+  (import "fedis" "error" (func $error (param i32 i32)))
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 1024))
+  (data (i32.const 0) "no such key")
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+  (func (export "copy") (param $keys i32) (param i32 i32 i32) (result i64)
+    (local $src i32) (local $src_len i32) (local $dst i32) (local $value i64)
+    (local.set $src_len (i32.load (local.get $keys)))
+    (local.set $src (i32.add (local.get $keys) (i32.const 4)))
+    (local.set $dst (i32.add (i32.add (local.get $src) (local.get $src_len)) (i32.const 4)))
+    (local.set $value (call $get (local.get $src) (local.get $src_len)))
+    (if (i64.lt_s (local.get $value) (i64.const 0))
+      (then
+        (call $error (i32.const 0) (i32.const 11))
+        (return (i64.const -1))))
+    (call $set
+      (local.get $dst)
+      (i32.load (i32.sub (local.get $dst) (i32.const 4)))
+      (i32.wrap_i64 (i64.shr_u (local.get $value) (i64.const 32)))
+      (i32.wrap_i64 (local.get $value)))
+    (local.get $value))
+  (func (export "spin") (param i32 i32 i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const -1)))"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wasm_modules_run_handlers_in_a_sandbox() {
+    let (executor, mut session, path) = make_executor().await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["WASM.LIST"]).await).contains("not enabled")
+    );
+    let settings = WasmSettings {
+        path: Some(path.with_extension("wasm")),
+        fuel: 1_000_000,
+        ..WasmSettings::default()
+    };
+    let executor = executor.with_wasm(WasmRuntime::new(settings.clone()).expect("wasm runtime"));
+
+    let loaded = run(&executor, &mut session, &["WASM.LOAD", "demo", DEMO_WAT]).await;
+    assert_eq!(expect_bulk(loaded), Some(b"demo".to_vec()));
+    assert!(
+        expect_error(run(&executor, &mut session, &["WASM.LOAD", "demo", DEMO_WAT]).await)
+            .contains("already exists")
+    );
+    run(
+        &executor,
+        &mut session,
+        &["WASM.LOAD", "demo", DEMO_WAT, "REPLACE"],
+    )
+    .await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["WASM.LOAD", "bad", "(module)"]).await)
+            .contains("must export")
+    );
+    let RespValue::Array(modules) = run(&executor, &mut session, &["WASM.LIST"]).await else {
+        panic!("expected array response");
+    };
+    assert_eq!(modules.len(), 1);
+    let RespValue::Array(fields) = &modules[0] else {
+        panic!("expected array response");
+    };
+    let RespValue::Array(functions) = &fields[3] else {
+        panic!("expected array response");
+    };
+    assert_eq!(functions.len(), 2);
+
+    run(&executor, &mut session, &["SET", "src", "hello"]).await;
+    let copy = ["WASM.CALL", "demo", "copy", "2", "src", "dst"];
+    let copied = run(&executor, &mut session, &copy).await;
+    assert_eq!(expect_bulk(copied), Some(b"hello".to_vec()));
+    let dst = run(&executor, &mut session, &["GET", "dst"]).await;
+    assert_eq!(expect_bulk(dst), Some(b"hello".to_vec()));
+
+    let missing = ["WASM.CALL", "demo", "copy", "2", "nothing", "dst"];
+    assert_eq!(
+        expect_error(run(&executor, &mut session, &missing).await),
+        "ERR no such key"
+    );
+    // The destination is an argument here, so the script may not write it.
+    let undeclared = ["WASM.CALL", "demo", "copy", "1", "src", "dst"];
+    assert!(
+        expect_error(run(&executor, &mut session, &undeclared).await).contains("undeclared key")
+    );
+    let read_only = ["WASM.CALL_RO", "demo", "copy", "2", "src", "dst"];
+    assert!(expect_error(run(&executor, &mut session, &read_only).await).contains("CALL_RO"));
+    let spin = ["WASM.CALL", "demo", "spin", "0"];
+    assert!(expect_error(run(&executor, &mut session, &spin).await).contains("out of fuel"));
+    let too_many = ["WASM.CALL", "demo", "copy", "3", "src", "dst"];
+    assert!(expect_error(run(&executor, &mut session, &too_many).await).contains("Number of keys"));
+
+    let RespValue::Array(keys) = run(
+        &executor,
+        &mut session,
+        &[
+            "COMMAND",
+            "GETKEYS",
+            "WASM.CALL",
+            "demo",
+            "copy",
+            "2",
+            "a",
+            "b",
+            "arg",
+        ],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    let keys: Vec<_> = keys.into_iter().filter_map(expect_bulk).collect();
+    assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+
+    // Loaded modules are kept on disk for the next start.
+    let restarted = WasmRuntime::new(settings.clone()).expect("wasm runtime");
+    assert_eq!(restarted.load_saved().expect("load saved"), 1);
+
+    expect_simple(run(&executor, &mut session, &["WASM.UNLOAD", "demo"]).await);
+    assert!(
+        expect_error(run(&executor, &mut session, &copy).await).contains("no such WASM module")
+    );
+    let restarted = WasmRuntime::new(settings).expect("wasm runtime");
+    assert_eq!(restarted.load_saved().expect("load saved"), 0);
+
+    let _ = std::fs::remove_dir_all(path.with_extension("wasm"));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn json_documents_are_their_own_type() {
+    let (executor, mut session, path) = make_executor().await;
+    run(
+        &executor,
+        &mut session,
+        &["JSON.SET", "j", "$", r#"{"a":[1,2]}"#],
+    )
+    .await;
+    run(&executor, &mut session, &["SET", "s", "plain"]).await;
+
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["TYPE", "j"]).await),
+        "ReJSON-RL"
+    );
+    for cmd in [
+        &["GET", "j"][..],
+        &["STRLEN", "j"],
+        &["APPEND", "j", "x"],
+        &["INCR", "j"],
+        &["GETRANGE", "j", "0", "1"],
+    ] {
+        assert!(
+            expect_error(run(&executor, &mut session, cmd).await).starts_with("WRONGTYPE"),
+            "{:?}",
+            cmd
+        );
+    }
+    let RespValue::Array(values) = run(&executor, &mut session, &["MGET", "j", "s"]).await else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        values.as_slice(),
+        [RespValue::Bulk(None), RespValue::Bulk(Some(_))]
+    ));
+
+    let payload = expect_bulk(run(&executor, &mut session, &["DUMP", "j"]).await).expect("dump");
+    let restore = vec![b"RESTORE".to_vec(), b"k".to_vec(), b"0".to_vec(), payload];
+    executor.execute(restore, &mut session).await;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["TYPE", "k"]).await),
+        "ReJSON-RL"
+    );
+
+    run(&executor, &mut session, &["SET", "k", "now a string"]).await;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["TYPE", "k"]).await),
+        "string"
+    );
+
+    // The AOF records the kind, so the document comes back as one.
+    let aof = Aof::open(&path, AofFsync::Always, None, AofFormat::Fedis)
+        .await
+        .expect("reopen aof");
+    let store = Store::new(aof, None).await.expect("reopen store");
+    assert_eq!(store.key_type(b"j").await, "ReJSON-RL");
+    assert_eq!(
+        store.json_text(b"j").await,
+        Ok(Some(Bytes::from_static(br#"{"a":[1,2]}"#)))
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn acl_whoami_and_module_list_work() {
+    let (executor, mut session, path) = make_executor().await;
+
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["ACL", "WHOAMI"]).await),
+        Some(b"default".to_vec())
+    );
+    let module = run(&executor, &mut session, &["MODULE", "LIST"]).await;
+    match module {
+        RespValue::Array(v) => assert!(v.is_empty()),
+        _ => panic!("expected array response"),
+    }
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn module_list_advertises_builtin_modules_when_enabled() {
+    let (executor, mut session, path) = make_executor().await;
+    let executor = executor.with_advertised_modules(true);
+
+    let RespValue::Array(modules) = run(&executor, &mut session, &["MODULE", "LIST"]).await else {
+        panic!("expected array response");
+    };
+    let names: Vec<String> = modules
+        .iter()
+        .map(|module| match module {
+            RespValue::Array(fields) => match fields.as_slice() {
+                [
+                    RespValue::Bulk(Some(key)),
+                    RespValue::Bulk(Some(name)),
+                    RespValue::Bulk(Some(ver)),
+                    RespValue::Integer(_),
+                    ..,
+                ] if key.as_ref() == b"name" && ver.as_ref() == b"ver" => {
+                    String::from_utf8_lossy(name).into_owned()
+                }
+                other => panic!("unexpected module {:?}", other),
+            },
+            other => panic!("unexpected module {:?}", other),
+        })
+        .collect();
+    assert_eq!(names, vec!["ReJSON", "search", "timeseries"]);
+
+    let RespValue::Array(commands) = run(
+        &executor,
+        &mut session,
+        &["COMMAND", "LIST", "FILTERBY", "MODULE", "ReJSON"],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    assert!(!commands.is_empty());
+    assert!(commands.iter().all(|name| matches!(
+        name,
+        RespValue::Bulk(Some(name)) if name.starts_with(b"json.")
+    )));
+
+    let _ = std::fs::remove_file(path);
+}
+
+/// Copies `src` to `dst` upper-cased, through the store like a downstream
+/// command would.
+struct UpperCopy;
+
+impl extension::CommandExtension for UpperCopy {
+    fn name(&self) -> &str {
+        "test.uppercopy"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> &'static [&'static str] {
+        &["write", "denyoom"]
+    }
+
+    fn key_range(&self) -> (i64, i64, i64) {
+        (1, 2, 1)
+    }
+
+    fn call<'a>(
+        &'a self,
+        ctx: extension::ExtensionContext<'a>,
+        args: &'a [Vec<u8>],
+    ) -> extension::ExtensionReply<'a> {
+        Box::pin(async move {
+            let value = match ctx.store().get(&args[1]).await {
+                Ok(Some(value)) => value.to_ascii_uppercase(),
+                Ok(None) => return RespValue::Integer(0),
+                Err(e) => return RespValue::Error(e.to_string()),
+            };
+            match ctx
+                .store()
+                .set(
+                    args[2].clone(),
+                    value,
+                    None,
+                    crate::store::SetCondition::None,
+                )
+                .await
+            {
+                Ok(_) => RespValue::Integer(1),
+                Err(e) => RespValue::Error(format!("ERR {}", e)),
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn registered_extensions_dispatch_like_builtin_commands() {
+    let (executor, mut session, path) = make_executor().await;
+    extension::register_command(UpperCopy).expect("register");
+    assert!(extension::register_command(UpperCopy).is_err());
+
+    run(&executor, &mut session, &["SET", "in", "hello"]).await;
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["TEST.UPPERCOPY", "in", "out"]).await),
+        1
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "out"]).await),
+        Some(b"HELLO".to_vec())
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["test.uppercopy", "in"]).await)
+            .contains("wrong number of arguments")
+    );
+
+    let RespValue::Array(info) = run(
+        &executor,
+        &mut session,
+        &["COMMAND", "INFO", "test.uppercopy"],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    assert!(matches!(
+        info.as_slice(),
+        [RespValue::Array(entry)] if matches!(entry[1], RespValue::Integer(3))
+    ));
+    let RespValue::Array(keys) = run(
+        &executor,
+        &mut session,
+        &["COMMAND", "GETKEYS", "test.uppercopy", "in", "out"],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    assert_eq!(keys.len(), 2);
+
+    // Categories come from the flags, so `+@write` grants it and `+@read` does not.
+    run(
+        &executor,
+        &mut session,
+        &[
+            "ACL", "SETUSER", "admin", "on", ">secret", "+@all", "allkeys",
+        ],
+    )
+    .await;
+    run(&executor, &mut session, &["AUTH", "admin", "secret"]).await;
+    for (user, rule, allowed) in [("writer", "+@write", true), ("reader", "+@read", false)] {
+        run(
+            &executor,
+            &mut session,
+            &["ACL", "SETUSER", user, "on", ">pw", rule, "allkeys"],
+        )
+        .await;
+        let mut client = SessionAuth::default();
+        run(&executor, &mut client, &["AUTH", user, "pw"]).await;
+        let reply = run(&executor, &mut client, &["TEST.UPPERCOPY", "in", "out"]).await;
+        match reply {
+            RespValue::Integer(1) => assert!(allowed),
+            RespValue::Error(e) => assert!(!allowed && e.starts_with("NOPERM"), "{e}"),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn cluster_probes_report_a_standalone_node() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let info = expect_bulk(run(&executor, &mut session, &["CLUSTER", "INFO"]).await)
+        .expect("cluster info");
+    assert!(
+        String::from_utf8(info)
+            .unwrap()
+            .starts_with("cluster_enabled:0\r\n")
+    );
+    let id =
+        expect_bulk(run(&executor, &mut session, &["CLUSTER", "MYID"]).await).expect("node id");
+    assert_eq!(id.len(), 40);
+    for sub in ["SLOTS", "SHARDS"] {
+        match run(&executor, &mut session, &["CLUSTER", sub]).await {
+            RespValue::Array(v) => assert!(v.is_empty()),
+            _ => panic!("expected array response"),
+        }
+    }
+    let info = expect_bulk(run(&executor, &mut session, &["INFO", "cluster"]).await).expect("info");
+    assert_eq!(info, b"# Cluster\ncluster_enabled:0");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn cluster_mode_redirects_keys_owned_by_other_nodes() {
+    let (executor, mut session, path) = make_executor().await;
+    let nodes =
+        crate::cluster::parse_cluster_nodes("127.0.0.1:7000 0-8191;127.0.0.1:7001 8192-16383")
+            .expect("nodes");
+    let cluster = Cluster::new(("127.0.0.1".to_string(), 7000), &nodes).expect("cluster");
+    let executor = executor.with_cluster(cluster);
+    let other = crate::cluster::node_id("127.0.0.1", 7001);
+
+    // "bar" hashes to slot 5061, "foo" to 12182.
+    assert!(matches!(
+        run(&executor, &mut session, &["SET", "bar", "1"]).await,
+        RespValue::Simple(reply) if reply == "OK"
+    ));
+    assert_eq!(
+        expect_error(run(&executor, &mut session, &["GET", "foo"]).await),
+        "MOVED 12182 127.0.0.1:7001"
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["MGET", "foo", "bar"]).await)
+            .starts_with("CROSSSLOT")
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["CLUSTER", "KEYSLOT", "{bar}.x"]).await),
+        5061
+    );
+
+    run(
+        &executor,
+        &mut session,
+        &["CLUSTER", "SETSLOT", "5061", "MIGRATING", &other],
+    )
+    .await;
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "bar"]).await),
+        Some(b"1".to_vec())
+    );
+    assert_eq!(
+        expect_error(run(&executor, &mut session, &["GET", "{bar}.gone"]).await),
+        "ASK 5061 127.0.0.1:7001"
+    );
+
+    run(
+        &executor,
+        &mut session,
+        &["CLUSTER", "SETSLOT", "12182", "IMPORTING", &other],
+    )
+    .await;
+    run(&executor, &mut session, &["ASKING"]).await;
+    assert!(matches!(
+        run(&executor, &mut session, &["SET", "foo", "2"]).await,
+        RespValue::Simple(reply) if reply == "OK"
+    ));
+    assert!(expect_error(run(&executor, &mut session, &["GET", "foo"]).await).starts_with("MOVED"));
+
+    let nodes =
+        expect_bulk(run(&executor, &mut session, &["CLUSTER", "NODES"]).await).expect("nodes");
+    let nodes = String::from_utf8(nodes).unwrap();
+    assert!(nodes.contains("127.0.0.1:7000@17000 myself,master - 0 0 0 connected 0-8191 [5061->-"));
+    let info = expect_bulk(run(&executor, &mut session, &["INFO", "cluster"]).await).expect("info");
+    assert_eq!(info, b"# Cluster\ncluster_enabled:1");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn dump_and_restore_round_trip_values() {
+    let (executor, mut session, path) = make_executor().await;
+
+    run(&executor, &mut session, &["SET", "a", "hello"]).await;
+    let payload = expect_bulk(run(&executor, &mut session, &["DUMP", "a"]).await).expect("dump");
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["DUMP", "missing"]).await),
+        None
+    );
+    let restore = |key: &str, ttl: &str, extra: &[&str]| {
+        let mut args = vec![b"RESTORE".to_vec(), key.into(), ttl.into(), payload.clone()];
+        args.extend(extra.iter().map(|arg| arg.as_bytes().to_vec()));
+        args
+    };
+    assert!(matches!(
+        executor.execute(restore("b", "60000", &[]), &mut session).await.0,
+        RespValue::Simple(reply) if reply == "OK"
+    ));
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "b"]).await),
+        Some(b"hello".to_vec())
+    );
+    assert!(expect_int(run(&executor, &mut session, &["PTTL", "b"]).await) > 59_000);
+    assert_eq!(
+        expect_error(
+            executor
+                .execute(restore("b", "0", &[]), &mut session)
+                .await
+                .0
+        ),
+        "BUSYKEY Target key name already exists."
+    );
+    assert!(matches!(
+        executor.execute(restore("b", "0", &["REPLACE"]), &mut session).await.0,
+        RespValue::Simple(reply) if reply == "OK"
+    ));
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["PTTL", "b"]).await),
+        -1
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["RESTORE", "c", "0", "junk"]).await)
+            .contains("checksum")
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn memory_stats_doctor_and_purge_describe_the_dataset() {
+    let (executor, mut session, path) = make_executor().await;
+
+    assert!(
+        expect_bulk(run(&executor, &mut session, &["MEMORY", "DOCTOR"]).await)
+            .is_some_and(|report| report.starts_with(b"This instance is empty"))
+    );
+    run(&executor, &mut session, &["SET", "a", "hello"]).await;
+    run(&executor, &mut session, &["SET", "b", "world!", "EX", "60"]).await;
+
+    let RespValue::Array(stats) = run(&executor, &mut session, &["MEMORY", "STATS"]).await else {
+        panic!("expected MEMORY STATS array");
+    };
+    let stat = |name: &str| {
+        let idx = stats
+            .iter()
+            .position(
+                |field| matches!(field, RespValue::Bulk(Some(v)) if &v[..] == name.as_bytes()),
+            )
+            .unwrap_or_else(|| panic!("no {name} in MEMORY STATS"));
+        stats[idx + 1].clone()
+    };
+    assert_eq!(expect_int(stat("keys.count")), 2);
+    // Two keys and two values, each rounded up to a 16-byte allocation.
+    assert_eq!(expect_int(stat("dataset.bytes")), 64);
+    let total = expect_int(stat("total.allocated"));
+    assert!(total > 64);
+    assert!(expect_int(stat("peak.allocated")) >= total);
+    let RespValue::Array(db) = stat("db.0") else {
+        panic!("expected db.0 breakdown");
+    };
+    assert!(expect_int(db[3].clone()) > 0, "b has an expiry");
+
+    let info = expect_bulk(run(&executor, &mut session, &["INFO", "memory"]).await).expect("info");
+    let info = String::from_utf8(info).unwrap();
+    assert!(info.contains(&format!("used_memory:{total}\n")));
+    assert!(info.contains("used_memory_dataset:64\n"));
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["MEMORY", "PURGE"]).await),
+        "OK"
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn slotmigrate_moves_keys_to_another_node() {
+    let (target, _, target_path) = make_executor().await;
+    let target = Arc::new(target);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let port = listener.local_addr().expect("addr").port();
+    tokio::spawn({
+        let target = target.clone();
+        async move {
+            let (socket, _) = listener.accept().await.expect("accept");
+            let (read, mut write) = socket.into_split();
+            let mut read = tokio::io::BufReader::new(read);
+            let mut session = SessionAuth::default();
+            loop {
+                let Ok(Some(frame)) = crate::protocol::read_frame(&mut read).await else {
+                    return;
+                };
+                let args = crate::protocol::frame_to_args(frame).expect("args");
+                let (resp, _) = target.execute(args, &mut session).await;
+                tokio::io::AsyncWriteExt::write_all(&mut write, &crate::protocol::encode(resp))
+                    .await
+                    .expect("reply");
+            }
+        }
+    });
+
+    let (executor, mut session, path) = make_executor().await;
+    run(&executor, &mut session, &["SET", "{a}1", "one"]).await;
+    run(
+        &executor,
+        &mut session,
+        &["SET", "{a}2", "two", "EX", "100"],
+    )
+    .await;
+    run(&executor, &mut session, &["SET", "b", "stays"]).await;
+    let slot = crate::cluster::key_slot(b"a").to_string();
+    let port = port.to_string();
+    assert!(matches!(
+        run(&executor, &mut session, &["SLOTMIGRATE", "127.0.0.1", &port, &slot, "BATCH", "1"]).await,
+        RespValue::Simple(reply) if reply == "OK"
+    ));
+
+    let status = loop {
+        let status = run(&executor, &mut session, &["SLOTMIGRATE", "STATUS"]).await;
+        let RespValue::Array(fields) = status else {
+            panic!("expected array response");
+        };
+        if !matches!(&fields[1], RespValue::Bulk(Some(state)) if &state[..] == b"running") {
+            break fields;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    };
+    assert!(matches!(&status[1], RespValue::Bulk(Some(state)) if &state[..] == b"done"));
+    assert!(matches!(status[9], RespValue::Integer(2)));
+
+    let mut target_session = SessionAuth::default();
+    assert_eq!(
+        expect_bulk(run(&target, &mut target_session, &["GET", "{a}1"]).await),
+        Some(b"one".to_vec())
+    );
+    assert!(expect_int(run(&target, &mut target_session, &["TTL", "{a}2"]).await) > 90);
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["EXISTS", "{a}1", "{a}2", "b"]).await),
+        1
+    );
+
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(target_path);
+}
+
+#[tokio::test]
+async fn acl_save_and_load_round_trip_through_acl_file() {
+    let acl_path = temp_path("acl");
+    let mut users: HashMap<String, User> = HashMap::new();
+    users.insert(
+        "default".to_string(),
+        User::new("secret".to_string(), true, Permissions::All),
+    );
+    let (executor, mut session, path) = TestExecutor::default()
+        .auth(Auth::new(
+            users,
+            "default".to_string(),
+            Some(acl_path.clone()),
+        ))
+        .build()
+        .await;
+    let _ = run(&executor, &mut session, &["AUTH", "secret"]).await;
+
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["ACL", "SETUSER", "reader", "on", ">pw", "+get"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["ACL", "SAVE"]).await),
+        "OK"
+    );
+    let saved = std::fs::read_to_string(&acl_path).expect("read acl file");
+    assert!(saved.contains(
+        "user reader on #30c952fab122c3f9759f02a6d95c3758b246b4fee239957b2d4fee46e26170c4 -@all +get"
+    ));
+    assert!(!saved.contains(">pw"));
+
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["ACL", "DELUSER", "reader"]).await),
+        1
+    );
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["ACL", "LOAD"]).await),
+        "OK"
+    );
+    let mut reader = SessionAuth::default();
+    assert_eq!(
+        expect_simple(run(&executor, &mut reader, &["AUTH", "reader", "pw"]).await),
+        "OK"
+    );
+    let err = expect_error(run(&executor, &mut reader, &["SET", "a", "1"]).await);
+    assert!(err.starts_with("NOPERM"));
+
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(acl_path);
+}
+
+#[tokio::test]
+async fn nopass_users_from_the_acl_file_accept_any_password() {
+    let acl_path = temp_path("acl");
+    std::fs::write(
+        &acl_path,
+        "user default on >secret +@all\nuser guest on nopass +get\nuser fresh on +get\n",
+    )
+    .expect("write acl file");
+    let auth = Auth::new(
+        HashMap::new(),
+        "default".to_string(),
+        Some(acl_path.clone()),
+    );
+    auth.load_acl_file().expect("load acl file");
+    let (executor, mut session, path) = TestExecutor::default().auth(auth).build().await;
+
+    for password in ["", "anything"] {
+        assert_eq!(
+            expect_simple(run(&executor, &mut session, &["AUTH", "guest", password]).await),
+            "OK"
+        );
+    }
+    // Without `nopass` a user with no password cannot log in at all.
+    let err = expect_error(run(&executor, &mut session, &["AUTH", "fresh", ""]).await);
+    assert!(err.starts_with("WRONGPASS"));
+
+    let _ = run(&executor, &mut session, &["AUTH", "secret"]).await;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["ACL", "SAVE"]).await),
+        "OK"
+    );
+    let saved = std::fs::read_to_string(&acl_path).expect("read acl file");
+    assert!(
+        saved.contains("user guest on nopass -@all +get"),
+        "{}",
+        saved
+    );
+    assert!(saved.contains("user fresh on -@all +get"), "{}", saved);
+
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(acl_path);
+}
+
+#[tokio::test]
+async fn acl_getuser_exposes_only_password_hashes() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let _ = run(
+        &executor,
+        &mut session,
+        &["ACL", "SETUSER", "alice", "on", ">hunter2", "+@all"],
+    )
+    .await;
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["AUTH", "alice", "hunter2"]).await),
+        "OK"
+    );
+    let RespValue::Array(fields) = run(&executor, &mut session, &["ACL", "GETUSER", "alice"]).await
+    else {
+        panic!("expected array response");
+    };
+    let RespValue::Array(passwords) = &fields[3] else {
+        panic!("expected passwords array");
+    };
+    assert_eq!(passwords.len(), 1);
+    let RespValue::Bulk(Some(hash)) = &passwords[0] else {
+        panic!("expected bulk hash");
+    };
+    assert_eq!(hash.len(), 64);
+    assert!(!hash.windows(7).any(|w| w == b"hunter2"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn acl_log_records_auth_and_permission_failures() {
+    let (executor, mut session, path) = make_executor().await;
+
+    let _ = run(
+        &executor,
+        &mut session,
+        &["ACL", "SETUSER", "reader", "on", ">pw", "+get", "+acl"],
+    )
+    .await;
+    let mut attacker = SessionAuth::default();
+    let _ = run(&executor, &mut attacker, &["AUTH", "reader", "wrong"]).await;
+    let _ = run(&executor, &mut attacker, &["AUTH", "reader", "wrong"]).await;
+    let _ = run(&executor, &mut session, &["AUTH", "reader", "pw"]).await;
+    let _ = run(&executor, &mut session, &["SET", "a", "1"]).await;
+
+    let RespValue::Array(entries) = run(&executor, &mut session, &["ACL", "LOG"]).await else {
+        panic!("expected array response");
+    };
+    assert_eq!(entries.len(), 2);
+    let RespValue::Array(latest) = &entries[0] else {
+        panic!("expected entry array");
+    };
+    assert!(matches!(&latest[3], RespValue::Bulk(Some(v)) if &v[..] == b"command"));
+    assert!(matches!(&latest[7], RespValue::Bulk(Some(v)) if &v[..] == b"set"));
+    let RespValue::Array(auth_entry) = &entries[1] else {
+        panic!("expected entry array");
+    };
+    assert_eq!(expect_int(auth_entry[1].clone()), 2);
+    assert!(matches!(&auth_entry[3], RespValue::Bulk(Some(v)) if &v[..] == b"auth"));
+
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["ACL", "LOG", "RESET"]).await),
+        "OK"
+    );
+    let genpass = expect_bulk(run(&executor, &mut session, &["ACL", "GENPASS", "32"]).await)
+        .expect("generated password");
+    assert_eq!(genpass.len(), 8);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn per_user_rate_limit_throttles_excess_commands() {
+    let (executor, mut session, path) = TestExecutor::default()
+        .rate_limit(
+            "default",
+            RateLimit {
+                commands_per_sec: Some(2),
+                bytes_per_sec: None,
+            },
+        )
+        .build()
+        .await;
+
+    let _ = expect_bulk(run(&executor, &mut session, &["GET", "a"]).await);
+    let _ = expect_bulk(run(&executor, &mut session, &["GET", "a"]).await);
+    let err = expect_error(run(&executor, &mut session, &["GET", "a"]).await);
+    assert!(err.starts_with("THROTTLED"));
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["PING"]).await),
+        "PONG"
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn audit_log_records_auth_and_acl_changes_without_secrets() {
+    let audit_path = temp_path("log");
+    let (executor, mut session, path) = TestExecutor::default()
+        .audit_log(audit_path.clone())
+        .build()
+        .await;
+
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["CLIENT", "KILL", "ID", "999"]).await),
+        0
+    );
+    let _ = run(
+        &executor,
+        &mut session,
+        &["ACL", "SETUSER", "ops", "on", ">topsecret", "+@all"],
+    )
+    .await;
+    let _ = run(&executor, &mut session, &["AUTH", "ops", "wrong"]).await;
+    let _ = run(&executor, &mut session, &["GET", "a"]).await;
+    executor.audit_log().drain().await;
+
+    let contents = std::fs::read_to_string(&audit_path).expect("read audit log");
+    let lines: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("json line"))
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["event"], "client_kill");
+    assert_eq!(lines[0]["detail"], "ID 999");
+    assert_eq!(lines[1]["event"], "acl");
+    assert_eq!(lines[1]["detail"], "setuser ops");
+    assert_eq!(lines[2]["event"], "auth");
+    assert_eq!(lines[2]["outcome"], "WRONGPASS");
+    assert!(!contents.contains("topsecret"));
+    assert!(!contents.contains("wrong"));
+
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(audit_path);
+}
+
+#[tokio::test]
+async fn repeated_auth_failures_lock_out_client_and_user() {
+    let auth =
+        Auth::new(HashMap::new(), "default".to_string(), None).with_lockout(Some(LockoutPolicy {
+            max_failures: 2,
+            base_delay: std::time::Duration::from_secs(60),
+            max_delay: std::time::Duration::from_secs(600),
+        }));
+    let (executor, mut session, path) = TestExecutor::default().auth(auth).build().await;
+    let _ = run(
+        &executor,
+        &mut session,
+        &["ACL", "SETUSER", "ops", "on", ">pw", "+@all"],
+    )
+    .await;
+    let _ = run(&executor, &mut session, &["AUTH", "ops", "pw"]).await;
+
+    let mut attacker = SessionAuth {
+        peer_addr: Some("10.0.0.9:5000".to_string()),
+        ..SessionAuth::default()
+    };
+    for _ in 0..3 {
+        let err = expect_error(run(&executor, &mut attacker, &["AUTH", "ops", "guess"]).await);
+        assert!(err.starts_with("WRONGPASS"));
+    }
+    let err = expect_error(run(&executor, &mut attacker, &["AUTH", "ops", "pw"]).await);
+    assert!(err.contains("too many failed authentication attempts"));
+
+    // The user is locked regardless of which client asks.
+    let mut other = SessionAuth {
+        peer_addr: Some("10.0.0.10:5000".to_string()),
+        ..SessionAuth::default()
+    };
+    let err = expect_error(run(&executor, &mut other, &["AUTH", "ops", "pw"]).await);
+    assert!(err.contains("too many failed authentication attempts"));
+
+    let RespValue::Array(entries) = run(&executor, &mut session, &["ACL", "LOG", "1"]).await else {
+        panic!("expected array response");
+    };
+    let RespValue::Array(latest) = &entries[0] else {
+        panic!("expected entry array");
+    };
+    assert!(matches!(&latest[7], RespValue::Bulk(Some(v)) if &v[..] == b"AUTH-LOCKOUT"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn read_only_mode_rejects_writes_server_wide_and_per_user() {
+    let (executor, mut session, path) = make_executor().await;
+
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["CONFIG", "SET", "read-only", "yes"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    let err = expect_error(run(&executor, &mut session, &["SET", "a", "1"]).await);
+    assert!(err.starts_with("READONLY"));
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "a"]).await),
+        None
+    );
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["CONFIG", "SET", "read-only", "no"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["SET", "a", "1"]).await),
+        "OK"
+    );
+
+    let _ = run(
+        &executor,
+        &mut session,
+        &[
+            "ACL", "SETUSER", "analyst", "on", ">pw", "readonly", "+@all",
+        ],
+    )
+    .await;
+    let mut analyst = SessionAuth::default();
+    let _ = run(&executor, &mut analyst, &["AUTH", "analyst", "pw"]).await;
+    let err = expect_error(run(&executor, &mut analyst, &["DEL", "a"]).await);
+    assert!(err.starts_with("READONLY"));
+    assert_eq!(
+        expect_bulk(run(&executor, &mut analyst, &["GET", "a"]).await),
+        Some(b"1".to_vec())
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn acl_selectors_grant_separate_read_and_write_key_patterns() {
+    let (executor, mut session, path) = make_executor().await;
+
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &[
+                    "ACL", "SETUSER", "svc", "on", ">pw", "+get", "+acl", "%R~*", "(+set",
+                    "~svc:*)"
+                ],
+            )
+            .await
+        ),
+        "OK"
+    );
+    let mut svc = SessionAuth::default();
+    let _ = run(&executor, &mut svc, &["AUTH", "svc", "pw"]).await;
+
+    assert_eq!(
+        expect_simple(run(&executor, &mut svc, &["SET", "svc:a", "1"]).await),
+        "OK"
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut svc, &["GET", "svc:a"]).await),
+        Some(b"1".to_vec())
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut svc, &["GET", "other"]).await),
+        None
+    );
+    assert_eq!(
+        expect_error(run(&executor, &mut svc, &["SET", "other", "1"]).await),
+        "NOPERM No permissions to access a key"
+    );
+    assert!(
+        expect_error(run(&executor, &mut svc, &["DEL", "svc:a"]).await)
+            .contains("no permissions to run the 'del' command")
+    );
+
+    let RespValue::Array(users) = run(&executor, &mut svc, &["ACL", "LIST"]).await else {
+        panic!("expected array response");
+    };
+    let line = users
+        .iter()
+        .find_map(|u| match u {
+            RespValue::Bulk(Some(v)) if v.starts_with(b"user svc ") => {
+                Some(String::from_utf8_lossy(v).to_string())
+            }
+            _ => None,
+        })
+        .expect("svc user line");
+    assert!(line.ends_with("-@all +acl +get %R~* (~svc:* -@all +set)"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn bearer_tokens_authenticate_with_claimed_permissions_until_expiry() {
+    let verifier =
+        JwtVerifier::new(Some(b"edge-secret".to_vec()), None, None, None).expect("jwt verifier");
+    let (executor, mut session, path) = TestExecutor::default()
+        .auth(
+            Auth::new(HashMap::new(), "default".to_string(), None)
+                .with_token_verifier(Some(verifier)),
+        )
+        .build()
+        .await;
+
+    assert!(
+        expect_error(run(&executor, &mut session, &["GET", "edge:a"]).await).starts_with("NOAUTH")
+    );
+
+    let token = sign_hs256(
+        b"edge-secret",
+        &serde_json::json!({"sub": "edge", "acl": ["+get", "~edge:*"]}),
+    );
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["AUTH", &token]).await),
+        "OK"
+    );
+    assert_eq!(
+        expect_bulk(run(&executor, &mut session, &["GET", "edge:a"]).await),
+        None
+    );
+    assert_eq!(
+        expect_error(run(&executor, &mut session, &["GET", "other"]).await),
+        "NOPERM No permissions to access a key"
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["SET", "edge:a", "1"]).await)
+            .starts_with("NOPERM")
+    );
+
+    if let Some(grant) = session.token.as_mut() {
+        grant.expires_at_ms = Some(1);
+    }
+    assert!(
+        expect_error(run(&executor, &mut session, &["GET", "edge:a"]).await).starts_with("NOAUTH")
+    );
+
+    let unknown_user = sign_hs256(b"edge-secret", &serde_json::json!({"sub": "nobody"}));
+    assert!(
+        expect_error(run(&executor, &mut session, &["AUTH", &unknown_user]).await)
+            .starts_with("WRONGPASS")
+    );
+    let expired = sign_hs256(
+        b"edge-secret",
+        &serde_json::json!({"sub": "edge", "exp": 1, "acl": "+@all"}),
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["AUTH", &expired]).await)
+            .starts_with("WRONGPASS")
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn acl_changes_revalidate_live_sessions() {
+    let auth =
+        Auth::new(HashMap::new(), "default".to_string(), None).with_kill_deleted_sessions(true);
+    let (executor, mut admin, path) = TestExecutor::default().auth(auth).build().await;
+    let _ = run(
+        &executor,
+        &mut admin,
+        &["ACL", "SETUSER", "admin", "on", ">root", "+@all"],
+    )
+    .await;
+    let _ = run(&executor, &mut admin, &["AUTH", "admin", "root"]).await;
+    let _ = run(
+        &executor,
+        &mut admin,
+        &["ACL", "SETUSER", "ops", "on", ">pw", "+get", "+set"],
+    )
+    .await;
+    let mut ops = SessionAuth::default();
+    let _ = run(&executor, &mut ops, &["AUTH", "ops", "pw"]).await;
+    assert_eq!(
+        expect_bulk(run(&executor, &mut ops, &["GET", "k"]).await),
+        None
+    );
+
+    let _ = run(&executor, &mut admin, &["ACL", "SETUSER", "ops", "-get"]).await;
+    assert!(expect_error(run(&executor, &mut ops, &["GET", "k"]).await).starts_with("NOPERM"));
+
+    let _ = run(&executor, &mut admin, &["ACL", "SETUSER", "ops", "off"]).await;
+    assert!(expect_error(run(&executor, &mut ops, &["SET", "k", "v"]).await).starts_with("NOAUTH"));
+    assert!(ops.user.is_none());
+
+    let _ = run(&executor, &mut admin, &["ACL", "SETUSER", "ops", "on"]).await;
+    let _ = run(&executor, &mut ops, &["AUTH", "ops", "pw"]).await;
+    assert_eq!(
+        expect_simple(run(&executor, &mut ops, &["SET", "k", "v"]).await),
+        "OK"
+    );
+
+    let _ = run(&executor, &mut admin, &["ACL", "DELUSER", "ops"]).await;
+    let (resp, action) = executor
+        .execute(vec![b"GET".to_vec(), b"k".to_vec()], &mut ops)
+        .await;
+    assert!(expect_error(resp).contains("deleted"));
+    assert!(matches!(action, SessionAction::Close));
+    assert_eq!(
+        expect_bulk(run(&executor, &mut admin, &["GET", "k"]).await),
+        Some(b"v".to_vec())
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn slowlog_captures_commands_over_the_runtime_threshold() {
+    let (executor, mut session, path) = make_executor().await;
+    session.peer_addr = Some("10.0.0.1:4000".to_string());
+    session.client_name = Some("loader".to_string());
+
+    let _ = run(&executor, &mut session, &["SET", "a", "1"]).await;
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["SLOWLOG", "LEN"]).await),
+        0
+    );
+
+    assert_eq!(
+        expect_simple(
+            run(
+                &executor,
+                &mut session,
+                &["CONFIG", "SET", "slowlog-log-slower-than", "0"]
+            )
+            .await
+        ),
+        "OK"
+    );
+    let _ = run(&executor, &mut session, &["GET", "a"]).await;
+    let _ = run(&executor, &mut session, &["ECHO", "hi"]).await;
+    let RespValue::Array(entries) = run(&executor, &mut session, &["SLOWLOG", "GET", "1"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert_eq!(entries.len(), 1);
+    let RespValue::Array(entry) = &entries[0] else {
+        panic!("expected entry array");
+    };
+    assert!(matches!(&entry[3], RespValue::Array(args) if args.len() == 2));
+    assert!(matches!(&entry[4], RespValue::Bulk(Some(v)) if &v[..] == b"10.0.0.1:4000"));
+    assert!(matches!(&entry[5], RespValue::Bulk(Some(v)) if &v[..] == b"loader"));
+    assert!(matches!(&entry[6], RespValue::Bulk(Some(v)) if &v[..] == b"default"));
+
+    let matching = |reply: RespValue| match reply {
+        RespValue::Array(entries) => entries.len(),
+        other => panic!("expected array response, got {:?}", other),
+    };
+    let filtered = &[
+        "SLOWLOG", "GET", "-1", "USER", "default", "CLIENT", "loader",
+    ];
+    // CONFIG SET, GET, ECHO and the first SLOWLOG GET.
+    assert_eq!(matching(run(&executor, &mut session, filtered).await), 4);
+    let other_user = &["SLOWLOG", "GET", "USER", "someone"];
+    assert_eq!(matching(run(&executor, &mut session, other_user).await), 0);
+    let by_addr = &["SLOWLOG", "GET", "CLIENT", "10.0.0.1:4000"];
+    assert!(matching(run(&executor, &mut session, by_addr).await) > 0);
+    let err = expect_error(run(&executor, &mut session, &["SLOWLOG", "GET", "1", "USER"]).await);
+    assert_eq!(err, "ERR syntax error");
+
+    let RespValue::Array(config) = run(
+        &executor,
+        &mut session,
+        &["CONFIG", "GET", "slowlog-log-slower-than"],
+    )
+    .await
+    else {
+        panic!("expected array response");
+    };
+    assert_eq!(expect_bulk(config[1].clone()), Some(b"0".to_vec()));
+
+    let err = expect_error(run(&executor, &mut session, &["SLOWLOG", "GET", "-2"]).await);
+    assert_eq!(err, "ERR count should be greater than or equal to -1");
+    assert_eq!(
+        expect_simple(run(&executor, &mut session, &["SLOWLOG", "RESET"]).await),
+        "OK"
+    );
+    // The RESET itself is slower than the zero threshold and logged.
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["SLOWLOG", "LEN"]).await),
+        1
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn latency_percentiles_and_histograms_cover_recorded_commands() {
+    let (executor, mut session, path) = make_executor().await;
+    for usec in [3, 10, 10, 700] {
+        executor.record_command_stats("GET", usec);
+    }
+    executor.record_command_stats("SET", 5);
+
+    let info = expect_bulk(run(&executor, &mut session, &["INFO", "latencystats"]).await)
+        .expect("info payload");
+    let info = String::from_utf8(info).unwrap();
+    assert!(info.starts_with("# Latencystats"));
+    assert!(info.contains("latency_percentiles_usec_get:p50=10.000,p99=700.000,p99.9=700.000"));
+    assert!(info.contains("latency_percentiles_usec_set:p50=5.000"));
+
+    let RespValue::Array(reply) =
+        run(&executor, &mut session, &["LATENCY", "HISTOGRAM", "get"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert_eq!(reply.len(), 2, "only the requested command");
+    assert_eq!(expect_bulk(reply[0].clone()), Some(b"get".to_vec()));
+    let RespValue::Array(fields) = &reply[1] else {
+        panic!("expected histogram fields");
+    };
+    assert!(matches!(fields[1], RespValue::Integer(4)));
+    let RespValue::Array(buckets) = &fields[3] else {
+        panic!("expected bucket array");
+    };
+    let pairs: Vec<i64> = buckets
+        .iter()
+        .map(|v| match v {
+            RespValue::Integer(n) => *n,
+            _ => panic!("expected integer"),
+        })
+        .collect();
+    assert_eq!(pairs, vec![4, 1, 16, 3, 1024, 4]);
+
+    let RespValue::Array(all) = run(&executor, &mut session, &["LATENCY", "HISTOGRAM"]).await
+    else {
+        panic!("expected array response");
+    };
+    assert_eq!(all.len(), 4, "every recorded command");
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn errorstats_count_error_replies_by_prefix() {
+    let (executor, mut session, path) = make_executor().await;
+    executor.record_error_reply(
+        "GET",
+        "WRONGTYPE Operation against a key holding the wrong kind of value",
+    );
+    executor.record_error_reply(
+        "LPUSH",
+        "WRONGTYPE Operation against a key holding the wrong kind of value",
+    );
+    executor.record_error_reply("SET", "ERR syntax error");
+
+    let info = expect_bulk(run(&executor, &mut session, &["INFO", "errorstats"]).await)
+        .expect("info payload");
+    assert_eq!(
+        String::from_utf8(info).unwrap(),
+        "# Errorstats\nerrorstat_ERR:count=1\nerrorstat_WRONGTYPE:count=2"
+    );
+    let info =
+        expect_bulk(run(&executor, &mut session, &["INFO", "stats"]).await).expect("info payload");
+    assert!(
+        String::from_utf8(info)
+            .unwrap()
+            .contains("total_error_replies:3")
+    );
+
+    for n in 0..200 {
+        executor.record_error_reply("GET", &format!("CUSTOM{} failure", n));
+    }
+    let info = expect_bulk(run(&executor, &mut session, &["INFO", "errorstats"]).await)
+        .expect("info payload");
+    let info = String::from_utf8(info).unwrap();
+    assert_eq!(
+        info.lines().count() - 1,
+        128,
+        "distinct prefixes are capped"
+    );
+    assert!(info.contains("errorstat_WRONGTYPE:count=2"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn export_checks_its_arguments() {
+    let (executor, mut session, path) = make_executor().await;
+
+    for name in ["../keys.ndjson", "/tmp/keys.ndjson", ".hidden", ""] {
+        assert_eq!(
+            expect_error(run(&executor, &mut session, &["EXPORT", name]).await),
+            "ERR the export file must be a plain file name"
+        );
+    }
+    assert_eq!(
+        expect_error(
+            run(
+                &executor,
+                &mut session,
+                &["EXPORT", "keys", "FORMAT", "XML"]
+            )
+            .await
+        ),
+        "ERR syntax error"
+    );
+    assert_eq!(
+        expect_error(run(&executor, &mut session, &["EXPORT", "keys", "MATCH"]).await),
+        "ERR syntax error"
+    );
+    assert_eq!(
+        expect_error(
+            run(
+                &executor,
+                &mut session,
+                &["EXPORT", "keys.csv", "FORMAT", "csv"]
+            )
+            .await
+        ),
+        "ERR exports are not configured"
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn follow_needs_non_redis_mode() {
+    let (executor, mut session, path) = make_executor().await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["FOLLOW", "*"]).await)
+            .contains("FEDIS_NON_REDIS_MODE")
+    );
+    let listed = |reply: RespValue| {
+        let RespValue::Array(items) = reply else {
+            panic!("expected array response");
+        };
+        items
+            .into_iter()
+            .filter_map(expect_bulk)
+            .any(|name| name == b"follow")
+    };
+    assert!(!listed(
+        run(&executor, &mut session, &["COMMAND", "LIST"]).await
+    ));
+    assert!(matches!(
+        run(&executor, &mut session, &["COMMAND", "INFO", "follow"]).await,
+        RespValue::Array(info) if matches!(info[0], RespValue::Bulk(None))
+    ));
+
+    let executor = executor.with_non_redis_mode(true);
+    assert!(listed(
+        run(&executor, &mut session, &["COMMAND", "LIST"]).await
+    ));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn getmeta_reports_value_ttl_and_type_in_non_redis_mode() {
+    let (executor, mut session, path) = make_executor().await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["GETMETA", "a"]).await)
+            .contains("FEDIS_NON_REDIS_MODE")
+    );
+    let executor = executor.with_non_redis_mode(true);
+    run(&executor, &mut session, &["SET", "a", "1", "EX", "100"]).await;
+    run(
+        &executor,
+        &mut session,
+        &["JSON.SET", "doc", "$", r#"{"n":1}"#],
+    )
+    .await;
+
+    let RespValue::Map(entries) = run(
+        &executor,
+        &mut session,
+        &["GETMETA", "a", "doc", "missing", "a"],
+    )
+    .await
+    else {
+        panic!("expected a map reply");
+    };
+    let keys: Vec<_> = entries
+        .iter()
+        .map(|(key, _)| match key {
+            RespValue::Bulk(Some(key)) => key.clone(),
+            other => panic!("unexpected key {:?}", other),
+        })
+        .collect();
+    assert_eq!(keys, ["a", "doc", "missing"]);
+    let text = |value: &RespValue| match value {
+        RespValue::Bulk(Some(v)) => String::from_utf8_lossy(v).to_string(),
+        RespValue::Integer(n) => n.to_string(),
+        other => panic!("unexpected field value {:?}", other),
+    };
+    let fields = |meta: &RespValue| -> Vec<(String, String)> {
+        let RespValue::Map(fields) = meta else {
+            panic!("expected the fields of a key, got {:?}", meta);
+        };
+        fields
+            .iter()
+            .map(|(name, value)| (text(name), text(value)))
+            .collect()
+    };
+    let a = fields(&entries[0].1);
+    assert_eq!(a[0], ("value".to_string(), "1".to_string()));
+    let ttl: i64 = a[1].1.parse().expect("ttl");
+    assert!(ttl > 99_000 && ttl <= 100_000, "{}", ttl);
+    assert_eq!(a[2], ("type".to_string(), "string".to_string()));
+    assert_eq!(a[3].0, "version");
+    let doc = fields(&entries[1].1);
+    assert_eq!(doc[0].1, r#"{"n":1}"#);
+    assert_eq!(doc[1].1, "-1");
+    assert_eq!(doc[2].1, "ReJSON-RL");
+    assert!(matches!(entries[2].1, RespValue::Bulk(None)));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn object_version_rises_with_every_write() {
+    let (executor, mut session, path) = make_executor().await;
+    run(&executor, &mut session, &["SET", "a", "1"]).await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await)
+            .contains("FEDIS_NON_REDIS_MODE")
+    );
+    let executor = executor.with_non_redis_mode(true);
+    let version = |reply| match reply {
+        RespValue::Integer(version) => version,
+        other => panic!("expected a version, got {:?}", other),
+    };
+    let first = version(run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await);
+    assert_eq!(
+        version(run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await),
+        first
+    );
+    run(&executor, &mut session, &["GET", "a"]).await;
+    run(&executor, &mut session, &["SET", "b", "1"]).await;
+    assert_eq!(
+        version(run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await),
+        first
+    );
+
+    let mut last = first;
+    for cmd in [
+        &["SET", "a", "2"][..],
+        &["EXPIRE", "a", "100"],
+        &["PERSIST", "a"],
+        &["APPEND", "a", "3"],
+    ] {
+        run(&executor, &mut session, cmd).await;
+        let next = version(run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await);
+        assert!(next > last, "{:?} did not raise the version", cmd);
+        last = next;
+    }
+    // Deleting and writing the key again does not reuse a version.
+    run(&executor, &mut session, &["DEL", "a"]).await;
+    assert!(matches!(
+        run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await,
+        RespValue::Bulk(None)
+    ));
+    run(&executor, &mut session, &["SET", "a", "4"]).await;
+    assert!(version(run(&executor, &mut session, &["OBJECT", "VERSION", "a"]).await) > last);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn undelete_needs_non_redis_mode_and_soft_delete() {
+    let (executor, mut session, path) = make_executor().await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["UNDELETE", "a"]).await)
+            .contains("FEDIS_NON_REDIS_MODE")
+    );
+    let executor = executor.with_non_redis_mode(true);
+    run(&executor, &mut session, &["SET", "a", "1"]).await;
+    run(&executor, &mut session, &["DEL", "a"]).await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["UNDELETE", "a"]).await)
+            .contains("FEDIS_SOFT_DELETE_RETENTION_SEC")
+    );
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn locks_are_released_and_extended_only_with_their_token() {
+    let (executor, mut session, path) = make_executor().await;
+    assert!(
+        expect_error(run(&executor, &mut session, &["LOCK", "job", "1000"]).await)
+            .contains("FEDIS_NON_REDIS_MODE")
+    );
+    let executor = executor.with_non_redis_mode(true);
+    let first = expect_int(run(&executor, &mut session, &["LOCK", "job", "1000"]).await);
+    assert!(matches!(
+        run(&executor, &mut session, &["LOCK", "job", "1000"]).await,
+        RespValue::Bulk(None)
+    ));
+    let stale = (first - 1).to_string();
+    let token = first.to_string();
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["EXTEND", "job", &stale, "5000"]).await),
+        0
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["EXTEND", "job", &token, "5000"]).await),
+        1
+    );
+    let RespValue::Integer(ttl) = run(&executor, &mut session, &["PTTL", "job"]).await else {
+        panic!("expected a TTL");
+    };
+    assert!(ttl > 4000, "{}", ttl);
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["UNLOCK", "job", &stale]).await),
+        0
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["UNLOCK", "job", &token]).await),
+        1
+    );
+    assert_eq!(
+        expect_int(run(&executor, &mut session, &["UNLOCK", "job", &token]).await),
+        0
+    );
+
+    // The next holder gets a higher token, also after the lease expired.
+    let second = expect_int(run(&executor, &mut session, &["LOCK", "job", "10"]).await);
+    assert!(second > first);
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    let expired = second.to_string();
+    assert_eq!(
+        expect_int(
+            run(
+                &executor,
+                &mut session,
+                &["EXTEND", "job", &expired, "1000"]
+            )
+            .await
+        ),
+        0
+    );
+    let third = expect_int(run(&executor, &mut session, &["LOCK", "job", "1000"]).await);
+    assert!(third > second);
+
+    assert!(
+        expect_error(run(&executor, &mut session, &["LOCK", "job", "0"]).await).contains("expire")
+    );
+    assert!(
+        expect_error(run(&executor, &mut session, &["UNLOCK", "job", "abc"]).await)
+            .contains("not an integer")
     );
-    let module = run(&executor, &mut session, &["MODULE", "LIST"]).await;
-    match module {
-        RespValue::Array(v) => assert!(v.is_empty()),
-        _ => panic!("expected array response"),
-    }
 
     let _ = std::fs::remove_file(path);
 }
@@ -0,0 +1,67 @@
+use super::*;
+use crate::store::{IncrByError, WrongType};
+use crate::throttle::Gcra;
+
+impl CommandExecutor {
+    /// `THROTTLE key max_burst count period [quantity]`, redis-cell's
+    /// `CL.THROTTLE` in non_redis_mode: `count` requests per `period`
+    /// seconds plus bursts of `max_burst`, replying `[limited, limit,
+    /// remaining, retry_after, reset_after]` with the times in whole seconds,
+    /// rounded up, and a retry of -1 when allowed.
+    pub(super) async fn throttle(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if args.len() > 6 {
+            return (wrong_arity("THROTTLE"), SessionAction::Continue);
+        }
+        let numbers: Option<Vec<u64>> = args[2..].iter().map(|raw| parse_u64(raw)).collect();
+        let Some(numbers) = numbers else {
+            return (
+                RespValue::Error("ERR value is not an integer or out of range".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let quantity = numbers.get(3).copied().unwrap_or(1);
+        let Some(gcra) = Gcra::new(numbers[0], numbers[1], numbers[2]) else {
+            return (
+                RespValue::Error("ERR count and period must be positive".to_string()),
+                SessionAction::Continue,
+            );
+        };
+
+        let now_us = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let decision = match self.store.throttle(&args[1], &gcra, quantity, now_us).await {
+            Ok(decision) => decision,
+            Err(IncrByError::NotInteger | IncrByError::OutOfRange) => {
+                return (
+                    RespValue::Error("ERR value is not an integer or out of range".to_string()),
+                    SessionAction::Continue,
+                );
+            }
+            Err(IncrByError::WrongType) => {
+                return (
+                    RespValue::Error(WrongType.to_string()),
+                    SessionAction::Continue,
+                );
+            }
+            Err(IncrByError::Internal) => {
+                return (
+                    RespValue::Error("ERR internal persistence failure".to_string()),
+                    SessionAction::Continue,
+                );
+            }
+        };
+        let secs = |us: u64| us.div_ceil(1_000_000) as i64;
+        (
+            RespValue::Array(vec![
+                RespValue::Integer(decision.limited as i64),
+                RespValue::Integer(decision.limit as i64),
+                RespValue::Integer(decision.remaining as i64),
+                RespValue::Integer(decision.retry_after.map_or(-1, secs)),
+                RespValue::Integer(secs(decision.reset_after)),
+            ]),
+            SessionAction::Continue,
+        )
+    }
+}
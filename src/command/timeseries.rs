@@ -0,0 +1,195 @@
+use super::*;
+use crate::timeseries::{self, Aggregation, Series};
+
+impl CommandExecutor {
+    /// `TS.CREATE key [RETENTION ms]`: an empty series; a retention of 0,
+    /// the default, keeps every sample.
+    pub(super) async fn ts_create(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let retention = match parse_retention(&args[2..]) {
+            Ok(retention) => retention,
+            Err(reply) => return reply,
+        };
+        match self.store.ts_create(&args[1], retention).await {
+            Ok(()) => (RespValue::Simple("OK".to_string()), SessionAction::Continue),
+            Err(e) => (RespValue::Error(e.reply()), SessionAction::Continue),
+        }
+    }
+
+    /// `TS.ADD key timestamp|* value [RETENTION ms]`: replies with the
+    /// sample's timestamp, `*` being the server's clock. A missing series is
+    /// created with the retention given.
+    pub(super) async fn ts_add(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let (timestamp, value) = match parse_sample(&args[2], &args[3]) {
+            Ok(sample) => sample,
+            Err(e) => return (RespValue::Error(e), SessionAction::Continue),
+        };
+        let retention = match parse_retention(&args[4..]) {
+            Ok(retention) => retention,
+            Err(reply) => return reply,
+        };
+        match self
+            .store
+            .ts_add(&args[1], timestamp, value, Some(retention))
+            .await
+        {
+            Ok(()) => (
+                RespValue::Integer(timestamp as i64),
+                SessionAction::Continue,
+            ),
+            Err(e) => (RespValue::Error(e.reply()), SessionAction::Continue),
+        }
+    }
+
+    /// `TS.MADD key timestamp value [key timestamp value ...]`: each sample
+    /// is added on its own, to a series that must exist, and answered with
+    /// its timestamp or its error.
+    pub(super) async fn ts_madd(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        if !(args.len() - 1).is_multiple_of(3) {
+            return (wrong_arity("TS.MADD"), SessionAction::Continue);
+        }
+        let mut replies = Vec::with_capacity(args.len() / 3);
+        for sample in args[1..].chunks(3) {
+            let reply = match parse_sample(&sample[1], &sample[2]) {
+                Ok((timestamp, value)) => {
+                    match self.store.ts_add(&sample[0], timestamp, value, None).await {
+                        Ok(()) => RespValue::Integer(timestamp as i64),
+                        Err(e) => RespValue::Error(e.reply()),
+                    }
+                }
+                Err(e) => RespValue::Error(e),
+            };
+            replies.push(reply);
+        }
+        (RespValue::Array(replies), SessionAction::Continue)
+    }
+
+    /// `TS.GET key`: the newest sample as `[timestamp, value]`, or an empty
+    /// array for an empty series.
+    pub(super) async fn ts_get(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let series = match self.store.ts_series(&args[1]).await {
+            Ok(series) => series,
+            Err(e) => return (RespValue::Error(e.reply()), SessionAction::Continue),
+        };
+        let reply = match Series::new(&series).last() {
+            Some(sample) => sample_reply(sample),
+            None => RespValue::Array(Vec::new()),
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `TS.RANGE key from|- to|+ [COUNT n] [AGGREGATION avg|min|max|sum
+    /// bucket]`: the samples between the two timestamps, both included, or
+    /// one per bucket of `bucket` ms that has any.
+    pub(super) async fn ts_range(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let (Some(from), Some(to)) = (parse_bound(&args[2]), parse_bound(&args[3])) else {
+            return (
+                RespValue::Error("ERR TSDB: invalid timestamp".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        let mut count = usize::MAX;
+        let mut aggregation = None;
+        let mut idx = 4;
+        while idx < args.len() {
+            match upper(&args[idx]).as_str() {
+                "COUNT" => {
+                    let Some(n) = args.get(idx + 1).and_then(|raw| parse_u64(raw)) else {
+                        return (
+                            RespValue::Error("ERR TSDB: Couldn't parse COUNT".to_string()),
+                            SessionAction::Continue,
+                        );
+                    };
+                    count = n as usize;
+                    idx += 2;
+                }
+                "AGGREGATION" => {
+                    let Some(kind) = args.get(idx + 1).and_then(|raw| Aggregation::parse(raw))
+                    else {
+                        return (
+                            RespValue::Error("ERR TSDB: unknown aggregation type".to_string()),
+                            SessionAction::Continue,
+                        );
+                    };
+                    let Some(bucket) = args
+                        .get(idx + 2)
+                        .and_then(|raw| parse_u64(raw))
+                        .filter(|bucket| *bucket > 0)
+                    else {
+                        return (
+                            RespValue::Error(
+                                "ERR TSDB: bucketDuration must be greater than zero".to_string(),
+                            ),
+                            SessionAction::Continue,
+                        );
+                    };
+                    aggregation = Some((kind, bucket));
+                    idx += 3;
+                }
+                _ => {
+                    return (
+                        RespValue::Error("ERR syntax error".to_string()),
+                        SessionAction::Continue,
+                    );
+                }
+            }
+        }
+
+        let series = match self.store.ts_series(&args[1]).await {
+            Ok(series) => series,
+            Err(e) => return (RespValue::Error(e.reply()), SessionAction::Continue),
+        };
+        let samples = Series::new(&series).range(from, to);
+        let samples: Vec<(u64, f64)> = match aggregation {
+            Some((kind, bucket)) => timeseries::aggregate(samples, kind, bucket),
+            None => samples.collect(),
+        };
+        let reply = samples.into_iter().take(count).map(sample_reply).collect();
+        (RespValue::Array(reply), SessionAction::Continue)
+    }
+}
+
+/// `[timestamp, value]`, the value a string as RedisTimeSeries sends it.
+fn sample_reply((timestamp, value): (u64, f64)) -> RespValue {
+    RespValue::Array(vec![
+        RespValue::Integer(timestamp as i64),
+        RespValue::Simple(value.to_string()),
+    ])
+}
+
+/// A timestamp, `*` for now, and a finite value.
+fn parse_sample(timestamp: &[u8], value: &[u8]) -> Result<(u64, f64), String> {
+    let timestamp = match timestamp {
+        b"*" => now_ms(),
+        raw => parse_u64(raw).ok_or("ERR TSDB: invalid timestamp")?,
+    };
+    let value = std::str::from_utf8(value)
+        .ok()
+        .and_then(|raw| raw.parse::<f64>().ok())
+        .filter(|value| value.is_finite())
+        .ok_or("ERR TSDB: invalid value")?;
+    Ok((timestamp, value))
+}
+
+/// A range end, where `-` and `+` stand for the oldest and newest samples.
+fn parse_bound(raw: &[u8]) -> Option<u64> {
+    match raw {
+        b"-" => Some(0),
+        b"+" => Some(u64::MAX),
+        raw => parse_u64(raw),
+    }
+}
+
+/// The optional `RETENTION ms` that ends `TS.CREATE` and `TS.ADD`.
+fn parse_retention(args: &[Vec<u8>]) -> Result<u64, (RespValue, SessionAction)> {
+    match args {
+        [] => Ok(0),
+        [option, raw] if upper(option) == "RETENTION" => parse_u64(raw).ok_or((
+            RespValue::Error("ERR TSDB: invalid RETENTION value".to_string()),
+            SessionAction::Continue,
+        )),
+        _ => Err((
+            RespValue::Error("ERR syntax error".to_string()),
+            SessionAction::Continue,
+        )),
+    }
+}
@@ -0,0 +1,73 @@
+use super::*;
+use crate::cli::Reply;
+use crate::store::SetCondition;
+use crate::upstream::into_resp;
+
+impl CommandExecutor {
+    /// With `FEDIS_UPSTREAM_URL` set, this server sits in front of another
+    /// Redis: a `GET` of a key it does not have reads through to the upstream,
+    /// and writes go to the upstream first. `None` runs the command here as
+    /// usual.
+    pub(super) async fn through_upstream(
+        &self,
+        spec: &CommandSpec,
+        args: &[Vec<u8>],
+    ) -> Option<RespValue> {
+        let upstream = self.upstream.as_ref()?;
+        // The upstream cannot have the module.
+        if spec.is_write() && spec.name != "WASM.CALL" {
+            return self.write_through(spec, args).await;
+        }
+        if spec.name == "GET" && matches!(self.store.get(&args[1]).await, Ok(None)) {
+            let key = args[1].clone();
+            let reads = [
+                vec![b"GET".to_vec(), key.clone()],
+                vec![b"PTTL".to_vec(), key.clone()],
+            ];
+            let replies = match upstream.pipeline(&reads).await {
+                Ok(replies) => replies,
+                Err(e) => return Some(RespValue::Error(format!("ERR {}", e))),
+            };
+            let mut replies = replies.into_iter();
+            let (value, ttl) = (replies.next(), replies.next());
+            if let (Some(Reply::Bulk(value)), Some(cache_ttl)) = (&value, upstream.cache_ttl()) {
+                let ttl = match ttl {
+                    Some(Reply::Integer(left)) if left > 0 => {
+                        (left as u64).min(cache_ttl.as_millis() as u64)
+                    }
+                    _ => cache_ttl.as_millis() as u64,
+                };
+                // NX: a value written here in the meantime is newer.
+                let _ = self
+                    .store
+                    .set(key, value.clone(), Some(now_ms() + ttl), SetCondition::Nx)
+                    .await;
+            }
+            return Some(value.map_or(RespValue::Bulk(None), into_resp));
+        }
+        None
+    }
+
+    /// Sends the write upstream and answers with its reply. The keys it
+    /// names are dropped here rather than written, so the next `GET` reads
+    /// the upstream's result back; a write without keys, such as
+    /// `FLUSHALL`, runs here as well.
+    async fn write_through(&self, spec: &CommandSpec, args: &[Vec<u8>]) -> Option<RespValue> {
+        let upstream = self.upstream.as_ref()?;
+        let reply = match upstream.call(args).await {
+            Ok(reply) => into_resp(reply),
+            Err(e) => return Some(RespValue::Error(format!("ERR {}", e))),
+        };
+        if matches!(reply, RespValue::Error(_)) {
+            return Some(reply);
+        }
+        let keys: Vec<Vec<u8>> = spec.keys(args).into_iter().map(<[u8]>::to_vec).collect();
+        if keys.is_empty() {
+            return None;
+        }
+        if let Err(e) = self.store.forget(&keys).await {
+            return Some(RespValue::Error(format!("ERR internal: {}", e)));
+        }
+        Some(reply)
+    }
+}
@@ -0,0 +1,110 @@
+use super::*;
+use crate::wasm::WasmCall;
+
+impl CommandExecutor {
+    /// `WASM.LOAD name module [REPLACE]`: compiles a module, in the binary
+    /// or the text format, and replies with its name.
+    pub(super) async fn wasm_load(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(runtime) = &self.wasm else {
+            return (wasm_disabled(), SessionAction::Continue);
+        };
+        let replace = match &args[3..] {
+            [] => false,
+            [option] if upper(option) == "REPLACE" => true,
+            _ => {
+                return (
+                    RespValue::Error("ERR syntax error".to_string()),
+                    SessionAction::Continue,
+                );
+            }
+        };
+        let name = String::from_utf8_lossy(&args[1]).into_owned();
+        match runtime.load(&name, args[2].clone(), replace).await {
+            Ok(()) => (
+                RespValue::Bulk(Some(Bytes::from(name))),
+                SessionAction::Continue,
+            ),
+            Err(e) => (RespValue::Error(e), SessionAction::Continue),
+        }
+    }
+
+    /// `WASM.UNLOAD name`.
+    pub(super) async fn wasm_unload(&self, args: &[Vec<u8>]) -> (RespValue, SessionAction) {
+        let Some(runtime) = &self.wasm else {
+            return (wasm_disabled(), SessionAction::Continue);
+        };
+        let reply = match runtime.unload(&String::from_utf8_lossy(&args[1])) {
+            Ok(true) => RespValue::Simple("OK".to_string()),
+            Ok(false) => RespValue::Error("ERR no such WASM module".to_string()),
+            Err(e) => RespValue::Error(format!("ERR failed to remove WASM module: {}", e)),
+        };
+        (reply, SessionAction::Continue)
+    }
+
+    /// `WASM.LIST`: `[name, functions]` pairs like `FUNCTION LIST`'s.
+    pub(super) async fn wasm_list(&self) -> (RespValue, SessionAction) {
+        let Some(runtime) = &self.wasm else {
+            return (wasm_disabled(), SessionAction::Continue);
+        };
+        let modules = runtime
+            .list()
+            .into_iter()
+            .map(|(name, functions)| {
+                RespValue::Array(vec![
+                    RespValue::Bulk(Some(Bytes::from_static(b"module_name"))),
+                    RespValue::Bulk(Some(Bytes::from(name))),
+                    RespValue::Bulk(Some(Bytes::from_static(b"functions"))),
+                    RespValue::Array(
+                        functions
+                            .into_iter()
+                            .map(|f| RespValue::Bulk(Some(Bytes::from(f))))
+                            .collect(),
+                    ),
+                ])
+            })
+            .collect();
+        (RespValue::Array(modules), SessionAction::Continue)
+    }
+
+    /// `WASM.CALL module function numkeys key [key ...] arg [arg ...]`, and
+    /// `WASM.CALL_RO`, where the script may only read.
+    pub(super) async fn wasm_call(
+        &self,
+        args: &[Vec<u8>],
+        read_only: bool,
+    ) -> (RespValue, SessionAction) {
+        let Some(runtime) = &self.wasm else {
+            return (wasm_disabled(), SessionAction::Continue);
+        };
+        let Some(numkeys) = parse_u64(&args[3]).map(|n| n as usize) else {
+            return (
+                RespValue::Error("ERR value is not an integer or out of range".to_string()),
+                SessionAction::Continue,
+            );
+        };
+        if numkeys > args.len() - 4 {
+            return (
+                RespValue::Error(
+                    "ERR Number of keys can't be greater than number of args".to_string(),
+                ),
+                SessionAction::Continue,
+            );
+        }
+        let call = WasmCall {
+            module: &String::from_utf8_lossy(&args[1]),
+            function: &String::from_utf8_lossy(&args[2]),
+            keys: &args[4..4 + numkeys],
+            args: &args[4 + numkeys..],
+            read_only,
+        };
+        let reply = match runtime.call(&self.store, call).await {
+            Ok(value) => RespValue::Bulk(value.map(Bytes::from)),
+            Err(e) => RespValue::Error(e),
+        };
+        (reply, SessionAction::Continue)
+    }
+}
+
+fn wasm_disabled() -> RespValue {
+    RespValue::Error("ERR WASM scripting is not enabled".to_string())
+}
@@ -0,0 +1,153 @@
+use std::io::{Read, Write};
+
+/// Compression applied to persisted payloads. The tag byte is what gets written to
+/// disk, so existing values must never be renumbered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+const ZSTD_LEVEL: i32 = 3;
+
+impl Compression {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" | "no" => Ok(Self::None),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            other => Err(format!(
+                "unknown compression '{}', expected one of: none, lz4, zstd",
+                other
+            )),
+        }
+    }
+
+    pub fn tag(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Lz4),
+            2 => Ok(Self::Zstd),
+            other => Err(format!("unknown compression tag {}", other)),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| format!("lz4 decompression failed: {}", e)),
+            Self::Zstd => {
+                zstd::decode_all(data).map_err(|e| format!("zstd decompression failed: {}", e))
+            }
+        }
+    }
+
+    /// Streaming counterpart of `compress`. LZ4 output is a frame rather than a
+    /// size-prefixed block, so it is read back with `decompress_framed`.
+    pub fn writer<W: Write>(self, out: W) -> Result<CompressWriter<W>, String> {
+        Ok(match self {
+            Self::None => CompressWriter::None(out),
+            Self::Lz4 => CompressWriter::Lz4(lz4_flex::frame::FrameEncoder::new(out)),
+            Self::Zstd => CompressWriter::Zstd(
+                zstd::stream::write::Encoder::new(out, ZSTD_LEVEL).map_err(|e| e.to_string())?,
+            ),
+        })
+    }
+
+    pub fn decompress_framed(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Lz4 => {
+                let mut out = Vec::new();
+                lz4_flex::frame::FrameDecoder::new(data)
+                    .read_to_end(&mut out)
+                    .map_err(|e| format!("lz4 decompression failed: {}", e))?;
+                Ok(out)
+            }
+            other => other.decompress(data),
+        }
+    }
+}
+
+pub enum CompressWriter<W: Write> {
+    None(W),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressWriter<W> {
+    /// Writes out the end of the frame and hands back the underlying writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        match self {
+            Self::None(out) => Ok(out),
+            Self::Lz4(encoder) => encoder.finish().map_err(std::io::Error::other),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(out) => out.write(buf),
+            Self::Lz4(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::None(out) => out.flush(),
+            Self::Lz4(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_algorithm_round_trips() {
+        let data = br#"{"items":[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]}"#.repeat(20);
+        for alg in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let packed = alg.compress(&data).expect("compress");
+            if alg != Compression::None {
+                assert!(packed.len() < data.len() / 4);
+            }
+            let tag = Compression::from_tag(alg.tag()).expect("tag");
+            assert_eq!(tag.decompress(&packed).expect("decompress"), data);
+
+            let mut writer = alg.writer(Vec::new()).expect("writer");
+            for chunk in data.chunks(100) {
+                writer.write_all(chunk).expect("write");
+            }
+            let framed = writer.finish().expect("finish");
+            assert_eq!(alg.decompress_framed(&framed).expect("decompress"), data);
+        }
+        assert!(
+            Compression::Lz4
+                .decompress(b"\xff\xff\xff\x7fjunk")
+                .is_err()
+        );
+    }
+}
@@ -1,31 +1,154 @@
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
-use crate::auth::{Permissions, User};
-use crate::persistence::AofFsync;
+use crate::auth::{Permissions, User, read_acl_file};
+use crate::backend::{StorageEngine, parse_storage_engine};
+use crate::cluster::{StaticNode, parse_cluster_nodes};
+use crate::compression::Compression;
+use crate::encryption::Keyring;
+use crate::ipfilter::{IpFilter, parse_cidr_list};
+use crate::jwt::JwtVerifier;
+use crate::lockout::LockoutPolicy;
+use crate::logging::{DEFAULT_LOG_RETAIN, LogFile};
+use crate::metrics::MetricsAccess;
+use crate::otel::{OtelConfig, parse_otel};
+use crate::persistence::{AofFormat, AofFsync};
+use crate::ratelimit::RateLimit;
+use crate::remote::RemoteSnapshots;
+use crate::replication::parse_replica_of;
+use crate::runtime::{RuntimeConfig, parse_runtime};
+use crate::s3::S3Credentials;
+use crate::statsd::{StatsdConfig, parse_statsd};
+use crate::store::SaveRule;
+use crate::tls::{
+    TlsAuthClients, TlsClientUser, TlsSettings, parse_auth_clients, parse_client_user,
+};
+use crate::upstream::{UpstreamConfig, parse_upstream};
+use crate::wasm::{WasmSettings, parse_wasm};
+use crate::webhook::{ExpiryWebhookConfig, parse_expiry_webhook};
+use crate::write_behind::{WriteBehindConfig, parse_write_behind};
+
+type UrlCredentials = (String, String, Permissions);
 
 #[derive(Clone)]
 pub struct Config {
     pub listen_addr: String,
     pub aof_path: PathBuf,
+    pub storage_engine: StorageEngine,
     pub users: HashMap<String, User>,
     pub default_user: String,
+    /// The default user's password as configured, for `fedis ping` to log
+    /// in with; the server itself keeps only its hash.
+    pub default_password: Option<String>,
+    pub acl_file: Option<PathBuf>,
+    pub audit_log_path: Option<PathBuf>,
     pub aof_fsync: AofFsync,
+    pub aof_format: AofFormat,
+    pub aof_load_truncated: bool,
+    pub aof_timestamps: bool,
+    pub recover_to_ts: Option<u64>,
+    pub aof_compression: Compression,
+    pub aof_compression_min_bytes: usize,
+    pub encryption: Option<Arc<Keyring>>,
     pub snapshot_path: Option<PathBuf>,
+    /// Redis `dump.rdb` loaded at startup when the store is empty.
+    pub rdb_import_path: Option<PathBuf>,
+    /// Redis-compatible RDB file written on every `SAVE`/`BGSAVE`.
+    pub rdb_export_path: Option<PathBuf>,
+    /// The directory `EXPORT` writes to.
+    pub export_dir: PathBuf,
+    pub snapshot_compression: Compression,
+    /// Object store (or directory) that receives a copy of every snapshot.
+    pub snapshot_remote: Option<RemoteSnapshots>,
     pub snapshot_interval_sec: Option<u64>,
+    /// Redis-style `save <seconds> <changes>` rules.
+    pub save_rules: Vec<SaveRule>,
     pub max_connections: usize,
+    pub ip_filter: IpFilter,
     pub max_request_bytes: usize,
     pub idle_timeout_sec: u64,
     pub max_memory_bytes: Option<u64>,
+    /// Removed values at least this large are freed on a background thread.
+    pub lazyfree_threshold_bytes: usize,
+    /// `slowlog-log-slower-than` in microseconds: negative disables the slowlog.
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    /// The Tokio runtime the server runs on, built before anything else starts.
+    pub runtime: RuntimeConfig,
+    pub user_rate_limits: HashMap<String, RateLimit>,
+    pub auth_lockout: Option<LockoutPolicy>,
+    pub jwt: Option<JwtVerifier>,
+    pub read_only: bool,
+    /// Refuse client writes while following a master.
+    pub replica_read_only: bool,
+    /// `replica-priority`, which Sentinel uses to pick a replica to promote.
+    pub replica_priority: u32,
+    /// Follow this master (host, port) as a replica.
+    pub replica_of: Option<(String, u16)>,
+    /// `masteruser`/`masterauth`: credentials for whichever master is followed,
+    /// at startup or after `REPLICAOF`.
+    pub master_user: Option<String>,
+    pub master_auth: Option<String>,
+    /// Bytes of recent writes kept for replicas to resume from.
+    pub repl_backlog_bytes: usize,
+    /// Cluster mode: the address this node announces in redirections, set
+    /// when `FEDIS_CLUSTER_ENABLED` is on.
+    pub cluster_announce: Option<(String, u16)>,
+    /// Static cluster membership and the slots each node starts with.
+    pub cluster_nodes: Vec<StaticNode>,
+    pub kill_deleted_user_sessions: bool,
+    /// The gRPC listener's address, from `FEDIS_GRPC_ADDR`; off when unset.
+    pub grpc_addr: Option<String>,
+    /// Rebound to the loopback interface unless `FEDIS_METRICS_LOCALHOST_ONLY`
+    /// is turned off.
     pub metrics_addr: Option<String>,
+    pub metrics_access: MetricsAccess,
+    pub metrics_tls: Option<TlsSettings>,
+    /// Replaces `fedis` at the start of every exported metric name.
+    pub metrics_prefix: String,
+    /// Log to this file with rotation instead of stdout.
+    pub log_file: Option<LogFile>,
+    /// OTLP span and metric export, from the standard `OTEL_*` variables.
+    pub otel: Option<OtelConfig>,
+    pub statsd: Option<StatsdConfig>,
+    /// Read through to and write through to another Redis.
+    pub upstream: Option<UpstreamConfig>,
+    /// Stream committed writes to PostgreSQL or Kafka.
+    pub write_behind: Option<WriteBehindConfig>,
+    /// Post the keys that expire to an HTTP endpoint; non_redis_mode only.
+    pub expiry_webhook: Option<ExpiryWebhookConfig>,
+    /// How long `DEL` keeps keys for `UNDELETE`; off when `None`.
+    /// non_redis_mode only.
+    pub soft_delete_retention: Option<Duration>,
+    pub tls: Option<TlsSettings>,
     pub non_redis_mode: bool,
     pub debug_response_ids: bool,
+    /// List the built-in JSON, search and time series commands as modules.
+    pub advertise_modules: bool,
+    /// Limits of `WASM.CALL` and where `WASM.LOAD` keeps modules.
+    pub wasm: WasmSettings,
 }
 
 impl Config {
+    /// Reads the `FEDIS_*` variables, then the `FEDIS_CONFIG` file for those
+    /// not set, and the listen address or `redis://` URL given as the first
+    /// argument.
     pub fn from_env_and_args() -> Result<Self, Box<dyn std::error::Error>> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        Self::from_env_with_args(&args)
+    }
+
+    /// `from_env_and_args` without the arguments, for the modes that take
+    /// arguments of their own but connect to the configured server.
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_env_with_args(&[])
+    }
+
+    pub(crate) fn from_env_with_args(args: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
         let file_settings = if let Ok(path) = env::var("FEDIS_CONFIG") {
             parse_env_file(std::path::Path::new(&path))?
         } else {
@@ -36,7 +159,18 @@ impl Config {
                 .ok()
                 .or_else(|| file_settings.get(key).cloned())
         };
+        Self::from_settings(&setting, args)
+    }
 
+    /// A configuration built in code, independent of the environment.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    fn from_settings(
+        setting: &dyn Fn(&str) -> Option<String>,
+        args: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let host = setting("FEDIS_HOST").unwrap_or_else(|| "127.0.0.1".to_string());
         let port = setting("FEDIS_PORT").unwrap_or_else(|| "6379".to_string());
         let mut listen_addr =
@@ -45,9 +179,26 @@ impl Config {
         let mut default_user = setting("FEDIS_USERNAME").unwrap_or_else(|| "default".to_string());
 
         let data_path = setting("FEDIS_DATA_PATH").unwrap_or_else(|| ".".to_string());
-        let mut aof_path = PathBuf::from(data_path).join("fedis.aof");
+        let mut aof_path = PathBuf::from(&data_path).join("fedis.aof");
+        let mut storage_engine = parse_storage_engine(
+            setting("FEDIS_STORAGE_ENGINE").as_deref(),
+            setting("FEDIS_STORAGE_PATH").map(PathBuf::from),
+            Path::new(&data_path),
+        )?;
+        if let StorageEngine::Tiered(tier) = &mut storage_engine {
+            if let Some(bytes) = setting("FEDIS_TIER_VALUE_THRESHOLD_BYTES") {
+                tier.value_threshold = parse_u64(&bytes)? as usize;
+            }
+            if let Some(bytes) = setting("FEDIS_TIER_HOT_BYTES") {
+                tier.hot_bytes = parse_u64(&bytes)? as usize;
+            }
+        }
 
-        if let Some(password) = setting("FEDIS_PASSWORD") {
+        let password = match setting("FEDIS_PASSWORD_FILE") {
+            Some(path) => Some(read_secret_file(&path)?),
+            None => setting("FEDIS_PASSWORD"),
+        };
+        if let Some(password) = &password {
             let enabled = setting("FEDIS_USER_ENABLED")
                 .map(|v| parse_bool(v.as_str()))
                 .unwrap_or(true);
@@ -56,9 +207,10 @@ impl Config {
                 .unwrap_or(Permissions::All);
             users.insert(
                 default_user.clone(),
-                User::new(password, enabled, permissions),
+                User::new(password.clone(), enabled, permissions),
             );
         }
+        let mut default_password = password;
 
         if let Some(user_list) = setting("FEDIS_USERS") {
             for pair in user_list
@@ -69,7 +221,14 @@ impl Config {
                 if let Some((user, definition)) = pair.split_once(':') {
                     let user = user.trim().to_string();
                     let mut chunks = definition.split(':').map(|v| v.trim());
-                    let password = chunks.next().unwrap_or_default().to_string();
+                    let mut password = chunks.next().unwrap_or_default().to_string();
+                    // `user:file:/run/secrets/pw[:...]` reads the password from a mounted file.
+                    if password == "file" {
+                        let path = chunks.next().ok_or_else(|| {
+                            format!("FEDIS_USERS: missing secret file path for user '{}'", user)
+                        })?;
+                        password = read_secret_file(path)?;
+                    }
                     let next = chunks.next();
                     let (enabled, permissions) = if let Some(token) = next {
                         if is_bool_token(token) {
@@ -85,13 +244,13 @@ impl Config {
             }
         }
 
-        let args: Vec<String> = env::args().skip(1).collect();
-        if let Some(first) = args.first() {
+        if let Some(first) = args.first().filter(|arg| !arg.starts_with("--")) {
             if first.starts_with("redis://") {
                 let parsed = Self::parse_redis_url(first)?;
                 listen_addr = parsed.0;
                 if let Some((u, p, perms)) = parsed.1 {
                     default_user = u.clone();
+                    default_password = Some(p.clone());
                     users.insert(u, User::new(p, true, perms));
                 }
             } else {
@@ -104,10 +263,17 @@ impl Config {
             listen_addr = parsed.0;
             if let Some((u, p, perms)) = parsed.1 {
                 default_user = u.clone();
+                default_password = Some(p.clone());
                 users.insert(u, User::new(p, true, perms));
             }
         }
 
+        let acl_file = setting("FEDIS_ACL_FILE").map(PathBuf::from);
+        let audit_log_path = setting("FEDIS_AUDIT_LOG").map(PathBuf::from);
+        if let Some(path) = acl_file.as_ref().filter(|p| p.exists()) {
+            users = read_acl_file(path)?;
+        }
+
         if let Some(path) = setting("FEDIS_AOF_PATH") {
             aof_path = PathBuf::from(path);
         }
@@ -129,17 +295,125 @@ impl Config {
         let debug_response_ids = setting("FEDIS_DEBUG_RESPONSE_ID")
             .map(|v| parse_bool(v.as_str()))
             .unwrap_or(false);
+        let advertise_modules = setting("FEDIS_ADVERTISE_MODULES")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(false);
         let aof_fsync = parse_aof_fsync(setting("FEDIS_AOF_FSYNC").as_deref())?;
+        let aof_format = parse_aof_format(setting("FEDIS_AOF_FORMAT").as_deref())?;
+        let aof_compression = setting("FEDIS_AOF_COMPRESSION")
+            .as_deref()
+            .map(Compression::parse)
+            .transpose()?
+            .unwrap_or_default();
+        if aof_compression != Compression::None && aof_format == AofFormat::Resp {
+            return Err("FEDIS_AOF_COMPRESSION cannot be used with FEDIS_AOF_FORMAT=redis".into());
+        }
+        let aof_compression_min_bytes = setting("FEDIS_AOF_COMPRESSION_MIN_BYTES")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(1024) as usize;
+        let aof_load_truncated = setting("FEDIS_AOF_LOAD_TRUNCATED")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(true);
+        let aof_timestamps = setting("FEDIS_AOF_TIMESTAMPS")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(false);
+        let recover_to_ts = setting("FEDIS_RECOVER_TO_TS")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?;
+        let encryption_keys = match setting("FEDIS_ENCRYPTION_KEY_FILE") {
+            Some(path) => Some(read_secret_file(&path)?),
+            None => setting("FEDIS_ENCRYPTION_KEYS"),
+        };
+        let encryption = encryption_keys
+            .map(|keys| {
+                let active = setting("FEDIS_ENCRYPTION_KEY_ID")
+                    .map(|id| id.trim().parse::<u32>())
+                    .transpose()
+                    .map_err(|_| "FEDIS_ENCRYPTION_KEY_ID must be a number")?;
+                Keyring::parse(&keys, active).map(Arc::new)
+            })
+            .transpose()?;
         let snapshot_path = setting("FEDIS_SNAPSHOT_PATH").map(PathBuf::from);
+        let rdb_import_path = setting("FEDIS_RDB_IMPORT_PATH")
+            .map(PathBuf::from)
+            .or_else(|| {
+                snapshot_path
+                    .as_ref()
+                    .map(|p| p.with_file_name("dump.rdb"))
+                    .filter(|p| p.exists())
+            });
+        let rdb_export_path = setting("FEDIS_RDB_EXPORT_PATH").map(PathBuf::from);
+        let export_dir = setting("FEDIS_EXPORT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(&data_path).join("export"));
+        let snapshot_remote = match setting("FEDIS_SNAPSHOT_REMOTE") {
+            Some(target) => {
+                if snapshot_path.is_none() {
+                    return Err("FEDIS_SNAPSHOT_REMOTE requires FEDIS_SNAPSHOT_PATH".into());
+                }
+                let keep = setting("FEDIS_SNAPSHOT_REMOTE_KEEP")
+                    .as_deref()
+                    .map(parse_u64)
+                    .transpose()?
+                    .unwrap_or(7) as usize;
+                let secret_access_key =
+                    match setting("FEDIS_SNAPSHOT_REMOTE_SECRET_ACCESS_KEY_FILE") {
+                        Some(path) => Some(read_secret_file(&path)?),
+                        None => setting("FEDIS_SNAPSHOT_REMOTE_SECRET_ACCESS_KEY")
+                            .or_else(|| setting("AWS_SECRET_ACCESS_KEY")),
+                    };
+                let credentials = setting("FEDIS_SNAPSHOT_REMOTE_ACCESS_KEY_ID")
+                    .or_else(|| setting("AWS_ACCESS_KEY_ID"))
+                    .zip(secret_access_key)
+                    .map(|(access_key_id, secret_access_key)| S3Credentials {
+                        access_key_id,
+                        secret_access_key,
+                        session_token: setting("AWS_SESSION_TOKEN"),
+                    });
+                Some(RemoteSnapshots::parse(
+                    &target,
+                    keep,
+                    setting("FEDIS_SNAPSHOT_REMOTE_REGION").or_else(|| setting("AWS_REGION")),
+                    setting("FEDIS_SNAPSHOT_REMOTE_ENDPOINT"),
+                    credentials,
+                )?)
+            }
+            None => None,
+        };
+        let snapshot_compression = setting("FEDIS_SNAPSHOT_COMPRESSION")
+            .as_deref()
+            .map(Compression::parse)
+            .transpose()?
+            .unwrap_or_default();
         let snapshot_interval_sec = setting("FEDIS_SNAPSHOT_INTERVAL_SEC")
             .as_deref()
             .map(parse_u64)
             .transpose()?;
+        let save_rules = setting("FEDIS_SAVE")
+            .as_deref()
+            .map(parse_save_rules)
+            .transpose()?
+            .unwrap_or_default();
         let max_connections = setting("FEDIS_MAX_CONNECTIONS")
             .as_deref()
             .map(parse_u64)
             .transpose()?
             .unwrap_or(1024) as usize;
+        let ip_filter = IpFilter {
+            allow: setting("FEDIS_ALLOW_CIDRS")
+                .as_deref()
+                .map(parse_cidr_list)
+                .transpose()?
+                .unwrap_or_default(),
+            deny: setting("FEDIS_DENY_CIDRS")
+                .as_deref()
+                .map(parse_cidr_list)
+                .transpose()?
+                .unwrap_or_default(),
+        };
         let max_request_bytes = setting("FEDIS_MAX_REQUEST_BYTES")
             .as_deref()
             .map(parse_u64)
@@ -154,9 +428,234 @@ impl Config {
             .as_deref()
             .map(parse_u64)
             .transpose()?;
-        let metrics_addr = setting("FEDIS_METRICS_ADDR");
+        let lazyfree_threshold_bytes = setting("FEDIS_LAZYFREE_THRESHOLD_BYTES")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(crate::lazyfree::DEFAULT_THRESHOLD as u64)
+            as usize;
+        let slowlog_log_slower_than = setting("FEDIS_SLOWLOG_LOG_SLOWER_THAN")
+            .map(|v| {
+                v.trim()
+                    .parse::<i64>()
+                    .map_err(|_| "FEDIS_SLOWLOG_LOG_SLOWER_THAN must be an integer")
+            })
+            .transpose()?
+            .unwrap_or(crate::slowlog::DEFAULT_SLOWER_THAN_USEC);
+        let slowlog_max_len = setting("FEDIS_SLOWLOG_MAX_LEN")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(crate::slowlog::DEFAULT_MAX_LEN as u64)
+            as usize;
+        let runtime = parse_runtime(
+            setting("FEDIS_RUNTIME").as_deref(),
+            setting("FEDIS_WORKER_THREADS").as_deref(),
+            setting("FEDIS_MAX_BLOCKING_THREADS").as_deref(),
+        )?;
+        let user_rate_limits = setting("FEDIS_USER_RATE_LIMITS")
+            .as_deref()
+            .map(parse_rate_limits)
+            .transpose()?
+            .unwrap_or_default();
+        let auth_max_failures = setting("FEDIS_AUTH_MAX_FAILURES")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(10);
+        let auth_lockout_sec = setting("FEDIS_AUTH_LOCKOUT_SEC")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(1);
+        let auth_lockout_max_sec = setting("FEDIS_AUTH_LOCKOUT_MAX_SEC")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(300);
+        let auth_lockout = (auth_max_failures > 0).then(|| LockoutPolicy {
+            max_failures: auth_max_failures.min(u32::MAX as u64) as u32,
+            base_delay: Duration::from_secs(auth_lockout_sec.max(1)),
+            max_delay: Duration::from_secs(auth_lockout_max_sec.max(auth_lockout_sec).max(1)),
+        });
+        let jwt_hs256_secret = match setting("FEDIS_JWT_HS256_SECRET_FILE") {
+            Some(path) => Some(read_secret_file(&path)?),
+            None => setting("FEDIS_JWT_HS256_SECRET"),
+        };
+        let jwt_rs256_key = setting("FEDIS_JWT_RS256_PUBLIC_KEY_FILE").map(PathBuf::from);
+        let jwt = if jwt_hs256_secret.is_some() || jwt_rs256_key.is_some() {
+            Some(JwtVerifier::new(
+                jwt_hs256_secret.map(String::into_bytes),
+                jwt_rs256_key.as_deref(),
+                setting("FEDIS_JWT_ISSUER"),
+                setting("FEDIS_JWT_AUDIENCE"),
+            )?)
+        } else {
+            None
+        };
+        let read_only = setting("FEDIS_READ_ONLY")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(false);
+        let replica_read_only = setting("FEDIS_REPLICA_READ_ONLY")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(true);
+        let replica_priority = setting("FEDIS_REPLICA_PRIORITY")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .map(|priority| {
+                u32::try_from(priority).map_err(|_| "FEDIS_REPLICA_PRIORITY is too large")
+            })
+            .transpose()?
+            .unwrap_or(100);
+        let replica_of = setting("FEDIS_REPLICAOF")
+            .as_deref()
+            .map(parse_replica_of)
+            .transpose()?;
+        let master_auth = match setting("FEDIS_MASTERAUTH_FILE") {
+            Some(path) => Some(read_secret_file(&path)?),
+            None => setting("FEDIS_MASTERAUTH"),
+        };
+        let master_user = setting("FEDIS_MASTERUSER");
+        let repl_backlog_bytes = setting("FEDIS_REPL_BACKLOG_BYTES")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or(1024 * 1024) as usize;
+        let cluster_announce = if setting("FEDIS_CLUSTER_ENABLED")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(false)
+        {
+            Some(match setting("FEDIS_CLUSTER_ANNOUNCE") {
+                Some(address) => parse_replica_of(&address)
+                    .map_err(|_| "FEDIS_CLUSTER_ANNOUNCE must be '<host>:<port>'")?,
+                None => default_announce(&listen_addr)?,
+            })
+        } else {
+            None
+        };
+        let cluster_nodes = setting("FEDIS_CLUSTER_NODES")
+            .as_deref()
+            .map(parse_cluster_nodes)
+            .transpose()?
+            .unwrap_or_default();
+        let kill_deleted_user_sessions = setting("FEDIS_ACL_KILL_DELETED_USER_SESSIONS")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(false);
+        let grpc_addr = setting("FEDIS_GRPC_ADDR");
+        let metrics_localhost_only = setting("FEDIS_METRICS_LOCALHOST_ONLY")
+            .map(|v| parse_bool(v.as_str()))
+            .unwrap_or(true);
+        let metrics_addr = setting("FEDIS_METRICS_ADDR")
+            .map(|addr| metrics_bind_addr(&addr, metrics_localhost_only))
+            .transpose()?;
+        let metrics_prefix = setting("FEDIS_METRICS_PREFIX").unwrap_or_else(|| "fedis".to_string());
+        if !is_metric_name(&metrics_prefix) {
+            return Err("FEDIS_METRICS_PREFIX must match [a-zA-Z_:][a-zA-Z0-9_:]*".into());
+        }
+        let metrics_password = match setting("FEDIS_METRICS_PASSWORD_FILE") {
+            Some(path) => Some(read_secret_file(&path)?),
+            None => setting("FEDIS_METRICS_PASSWORD"),
+        };
+        let metrics_access = MetricsAccess {
+            token: match setting("FEDIS_METRICS_TOKEN_FILE") {
+                Some(path) => Some(read_secret_file(&path)?),
+                None => setting("FEDIS_METRICS_TOKEN"),
+            }
+            .filter(|token| !token.is_empty()),
+            basic: match (setting("FEDIS_METRICS_USER"), metrics_password) {
+                (Some(user), Some(password)) => Some((user, password)),
+                (None, None) => None,
+                _ => {
+                    return Err(
+                        "FEDIS_METRICS_USER and FEDIS_METRICS_PASSWORD must be set together".into(),
+                    );
+                }
+            },
+            admin_token: match setting("FEDIS_METRICS_ADMIN_TOKEN_FILE") {
+                Some(path) => Some(read_secret_file(&path)?),
+                None => setting("FEDIS_METRICS_ADMIN_TOKEN"),
+            }
+            .filter(|token| !token.is_empty()),
+        };
+        let log_file = match setting("FEDIS_LOG_FILE") {
+            Some(path) => Some(LogFile {
+                path: PathBuf::from(path),
+                max_bytes: setting("FEDIS_LOG_MAX_BYTES")
+                    .as_deref()
+                    .map(parse_u64)
+                    .transpose()?
+                    .filter(|max| *max > 0),
+                daily: setting("FEDIS_LOG_ROTATE_DAILY")
+                    .map(|v| parse_bool(v.as_str()))
+                    .unwrap_or(false),
+                retain: setting("FEDIS_LOG_RETAIN")
+                    .as_deref()
+                    .map(parse_u64)
+                    .transpose()?
+                    .unwrap_or(DEFAULT_LOG_RETAIN as u64) as usize,
+            }),
+            None => None,
+        };
+        let otel = parse_otel(&setting)?;
+        let statsd = parse_statsd(&setting)?;
+        let upstream = parse_upstream(&setting)?;
+        let write_behind = parse_write_behind(&setting)?;
+        let expiry_webhook = parse_expiry_webhook(&setting)?;
+        let soft_delete_retention = setting("FEDIS_SOFT_DELETE_RETENTION_SEC")
+            .as_deref()
+            .map(parse_u64)
+            .transpose()?
+            .filter(|&sec| sec > 0)
+            .map(Duration::from_secs);
+        let wasm = parse_wasm(&setting, Path::new(&data_path))?;
+        let tls = match (
+            setting("FEDIS_TLS_CERT_FILE"),
+            setting("FEDIS_TLS_KEY_FILE"),
+        ) {
+            (Some(cert_file), Some(key_file)) => Some(TlsSettings {
+                cert_file: PathBuf::from(cert_file),
+                key_file: PathBuf::from(key_file),
+                ca_cert_file: setting("FEDIS_TLS_CA_CERT_FILE").map(PathBuf::from),
+                auth_clients: setting("FEDIS_TLS_AUTH_CLIENTS")
+                    .as_deref()
+                    .map(parse_auth_clients)
+                    .transpose()?
+                    .unwrap_or(TlsAuthClients::No),
+                client_user: setting("FEDIS_TLS_AUTH_CLIENTS_USER")
+                    .as_deref()
+                    .map(parse_client_user)
+                    .transpose()?
+                    .unwrap_or(TlsClientUser::Off),
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(
+                    "FEDIS_TLS_CERT_FILE and FEDIS_TLS_KEY_FILE must be set together".into(),
+                );
+            }
+        };
+        let metrics_tls = match (
+            setting("FEDIS_METRICS_TLS_CERT_FILE"),
+            setting("FEDIS_METRICS_TLS_KEY_FILE"),
+        ) {
+            (Some(cert_file), Some(key_file)) => Some(TlsSettings {
+                cert_file: PathBuf::from(cert_file),
+                key_file: PathBuf::from(key_file),
+                ca_cert_file: None,
+                auth_clients: TlsAuthClients::No,
+                client_user: TlsClientUser::Off,
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(
+                    "FEDIS_METRICS_TLS_CERT_FILE and FEDIS_METRICS_TLS_KEY_FILE must be set together"
+                        .into(),
+                );
+            }
+        };
 
-        if let Some(path) = &snapshot_path {
+        for path in [&snapshot_path, &rdb_export_path].into_iter().flatten() {
             if let Some(parent) = path.parent() {
                 std::fs::create_dir_all(parent)?;
             }
@@ -165,24 +664,73 @@ impl Config {
         Ok(Self {
             listen_addr,
             aof_path,
+            storage_engine,
             users,
             default_user,
+            default_password,
+            acl_file,
+            audit_log_path,
             aof_fsync,
+            aof_format,
+            aof_load_truncated,
+            aof_timestamps,
+            recover_to_ts,
+            aof_compression,
+            aof_compression_min_bytes,
+            encryption,
             snapshot_path,
+            rdb_import_path,
+            rdb_export_path,
+            export_dir,
+            snapshot_remote,
+            snapshot_compression,
             snapshot_interval_sec,
+            save_rules,
             max_connections,
+            ip_filter,
             max_request_bytes,
             idle_timeout_sec,
             max_memory_bytes,
+            lazyfree_threshold_bytes,
+            slowlog_log_slower_than,
+            slowlog_max_len,
+            runtime,
+            user_rate_limits,
+            auth_lockout,
+            jwt,
+            read_only,
+            replica_read_only,
+            replica_priority,
+            replica_of,
+            master_user,
+            master_auth,
+            repl_backlog_bytes,
+            cluster_announce,
+            cluster_nodes,
+            kill_deleted_user_sessions,
+            grpc_addr,
             metrics_addr,
+            metrics_access,
+            metrics_tls,
+            metrics_prefix,
+            log_file,
+            otel,
+            statsd,
+            upstream,
+            write_behind,
+            expiry_webhook,
+            soft_delete_retention,
+            tls,
             non_redis_mode,
             debug_response_ids,
+            advertise_modules,
+            wasm,
         })
     }
 
     fn parse_redis_url(
         input: &str,
-    ) -> Result<(String, Option<(String, String, Permissions)>), Box<dyn std::error::Error>> {
+    ) -> Result<(String, Option<UrlCredentials>), Box<dyn std::error::Error>> {
         let url = Url::parse(input)?;
         if url.scheme() != "redis" {
             return Err("URL scheme must be redis://".into());
@@ -214,6 +762,60 @@ impl Config {
     }
 }
 
+/// Builds a `Config` from settings given in code rather than the process
+/// environment. Each setting is one of the `FEDIS_*` variables, parsed and
+/// defaulted exactly as `from_env_and_args` would; the methods cover the
+/// common ones and `set` the rest.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigBuilder {
+    settings: HashMap<String, String>,
+}
+
+impl ConfigBuilder {
+    /// `FEDIS_*` variable `name`, e.g. `set("FEDIS_AOF_FSYNC", "always")`.
+    pub fn set(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.settings.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// `host:port` to listen on; port 0 picks a free one, which
+    /// `ServerHandle::local_addr` reports.
+    pub fn listen_addr(self, addr: impl Into<String>) -> Self {
+        self.set("FEDIS_LISTEN", addr)
+    }
+
+    /// Directory for the AOF and, unless set otherwise, the snapshot.
+    pub fn data_path(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        self.set("FEDIS_DATA_PATH", path)
+    }
+
+    /// Password of the default user; without one no `AUTH` is needed.
+    pub fn password(self, password: impl Into<String>) -> Self {
+        self.set("FEDIS_PASSWORD", password)
+    }
+
+    pub fn non_redis_mode(self, enabled: bool) -> Self {
+        self.set("FEDIS_NON_REDIS_MODE", if enabled { "1" } else { "0" })
+    }
+
+    pub fn build(self) -> Result<Config, Box<dyn std::error::Error>> {
+        Config::from_settings(&|key: &str| self.settings.get(key).cloned(), &[])
+    }
+}
+
+/// Reads a secret from a mounted file, dropping the trailing newline most
+/// secret stores append. The contents never appear in error messages.
+fn read_secret_file(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read secret file {}: {}", path, e))?;
+    let secret = contents.trim_end_matches(['\r', '\n']);
+    if secret.is_empty() {
+        return Err(format!("secret file {} is empty", path).into());
+    }
+    Ok(secret.to_string())
+}
+
 fn parse_env_file(
     path: &std::path::Path,
 ) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
@@ -253,6 +855,47 @@ fn parse_permissions(raw: Option<&str>) -> Permissions {
     }
 }
 
+/// The listen address, with a wildcard host replaced by loopback: clients
+/// cannot be redirected to `0.0.0.0`.
+/// Where the metrics listener binds: `127.0.0.1` on the port of `addr` when
+/// `localhost_only`, else `addr` as given. A bare port binds every interface
+/// when not `localhost_only`.
+fn metrics_bind_addr(
+    addr: &str,
+    localhost_only: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (host, port) = match addr.trim().rsplit_once(':') {
+        Some((host, port)) => (Some(host), port),
+        None => (None, addr.trim()),
+    };
+    let port: u16 = port
+        .parse()
+        .map_err(|_| "FEDIS_METRICS_ADDR must be '<host>:<port>' or '<port>'")?;
+    Ok(match host {
+        _ if localhost_only => format!("127.0.0.1:{}", port),
+        Some(host) => format!("{}:{}", host, port),
+        None => format!("0.0.0.0:{}", port),
+    })
+}
+
+/// Whether `name` is a valid Prometheus metric name.
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn default_announce(listen_addr: &str) -> Result<(String, u16), Box<dyn std::error::Error>> {
+    let (host, port) = parse_replica_of(listen_addr)?;
+    let host = match host.as_str() {
+        "0.0.0.0" | "::" | "[::]" => "127.0.0.1".to_string(),
+        _ => host,
+    };
+    Ok((host, port))
+}
+
 fn parse_bool(value: &str) -> bool {
     matches!(
         value.trim().to_ascii_lowercase().as_str(),
@@ -281,9 +924,71 @@ fn parse_aof_fsync(value: Option<&str>) -> Result<AofFsync, Box<dyn std::error::
     }
 }
 
+fn parse_aof_format(value: Option<&str>) -> Result<AofFormat, Box<dyn std::error::Error>> {
+    match value
+        .unwrap_or("fedis")
+        .trim()
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "fedis" => Ok(AofFormat::Fedis),
+        "redis" | "resp" => Ok(AofFormat::Resp),
+        _ => Err("FEDIS_AOF_FORMAT must be one of: fedis, redis".into()),
+    }
+}
+
+/// Parses `"900 1 300 10 60 10000"` (commas also separate) into save rules.
+fn parse_save_rules(value: &str) -> Result<Vec<SaveRule>, Box<dyn std::error::Error>> {
+    let numbers = value
+        .split([' ', ','])
+        .filter(|v| !v.is_empty())
+        .map(parse_u64)
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() % 2 != 0 {
+        return Err("FEDIS_SAVE must be pairs of '<seconds> <changes>'".into());
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| SaveRule {
+            seconds: pair[0],
+            changes: pair[1],
+        })
+        .collect())
+}
+
 fn parse_u64(value: &str) -> Result<u64, Box<dyn std::error::Error>> {
     value
         .trim()
         .parse::<u64>()
         .map_err(|_| "value must be an unsigned integer".into())
 }
+
+/// Parses `user:commands_per_sec[:bytes_per_sec],...`; an empty or `0` field means unlimited.
+fn parse_rate_limits(
+    value: &str,
+) -> Result<HashMap<String, RateLimit>, Box<dyn std::error::Error>> {
+    let mut out = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let mut parts = entry.split(':').map(str::trim);
+        let user = parts.next().unwrap_or_default();
+        if user.is_empty() {
+            return Err("FEDIS_USER_RATE_LIMITS entries must start with a user name".into());
+        }
+        let mut limit = || -> Result<Option<u64>, Box<dyn std::error::Error>> {
+            match parts.next() {
+                None | Some("") => Ok(None),
+                Some(v) => Ok(Some(parse_u64(v)?).filter(|v| *v > 0)),
+            }
+        };
+        let commands_per_sec = limit()?;
+        let bytes_per_sec = limit()?;
+        out.insert(
+            user.to_string(),
+            RateLimit {
+                commands_per_sec,
+                bytes_per_sec,
+            },
+        );
+    }
+    Ok(out)
+}
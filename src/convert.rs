@@ -0,0 +1,357 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{Map, Value, json};
+
+use crate::atomic_file::AtomicFile;
+use crate::compression::Compression;
+use crate::encoding::ValueType;
+use crate::encryption::Keyring;
+use crate::inspect::Keyspace;
+use crate::persistence::{AofFormat, write_log, write_log_to};
+use crate::rdb::write_rdb;
+use crate::store::{EntryRef, SnapshotEntry, write_snapshot};
+use crate::timeseries::{self, Series};
+
+const USAGE: &str =
+    "usage: fedis convert <input> <output|-> --to aof|redis-aof|snapshot|rdb|ndjson";
+
+/// A conversion requested with `fedis convert` instead of starting the
+/// server: the input, whichever format it is in, is read as `fedis dump`
+/// reads it and written out whole in another format.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Convert {
+    input: PathBuf,
+    /// `None` writes to stdout.
+    output: Option<PathBuf>,
+    to: Format,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// The binary `FDLOG` AOF, encrypted when a key is configured.
+    Aof,
+    /// The AOF as plain Redis commands.
+    RedisAof,
+    /// An `FDSNP` snapshot, encrypted when a key is configured.
+    Snapshot,
+    Rdb,
+    /// One JSON object per key.
+    Ndjson,
+}
+
+impl Convert {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if args.first().map(String::as_str) != Some("convert") {
+            return Ok(None);
+        }
+        let [_, input, output, flag, to] = args else {
+            return Err(USAGE.into());
+        };
+        if flag != "--to" {
+            return Err(USAGE.into());
+        }
+        let to = match to.as_str() {
+            "aof" => Format::Aof,
+            "redis-aof" => Format::RedisAof,
+            "snapshot" => Format::Snapshot,
+            "rdb" => Format::Rdb,
+            "ndjson" => Format::Ndjson,
+            other => return Err(format!("unknown format '{}'; {}", other, USAGE).into()),
+        };
+        let output = (output != "-").then(|| PathBuf::from(output));
+        if output.is_none() && !matches!(to, Format::RedisAof | Format::Ndjson) {
+            return Err("only redis-aof and ndjson can be written to stdout".into());
+        }
+        Ok(Some(Self {
+            input: PathBuf::from(input),
+            output,
+            to,
+        }))
+    }
+
+    /// Converts and returns the process exit code: 0 on success, 1 when
+    /// the input could not be read or the output written.
+    pub fn run(&self, keyring: Option<&Keyring>) -> i32 {
+        match self.convert(keyring) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    }
+
+    fn convert(&self, keyring: Option<&Keyring>) -> Result<(), Box<dyn std::error::Error>> {
+        let keyspace = Keyspace::read(&self.input, keyring)
+            .map_err(|e| format!("{}: {}", self.input.display(), e))?;
+        keyspace.warn_incomplete();
+        let entries = keyspace.entry_refs();
+        let Some(output) = &self.output else {
+            let mut stdout = std::io::stdout().lock();
+            match self.to {
+                Format::RedisAof => write_log_to(&mut stdout, entries, AofFormat::Resp, None)?,
+                _ => write_ndjson_to(&mut stdout, entries)?,
+            }
+            stdout.flush()?;
+            return Ok(());
+        };
+        match self.to {
+            Format::Aof => write_log(output, entries, AofFormat::Fedis, keyring)?,
+            Format::RedisAof => write_log(output, entries, AofFormat::Resp, None)?,
+            Format::Snapshot => write_snapshot(output, entries, keyring, Compression::None)?,
+            Format::Rdb => write_rdb(output, entries)?,
+            Format::Ndjson => write_ndjson(output, entries)?,
+        }
+        println!(
+            "converted {} keys from {} {} to {}",
+            keyspace.entries.len(),
+            keyspace.format,
+            self.input.display(),
+            output.display()
+        );
+        Ok(())
+    }
+}
+
+fn write_ndjson<'a>(
+    path: &Path,
+    entries: impl Iterator<Item = EntryRef<'a>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = AtomicFile::create(path, "ndjson.tmp")?;
+    write_ndjson_to(&mut file, entries)?;
+    file.commit()?;
+    Ok(())
+}
+
+fn write_ndjson_to<'a>(
+    out: &mut impl Write,
+    entries: impl Iterator<Item = EntryRef<'a>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = std::io::BufWriter::new(out);
+    for (key, kind, value, expires_at) in entries {
+        writeln!(out, "{}", ndjson_line(key, kind, value, expires_at))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// A key as one NDJSON object: `key` and a string `value` are text when
+/// they are UTF-8 and base64 under `key_base64` / `value_base64` when not;
+/// a JSON document's `value` is the document itself, and a time series has
+/// `retention_ms` and `samples` as `[timestamp, value]` pairs.
+pub(crate) fn ndjson_line(
+    key: &[u8],
+    kind: ValueType,
+    value: &[u8],
+    expires_at: Option<u64>,
+) -> String {
+    let mut object = Map::new();
+    binary_field(&mut object, "key", key);
+    object.insert("type".to_string(), kind.name().into());
+    match kind {
+        ValueType::Json => {
+            let document = serde_json::from_slice(value)
+                .unwrap_or_else(|_| String::from_utf8_lossy(value).into());
+            object.insert("value".to_string(), document);
+        }
+        ValueType::TimeSeries => {
+            let series = Series::new(value);
+            object.insert("retention_ms".to_string(), series.retention().into());
+            let samples = series
+                .range(0, u64::MAX)
+                .map(|(timestamp, sample)| json!([timestamp, sample]))
+                .collect();
+            object.insert("samples".to_string(), Value::Array(samples));
+        }
+        _ => binary_field(&mut object, "value", value),
+    }
+    if let Some(expires_at) = expires_at {
+        object.insert("expires_at_ms".to_string(), expires_at.into());
+    }
+    Value::Object(object).to_string()
+}
+
+fn binary_field(object: &mut Map<String, Value>, name: &str, bytes: &[u8]) {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => object.insert(name.to_string(), text.into()),
+        Err(_) => object.insert(format!("{}_base64", name), BASE64.encode(bytes).into()),
+    };
+}
+
+/// Reads back a line `ndjson_line` wrote.
+pub(crate) fn parse_ndjson(line: &str) -> Result<SnapshotEntry, String> {
+    let object: Map<String, Value> =
+        serde_json::from_str(line).map_err(|e| format!("invalid JSON: {}", e))?;
+    let key = read_binary_field(&object, "key")?.ok_or("missing key")?;
+    let kind = match object.get("type").and_then(Value::as_str) {
+        None | Some("string") => ValueType::String,
+        Some("json") => ValueType::Json,
+        Some("timeseries") => ValueType::TimeSeries,
+        Some(other) => return Err(format!("unsupported type '{}'", other)),
+    };
+    let value = match kind {
+        ValueType::Json => object
+            .get("value")
+            .ok_or("missing value")?
+            .to_string()
+            .into_bytes(),
+        ValueType::TimeSeries => {
+            let retention = object
+                .get("retention_ms")
+                .and_then(Value::as_u64)
+                .unwrap_or(0);
+            let mut series = timeseries::new(retention);
+            let samples = object.get("samples").and_then(Value::as_array);
+            for sample in samples.into_iter().flatten() {
+                let (Some(timestamp), Some(value)) = (
+                    sample.get(0).and_then(Value::as_u64),
+                    sample.get(1).and_then(Value::as_f64),
+                ) else {
+                    return Err("samples must be [timestamp, value] pairs".to_string());
+                };
+                Series::new(&series)
+                    .check(timestamp)
+                    .map_err(|e| e.reply())?;
+                series = timeseries::add(series, timestamp, value);
+            }
+            series.to_vec()
+        }
+        _ => read_binary_field(&object, "value")?.ok_or("missing value")?,
+    };
+    let expires_at = match object.get("expires_at_ms") {
+        None | Some(Value::Null) => None,
+        Some(at) => Some(at.as_u64().ok_or("expires_at_ms must be a number")?),
+    };
+    Ok((key, kind, value, expires_at))
+}
+
+fn read_binary_field(object: &Map<String, Value>, name: &str) -> Result<Option<Vec<u8>>, String> {
+    if let Some(text) = object.get(name) {
+        let text = text.as_str().ok_or(format!("{} must be a string", name))?;
+        return Ok(Some(text.as_bytes().to_vec()));
+    }
+    match object.get(&format!("{}_base64", name)) {
+        Some(encoded) => {
+            let encoded = encoded
+                .as_str()
+                .ok_or(format!("{}_base64 must be a string", name))?;
+            let bytes = BASE64
+                .decode(encoded)
+                .map_err(|_| format!("{}_base64 is not valid base64", name))?;
+            Ok(Some(bytes))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_convert_options() {
+        assert_eq!(Convert::from_args(&args(&["dump", "f"])).unwrap(), None);
+        assert_eq!(
+            Convert::from_args(&args(&["convert", "fedis.aof", "-", "--to", "ndjson"])).unwrap(),
+            Some(Convert {
+                input: PathBuf::from("fedis.aof"),
+                output: None,
+                to: Format::Ndjson
+            })
+        );
+        assert!(Convert::from_args(&args(&["convert", "a", "b"])).is_err());
+        assert!(Convert::from_args(&args(&["convert", "a", "b", "--to", "csv"])).is_err());
+        assert!(Convert::from_args(&args(&["convert", "a", "-", "--to", "rdb"])).is_err());
+    }
+
+    #[test]
+    fn ndjson_lines_round_trip() {
+        let series = timeseries::add(timeseries::new(60_000), 5, 1.5);
+        let entries: [EntryRef; 4] = [
+            (b"plain", ValueType::String, b"hello", Some(42)),
+            (b"\xff\x00", ValueType::String, b"\x01\xfe", None),
+            (b"doc", ValueType::Json, br#"{"a":[1,2]}"#, None),
+            (b"ts", ValueType::TimeSeries, &series, None),
+        ];
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|&(key, kind, value, exp)| ndjson_line(key, kind, value, exp))
+            .collect();
+        assert_eq!(
+            lines[0],
+            r#"{"expires_at_ms":42,"key":"plain","type":"string","value":"hello"}"#
+        );
+        assert_eq!(
+            lines[1],
+            r#"{"key_base64":"/wA=","type":"string","value_base64":"Af4="}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"key":"doc","type":"json","value":{"a":[1,2]}}"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"key":"ts","retention_ms":60000,"samples":[[5,1.5]],"type":"timeseries"}"#
+        );
+        for (line, (key, kind, value, exp)) in lines.iter().zip(entries) {
+            let parsed = parse_ndjson(line).unwrap();
+            assert_eq!(parsed, (key.to_vec(), kind, value.to_vec(), exp));
+        }
+        assert!(parse_ndjson(r#"{"key":"k","type":"hash","value":"v"}"#).is_err());
+        assert!(parse_ndjson(r#"{"type":"string","value":"v"}"#).is_err());
+    }
+
+    #[test]
+    fn converts_between_every_format() {
+        let dir = std::env::temp_dir().join(format!("fedis-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.ndjson");
+        std::fs::write(
+            &source,
+            concat!(
+                r#"{"key":"a","value":"1"}"#,
+                "\n",
+                r#"{"key":"doc","type":"json","value":{"n":1}}"#,
+                "\n",
+                r#"{"key":"t","value":"x","expires_at_ms":99999999999999}"#,
+                "\n",
+                r#"{"key":"old","value":"x","expires_at_ms":1}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        let mut input = source.clone();
+        for (to, name) in [
+            (Format::Aof, "fedis.aof"),
+            (Format::Snapshot, "dump.fdsnp"),
+            (Format::Rdb, "dump.rdb"),
+            (Format::RedisAof, "redis.aof"),
+            (Format::Ndjson, "back.ndjson"),
+        ] {
+            let output = dir.join(name);
+            let convert = Convert {
+                input: input.clone(),
+                output: Some(output.clone()),
+                to,
+            };
+            convert.convert(None).unwrap();
+            let keyspace = Keyspace::read(&output, None).unwrap();
+            let keys: Vec<_> = keyspace.entries.keys().map(|k| k.as_slice()).collect();
+            assert_eq!(keys, [&b"a"[..], b"doc", b"t"], "{:?}", to);
+            assert_eq!(keyspace.entries[&b"doc"[..]].kind, ValueType::Json);
+            assert_eq!(keyspace.entries[&b"t"[..]].expires_at, Some(99999999999999));
+            input = output;
+        }
+        let back = std::fs::read_to_string(&input).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(back.lines().count(), 3);
+    }
+}
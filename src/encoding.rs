@@ -0,0 +1,134 @@
+use std::io::Write;
+
+/// Type of a persisted value. AOF records and snapshot entries write
+/// `type u8 | version u8` in front of every value, so new types and new payload
+/// layouts slot in without changing the framing around them.
+///
+/// Only strings, JSON documents and time series live in the keyspace today; the other tags
+/// are reserved so that files written by a build that has them are rejected by
+/// name instead of misparsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Hash,
+    List,
+    Set,
+    ZSet,
+    Stream,
+    Json,
+    TimeSeries,
+}
+
+impl ValueType {
+    pub fn tag(self) -> u8 {
+        match self {
+            Self::String => 0,
+            Self::Hash => 1,
+            Self::List => 2,
+            Self::Set => 3,
+            Self::ZSet => 4,
+            Self::Stream => 5,
+            Self::Json => 6,
+            Self::TimeSeries => 7,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(Self::String),
+            1 => Ok(Self::Hash),
+            2 => Ok(Self::List),
+            3 => Ok(Self::Set),
+            4 => Ok(Self::ZSet),
+            5 => Ok(Self::Stream),
+            6 => Ok(Self::Json),
+            7 => Ok(Self::TimeSeries),
+            other => Err(format!("unknown value type tag {}", other)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Hash => "hash",
+            Self::List => "list",
+            Self::Set => "set",
+            Self::ZSet => "zset",
+            Self::Stream => "stream",
+            Self::Json => "json",
+            Self::TimeSeries => "timeseries",
+        }
+    }
+}
+
+/// Payload layout version written for strings: the raw bytes.
+pub const STRING_VERSION: u8 = 1;
+/// Payload layout version written for JSON documents: their compact text.
+pub const JSON_VERSION: u8 = 1;
+/// Payload layout version written for time series: the retention and the
+/// packed samples (see `timeseries`).
+pub const TIMESERIES_VERSION: u8 = 1;
+
+/// Writes the header of a value of `kind` in the layout this build writes.
+/// Only kinds the keyspace holds are ever written.
+pub fn write_value_header(out: &mut impl Write, kind: ValueType) -> std::io::Result<()> {
+    let version = match kind {
+        ValueType::Json => JSON_VERSION,
+        ValueType::TimeSeries => TIMESERIES_VERSION,
+        _ => STRING_VERSION,
+    };
+    out.write_all(&[kind.tag(), version])
+}
+
+/// Reads a value header and checks that the keyspace can hold what follows.
+pub fn read_value_header(header: &[u8]) -> Result<ValueType, String> {
+    let [tag, version] = header else {
+        return Err("truncated value header".to_string());
+    };
+    match (ValueType::from_tag(*tag)?, *version) {
+        (ValueType::String, STRING_VERSION) => Ok(ValueType::String),
+        (ValueType::Json, JSON_VERSION) => Ok(ValueType::Json),
+        (ValueType::TimeSeries, TIMESERIES_VERSION) => Ok(ValueType::TimeSeries),
+        (kind @ (ValueType::String | ValueType::Json | ValueType::TimeSeries), version) => {
+            Err(format!(
+                "{} value encoding version {} is newer than this build reads",
+                kind.name(),
+                version
+            ))
+        }
+        (kind, _) => Err(format!(
+            "{} values are not supported by this build",
+            kind.name()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_name_what_cannot_be_loaded() {
+        let mut header = Vec::new();
+        write_value_header(&mut header, ValueType::String).expect("write");
+        assert_eq!(header, vec![0, STRING_VERSION]);
+        assert_eq!(read_value_header(&header), Ok(ValueType::String));
+        header.clear();
+        write_value_header(&mut header, ValueType::Json).expect("write");
+        assert_eq!(read_value_header(&header), Ok(ValueType::Json));
+        header.clear();
+        write_value_header(&mut header, ValueType::TimeSeries).expect("write");
+        assert_eq!(read_value_header(&header), Ok(ValueType::TimeSeries));
+
+        for tag in 0..=7 {
+            assert_eq!(ValueType::from_tag(tag).map(ValueType::tag), Ok(tag));
+        }
+        assert_eq!(
+            read_value_header(&[ValueType::Hash.tag(), 1]),
+            Err("hash values are not supported by this build".to_string())
+        );
+        assert!(read_value_header(&[0, 9]).is_err());
+        assert!(read_value_header(&[42, 1]).is_err());
+        assert!(read_value_header(&[0]).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hkdf::{HKDF_SHA256, Salt};
+
+/// Length of the random salt a file's subkey is derived with.
+pub const SALT_LEN: usize = 32;
+
+/// AES-256-GCM keys for data at rest, indexed by key id. New data is sealed with
+/// the active key; any known key can open old data, which is how keys rotate.
+pub struct Keyring {
+    active: u32,
+    keys: HashMap<u32, LessSafeKey>,
+    /// The raw keys, which per-file subkeys are derived from.
+    secrets: HashMap<u32, Vec<u8>>,
+}
+
+/// A key for one file, derived from a keyring key and a salt the file
+/// stores, so records of different files are never sealed under one key.
+pub struct FileKey {
+    id: u32,
+    salt: [u8; SALT_LEN],
+    key: LessSafeKey,
+}
+
+impl Keyring {
+    /// Parses `id:hexkey` entries separated by commas or newlines. The active key is
+    /// `active` when given, otherwise the last entry.
+    pub fn parse(spec: &str, active: Option<u32>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut keys = HashMap::new();
+        let mut secrets = HashMap::new();
+        let mut last = None;
+        for entry in spec
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|v| !v.is_empty() && !v.starts_with('#'))
+        {
+            let (id, hex) = entry
+                .split_once(':')
+                .ok_or("encryption keys must be written as id:hexkey")?;
+            let id: u32 = id
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid encryption key id '{}'", id.trim()))?;
+            let bytes = decode_hex(hex.trim())
+                .filter(|b| b.len() == 32)
+                .ok_or_else(|| format!("encryption key {} must be 64 hex characters", id))?;
+            let key = UnboundKey::new(&AES_256_GCM, &bytes)
+                .map_err(|_| format!("invalid encryption key {}", id))?;
+            keys.insert(id, LessSafeKey::new(key));
+            secrets.insert(id, bytes);
+            last = Some(id);
+        }
+        let active = active.or(last).ok_or("no encryption keys configured")?;
+        if !keys.contains_key(&active) {
+            return Err(format!("active encryption key {} is not configured", active).into());
+        }
+        Ok(Self {
+            active,
+            keys,
+            secrets,
+        })
+    }
+
+    pub fn active(&self) -> u32 {
+        self.active
+    }
+
+    /// A subkey of the active key for a new file, under a fresh salt.
+    pub fn new_file_key(&self) -> Result<FileKey, String> {
+        let mut salt = [0_u8; SALT_LEN];
+        getrandom::fill(&mut salt).map_err(|e| e.to_string())?;
+        self.file_key(self.active, &salt)
+    }
+
+    /// The subkey a file written with key `id` and `salt` was sealed under.
+    pub fn file_key(&self, id: u32, salt: &[u8]) -> Result<FileKey, String> {
+        let secret = self
+            .secrets
+            .get(&id)
+            .ok_or_else(|| format!("encryption key {} is not configured", id))?;
+        let salt: [u8; SALT_LEN] = salt
+            .try_into()
+            .map_err(|_| "bad encryption salt".to_string())?;
+        let prk = Salt::new(HKDF_SHA256, &salt).extract(secret);
+        let okm = prk
+            .expand(&[b"fedis file key"], &AES_256_GCM)
+            .map_err(|_| "key derivation failed".to_string())?;
+        Ok(FileKey {
+            id,
+            salt,
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+        })
+    }
+
+    /// Returns `key_id (u32 BE) | nonce | ciphertext+tag`.
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::with_capacity(4 + NONCE_LEN + plaintext.len() + 16);
+        out.extend_from_slice(&self.active.to_be_bytes());
+        seal_into(&self.keys[&self.active], plaintext, aad, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        if sealed.len() < 4 + NONCE_LEN {
+            return Err("truncated encrypted block".to_string());
+        }
+        let id = u32::from_be_bytes(sealed[..4].try_into().map_err(|_| "bad key id")?);
+        let key = self
+            .keys
+            .get(&id)
+            .ok_or_else(|| format!("encryption key {} is not configured", id))?;
+        open(key, &sealed[4..], aad).ok_or_else(|| format!("decryption with key {} failed", id))
+    }
+}
+
+impl FileKey {
+    /// The keyring key this subkey was derived from.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn salt(&self) -> &[u8; SALT_LEN] {
+        &self.salt
+    }
+
+    /// Returns `nonce | ciphertext+tag`.
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::with_capacity(NONCE_LEN + plaintext.len() + 16);
+        seal_into(&self.key, plaintext, aad, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        open(&self.key, sealed, aad)
+            .ok_or_else(|| format!("decryption with a subkey of key {} failed", self.id))
+    }
+}
+
+/// Appends a random nonce and the sealed `plaintext` to `out`.
+fn seal_into(
+    key: &LessSafeKey,
+    plaintext: &[u8],
+    aad: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    let mut nonce = [0_u8; NONCE_LEN];
+    getrandom::fill(&mut nonce).map_err(|e| e.to_string())?;
+    out.extend_from_slice(&nonce);
+    let mut body = plaintext.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(aad),
+        &mut body,
+    )
+    .map_err(|_| "encryption failed".to_string())?;
+    out.extend_from_slice(&body);
+    Ok(())
+}
+
+/// Opens `nonce | ciphertext+tag`; `None` when it does not authenticate.
+fn open(key: &LessSafeKey, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    let nonce = Nonce::try_assume_unique_for_key(sealed.get(..NONCE_LEN)?).ok()?;
+    let mut body = sealed[NONCE_LEN..].to_vec();
+    let plain_len = key
+        .open_in_place(nonce, Aad::from(aad), &mut body)
+        .ok()?
+        .len();
+    body.truncate(plain_len);
+    Some(body)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_1: &str = "1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_2: &str = "2:1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    #[test]
+    fn rotated_keyring_opens_data_sealed_with_retired_key() {
+        let old = Keyring::parse(KEY_1, None).expect("old keyring");
+        let sealed = old.seal(b"secret", b"aad").expect("seal");
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+
+        let rotated = Keyring::parse(&format!("{}\n{}", KEY_1, KEY_2), None).expect("keyring");
+        assert_eq!(rotated.open(&sealed, b"aad"), Ok(b"secret".to_vec()));
+        assert_eq!(
+            &rotated.seal(b"x", b"aad").expect("seal")[..4],
+            &2_u32.to_be_bytes()
+        );
+        assert!(rotated.open(&sealed, b"other").is_err());
+
+        let without_old = Keyring::parse(KEY_2, None).expect("keyring");
+        assert!(without_old.open(&sealed, b"aad").is_err());
+    }
+
+    #[test]
+    fn file_keys_differ_per_salt_and_rederive_from_it() {
+        let keyring = Keyring::parse(&format!("{}\n{}", KEY_1, KEY_2), Some(1)).expect("keyring");
+        let first = keyring.new_file_key().expect("file key");
+        let second = keyring.new_file_key().expect("file key");
+        assert_eq!(first.id(), 1);
+        assert_ne!(first.salt(), second.salt());
+
+        let sealed = first.seal(b"record", b"at 6").expect("seal");
+        assert!(second.open(&sealed, b"at 6").is_err());
+        assert!(first.open(&sealed, b"at 7").is_err());
+        let again = keyring.file_key(1, first.salt()).expect("rederive");
+        assert_eq!(again.open(&sealed, b"at 6"), Ok(b"record".to_vec()));
+        assert!(keyring.file_key(3, first.salt()).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! `EXPORT`: the keyspace written out as NDJSON or CSV, one line per key,
+//! for analytics and for checking backups against the live data.
+
+use std::io::Write;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::atomic_file::AtomicFile;
+use crate::convert::ndjson_line;
+use crate::encoding::ValueType;
+use crate::store::{EntryRef, glob_match};
+use crate::timeseries::Series;
+
+pub(crate) const CSV_HEADER: &str = "key,type,expires_at_ms,value";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The objects `fedis convert --to ndjson` writes, which it reads back.
+    Ndjson,
+    /// `key,type,expires_at_ms,value` with a header row.
+    Csv,
+}
+
+/// Writes the entries whose keys match `pattern` to `path`, replacing it
+/// only once complete. Returns the number of keys written.
+pub(crate) fn write_export<'a>(
+    path: &Path,
+    entries: impl Iterator<Item = EntryRef<'a>>,
+    format: ExportFormat,
+    pattern: Option<&[u8]>,
+) -> std::io::Result<usize> {
+    let mut out = AtomicFile::create(path, "export.tmp")?;
+    if format == ExportFormat::Csv {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+    let mut written = 0;
+    for (key, kind, value, expires_at) in entries {
+        if pattern.is_some_and(|pattern| !glob_match(pattern, key)) {
+            continue;
+        }
+        let line = match format {
+            ExportFormat::Ndjson => ndjson_line(key, kind, value, expires_at),
+            ExportFormat::Csv => csv_line(key, kind, value, expires_at),
+        };
+        writeln!(out, "{}", line)?;
+        written += 1;
+    }
+    out.commit()?;
+    Ok(written)
+}
+
+/// One CSV row. Keys and values that are not UTF-8 are written with
+/// `\xNN` escapes, JSON documents as their text and a time series as its
+/// `[timestamp, value]` pairs in JSON; an empty `expires_at_ms` means the
+/// key does not expire.
+pub(crate) fn csv_line(
+    key: &[u8],
+    kind: ValueType,
+    value: &[u8],
+    expires_at: Option<u64>,
+) -> String {
+    let value = match kind {
+        ValueType::TimeSeries => series_json(value),
+        _ => text(value),
+    };
+    [
+        text(key),
+        kind.name().to_string(),
+        expires_at.map(|at| at.to_string()).unwrap_or_default(),
+        value,
+    ]
+    .iter()
+    .map(|field| csv_field(field))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// A time series' samples as `[[timestamp, value], ...]`.
+pub(crate) fn series_json(value: &[u8]) -> String {
+    let samples: Vec<_> = Series::new(value)
+        .range(0, u64::MAX)
+        .map(|(timestamp, sample)| json!([timestamp, sample]))
+        .collect();
+    serde_json::Value::Array(samples).to_string()
+}
+
+pub(crate) fn text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.escape_ascii().to_string(),
+    }
+}
+
+/// Quotes a field as RFC 4180 asks when it holds a comma, a quote or a line
+/// break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_quote_and_escape() {
+        assert_eq!(
+            csv_line(b"plain", ValueType::String, b"v", None),
+            "plain,string,,v"
+        );
+        assert_eq!(
+            csv_line(b"a,b", ValueType::String, b"say \"hi\"\n", Some(42)),
+            "\"a,b\",string,42,\"say \"\"hi\"\"\n\""
+        );
+        assert_eq!(
+            csv_line(b"\xff", ValueType::Json, br#"{"a":1}"#, None),
+            "\\xff,json,,\"{\"\"a\"\":1}\""
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! The gRPC service from `proto/fedis.proto`, on its own port. Every call
+//! runs the Redis command it stands for through the `CommandExecutor`, so
+//! authentication, ACLs, read-only mode, `maxmemory`, the AOF and the
+//! command statistics apply as they do to RESP clients.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use base64::Engine;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::auth::SessionAuth;
+use crate::command::{CommandExecutor, SessionAction};
+use crate::protocol::RespValue;
+
+pub mod proto {
+    tonic::include_proto!("fedis.v1");
+}
+
+use proto::fedis_server::{Fedis, FedisServer};
+use proto::{
+    DelRequest, DelResponse, ExpireRequest, ExpireResponse, GetRequest, GetResponse, Message,
+    ScanRequest, ScanResponse, SetRequest, SetResponse, SubscribeRequest,
+};
+
+/// Messages a `Subscribe` stream may queue for a slow client.
+const STREAM_QUEUE: usize = 256;
+
+/// Serves gRPC on `listener` until the task is aborted.
+pub(crate) async fn serve_grpc(listener: TcpListener, executor: Arc<CommandExecutor>) {
+    let addr = listener.local_addr().ok();
+    info!(grpc_addr = ?addr, "gRPC listener started");
+    let result = tonic::transport::Server::builder()
+        .add_service(FedisServer::new(GrpcService { executor }))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await;
+    if let Err(e) = result {
+        warn!(error = %e, "gRPC listener failed");
+    }
+}
+
+struct GrpcService {
+    executor: Arc<CommandExecutor>,
+}
+
+impl GrpcService {
+    /// A session for one call, logged in with the request's `authorization`
+    /// metadata: `Basic` user and password, or `Bearer` with a password or,
+    /// in non_redis_mode, a JWT.
+    async fn session<T>(&self, request: &Request<T>) -> Result<SessionAuth, Status> {
+        let mut session = SessionAuth {
+            peer_addr: request
+                .remote_addr()
+                .map(|addr: SocketAddr| addr.to_string()),
+            ..SessionAuth::default()
+        };
+        let Some(header) = request.metadata().get("authorization") else {
+            return Ok(session);
+        };
+        let header = header
+            .to_str()
+            .map_err(|_| Status::unauthenticated("invalid authorization metadata"))?;
+        let mut auth = vec![b"AUTH".to_vec()];
+        if let Some(token) = header.strip_prefix("Bearer ") {
+            auth.push(token.trim().as_bytes().to_vec());
+        } else if let Some(encoded) = header.strip_prefix("Basic ") {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .map_err(|_| Status::unauthenticated("invalid basic credentials"))?;
+            let Some(colon) = decoded.iter().position(|b| *b == b':') else {
+                return Err(Status::unauthenticated("invalid basic credentials"));
+            };
+            auth.push(decoded[..colon].to_vec());
+            auth.push(decoded[colon + 1..].to_vec());
+        } else {
+            return Err(Status::unauthenticated(
+                "authorization must be Basic or Bearer",
+            ));
+        }
+        self.call(&mut session, auth).await?;
+        Ok(session)
+    }
+
+    /// Runs one command, recording it like a client's, and turns an error
+    /// reply into a status.
+    async fn call(
+        &self,
+        session: &mut SessionAuth,
+        args: Vec<Vec<u8>>,
+    ) -> Result<(RespValue, SessionAction), Status> {
+        let command = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let started = Instant::now();
+        let (reply, action) = self.executor.execute(args, session).await;
+        let elapsed_usec = started.elapsed().as_micros() as u64;
+        self.executor.record_command_stats(&command, elapsed_usec);
+        self.executor.record_command_outcome(
+            &command,
+            session,
+            matches!(reply, RespValue::Error(_)),
+            elapsed_usec,
+        );
+        match reply {
+            RespValue::Error(message) => {
+                self.executor.record_error_reply(&command, &message);
+                Err(status(message))
+            }
+            reply => Ok((reply, action)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Fedis for GrpcService {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let mut session = self.session(&request).await?;
+        let key = request.into_inner().key;
+        let (reply, _) = self.call(&mut session, vec![b"GET".to_vec(), key]).await?;
+        let RespValue::Bulk(value) = reply else {
+            return Err(unexpected());
+        };
+        Ok(Response::new(GetResponse {
+            value: value.map(Vec::from),
+        }))
+    }
+
+    async fn set(&self, request: Request<SetRequest>) -> Result<Response<SetResponse>, Status> {
+        let mut session = self.session(&request).await?;
+        let request = request.into_inner();
+        let mut args = vec![b"SET".to_vec(), request.key, request.value];
+        if request.ttl_ms > 0 {
+            args.push(b"PX".to_vec());
+            args.push(request.ttl_ms.to_string().into_bytes());
+        }
+        if request.nx {
+            args.push(b"NX".to_vec());
+        }
+        if request.xx {
+            args.push(b"XX".to_vec());
+        }
+        let (reply, _) = self.call(&mut session, args).await?;
+        Ok(Response::new(SetResponse {
+            stored: !matches!(reply, RespValue::Bulk(None)),
+        }))
+    }
+
+    async fn del(&self, request: Request<DelRequest>) -> Result<Response<DelResponse>, Status> {
+        let mut session = self.session(&request).await?;
+        let keys = request.into_inner().keys;
+        if keys.is_empty() {
+            return Ok(Response::new(DelResponse { deleted: 0 }));
+        }
+        let mut args = vec![b"DEL".to_vec()];
+        args.extend(keys);
+        let (reply, _) = self.call(&mut session, args).await?;
+        let RespValue::Integer(deleted) = reply else {
+            return Err(unexpected());
+        };
+        Ok(Response::new(DelResponse { deleted }))
+    }
+
+    async fn expire(
+        &self,
+        request: Request<ExpireRequest>,
+    ) -> Result<Response<ExpireResponse>, Status> {
+        let mut session = self.session(&request).await?;
+        let request = request.into_inner();
+        let args = vec![
+            b"PEXPIRE".to_vec(),
+            request.key,
+            request.ttl_ms.to_string().into_bytes(),
+        ];
+        let (reply, _) = self.call(&mut session, args).await?;
+        let RespValue::Integer(updated) = reply else {
+            return Err(unexpected());
+        };
+        Ok(Response::new(ExpireResponse {
+            updated: updated == 1,
+        }))
+    }
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<ScanResponse>, Status> {
+        let mut session = self.session(&request).await?;
+        let request = request.into_inner();
+        let mut args = vec![b"SCAN".to_vec(), request.cursor.to_string().into_bytes()];
+        if !request.pattern.is_empty() {
+            args.push(b"MATCH".to_vec());
+            args.push(request.pattern.into_bytes());
+        }
+        if request.count > 0 {
+            args.push(b"COUNT".to_vec());
+            args.push(request.count.to_string().into_bytes());
+        }
+        let (reply, _) = self.call(&mut session, args).await?;
+        let RespValue::Array(mut parts) = reply else {
+            return Err(unexpected());
+        };
+        let (Some(RespValue::Array(keys)), Some(RespValue::Bulk(Some(cursor)))) =
+            (parts.pop(), parts.pop())
+        else {
+            return Err(unexpected());
+        };
+        let cursor = std::str::from_utf8(&cursor)
+            .ok()
+            .and_then(|cursor| cursor.parse().ok())
+            .ok_or_else(unexpected)?;
+        let keys = keys
+            .into_iter()
+            .filter_map(|key| match key {
+                RespValue::Bulk(Some(key)) => Some(Vec::from(key)),
+                _ => None,
+            })
+            .collect();
+        Ok(Response::new(ScanResponse { cursor, keys }))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<Message, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let mut session = self.session(&request).await?;
+        let channels = request.into_inner().channels;
+        if channels.is_empty() {
+            return Err(Status::invalid_argument("no channels to subscribe to"));
+        }
+        let mut args = vec![b"SUBSCRIBE".to_vec()];
+        args.extend(channels.iter().cloned());
+        // Run for its checks: SUBSCRIBE itself only switches a connection
+        // into subscriber mode.
+        self.call(&mut session, args).await?;
+
+        let mut subscriber = self.executor.pubsub().subscriber(channels);
+        let (tx, rx) = mpsc::channel(STREAM_QUEUE);
+        tokio::spawn(async move {
+            loop {
+                let message = tokio::select! {
+                    message = subscriber.recv() => message,
+                    _ = tx.closed() => break,
+                };
+                let item = match message {
+                    Ok(Some((channel, payload))) => Ok(Message {
+                        channel: Vec::from(channel),
+                        payload: Vec::from(payload),
+                    }),
+                    Ok(None) => break,
+                    Err(e) => Err(Status::resource_exhausted(e)),
+                };
+                let failed = item.is_err();
+                if tx.send(item).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// The status for an error reply, by its Redis error code.
+fn status(message: String) -> Status {
+    let code = message.split_whitespace().next().unwrap_or_default();
+    match code {
+        "NOAUTH" | "WRONGPASS" => Status::unauthenticated(message),
+        "NOPERM" => Status::permission_denied(message),
+        "WRONGTYPE" | "READONLY" => Status::failed_precondition(message),
+        "OOM" => Status::resource_exhausted(message),
+        "MOVED" | "ASK" | "TRYAGAIN" | "CLUSTERDOWN" => Status::unavailable(message),
+        _ => Status::invalid_argument(message),
+    }
+}
+
+fn unexpected() -> Status {
+    Status::internal("unexpected reply")
+}
@@ -0,0 +1,452 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use serde_json::{Value, json};
+
+use crate::convert::parse_ndjson;
+use crate::encoding::ValueType;
+use crate::encryption::Keyring;
+use crate::persistence::{Aof, LogRecord};
+use crate::rdb::read_rdb;
+use crate::store::{EntryRef, SNAP_MAGIC_PREFIX, glob_match, read_snapshot};
+use crate::timeseries::{self, Series};
+
+const DUMP_USAGE: &str = "usage: fedis dump <path> [--json] [--top n]";
+const KEYS_USAGE: &str = "usage: fedis keys <path> [--match pattern] [--json]";
+
+/// An offline look into a snapshot, AOF, RDB file or NDJSON export requested on the command line
+/// instead of starting the server, like `redis-rdb-tools`: the file is
+/// replayed in memory and what it would load is printed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Inspect {
+    /// `dump <path> [--json] [--top n]`: counts, sizes, a histogram by type
+    /// and the largest keys.
+    Dump {
+        path: PathBuf,
+        json: bool,
+        top: usize,
+    },
+    /// `keys <path> [--match pattern] [--json]`: every key with its type,
+    /// size and TTL.
+    Keys {
+        path: PathBuf,
+        json: bool,
+        pattern: Option<String>,
+    },
+}
+
+impl Inspect {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let usage = match args.first().map(String::as_str) {
+            Some("dump") => DUMP_USAGE,
+            Some("keys") => KEYS_USAGE,
+            _ => return Ok(None),
+        };
+        let path = args
+            .get(1)
+            .filter(|arg| !arg.starts_with("--"))
+            .map(PathBuf::from)
+            .ok_or(usage)?;
+        let mut json = false;
+        let mut top = 10;
+        let mut pattern = None;
+        let mut rest = args[2..].iter();
+        while let Some(flag) = rest.next() {
+            match (flag.as_str(), usage) {
+                ("--json", _) => json = true,
+                ("--top", DUMP_USAGE) => {
+                    top = rest
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or("--top needs a number")?
+                }
+                ("--match", KEYS_USAGE) => pattern = Some(rest.next().ok_or(usage)?.clone()),
+                _ => return Err(usage.into()),
+            }
+        }
+        Ok(Some(match usage {
+            DUMP_USAGE => Self::Dump { path, json, top },
+            _ => Self::Keys {
+                path,
+                json,
+                pattern,
+            },
+        }))
+    }
+
+    /// Prints the report and returns the process exit code: 0 when the file
+    /// could be read, 1 when it could not.
+    pub fn run(&self, keyring: Option<&Keyring>) -> i32 {
+        let path = match self {
+            Self::Dump { path, .. } | Self::Keys { path, .. } => path,
+        };
+        let keyspace = match Keyspace::read(path, keyring) {
+            Ok(keyspace) => keyspace,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return 1;
+            }
+        };
+        keyspace.warn_incomplete();
+        match self {
+            Self::Dump { json, top, .. } => keyspace.print_summary(*json, *top),
+            Self::Keys { json, pattern, .. } => keyspace.print_keys(*json, pattern.as_deref()),
+        }
+        0
+    }
+}
+
+/// What a file would load: keys already past their expiry are counted and
+/// left out, as loading leaves them out.
+pub(crate) struct Keyspace {
+    path: PathBuf,
+    pub format: &'static str,
+    file_len: u64,
+    pub entries: BTreeMap<Vec<u8>, Entry>,
+    expired: usize,
+    /// RDB keys of types fedis does not hold, or in another database.
+    pub skipped: usize,
+    pub first_bad: Option<(usize, String)>,
+    now_ms: u64,
+}
+
+pub(crate) struct Entry {
+    pub kind: ValueType,
+    pub value: Bytes,
+    pub expires_at: Option<u64>,
+}
+
+impl Keyspace {
+    /// Reads a snapshot, an AOF in either format, a Redis RDB file or an
+    /// NDJSON export, told apart by their first bytes.
+    pub fn read(
+        path: &Path,
+        keyring: Option<&Keyring>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let file_len = std::fs::metadata(path)?.len();
+        let mut magic = Vec::new();
+        std::fs::File::open(path)?
+            .take(SNAP_MAGIC_PREFIX.len() as u64)
+            .read_to_end(&mut magic)?;
+        let mut entries = BTreeMap::new();
+        let mut skipped = 0;
+        let mut first_bad = None;
+        let mut insert = |key, kind, value: Bytes, expires_at| {
+            let entry = Entry {
+                kind,
+                value,
+                expires_at,
+            };
+            entries.insert(key, entry);
+        };
+        let format = if magic == SNAP_MAGIC_PREFIX {
+            for (key, kind, value, expires_at) in read_snapshot(path, keyring)? {
+                insert(key, kind, value.into(), expires_at);
+            }
+            "snapshot"
+        } else if magic == b"REDIS" {
+            let dump = read_rdb(path)?;
+            skipped = dump.skipped;
+            for (key, kind, value, expires_at) in dump.entries {
+                insert(key, kind, value.into(), expires_at);
+            }
+            "RDB"
+        } else if magic.first() == Some(&b'{') {
+            let file = std::io::BufReader::new(std::fs::File::open(path)?);
+            for (idx, line) in file.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (key, kind, value, expires_at) =
+                    parse_ndjson(&line).map_err(|e| format!("line {}: {}", idx + 1, e))?;
+                insert(key, kind, value.into(), expires_at);
+            }
+            "NDJSON"
+        } else {
+            let log = Aof::read_offline(path, keyring)?;
+            first_bad = log.first_bad;
+            for record in log.records {
+                replay(&mut entries, record);
+            }
+            "AOF"
+        };
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let before = entries.len();
+        entries.retain(|_, entry| entry.expires_at.is_none_or(|at| at > now_ms));
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            file_len,
+            expired: before - entries.len(),
+            entries,
+            skipped,
+            first_bad,
+            now_ms,
+        })
+    }
+
+    /// Warns on stderr about what was not read.
+    pub fn warn_incomplete(&self) {
+        if let Some((offset, reason)) = &self.first_bad {
+            eprintln!(
+                "warning: AOF is invalid from offset {} ({}); using the records before it",
+                offset, reason
+            );
+        }
+        if self.skipped > 0 {
+            eprintln!(
+                "warning: skipped {} RDB keys of types fedis does not hold or in databases other than 0",
+                self.skipped
+            );
+        }
+    }
+
+    /// The entries as the snapshot, log and RDB writers take them.
+    pub fn entry_refs(&self) -> impl Iterator<Item = EntryRef<'_>> + Clone {
+        self.entries.iter().map(|(key, entry)| {
+            (
+                key.as_slice(),
+                entry.kind,
+                &entry.value[..],
+                entry.expires_at,
+            )
+        })
+    }
+
+    fn ttl_ms(&self, entry: &Entry) -> i64 {
+        entry
+            .expires_at
+            .map_or(-1, |at| at.saturating_sub(self.now_ms) as i64)
+    }
+
+    fn key_json(&self, key: &[u8], entry: &Entry) -> Value {
+        json!({
+            "key": String::from_utf8_lossy(key),
+            "type": entry.kind.name(),
+            "bytes": entry.value.len(),
+            "ttl_ms": self.ttl_ms(entry),
+        })
+    }
+
+    fn print_summary(&self, as_json: bool, top: usize) {
+        let mut types: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for entry in self.entries.values() {
+            let (keys, bytes) = types.entry(entry.kind.name()).or_default();
+            *keys += 1;
+            *bytes += entry.value.len();
+        }
+        let mut largest: Vec<_> = self.entries.iter().collect();
+        largest.sort_by(|a, b| b.1.value.len().cmp(&a.1.value.len()).then(a.0.cmp(b.0)));
+        largest.truncate(top);
+        let expiring = self
+            .entries
+            .values()
+            .filter(|entry| entry.expires_at.is_some())
+            .count();
+        let key_bytes: usize = self.entries.keys().map(Vec::len).sum();
+        let value_bytes: usize = self.entries.values().map(|entry| entry.value.len()).sum();
+
+        if as_json {
+            let report = json!({
+                "path": self.path.display().to_string(),
+                "format": self.format,
+                "file_bytes": self.file_len,
+                "keys": self.entries.len(),
+                "expiring": expiring,
+                "expired": self.expired,
+                "key_bytes": key_bytes,
+                "value_bytes": value_bytes,
+                "types": types
+                    .iter()
+                    .map(|(name, (keys, bytes))| {
+                        (name.to_string(), json!({"keys": keys, "bytes": bytes}))
+                    })
+                    .collect::<serde_json::Map<_, _>>(),
+                "largest": largest
+                    .iter()
+                    .map(|(key, entry)| self.key_json(key, entry))
+                    .collect::<Vec<_>>(),
+                "first_invalid_offset": self.first_bad.as_ref().map(|(offset, _)| offset),
+            });
+            println!("{}", report);
+            return;
+        }
+        println!(
+            "{} {}: {} bytes",
+            self.format,
+            self.path.display(),
+            self.file_len
+        );
+        println!(
+            "{} keys ({} with an expiry, {} expired and left out), {} key bytes, {} value bytes",
+            self.entries.len(),
+            expiring,
+            self.expired,
+            key_bytes,
+            value_bytes
+        );
+        if !types.is_empty() {
+            println!("by type:");
+        }
+        for (name, (keys, bytes)) in &types {
+            println!("  {:<10} {:>10} keys {:>14} bytes", name, keys, bytes);
+        }
+        if !largest.is_empty() {
+            println!("largest keys:");
+        }
+        for (key, entry) in largest {
+            println!(
+                "  {:>14} bytes  {:<10} {}",
+                entry.value.len(),
+                entry.kind.name(),
+                key.escape_ascii()
+            );
+        }
+    }
+
+    fn print_keys(&self, as_json: bool, pattern: Option<&str>) {
+        let matching = self
+            .entries
+            .iter()
+            .filter(|(key, _)| pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), key)));
+        for (key, entry) in matching {
+            if as_json {
+                println!("{}", self.key_json(key, entry));
+            } else {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    key.escape_ascii(),
+                    entry.kind.name(),
+                    entry.value.len(),
+                    self.ttl_ms(entry)
+                );
+            }
+        }
+    }
+}
+
+/// Applies one record as the store's replay does.
+fn replay(entries: &mut BTreeMap<Vec<u8>, Entry>, record: LogRecord) {
+    match record {
+        LogRecord::Set {
+            key,
+            kind,
+            value,
+            expires_at,
+        } => {
+            entries.insert(
+                key,
+                Entry {
+                    kind,
+                    value,
+                    expires_at,
+                },
+            );
+        }
+        LogRecord::Del { key } => {
+            entries.remove(&key);
+        }
+        LogRecord::Expire { key, expires_at } => {
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.expires_at = Some(expires_at);
+            }
+        }
+        LogRecord::Persist { key } => {
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.expires_at = None;
+            }
+        }
+        LogRecord::TsAdd {
+            key,
+            timestamp,
+            value,
+        } => {
+            if let Some(entry) = entries.get_mut(&key)
+                && entry.kind == ValueType::TimeSeries
+                && Series::new(&entry.value).check(timestamp).is_ok()
+            {
+                entry.value = timeseries::add(std::mem::take(&mut entry.value), timestamp, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_inspect_modes() {
+        assert_eq!(Inspect::from_args(&args(&["cli"])).unwrap(), None);
+        assert_eq!(
+            Inspect::from_args(&args(&["dump", "fedis.aof", "--top", "3", "--json"])).unwrap(),
+            Some(Inspect::Dump {
+                path: PathBuf::from("fedis.aof"),
+                json: true,
+                top: 3
+            })
+        );
+        assert_eq!(
+            Inspect::from_args(&args(&["keys", "dump.fdsnp", "--match", "user:*"])).unwrap(),
+            Some(Inspect::Keys {
+                path: PathBuf::from("dump.fdsnp"),
+                json: false,
+                pattern: Some("user:*".to_string())
+            })
+        );
+        assert!(Inspect::from_args(&args(&["dump"])).is_err());
+        assert!(Inspect::from_args(&args(&["dump", "--json"])).is_err());
+        assert!(Inspect::from_args(&args(&["dump", "f", "--match", "*"])).is_err());
+        assert!(Inspect::from_args(&args(&["keys", "f", "--top", "1"])).is_err());
+    }
+
+    #[test]
+    fn replays_an_aof_into_what_it_would_load() {
+        let path = std::env::temp_dir().join(format!("fedis-inspect-{}.aof", std::process::id()));
+        let command = |args: &[&str]| {
+            let mut out = format!("*{}\r\n", args.len());
+            for arg in args {
+                out.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+            }
+            out
+        };
+        let log = [
+            command(&["SET", "a", "1"]),
+            command(&["SET", "gone", "x"]),
+            command(&["DEL", "gone"]),
+            command(&["SET", "old", "x", "PXAT", "1"]),
+            command(&["JSON.SET", "doc", "$", r#"{"n":1}"#]),
+            command(&["SET", "b", "hello", "PXAT", "99999999999999"]),
+            command(&["PERSIST", "b"]),
+            command(&["TS.CREATE", "ts", "RETENTION", "0"]),
+            command(&["TS.ADD", "ts", "1", "2.5"]),
+        ]
+        .concat();
+        std::fs::write(&path, log).unwrap();
+        let keyspace = Keyspace::read(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(keyspace.format, "AOF");
+        assert_eq!(keyspace.expired, 1);
+        assert!(keyspace.first_bad.is_none());
+        let keys: Vec<_> = keyspace.entries.keys().map(|k| k.as_slice()).collect();
+        assert_eq!(keys, [&b"a"[..], b"b", b"doc", b"ts"]);
+        let b = &keyspace.entries[&b"b"[..]];
+        assert_eq!((b.value.len(), keyspace.ttl_ms(b)), (5, -1));
+        assert_eq!(keyspace.entries[&b"doc"[..]].kind, ValueType::Json);
+        let ts = &keyspace.entries[&b"ts"[..]];
+        assert_eq!(Series::new(&ts.value).last(), Some((1, 2.5)));
+    }
+}
@@ -0,0 +1,854 @@
+use serde_json::Value as JsonValue;
+
+/// A failed JSON command, as the reply it turns into.
+#[derive(Debug, PartialEq)]
+pub enum JsonError {
+    /// The key holds a value that is not a JSON document.
+    WrongType,
+    /// A finished `ERR ...` reply.
+    Reply(String),
+    /// Writing the change to the log failed.
+    Internal(String),
+}
+
+impl JsonError {
+    pub fn reply(&self) -> String {
+        match self {
+            JsonError::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            }
+            JsonError::Reply(message) => message.clone(),
+            JsonError::Internal(e) => format!("ERR internal: {}", e),
+        }
+    }
+}
+
+/// One step from a value to a child: an object member or an array element.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A concrete place in a document, as the segments leading to it.
+pub type Location = Vec<Segment>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Wildcard,
+    /// `[a,b,...]`: every listed selector, in order.
+    Union(Vec<Selector>),
+    Filter(Box<Filter>),
+    /// `..sel`: `sel` applied to the value and everything below it.
+    Descendant(Box<Selector>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    /// True when the operand selects anything.
+    Exists(Operand),
+    Compare(Operand, Comparison, Operand),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    /// `@...`, relative to the element being filtered.
+    Current(Vec<Selector>),
+    /// `$...`, from the document root.
+    Root(Vec<Selector>),
+    Literal(JsonValue),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A path as the JSON commands take it. Paths starting with `$` are
+/// JSONPath and answer with every match; the older `.a.b` form (RedisJSON's
+/// legacy paths) names a single value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonPath {
+    raw: String,
+    selectors: Vec<Selector>,
+    legacy: bool,
+}
+
+impl JsonPath {
+    /// The whole document, as commands default to when no path is given.
+    pub fn root() -> Self {
+        Self {
+            raw: ".".to_string(),
+            selectors: Vec::new(),
+            legacy: true,
+        }
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<Self, JsonError> {
+        let text = std::str::from_utf8(raw)
+            .map_err(|_| JsonError::Reply("ERR path is not valid UTF-8".to_string()))?;
+        let syntax = |reason: String| {
+            JsonError::Reply(format!("ERR invalid JSONPath '{}': {}", text, reason))
+        };
+        let (legacy, rest) = match text.strip_prefix('$') {
+            Some(rest) => (false, rest.to_string()),
+            None if text == "." => (true, String::new()),
+            None if text.starts_with('.') || text.starts_with('[') => (true, text.to_string()),
+            None => (true, format!(".{}", text)),
+        };
+        let mut parser = Parser::new(&rest);
+        let selectors = parser.selectors().map_err(syntax)?;
+        if !parser.at_end() {
+            return Err(syntax(format!("unexpected '{}'", parser.rest())));
+        }
+        Ok(Self {
+            raw: text.to_string(),
+            selectors,
+            legacy,
+        })
+    }
+
+    /// Legacy paths reply with one value rather than an array of matches.
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.selectors.is_empty()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// `ERR Path '...' does not exist`, for legacy paths that match nothing.
+    pub fn missing(&self) -> JsonError {
+        JsonError::Reply(format!("ERR Path '{}' does not exist", self.raw))
+    }
+
+    /// Where the path matches in `root`, in document order.
+    pub fn locate(&self, root: &JsonValue) -> Vec<Location> {
+        let mut out = Vec::new();
+        walk(&self.selectors, root, root, &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// The values the path matches in `root`, in document order.
+    pub fn select<'a>(&self, root: &'a JsonValue) -> Vec<&'a JsonValue> {
+        self.locate(root)
+            .iter()
+            .filter_map(|location| get(root, location))
+            .collect()
+    }
+
+    /// The path of the parent and the member name, when the path ends in a
+    /// plain `.name`: where `JSON.SET` may add a missing member.
+    pub fn parent_and_key(&self) -> Option<(JsonPath, &str)> {
+        let (last, parent) = self.selectors.split_last()?;
+        let Selector::Name(name) = last else {
+            return None;
+        };
+        Some((
+            JsonPath {
+                raw: self.raw.clone(),
+                selectors: parent.to_vec(),
+                legacy: self.legacy,
+            },
+            name,
+        ))
+    }
+}
+
+pub fn get<'a>(root: &'a JsonValue, location: &[Segment]) -> Option<&'a JsonValue> {
+    location
+        .iter()
+        .try_fold(root, |value, segment| match (segment, value) {
+            (Segment::Key(key), JsonValue::Object(map)) => map.get(key),
+            (Segment::Index(idx), JsonValue::Array(items)) => items.get(*idx),
+            _ => None,
+        })
+}
+
+pub fn get_mut<'a>(root: &'a mut JsonValue, location: &[Segment]) -> Option<&'a mut JsonValue> {
+    location
+        .iter()
+        .try_fold(root, |value, segment| match (segment, value) {
+            (Segment::Key(key), JsonValue::Object(map)) => map.get_mut(key),
+            (Segment::Index(idx), JsonValue::Array(items)) => items.get_mut(*idx),
+            _ => None,
+        })
+}
+
+/// Removes every location from `root`, returning how many went. Locations
+/// below another removed one count only once.
+pub fn remove(root: &mut JsonValue, mut locations: Vec<Location>) -> usize {
+    locations.sort();
+    locations.dedup();
+    let kept: Vec<Location> = locations
+        .iter()
+        .filter(|location| {
+            !locations
+                .iter()
+                .any(|other| other.len() < location.len() && location.starts_with(other))
+        })
+        .cloned()
+        .collect();
+    let mut removed = 0;
+    // Last first, so array positions still to be removed do not shift.
+    for location in kept.iter().rev() {
+        let Some((last, parent)) = location.split_last() else {
+            continue;
+        };
+        let gone = match (get_mut(root, parent), last) {
+            (Some(JsonValue::Object(map)), Segment::Key(key)) => map.remove(key).is_some(),
+            (Some(JsonValue::Array(items)), Segment::Index(idx)) if *idx < items.len() => {
+                items.remove(*idx);
+                true
+            }
+            _ => false,
+        };
+        removed += usize::from(gone);
+    }
+    removed
+}
+
+/// Applies an RFC 7386 merge patch to `target`: objects merge member by
+/// member, a `null` member deletes, and anything else replaces.
+pub fn merge_patch(target: &mut JsonValue, patch: &JsonValue) {
+    let JsonValue::Object(members) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = JsonValue::Object(serde_json::Map::new());
+    }
+    let JsonValue::Object(map) = target else {
+        return;
+    };
+    for (key, value) in members {
+        if value.is_null() {
+            map.remove(key);
+        } else {
+            merge_patch(map.entry(key.clone()).or_insert(JsonValue::Null), value);
+        }
+    }
+}
+
+/// The type names `JSON.TYPE` replies with.
+pub fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+/// The whitespace `JSON.GET` puts between tokens: `indent` once per level
+/// before each member or element, `newline` before those and before the
+/// closing bracket, `space` after each colon. All empty gives compact text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JsonFormat {
+    pub indent: String,
+    pub newline: String,
+    pub space: String,
+}
+
+impl JsonFormat {
+    pub fn is_compact(&self) -> bool {
+        self.indent.is_empty() && self.newline.is_empty() && self.space.is_empty()
+    }
+}
+
+/// Serializes `value` laid out as RedisJSON does for these options.
+pub fn format(value: &JsonValue, layout: &JsonFormat) -> String {
+    let mut out = String::new();
+    write_formatted(value, layout, 0, &mut out);
+    out
+}
+
+fn write_formatted(value: &JsonValue, layout: &JsonFormat, depth: usize, out: &mut String) {
+    let items: Vec<(Option<&String>, &JsonValue)> = match value {
+        JsonValue::Array(items) if !items.is_empty() => items.iter().map(|v| (None, v)).collect(),
+        JsonValue::Object(map) if !map.is_empty() => {
+            map.iter().map(|(k, v)| (Some(k), v)).collect()
+        }
+        scalar => {
+            out.push_str(&scalar.to_string());
+            return;
+        }
+    };
+    let (open, close) = if value.is_array() {
+        ('[', ']')
+    } else {
+        ('{', '}')
+    };
+    out.push(open);
+    for (i, (key, item)) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&layout.newline);
+        out.push_str(&layout.indent.repeat(depth + 1));
+        if let Some(key) = key {
+            out.push_str(&JsonValue::String(key.clone()).to_string());
+            out.push(':');
+            out.push_str(&layout.space);
+        }
+        write_formatted(item, layout, depth + 1, out);
+    }
+    out.push_str(&layout.newline);
+    out.push_str(&layout.indent.repeat(depth));
+    out.push(close);
+}
+
+fn walk(
+    selectors: &[Selector],
+    root: &JsonValue,
+    value: &JsonValue,
+    location: &mut Location,
+    out: &mut Vec<Location>,
+) {
+    let Some((first, rest)) = selectors.split_first() else {
+        out.push(location.clone());
+        return;
+    };
+    for segment in children(first, root, value) {
+        let Some(child) = get(value, std::slice::from_ref(&segment)) else {
+            continue;
+        };
+        location.push(segment);
+        walk(rest, root, child, location, out);
+        location.pop();
+    }
+    if let Selector::Descendant(inner) = first {
+        let next: Vec<Selector> = std::iter::once(Selector::Descendant(inner.clone()))
+            .chain(rest.iter().cloned())
+            .collect();
+        for segment in all_children(value) {
+            let Some(child) = get(value, std::slice::from_ref(&segment)) else {
+                continue;
+            };
+            location.push(segment);
+            walk(&next, root, child, location, out);
+            location.pop();
+        }
+    }
+}
+
+/// The children of `value` that `selector` picks, one step down.
+fn children(selector: &Selector, root: &JsonValue, value: &JsonValue) -> Vec<Segment> {
+    match (selector, value) {
+        (Selector::Name(name), JsonValue::Object(map)) if map.contains_key(name) => {
+            vec![Segment::Key(name.clone())]
+        }
+        (Selector::Index(idx), JsonValue::Array(items)) => {
+            let len = items.len() as i64;
+            let idx = if *idx < 0 { len + idx } else { *idx };
+            if (0..len).contains(&idx) {
+                vec![Segment::Index(idx as usize)]
+            } else {
+                Vec::new()
+            }
+        }
+        (Selector::Slice(start, end, step), JsonValue::Array(items)) => {
+            slice(items.len(), *start, *end, *step)
+                .into_iter()
+                .map(Segment::Index)
+                .collect()
+        }
+        (Selector::Wildcard, _) => all_children(value),
+        (Selector::Union(parts), _) => parts
+            .iter()
+            .flat_map(|part| children(part, root, value))
+            .collect(),
+        (Selector::Filter(filter), _) => all_children(value)
+            .into_iter()
+            .filter(|segment| {
+                get(value, std::slice::from_ref(segment))
+                    .is_some_and(|child| filter.test(root, child))
+            })
+            .collect(),
+        (Selector::Descendant(inner), _) => children(inner, root, value),
+        _ => Vec::new(),
+    }
+}
+
+fn all_children(value: &JsonValue) -> Vec<Segment> {
+    match value {
+        JsonValue::Object(map) => map.keys().cloned().map(Segment::Key).collect(),
+        JsonValue::Array(items) => (0..items.len()).map(Segment::Index).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Positions `[start:end:step]` picks from an array of `len`, Python style:
+/// negative bounds count from the end and a negative step walks backwards.
+fn slice(len: usize, start: Option<i64>, end: Option<i64>, step: Option<i64>) -> Vec<usize> {
+    let len = len as i64;
+    let step = step.unwrap_or(1);
+    let normalize = |i: i64| if i < 0 { len + i } else { i };
+    let mut out = Vec::new();
+    if step > 0 {
+        let lower = start.map(normalize).unwrap_or(0).clamp(0, len);
+        let upper = end.map(normalize).unwrap_or(len).clamp(0, len);
+        let mut i = lower;
+        while i < upper {
+            out.push(i as usize);
+            i += step;
+        }
+    } else if step < 0 {
+        let upper = start.map(normalize).unwrap_or(len - 1).clamp(-1, len - 1);
+        let lower = end.map(normalize).unwrap_or(-1).clamp(-1, len - 1);
+        let mut i = upper;
+        while i > lower {
+            out.push(i as usize);
+            i += step;
+        }
+    }
+    out
+}
+
+impl Filter {
+    fn test(&self, root: &JsonValue, current: &JsonValue) -> bool {
+        match self {
+            Filter::Or(a, b) => a.test(root, current) || b.test(root, current),
+            Filter::And(a, b) => a.test(root, current) && b.test(root, current),
+            Filter::Not(inner) => !inner.test(root, current),
+            Filter::Exists(operand) => operand.resolve(root, current).is_some(),
+            Filter::Compare(left, op, right) => {
+                match (left.resolve(root, current), right.resolve(root, current)) {
+                    (Some(left), Some(right)) => compare(left, *op, right),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    /// The operand's value; a path that matches several values stands for
+    /// the first.
+    fn resolve<'a>(&'a self, root: &'a JsonValue, current: &'a JsonValue) -> Option<&'a JsonValue> {
+        let (start, selectors) = match self {
+            Operand::Literal(value) => return Some(value),
+            Operand::Current(selectors) => (current, selectors),
+            Operand::Root(selectors) => (root, selectors),
+        };
+        let mut out = Vec::new();
+        walk(selectors, root, start, &mut Vec::new(), &mut out);
+        out.first().and_then(|location| get(start, location))
+    }
+}
+
+fn compare(left: &JsonValue, op: Comparison, right: &JsonValue) -> bool {
+    let ordering = match (left, right) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (JsonValue::String(a), JsonValue::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match op {
+        Comparison::Eq => ordering.map_or(left == right, |o| o.is_eq()),
+        Comparison::Ne => !ordering.map_or(left == right, |o| o.is_eq()),
+        Comparison::Lt => ordering.is_some_and(|o| o.is_lt()),
+        Comparison::Le => ordering.is_some_and(|o| o.is_le()),
+        Comparison::Gt => ordering.is_some_and(|o| o.is_gt()),
+        Comparison::Ge => ordering.is_some_and(|o| o.is_ge()),
+    }
+}
+
+/// A recursive-descent parser over the path after its `$`.
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.src.len()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        self.skip_spaces();
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", token))
+        }
+    }
+
+    /// Selectors up to the end of the path, or up to whatever cannot start
+    /// one (inside a filter: an operator or a closing parenthesis).
+    fn selectors(&mut self) -> Result<Vec<Selector>, String> {
+        let mut out = Vec::new();
+        loop {
+            if self.eat("..") {
+                let inner = if self.eat("*") {
+                    Selector::Wildcard
+                } else if self.peek() == Some('[') {
+                    self.pos += 1;
+                    self.bracket()?
+                } else {
+                    Selector::Name(self.name()?)
+                };
+                out.push(Selector::Descendant(Box::new(inner)));
+            } else if self.eat(".") {
+                if self.eat("*") {
+                    out.push(Selector::Wildcard);
+                } else {
+                    out.push(Selector::Name(self.name()?));
+                }
+            } else if self.eat("[") {
+                out.push(self.bracket()?);
+            } else {
+                return Ok(out);
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self
+            .rest()
+            .find(|c: char| {
+                matches!(
+                    c,
+                    '.' | '[' | ']' | '(' | ')' | '=' | '!' | '<' | '>' | '&' | '|' | ','
+                ) || c.is_whitespace()
+            })
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err("expected a member name".to_string());
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    /// The inside of `[...]`, after the opening bracket.
+    fn bracket(&mut self) -> Result<Selector, String> {
+        self.skip_spaces();
+        if self.eat("?") {
+            let filter = self.or()?;
+            self.expect("]")?;
+            return Ok(Selector::Filter(Box::new(filter)));
+        }
+        let mut parts = Vec::new();
+        loop {
+            self.skip_spaces();
+            parts.push(self.bracket_item()?);
+            self.skip_spaces();
+            if self.eat("]") {
+                break;
+            }
+            if !self.eat(",") {
+                return Err("expected ',' or ']'".to_string());
+            }
+        }
+        Ok(if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Selector::Union(parts)
+        })
+    }
+
+    fn bracket_item(&mut self) -> Result<Selector, String> {
+        if self.eat("*") {
+            return Ok(Selector::Wildcard);
+        }
+        if matches!(self.peek(), Some('\'') | Some('"')) {
+            return Ok(Selector::Name(self.quoted()?));
+        }
+        let start = self.integer()?;
+        self.skip_spaces();
+        if !self.eat(":") {
+            return start
+                .map(Selector::Index)
+                .ok_or_else(|| "expected an index".to_string());
+        }
+        self.skip_spaces();
+        let end = self.integer()?;
+        self.skip_spaces();
+        let step = if self.eat(":") {
+            self.skip_spaces();
+            self.integer()?
+        } else {
+            None
+        };
+        Ok(Selector::Slice(start, end, step))
+    }
+
+    fn integer(&mut self) -> Result<Option<i64>, String> {
+        let len = self
+            .rest()
+            .char_indices()
+            .take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && *c == '-'))
+            .count();
+        if len == 0 {
+            return Ok(None);
+        }
+        let digits = &self.rest()[..len];
+        let n = digits
+            .parse::<i64>()
+            .map_err(|_| format!("'{}' is not an integer", digits))?;
+        self.pos += len;
+        Ok(Some(n))
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        let quote = self.peek().unwrap_or('"');
+        self.pos += 1;
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => out.push(escaped),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                c => out.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut left = self.and()?;
+        loop {
+            self.skip_spaces();
+            if !self.eat("||") {
+                return Ok(left);
+            }
+            left = Filter::Or(Box::new(left), Box::new(self.and()?));
+        }
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut left = self.unary()?;
+        loop {
+            self.skip_spaces();
+            if !self.eat("&&") {
+                return Ok(left);
+            }
+            left = Filter::And(Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        self.skip_spaces();
+        if self.rest().starts_with("!=") {
+            return Err("unexpected '!='".to_string());
+        }
+        if self.eat("!") {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let inner = self.or()?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+        let left = self.operand()?;
+        self.skip_spaces();
+        let op = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.eat(token));
+        match op {
+            Some((_, op)) => Ok(Filter::Compare(left, op, self.operand()?)),
+            None if matches!(left, Operand::Literal(_)) => {
+                Err("a literal needs a comparison".to_string())
+            }
+            None => Ok(Filter::Exists(left)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        self.skip_spaces();
+        if self.eat("@") {
+            return Ok(Operand::Current(self.selectors()?));
+        }
+        if self.eat("$") {
+            return Ok(Operand::Root(self.selectors()?));
+        }
+        if matches!(self.peek(), Some('\'') | Some('"')) {
+            return Ok(Operand::Literal(JsonValue::String(self.quoted()?)));
+        }
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')))
+            .unwrap_or(self.rest().len());
+        let token = &self.rest()[..len];
+        let value = serde_json::from_str::<JsonValue>(token)
+            .ok()
+            .filter(|v| !v.is_array() && !v.is_object())
+            .ok_or_else(|| format!("expected a value, not '{}'", token))?;
+        self.pos += len;
+        Ok(Operand::Literal(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn select(path: &str, doc: &JsonValue) -> Vec<JsonValue> {
+        JsonPath::parse(path.as_bytes())
+            .unwrap()
+            .select(doc)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn selects_members_indices_wildcards_slices_and_filters() {
+        let doc = json!({
+            "store": {
+                "book": [
+                    {"title": "a", "price": 8, "tags": ["x"]},
+                    {"title": "b", "price": 12},
+                    {"title": "c", "price": 5.5, "isbn": "1"},
+                ],
+                "bicycle": {"price": 20},
+            }
+        });
+        assert_eq!(select("$.store.bicycle.price", &doc), vec![json!(20)]);
+        assert_eq!(select("$.store.book[-1].title", &doc), vec![json!("c")]);
+        assert_eq!(
+            select("$.store.book[*].title", &doc),
+            vec![json!("a"), json!("b"), json!("c")]
+        );
+        assert_eq!(
+            select("$.store.book[0:2].title", &doc),
+            vec![json!("a"), json!("b")]
+        );
+        assert_eq!(
+            select("$.store.book[::-2].title", &doc),
+            vec![json!("c"), json!("a")]
+        );
+        assert_eq!(
+            select("$.store.book[0,'missing',2].title", &doc),
+            vec![json!("a"), json!("c")]
+        );
+        assert_eq!(select("$..price", &doc).len(), 4);
+        assert_eq!(
+            select("$.store.book[?(@.price < 10 && !@.isbn)].title", &doc),
+            vec![json!("a")]
+        );
+        assert_eq!(
+            select("$.store.book[?@.title == 'b' || @.price >= 8].title", &doc),
+            vec![json!("a"), json!("b")]
+        );
+        assert_eq!(
+            select("$.store.book[?(@.price < $.store.bicycle.price)]", &doc).len(),
+            3
+        );
+        assert_eq!(select("$.store.nothing", &doc), Vec::<JsonValue>::new());
+
+        let legacy = JsonPath::parse(b"store.book[1].title").unwrap();
+        assert!(legacy.is_legacy());
+        assert_eq!(legacy.select(&doc), vec![&json!("b")]);
+        assert!(JsonPath::parse(b".").unwrap().is_root());
+        assert!(JsonPath::parse(b"$.store[").is_err());
+        assert!(JsonPath::parse(b"$.a[?(@.b ==)]").is_err());
+    }
+
+    #[test]
+    fn removes_matches_without_shifting_the_rest() {
+        let mut doc = json!({"a": [1, 2, 3, 4], "b": {"c": 1}});
+        let path = JsonPath::parse(b"$.a[?(@ > 1)]").unwrap();
+        let locations = path.locate(&doc);
+        assert_eq!(remove(&mut doc, locations), 3);
+        assert_eq!(doc, json!({"a": [1], "b": {"c": 1}}));
+
+        let path = JsonPath::parse(b"$..c").unwrap();
+        let mut locations = path.locate(&doc);
+        locations.push(vec![Segment::Key("b".to_string())]);
+        assert_eq!(remove(&mut doc, locations), 1, "b's member goes with b");
+        assert_eq!(doc, json!({"a": [1]}));
+    }
+
+    #[test]
+    fn format_lays_out_nested_values() {
+        let doc = json!({"a": [1, {"b": null}], "c": {}, "d": []});
+        assert_eq!(
+            format(&doc, &JsonFormat::default()),
+            r#"{"a":[1,{"b":null}],"c":{},"d":[]}"#
+        );
+        let layout = JsonFormat {
+            indent: "  ".to_string(),
+            newline: "\n".to_string(),
+            space: " ".to_string(),
+        };
+        assert_eq!(
+            format(&doc, &layout),
+            "{\n  \"a\": [\n    1,\n    {\n      \"b\": null\n    }\n  ],\n  \"c\": {},\n  \"d\": []\n}"
+        );
+    }
+
+    #[test]
+    fn merge_patch_follows_rfc_7386() {
+        let mut doc = json!({"a": "b", "c": {"d": "e", "f": "g"}, "list": [1, 2]});
+        merge_patch(
+            &mut doc,
+            &json!({"a": "z", "c": {"f": null, "h": {"i": null}}, "list": [3]}),
+        );
+        assert_eq!(
+            doc,
+            json!({"a": "z", "c": {"d": "e", "h": {}}, "list": [3]})
+        );
+        merge_patch(&mut doc, &json!("scalar"));
+        assert_eq!(doc, json!("scalar"));
+    }
+}
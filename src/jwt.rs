@@ -0,0 +1,262 @@
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::{hmac, signature};
+use rustls_pki_types::SubjectPublicKeyInfoDer;
+use rustls_pki_types::pem::PemObject;
+use x509_parser::prelude::FromDer;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+/// Verifies HS256/RS256 bearer tokens presented to AUTH in non-Redis mode.
+#[derive(Clone, Default)]
+pub struct JwtVerifier {
+    hs256_secret: Option<Vec<u8>>,
+    /// PKCS#1 `RSAPublicKey` DER, as ring expects it.
+    rs256_public_key: Option<Vec<u8>>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Claims {
+    pub subject: String,
+    pub expires_at_ms: Option<u64>,
+    /// ACL rules carried by the token; `None` means the subject's own ACL user applies.
+    pub acl: Option<Vec<String>>,
+}
+
+impl JwtVerifier {
+    pub fn new(
+        hs256_secret: Option<Vec<u8>>,
+        rs256_public_key_file: Option<&Path>,
+        issuer: Option<String>,
+        audience: Option<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rs256_public_key = match rs256_public_key_file {
+            Some(path) => {
+                let spki = SubjectPublicKeyInfoDer::from_pem_file(path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                let (_, parsed) = SubjectPublicKeyInfo::from_der(&spki)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                Some(parsed.subject_public_key.data.to_vec())
+            }
+            None => None,
+        };
+        Ok(Self {
+            hs256_secret,
+            rs256_public_key,
+            issuer,
+            audience,
+        })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.hs256_secret.is_some() || self.rs256_public_key.is_some()
+    }
+
+    /// Checks the signature and the registered claims (`exp`, `nbf`, `iss`, `aud`).
+    pub fn verify(&self, token: &str, now_ms: u64) -> Result<Claims, String> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(sig), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("malformed token".to_string());
+        };
+        let header = decode_json(header)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(sig)
+            .map_err(|_| "malformed token signature".to_string())?;
+        let signed = &token[..signing_input_len(token)];
+
+        match header.get("alg").and_then(|v| v.as_str()) {
+            Some("HS256") => {
+                let secret = self
+                    .hs256_secret
+                    .as_ref()
+                    .ok_or("HS256 tokens are not accepted")?;
+                let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+                hmac::verify(&key, signed.as_bytes(), &signature)
+                    .map_err(|_| "invalid token signature".to_string())?;
+            }
+            Some("RS256") => {
+                let public_key = self
+                    .rs256_public_key
+                    .as_ref()
+                    .ok_or("RS256 tokens are not accepted")?;
+                signature::UnparsedPublicKey::new(
+                    &signature::RSA_PKCS1_2048_8192_SHA256,
+                    public_key,
+                )
+                .verify(signed.as_bytes(), &signature)
+                .map_err(|_| "invalid token signature".to_string())?;
+            }
+            _ => return Err("unsupported token algorithm".to_string()),
+        }
+
+        let claims = decode_json(payload)?;
+        let now_sec = now_ms / 1000;
+        let expires_at_ms = claims.get("exp").and_then(|v| v.as_u64()).map(|v| v * 1000);
+        if expires_at_ms.is_some_and(|exp| exp <= now_ms) {
+            return Err("token expired".to_string());
+        }
+        if claims
+            .get("nbf")
+            .and_then(|v| v.as_u64())
+            .is_some_and(|nbf| nbf > now_sec)
+        {
+            return Err("token not yet valid".to_string());
+        }
+        if let Some(issuer) = &self.issuer
+            && claims.get("iss").and_then(|v| v.as_str()) != Some(issuer)
+        {
+            return Err("token issuer mismatch".to_string());
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match claims.get("aud") {
+                Some(serde_json::Value::String(aud)) => aud == audience,
+                Some(serde_json::Value::Array(auds)) => {
+                    auds.iter().any(|a| a.as_str() == Some(audience))
+                }
+                _ => false,
+            };
+            if !matches {
+                return Err("token audience mismatch".to_string());
+            }
+        }
+        let subject = claims
+            .get("sub")
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+            .ok_or("token has no subject")?
+            .to_string();
+        let acl = match claims.get("acl") {
+            None => None,
+            Some(serde_json::Value::String(rules)) => {
+                Some(rules.split_whitespace().map(str::to_string).collect())
+            }
+            Some(serde_json::Value::Array(rules)) => Some(
+                rules
+                    .iter()
+                    .map(|r| r.as_str().map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("token acl claim must contain strings")?,
+            ),
+            Some(_) => return Err("token acl claim must be a string or array".to_string()),
+        };
+        Ok(Claims {
+            subject,
+            expires_at_ms,
+            acl,
+        })
+    }
+}
+
+/// A compact JWS is `header.payload.signature`; callers check the shape first.
+pub fn looks_like_token(value: &str) -> bool {
+    value.matches('.').count() == 2 && value.starts_with("ey")
+}
+
+fn signing_input_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or(token.len())
+}
+
+fn decode_json(segment: &str) -> Result<serde_json::Value, String> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(segment)
+        .map_err(|_| "malformed token".to_string())?;
+    let value: serde_json::Value =
+        serde_json::from_slice(&bytes).map_err(|_| "malformed token".to_string())?;
+    if !value.is_object() {
+        return Err("malformed token".to_string());
+    }
+    Ok(value)
+}
+
+/// Signs `claims` as an HS256 token; used by tests to mint tokens.
+#[cfg(test)]
+pub fn sign_hs256(secret: &[u8], claims: &serde_json::Value) -> String {
+    let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+    let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signing_input = format!("{}.{}", header, payload);
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let tag = hmac::sign(&key, signing_input.as_bytes());
+    format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls_pki_types::PrivatePkcs8KeyDer;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/jwt")
+            .join(name)
+    }
+
+    #[test]
+    fn hs256_tokens_are_verified_with_expiry_and_audience() {
+        let verifier = JwtVerifier::new(Some(b"k".to_vec()), None, None, Some("fedis".to_string()))
+            .expect("verifier");
+        let token = sign_hs256(
+            b"k",
+            &serde_json::json!({"sub": "edge", "exp": 2_000, "aud": ["fedis"], "acl": "+get ~edge:*"}),
+        );
+
+        let claims = verifier.verify(&token, 1_000_000).expect("valid token");
+        assert_eq!(claims.subject, "edge");
+        assert_eq!(claims.expires_at_ms, Some(2_000_000));
+        assert_eq!(
+            claims.acl,
+            Some(vec!["+get".to_string(), "~edge:*".to_string()])
+        );
+
+        assert_eq!(
+            verifier.verify(&token, 2_000_000),
+            Err("token expired".to_string())
+        );
+        let forged = sign_hs256(
+            b"other",
+            &serde_json::json!({"sub": "edge", "aud": "fedis"}),
+        );
+        assert_eq!(
+            verifier.verify(&forged, 0),
+            Err("invalid token signature".to_string())
+        );
+    }
+
+    #[test]
+    fn rs256_tokens_are_verified_against_public_key() {
+        let verifier = JwtVerifier::new(None, Some(&fixture("rs256_public.pem")), None, None)
+            .expect("verifier");
+        let private_key =
+            PrivatePkcs8KeyDer::from_pem_file(fixture("rs256_private.pem")).expect("private key");
+        let key_pair =
+            signature::RsaKeyPair::from_pkcs8(private_key.secret_pkcs8_der()).expect("key pair");
+
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(br#"{"sub":"svc"}"#);
+        let signing_input = format!("{}.{}", header, payload);
+        let mut sig = vec![0; key_pair.public().modulus_len()];
+        key_pair
+            .sign(
+                &signature::RSA_PKCS1_SHA256,
+                &ring::rand::SystemRandom::new(),
+                signing_input.as_bytes(),
+                &mut sig,
+            )
+            .expect("sign");
+        let token = format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(&sig));
+
+        assert_eq!(
+            verifier.verify(&token, 0).map(|c| c.subject),
+            Ok("svc".to_string())
+        );
+        let hs = sign_hs256(b"k", &serde_json::json!({"sub": "svc"}));
+        assert_eq!(
+            verifier.verify(&hs, 0),
+            Err("HS256 tokens are not accepted".to_string())
+        );
+    }
+}
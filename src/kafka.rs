@@ -0,0 +1,572 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::checksum::crc32c;
+
+const CLIENT_ID: &str = "fedis";
+const API_PRODUCE: i16 = 0;
+const API_METADATA: i16 = 3;
+/// How long the leader may wait for the in-sync replicas to acknowledge.
+const PRODUCE_TIMEOUT_MS: i32 = 30_000;
+/// Larger responses are refused rather than allocated.
+const MAX_RESPONSE_BYTES: usize = 64 * 1024 * 1024;
+
+/// One record to append: a `None` value is a tombstone.
+#[derive(Debug, PartialEq)]
+pub(crate) struct KafkaRecord {
+    pub(crate) key: Vec<u8>,
+    pub(crate) value: Option<Vec<u8>>,
+    pub(crate) headers: Vec<(&'static str, Vec<u8>)>,
+    pub(crate) timestamp_ms: i64,
+}
+
+/// Why a batch was not appended.
+#[derive(Debug)]
+pub(crate) struct ProduceError {
+    message: String,
+    /// Set when the broker refused the batch itself.
+    code: Option<i16>,
+}
+
+impl ProduceError {
+    /// Whether the broker would refuse the same batch again: it is corrupt
+    /// or too large, or the topic name is invalid.
+    pub(crate) fn is_permanent(&self) -> bool {
+        // CORRUPT_MESSAGE, MESSAGE_TOO_LARGE, INVALID_TOPIC_EXCEPTION,
+        // RECORD_LIST_TOO_LARGE and INVALID_RECORD.
+        matches!(self.code, Some(2 | 10 | 17 | 18 | 87))
+    }
+}
+
+impl From<String> for ProduceError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            code: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ProduceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Appends records to one partition of a topic with `acks=all`, speaking the
+/// Kafka protocol directly: `Metadata` v4 to find the partition's leader,
+/// then `Produce` v3 with uncompressed v2 record batches.
+pub(crate) struct KafkaProducer {
+    bootstrap: Vec<String>,
+    topic: String,
+    partition: i32,
+    /// Dropped after any failure, so the next send asks for the leader again.
+    leader: Option<TcpStream>,
+    correlation_id: i32,
+}
+
+impl KafkaProducer {
+    pub(crate) fn new(bootstrap: Vec<String>, topic: String, partition: i32) -> Self {
+        Self {
+            bootstrap,
+            topic,
+            partition,
+            leader: None,
+            correlation_id: 0,
+        }
+    }
+
+    /// Appends the records as one batch; `Ok` once every in-sync replica
+    /// has them.
+    pub(crate) async fn send(&mut self, records: &[KafkaRecord]) -> Result<(), ProduceError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let result = self.try_send(records).await;
+        if result.is_err() {
+            self.leader = None;
+        }
+        result
+    }
+
+    async fn try_send(&mut self, records: &[KafkaRecord]) -> Result<(), ProduceError> {
+        let mut leader = match self.leader.take() {
+            Some(leader) => leader,
+            None => self.find_leader().await?,
+        };
+        let mut body = Vec::new();
+        put_i16(&mut body, -1); // no transactional id
+        put_i16(&mut body, -1); // acks=all
+        put_i32(&mut body, PRODUCE_TIMEOUT_MS);
+        put_i32(&mut body, 1);
+        put_str(&mut body, &self.topic);
+        put_i32(&mut body, 1);
+        put_i32(&mut body, self.partition);
+        let batch = record_batch(records);
+        put_i32(&mut body, batch.len() as i32);
+        body.extend_from_slice(&batch);
+        let response = self
+            .exchange(&mut leader, API_PRODUCE, 3, &body)
+            .await
+            .map_err(|e| format!("produce: {}", e))?;
+
+        let mut reader = Reader::new(&response);
+        for _ in 0..reader.i32()? {
+            reader.string()?;
+            for _ in 0..reader.i32()? {
+                let partition = reader.i32()?;
+                let error = reader.i16()?;
+                reader.i64()?;
+                reader.i64()?;
+                if error != 0 {
+                    return Err(ProduceError {
+                        message: format!(
+                            "broker refused the batch for {}-{}: error code {}",
+                            self.topic, partition, error
+                        ),
+                        code: Some(error),
+                    });
+                }
+            }
+        }
+        self.leader = Some(leader);
+        Ok(())
+    }
+
+    /// Asks each bootstrap broker in turn for the partition's leader and
+    /// connects to it.
+    async fn find_leader(&mut self) -> Result<TcpStream, String> {
+        let mut last_error = "no bootstrap brokers".to_string();
+        for addr in self.bootstrap.clone() {
+            let leader = async {
+                let mut stream = TcpStream::connect(&addr)
+                    .await
+                    .map_err(|e| format!("connecting to {}: {}", addr, e))?;
+                let mut body = Vec::new();
+                put_i32(&mut body, 1);
+                put_str(&mut body, &self.topic);
+                body.push(1); // allow_auto_topic_creation
+                let response = self.exchange(&mut stream, API_METADATA, 4, &body).await?;
+                let leader = self.leader_addr(&response)?;
+                if leader == addr {
+                    return Ok(stream);
+                }
+                TcpStream::connect(&leader)
+                    .await
+                    .map_err(|e| format!("connecting to leader {}: {}", leader, e))
+            };
+            match leader.await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// The `host:port` of the partition's leader, from a `Metadata` v4
+    /// response.
+    fn leader_addr(&self, response: &[u8]) -> Result<String, String> {
+        let mut reader = Reader::new(response);
+        reader.i32()?; // throttle_time_ms
+        let mut brokers = Vec::new();
+        for _ in 0..reader.i32()? {
+            let node_id = reader.i32()?;
+            let host = reader.string()?;
+            let port = reader.i32()?;
+            reader.nullable_string()?; // rack
+            brokers.push((node_id, format!("{}:{}", host, port)));
+        }
+        reader.nullable_string()?; // cluster_id
+        reader.i32()?; // controller_id
+        for _ in 0..reader.i32()? {
+            let error = reader.i16()?;
+            let name = reader.string()?;
+            reader.i8()?; // is_internal
+            if error != 0 && name == self.topic {
+                return Err(format!("topic {}: error code {}", self.topic, error));
+            }
+            for _ in 0..reader.i32()? {
+                reader.i16()?;
+                let partition = reader.i32()?;
+                let leader = reader.i32()?;
+                for _ in 0..2 {
+                    // replica_nodes, isr_nodes
+                    for _ in 0..reader.i32()? {
+                        reader.i32()?;
+                    }
+                }
+                if name == self.topic && partition == self.partition {
+                    return brokers
+                        .iter()
+                        .find(|(node_id, _)| *node_id == leader)
+                        .map(|(_, addr)| addr.clone())
+                        .ok_or_else(|| format!("{}-{} has no leader", self.topic, self.partition));
+                }
+            }
+        }
+        Err(format!(
+            "no partition {} in topic {}",
+            self.partition, self.topic
+        ))
+    }
+
+    /// Sends one request and returns the response body after the
+    /// correlation id.
+    async fn exchange(
+        &mut self,
+        stream: &mut TcpStream,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut request = Vec::with_capacity(body.len() + 32);
+        put_i32(&mut request, 0);
+        put_i16(&mut request, api_key);
+        put_i16(&mut request, api_version);
+        put_i32(&mut request, self.correlation_id);
+        put_str(&mut request, CLIENT_ID);
+        request.extend_from_slice(body);
+        let size = (request.len() - 4) as i32;
+        request[..4].copy_from_slice(&size.to_be_bytes());
+        stream
+            .write_all(&request)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let size = stream.read_i32().await.map_err(|e| e.to_string())?;
+        let size = usize::try_from(size)
+            .ok()
+            .filter(|size| (4..=MAX_RESPONSE_BYTES).contains(size))
+            .ok_or_else(|| format!("invalid response size {}", size))?;
+        let mut response = vec![0_u8; size];
+        stream
+            .read_exact(&mut response)
+            .await
+            .map_err(|e| e.to_string())?;
+        let correlation_id =
+            i32::from_be_bytes([response[0], response[1], response[2], response[3]]);
+        if correlation_id != self.correlation_id {
+            return Err("response out of order".to_string());
+        }
+        response.drain(..4);
+        Ok(response)
+    }
+}
+
+/// A v2 record batch holding `records`, uncompressed and outside any
+/// transaction.
+pub(crate) fn record_batch(records: &[KafkaRecord]) -> Vec<u8> {
+    let base_timestamp = records.first().map_or(0, |record| record.timestamp_ms);
+    let max_timestamp = records
+        .iter()
+        .map(|record| record.timestamp_ms)
+        .max()
+        .unwrap_or(base_timestamp);
+
+    // Everything from the attributes on, which the CRC covers.
+    let mut tail = Vec::new();
+    put_i16(&mut tail, 0); // attributes
+    put_i32(&mut tail, records.len() as i32 - 1); // last offset delta
+    put_i64(&mut tail, base_timestamp);
+    put_i64(&mut tail, max_timestamp);
+    put_i64(&mut tail, -1); // producer id
+    put_i16(&mut tail, -1); // producer epoch
+    put_i32(&mut tail, -1); // base sequence
+    put_i32(&mut tail, records.len() as i32);
+    for (offset, record) in records.iter().enumerate() {
+        let mut body = vec![0]; // attributes
+        put_varint(&mut body, record.timestamp_ms - base_timestamp);
+        put_varint(&mut body, offset as i64);
+        put_varbytes(&mut body, Some(&record.key));
+        put_varbytes(&mut body, record.value.as_deref());
+        put_varint(&mut body, record.headers.len() as i64);
+        for (name, value) in &record.headers {
+            put_varbytes(&mut body, Some(name.as_bytes()));
+            put_varbytes(&mut body, Some(value));
+        }
+        put_varint(&mut tail, body.len() as i64);
+        tail.extend_from_slice(&body);
+    }
+
+    let mut batch = Vec::with_capacity(tail.len() + 21);
+    put_i64(&mut batch, 0); // base offset, assigned by the broker
+    // Length of what follows: leader epoch, magic, CRC and the tail.
+    put_i32(&mut batch, (4 + 1 + 4 + tail.len()) as i32);
+    put_i32(&mut batch, -1); // partition leader epoch
+    batch.push(2); // magic
+    batch.extend_from_slice(&crc32c(&tail).to_be_bytes());
+    batch.extend_from_slice(&tail);
+    batch
+}
+
+fn put_i16(out: &mut Vec<u8>, value: i16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_i64(out: &mut Vec<u8>, value: i64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    put_i16(out, value.len() as i16);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// A zigzag varint, as record fields are written.
+fn put_varint(out: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push((zigzag as u8 & 0x7F) | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+/// Varint length and bytes, or -1 for null.
+fn put_varbytes(out: &mut Vec<u8>, value: Option<&[u8]>) {
+    match value {
+        Some(value) => {
+            put_varint(out, value.len() as i64);
+            out.extend_from_slice(value);
+        }
+        None => put_varint(out, -1),
+    }
+}
+
+/// Reads the big-endian fields of a response.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or("truncated response")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn i8(&mut self) -> Result<i8, String> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        let bytes = self.take(2)?;
+        Ok(i16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        let mut bytes = [0_u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+
+    fn nullable_string(&mut self) -> Result<Option<String>, String> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let bytes = self.take(len as usize)?;
+        Ok(Some(String::from_utf8_lossy(bytes).to_string()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.nullable_string()?
+            .ok_or_else(|| "unexpected null string".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn read_varint(reader: &mut Reader) -> i64 {
+        let mut value = 0_u64;
+        let mut shift = 0;
+        loop {
+            let byte = reader.i8().expect("varint") as u8;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        (value >> 1) as i64 ^ -((value & 1) as i64)
+    }
+
+    fn read_varbytes(reader: &mut Reader) -> Option<Vec<u8>> {
+        let len = read_varint(reader);
+        (len >= 0).then(|| reader.take(len as usize).expect("bytes").to_vec())
+    }
+
+    /// Decodes a batch back into its records, checking the framing and CRC
+    /// on the way.
+    fn decode_batch(batch: &[u8]) -> Vec<KafkaRecord> {
+        let mut reader = Reader::new(batch);
+        assert_eq!(reader.i64().expect("base offset"), 0);
+        assert_eq!(reader.i32().expect("length") as usize, batch.len() - 12);
+        reader.i32().expect("leader epoch");
+        assert_eq!(reader.i8().expect("magic"), 2);
+        let crc = reader.i32().expect("crc") as u32;
+        assert_eq!(crc, crc32c(&batch[21..]));
+        reader.i16().expect("attributes");
+        reader.i32().expect("last offset delta");
+        let base_timestamp = reader.i64().expect("base timestamp");
+        reader.take(8 + 8 + 2 + 4).expect("header");
+        let count = reader.i32().expect("count");
+        (0..count)
+            .map(|offset| {
+                let len = read_varint(&mut reader) as usize;
+                let end = reader.pos + len;
+                reader.i8().expect("attributes");
+                let timestamp_ms = base_timestamp + read_varint(&mut reader);
+                assert_eq!(read_varint(&mut reader), i64::from(offset));
+                let key = read_varbytes(&mut reader).expect("key");
+                let value = read_varbytes(&mut reader);
+                let headers = (0..read_varint(&mut reader))
+                    .map(|_| {
+                        let name = read_varbytes(&mut reader).expect("name");
+                        let name: &'static str = String::from_utf8(name).expect("utf8").leak();
+                        (name, read_varbytes(&mut reader).expect("value"))
+                    })
+                    .collect();
+                assert_eq!(reader.pos, end);
+                KafkaRecord {
+                    key,
+                    value,
+                    headers,
+                    timestamp_ms,
+                }
+            })
+            .collect()
+    }
+
+    fn records() -> Vec<KafkaRecord> {
+        vec![
+            KafkaRecord {
+                key: b"user:1".to_vec(),
+                value: Some(b"alice".to_vec()),
+                headers: vec![("op", b"set".to_vec())],
+                timestamp_ms: 1_700_000_000_000,
+            },
+            KafkaRecord {
+                key: b"user:2".to_vec(),
+                value: None,
+                headers: vec![("op", b"del".to_vec())],
+                timestamp_ms: 1_700_000_000_250,
+            },
+        ]
+    }
+
+    #[test]
+    fn record_batches_round_trip() {
+        assert_eq!(decode_batch(&record_batch(&records())), records());
+    }
+
+    /// A broker that answers one `Metadata` and one `Produce` request, the
+    /// latter with `error`, and hands back the batch it was sent.
+    async fn fake_broker(listener: TcpListener, error: i16) -> Vec<u8> {
+        let port = listener.local_addr().expect("addr").port();
+        let (mut stream, _) = listener.accept().await.expect("accept");
+        let mut batch = Vec::new();
+        for _ in 0..2 {
+            let size = stream.read_i32().await.expect("size") as usize;
+            let mut request = vec![0_u8; size];
+            stream.read_exact(&mut request).await.expect("request");
+            let mut reader = Reader::new(&request);
+            let api_key = reader.i16().expect("api key");
+            reader.i16().expect("version");
+            let correlation_id = reader.i32().expect("correlation id");
+            assert_eq!(reader.string().expect("client id"), CLIENT_ID);
+            let mut response = Vec::new();
+            put_i32(&mut response, correlation_id);
+            if api_key == API_METADATA {
+                put_i32(&mut response, 0);
+                put_i32(&mut response, 1);
+                put_i32(&mut response, 7);
+                put_str(&mut response, "127.0.0.1");
+                put_i32(&mut response, i32::from(port));
+                put_i16(&mut response, -1);
+                put_i16(&mut response, -1);
+                put_i32(&mut response, 7);
+                put_i32(&mut response, 1);
+                put_i16(&mut response, 0);
+                put_str(&mut response, "writes");
+                response.push(0);
+                put_i32(&mut response, 1);
+                put_i16(&mut response, 0);
+                put_i32(&mut response, 0);
+                put_i32(&mut response, 7);
+                put_i32(&mut response, 0);
+                put_i32(&mut response, 0);
+            } else {
+                assert_eq!(api_key, API_PRODUCE);
+                reader.i16().expect("transactional id");
+                assert_eq!(reader.i16().expect("acks"), -1);
+                reader.i32().expect("timeout");
+                assert_eq!(reader.i32().expect("topics"), 1);
+                assert_eq!(reader.string().expect("topic"), "writes");
+                assert_eq!(reader.i32().expect("partitions"), 1);
+                assert_eq!(reader.i32().expect("partition"), 0);
+                let len = reader.i32().expect("length") as usize;
+                batch = reader.take(len).expect("batch").to_vec();
+                put_i32(&mut response, 1);
+                put_str(&mut response, "writes");
+                put_i32(&mut response, 1);
+                put_i32(&mut response, 0);
+                put_i16(&mut response, error);
+                put_i64(&mut response, 0);
+                put_i64(&mut response, -1);
+                put_i32(&mut response, 0);
+            }
+            stream
+                .write_i32(response.len() as i32)
+                .await
+                .expect("write");
+            stream.write_all(&response).await.expect("write");
+        }
+        batch
+    }
+
+    #[tokio::test]
+    async fn produces_to_the_partition_leader() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        let broker = tokio::spawn(fake_broker(listener, 0));
+        let mut producer = KafkaProducer::new(vec![addr], "writes".to_string(), 0);
+        producer.send(&records()).await.expect("send");
+        let batch = broker.await.expect("broker");
+        assert_eq!(decode_batch(&batch), records());
+    }
+
+    #[tokio::test]
+    async fn a_batch_too_large_is_refused_for_good() {
+        for (code, permanent) in [(10, true), (6, false)] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+            let addr = listener.local_addr().expect("addr").to_string();
+            let broker = tokio::spawn(fake_broker(listener, code));
+            let mut producer = KafkaProducer::new(vec![addr], "writes".to_string(), 0);
+            let error = producer.send(&records()).await.expect_err("refused");
+            assert_eq!(error.is_permanent(), permanent, "{}", error);
+            broker.await.expect("broker");
+        }
+    }
+}
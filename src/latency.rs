@@ -0,0 +1,159 @@
+/// Sub-buckets per power of two: latencies are kept to within 1/16 (6.25%).
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Values below `SUB_BUCKETS` get a bucket each; every power of two above
+/// that up to `u64::MAX` gets `SUB_BUCKETS`.
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// A log-linear latency histogram in microseconds, in the spirit of HDR
+/// histograms: fixed memory, O(1) recording and percentiles accurate to one
+/// sub-bucket no matter how far apart the values are.
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    counts: Box<[u64; BUCKETS]>,
+    total: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: Box::new([0; BUCKETS]),
+            total: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, usec: u64) {
+        self.counts[bucket(usec)] += 1;
+        self.total += 1;
+        self.sum = self.sum.saturating_add(usec);
+        self.max = self.max.max(usec);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// All recorded values added up.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// The latency `pct` percent of the recorded values are at or below, as
+    /// the upper end of the bucket holding it.
+    pub fn percentile(&self, pct: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+        let rank = ((pct / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (idx, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return highest(idx).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// What was recorded after `earlier`, an older copy of this histogram.
+    /// The maximum stays this histogram's, so may predate the interval.
+    pub fn since(&self, earlier: &Self) -> Self {
+        let mut out = self.clone();
+        for (count, before) in out.counts.iter_mut().zip(earlier.counts.iter()) {
+            *count = count.saturating_sub(*before);
+        }
+        out.total = self.total.saturating_sub(earlier.total);
+        out.sum = self.sum.saturating_sub(earlier.sum);
+        out
+    }
+
+    /// How many recorded values are below `usec`. Exact when `usec` is a
+    /// bucket boundary, which every power of two is.
+    pub fn count_below(&self, usec: u64) -> u64 {
+        self.counts[..bucket(usec)].iter().sum()
+    }
+
+    /// Cumulative counts at power-of-two bounds, as `LATENCY HISTOGRAM`
+    /// reports them: each pair is a bound and how many values are below it,
+    /// listed from the first bound with any values up to the one covering
+    /// them all, skipping bounds that add nothing.
+    pub fn power_of_two_buckets(&self) -> Vec<(u64, u64)> {
+        let mut out: Vec<(u64, u64)> = Vec::new();
+        if self.total == 0 {
+            return out;
+        }
+        for exp in 0..64 {
+            let bound = 1_u64 << exp;
+            let below = self.count_below(bound);
+            if below > 0 && out.last().is_none_or(|&(_, last)| last != below) {
+                out.push((bound, below));
+            }
+            if below == self.total {
+                return out;
+            }
+        }
+        out.push((u64::MAX, self.total));
+        out
+    }
+}
+
+fn bucket(usec: u64) -> usize {
+    if usec < SUB_BUCKETS as u64 {
+        return usec as usize;
+    }
+    let exp = 63 - usec.leading_zeros();
+    let sub = (usec >> (exp - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exp - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+/// The largest value that lands in bucket `idx`.
+fn highest(idx: usize) -> u64 {
+    if idx < SUB_BUCKETS {
+        return idx as u64;
+    }
+    let exp = (idx / SUB_BUCKETS) as u32 + SUB_BUCKET_BITS - 1;
+    let sub = (idx % SUB_BUCKETS) as u64;
+    let width = 1_u64 << (exp - SUB_BUCKET_BITS);
+    let lowest = (1_u64 << exp) + sub * width;
+    lowest + (width - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_cover_the_range_and_keep_percentiles_close() {
+        for usec in [0, 1, 15, 16, 17, 1000, 65_535, 1 << 40, u64::MAX] {
+            let idx = bucket(usec);
+            assert!(idx < BUCKETS);
+            assert!(highest(idx) >= usec, "{usec} fits its bucket");
+            assert!(
+                highest(idx) - usec <= usec / SUB_BUCKETS as u64,
+                "{usec} within a sub-bucket"
+            );
+        }
+
+        let mut hist = LatencyHistogram::default();
+        for usec in 1..=1000 {
+            hist.record(usec);
+        }
+        assert_eq!(hist.count(), 1000);
+        let p50 = hist.percentile(50.0);
+        assert!((500..=532).contains(&p50), "p50 {p50}");
+        let p99 = hist.percentile(99.0);
+        assert!((990..=1000).contains(&p99), "p99 {p99}");
+        assert_eq!(hist.percentile(100.0), 1000);
+        assert_eq!(hist.count_below(512), 511);
+
+        let buckets = hist.power_of_two_buckets();
+        assert_eq!(buckets.first(), Some(&(2, 1)));
+        assert_eq!(buckets.last(), Some(&(1024, 1000)));
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use bytes::Bytes;
+
+use crate::backend::Garbage;
+
+/// Values at least this large are freed in the background by default.
+pub const DEFAULT_THRESHOLD: usize = 64 * 1024;
+
+/// Frees memory on a background thread, as Redis' lazyfree does, so removing
+/// a huge value or a whole keyspace does not stall the command that removed
+/// it, nor every other command waiting on the same shard. The thread exits
+/// once the store owning it is dropped.
+#[derive(Clone)]
+pub struct LazyFree {
+    tx: mpsc::Sender<Garbage>,
+    threshold: usize,
+    pending: Arc<AtomicUsize>,
+    freed: Arc<AtomicU64>,
+}
+
+impl LazyFree {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel::<Garbage>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let freed = Arc::new(AtomicU64::new(0));
+        let worker = (pending.clone(), freed.clone());
+        let spawned = std::thread::Builder::new()
+            .name("fedis-lazyfree".to_string())
+            .spawn(move || {
+                let (pending, freed) = worker;
+                for garbage in rx {
+                    drop(garbage);
+                    pending.fetch_sub(1, Ordering::Relaxed);
+                    freed.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        if let Err(e) = spawned {
+            // Without the thread sends fail and everything is freed inline.
+            tracing::warn!("could not start the lazyfree thread: {}", e);
+        }
+        Self {
+            tx,
+            threshold: DEFAULT_THRESHOLD,
+            pending,
+            freed,
+        }
+    }
+
+    /// Values smaller than `bytes` are freed inline; 0 frees every value in
+    /// the background.
+    pub fn with_threshold(mut self, bytes: usize) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    /// Frees a removed value: in the background when it is at least the
+    /// threshold, right away otherwise.
+    pub fn value(&self, value: Bytes) {
+        if value.len() >= self.threshold {
+            self.free(Box::new(value));
+        }
+    }
+
+    /// Frees `garbage` in the background, or right away if the thread is gone.
+    pub fn free(&self, garbage: Garbage) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if self.tx.send(garbage).is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// `lazyfree_pending_objects`: handed over and not freed yet.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// `lazyfreed_objects`: freed in the background since start.
+    pub fn freed(&self) -> u64 {
+        self.freed.load(Ordering::Relaxed)
+    }
+}
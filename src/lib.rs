@@ -0,0 +1,103 @@
+//! fedis as a library: the server the `fedis` binary runs, to embed a
+//! Redis-compatible store in another program or its tests.
+//!
+//! A [`Config`] comes from the environment as for the binary, or from
+//! [`Config::builder`]. [`Server::new`] opens the AOF and snapshot and
+//! replays them; [`Server::start`] then listens and returns a
+//! [`ServerHandle`] to run commands in-process and to shut down:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use fedis::{Config, RespValue, Server, SessionAuth};
+//!
+//! let config = Config::builder()
+//!     .listen_addr("127.0.0.1:0")
+//!     .data_path("/tmp/fedis-embedded")
+//!     .build()?;
+//! let handle = Server::new(config).await?.start().await?;
+//! let executor = handle.executor();
+//! let mut session = SessionAuth::default();
+//! let args = vec![b"SET".to_vec(), b"greeting".to_vec(), b"hello".to_vec()];
+//! let (reply, _) = executor.execute(args, &mut session).await;
+//! assert!(matches!(reply, RespValue::Simple(ok) if ok == "OK"));
+//! handle.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`testing`] runs a server in-process over in-memory connections for tests,
+//! and [`grpc::proto`] holds the client for the gRPC API.
+//!
+//! The server needs a multi-threaded Tokio runtime.
+
+mod allocator;
+mod atomic_file;
+mod audit;
+pub mod auth;
+mod backend;
+pub mod bench;
+mod changefeed;
+pub mod check;
+pub mod check_config;
+mod checksum;
+pub mod cli;
+mod clients;
+mod cluster;
+pub mod command;
+mod compression;
+pub mod config;
+pub mod convert;
+mod encoding;
+mod encryption;
+mod export;
+pub mod grpc;
+pub mod import;
+pub mod inspect;
+mod ipfilter;
+mod jsonpath;
+mod jwt;
+mod kafka;
+mod latency;
+mod lazyfree;
+mod lockout;
+pub mod logging;
+mod metrics;
+mod migration;
+mod otel;
+mod persistence;
+pub mod ping;
+mod postgres;
+pub mod protocol;
+mod pubsub;
+mod ratelimit;
+mod rdb;
+mod remote;
+mod replication;
+pub mod runtime;
+mod s3;
+mod search;
+pub mod server;
+mod slowlog;
+mod stats;
+mod statsd;
+pub mod store;
+pub mod testing;
+mod throttle;
+mod tier;
+mod timeseries;
+mod tls;
+mod tombstone;
+mod upstream;
+mod wasm;
+mod webhook;
+mod write_behind;
+
+pub use auth::SessionAuth;
+pub use command::extension::{
+    CommandExtension, ExtensionContext, ExtensionReply, register_command,
+};
+pub use command::{CommandExecutor, SessionAction};
+pub use config::{Config, ConfigBuilder};
+pub use protocol::RespValue;
+pub use server::{Server, ServerHandle};
+pub use store::Store;
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Subjects tracked at most. Once full, idle unlocked subjects are pruned on the
+/// next failure and, if that is not enough, the least recently failed unlocked ones
+/// are forgotten down to `KEPT_WHEN_FULL`, so a spray of attempts from many
+/// addresses cannot grow the map without bound. A subject still locked out is never
+/// forgotten: while locks alone fill the map, new subjects go untracked.
+const MAX_TRACKED_SUBJECTS: usize = 4096;
+const KEPT_WHEN_FULL: usize = MAX_TRACKED_SUBJECTS * 3 / 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Failures tolerated before the subject is locked out.
+    pub max_failures: u32,
+    /// Lockout after the first failure past the threshold; doubles on each further one.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Tracks failed AUTH attempts per subject (client IP or user name) and locks
+/// subjects out with exponential backoff once they exceed the policy threshold.
+pub struct AuthLockout {
+    policy: Option<LockoutPolicy>,
+    subjects: Mutex<HashMap<String, Failures>>,
+    failures: AtomicU64,
+    rejected: AtomicU64,
+}
+
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+    last_failure: Instant,
+}
+
+impl Failures {
+    fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+}
+
+impl AuthLockout {
+    pub fn new(policy: Option<LockoutPolicy>) -> Self {
+        Self {
+            policy,
+            subjects: Mutex::new(HashMap::new()),
+            failures: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Remaining lockout across `subjects`, if any of them is currently locked.
+    /// A locked attempt counts as rejected and is never checked against the password.
+    pub fn check(&self, subjects: &[String]) -> Option<Duration> {
+        self.policy?;
+        let now = Instant::now();
+        let state = self.subjects.lock().unwrap_or_else(|e| e.into_inner());
+        let remaining = subjects
+            .iter()
+            .filter_map(|s| state.get(s)?.locked_until)
+            .filter_map(|until| until.checked_duration_since(now))
+            .max()?;
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        Some(remaining)
+    }
+
+    /// Counts a failed attempt against every subject. Returns the lockout that
+    /// started because of it, if one did.
+    pub fn record_failure(&self, subjects: &[String]) -> Option<Duration> {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let policy = self.policy?;
+        let now = Instant::now();
+        let mut state = self.subjects.lock().unwrap_or_else(|e| e.into_inner());
+        if state.len() + subjects.len() > MAX_TRACKED_SUBJECTS {
+            state.retain(|_, f| {
+                f.is_locked(now) || now.duration_since(f.last_failure) < policy.max_delay
+            });
+        }
+        if state.len() + subjects.len() > MAX_TRACKED_SUBJECTS {
+            let mut oldest: Vec<(Instant, String)> = state
+                .iter()
+                .filter(|(_, f)| !f.is_locked(now))
+                .map(|(subject, f)| (f.last_failure, subject.clone()))
+                .collect();
+            let excess = state.len().saturating_sub(KEPT_WHEN_FULL).min(oldest.len());
+            if excess < oldest.len() {
+                oldest.select_nth_unstable(excess);
+            }
+            for (_, subject) in oldest.iter().take(excess) {
+                state.remove(subject);
+            }
+        }
+
+        let mut started = None;
+        for subject in subjects {
+            if state.len() >= MAX_TRACKED_SUBJECTS && !state.contains_key(subject) {
+                continue;
+            }
+            let entry = state.entry(subject.clone()).or_insert(Failures {
+                count: 0,
+                locked_until: None,
+                last_failure: now,
+            });
+            entry.count = entry.count.saturating_add(1);
+            entry.last_failure = now;
+            if entry.count > policy.max_failures {
+                let doublings = (entry.count - policy.max_failures - 1).min(31);
+                let delay = policy
+                    .base_delay
+                    .saturating_mul(1 << doublings)
+                    .min(policy.max_delay);
+                entry.locked_until = Some(now + delay);
+                started = started.max(Some(delay));
+            }
+        }
+        started
+    }
+
+    pub fn record_success(&self, subjects: &[String]) {
+        if self.policy.is_none() {
+            return;
+        }
+        let mut state = self.subjects.lock().unwrap_or_else(|e| e.into_inner());
+        for subject in subjects {
+            state.remove(subject);
+        }
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subject(i: usize) -> Vec<String> {
+        vec![format!("10.0.{}.{}", i / 256, i % 256)]
+    }
+
+    fn tracked(lockout: &AuthLockout) -> usize {
+        lockout.subjects.lock().expect("subjects").len()
+    }
+
+    #[test]
+    fn tracked_subjects_are_capped_by_evicting_the_oldest_unlocked() {
+        let lockout = AuthLockout::new(Some(LockoutPolicy {
+            max_failures: 1,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(600),
+        }));
+        let locked = subject(0);
+        lockout.record_failure(&locked);
+        assert!(lockout.record_failure(&locked).is_some());
+        for i in 1..=MAX_TRACKED_SUBJECTS * 2 {
+            lockout.record_failure(&subject(i));
+            assert!(tracked(&lockout) <= MAX_TRACKED_SUBJECTS);
+        }
+        // One failure each leaves them unlocked, so the oldest went to make
+        // room, but the locked subject stayed and the latest is tracked.
+        assert!(lockout.check(&locked).is_some());
+        let last = subject(MAX_TRACKED_SUBJECTS * 2);
+        assert!(lockout.check(&last).is_none());
+        assert!(lockout.record_failure(&last).is_some());
+    }
+
+    #[test]
+    fn locked_subjects_are_never_evicted() {
+        let lockout = AuthLockout::new(Some(LockoutPolicy {
+            max_failures: 0,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(600),
+        }));
+        for i in 0..MAX_TRACKED_SUBJECTS * 2 {
+            lockout.record_failure(&subject(i));
+            assert!(tracked(&lockout) <= MAX_TRACKED_SUBJECTS);
+        }
+        // Every subject is locked, so none made room: the first are still
+        // locked out and the ones past the cap went untracked.
+        assert!(lockout.check(&subject(0)).is_some());
+        assert!(lockout.check(&subject(MAX_TRACKED_SUBJECTS - 1)).is_some());
+        assert!(lockout.check(&subject(MAX_TRACKED_SUBJECTS)).is_none());
+    }
+}
@@ -1,19 +1,208 @@
-use tracing_subscriber::EnvFilter;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn init() -> Result<(), Box<dyn std::error::Error>> {
-    let filter =
-        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(default_filter()))?;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry, fmt, reload};
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .compact()
-        .try_init()
-        .map_err(|e| -> Box<dyn std::error::Error> { e.to_string().into() })?;
+/// Rotated files kept next to the live one, unless configured.
+pub const DEFAULT_LOG_RETAIN: usize = 7;
+
+/// Logging to `FEDIS_LOG_FILE` instead of stdout. Rotated files are renamed
+/// `<path>.1` (newest) up to `<path>.<retain>`, like logrotate's numbered
+/// scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// Rotate before a write would take the file past this size.
+    pub max_bytes: Option<u64>,
+    /// Rotate on the first write of each UTC day.
+    pub daily: bool,
+    pub retain: usize,
+}
+
+/// The live filter and the directives it was built from, for `set_level`.
+static FILTER: OnceLock<(reload::Handle<EnvFilter, Registry>, Mutex<String>)> = OnceLock::new();
+
+/// Starts the log subscriber: on stdout, or in `file` with rotation.
+pub fn init(file: Option<&LogFile>) -> Result<(), Box<dyn std::error::Error>> {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| default_filter());
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&directives)?);
+    let layer = fmt::layer().with_target(false).compact();
+    let registry = tracing_subscriber::registry().with(filter);
+    let result = match file {
+        Some(file) => registry
+            .with(
+                layer
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(RotatingFile::open(file.clone())?)),
+            )
+            .try_init(),
+        None => registry.with(layer).try_init(),
+    };
+    result.map_err(|e| -> Box<dyn std::error::Error> { e.to_string().into() })?;
+    let _ = FILTER.set((handle, Mutex::new(directives)));
 
     Ok(())
 }
 
+/// The filter directives in effect, such as `info` or `fedis=debug`.
+pub fn level() -> Option<String> {
+    let (_, directives) = FILTER.get()?;
+    Some(directives.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Swaps the filter for `directives` without restarting.
+pub fn set_level(directives: &str) -> Result<(), String> {
+    let (handle, current) = FILTER.get().ok_or("logging is not initialized")?;
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    *current.lock().unwrap_or_else(|e| e.into_inner()) = directives.to_string();
+    Ok(())
+}
+
 fn default_filter() -> String {
     std::env::var("FEDIS_LOG").unwrap_or_else(|_| "info".to_string())
 }
+
+/// An append-only log file that rotates itself as it is written.
+struct RotatingFile {
+    settings: LogFile,
+    file: File,
+    size: u64,
+    /// UTC day number the file was last written on.
+    day: u64,
+}
+
+impl RotatingFile {
+    fn open(settings: LogFile) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = open_append(&settings.path)
+            .map_err(|e| format!("{}: {}", settings.path.display(), e))?;
+        let meta = file.metadata()?;
+        // A file left over from an earlier day rotates on the first write.
+        let day = meta.modified().map(utc_day).unwrap_or_else(|_| today());
+        Ok(Self {
+            settings,
+            file,
+            size: meta.len(),
+            day,
+        })
+    }
+
+    fn rotation_due(&self, incoming: usize, today: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .settings
+            .max_bytes
+            .is_some_and(|max| self.size + incoming as u64 > max);
+        too_big || (self.settings.daily && today != self.day)
+    }
+
+    /// Shifts `<path>.n` to `<path>.n+1`, dropping the oldest past `retain`,
+    /// and starts an empty file.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        let numbered = |n: usize| {
+            let mut name = self.settings.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        let retain = self.settings.retain;
+        let _ = std::fs::remove_file(numbered(retain.max(1)));
+        if retain > 0 {
+            for n in (1..retain).rev() {
+                let _ = std::fs::rename(numbered(n), numbered(n + 1));
+            }
+            std::fs::rename(&self.settings.path, numbered(1))?;
+        } else {
+            std::fs::remove_file(&self.settings.path)?;
+        }
+        self.file = open_append(&self.settings.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let today = today();
+        // A failed rotation keeps writing to the current file rather than
+        // losing the line.
+        if self.rotation_due(buf.len(), today)
+            && let Err(e) = self.rotate()
+        {
+            eprintln!(
+                "fedis: rotating {} failed: {}",
+                self.settings.path.display(),
+                e
+            );
+        }
+        self.day = today;
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn today() -> u64 {
+    utc_day(SystemTime::now())
+}
+
+fn utc_day(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_by_size_and_day_keeping_the_newest_files() {
+        let dir = std::env::temp_dir().join(format!("fedis-log-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fedis.log");
+        let mut log = RotatingFile::open(LogFile {
+            path: path.clone(),
+            max_bytes: Some(10),
+            daily: true,
+            retain: 2,
+        })
+        .unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok();
+        assert_eq!(read("fedis.log").as_deref(), Some("fourth\n"));
+        assert_eq!(read("fedis.log.1").as_deref(), Some("third\n"));
+        assert_eq!(read("fedis.log.2").as_deref(), Some("second\n"));
+        assert_eq!(read("fedis.log.3"), None, "only `retain` files are kept");
+
+        log.settings.max_bytes = None;
+        log.write_all(b"same day\n").unwrap();
+        assert_eq!(read("fedis.log").as_deref(), Some("fourth\nsame day\n"));
+        log.day -= 1;
+        log.write_all(b"next day\n").unwrap();
+        assert_eq!(read("fedis.log").as_deref(), Some("next day\n"));
+        assert_eq!(read("fedis.log.1").as_deref(), Some("fourth\nsame day\n"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,20 +1,49 @@
-mod auth;
-mod command;
-mod config;
-mod logging;
-mod persistence;
-mod protocol;
-mod server;
-mod stats;
-mod store;
+use fedis::bench::Bench;
+use fedis::check::Check;
+use fedis::check_config::CheckConfig;
+use fedis::cli::Cli;
+use fedis::convert::Convert;
+use fedis::import::Import;
+use fedis::inspect::Inspect;
+use fedis::logging;
+use fedis::ping::Ping;
+use fedis::runtime::RuntimeConfig;
+use fedis::{Config, Server};
 
-use config::Config;
-use server::Server;
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    logging::init()?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(bench) = Bench::from_args(&args)? {
+        logging::init(None)?;
+        return RuntimeConfig::default().build()?.block_on(bench.run());
+    }
+    if let Some(cli) = Cli::from_args(&args)? {
+        return cli.run(&RuntimeConfig::default().build()?);
+    }
+    if let Some(import) = Import::from_args(&args)? {
+        return import.run(&RuntimeConfig::default().build()?);
+    }
+    if let Some(ping) = Ping::from_args(&args)? {
+        std::process::exit(ping.run(&Config::from_env()?));
+    }
+    if let Some(check) = CheckConfig::from_args(&args)? {
+        std::process::exit(check.run());
+    }
+    let check = Check::from_args(&args)?;
+    let inspect = Inspect::from_args(&args)?;
+    let convert = Convert::from_args(&args)?;
     let config = Config::from_env_and_args()?;
-    let server = Server::new(config).await?;
-    server.run().await
+    logging::init(config.log_file.as_ref())?;
+    if let Some(check) = check {
+        std::process::exit(check.run(config.encryption.as_deref()));
+    }
+    if let Some(inspect) = inspect {
+        std::process::exit(inspect.run(config.encryption.as_deref()));
+    }
+    if let Some(convert) = convert {
+        std::process::exit(convert.run(config.encryption.as_deref()));
+    }
+    config.runtime.build()?.block_on(async {
+        let server = Server::new(config).await?;
+        server.run().await
+    })
 }
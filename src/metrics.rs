@@ -0,0 +1,1135 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use serde_json::{Map, Value, json};
+
+use crate::auth::{Auth, secret_matches};
+use crate::command::CommandExecutor;
+use crate::latency::LatencyHistogram;
+use crate::logging;
+use crate::otel::percent_decode;
+use crate::slowlog::{SlowLog, SlowLogFilter};
+use crate::stats::ServerStats;
+use crate::store::Store;
+
+/// A request line and headers past this size get a 431 and the connection closes.
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+/// Request bodies past this size get a 413 and the connection closes.
+const MAX_BODY_BYTES: u64 = 8 * 1024;
+/// Keep-alive connections that send nothing for this long are closed.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+/// A TLS handshake that takes longer than this drops the connection.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who may use the listener's endpoints. `/health` and `/ready` stay open
+/// for probes; `/metrics`, `/stats` and `/slowlog` need the token or the
+/// basic-auth credentials when either is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsAccess {
+    /// Bearer token for `/metrics`, `/stats` and `/slowlog`.
+    pub token: Option<String>,
+    /// Basic-auth user and password for `/metrics`, `/stats` and `/slowlog`.
+    pub basic: Option<(String, String)>,
+    /// Bearer token for the `/admin/*` endpoints; without one they answer 404.
+    pub admin_token: Option<String>,
+}
+
+impl MetricsAccess {
+    fn is_open(&self) -> bool {
+        self.token.is_none() && self.basic.is_none()
+    }
+
+    /// Whether an `Authorization` header carries the token or the basic-auth
+    /// credentials.
+    fn admits(&self, authorization: Option<&str>) -> bool {
+        let Some((scheme, credentials)) = authorization.and_then(|v| v.split_once(' ')) else {
+            return false;
+        };
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return self
+                .token
+                .as_ref()
+                .is_some_and(|token| secret_matches(token, credentials));
+        }
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return false;
+        }
+        let Some((user, password)) = &self.basic else {
+            return false;
+        };
+        let Some(decoded) = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|v| String::from_utf8(v).ok())
+        else {
+            return false;
+        };
+        decoded.split_once(':').is_some_and(|(u, p)| {
+            // Both compared in full, so timing does not tell which was wrong.
+            secret_matches(user, u) & secret_matches(password, p)
+        })
+    }
+
+    /// The 401 for a request that `admits` turned down.
+    fn challenge(&self) -> Response {
+        let scheme = if self.basic.is_some() {
+            "Basic realm=\"fedis\""
+        } else {
+            "Bearer"
+        };
+        Response::text(401, "unauthorized\n").with_header("www-authenticate", scheme)
+    }
+}
+
+/// What the endpoints report on. The server fills it in once the store has
+/// replayed its log and the client port is listening; until then `/ready`
+/// and `/metrics` answer 503.
+pub struct MetricsSource {
+    pub executor: Arc<CommandExecutor>,
+    pub stats: Arc<ServerStats>,
+    pub store: Store,
+    pub auth: Auth,
+    /// Stands in for `fedis` at the start of metric names.
+    pub prefix: String,
+}
+
+pub type MetricsState = Arc<OnceLock<MetricsSource>>;
+
+/// Starts the HTTP listener of `FEDIS_METRICS_ADDR` before the store is
+/// loaded, so liveness probes pass during a long replay. Serves `/metrics`
+/// (Prometheus text), `/stats` (INFO as JSON), `/slowlog` (`SLOWLOG GET` as
+/// JSON), `/health` (liveness) and
+/// `/ready` (readiness), plus the `/admin/*` actions when `access` has a
+/// token for them; over TLS when `tls` is set.
+pub fn spawn_metrics_server(
+    addr: String,
+    state: MetricsState,
+    access: MetricsAccess,
+    tls: Option<TlsAcceptor>,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = run_metrics_server(addr, state, Arc::new(access), tls).await {
+            warn!(error = %e, "metrics server failed");
+        }
+    });
+}
+
+async fn run_metrics_server(
+    metrics_addr: String,
+    state: MetricsState,
+    access: Arc<MetricsAccess>,
+    tls: Option<TlsAcceptor>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(&metrics_addr).await?;
+    info!(
+        metrics_addr = %listener.local_addr()?,
+        tls = tls.is_some(),
+        auth = !access.is_open(),
+        "metrics server started"
+    );
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let (state, access, tls) = (state.clone(), access.clone(), tls.clone());
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(acceptor) => {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                        Ok(Ok(stream)) => serve_http(stream, &state, &access).await,
+                        Ok(Err(e)) => Err(e),
+                        Err(_) => return,
+                    }
+                }
+                None => serve_http(socket, &state, &access).await,
+            };
+            if let Err(e) = result {
+                debug!(peer = %peer, error = %e, "metrics connection failed");
+            }
+        });
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    keep_alive: bool,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn with_header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+}
+
+/// Answers requests on one connection until the client closes it, asks to,
+/// or goes idle.
+async fn serve_http<S>(
+    socket: S,
+    state: &OnceLock<MetricsSource>,
+    access: &MetricsAccess,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(socket);
+    let mut reader = BufReader::new(reader);
+    loop {
+        let request = match tokio::time::timeout(KEEP_ALIVE_TIMEOUT, read_request(&mut reader))
+            .await
+        {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) | Err(_) => return Ok(()),
+            Ok(Err(status)) => {
+                let body = format!("{}\n", reason(status).to_ascii_lowercase());
+                write_response(&mut writer, &Response::text(status, &body), false, true).await?;
+                return Ok(());
+            }
+        };
+        let response = route(&request, state, access).await;
+        let head_only = request.method == "HEAD";
+        write_response(&mut writer, &response, head_only, !request.keep_alive).await?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads a request line and its headers; `None` when the client closed the
+/// connection between requests, an HTTP status for a malformed request.
+async fn read_request<R>(reader: &mut R) -> Result<Option<Request>, u16>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut budget = MAX_HEAD_BYTES;
+    let Some(line) = read_line(reader, &mut budget).await? else {
+        return Ok(None);
+    };
+    let mut parts = line.split_ascii_whitespace();
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(400);
+    };
+    let mut keep_alive = match version {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => false,
+        _ => return Err(400),
+    };
+    let mut content_length = 0_u64;
+    let mut authorization = None;
+    loop {
+        let Some(header) = read_line(reader, &mut budget).await? else {
+            return Err(400);
+        };
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(400);
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("connection") {
+            if value.eq_ignore_ascii_case("close") {
+                keep_alive = false;
+            } else if value.eq_ignore_ascii_case("keep-alive") {
+                keep_alive = true;
+            }
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| 400_u16)?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_string());
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(413);
+    }
+    let mut body = Vec::with_capacity(content_length as usize);
+    reader
+        .take(content_length)
+        .read_to_end(&mut body)
+        .await
+        .map_err(|_| 400_u16)?;
+    if body.len() as u64 != content_length {
+        return Err(400);
+    }
+    let path = target.split('?').next().unwrap_or(target).to_string();
+    Ok(Some(Request {
+        method: method.to_string(),
+        path,
+        keep_alive,
+        authorization,
+        body,
+    }))
+}
+
+/// One CRLF-terminated line, charged against what is left of `budget`.
+async fn read_line<R>(reader: &mut R, budget: &mut u64) -> Result<Option<String>, u16>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    let read = reader
+        .take(*budget)
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|_| 400_u16)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if read as u64 == *budget { 431 } else { 400 });
+    }
+    *budget -= read as u64;
+    let line = String::from_utf8(line).map_err(|_| 400_u16)?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+async fn route(
+    request: &Request,
+    state: &OnceLock<MetricsSource>,
+    access: &MetricsAccess,
+) -> Response {
+    if let Some(action) = request.path.strip_prefix("/admin/") {
+        return admin(request, action, state, access).await;
+    }
+    if request.method != "GET" && request.method != "HEAD" {
+        return Response::text(405, "method not allowed\n");
+    }
+    let (path, query) = request
+        .path
+        .split_once('?')
+        .unwrap_or((request.path.as_str(), ""));
+    if matches!(path, "/metrics" | "/stats" | "/slowlog")
+        && !access.is_open()
+        && !access.admits(request.authorization.as_deref())
+    {
+        return access.challenge();
+    }
+    match (path, state.get()) {
+        ("/health", _) => Response::text(200, "ok\n"),
+        ("/ready", Some(_)) => Response::text(200, "ready\n"),
+        ("/ready" | "/metrics" | "/stats" | "/slowlog", None) => Response::text(503, "loading\n"),
+        ("/metrics", Some(source)) => Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            headers: Vec::new(),
+            body: with_prefix(
+                &format_metrics(
+                    &source.stats,
+                    &source.store,
+                    &source.auth,
+                    source.executor.slow_log(),
+                )
+                .await,
+                &source.prefix,
+            )
+            .into_bytes(),
+        },
+        ("/slowlog", Some(source)) => slowlog_json(source.executor.slow_log(), query),
+        ("/stats", Some(source)) => {
+            let info = source.executor.info_text("all").await.unwrap_or_default();
+            Response::json(200, info_to_json(&info))
+        }
+        _ => Response::text(404, "not found\n"),
+    }
+}
+
+/// `POST /admin/bgsave`, `POST /admin/bgrewriteaof` and `GET`/`PUT
+/// /admin/log-level` (the new filter directives as the body), for runbooks
+/// that drive the server over HTTP. Every answer is JSON.
+async fn admin(
+    request: &Request,
+    action: &str,
+    state: &OnceLock<MetricsSource>,
+    access: &MetricsAccess,
+) -> Response {
+    let Some(token) = &access.admin_token else {
+        return Response::text(404, "not found\n");
+    };
+    let presented = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|presented| secret_matches(token, presented.trim())) {
+        return Response::json(401, json!({"ok": false, "error": "unauthorized"}))
+            .with_header("www-authenticate", "Bearer");
+    }
+    let method = request.method.as_str();
+    match (method, action) {
+        ("GET", "log-level") => Response::json(200, json!({"ok": true, "level": logging::level()})),
+        ("PUT" | "POST", "log-level") => {
+            let level = String::from_utf8_lossy(&request.body).trim().to_string();
+            if level.is_empty() {
+                return Response::json(
+                    400,
+                    json!({"ok": false, "error": "the body must hold filter directives"}),
+                );
+            }
+            match logging::set_level(&level) {
+                Ok(()) => {
+                    info!(level = %level, "log level changed over HTTP");
+                    Response::json(200, json!({"ok": true, "level": level}))
+                }
+                Err(e) => Response::json(400, json!({"ok": false, "error": e})),
+            }
+        }
+        ("POST", "bgsave" | "bgrewriteaof") => {
+            let Some(source) = state.get() else {
+                return Response::json(503, json!({"ok": false, "error": "loading"}));
+            };
+            let (started, status, error) = if action == "bgsave" {
+                (
+                    source.store.bgsave().await,
+                    "Background saving started",
+                    "Background save already in progress or snapshots disabled",
+                )
+            } else {
+                (
+                    source.store.bgrewriteaof().await,
+                    "Background append only file rewriting started",
+                    "Background append only file rewriting already in progress",
+                )
+            };
+            info!(action, started, "admin action over HTTP");
+            if started {
+                Response::json(202, json!({"ok": true, "status": status}))
+            } else {
+                Response::json(409, json!({"ok": false, "error": error}))
+            }
+        }
+        (_, "log-level" | "bgsave" | "bgrewriteaof") => {
+            Response::json(405, json!({"ok": false, "error": "method not allowed"}))
+        }
+        _ => Response::text(404, "not found\n"),
+    }
+}
+
+/// `/slowlog?count=&user=&client=`, with the defaults and filters of
+/// `SLOWLOG GET`.
+fn slowlog_json(slowlog: &SlowLog, query: &str) -> Response {
+    let mut count = Some(10);
+    let mut filter = SlowLogFilter::default();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        match key {
+            "count" => match value.parse::<i64>() {
+                Ok(-1) => count = None,
+                Ok(n) if n >= 0 => count = Some(n as usize),
+                _ => {
+                    return Response::json(
+                        400,
+                        json!({"ok": false, "error": "count must be an integer from -1"}),
+                    );
+                }
+            },
+            "user" => filter.user = Some(value),
+            "client" => filter.client = Some(value),
+            _ => {}
+        }
+    }
+    let entries: Vec<Value> = slowlog
+        .entries(count, &filter)
+        .into_iter()
+        .map(|entry| {
+            json!({
+                "id": entry.id,
+                "timestamp": entry.timestamp,
+                "duration_usec": entry.duration_usec,
+                "args": entry
+                    .args
+                    .iter()
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect::<Vec<_>>(),
+                "client_addr": entry.client_addr,
+                "client_name": entry.client_name,
+                "user": entry.user,
+            })
+        })
+        .collect();
+    Response::json(200, json!({"len": slowlog.len(), "entries": entries}))
+}
+
+/// Turns an INFO report into `{"section": {"field": value}}`, the way Redis
+/// clients parse it: numbers become numbers, and `a=1,b=2` values such as
+/// `db0` or `cmdstat_get` become objects.
+fn info_to_json(info: &str) -> Value {
+    let mut sections = Map::new();
+    let mut current = Map::new();
+    let mut name: Option<String> = None;
+    for line in info.lines() {
+        if let Some(header) = line.strip_prefix("# ") {
+            if let Some(name) = name.replace(header.trim().to_ascii_lowercase()) {
+                sections.insert(name, Value::Object(std::mem::take(&mut current)));
+            }
+        } else if let Some((field, value)) = line.split_once(':') {
+            current.insert(field.to_string(), info_value(value));
+        }
+    }
+    if let Some(name) = name {
+        sections.insert(name, Value::Object(current));
+    }
+    Value::Object(sections)
+}
+
+fn info_value(value: &str) -> Value {
+    if value.contains('=') {
+        let pairs: Option<Map<String, Value>> = value
+            .split(',')
+            .map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                Some((key.to_string(), info_scalar(value)))
+            })
+            .collect();
+        if let Some(pairs) = pairs {
+            return Value::Object(pairs);
+        }
+    }
+    info_scalar(value)
+}
+
+fn info_scalar(value: &str) -> Value {
+    if let Ok(n) = value.parse::<i64>() {
+        return n.into();
+    }
+    if value.contains('.')
+        && let Ok(n) = value.parse::<f64>()
+        && n.is_finite()
+    {
+        return n.into();
+    }
+    value.into()
+}
+
+async fn write_response<W>(
+    writer: &mut W,
+    response: &Response,
+    head_only: bool,
+    close: bool,
+) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: {}\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        if close { "close" } else { "keep-alive" }
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut out = head.into_bytes();
+    if !head_only {
+        out.extend_from_slice(&response.body);
+    }
+    writer.write_all(&out).await?;
+    writer.flush().await
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+async fn format_metrics(
+    stats: &ServerStats,
+    store: &Store,
+    auth: &Auth,
+    slowlog: &SlowLog,
+) -> String {
+    let store_metrics = store.metrics().await;
+    let persistence = store.persistence_metrics();
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "fedis_connected_clients {}\n",
+        stats.connected_clients()
+    ));
+    out.push_str(&format!(
+        "fedis_total_connections {}\n",
+        stats.total_connections()
+    ));
+    out.push_str(&format!(
+        "fedis_ip_rejected_connections {}\n",
+        stats.ip_rejected_connections()
+    ));
+    out.push_str(&format!(
+        "fedis_total_commands {}\n",
+        stats.total_commands()
+    ));
+    out.push_str(&format!(
+        "fedis_instantaneous_ops_per_sec {}\n",
+        stats.instantaneous_ops_per_sec()
+    ));
+    out.push_str(&format!(
+        "fedis_total_command_usec {}\n",
+        stats.total_command_usec()
+    ));
+    out.push_str(&format!("fedis_keys {}\n", store_metrics.keys));
+    out.push_str(&format!(
+        "fedis_expiring_keys {}\n",
+        store_metrics.expiring_keys
+    ));
+    // One keyspace for now, labelled the way `INFO keyspace` names it.
+    out.push_str(&format!(
+        "fedis_db_keys{{db=\"db0\"}} {}\n",
+        store_metrics.keys
+    ));
+    out.push_str(&format!(
+        "fedis_db_expiring_keys{{db=\"db0\"}} {}\n",
+        store_metrics.expiring_keys
+    ));
+    out.push_str(&format!("fedis_slowlog_length {}\n", slowlog.len()));
+    out.push_str(&format!(
+        "fedis_total_slowlog_entries {}\n",
+        slowlog.total_logged()
+    ));
+    out.push_str(&format!(
+        "fedis_memory_bytes {}\n",
+        store_metrics.approx_memory_bytes
+    ));
+    out.push_str(&format!(
+        "fedis_aof_rewrite_in_progress {}\n",
+        if persistence.rewrite_in_progress {
+            1
+        } else {
+            0
+        }
+    ));
+    out.push_str(&format!(
+        "fedis_aof_rewrites {}\n",
+        persistence.rewrite_count
+    ));
+    out.push_str(&format!(
+        "fedis_aof_rewrite_failures {}\n",
+        persistence.rewrite_fail_count
+    ));
+    out.push_str(&format!(
+        "fedis_aof_pending_writes {}\n",
+        persistence.aof_pending_writes
+    ));
+    out.push_str(&format!(
+        "fedis_aof_last_fsync_age_ms {}\n",
+        persistence.aof_last_fsync_age_ms
+    ));
+    out.push_str(&format!(
+        "fedis_aof_last_write_ok {}\n",
+        if persistence.aof_last_write_ok { 1 } else { 0 }
+    ));
+    out.push_str(&format!(
+        "fedis_aof_base_size_bytes {}\n",
+        persistence.aof_base_size
+    ));
+    out.push_str(&format!(
+        "fedis_aof_current_size_bytes {}\n",
+        persistence.aof_current_size
+    ));
+    out.push_str(&format!(
+        "fedis_snapshot_changes_since_last_save {}\n",
+        persistence.changes_since_last_save
+    ));
+    out.push_str(&format!(
+        "fedis_snapshot_last_save_ok {}\n",
+        if persistence.last_snapshot_ok { 1 } else { 0 }
+    ));
+    out.push_str(&format!(
+        "fedis_snapshot_in_progress {}\n",
+        if persistence.snapshot_in_progress {
+            1
+        } else {
+            0
+        }
+    ));
+    out.push_str(&format!(
+        "fedis_snapshot_saves {}\n",
+        persistence.snapshot_count
+    ));
+    out.push_str(&format!(
+        "fedis_snapshot_failures {}\n",
+        persistence.snapshot_fail_count
+    ));
+    out.push_str(&format!(
+        "fedis_snapshot_last_save_epoch_sec {}\n",
+        persistence.last_snapshot_epoch_sec
+    ));
+
+    out.push_str(&format!(
+        "fedis_auth_failures {}\n",
+        auth.lockout().failures()
+    ));
+    out.push_str(&format!(
+        "fedis_auth_lockout_rejections {}\n",
+        auth.lockout().rejected()
+    ));
+
+    for (user, count) in stats.rate_limited_snapshot() {
+        out.push_str(&format!(
+            "fedis_rate_limited_commands{{user=\"{}\"}} {}\n",
+            user, count
+        ));
+    }
+
+    out.push_str(&format!(
+        "fedis_error_replies {}\n",
+        stats.total_error_replies()
+    ));
+    for (prefix, count) in stats.error_stats_snapshot() {
+        out.push_str(&format!("fedis_errors{{error=\"{}\"}} {}\n", prefix, count));
+    }
+    for (command, prefix, count) in stats.command_error_snapshot() {
+        out.push_str(&format!(
+            "fedis_command_errors{{command=\"{}\",error=\"{}\"}} {}\n",
+            command, prefix, count
+        ));
+    }
+
+    for (name, user, error, calls, usec) in stats.command_outcome_snapshot() {
+        let labels = format!(
+            "command=\"{}\",user=\"{}\",result=\"{}\"",
+            label_value(&name),
+            label_value(&user),
+            if error { "error" } else { "ok" }
+        );
+        out.push_str(&format!("fedis_command_calls{{{}}} {}\n", labels, calls));
+        out.push_str(&format!("fedis_command_usec{{{}}} {}\n", labels, usec));
+    }
+
+    let command_latencies = stats.command_latency_snapshot(None);
+    out.push_str("# TYPE fedis_command_latency_usec histogram\n");
+    push_histogram(
+        &mut out,
+        "fedis_command_latency_usec",
+        "",
+        &stats.command_latency(),
+        LATENCY_USEC_BOUNDS,
+    );
+    out.push_str("# TYPE fedis_command_latency_by_command_usec histogram\n");
+    for (name, latency) in &command_latencies {
+        push_histogram(
+            &mut out,
+            "fedis_command_latency_by_command_usec",
+            &format!("command=\"{}\"", name),
+            latency,
+            LATENCY_USEC_BOUNDS,
+        );
+    }
+    out.push_str("# TYPE fedis_command_latency_quantiles_usec summary\n");
+    for (name, latency) in &command_latencies {
+        push_summary(
+            &mut out,
+            "fedis_command_latency_quantiles_usec",
+            &format!("command=\"{}\"", name),
+            latency,
+        );
+    }
+    out.push_str("# TYPE fedis_connection_duration_ms histogram\n");
+    push_histogram(
+        &mut out,
+        "fedis_connection_duration_ms",
+        "",
+        &stats.connection_durations(),
+        CONNECTION_MS_BOUNDS,
+    );
+    out.push_str("# TYPE fedis_aof_fsync_latency_usec histogram\n");
+    push_histogram(
+        &mut out,
+        "fedis_aof_fsync_latency_usec",
+        "",
+        &persistence.aof_fsync_latency,
+        LATENCY_USEC_BOUNDS,
+    );
+    out
+}
+
+/// Escapes a label value per the Prometheus text format.
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renames the `fedis_` metrics of `text` to `<prefix>_`.
+fn with_prefix(text: &str, prefix: &str) -> String {
+    if prefix == "fedis" {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        let (head, rest) = match line.strip_prefix("# TYPE ") {
+            Some(rest) => ("# TYPE ", rest),
+            None => ("", line),
+        };
+        out.push_str(head);
+        match rest.strip_prefix("fedis_") {
+            Some(name) => {
+                out.push_str(prefix);
+                out.push('_');
+                out.push_str(name);
+            }
+            None => out.push_str(rest),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Histogram bounds for command and fsync latency: powers of four from 1us
+/// to about 4s. Powers of two are exact bucket edges of `LatencyHistogram`.
+pub(crate) const LATENCY_USEC_BOUNDS: &[u64] = &[
+    1, 4, 16, 64, 256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576, 4_194_304,
+];
+/// Connection lifetimes: powers of four from 1ms to about 4.7 hours.
+const CONNECTION_MS_BOUNDS: &[u64] = &[
+    1, 4, 16, 64, 256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576, 4_194_304, 16_777_216,
+];
+
+/// Writes a Prometheus histogram. Values are whole units, so the values below
+/// a bound are the ones at or below `bound - 1`; the `le` labels say so.
+fn push_histogram(
+    out: &mut String,
+    name: &str,
+    labels: &str,
+    histogram: &LatencyHistogram,
+    bounds: &[u64],
+) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    for &bound in bounds {
+        out.push_str(&format!(
+            "{}_bucket{{{}{}le=\"{}\"}} {}\n",
+            name,
+            labels,
+            sep,
+            bound - 1,
+            histogram.count_below(bound)
+        ));
+    }
+    out.push_str(&format!(
+        "{}_bucket{{{}{}le=\"+Inf\"}} {}\n",
+        name,
+        labels,
+        sep,
+        histogram.count()
+    ));
+    push_sum_and_count(out, name, labels, histogram);
+}
+
+/// Writes a Prometheus summary with the p50, p90, p99 and p99.9 quantiles.
+fn push_summary(out: &mut String, name: &str, labels: &str, histogram: &LatencyHistogram) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    for (quantile, pct) in [
+        ("0.5", 50.0),
+        ("0.9", 90.0),
+        ("0.99", 99.0),
+        ("0.999", 99.9),
+    ] {
+        out.push_str(&format!(
+            "{}{{{}{}quantile=\"{}\"}} {}\n",
+            name,
+            labels,
+            sep,
+            quantile,
+            histogram.percentile(pct)
+        ));
+    }
+    push_sum_and_count(out, name, labels, histogram);
+}
+
+fn push_sum_and_count(out: &mut String, name: &str, labels: &str, histogram: &LatencyHistogram) {
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    };
+    out.push_str(&format!("{}_sum{} {}\n", name, labels, histogram.sum()));
+    out.push_str(&format!("{}_count{} {}\n", name, labels, histogram.count()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exchange(requests: &str, state: &OnceLock<MetricsSource>) -> String {
+        exchange_with(requests, state, &MetricsAccess::default()).await
+    }
+
+    async fn exchange_with(
+        requests: &str,
+        state: &OnceLock<MetricsSource>,
+        access: &MetricsAccess,
+    ) -> String {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client.write_all(requests.as_bytes()).await.unwrap();
+        serve_http(server, state, access).await.unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        out
+    }
+
+    #[test]
+    fn info_reports_become_json_sections() {
+        let info = "# Server\nredis_version:7.2.0-fedis\nuptime_in_seconds:42\n\
+                    # Commandstats\ncmdstat_get:calls=3,usec=9,usec_per_call=3.00\n\
+                    # Persistence\naof_last_write_status:ok\n# Keyspace\ndb0:keys=2,expires=0";
+        let json = info_to_json(info);
+        assert_eq!(json["server"]["redis_version"], "7.2.0-fedis");
+        assert_eq!(json["server"]["uptime_in_seconds"], 42);
+        assert_eq!(json["commandstats"]["cmdstat_get"]["calls"], 3);
+        assert_eq!(json["commandstats"]["cmdstat_get"]["usec_per_call"], 3.0);
+        assert_eq!(json["persistence"]["aof_last_write_status"], "ok");
+        assert_eq!(json["keyspace"]["db0"]["keys"], 2);
+        assert_eq!(json.as_object().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn routes_probes_and_keeps_connections_alive() {
+        let state = OnceLock::new();
+        let out = exchange(
+            "GET /health HTTP/1.1\r\nHost: x\r\n\r\n\
+             GET /ready?verbose=1 HTTP/1.1\r\n\r\n\
+             HEAD /metrics HTTP/1.1\r\n\r\n\
+             GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n\
+             GET /health HTTP/1.1\r\n\r\n",
+            &state,
+        )
+        .await;
+        let statuses: Vec<&str> = out
+            .lines()
+            .filter(|line| line.starts_with("HTTP/1.1"))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "HTTP/1.1 200 OK",
+                "HTTP/1.1 503 Service Unavailable",
+                "HTTP/1.1 503 Service Unavailable",
+                "HTTP/1.1 404 Not Found",
+            ],
+            "the connection closes after the request asking for it"
+        );
+        assert!(out.contains("connection: keep-alive\r\n\r\nok\n"));
+        assert!(
+            out.contains("content-length: 8\r\nconnection: keep-alive\r\n\r\nHTTP/1.1 404"),
+            "HEAD has no body"
+        );
+
+        let out = exchange(
+            "GET /health HTTP/1.0\r\n\r\nGET /health HTTP/1.0\r\n\r\n",
+            &state,
+        )
+        .await;
+        assert_eq!(
+            out.matches("HTTP/1.1 200 OK").count(),
+            1,
+            "HTTP/1.0 closes by default"
+        );
+
+        let out = exchange("POST /metrics HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /health HTTP/1.1\r\nConnection: close\r\n\r\n", &state).await;
+        assert!(out.starts_with("HTTP/1.1 405 Method Not Allowed"));
+        assert!(out.ends_with("ok\n"), "the body is skipped");
+
+        let out = exchange(
+            &format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(9000)),
+            &state,
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 431"));
+        let out = exchange("garbage\r\n\r\n", &state).await;
+        assert!(out.starts_with("HTTP/1.1 400"));
+    }
+
+    #[tokio::test]
+    async fn admin_actions_need_the_bearer_token() {
+        let state = OnceLock::new();
+        let out = exchange(
+            "POST /admin/bgsave HTTP/1.1\r\nConnection: close\r\n\r\n",
+            &state,
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 404"), "off without a token");
+
+        let access = MetricsAccess {
+            admin_token: Some("s3cret".to_string()),
+            ..MetricsAccess::default()
+        };
+        let out = exchange_with(
+            "POST /admin/bgsave HTTP/1.1\r\n\r\n\
+             POST /admin/bgsave HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n\
+             POST /admin/bgsave HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n\
+             GET /admin/bgrewriteaof HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n\
+             PUT /admin/log-level HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\
+             Connection: close\r\n\r\n",
+            &state,
+            &access,
+        )
+        .await;
+        // JSON bodies end without a newline, so split on the status lines.
+        let statuses: Vec<&str> = out
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .filter_map(|response| response.lines().next())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "401 Unauthorized",
+                "401 Unauthorized",
+                "503 Service Unavailable",
+                "405 Method Not Allowed",
+                "400 Bad Request",
+            ]
+        );
+        assert!(out.contains("www-authenticate: Bearer\r\n"));
+        assert!(out.contains(r#"{"error":"loading","ok":false}"#));
+
+        let out = exchange(
+            &format!(
+                "PUT /admin/log-level HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY_BYTES + 1
+            ),
+            &state,
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 413"));
+    }
+
+    #[tokio::test]
+    async fn metrics_and_stats_need_credentials_when_configured() {
+        let state = OnceLock::new();
+        let access = MetricsAccess {
+            token: Some("scrape".to_string()),
+            basic: Some(("prom".to_string(), "pw".to_string())),
+            admin_token: None,
+        };
+        let basic = STANDARD.encode("prom:pw");
+        let wrong = STANDARD.encode("prom:nope");
+        let out = exchange_with(
+            &format!(
+                "GET /health HTTP/1.1\r\n\r\n\
+                 GET /metrics HTTP/1.1\r\n\r\n\
+                 GET /stats HTTP/1.1\r\nAuthorization: Basic {wrong}\r\n\r\n\
+                 GET /metrics HTTP/1.1\r\nAuthorization: Bearer scrape\r\n\r\n\
+                 GET /stats HTTP/1.1\r\nAuthorization: Basic {basic}\r\n\
+                 Connection: close\r\n\r\n"
+            ),
+            &state,
+            &access,
+        )
+        .await;
+        let statuses: Vec<&str> = out
+            .lines()
+            .filter(|line| line.starts_with("HTTP/1.1"))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "HTTP/1.1 200 OK",
+                "HTTP/1.1 401 Unauthorized",
+                "HTTP/1.1 401 Unauthorized",
+                "HTTP/1.1 503 Service Unavailable",
+                "HTTP/1.1 503 Service Unavailable",
+            ],
+            "probes stay open; the loading state is only told to callers who authenticate"
+        );
+        assert!(out.contains("www-authenticate: Basic realm=\"fedis\"\r\n"));
+    }
+
+    #[test]
+    fn metric_names_take_the_configured_prefix() {
+        let text = "# TYPE fedis_command_latency_usec histogram\n\
+                    fedis_command_calls{command=\"get\",user=\"fedis_app\",result=\"ok\"} 2\n\
+                    fedis_keys 3\n";
+        assert_eq!(
+            with_prefix(text, "redis"),
+            "# TYPE redis_command_latency_usec histogram\n\
+             redis_command_calls{command=\"get\",user=\"fedis_app\",result=\"ok\"} 2\n\
+             redis_keys 3\n"
+        );
+        assert_eq!(with_prefix(text, "fedis"), text);
+        assert_eq!(label_value("a\"b\\c"), "a\\\"b\\\\c");
+    }
+
+    #[test]
+    fn histograms_render_cumulative_prometheus_buckets() {
+        let mut latency = LatencyHistogram::default();
+        for usec in [0, 3, 10, 10, 700] {
+            latency.record(usec);
+        }
+        let mut out = String::new();
+        push_histogram(
+            &mut out,
+            "fedis_command_latency_by_command_usec",
+            "command=\"get\"",
+            &latency,
+            &[1, 4, 16, 1_024],
+        );
+        assert_eq!(
+            out,
+            "fedis_command_latency_by_command_usec_bucket{command=\"get\",le=\"0\"} 1\n\
+             fedis_command_latency_by_command_usec_bucket{command=\"get\",le=\"3\"} 2\n\
+             fedis_command_latency_by_command_usec_bucket{command=\"get\",le=\"15\"} 4\n\
+             fedis_command_latency_by_command_usec_bucket{command=\"get\",le=\"1023\"} 5\n\
+             fedis_command_latency_by_command_usec_bucket{command=\"get\",le=\"+Inf\"} 5\n\
+             fedis_command_latency_by_command_usec_sum{command=\"get\"} 723\n\
+             fedis_command_latency_by_command_usec_count{command=\"get\"} 5\n"
+        );
+
+        let mut out = String::new();
+        push_summary(&mut out, "fedis_aof_fsync_latency_usec", "", &latency);
+        assert!(out.starts_with("fedis_aof_fsync_latency_usec{quantile=\"0.5\"} 10\n"));
+        assert!(out.ends_with("fedis_aof_fsync_latency_usec_count 5\n"));
+    }
+}
@@ -0,0 +1,340 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::cluster::{Cluster, key_slot, node_id};
+use crate::protocol::{RespValue, encode};
+use crate::rdb::dump_payload;
+use crate::store::Store;
+
+/// What `SLOTMIGRATE` moves, and where to.
+pub struct MigrationJob {
+    pub host: String,
+    pub port: u16,
+    pub slots: Vec<u16>,
+    /// Keys sent per pipelined round trip.
+    pub batch: usize,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Running,
+    Done,
+    Failed,
+    Aborted,
+}
+
+impl MigrationState {
+    pub fn name(self) -> &'static str {
+        match self {
+            MigrationState::Running => "running",
+            MigrationState::Done => "done",
+            MigrationState::Failed => "failed",
+            MigrationState::Aborted => "aborted",
+        }
+    }
+}
+
+/// A snapshot of the latest migration, for `SLOTMIGRATE STATUS`.
+pub struct MigrationProgress {
+    pub target: String,
+    pub state: MigrationState,
+    pub slots: usize,
+    pub slots_done: usize,
+    pub keys_moved: u64,
+    pub current_slot: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// Moves the keys of hash slots to another node, one slot at a time, with
+/// `DUMP` payloads sent as pipelined `RESTORE` batches. A key is deleted here
+/// only once the target has it and if it was not written to meanwhile;
+/// otherwise the next pass over the slot sends it again.
+///
+/// In cluster mode each slot is set `IMPORTING` on the target and
+/// `MIGRATING` here first, so clients are sent `ASK` for keys already moved,
+/// and handed over with `SETSLOT NODE` on both nodes once it is empty. A
+/// failed or aborted migration leaves the slot migrating, so moved keys stay
+/// reachable until it is run again or the slot is set `STABLE`.
+#[derive(Default)]
+pub struct SlotMigrator {
+    current: Mutex<Option<Migration>>,
+}
+
+struct Migration {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    target: String,
+    slots: usize,
+    slots_done: AtomicUsize,
+    keys_moved: AtomicU64,
+    status: Mutex<Status>,
+}
+
+struct Status {
+    state: MigrationState,
+    current_slot: Option<u16>,
+    error: Option<String>,
+}
+
+impl Shared {
+    fn status(&self) -> std::sync::MutexGuard<'_, Status> {
+        self.status.lock().expect("migration status lock")
+    }
+}
+
+impl SlotMigrator {
+    /// Starts migrating in the background; refused while one is running.
+    pub fn start(
+        &self,
+        store: Store,
+        cluster: Option<Arc<Cluster>>,
+        job: MigrationJob,
+    ) -> Result<(), String> {
+        let mut current = self.lock();
+        if current
+            .as_ref()
+            .is_some_and(|migration| migration.shared.status().state == MigrationState::Running)
+        {
+            return Err("ERR a slot migration is already in progress".to_string());
+        }
+        if let Some(cluster) = &cluster {
+            let target = node_id(&job.host, job.port);
+            if !cluster.nodes().iter().any(|node| node.id == target) {
+                return Err(format!("ERR I don't know about node {target}"));
+            }
+        }
+        let shared = Arc::new(Shared {
+            target: format!("{}:{}", job.host, job.port),
+            slots: job.slots.len(),
+            slots_done: AtomicUsize::new(0),
+            keys_moved: AtomicU64::new(0),
+            status: Mutex::new(Status {
+                state: MigrationState::Running,
+                current_slot: None,
+                error: None,
+            }),
+        });
+        let task = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                let result = migrate(&store, cluster.as_deref(), &job, &shared).await;
+                let mut status = shared.status();
+                match result {
+                    Ok(()) => {
+                        info!(target = %shared.target, slots = shared.slots, "slot migration done");
+                        status.state = MigrationState::Done;
+                        status.current_slot = None;
+                    }
+                    Err(e) => {
+                        warn!(target = %shared.target, "slot migration failed: {}", e);
+                        status.state = MigrationState::Failed;
+                        status.error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+        *current = Some(Migration { shared, task });
+        Ok(())
+    }
+
+    /// Stops a running migration; false when none is running.
+    pub fn abort(&self) -> bool {
+        let current = self.lock();
+        let Some(migration) = current.as_ref() else {
+            return false;
+        };
+        let mut status = migration.shared.status();
+        if status.state != MigrationState::Running {
+            return false;
+        }
+        migration.task.abort();
+        status.state = MigrationState::Aborted;
+        true
+    }
+
+    pub fn progress(&self) -> Option<MigrationProgress> {
+        let current = self.lock();
+        let shared = &current.as_ref()?.shared;
+        let status = shared.status();
+        Some(MigrationProgress {
+            target: shared.target.clone(),
+            state: status.state,
+            slots: shared.slots,
+            slots_done: shared.slots_done.load(Ordering::Relaxed),
+            keys_moved: shared.keys_moved.load(Ordering::Relaxed),
+            current_slot: status.current_slot,
+            error: status.error.clone(),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Migration>> {
+        self.current.lock().expect("slot migrator lock")
+    }
+}
+
+/// Up to `count` keys that hash to `slot`.
+pub async fn keys_in_slot(store: &Store, slot: u16, count: usize) -> Vec<Vec<u8>> {
+    store
+        .keys(b"*")
+        .await
+        .into_iter()
+        .filter(|key| key_slot(key) == slot)
+        .take(count)
+        .collect()
+}
+
+async fn migrate(
+    store: &Store,
+    cluster: Option<&Cluster>,
+    job: &MigrationJob,
+    shared: &Shared,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut peer = Peer::connect(job).await?;
+    let target = node_id(&job.host, job.port);
+    for &slot in &job.slots {
+        shared.status().current_slot = Some(slot);
+        let slot_arg = slot.to_string().into_bytes();
+        if let Some(cluster) = cluster {
+            peer.call(vec![
+                b"CLUSTER".to_vec(),
+                b"SETSLOT".to_vec(),
+                slot_arg.clone(),
+                b"IMPORTING".to_vec(),
+                cluster.myself().id.clone().into_bytes(),
+            ])
+            .await?;
+            cluster.set_migrating(slot, &target)?;
+        }
+        loop {
+            let keys = keys_in_slot(store, slot, usize::MAX).await;
+            if keys.is_empty() {
+                break;
+            }
+            for batch in keys.chunks(job.batch) {
+                let moved = move_batch(store, &mut peer, batch, cluster.is_some()).await?;
+                shared.keys_moved.fetch_add(moved, Ordering::Relaxed);
+            }
+        }
+        if let Some(cluster) = cluster {
+            peer.call(vec![
+                b"CLUSTER".to_vec(),
+                b"SETSLOT".to_vec(),
+                slot_arg,
+                b"NODE".to_vec(),
+                target.clone().into_bytes(),
+            ])
+            .await?;
+            cluster.set_owner(slot, &target)?;
+        }
+        shared.slots_done.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Sends one batch and deletes what the target accepted; returns the number
+/// of keys moved.
+async fn move_batch(
+    store: &Store,
+    peer: &mut Peer,
+    keys: &[Vec<u8>],
+    asking: bool,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut pipeline = Vec::new();
+    let mut sent = Vec::new();
+    for key in keys {
+        let Some((kind, value, expires_at)) = store.get_with_expiry(key).await else {
+            continue;
+        };
+        if asking {
+            pipeline.extend(command(vec![b"ASKING".to_vec()]));
+        }
+        pipeline.extend(command(vec![
+            b"RESTORE".to_vec(),
+            key.clone(),
+            expires_at.unwrap_or(0).to_string().into_bytes(),
+            dump_payload(kind, &value),
+            b"REPLACE".to_vec(),
+            b"ABSTTL".to_vec(),
+        ]));
+        sent.push((key, value));
+    }
+    peer.writer.write_all(&pipeline).await?;
+    for _ in 0..sent.len() * (1 + usize::from(asking)) {
+        peer.status().await?;
+    }
+    let mut moved = 0;
+    for (key, value) in sent {
+        if store.del_if_unchanged(key, &value).await? {
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
+
+fn command(args: Vec<Vec<u8>>) -> Vec<u8> {
+    encode(RespValue::Array(
+        args.into_iter()
+            .map(|arg| RespValue::Bulk(Some(arg.into())))
+            .collect(),
+    ))
+}
+
+/// A connection to the target node. Every command sent answers with a
+/// status line.
+struct Peer {
+    address: String,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Peer {
+    async fn connect(job: &MigrationJob) -> Result<Self, Box<dyn std::error::Error>> {
+        let address = format!("{}:{}", job.host, job.port);
+        let stream = TcpStream::connect((job.host.as_str(), job.port)).await?;
+        let (read, writer) = stream.into_split();
+        let mut peer = Self {
+            address,
+            reader: BufReader::new(read),
+            writer,
+        };
+        if let Some(password) = &job.password {
+            let mut auth = vec![b"AUTH".to_vec()];
+            auth.extend(job.user.as_ref().map(|user| user.clone().into_bytes()));
+            auth.push(password.clone().into_bytes());
+            peer.call(auth).await?;
+        }
+        Ok(peer)
+    }
+
+    async fn call(&mut self, args: Vec<Vec<u8>>) -> Result<(), Box<dyn std::error::Error>> {
+        self.writer.write_all(&command(args)).await?;
+        self.status().await
+    }
+
+    async fn status(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = Vec::new();
+        (&mut self.reader)
+            .take(4096)
+            .read_until(b'\n', &mut line)
+            .await?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        match line.as_bytes().first() {
+            Some(b'+') => Ok(()),
+            Some(b'-') => Err(format!("{} replied: {}", self.address, &line[1..]).into()),
+            None => Err(format!("{} closed the connection", self.address).into()),
+            _ => Err(format!("unexpected reply from {}: '{}'", self.address, line).into()),
+        }
+    }
+}
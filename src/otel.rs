@@ -0,0 +1,935 @@
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::latency::LatencyHistogram;
+use crate::metrics::LATENCY_USEC_BOUNDS;
+use crate::s3::{read_body, read_response_head};
+use crate::stats::ServerStats;
+use crate::store::Store;
+
+/// OpenTelemetry's defaults for the batch span processor and metric reader.
+const DEFAULT_SCHEDULE_DELAY_MS: u64 = 5_000;
+const DEFAULT_MAX_QUEUE_SIZE: usize = 2_048;
+const DEFAULT_MAX_EXPORT_BATCH_SIZE: usize = 512;
+const DEFAULT_METRIC_INTERVAL_MS: u64 = 60_000;
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+/// OTLP's `SPAN_KIND_SERVER` and `STATUS_CODE_ERROR`.
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_CODE_ERROR: u8 = 2;
+/// OTLP's `AGGREGATION_TEMPORALITY_CUMULATIVE`.
+const CUMULATIVE: u8 = 2;
+
+/// OTLP export settings, read from the standard `OTEL_*` variables. fedis
+/// speaks OTLP over HTTP with JSON bodies, which every collector accepts on
+/// port 4318.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OtelConfig {
+    /// Where spans are posted; `None` when traces are off.
+    pub traces_endpoint: Option<Url>,
+    /// Where metrics are posted; `None` when metrics are off.
+    pub metrics_endpoint: Option<Url>,
+    pub headers: Vec<(String, String)>,
+    /// Resource attributes, `service.name` included.
+    pub resource: Vec<(String, String)>,
+    /// Share of commands that get a span, from 0 to 1.
+    pub sample_ratio: f64,
+    pub schedule_delay: Duration,
+    pub max_queue_size: usize,
+    pub max_export_batch_size: usize,
+    pub metric_interval: Duration,
+    pub timeout: Duration,
+}
+
+/// Builds the export settings from `setting`; `None` when no OTLP endpoint is
+/// configured or `OTEL_SDK_DISABLED` is set.
+pub fn parse_otel(
+    setting: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<OtelConfig>, Box<dyn std::error::Error>> {
+    if setting("OTEL_SDK_DISABLED").is_some_and(|v| v.trim().eq_ignore_ascii_case("true")) {
+        return Ok(None);
+    }
+    let base = setting("OTEL_EXPORTER_OTLP_ENDPOINT");
+    let endpoint = |signal: &str, path: &str| -> Result<Option<Url>, Box<dyn std::error::Error>> {
+        let upper = signal.to_ascii_uppercase();
+        match setting(&format!("OTEL_{}_EXPORTER", upper))
+            .as_deref()
+            .map(str::trim)
+        {
+            None | Some("otlp") => {}
+            Some("none") => return Ok(None),
+            Some(other) => {
+                return Err(format!(
+                    "OTEL_{}_EXPORTER must be otlp or none, not '{}'",
+                    upper, other
+                )
+                .into());
+            }
+        }
+        let protocol = setting(&format!("OTEL_EXPORTER_OTLP_{}_PROTOCOL", upper))
+            .or_else(|| setting("OTEL_EXPORTER_OTLP_PROTOCOL"));
+        if let Some(protocol) = protocol
+            && protocol.trim() != "http/json"
+        {
+            return Err(
+                format!("fedis exports OTLP as http/json, not '{}'", protocol.trim()).into(),
+            );
+        }
+        let url = match setting(&format!("OTEL_EXPORTER_OTLP_{}_ENDPOINT", upper)) {
+            Some(url) => url,
+            None => match &base {
+                Some(base) => format!("{}/{}", base.trim().trim_end_matches('/'), path),
+                None => return Ok(None),
+            },
+        };
+        let url = Url::parse(url.trim()).map_err(|e| format!("invalid OTLP endpoint: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err("OTLP endpoints must be http(s) URLs".into());
+        }
+        Ok(Some(url))
+    };
+    let traces_endpoint = endpoint("traces", "v1/traces")?;
+    let metrics_endpoint = endpoint("metrics", "v1/metrics")?;
+    if traces_endpoint.is_none() && metrics_endpoint.is_none() {
+        return Ok(None);
+    }
+
+    let headers = setting("OTEL_EXPORTER_OTLP_HEADERS")
+        .as_deref()
+        .map(parse_pairs)
+        .transpose()?
+        .unwrap_or_default();
+    let mut resource = setting("OTEL_RESOURCE_ATTRIBUTES")
+        .as_deref()
+        .map(parse_pairs)
+        .transpose()?
+        .unwrap_or_default();
+    let service_name = setting("OTEL_SERVICE_NAME").or_else(|| {
+        resource
+            .iter()
+            .find(|(key, _)| key == "service.name")
+            .map(|(_, value)| value.clone())
+    });
+    resource.retain(|(key, _)| key != "service.name");
+    resource.insert(
+        0,
+        (
+            "service.name".to_string(),
+            service_name.unwrap_or_else(|| "fedis".to_string()),
+        ),
+    );
+
+    let ratio = setting("OTEL_TRACES_SAMPLER_ARG")
+        .map(|v| match v.trim().parse::<f64>() {
+            Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+            _ => Err("OTEL_TRACES_SAMPLER_ARG must be a number from 0 to 1"),
+        })
+        .transpose()?;
+    // A `TRACEID` parent's sampled flag is not consulted, so the parent-based
+    // samplers behave like their root samplers.
+    let sample_ratio = match setting("OTEL_TRACES_SAMPLER").as_deref().map(str::trim) {
+        None | Some("always_on") | Some("parentbased_always_on") => 1.0,
+        Some("always_off") | Some("parentbased_always_off") => 0.0,
+        Some("traceidratio") | Some("parentbased_traceidratio") => ratio.unwrap_or(1.0),
+        Some(other) => return Err(format!("unsupported OTEL_TRACES_SAMPLER '{}'", other).into()),
+    };
+
+    let number = |key: &str, default: u64| -> Result<u64, Box<dyn std::error::Error>> {
+        match setting(key) {
+            Some(value) => match value.trim().parse::<u64>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(format!("{} must be a positive integer", key).into()),
+            },
+            None => Ok(default),
+        }
+    };
+    Ok(Some(OtelConfig {
+        traces_endpoint,
+        metrics_endpoint,
+        headers,
+        resource,
+        sample_ratio,
+        schedule_delay: Duration::from_millis(number(
+            "OTEL_BSP_SCHEDULE_DELAY",
+            DEFAULT_SCHEDULE_DELAY_MS,
+        )?),
+        max_queue_size: number("OTEL_BSP_MAX_QUEUE_SIZE", DEFAULT_MAX_QUEUE_SIZE as u64)? as usize,
+        max_export_batch_size: number(
+            "OTEL_BSP_MAX_EXPORT_BATCH_SIZE",
+            DEFAULT_MAX_EXPORT_BATCH_SIZE as u64,
+        )? as usize,
+        metric_interval: Duration::from_millis(number(
+            "OTEL_METRIC_EXPORT_INTERVAL",
+            DEFAULT_METRIC_INTERVAL_MS,
+        )?),
+        timeout: Duration::from_millis(number("OTEL_EXPORTER_OTLP_TIMEOUT", DEFAULT_TIMEOUT_MS)?),
+    }))
+}
+
+/// `key=value,key=value` as the OTel variables write it, values
+/// percent-decoded.
+fn parse_pairs(raw: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not key=value", pair))?;
+            Ok((key.trim().to_string(), percent_decode(value.trim())))
+        })
+        .collect()
+}
+
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// One command as a span: the connection it came in on, who sent it and
+/// whether it failed. Arguments are left out; they may hold secrets.
+pub struct CommandSpan<'a> {
+    pub command: &'a str,
+    pub elapsed: Duration,
+    pub connection_id: u64,
+    pub peer_addr: &'a str,
+    pub user: Option<&'a str>,
+    pub error: Option<&'a str>,
+    /// The client's `TRACEID`: a W3C `traceparent` or 32-hex trace id joins
+    /// that trace, anything else is kept as the `fedis.trace_id` attribute.
+    pub trace_id: Option<&'a str>,
+}
+
+struct SpanRecord {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    /// A client trace id that is not a W3C one.
+    trace_label: Option<String>,
+    name: String,
+    start_ns: u64,
+    end_ns: u64,
+    connection_id: u64,
+    peer_addr: String,
+    user: Option<String>,
+    error: Option<String>,
+}
+
+/// The running exporter: commands are queued as spans for a background task
+/// that posts them in batches, and another task posts runtime metrics on an
+/// interval. A full queue drops spans rather than slowing commands down.
+pub struct Telemetry {
+    spans: Option<mpsc::Sender<SpanRecord>>,
+    sample_ratio: f64,
+    dropped_spans: Arc<AtomicU64>,
+}
+
+impl Telemetry {
+    /// Spawns the export tasks; call from within the runtime.
+    pub fn start(
+        config: &OtelConfig,
+        stats: Arc<ServerStats>,
+        store: Store,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut resource = config.resource.clone();
+        resource.push((
+            "service.version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ));
+        resource.push((
+            "service.instance.id".to_string(),
+            stats.run_id().to_string(),
+        ));
+        let exporter = Arc::new(Exporter::new(config, &resource)?);
+        let dropped_spans = Arc::new(AtomicU64::new(0));
+
+        let spans = config.traces_endpoint.clone().map(|url| {
+            let (tx, rx) = mpsc::channel(config.max_queue_size);
+            tokio::spawn(export_spans(
+                rx,
+                exporter.clone(),
+                url,
+                config.schedule_delay,
+                config.max_export_batch_size,
+            ));
+            tx
+        });
+        if let Some(url) = config.metrics_endpoint.clone() {
+            tokio::spawn(export_metrics(
+                exporter.clone(),
+                url,
+                config.metric_interval,
+                stats,
+                store,
+                dropped_spans.clone(),
+            ));
+        }
+        info!(
+            traces = ?config.traces_endpoint.as_ref().map(Url::as_str),
+            metrics = ?config.metrics_endpoint.as_ref().map(Url::as_str),
+            "OTLP export enabled"
+        );
+        Ok(Self {
+            spans,
+            sample_ratio: config.sample_ratio,
+            dropped_spans,
+        })
+    }
+
+    pub fn record_command(&self, span: CommandSpan<'_>) {
+        let Some(spans) = &self.spans else {
+            return;
+        };
+        let mut ids = [0_u8; 24];
+        if getrandom::fill(&mut ids).is_err() {
+            return;
+        }
+        let mut trace_id = [0_u8; 16];
+        trace_id.copy_from_slice(&ids[..16]);
+        let parent = span.trace_id.and_then(parse_trace_context);
+        let mut parent_span_id = None;
+        if let Some((client_trace, client_parent)) = parent {
+            trace_id = client_trace;
+            parent_span_id = client_parent;
+        }
+        // TraceIdRatioBased: the low 8 bytes of the trace id, as a fraction of
+        // their range, decide.
+        let draw = u64::from_be_bytes(trace_id[8..].try_into().unwrap_or_default());
+        if self.sample_ratio < 1.0 && draw as f64 >= self.sample_ratio * u64::MAX as f64 {
+            return;
+        }
+        let mut span_id = [0_u8; 8];
+        span_id.copy_from_slice(&ids[16..]);
+        let end_ns = unix_nanos(SystemTime::now());
+        let record = SpanRecord {
+            trace_id,
+            span_id,
+            parent_span_id,
+            trace_label: span
+                .trace_id
+                .filter(|_| parent.is_none())
+                .map(str::to_string),
+            name: span.command.to_string(),
+            start_ns: end_ns.saturating_sub(span.elapsed.as_nanos() as u64),
+            end_ns,
+            connection_id: span.connection_id,
+            peer_addr: span.peer_addr.to_string(),
+            user: span.user.map(str::to_string),
+            error: span.error.map(str::to_string),
+        };
+        if spans.try_send(record).is_err() {
+            self.dropped_spans.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Posts OTLP bodies; the blocking I/O runs on the blocking pool.
+struct Exporter {
+    headers: Vec<(String, String)>,
+    resource: Value,
+    timeout: Duration,
+    tls: Arc<rustls::ClientConfig>,
+}
+
+impl Exporter {
+    fn new(
+        config: &OtelConfig,
+        resource: &[(String, String)],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let tls = client_tls()?;
+        Ok(Self {
+            headers: config.headers.clone(),
+            resource: json!({
+                "attributes": resource
+                    .iter()
+                    .map(|(key, value)| attribute(key, json!({ "stringValue": value })))
+                    .collect::<Vec<_>>()
+            }),
+            timeout: config.timeout,
+            tls,
+        })
+    }
+
+    async fn export(self: &Arc<Self>, url: &Url, body: Value) {
+        let exporter = self.clone();
+        let target = url.clone();
+        let body = body.to_string().into_bytes();
+        let result = tokio::task::spawn_blocking(move || exporter.post(&target, &body)).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!(url = %url, error = %e, "OTLP export failed"),
+            Err(e) => warn!(url = %url, error = %e, "OTLP export task failed"),
+        }
+    }
+
+    fn post(&self, url: &Url, body: &[u8]) -> Result<(), String> {
+        post_json(&self.tls, url, &self.headers, body, self.timeout)
+    }
+}
+
+/// Posts a JSON `body` to an `http` or `https` URL, blocking; any status
+/// outside 2xx is an error carrying the start of the response body.
+pub(crate) fn post_json(
+    tls: &Arc<rustls::ClientConfig>,
+    url: &Url,
+    headers: &[(String, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<(), String> {
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let tcp = TcpStream::connect((host.as_str(), port)).map_err(|e| format!("{}: {}", host, e))?;
+    tcp.set_read_timeout(Some(timeout))
+        .and_then(|_| tcp.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+    let mut stream: Box<dyn ReadWrite> = if url.scheme() == "http" {
+        Box::new(tcp)
+    } else {
+        let server_name =
+            rustls_pki_types::ServerName::try_from(host.clone()).map_err(|e| e.to_string())?;
+        let conn =
+            rustls::ClientConnection::new(tls.clone(), server_name).map_err(|e| e.to_string())?;
+        Box::new(rustls::StreamOwned::new(conn, tcp))
+    };
+    let host_header = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    };
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nhost: {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n",
+        &url[url::Position::BeforePath..],
+        host_header,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .and_then(|_| stream.flush())
+        .map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+    let (status, length, chunked) = read_response_head(&mut reader).map_err(|e| e.to_string())?;
+    if (200..300).contains(&status) {
+        return Ok(());
+    }
+    let mut detail = Vec::new();
+    let _ = read_body(&mut reader, length, chunked, &mut detail);
+    detail.truncate(256);
+    Err(format!(
+        "HTTP {}: {}",
+        status,
+        String::from_utf8_lossy(&detail).trim()
+    ))
+}
+
+/// A client configuration trusting the Mozilla roots.
+pub(crate) fn client_tls() -> Result<Arc<rustls::ClientConfig>, rustls::Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(tls))
+}
+
+trait ReadWrite: Read + Write + Send {}
+impl<T: Read + Write + Send> ReadWrite for T {}
+
+/// Posts queued spans once a batch is full or the schedule delay passes.
+async fn export_spans(
+    mut rx: mpsc::Receiver<SpanRecord>,
+    exporter: Arc<Exporter>,
+    url: Url,
+    delay: Duration,
+    max_batch: usize,
+) {
+    let mut ticker = tokio::time::interval(delay);
+    let mut batch = Vec::with_capacity(max_batch);
+    loop {
+        let closed = tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < max_batch {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = ticker.tick() => false,
+        };
+        if !batch.is_empty() {
+            let body = spans_body(&exporter.resource, &batch);
+            batch.clear();
+            exporter.export(&url, body).await;
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+fn spans_body(resource: &Value, batch: &[SpanRecord]) -> Value {
+    let spans: Vec<Value> = batch
+        .iter()
+        .map(|span| {
+            let mut attributes = vec![
+                attribute("db.system", json!({ "stringValue": "redis" })),
+                attribute("db.operation.name", json!({ "stringValue": span.name })),
+                attribute(
+                    "fedis.connection.id",
+                    json!({ "intValue": span.connection_id.to_string() }),
+                ),
+            ];
+            if let Some((address, port)) = span.peer_addr.rsplit_once(':') {
+                attributes.push(attribute(
+                    "client.address",
+                    json!({ "stringValue": address.trim_matches(['[', ']']) }),
+                ));
+                if let Ok(port) = port.parse::<u16>() {
+                    attributes.push(attribute(
+                        "client.port",
+                        json!({ "intValue": port.to_string() }),
+                    ));
+                }
+            }
+            if let Some(user) = &span.user {
+                attributes.push(attribute("enduser.id", json!({ "stringValue": user })));
+            }
+            if let Some(label) = &span.trace_label {
+                attributes.push(attribute("fedis.trace_id", json!({ "stringValue": label })));
+            }
+            let mut out = json!({
+                "traceId": hex(&span.trace_id),
+                "spanId": hex(&span.span_id),
+                "name": span.name,
+                "kind": SPAN_KIND_SERVER,
+                "startTimeUnixNano": span.start_ns.to_string(),
+                "endTimeUnixNano": span.end_ns.to_string(),
+                "attributes": attributes,
+            });
+            if let Some(parent) = &span.parent_span_id {
+                out["parentSpanId"] = json!(hex(parent));
+            }
+            if let Some(error) = &span.error {
+                out["status"] = json!({ "code": STATUS_CODE_ERROR, "message": error });
+            }
+            out
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": resource,
+            "scopeSpans": [{ "scope": scope(), "spans": spans }],
+        }]
+    })
+}
+
+/// Posts the server's counters, the Tokio runtime's and the command latency
+/// histograms every `interval`, cumulative since startup.
+async fn export_metrics(
+    exporter: Arc<Exporter>,
+    url: Url,
+    interval: Duration,
+    stats: Arc<ServerStats>,
+    store: Store,
+    dropped_spans: Arc<AtomicU64>,
+) {
+    let start_ns = unix_nanos(SystemTime::now());
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let store_metrics = store.metrics().await;
+        let runtime = tokio::runtime::Handle::current().metrics();
+        let now_ns = unix_nanos(SystemTime::now());
+        let gauge = |name: &str, unit: &str, value: u64| {
+            json!({
+                "name": name,
+                "unit": unit,
+                "gauge": { "dataPoints": [{ "asInt": value.to_string(), "timeUnixNano": now_ns.to_string() }] }
+            })
+        };
+        let counter = |name: &str, unit: &str, value: u64| {
+            json!({
+                "name": name,
+                "unit": unit,
+                "sum": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "isMonotonic": true,
+                    "dataPoints": [{
+                        "asInt": value.to_string(),
+                        "startTimeUnixNano": start_ns.to_string(),
+                        "timeUnixNano": now_ns.to_string(),
+                    }]
+                }
+            })
+        };
+        let latencies = stats.command_latency_snapshot(None);
+        let metrics = vec![
+            gauge(
+                "fedis.clients.connected",
+                "{client}",
+                stats.connected_clients() as u64,
+            ),
+            counter(
+                "fedis.connections",
+                "{connection}",
+                stats.total_connections(),
+            ),
+            counter("fedis.commands", "{command}", stats.total_commands()),
+            gauge("fedis.keys", "{key}", store_metrics.keys as u64),
+            gauge(
+                "fedis.memory.used",
+                "By",
+                store_metrics.approx_memory_bytes as u64,
+            ),
+            gauge(
+                "fedis.runtime.workers",
+                "{thread}",
+                runtime.num_workers() as u64,
+            ),
+            gauge(
+                "fedis.runtime.alive_tasks",
+                "{task}",
+                runtime.num_alive_tasks() as u64,
+            ),
+            gauge(
+                "fedis.runtime.global_queue_depth",
+                "{task}",
+                runtime.global_queue_depth() as u64,
+            ),
+            counter(
+                "fedis.otel.dropped_spans",
+                "{span}",
+                dropped_spans.load(Ordering::Relaxed),
+            ),
+            json!({
+                "name": "fedis.command.duration",
+                "unit": "us",
+                "histogram": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "dataPoints": latencies
+                        .iter()
+                        .map(|(command, latency)| histogram_point(command, latency, start_ns, now_ns))
+                        .collect::<Vec<_>>(),
+                }
+            }),
+        ];
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": exporter.resource,
+                "scopeMetrics": [{ "scope": scope(), "metrics": metrics }],
+            }]
+        });
+        exporter.export(&url, body).await;
+    }
+}
+
+/// A data point with the `/metrics` bounds. Values are whole microseconds,
+/// so the ones below a bound are those up to `bound - 1`.
+fn histogram_point(command: &str, latency: &LatencyHistogram, start_ns: u64, now_ns: u64) -> Value {
+    let mut counts = Vec::with_capacity(LATENCY_USEC_BOUNDS.len() + 1);
+    let mut below = 0;
+    for &bound in LATENCY_USEC_BOUNDS {
+        let cumulative = latency.count_below(bound);
+        counts.push((cumulative - below).to_string());
+        below = cumulative;
+    }
+    counts.push((latency.count() - below).to_string());
+    json!({
+        "attributes": [attribute("db.operation.name", json!({ "stringValue": command }))],
+        "startTimeUnixNano": start_ns.to_string(),
+        "timeUnixNano": now_ns.to_string(),
+        "count": latency.count().to_string(),
+        "sum": latency.sum() as f64,
+        "bucketCounts": counts,
+        "explicitBounds": LATENCY_USEC_BOUNDS.iter().map(|&bound| (bound - 1) as f64).collect::<Vec<_>>(),
+    })
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn scope() -> Value {
+    json!({ "name": "fedis", "version": env!("CARGO_PKG_VERSION") })
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// The trace id, and parent span id if given, of a W3C `traceparent`
+/// (`00-<trace id>-<parent id>-<flags>`) or a bare 32-hex trace id. All-zero
+/// ids are invalid.
+fn parse_trace_context(value: &str) -> Option<([u8; 16], Option<[u8; 8]>)> {
+    let (trace, parent) = match value.split('-').collect::<Vec<_>>()[..] {
+        [trace] => (trace, None),
+        ["00", trace, parent, flags] if flags.len() == 2 => (trace, Some(parent)),
+        _ => return None,
+    };
+    let trace: [u8; 16] = unhex(trace)?;
+    let parent = match parent {
+        Some(parent) => Some(unhex::<8>(parent).filter(|id| *id != [0; 8])?),
+        None => None,
+    };
+    (trace != [0; 16]).then_some((trace, parent))
+}
+
+fn unhex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 {
+        return None;
+    }
+    let mut out = [0_u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(vars: &[(&str, &str)]) -> Result<Option<OtelConfig>, Box<dyn std::error::Error>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        parse_otel(&|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn client_trace_ids_join_w3c_traces() {
+        let (trace, parent) =
+            parse_trace_context("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").unwrap();
+        assert_eq!(hex(&trace), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(parent.map(|p| hex(&p)).as_deref(), Some("b7ad6b7169203331"));
+        let (trace, parent) = parse_trace_context("0af7651916cd43dd8448eb211c80319c").unwrap();
+        assert_eq!(trace[0], 0x0a);
+        assert_eq!(parent, None);
+        assert_eq!(parse_trace_context(&"0".repeat(32)), None);
+        assert_eq!(parse_trace_context("order-1234"), None);
+    }
+
+    #[test]
+    fn reads_the_standard_otel_variables() {
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(
+            parse(&[
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+                ("OTEL_SDK_DISABLED", "true"),
+            ])
+            .unwrap(),
+            None
+        );
+
+        let config = parse(&[
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318/"),
+            ("OTEL_METRICS_EXPORTER", "none"),
+            (
+                "OTEL_EXPORTER_OTLP_HEADERS",
+                "Authorization=Basic%20abc, x-team=cache",
+            ),
+            (
+                "OTEL_RESOURCE_ATTRIBUTES",
+                "service.name=ignored,deployment.environment=prod",
+            ),
+            ("OTEL_SERVICE_NAME", "sessions-cache"),
+            ("OTEL_TRACES_SAMPLER", "parentbased_traceidratio"),
+            ("OTEL_TRACES_SAMPLER_ARG", "0.25"),
+            ("OTEL_BSP_SCHEDULE_DELAY", "1000"),
+        ])
+        .unwrap()
+        .expect("export enabled");
+        assert_eq!(
+            config.traces_endpoint.as_ref().map(Url::as_str),
+            Some("http://collector:4318/v1/traces")
+        );
+        assert_eq!(config.metrics_endpoint, None);
+        assert_eq!(
+            config.headers[0],
+            ("Authorization".to_string(), "Basic abc".to_string())
+        );
+        assert_eq!(
+            config.resource[0],
+            ("service.name".to_string(), "sessions-cache".to_string())
+        );
+        assert_eq!(config.resource.len(), 2);
+        assert_eq!(config.sample_ratio, 0.25);
+        assert_eq!(config.schedule_delay, Duration::from_secs(1));
+        assert_eq!(config.max_queue_size, DEFAULT_MAX_QUEUE_SIZE);
+
+        let config = parse(&[(
+            "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT",
+            "https://otlp.example.com/v1/metrics",
+        )])
+        .unwrap()
+        .expect("metrics only");
+        assert_eq!(config.traces_endpoint, None);
+        assert_eq!(config.resource[0].1, "fedis");
+
+        assert!(
+            parse(&[
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+                ("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc"),
+            ])
+            .is_err()
+        );
+        assert!(
+            parse(&[
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+                ("OTEL_TRACES_SAMPLER_ARG", "2"),
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn posts_command_spans_as_otlp_json() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/v1/traces",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let collector = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0_u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .and_then(|v| v.parse().ok())
+                        .unwrap();
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let config = parse(&[
+            ("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT", url.as_str()),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "x-api-key=secret"),
+        ])
+        .unwrap()
+        .unwrap();
+        let exporter = Exporter::new(&config, &config.resource).unwrap();
+        let span = SpanRecord {
+            trace_id: [1; 16],
+            span_id: [2; 8],
+            parent_span_id: Some([3; 8]),
+            trace_label: None,
+            name: "GET".to_string(),
+            start_ns: 1_000,
+            end_ns: 2_500,
+            connection_id: 7,
+            peer_addr: "10.0.0.1:4000".to_string(),
+            user: Some("app".to_string()),
+            error: Some("WRONGTYPE".to_string()),
+        };
+        let body = spans_body(&exporter.resource, &[span]).to_string();
+        exporter.post(&url, body.as_bytes()).unwrap();
+
+        let request = collector.join().unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        assert!(head.contains("\r\nx-api-key: secret"));
+        let body: Value = serde_json::from_str(body).unwrap();
+        let resource_spans = &body["resourceSpans"][0];
+        assert_eq!(
+            resource_spans["resource"]["attributes"][0]["value"]["stringValue"],
+            "fedis"
+        );
+        let span = &resource_spans["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "01".repeat(16));
+        assert_eq!(span["parentSpanId"], "03".repeat(8));
+        assert_eq!(span["name"], "GET");
+        assert_eq!(span["kind"], 2);
+        assert_eq!(span["endTimeUnixNano"], "2500");
+        assert_eq!(span["status"]["code"], 2);
+        let attributes: Vec<&str> = span["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["key"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                "db.system",
+                "db.operation.name",
+                "fedis.connection.id",
+                "client.address",
+                "client.port",
+                "enduser.id"
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_points_hold_per_bucket_counts() {
+        let mut latency = LatencyHistogram::default();
+        for usec in [3, 10, 10, 700, 10_000_000] {
+            latency.record(usec);
+        }
+        let point = histogram_point("get", &latency, 1, 2);
+        let counts: Vec<u64> = point["bucketCounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c.as_str().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(counts.len(), LATENCY_USEC_BOUNDS.len() + 1);
+        assert_eq!(counts.iter().sum::<u64>(), 5);
+        assert_eq!(counts[1], 1, "3us is below 4");
+        assert_eq!(counts[2], 2, "10us is below 16");
+        assert_eq!(*counts.last().unwrap(), 1, "past the last bound");
+    }
+}
@@ -1,15 +1,56 @@
-use std::io::{ErrorKind, Read};
+use bytes::Bytes;
+use std::io::{ErrorKind, Read, Write};
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
-const MAGIC: &[u8] = b"FDLOG1";
+use tracing::warn;
+
+use crate::atomic_file::AtomicFile;
+use crate::checksum::crc64;
+use crate::compression::Compression;
+use crate::encoding::{ValueType, read_value_header, write_value_header};
+use crate::encryption::{FileKey, Keyring, SALT_LEN};
+use crate::latency::LatencyHistogram;
+use crate::protocol::{RespValue, encode};
+use crate::timeseries::{self, Series};
+
+/// Every record is followed by the CRC-64 of its payload.
+const MAGIC: &[u8] = b"FDLOG2";
+/// The original format without record checksums; still read, rewritten on startup.
+const LEGACY_MAGIC: &[u8] = b"FDLOG1";
+/// Encrypted logs name the key and salt of the subkey their records are sealed
+/// under after the magic, and frame every record as `nonce | ciphertext`
+/// instead, bound to its offset in the file; the AEAD tag takes the place of
+/// the checksum.
+const ENCRYPTED_MAGIC: &[u8] = b"FDLOGK";
+/// Encrypted logs that sealed every record as `key_id | nonce | ciphertext`
+/// under the key itself; still read, rewritten on startup.
+const LEGACY_ENCRYPTED_MAGIC: &[u8] = b"FDLOGE";
+/// Magic, key id and salt.
+const ENCRYPTED_HEADER_LEN: usize = 6 + 4 + SALT_LEN;
 const OP_SET: u8 = 1;
 const OP_DEL: u8 = 2;
 const OP_EXPIRE: u8 = 3;
 const OP_PERSIST: u8 = 4;
+/// A SET whose value is stored compressed, with the algorithm tag before it.
+const OP_SET_COMPRESSED: u8 = 5;
+/// Not a change: the unix time (seconds) of the records that follow it.
+const OP_TIMESTAMP: u8 = 6;
+/// A SET of any value type: `key | type | version | compression tag | value |
+/// expiry`. Replaces OP_SET and OP_SET_COMPRESSED, which are still read.
+const OP_SET_TYPED: u8 = 7;
+/// A sample added to a time series: `key | timestamp | value bits`.
+const OP_TS_ADD: u8 = 8;
+/// Redis-format logs start by selecting database 0, like Redis does itself.
+const RESP_PREAMBLE: &[u8] = b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n";
+/// Most appends the writer task joins into one write (and one fsync).
+const GROUP_COMMIT_MAX_WRITES: u64 = 256;
 
 #[derive(Clone, Copy)]
 pub enum AofFsync {
@@ -18,19 +59,101 @@ pub enum AofFsync {
     No,
 }
 
+/// On-disk layout of the log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AofFormat {
+    /// Length-prefixed, checksummed binary records (`FDLOG2`), optionally encrypted.
+    Fedis,
+    /// Plain RESP commands that Redis can replay and `redis-check-aof` understands.
+    Resp,
+}
+
+enum WriterMsg {
+    /// Records for the writer to frame: payloads in the fedis format, whole
+    /// commands in the Redis one. Under `appendfsync always` the sender waits on the
+    /// channel for whether they were written and fsynced.
+    Write(Vec<Vec<u8>>, Option<oneshot::Sender<bool>>),
+    /// Answered once every write queued before it is in the file.
+    Drained(oneshot::Sender<()>),
+}
+
+/// The open log, with the subkey its records are sealed under when it is
+/// encrypted.
+struct LogFile {
+    file: tokio::fs::File,
+    key: Option<FileKey>,
+}
+
 #[derive(Clone)]
 pub struct Aof {
-    inner: std::sync::Arc<Mutex<tokio::fs::File>>,
+    inner: std::sync::Arc<Mutex<LogFile>>,
     path: std::path::PathBuf,
     fsync: AofFsync,
-    tx: Option<mpsc::Sender<Vec<u8>>>,
+    tx: mpsc::Sender<WriterMsg>,
+    /// Writes handed to the background writer but not yet in the file.
+    pending: Arc<AtomicU64>,
+    last_fsync_ms: Arc<AtomicU64>,
+    /// How long each fsync of the log took, in microseconds.
+    fsync_latency: Arc<std::sync::Mutex<LatencyHistogram>>,
+    /// Whether the most recent write to the file succeeded.
+    last_write_ok: Arc<AtomicBool>,
+    /// File size after startup or the last rewrite (Redis' `aof_base_size`).
+    base_size: Arc<AtomicU64>,
+    keyring: Option<Arc<Keyring>>,
+    format: AofFormat,
+    /// Load up to the last valid record when the tail of the log is torn or
+    /// corrupt, instead of refusing to start (Redis' `aof-load-truncated`).
+    load_truncated: bool,
+    /// Compress SET values of at least this many bytes (fedis format only).
+    compression: Option<(Compression, usize)>,
+    /// Write a timestamp annotation whenever the second changes between records
+    /// (Redis' `aof-timestamp-enabled`).
+    timestamps: bool,
+    last_timestamp: Arc<AtomicU64>,
+    /// Point-in-time recovery: load only the records annotated at or before this
+    /// unix time and cut the rest off.
+    recover_to: Option<u64>,
+    /// The file on disk is in the other (plain vs encrypted) format than the one
+    /// configured; the store rewrites it after replay.
+    format_mismatch: bool,
+    /// While a rewrite runs, every record the writer appends, so the new log
+    /// gets the writes made after the keyspace was frozen for it (Redis'
+    /// AOF rewrite buffer). Unframed, as the new log frames them its own way.
+    rewrite_buffer: Arc<std::sync::Mutex<Option<Vec<Vec<u8>>>>>,
+    /// Held by the rewrite in progress, so there is one at a time.
+    rewrite_lock: Arc<Mutex<()>>,
+}
+
+/// A rewrite started by `Aof::begin_rewrite`: the keyspace goes into a new
+/// log with `write_snapshot`, and `commit` swaps that log in.
+pub struct AofRewrite {
+    aof: Aof,
+    file: AtomicFile,
+    /// The new log's own subkey when it is encrypted.
+    key: Option<FileKey>,
+    /// Bytes in `file` so far.
+    written: u64,
+    capture: Capture,
+}
+
+/// Keeps the rewrite buffer filling until dropped.
+struct Capture {
+    buffer: Arc<std::sync::Mutex<Option<Vec<Vec<u8>>>>>,
+    _rewriting: tokio::sync::OwnedMutexGuard<()>,
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        *self.buffer.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum LogRecord {
     Set {
         key: Vec<u8>,
-        value: Vec<u8>,
+        kind: ValueType,
+        value: Bytes,
         expires_at: Option<u64>,
     },
     Del {
@@ -43,158 +166,478 @@ pub enum LogRecord {
     Persist {
         key: Vec<u8>,
     },
+    /// A sample added to an existing time series, which is written whole
+    /// only when it is created.
+    TsAdd {
+        key: Vec<u8>,
+        timestamp: u64,
+        value: f64,
+    },
 }
 
 impl Aof {
-    pub async fn open(path: &Path, fsync: AofFsync) -> Result<Self, Box<dyn std::error::Error>> {
-        let exists = std::fs::metadata(path).is_ok();
-        if !exists {
-            std::fs::write(path, MAGIC)?;
+    /// Opens the log, encrypting new records when a keyring is given. Encryption
+    /// only applies to the fedis format.
+    pub async fn open(
+        path: &Path,
+        fsync: AofFsync,
+        keyring: Option<Arc<Keyring>>,
+        format: AofFormat,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if keyring.is_some() && format == AofFormat::Resp {
+            return Err("AOF encryption is not available with the Redis AOF format".into());
+        }
+        if std::fs::metadata(path).is_err() {
+            let key = keyring.as_deref().map(Keyring::new_file_key).transpose()?;
+            std::fs::write(path, header(format, key.as_ref()))?;
         }
+        let magic = magic(format, keyring.is_some());
+        let stored_key = match keyring.as_deref() {
+            Some(keyring) => stored_file_key(path, keyring)?,
+            None => None,
+        };
+        // A log sealed under a retired key is rewritten under the active one.
+        let format_mismatch = read_magic(path)?.is_some_and(|found| found != magic[..MAGIC.len()])
+            || stored_key
+                .as_ref()
+                .zip(keyring.as_deref())
+                .is_some_and(|(key, keyring)| key.id() != keyring.active());
+        // A log the keyring cannot open fails to load; one in the other format
+        // is rewritten before anything is appended. Either way the subkey
+        // appends would use is a fresh one.
+        let key = match stored_key {
+            Some(key) => Some(key),
+            None => keyring.as_deref().map(Keyring::new_file_key).transpose()?,
+        };
 
         let file = OpenOptions::new()
             .append(true)
             .create(true)
             .open(path)
             .await?;
-        let mut tx = None;
-        if matches!(fsync, AofFsync::EverySec | AofFsync::No) {
-            let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(4096);
-            let inner = std::sync::Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(path)
-                    .await?,
-            ));
-            let write_inner = inner.clone();
-            tokio::spawn(async move {
-                while let Some(mut batch) = receiver.recv().await {
-                    let mut took = 1usize;
-                    while took < 256 {
-                        match receiver.try_recv() {
-                            Ok(next) => {
-                                batch.extend_from_slice(&next);
-                                took += 1;
-                            }
-                            Err(_) => break,
+        let inner = std::sync::Arc::new(Mutex::new(LogFile { file, key }));
+        let pending = Arc::new(AtomicU64::new(0));
+        let last_fsync_ms = Arc::new(AtomicU64::new(now_ms()));
+        let fsync_latency = Arc::new(std::sync::Mutex::new(LatencyHistogram::default()));
+        let last_write_ok = Arc::new(AtomicBool::new(true));
+        // Every append goes through one writer task. Appends that queue up
+        // while it writes are written together, and under `always` fsynced
+        // together: a group commit, where each append still waits for its
+        // own records to be durable before its command is answered.
+        let (tx, mut receiver) = mpsc::channel::<WriterMsg>(4096);
+        let write_inner = inner.clone();
+        let write_pending = pending.clone();
+        let write_ok = last_write_ok.clone();
+        let write_fsync_ms = last_fsync_ms.clone();
+        let write_fsync_latency = fsync_latency.clone();
+        let rewrite_buffer = Arc::new(std::sync::Mutex::new(None::<Vec<Vec<u8>>>));
+        let write_rewrite_buffer = rewrite_buffer.clone();
+        tokio::spawn(async move {
+            while let Some(first) = receiver.recv().await {
+                let mut batch = Vec::new();
+                let mut writes = 0_u64;
+                let mut acks = Vec::new();
+                let mut waiters = Vec::new();
+                let mut next = Some(first);
+                while let Some(msg) = next.take() {
+                    match msg {
+                        WriterMsg::Write(records, ack) => {
+                            batch.extend(records);
+                            writes += 1;
+                            acks.extend(ack);
                         }
+                        WriterMsg::Drained(waiter) => waiters.push(waiter),
+                    }
+                    if writes < GROUP_COMMIT_MAX_WRITES {
+                        next = receiver.try_recv().ok();
                     }
-                    let mut file = write_inner.lock().await;
-                    let _ = file.write_all(&batch).await;
                 }
-            });
-            tx = Some(sender);
-            let aof = Self {
-                inner,
-                path: path.to_path_buf(),
-                fsync,
-                tx,
-            };
-
-            if matches!(fsync, AofFsync::EverySec) {
-                let inner = aof.inner.clone();
-                tokio::spawn(async move {
-                    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
-                    loop {
-                        interval.tick().await;
-                        let mut file = inner.lock().await;
-                        let _ = file.flush().await;
-                        let _ = file.sync_data().await;
+                let mut log = write_inner.lock().await;
+                let mut written = match log.frame(format, &batch).await {
+                    Ok(wire) => {
+                        if let Some(captured) = write_rewrite_buffer
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .as_mut()
+                        {
+                            captured.extend(batch);
+                        }
+                        log.file.write_all(&wire).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if written.is_ok() && matches!(fsync, AofFsync::Always) {
+                    written = match log.file.flush().await {
+                        Ok(()) => timed_sync(&mut log.file, &write_fsync_latency).await,
+                        Err(e) => Err(e),
+                    };
+                    if written.is_ok() {
+                        write_fsync_ms.store(now_ms(), Ordering::Relaxed);
                     }
-                });
+                }
+                drop(log);
+                if let Err(e) = &written {
+                    warn!(error = %e, "AOF write failed");
+                }
+                write_ok.store(written.is_ok(), Ordering::Relaxed);
+                write_pending.fetch_sub(writes, Ordering::Relaxed);
+                for ack in acks {
+                    let _ = ack.send(written.is_ok());
+                }
+                for waiter in waiters {
+                    let _ = waiter.send(());
+                }
             }
-
-            return Ok(aof);
-        }
-
-        let aof = Self {
-            inner: std::sync::Arc::new(Mutex::new(file)),
-            path: path.to_path_buf(),
-            fsync,
-            tx,
-        };
+        });
 
         if matches!(fsync, AofFsync::EverySec) {
-            let inner = aof.inner.clone();
+            let inner = inner.clone();
+            let last_fsync_ms = last_fsync_ms.clone();
+            let fsync_latency = fsync_latency.clone();
             tokio::spawn(async move {
                 let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
                 loop {
                     interval.tick().await;
-                    let mut file = inner.lock().await;
-                    let _ = file.flush().await;
-                    let _ = file.sync_data().await;
+                    let mut log = inner.lock().await;
+                    let _ = log.file.flush().await;
+                    if timed_sync(&mut log.file, &fsync_latency).await.is_ok() {
+                        last_fsync_ms.store(now_ms(), Ordering::Relaxed);
+                    }
                 }
             });
         }
 
-        Ok(aof)
+        Ok(Self {
+            inner,
+            path: path.to_path_buf(),
+            fsync,
+            tx,
+            pending,
+            last_fsync_ms,
+            fsync_latency,
+            last_write_ok,
+            base_size: Arc::new(AtomicU64::new(std::fs::metadata(path)?.len())),
+            keyring,
+            format,
+            load_truncated: true,
+            compression: None,
+            timestamps: false,
+            last_timestamp: Arc::new(AtomicU64::new(0)),
+            recover_to: None,
+            format_mismatch,
+            rewrite_buffer,
+            rewrite_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn with_load_truncated(mut self, enabled: bool) -> Self {
+        self.load_truncated = enabled;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression, min_bytes: usize) -> Self {
+        self.compression = (compression != Compression::None).then_some((compression, min_bytes));
+        self
+    }
+
+    pub fn with_timestamps(mut self, enabled: bool) -> Self {
+        self.timestamps = enabled;
+        self
     }
 
+    pub fn with_recover_to(mut self, unix_sec: Option<u64>) -> Self {
+        self.recover_to = unix_sec;
+        self
+    }
+
+    /// Whether startup is rolling the log back to `recover_to`.
+    pub fn recovering(&self) -> bool {
+        self.recover_to.is_some()
+    }
+
+    /// Reads every record. A torn or corrupt tail is cut off with a warning when
+    /// `load_truncated` is on, and is an error otherwise.
     pub fn read_all(&self) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>> {
-        Self::read_all_from_path(&self.path)
+        let mut loaded = Self::read_all_from_path(&self.path, self.keyring.as_deref())?;
+        if let Some((offset, reason)) = loaded.corrupt {
+            return Err(format!("AOF record at offset {} is corrupt ({})", offset, reason).into());
+        }
+        if let Some((offset, reason)) = loaded.bad_tail.take() {
+            if !self.load_truncated {
+                return Err(format!(
+                    "AOF {} is truncated at offset {} ({}); set FEDIS_AOF_LOAD_TRUNCATED=yes to load it up to the last valid record",
+                    self.path.display(),
+                    offset,
+                    reason
+                )
+                .into());
+            }
+            warn!(
+                path = %self.path.display(),
+                offset,
+                reason,
+                records = loaded.records.len(),
+                "AOF tail is truncated; loaded up to the last valid record and cut the rest"
+            );
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&self.path)?
+                .set_len(offset as u64)?;
+        }
+        if let Some(target) = self.recover_to {
+            self.recover(&mut loaded, target)?;
+        }
+        self.base_size.store(self.current_size(), Ordering::Relaxed);
+        Ok(loaded.records)
     }
 
-    pub async fn append(&self, record: LogRecord) -> Result<(), Box<dyn std::error::Error>> {
-        let payload = encode_record(record);
-        let mut wire = Vec::with_capacity(4 + payload.len());
-        wire.extend_from_slice(&(payload.len() as u32).to_be_bytes());
-        wire.extend_from_slice(&payload);
-
-        if let Some(tx) = &self.tx {
-            tx.send(wire)
-                .await
-                .map_err(|_| "AOF writer task is not available")?;
+    /// Drops every record after the first timestamp annotation later than
+    /// `target`, keeping the original log next to the file as a backup.
+    fn recover(
+        &self,
+        loaded: &mut LoadedLog,
+        target: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let backup = self.path.with_extension("aof.before-recovery");
+        if backup.exists() {
+            return Err(format!(
+                "{} already exists; unset FEDIS_RECOVER_TO_TS after a recovery, or remove the backup to recover again",
+                backup.display()
+            )
+            .into());
+        }
+        let Some(&(_, _, oldest)) = loaded.timestamps.first() else {
+            return Err(
+                "AOF has no timestamp annotations; point-in-time recovery needs FEDIS_AOF_TIMESTAMPS=yes"
+                    .into(),
+            );
+        };
+        if target < oldest {
+            return Err(format!(
+                "FEDIS_RECOVER_TO_TS {} is older than the oldest AOF timestamp {}; the log only reaches back to its last rewrite",
+                target, oldest
+            )
+            .into());
+        }
+        let Some(&(records, offset, _)) = loaded.timestamps.iter().find(|(_, _, ts)| *ts > target)
+        else {
+            warn!(
+                target,
+                "AOF has no records after the recovery target; nothing to cut"
+            );
             return Ok(());
+        };
+        std::fs::copy(&self.path, &backup)?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)?
+            .set_len(offset as u64)?;
+        warn!(
+            target,
+            kept = records,
+            dropped = loaded.records.len() - records,
+            backup = %backup.display(),
+            "recovered AOF to point in time; unset FEDIS_RECOVER_TO_TS before the next restart"
+        );
+        loaded.records.truncate(records);
+        Ok(())
+    }
+
+    /// Validates a log without opening it for writing, for `fedis --check-aof`.
+    pub fn check(
+        path: &Path,
+        keyring: Option<&Keyring>,
+    ) -> Result<AofCheck, Box<dyn std::error::Error>> {
+        let file_len = std::fs::metadata(path)?.len();
+        let loaded = Self::read_all_from_path(path, keyring)?;
+        Ok(AofCheck {
+            file_len,
+            records: loaded.records.len(),
+            timestamps: loaded.timestamps.len(),
+            first_bad: loaded.first_bad(),
+        })
+    }
+
+    /// The valid records of a log, read without opening it for writing, for
+    /// `fedis dump` and `fedis keys`; with the first invalid record, where
+    /// reading stopped, as `check` reports it.
+    pub fn read_offline(
+        path: &Path,
+        keyring: Option<&Keyring>,
+    ) -> Result<OfflineLog, Box<dyn std::error::Error>> {
+        let loaded = Self::read_all_from_path(path, keyring)?;
+        Ok(OfflineLog {
+            first_bad: loaded.first_bad(),
+            records: loaded.records,
+        })
+    }
+
+    pub fn keyring(&self) -> Option<Arc<Keyring>> {
+        self.keyring.clone()
+    }
+
+    pub fn format_mismatch(&self) -> bool {
+        self.format_mismatch
+    }
+
+    /// Encodes one record, for the writer to frame.
+    fn encode(&self, record: LogRecord) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.format == AofFormat::Resp {
+            return Ok(encode_resp_record(record));
         }
+        Ok(encode_record(record, self.compression)?)
+    }
 
-        let mut file = self.inner.lock().await;
-        file.write_all(&wire).await?;
-        match self.fsync {
-            AofFsync::Always => {
-                file.flush().await?;
-                file.sync_data().await?;
-            }
-            AofFsync::EverySec | AofFsync::No => {}
+    /// Encodes a timestamp annotation; Redis writes these as `#TS:<unix>` lines.
+    fn encode_timestamp(&self, unix_sec: u64) -> Vec<u8> {
+        if self.format == AofFormat::Resp {
+            return format!("#TS:{}\r\n", unix_sec).into_bytes();
         }
-        Ok(())
+        let mut payload = vec![OP_TIMESTAMP];
+        payload.extend_from_slice(&unix_sec.to_be_bytes());
+        payload
+    }
+
+    pub async fn append(&self, record: LogRecord) -> Result<(), Box<dyn std::error::Error>> {
+        self.append_batch(vec![record]).await
     }
 
-    pub async fn rewrite_from_snapshot(
+    /// Appends `records` with a single write, and a single fsync under
+    /// `appendfsync always`, as one multi-key command produces them.
+    pub async fn append_batch(
         &self,
-        entries: Vec<(Vec<u8>, Vec<u8>, Option<u64>)>,
+        records: Vec<LogRecord>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let temp_path = self.path.with_extension("aof.rewrite");
-        let mut buf = Vec::with_capacity(1024 + entries.len() * 32);
-        buf.extend_from_slice(MAGIC);
-
-        for (key, value, expires_at) in entries {
-            let payload = encode_record(LogRecord::Set {
-                key,
-                value,
-                expires_at,
-            });
-            buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
-            buf.extend_from_slice(&payload);
+        let mut encoded = Vec::with_capacity(records.len() + 1);
+        if self.timestamps {
+            let now = now_ms() / 1000;
+            if self.last_timestamp.fetch_max(now, Ordering::Relaxed) < now {
+                encoded.push(self.encode_timestamp(now));
+            }
+        }
+        for record in records {
+            encoded.push(self.encode(record)?);
         }
 
-        let mut file_guard = self.inner.lock().await;
-        std::fs::write(&temp_path, &buf)?;
-        std::fs::rename(&temp_path, &self.path)?;
+        let (ack, durable) = match self.fsync {
+            AofFsync::Always => {
+                let (ack, durable) = oneshot::channel();
+                (Some(ack), Some(durable))
+            }
+            AofFsync::EverySec | AofFsync::No => (None, None),
+        };
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if self.tx.send(WriterMsg::Write(encoded, ack)).await.is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            return Err("AOF writer task is not available".into());
+        }
+        if let Some(durable) = durable
+            && !durable.await.unwrap_or(false)
+        {
+            return Err("AOF write failed".into());
+        }
+        Ok(())
+    }
 
-        let replacement = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&self.path)
-            .await?;
-        *file_guard = replacement;
+    /// Waits for the background writer to drain, then flushes and fsyncs the file.
+    /// Called on shutdown so buffered writes are not lost.
+    pub async fn sync(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.drain().await?;
+        let mut log = self.inner.lock().await;
+        log.file.flush().await?;
+        timed_sync(&mut log.file, &self.fsync_latency).await?;
+        self.last_fsync_ms.store(now_ms(), Ordering::Relaxed);
+        Ok(())
+    }
 
+    /// Waits until every write queued so far is in the file.
+    async fn drain(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let (done, drained) = oneshot::channel();
+        self.tx
+            .send(WriterMsg::Drained(done))
+            .await
+            .map_err(|_| "AOF writer task is not available")?;
+        drained
+            .await
+            .map_err(|_| "AOF writer task is not available")?;
         Ok(())
     }
 
-    fn read_all_from_path(path: &Path) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>> {
+    /// Records queued for the background writer.
+    pub fn pending_writes(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn last_fsync_age_ms(&self) -> u64 {
+        now_ms().saturating_sub(self.last_fsync_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn fsync_latency(&self) -> LatencyHistogram {
+        self.fsync_latency
+            .lock()
+            .expect("fsync latency lock")
+            .clone()
+    }
+
+    pub fn last_write_ok(&self) -> bool {
+        self.last_write_ok.load(Ordering::Relaxed)
+    }
+
+    pub fn base_size(&self) -> u64 {
+        self.base_size.load(Ordering::Relaxed)
+    }
+
+    /// Size of the file on disk; writes still queued for the background writer
+    /// are not counted.
+    pub fn current_size(&self) -> u64 {
+        std::fs::metadata(&self.path).map_or(0, |meta| meta.len())
+    }
+
+    /// Starts a rewrite into a new log next to the current one, waiting for
+    /// any rewrite in progress. Every append from here on is also kept for
+    /// the new log, so freeze the keyspace for it after this returns.
+    pub async fn begin_rewrite(&self) -> Result<AofRewrite, Box<dyn std::error::Error>> {
+        let rewriting = self.rewrite_lock.clone().lock_owned().await;
+        // What was queued before goes to the current log only, or a write
+        // that a flush has since cleared would come back with the new one.
+        self.drain().await?;
+        let key = self
+            .keyring
+            .as_deref()
+            .map(Keyring::new_file_key)
+            .transpose()?;
+        let mut file = AtomicFile::create(&self.path, "aof.rewrite")?;
+        let header = header(self.format, key.as_ref());
+        file.write_all(&header)?;
+        *self
+            .rewrite_buffer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Vec::new());
+        let mut rewrite = AofRewrite {
+            aof: self.clone(),
+            file,
+            key,
+            written: header.len() as u64,
+            capture: Capture {
+                buffer: self.rewrite_buffer.clone(),
+                _rewriting: rewriting,
+            },
+        };
+        if self.timestamps {
+            let now = now_ms() / 1000;
+            self.last_timestamp.fetch_max(now, Ordering::Relaxed);
+            rewrite.write(&[self.encode_timestamp(now)])?;
+        }
+        Ok(rewrite)
+    }
+
+    fn read_all_from_path(
+        path: &Path,
+        keyring: Option<&Keyring>,
+    ) -> Result<LoadedLog, Box<dyn std::error::Error>> {
+        let mut loaded = LoadedLog::default();
         if !path.exists() {
-            return Ok(Vec::new());
+            return Ok(loaded);
         }
 
         let mut bytes = Vec::new();
@@ -202,48 +645,433 @@ impl Aof {
         file.read_to_end(&mut bytes)?;
 
         if bytes.is_empty() {
-            return Ok(Vec::new());
+            return Ok(loaded);
         }
 
-        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
-            return Err("invalid AOF magic header".into());
+        if bytes[0] == b'*' {
+            return decode_resp_log(&bytes);
         }
+        let encrypted = || keyring.ok_or("AOF is encrypted but no encryption key is configured");
+        let (unseal, checksummed, mut idx) = match bytes.get(..MAGIC.len()) {
+            Some(magic) if magic == MAGIC => (None, true, MAGIC.len()),
+            Some(magic) if magic == LEGACY_MAGIC => (None, false, MAGIC.len()),
+            Some(magic) if magic == LEGACY_ENCRYPTED_MAGIC => {
+                (Some(Unseal::Keyring(encrypted()?)), false, MAGIC.len())
+            }
+            Some(magic) if magic == ENCRYPTED_MAGIC => {
+                let key = file_key(&bytes, encrypted()?)?;
+                (
+                    Some(Unseal::File(Box::new(key))),
+                    false,
+                    ENCRYPTED_HEADER_LEN,
+                )
+            }
+            _ => return Err("invalid AOF magic header".into()),
+        };
 
-        let mut idx = MAGIC.len();
-        let mut out = Vec::new();
         while idx < bytes.len() {
-            if idx + 4 > bytes.len() {
-                return Err(
-                    std::io::Error::new(ErrorKind::InvalidData, "truncated AOF size").into(),
-                );
+            let Some(size) = bytes.get(idx..idx + 4) else {
+                loaded.bad_tail = Some((idx, "incomplete record header".to_string()));
+                break;
+            };
+            let size = u32::from_be_bytes(size.try_into()?) as usize;
+            let payload_end = idx + 4 + size;
+            let end = payload_end + if checksummed { 8 } else { 0 };
+            if end > bytes.len() {
+                loaded.bad_tail = Some((idx, "incomplete record".to_string()));
+                break;
             }
-            let size = u32::from_be_bytes(bytes[idx..idx + 4].try_into()?) as usize;
-            idx += 4;
-            if idx + size > bytes.len() {
-                return Err(
-                    std::io::Error::new(ErrorKind::InvalidData, "truncated AOF record").into(),
-                );
+            let payload = &bytes[idx + 4..payload_end];
+            let plain = match &unseal {
+                // A record that does not authenticate was written whole, as its
+                // length says, so it is not a torn write but the wrong key or
+                // tampering; loading less of the log would hide that.
+                Some(unseal) => match unseal.open(payload, idx) {
+                    Ok(plain) => plain,
+                    Err(e) => {
+                        loaded.corrupt = Some((idx, e));
+                        break;
+                    }
+                },
+                None if checksummed && crc64(payload).to_be_bytes() != bytes[payload_end..end] => {
+                    // Only the final record can be a torn write; damage before it is not
+                    // something loading less of the log would fix.
+                    let reason = "record checksum mismatch".to_string();
+                    if end == bytes.len() {
+                        loaded.bad_tail = Some((idx, reason));
+                    } else {
+                        loaded.corrupt = Some((idx, reason));
+                    }
+                    break;
+                }
+                None => payload.to_vec(),
+            };
+            if plain.first() == Some(&OP_TIMESTAMP) {
+                let unix_sec = plain
+                    .get(1..9)
+                    .ok_or("invalid AOF timestamp record")?
+                    .try_into()
+                    .map(u64::from_be_bytes)?;
+                loaded
+                    .timestamps
+                    .push((loaded.records.len(), idx, unix_sec));
+            } else {
+                loaded.records.push(decode_record(&plain)?);
             }
-            let record = decode_record(&bytes[idx..idx + size])?;
-            idx += size;
-            out.push(record);
+            idx = end;
         }
 
-        Ok(out)
+        Ok(loaded)
+    }
+}
+
+impl AofRewrite {
+    /// Streams `entries` into the new log. Blocking, with an fsync every few
+    /// MiB, so it belongs on a blocking thread.
+    pub fn write_snapshot<'a>(
+        &mut self,
+        entries: impl Iterator<Item = (&'a [u8], ValueType, &'a [u8], Option<u64>)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (key, kind, value, expires_at) in entries {
+            let record = self.aof.encode(LogRecord::Set {
+                key: key.to_vec(),
+                kind,
+                value: Bytes::copy_from_slice(value),
+                expires_at,
+            })?;
+            self.write(&[record])?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, records: &[Vec<u8>]) -> Result<(), Box<dyn std::error::Error>> {
+        write_records(
+            &mut self.file,
+            self.aof.format,
+            self.key.as_ref(),
+            records,
+            &mut self.written,
+        )
+    }
+
+    /// Appends what was written to the current log since the rewrite began
+    /// and swaps the new log in, with the writer held off meanwhile.
+    pub async fn commit(self) -> Result<(), Box<dyn std::error::Error>> {
+        let AofRewrite {
+            aof,
+            mut file,
+            key,
+            mut written,
+            capture,
+        } = self;
+        let mut log = aof.inner.lock().await;
+        let captured = capture
+            .buffer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .unwrap_or_default();
+        let format = aof.format;
+        let key = tokio::task::spawn_blocking(move || {
+            write_records(&mut file, format, key.as_ref(), &captured, &mut written)
+                .map_err(|e| e.to_string())?;
+            file.commit().map_err(|e| e.to_string())?;
+            Ok::<_, String>(key)
+        })
+        .await??;
+
+        let replacement = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&aof.path)
+            .await?;
+        *log = LogFile {
+            file: replacement,
+            key,
+        };
+        aof.base_size.store(aof.current_size(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// What `fedis --check-aof` reports about a log.
+pub struct AofCheck {
+    pub file_len: u64,
+    /// Valid records before `first_bad`, or in the whole file.
+    pub records: usize,
+    pub timestamps: usize,
+    /// Where the first invalid record starts, and what is wrong with it.
+    pub first_bad: Option<(usize, String)>,
+}
+
+/// What `Aof::read_offline` read.
+pub struct OfflineLog {
+    pub records: Vec<LogRecord>,
+    pub first_bad: Option<(usize, String)>,
+}
+
+/// Records read back from the log.
+#[derive(Default)]
+struct LoadedLog {
+    records: Vec<LogRecord>,
+    /// Offset where a torn or corrupt tail starts, and what was wrong with it.
+    bad_tail: Option<(usize, String)>,
+    /// Offset of a damaged record that is not a torn write, and what is wrong
+    /// with it. Records after it are not read.
+    corrupt: Option<(usize, String)>,
+    /// Timestamp annotations as `(records before it, file offset, unix seconds)`.
+    timestamps: Vec<(usize, usize, u64)>,
+}
+
+impl LoadedLog {
+    fn first_bad(&self) -> Option<(usize, String)> {
+        self.corrupt.clone().or_else(|| self.bad_tail.clone())
     }
 }
 
-fn encode_record(record: LogRecord) -> Vec<u8> {
+/// Fsyncs the log and records how long it took when it succeeded.
+async fn timed_sync(
+    file: &mut tokio::fs::File,
+    latency: &std::sync::Mutex<LatencyHistogram>,
+) -> std::io::Result<()> {
+    let started = Instant::now();
+    file.sync_data().await?;
+    latency
+        .lock()
+        .expect("fsync latency lock")
+        .record(started.elapsed().as_micros() as u64);
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Writes `entries` as a complete log in `format`, as a rewrite would,
+/// replacing `path` atomically; for `fedis convert`. Only the fedis format is
+/// encrypted.
+pub fn write_log<'a>(
+    path: &Path,
+    entries: impl Iterator<Item = (&'a [u8], ValueType, &'a [u8], Option<u64>)>,
+    format: AofFormat,
+    keyring: Option<&Keyring>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = AtomicFile::create(path, "aof.tmp")?;
+    write_log_to(&mut file, entries, format, keyring)?;
+    file.commit()?;
+    Ok(())
+}
+
+pub fn write_log_to<'a>(
+    out: &mut impl Write,
+    entries: impl Iterator<Item = (&'a [u8], ValueType, &'a [u8], Option<u64>)>,
+    format: AofFormat,
+    keyring: Option<&Keyring>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_key = keyring
+        .filter(|_| format == AofFormat::Fedis)
+        .map(Keyring::new_file_key)
+        .transpose()?;
+    let header = header(format, file_key.as_ref());
+    out.write_all(&header)?;
+    let mut written = header.len() as u64;
+    for (key, kind, value, expires_at) in entries {
+        let record = LogRecord::Set {
+            key: key.to_vec(),
+            kind,
+            value: Bytes::copy_from_slice(value),
+            expires_at,
+        };
+        let encoded = match format {
+            AofFormat::Resp => encode_resp_record(record),
+            AofFormat::Fedis => encode_record(record, None)?,
+        };
+        write_records(out, format, file_key.as_ref(), &[encoded], &mut written)?;
+    }
+    Ok(())
+}
+
+/// Frames `records` and writes them to `out`, which holds `written` bytes so far.
+fn write_records(
+    out: &mut impl Write,
+    format: AofFormat,
+    key: Option<&FileKey>,
+    records: &[Vec<u8>],
+    written: &mut u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let wire = frame_records(format, key, records, *written)?;
+    out.write_all(&wire)?;
+    *written += wire.len() as u64;
+    Ok(())
+}
+
+/// Frames `records` to be written at `offset`. Redis-format records are
+/// written as they are.
+fn frame_records(
+    format: AofFormat,
+    key: Option<&FileKey>,
+    records: &[Vec<u8>],
+    offset: u64,
+) -> Result<Vec<u8>, String> {
+    let mut wire = Vec::new();
+    for record in records {
+        match format {
+            AofFormat::Resp => wire.extend_from_slice(record),
+            AofFormat::Fedis => {
+                let at = offset + wire.len() as u64;
+                wire.extend_from_slice(&frame_payload(record, key, at)?);
+            }
+        }
+    }
+    Ok(wire)
+}
+
+/// A record's length-prefixed payload, sealed under the log's subkey and bound
+/// to `offset` when it is encrypted, and followed by its checksum otherwise.
+fn frame_payload(payload: &[u8], key: Option<&FileKey>, offset: u64) -> Result<Vec<u8>, String> {
+    let sealed = key
+        .map(|key| key.seal(payload, &record_aad(offset)))
+        .transpose()?;
+    let body = sealed.as_deref().unwrap_or(payload);
+    let mut wire = Vec::with_capacity(12 + body.len());
+    wire.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    wire.extend_from_slice(body);
+    if key.is_none() {
+        wire.extend_from_slice(&crc64(body).to_be_bytes());
+    }
+    Ok(wire)
+}
+
+/// What an encrypted record is authenticated with besides its contents: where
+/// it starts, so it cannot be moved, dropped or replayed within the log.
+fn record_aad(offset: u64) -> [u8; 14] {
+    let mut aad = [0_u8; 14];
+    aad[..6].copy_from_slice(ENCRYPTED_MAGIC);
+    aad[6..].copy_from_slice(&offset.to_be_bytes());
+    aad
+}
+
+/// How the records of an encrypted log are opened.
+enum Unseal<'a> {
+    /// `LEGACY_ENCRYPTED_MAGIC` logs, sealed under the keys themselves.
+    Keyring(&'a Keyring),
+    File(Box<FileKey>),
+}
+
+impl Unseal<'_> {
+    fn open(&self, payload: &[u8], offset: usize) -> Result<Vec<u8>, String> {
+        match self {
+            Unseal::Keyring(keyring) => keyring.open(payload, LEGACY_ENCRYPTED_MAGIC),
+            Unseal::File(key) => key.open(payload, &record_aad(offset as u64)),
+        }
+    }
+}
+
+impl LogFile {
+    /// Frames `records` for the end of the file.
+    async fn frame(&mut self, format: AofFormat, records: &[Vec<u8>]) -> std::io::Result<Vec<u8>> {
+        let offset = match self.key {
+            // Encrypted records are bound to where they start, so find the end
+            // of the file once the write in flight is in it.
+            Some(_) => {
+                self.file.flush().await?;
+                self.file.metadata().await?.len()
+            }
+            None => 0,
+        };
+        frame_records(format, self.key.as_ref(), records, offset).map_err(std::io::Error::other)
+    }
+}
+
+/// The magic a log in `format` starts with.
+fn magic(format: AofFormat, encrypted: bool) -> &'static [u8] {
+    match (format, encrypted) {
+        (AofFormat::Resp, _) => RESP_PREAMBLE,
+        (AofFormat::Fedis, true) => ENCRYPTED_MAGIC,
+        (AofFormat::Fedis, false) => MAGIC,
+    }
+}
+
+/// The start of a new log; an encrypted one names its subkey.
+fn header(format: AofFormat, key: Option<&FileKey>) -> Vec<u8> {
+    let mut header = magic(format, key.is_some()).to_vec();
+    if let Some(key) = key {
+        header.extend_from_slice(&key.id().to_be_bytes());
+        header.extend_from_slice(key.salt());
+    }
+    header
+}
+
+/// The subkey an encrypted log's header names.
+fn file_key(bytes: &[u8], keyring: &Keyring) -> Result<FileKey, Box<dyn std::error::Error>> {
+    let header = bytes
+        .get(..ENCRYPTED_HEADER_LEN)
+        .ok_or("encrypted AOF header is truncated")?;
+    let id = u32::from_be_bytes(header[MAGIC.len()..MAGIC.len() + 4].try_into()?);
+    Ok(keyring.file_key(id, &header[MAGIC.len() + 4..])?)
+}
+
+/// The subkey of the encrypted log at `path`, or `None` when it is not one or
+/// the keyring cannot derive it, which loading the log reports.
+fn stored_file_key(
+    path: &Path,
+    keyring: &Keyring,
+) -> Result<Option<FileKey>, Box<dyn std::error::Error>> {
+    let mut header = Vec::with_capacity(ENCRYPTED_HEADER_LEN);
+    std::fs::File::open(path)?
+        .take(ENCRYPTED_HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    if !header.starts_with(ENCRYPTED_MAGIC) {
+        return Ok(None);
+    }
+    Ok(file_key(&header, keyring).ok())
+}
+
+fn read_magic(path: &Path) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut magic = vec![0_u8; MAGIC.len()];
+    let mut file = std::fs::File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(Some(magic)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn encode_record(
+    record: LogRecord,
+    compression: Option<(Compression, usize)>,
+) -> Result<Vec<u8>, String> {
     let mut payload = Vec::new();
     match record {
         LogRecord::Set {
             key,
+            kind,
             value,
             expires_at,
         } => {
-            payload.push(OP_SET);
+            let packed = match compression {
+                Some((compression, min_bytes)) if value.len() >= min_bytes => {
+                    Some((compression, compression.compress(&value)?))
+                }
+                _ => None,
+            };
+            payload.push(OP_SET_TYPED);
             write_bytes(&mut payload, &key);
-            write_bytes(&mut payload, &value);
+            write_value_header(&mut payload, kind).map_err(|e| e.to_string())?;
+            match packed {
+                // Incompressible values are stored as they are.
+                Some((compression, packed)) if packed.len() < value.len() => {
+                    payload.push(compression.tag());
+                    write_bytes(&mut payload, &packed);
+                }
+                _ => {
+                    payload.push(Compression::None.tag());
+                    write_bytes(&mut payload, &value);
+                }
+            }
             write_i64(&mut payload, expires_at.map(|v| v as i64).unwrap_or(-1));
         }
         LogRecord::Del { key } => {
@@ -259,8 +1087,275 @@ fn encode_record(record: LogRecord) -> Vec<u8> {
             payload.push(OP_PERSIST);
             write_bytes(&mut payload, &key);
         }
+        LogRecord::TsAdd {
+            key,
+            timestamp,
+            value,
+        } => {
+            payload.push(OP_TS_ADD);
+            write_bytes(&mut payload, &key);
+            write_i64(&mut payload, timestamp as i64);
+            write_i64(&mut payload, value.to_bits() as i64);
+        }
     }
-    payload
+    Ok(payload)
+}
+
+/// Writes a record as the command Redis would propagate for it. A JSON
+/// document is written as `JSON.SET key $ doc`, followed by `PEXPIREAT` when
+/// it expires, as RedisJSON has no form that sets both. A time series is
+/// recreated with `DEL`, `TS.CREATE` and a `TS.MADD` of its samples.
+pub fn encode_resp_record(record: LogRecord) -> Vec<u8> {
+    let args: Vec<Bytes> = match record {
+        LogRecord::Set {
+            key,
+            kind: ValueType::TimeSeries,
+            value,
+            expires_at,
+        } => {
+            let series = Series::new(&value);
+            let mut out = encode_resp_record(LogRecord::Del { key: key.clone() });
+            out.extend(encode_command(vec![
+                Bytes::from_static(b"TS.CREATE"),
+                key.clone().into(),
+                Bytes::from_static(b"RETENTION"),
+                series.retention().to_string().into(),
+            ]));
+            let mut madd = vec![Bytes::from_static(b"TS.MADD")];
+            for (timestamp, sample) in series.range(0, u64::MAX) {
+                madd.push(key.clone().into());
+                madd.push(timestamp.to_string().into());
+                madd.push(sample.to_string().into());
+            }
+            if madd.len() > 1 {
+                out.extend(encode_command(madd));
+            }
+            if let Some(expires_at) = expires_at {
+                out.extend(encode_resp_record(LogRecord::Expire { key, expires_at }));
+            }
+            return out;
+        }
+        LogRecord::Set {
+            key,
+            kind: ValueType::Json,
+            value,
+            expires_at,
+        } => {
+            let mut out = encode_command(vec![
+                Bytes::from_static(b"JSON.SET"),
+                key.clone().into(),
+                Bytes::from_static(b"$"),
+                value,
+            ]);
+            if let Some(expires_at) = expires_at {
+                out.extend(encode_resp_record(LogRecord::Expire { key, expires_at }));
+            }
+            return out;
+        }
+        LogRecord::Set {
+            key,
+            value,
+            expires_at: None,
+            ..
+        } => vec![Bytes::from_static(b"SET"), key.into(), value],
+        LogRecord::Set {
+            key,
+            value,
+            expires_at: Some(expires_at),
+            ..
+        } => vec![
+            Bytes::from_static(b"SET"),
+            key.into(),
+            value,
+            Bytes::from_static(b"PXAT"),
+            expires_at.to_string().into(),
+        ],
+        LogRecord::Del { key } => vec![Bytes::from_static(b"DEL"), key.into()],
+        LogRecord::Expire { key, expires_at } => vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            key.into(),
+            expires_at.to_string().into(),
+        ],
+        LogRecord::Persist { key } => vec![Bytes::from_static(b"PERSIST"), key.into()],
+        LogRecord::TsAdd {
+            key,
+            timestamp,
+            value,
+        } => vec![
+            Bytes::from_static(b"TS.ADD"),
+            key.into(),
+            timestamp.to_string().into(),
+            value.to_string().into(),
+        ],
+    };
+    encode_command(args)
+}
+
+fn encode_command(args: Vec<Bytes>) -> Vec<u8> {
+    encode(RespValue::Array(
+        args.into_iter().map(|v| RespValue::Bulk(Some(v))).collect(),
+    ))
+}
+
+/// Replays a Redis-format log. Only the commands fedis writes (plus `SELECT 0`
+/// and `MULTI`/`EXEC` wrappers) are understood.
+fn decode_resp_log(bytes: &[u8]) -> Result<LoadedLog, Box<dyn std::error::Error>> {
+    let mut idx = 0;
+    let mut loaded = LoadedLog::default();
+    let out = &mut loaded.records;
+    while idx < bytes.len() {
+        let start = idx;
+        if bytes[idx] == b'#' {
+            let Some(len) = bytes[idx..].windows(2).position(|w| w == b"\r\n") else {
+                loaded.bad_tail = Some((start, "incomplete annotation".to_string()));
+                break;
+            };
+            let line = &bytes[idx + 1..idx + len];
+            idx += len + 2;
+            if let Some(ts) = line.strip_prefix(b"TS:") {
+                loaded
+                    .timestamps
+                    .push((out.len(), start, parse_resp_u64(ts)?));
+            }
+            continue;
+        }
+        let args = match read_resp_command(bytes, &mut idx) {
+            Ok(args) => args,
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof) =>
+            {
+                loaded.bad_tail = Some((start, "incomplete command".to_string()));
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let name = args[0].to_ascii_uppercase();
+        match (name.as_slice(), args.len()) {
+            (b"SELECT", 2) if args[1] == b"0" => {}
+            (b"SELECT", _) => return Err("AOF selects a database other than 0".into()),
+            (b"MULTI" | b"EXEC", 1) => {}
+            (b"SET", 3) => out.push(LogRecord::Set {
+                key: args[1].clone(),
+                kind: ValueType::String,
+                value: args[2].clone().into(),
+                expires_at: None,
+            }),
+            (b"SET", 5) if args[3].eq_ignore_ascii_case(b"PXAT") => out.push(LogRecord::Set {
+                key: args[1].clone(),
+                kind: ValueType::String,
+                value: args[2].clone().into(),
+                expires_at: Some(parse_resp_u64(&args[4])?),
+            }),
+            (b"JSON.SET", 4) if args[2] == b"$" => out.push(LogRecord::Set {
+                key: args[1].clone(),
+                kind: ValueType::Json,
+                value: args[3].clone().into(),
+                expires_at: None,
+            }),
+            (b"DEL", n) if n >= 2 => {
+                out.extend(
+                    args[1..]
+                        .iter()
+                        .map(|key| LogRecord::Del { key: key.clone() }),
+                );
+            }
+            (b"PEXPIREAT", 3) => out.push(LogRecord::Expire {
+                key: args[1].clone(),
+                expires_at: parse_resp_u64(&args[2])?,
+            }),
+            (b"PERSIST", 2) => out.push(LogRecord::Persist {
+                key: args[1].clone(),
+            }),
+            (b"TS.CREATE", 4) if args[2].eq_ignore_ascii_case(b"RETENTION") => {
+                out.push(LogRecord::Set {
+                    key: args[1].clone(),
+                    kind: ValueType::TimeSeries,
+                    value: timeseries::new(parse_resp_u64(&args[3])?),
+                    expires_at: None,
+                })
+            }
+            (b"TS.ADD", 4) => out.push(ts_add_record(&args[1..])?),
+            (b"TS.MADD", n) if n > 1 && (n - 1) % 3 == 0 => {
+                for sample in args[1..].chunks(3) {
+                    out.push(ts_add_record(sample)?);
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "unsupported command in AOF: {}",
+                    String::from_utf8_lossy(&args[0])
+                )
+                .into());
+            }
+        }
+    }
+    Ok(loaded)
+}
+
+/// `key timestamp value` of a `TS.ADD` or `TS.MADD`.
+fn ts_add_record(args: &[Vec<u8>]) -> Result<LogRecord, Box<dyn std::error::Error>> {
+    let value = std::str::from_utf8(&args[2])?.parse::<f64>()?;
+    Ok(LogRecord::TsAdd {
+        key: args[0].clone(),
+        timestamp: parse_resp_u64(&args[1])?,
+        value,
+    })
+}
+
+fn read_resp_command(
+    input: &[u8],
+    idx: &mut usize,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let count = read_resp_header(input, idx, b'*')?;
+    if count == 0 {
+        return Err("empty command in AOF".into());
+    }
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = read_resp_header(input, idx, b'$')?;
+        let end = *idx + len;
+        if input.get(end..end + 2) != Some(b"\r\n") {
+            return Err(
+                std::io::Error::new(ErrorKind::InvalidData, "truncated AOF command").into(),
+            );
+        }
+        args.push(input[*idx..end].to_vec());
+        *idx = end + 2;
+    }
+    Ok(args)
+}
+
+fn read_resp_header(
+    input: &[u8],
+    idx: &mut usize,
+    prefix: u8,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    match input.get(*idx) {
+        Some(found) if *found == prefix => {}
+        Some(_) => {
+            return Err(
+                std::io::Error::new(ErrorKind::InvalidData, "malformed AOF command").into(),
+            );
+        }
+        None => {
+            return Err(
+                std::io::Error::new(ErrorKind::UnexpectedEof, "truncated AOF command").into(),
+            );
+        }
+    }
+    let line_end = input[*idx..]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|p| *idx + p)
+        .ok_or_else(|| std::io::Error::new(ErrorKind::UnexpectedEof, "truncated AOF command"))?;
+    let value = std::str::from_utf8(&input[*idx + 1..line_end])?.parse::<usize>()?;
+    *idx = line_end + 2;
+    Ok(value)
+}
+
+fn parse_resp_u64(value: &[u8]) -> Result<u64, Box<dyn std::error::Error>> {
+    Ok(std::str::from_utf8(value)?.parse::<u64>()?)
 }
 
 fn write_bytes(dst: &mut Vec<u8>, value: &[u8]) {
@@ -309,7 +1404,46 @@ fn decode_record(input: &[u8]) -> Result<LogRecord, Box<dyn std::error::Error>>
             let exp = read_i64(input, &mut idx)?;
             Ok(LogRecord::Set {
                 key,
-                value,
+                kind: ValueType::String,
+                value: value.into(),
+                expires_at: if exp < 0 { None } else { Some(exp as u64) },
+            })
+        }
+        OP_SET_COMPRESSED => {
+            let key = read_bytes(input, &mut idx)?;
+            let compression =
+                Compression::from_tag(*input.get(idx).ok_or("invalid record compression tag")?)?;
+            idx += 1;
+            let value = compression.decompress(&read_bytes(input, &mut idx)?)?;
+            let exp = read_i64(input, &mut idx)?;
+            Ok(LogRecord::Set {
+                key,
+                kind: ValueType::String,
+                value: value.into(),
+                expires_at: if exp < 0 { None } else { Some(exp as u64) },
+            })
+        }
+        OP_SET_TYPED => {
+            let key = read_bytes(input, &mut idx)?;
+            let kind = read_value_header(
+                input
+                    .get(idx..idx + 2)
+                    .ok_or("invalid record value header")?,
+            )?;
+            idx += 2;
+            let compression =
+                Compression::from_tag(*input.get(idx).ok_or("invalid record compression tag")?)?;
+            idx += 1;
+            let value = read_bytes(input, &mut idx)?;
+            let value = match compression {
+                Compression::None => value,
+                compression => compression.decompress(&value)?,
+            };
+            let exp = read_i64(input, &mut idx)?;
+            Ok(LogRecord::Set {
+                key,
+                kind,
+                value: value.into(),
                 expires_at: if exp < 0 { None } else { Some(exp as u64) },
             })
         }
@@ -332,6 +1466,307 @@ fn decode_record(input: &[u8]) -> Result<LogRecord, Box<dyn std::error::Error>>
             let key = read_bytes(input, &mut idx)?;
             Ok(LogRecord::Persist { key })
         }
+        OP_TS_ADD => {
+            let key = read_bytes(input, &mut idx)?;
+            let timestamp = read_i64(input, &mut idx)? as u64;
+            let value = f64::from_bits(read_i64(input, &mut idx)? as u64);
+            Ok(LogRecord::TsAdd {
+                key,
+                timestamp,
+                value,
+            })
+        }
         _ => Err("unknown AOF operation".into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded_set(payload: &[u8]) -> (Vec<u8>, Vec<u8>, Option<u64>) {
+        match decode_record(payload).expect("decode") {
+            LogRecord::Set {
+                key,
+                value,
+                expires_at,
+                ..
+            } => (key, value.to_vec(), expires_at),
+            _ => panic!("not a SET"),
+        }
+    }
+
+    #[test]
+    fn set_records_of_every_generation_decode() {
+        let mut legacy = vec![OP_SET];
+        write_bytes(&mut legacy, b"k");
+        write_bytes(&mut legacy, b"v");
+        write_i64(&mut legacy, 7);
+        assert_eq!(
+            decoded_set(&legacy),
+            (b"k".to_vec(), b"v".to_vec(), Some(7))
+        );
+
+        let blob = b"abcd".repeat(100);
+        for compression in [None, Some((Compression::Lz4, 0))] {
+            let typed = encode_record(
+                LogRecord::Set {
+                    key: b"k".to_vec(),
+                    kind: ValueType::String,
+                    value: blob.clone().into(),
+                    expires_at: None,
+                },
+                compression,
+            )
+            .expect("encode");
+            assert_eq!(typed[0], OP_SET_TYPED);
+            assert_eq!(decoded_set(&typed), (b"k".to_vec(), blob.clone(), None));
+        }
+
+        let json = encode_record(
+            LogRecord::Set {
+                key: b"doc".to_vec(),
+                kind: ValueType::Json,
+                value: Bytes::from_static(b"{\"a\":1}"),
+                expires_at: Some(9),
+            },
+            None,
+        )
+        .expect("encode");
+        assert!(matches!(
+            decode_record(&json).expect("decode"),
+            LogRecord::Set {
+                kind: ValueType::Json,
+                expires_at: Some(9),
+                ..
+            }
+        ));
+
+        let mut hash = encode_record(
+            LogRecord::Set {
+                key: b"k".to_vec(),
+                kind: ValueType::String,
+                value: Bytes::from_static(b"v"),
+                expires_at: None,
+            },
+            None,
+        )
+        .expect("encode");
+        hash[1 + 4 + 1] = ValueType::Hash.tag();
+        let err = decode_record(&hash).expect_err("hash record");
+        assert_eq!(
+            err.to_string(),
+            "hash values are not supported by this build"
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_made_during_a_rewrite_reach_the_new_log() {
+        let path =
+            std::env::temp_dir().join(format!("fedis-rewrite-test-{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let aof = Aof::open(&path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open");
+        let set = |key: &[u8]| LogRecord::Set {
+            key: key.to_vec(),
+            kind: ValueType::String,
+            value: Bytes::from_static(b"v"),
+            expires_at: None,
+        };
+        let mut rewrite = aof.begin_rewrite().await.expect("begin");
+        aof.append(set(b"during")).await.expect("append");
+        rewrite
+            .write_snapshot([(&b"frozen"[..], ValueType::String, &b"v"[..], None)].into_iter())
+            .expect("snapshot");
+        rewrite.commit().await.expect("commit");
+        aof.append(set(b"after")).await.expect("append");
+
+        let keys: Vec<Vec<u8>> = aof
+            .read_all()
+            .expect("read")
+            .into_iter()
+            .map(|record| match record {
+                LogRecord::Set { key, .. } => key,
+                _ => panic!("not a SET"),
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            [b"frozen".to_vec(), b"during".to_vec(), b"after".to_vec()]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn encrypted_records_are_bound_to_their_offset() {
+        let path =
+            std::env::temp_dir().join(format!("fedis-sealed-test-{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keyring = Arc::new(
+            Keyring::parse(
+                "3:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+                None,
+            )
+            .expect("keyring"),
+        );
+        let aof = Aof::open(
+            &path,
+            AofFsync::Always,
+            Some(keyring.clone()),
+            AofFormat::Fedis,
+        )
+        .await
+        .expect("open");
+        let set = |key: &[u8]| LogRecord::Set {
+            key: key.to_vec(),
+            kind: ValueType::String,
+            value: Bytes::from_static(b"v"),
+            expires_at: None,
+        };
+        let keys = |records: Vec<LogRecord>| -> Vec<Vec<u8>> {
+            records
+                .into_iter()
+                .map(|record| match record {
+                    LogRecord::Set { key, .. } => key,
+                    _ => panic!("not a SET"),
+                })
+                .collect()
+        };
+        aof.append(set(b"a")).await.expect("append");
+        let rewrite = aof.begin_rewrite().await.expect("begin");
+        rewrite.commit().await.expect("commit");
+        aof.append(set(b"b")).await.expect("append");
+        aof.append(set(b"c")).await.expect("append");
+        aof.sync().await.expect("sync");
+        assert_eq!(
+            keys(aof.read_all().expect("read")),
+            [b"b".to_vec(), b"c".to_vec()]
+        );
+
+        // Swapped records are whole and the right size, but sealed for
+        // other offsets.
+        let bytes = std::fs::read(&path).expect("read log");
+        let record = (bytes.len() - ENCRYPTED_HEADER_LEN) / 2;
+        let (header, records) = bytes.split_at(ENCRYPTED_HEADER_LEN);
+        let swapped = [header, &records[record..], &records[..record]].concat();
+        std::fs::write(&path, &swapped).expect("write log");
+        let first_bad = Aof::check(&path, Some(&keyring)).expect("check").first_bad;
+        assert_eq!(
+            first_bad.map(|(offset, _)| offset),
+            Some(ENCRYPTED_HEADER_LEN)
+        );
+
+        // A final record that fails to authenticate is not taken for a torn
+        // write and cut off.
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().expect("record") ^= 1;
+        std::fs::write(&path, &tampered).expect("write log");
+        assert!(aof.read_all().is_err());
+        assert_eq!(std::fs::read(&path).expect("read log"), tampered);
+
+        let other = Keyring::parse(
+            "3:1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100",
+            None,
+        )
+        .expect("keyring");
+        std::fs::write(&path, &bytes).expect("write log");
+        assert!(
+            Aof::check(&path, Some(&other))
+                .expect("check")
+                .first_bad
+                .is_some()
+        );
+
+        let sealed = keyring
+            .seal(
+                &encode_record(set(b"legacy"), None).expect("encode"),
+                LEGACY_ENCRYPTED_MAGIC,
+            )
+            .expect("seal");
+        let legacy = [
+            LEGACY_ENCRYPTED_MAGIC,
+            &(sealed.len() as u32).to_be_bytes(),
+            &sealed,
+        ]
+        .concat();
+        std::fs::write(&path, legacy).expect("write log");
+        let log = Aof::read_offline(&path, Some(&keyring)).expect("read legacy");
+        assert!(log.first_bad.is_none());
+        assert_eq!(keys(log.records), [b"legacy".to_vec()]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn json_records_replay_from_redis_format_logs() {
+        let bytes = encode_resp_record(LogRecord::Set {
+            key: b"doc".to_vec(),
+            kind: ValueType::Json,
+            value: Bytes::from_static(b"[1]"),
+            expires_at: Some(42),
+        });
+        assert!(bytes.starts_with(b"*4\r\n$8\r\nJSON.SET\r\n"));
+        let records = decode_resp_log(&bytes).expect("decode").records;
+        assert!(matches!(
+            records.as_slice(),
+            [
+                LogRecord::Set {
+                    kind: ValueType::Json,
+                    expires_at: None,
+                    ..
+                },
+                LogRecord::Expire { expires_at: 42, .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn time_series_records_replay_from_both_formats() {
+        let sample = LogRecord::TsAdd {
+            key: b"ts".to_vec(),
+            timestamp: 7,
+            value: -1.5,
+        };
+        let payload = encode_record(sample.clone(), None).expect("encode");
+        assert!(matches!(
+            decode_record(&payload).expect("decode"),
+            LogRecord::TsAdd {
+                timestamp: 7,
+                value: -1.5,
+                ..
+            }
+        ));
+
+        let series = timeseries::add(timeseries::new(60_000), 5, 2.25);
+        let mut bytes = encode_resp_record(LogRecord::Set {
+            key: b"ts".to_vec(),
+            kind: ValueType::TimeSeries,
+            value: series.clone(),
+            expires_at: None,
+        });
+        bytes.extend(encode_resp_record(sample));
+        let records = decode_resp_log(&bytes).expect("decode").records;
+        let [
+            LogRecord::Del { .. },
+            LogRecord::Set {
+                kind: ValueType::TimeSeries,
+                value,
+                ..
+            },
+            LogRecord::TsAdd {
+                timestamp: 5,
+                value: 2.25,
+                ..
+            },
+            LogRecord::TsAdd {
+                timestamp: 7,
+                value: -1.5,
+                ..
+            },
+        ] = records.as_slice()
+        else {
+            panic!("unexpected records: {:?}", records);
+        };
+        assert_eq!(Series::new(value).retention(), 60_000);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use rustls_pki_types::ServerName;
+use tokio::net::TcpStream;
+
+use crate::cli::{Connection, Reply};
+use crate::config::Config;
+use crate::runtime::{RuntimeConfig, RuntimeFlavor};
+use crate::tls::build_self_connector;
+
+const USAGE: &str = "usage: fedis ping [--addr host:port] [--user name] [--password secret] \
+     [--timeout seconds]";
+
+/// A health check requested with `fedis ping` instead of starting the
+/// server, for container `HEALTHCHECK`s and probes: it sends `PING` to the
+/// configured server, over TLS and logged in as the configured default user
+/// when it has those, and exits 0 on `PONG`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ping {
+    /// Replaces the configured listen address.
+    addr: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    timeout: Duration,
+}
+
+impl Ping {
+    /// Returns `None` when the arguments ask for the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if args.first().map(String::as_str) != Some("ping") {
+            return Ok(None);
+        }
+        let mut ping = Self {
+            addr: None,
+            user: None,
+            password: None,
+            timeout: Duration::from_secs(3),
+        };
+        let mut rest = args[1..].iter();
+        while let Some(flag) = rest.next() {
+            let value = rest.next().ok_or(USAGE)?;
+            match flag.as_str() {
+                "--addr" => ping.addr = Some(value.clone()),
+                "--user" => ping.user = Some(value.clone()),
+                "--password" => ping.password = Some(value.clone()),
+                "--timeout" => {
+                    let seconds = value.parse::<f64>().ok().filter(|s| *s > 0.0);
+                    ping.timeout = Duration::from_secs_f64(seconds.ok_or(USAGE)?);
+                }
+                _ => return Err(USAGE.into()),
+            }
+        }
+        Ok(Some(ping))
+    }
+
+    /// Pings and returns the process exit code: 0 when the server answered
+    /// `PONG`, 1 when it could not be reached in time or answered anything
+    /// else.
+    pub fn run(&self, config: &Config) -> i32 {
+        let runtime = RuntimeConfig {
+            flavor: RuntimeFlavor::CurrentThread,
+            worker_threads: 1,
+            ..RuntimeConfig::default()
+        }
+        .build();
+        let result = match runtime {
+            Ok(runtime) => runtime.block_on(async {
+                tokio::time::timeout(self.timeout, self.ping(config))
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".into()))
+            }),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => {
+                println!("PONG");
+                0
+            }
+            Err(e) => {
+                eprintln!("fedis ping: {}", e);
+                1
+            }
+        }
+    }
+
+    async fn ping(&self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let addr = self
+            .addr
+            .clone()
+            .unwrap_or_else(|| local_addr(&config.listen_addr));
+        let stream = TcpStream::connect(&addr)
+            .await
+            .map_err(|e| format!("connecting to {}: {}", addr, e))?;
+        let mut connection = match &config.tls {
+            Some(settings) => {
+                let connector = build_self_connector(settings)?;
+                // The certificate is pinned, so any name will do.
+                let name = ServerName::try_from("localhost")?;
+                let stream = connector
+                    .connect(name, stream)
+                    .await
+                    .map_err(|e| format!("TLS handshake with {}: {}", addr, e))?;
+                Connection::new(stream)
+            }
+            None => Connection::new(stream),
+        };
+        let password = self.password.as_ref().or(config.default_password.as_ref());
+        if let Some(password) = password {
+            let user = self.user.as_deref().unwrap_or(&config.default_user);
+            connection.auth(Some(user), password).await?;
+        }
+        match connection.call(&[b"PING".to_vec()]).await? {
+            Reply::Simple(pong) if pong == "PONG" => Ok(()),
+            Reply::Error(e) => Err(e.into()),
+            other => Err(format!("unexpected reply {:?}", other).into()),
+        }
+    }
+}
+
+/// The address to reach a server listening on `listen_addr` from the same
+/// box: a wildcard address becomes the loopback one.
+fn local_addr(listen_addr: &str) -> String {
+    if let Some(port) = listen_addr.strip_prefix("0.0.0.0:") {
+        format!("127.0.0.1:{}", port)
+    } else if let Some(port) = listen_addr.strip_prefix("[::]:") {
+        format!("[::1]:{}", port)
+    } else {
+        listen_addr.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_ping_options() {
+        assert_eq!(Ping::from_args(&args(&["cli"])).expect("parse"), None);
+        assert!(Ping::from_args(&args(&["ping", "--timeout"])).is_err());
+        assert!(Ping::from_args(&args(&["ping", "--timeout", "0"])).is_err());
+        let ping = Ping::from_args(&args(&["ping"]))
+            .expect("parse")
+            .expect("ping");
+        assert_eq!(ping.timeout, Duration::from_secs(3));
+        let ping = Ping::from_args(&args(&[
+            "ping",
+            "--addr",
+            "10.0.0.5:6380",
+            "--timeout",
+            "0.5",
+        ]))
+        .expect("parse")
+        .expect("ping");
+        assert_eq!(ping.addr.as_deref(), Some("10.0.0.5:6380"));
+        assert_eq!(ping.timeout, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn wildcard_listen_addresses_become_loopback() {
+        assert_eq!(local_addr("0.0.0.0:6379"), "127.0.0.1:6379");
+        assert_eq!(local_addr("[::]:7000"), "[::1]:7000");
+        assert_eq!(local_addr("10.0.0.5:6379"), "10.0.0.5:6379");
+    }
+}
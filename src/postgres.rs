@@ -0,0 +1,461 @@
+use std::num::NonZeroU32;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::{digest, hmac, pbkdf2};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Protocol 3.0.
+const PROTOCOL_VERSION: i32 = 196_608;
+/// Larger messages are refused rather than allocated.
+const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Where to connect and as whom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PgTarget {
+    pub(crate) addr: String,
+    pub(crate) user: String,
+    pub(crate) password: Option<String>,
+    pub(crate) database: String,
+}
+
+/// Why a statement failed.
+#[derive(Debug)]
+pub(crate) enum PgError {
+    /// The connection broke, so the statement may or may not have run.
+    Connection(String),
+    /// The server refused the statement.
+    Refused { sqlstate: String, message: String },
+}
+
+impl PgError {
+    /// Whether running the same statement again would be refused again: a
+    /// data exception, an integrity constraint, a syntax error or a program
+    /// limit. A missing table or privilege may be fixed meanwhile.
+    pub(crate) fn is_permanent(&self) -> bool {
+        match self {
+            PgError::Connection(_) => false,
+            PgError::Refused { sqlstate, .. } => {
+                matches!(sqlstate.get(..2), Some("22" | "23" | "54")) || sqlstate == "42601"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgError::Connection(message) => write!(f, "{}", message),
+            PgError::Refused { sqlstate, message } => write!(f, "{} {}", sqlstate, message),
+        }
+    }
+}
+
+/// A PostgreSQL connection over plain TCP that runs statements with the
+/// simple query protocol, logged in with trust, a cleartext password or
+/// SCRAM-SHA-256.
+pub(crate) struct PgConnection {
+    stream: TcpStream,
+}
+
+impl PgConnection {
+    pub(crate) async fn connect(target: &PgTarget) -> Result<Self, String> {
+        let stream = TcpStream::connect(&target.addr)
+            .await
+            .map_err(|e| format!("connecting to {}: {}", target.addr, e))?;
+        let mut connection = Self { stream };
+        let mut startup = Vec::new();
+        startup.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        for (name, value) in [
+            ("user", target.user.as_str()),
+            ("database", target.database.as_str()),
+            ("application_name", "fedis"),
+        ] {
+            put_cstr(&mut startup, name);
+            put_cstr(&mut startup, value);
+        }
+        startup.push(0);
+        let mut message = ((startup.len() + 4) as i32).to_be_bytes().to_vec();
+        message.extend_from_slice(&startup);
+        connection.write(&message).await?;
+        connection.authenticate(target).await?;
+        connection.ready().await.map_err(|e| e.to_string())?;
+        Ok(connection)
+    }
+
+    /// Runs `sql`, which may hold several statements; the first error
+    /// reported fails it.
+    pub(crate) async fn execute(&mut self, sql: &str) -> Result<(), PgError> {
+        let mut body = Vec::with_capacity(sql.len() + 1);
+        put_cstr(&mut body, sql);
+        self.send(b'Q', &body).await.map_err(PgError::Connection)?;
+        self.ready().await
+    }
+
+    async fn authenticate(&mut self, target: &PgTarget) -> Result<(), String> {
+        let password = || {
+            target
+                .password
+                .as_deref()
+                .ok_or_else(|| "the server asks for a password".to_string())
+        };
+        let mut scram: Option<Scram> = None;
+        loop {
+            let (kind, body) = self.receive().await?;
+            if kind == b'E' {
+                return Err(refusal(&body).to_string());
+            }
+            if kind != b'R' || body.len() < 4 {
+                return Err(format!(
+                    "unexpected message '{}' during login",
+                    kind as char
+                ));
+            }
+            let code = i32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+            let data = &body[4..];
+            match code {
+                // A SCRAM login only counts once the server proved it knows
+                // the password too, in its final message.
+                0 if scram.is_some() => {
+                    return Err("the server skipped the SCRAM server signature".to_string());
+                }
+                0 => return Ok(()),
+                // Cleartext.
+                3 => {
+                    let mut reply = Vec::new();
+                    put_cstr(&mut reply, password()?);
+                    self.send(b'p', &reply).await?;
+                }
+                5 => {
+                    return Err(
+                        "MD5 password authentication is not supported; use scram-sha-256"
+                            .to_string(),
+                    );
+                }
+                // SASL: the mechanisms offered.
+                10 => {
+                    let offered = data
+                        .split(|byte| *byte == 0)
+                        .any(|mechanism| mechanism == b"SCRAM-SHA-256");
+                    if !offered {
+                        return Err(
+                            "the server offers no SASL mechanism fedis supports".to_string()
+                        );
+                    }
+                    let state = Scram::new(password()?);
+                    let first = state.client_first();
+                    let mut reply = Vec::new();
+                    put_cstr(&mut reply, "SCRAM-SHA-256");
+                    reply.extend_from_slice(&(first.len() as i32).to_be_bytes());
+                    reply.extend_from_slice(first.as_bytes());
+                    self.send(b'p', &reply).await?;
+                    scram = Some(state);
+                }
+                11 => {
+                    let state = scram.as_mut().ok_or("unexpected SASL continue")?;
+                    let server_first = String::from_utf8_lossy(data).to_string();
+                    let client_final = state.client_final(&server_first)?;
+                    self.send(b'p', client_final.as_bytes()).await?;
+                }
+                12 => {
+                    let state = scram.take().ok_or("unexpected SASL final")?;
+                    state.verify_server(&String::from_utf8_lossy(data))?;
+                }
+                other => return Err(format!("unsupported authentication method {}", other)),
+            }
+        }
+    }
+
+    /// Reads until `ReadyForQuery`, keeping the first error.
+    async fn ready(&mut self) -> Result<(), PgError> {
+        let mut error = None;
+        loop {
+            let (kind, body) = self.receive().await.map_err(PgError::Connection)?;
+            match kind {
+                b'Z' => return error.map_or(Ok(()), Err),
+                b'E' => {
+                    error.get_or_insert_with(|| refusal(&body));
+                }
+                // Parameter status, backend key data, notices and the
+                // results of the statements.
+                _ => {}
+            }
+        }
+    }
+
+    async fn send(&mut self, kind: u8, body: &[u8]) -> Result<(), String> {
+        let mut message = Vec::with_capacity(body.len() + 5);
+        message.push(kind);
+        message.extend_from_slice(&((body.len() + 4) as i32).to_be_bytes());
+        message.extend_from_slice(body);
+        self.write(&message).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.stream
+            .write_all(bytes)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn receive(&mut self) -> Result<(u8, Vec<u8>), String> {
+        let kind = self.stream.read_u8().await.map_err(|e| e.to_string())?;
+        let len = self.stream.read_i32().await.map_err(|e| e.to_string())?;
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| (4..=MAX_MESSAGE_BYTES).contains(len))
+            .ok_or_else(|| format!("invalid message length {}", len))?;
+        let mut body = vec![0_u8; len - 4];
+        self.stream
+            .read_exact(&mut body)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok((kind, body))
+    }
+}
+
+/// The SQLSTATE and message of an `ErrorResponse`.
+fn refusal(body: &[u8]) -> PgError {
+    let mut code = "";
+    let mut message = "";
+    for field in body.split(|byte| *byte == 0) {
+        let Some((&kind, value)) = field.split_first() else {
+            continue;
+        };
+        let value = std::str::from_utf8(value).unwrap_or_default();
+        match kind {
+            b'C' => code = value,
+            b'M' => message = value,
+            _ => {}
+        }
+    }
+    PgError::Refused {
+        sqlstate: code.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn put_cstr(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+}
+
+/// A string literal for SQL: quotes doubled, for a server with
+/// `standard_conforming_strings` on, as it is by default.
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// A `bytea` literal in hex format.
+pub(crate) fn bytea_literal(value: &[u8]) -> String {
+    let mut out = String::with_capacity(value.len() * 2 + 12);
+    out.push_str("'\\x");
+    for byte in value {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out.push_str("'::bytea");
+    out
+}
+
+/// The client side of SCRAM-SHA-256 (RFC 5802, RFC 7677) without channel
+/// binding.
+struct Scram {
+    /// Empty for PostgreSQL, which takes the user from the startup message.
+    user: String,
+    password: String,
+    nonce: String,
+    /// Set by `client_final`, checked against the server's signature.
+    auth_message: String,
+    salted_password: Vec<u8>,
+}
+
+impl Scram {
+    fn new(password: &str) -> Self {
+        let mut nonce = [0_u8; 18];
+        getrandom::fill(&mut nonce).expect("random nonce");
+        Self::with_nonce("", password, STANDARD.encode(nonce))
+    }
+
+    fn with_nonce(user: &str, password: &str, nonce: String) -> Self {
+        Self {
+            user: user.to_string(),
+            password: password.to_string(),
+            nonce,
+            auth_message: String::new(),
+            salted_password: Vec::new(),
+        }
+    }
+
+    fn client_first_bare(&self) -> String {
+        format!("n={},r={}", self.user, self.nonce)
+    }
+
+    fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare())
+    }
+
+    fn client_final(&mut self, server_first: &str) -> Result<String, String> {
+        let attribute = |name: char| {
+            server_first
+                .split(',')
+                .find_map(|part| part.strip_prefix(name)?.strip_prefix('='))
+                .ok_or_else(|| format!("SCRAM server message lacks '{}'", name))
+        };
+        let nonce = attribute('r')?;
+        if !nonce.starts_with(&self.nonce) {
+            return Err("SCRAM server nonce does not extend ours".to_string());
+        }
+        let salt = STANDARD
+            .decode(attribute('s')?)
+            .map_err(|_| "SCRAM salt is not base64")?;
+        let iterations = attribute('i')?
+            .parse::<u32>()
+            .ok()
+            .and_then(NonZeroU32::new)
+            .ok_or("SCRAM iteration count is invalid")?;
+
+        let mut salted = [0_u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            self.password.as_bytes(),
+            &mut salted,
+        );
+        let client_key = hmac_sha256(&salted, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, &client_key);
+        let without_proof = format!("c=biws,r={}", nonce);
+        self.auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare(),
+            server_first,
+            without_proof
+        );
+        let signature = hmac_sha256(stored_key.as_ref(), self.auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(&signature)
+            .map(|(key, sig)| key ^ sig)
+            .collect();
+        self.salted_password = salted.to_vec();
+        Ok(format!("{},p={}", without_proof, STANDARD.encode(proof)))
+    }
+
+    fn verify_server(&self, server_final: &str) -> Result<(), String> {
+        let server_key = hmac_sha256(&self.salted_password, b"Server Key");
+        let expected = hmac_sha256(&server_key, self.auth_message.as_bytes());
+        match server_final.strip_prefix("v=").map(|v| STANDARD.decode(v)) {
+            Some(Ok(signature)) if signature == expected => Ok(()),
+            _ => Err("SCRAM server signature does not match".to_string()),
+        }
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn scram_matches_the_rfc_7677_example() {
+        let mut scram = Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO".to_string());
+        assert_eq!(scram.client_first(), "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        assert_eq!(
+            scram.client_final(server_first).expect("final"),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert!(
+            scram
+                .verify_server("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+                .is_ok()
+        );
+        assert!(scram.verify_server("v=AAAA").is_err());
+        assert!(scram.client_final("r=someone-else,s=AA==,i=1").is_err());
+    }
+
+    async fn send_auth(stream: &mut TcpStream, code: i32, data: &[u8]) {
+        let mut message = vec![b'R'];
+        message.extend_from_slice(&((data.len() + 8) as i32).to_be_bytes());
+        message.extend_from_slice(&code.to_be_bytes());
+        message.extend_from_slice(data);
+        stream.write_all(&message).await.expect("write");
+    }
+
+    async fn read_message(stream: &mut TcpStream, tagged: bool) -> Vec<u8> {
+        if tagged {
+            stream.read_u8().await.expect("kind");
+        }
+        let len = stream.read_i32().await.expect("len") as usize;
+        let mut body = vec![0_u8; len - 4];
+        stream.read_exact(&mut body).await.expect("body");
+        body
+    }
+
+    /// A server that starts SCRAM and then either sends `server_final` or,
+    /// without one, reports the login as done.
+    async fn fake_server(listener: TcpListener, server_final: Option<&'static str>) {
+        let (mut stream, _) = listener.accept().await.expect("accept");
+        read_message(&mut stream, false).await;
+        send_auth(&mut stream, 10, b"SCRAM-SHA-256\0\0").await;
+        let first = read_message(&mut stream, true).await;
+        let first = String::from_utf8_lossy(&first).to_string();
+        let nonce = first.split("r=").nth(1).expect("nonce");
+        let server_first = format!("r={}srv,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096", nonce);
+        send_auth(&mut stream, 11, server_first.as_bytes()).await;
+        read_message(&mut stream, true).await;
+        match server_final {
+            Some(server_final) => send_auth(&mut stream, 12, server_final.as_bytes()).await,
+            None => send_auth(&mut stream, 0, b"").await,
+        }
+        let _ = stream.read_u8().await;
+    }
+
+    #[tokio::test]
+    async fn scram_login_needs_the_server_signature() {
+        for server_final in [Some("v=AAAA"), None] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+            let target = PgTarget {
+                addr: listener.local_addr().expect("addr").to_string(),
+                user: "app".to_string(),
+                password: Some("pencil".to_string()),
+                database: "app".to_string(),
+            };
+            let server = tokio::spawn(fake_server(listener, server_final));
+            let error = PgConnection::connect(&target)
+                .await
+                .err()
+                .expect("login must fail");
+            assert!(error.contains("SCRAM server signature"), "{}", error);
+            server.await.expect("server");
+        }
+    }
+
+    #[test]
+    fn refusals_of_the_statement_itself_are_permanent() {
+        let refused = |sqlstate: &str| PgError::Refused {
+            sqlstate: sqlstate.to_string(),
+            message: String::new(),
+        };
+        for sqlstate in ["22021", "23505", "42601", "54000"] {
+            assert!(refused(sqlstate).is_permanent(), "{}", sqlstate);
+        }
+        for sqlstate in ["42P01", "42501", "57P01", "40001"] {
+            assert!(!refused(sqlstate).is_permanent(), "{}", sqlstate);
+        }
+        assert!(!PgError::Connection("reset".to_string()).is_permanent());
+    }
+
+    #[test]
+    fn literals_are_quoted() {
+        assert_eq!(quote_literal("it's"), "'it''s'");
+        assert_eq!(bytea_literal(b"\x00hi"), "'\\x006869'::bytea");
+    }
+}
@@ -1,4 +1,10 @@
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt};
+
+/// How much more a `FrameReader` asks the socket for when its buffer runs dry.
+const READ_CHUNK: usize = 16 * 1024;
 
 #[derive(Clone, Copy)]
 pub struct ReadLimits {
@@ -12,11 +18,165 @@ pub enum RespValue {
     Simple(String),
     Error(String),
     Integer(i64),
-    Bulk(Option<Vec<u8>>),
+    Bulk(Option<Bytes>),
     Array(Vec<RespValue>),
     Map(Vec<(RespValue, RespValue)>),
 }
 
+/// Reads frames from a client connection through one growing buffer: every
+/// socket read may bring in several pipelined frames, and bulk strings are
+/// sliced out of that buffer rather than copied into allocations of their own.
+pub struct FrameReader<R> {
+    reader: R,
+    buf: BytesMut,
+    limits: ReadLimits,
+}
+
+impl<R> FrameReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(reader: R, limits: ReadLimits) -> Self {
+        Self {
+            reader,
+            buf: BytesMut::with_capacity(READ_CHUNK),
+            limits,
+        }
+    }
+
+    /// The next frame, or `None` once the peer closed the connection between
+    /// frames. Cancel safe: what a cancelled call read stays buffered for the
+    /// next one.
+    pub async fn read_frame(&mut self) -> Result<Option<RespValue>, Box<dyn std::error::Error>> {
+        loop {
+            if let Some(frame) = decode(&mut self.buf, self.limits)? {
+                return Ok(Some(frame));
+            }
+            self.buf.reserve(READ_CHUNK);
+            if self.reader.read_buf(&mut self.buf).await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err("connection closed in the middle of a frame".into());
+            }
+        }
+    }
+}
+
+/// Takes one complete frame off the front of `buf`, or returns `None` and
+/// leaves `buf` alone when the frame has not fully arrived yet.
+pub fn decode(
+    buf: &mut BytesMut,
+    limits: ReadLimits,
+) -> Result<Option<RespValue>, Box<dyn std::error::Error>> {
+    let Some((parsed, end)) = parse(buf, 0, limits, true)? else {
+        return Ok(None);
+    };
+    let frame = buf.split_to(end).freeze();
+    Ok(Some(parsed.resolve(&frame)))
+}
+
+/// `None` while the input is incomplete.
+type Parse<T> = Result<Option<T>, Box<dyn std::error::Error>>;
+
+/// A frame located in the buffer: bulk strings are ranges into it until the
+/// frame is complete and split off.
+enum Parsed {
+    Simple(String),
+    Integer(i64),
+    Bulk(Option<Range<usize>>),
+    Array(Vec<Parsed>),
+}
+
+impl Parsed {
+    fn resolve(self, frame: &Bytes) -> RespValue {
+        match self {
+            Self::Simple(v) => RespValue::Simple(v),
+            Self::Integer(n) => RespValue::Integer(n),
+            Self::Bulk(range) => RespValue::Bulk(range.map(|range| frame.slice(range))),
+            Self::Array(items) => {
+                RespValue::Array(items.into_iter().map(|item| item.resolve(frame)).collect())
+            }
+        }
+    }
+}
+
+/// Parses the frame starting at `pos`. Returns it with the position just past
+/// it, or `None` when `buf` ends first. Only the top level may be an array, as
+/// with `read_frame_with_limits`.
+fn parse(buf: &[u8], pos: usize, limits: ReadLimits, top: bool) -> Parse<(Parsed, usize)> {
+    let Some(&kind) = buf.get(pos) else {
+        return Ok(None);
+    };
+    if !matches!(kind, b'*' | b'+' | b'$' | b':') || (kind == b'*' && !top) {
+        return Err(if top {
+            "unsupported RESP type".into()
+        } else {
+            "unsupported RESP array element".into()
+        });
+    }
+    let Some((line, mut next)) = parse_line(buf, pos + 1, limits.max_line_bytes)? else {
+        return Ok(None);
+    };
+    let parsed = match kind {
+        b'*' => {
+            let count = std::str::from_utf8(line)?.parse::<usize>()?;
+            if count > limits.max_array_len {
+                return Err("array length exceeds server limit".into());
+            }
+            let mut items = Vec::with_capacity(count);
+            for _ in 0..count {
+                let Some((item, after)) = parse(buf, next, limits, false)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                next = after;
+            }
+            Parsed::Array(items)
+        }
+        b'+' => Parsed::Simple(String::from_utf8(line.to_vec())?),
+        b':' => Parsed::Integer(std::str::from_utf8(line)?.parse::<i64>()?),
+        _ => {
+            let len = std::str::from_utf8(line)?.parse::<i64>()?;
+            if len < 0 {
+                Parsed::Bulk(None)
+            } else {
+                let len = len as usize;
+                if len > limits.max_bulk_bytes {
+                    return Err("bulk string exceeds server limit".into());
+                }
+                let end = next + len;
+                let Some(ending) = buf.get(end..end + 2) else {
+                    return Ok(None);
+                };
+                if ending != b"\r\n" {
+                    return Err("invalid RESP bulk ending".into());
+                }
+                let range = next..end;
+                next = end + 2;
+                Parsed::Bulk(Some(range))
+            }
+        }
+    };
+    Ok(Some((parsed, next)))
+}
+
+/// The CRLF-terminated line starting at `start`, without its CRLF, and the
+/// position after it.
+fn parse_line(buf: &[u8], start: usize, max_line_bytes: usize) -> Parse<(&[u8], usize)> {
+    let rest = &buf[start.min(buf.len())..];
+    let Some(newline) = rest.iter().take(max_line_bytes).position(|&b| b == b'\n') else {
+        if rest.len() >= max_line_bytes {
+            return Err("line length exceeds server limit".into());
+        }
+        return Ok(None);
+    };
+    if newline == 0 || rest[newline - 1] != b'\r' {
+        return Err("invalid RESP line ending".into());
+    }
+    Ok(Some((&rest[..newline - 1], start + newline + 1)))
+}
+
 #[allow(dead_code)]
 pub async fn read_frame<R>(reader: &mut R) -> Result<Option<RespValue>, Box<dyn std::error::Error>>
 where
@@ -67,7 +227,7 @@ where
                                 return Err("bulk string exceeds server limit".into());
                             }
                             let bulk = read_bulk(reader, len as usize).await?;
-                            values.push(RespValue::Bulk(Some(bulk)));
+                            values.push(RespValue::Bulk(Some(bulk.into())));
                         }
                     }
                     b'+' => values.push(RespValue::Simple(
@@ -93,7 +253,7 @@ where
                 if len as usize > limits.max_bulk_bytes {
                     return Err("bulk string exceeds server limit".into());
                 }
-                RespValue::Bulk(Some(read_bulk(reader, len as usize).await?))
+                RespValue::Bulk(Some(read_bulk(reader, len as usize).await?.into()))
             }
         }
         b':' => RespValue::Integer(
@@ -164,7 +324,7 @@ pub fn frame_to_args(frame: RespValue) -> Result<Vec<Vec<u8>>, String> {
             let mut args = Vec::with_capacity(items.len());
             for item in items {
                 match item {
-                    RespValue::Bulk(Some(v)) => args.push(v),
+                    RespValue::Bulk(Some(v)) => args.push(v.into()),
                     RespValue::Simple(v) => args.push(v.into_bytes()),
                     _ => return Err("ERR command must be bulk-string array".to_string()),
                 }
@@ -226,3 +386,148 @@ where
     payload.truncate(len);
     Ok(payload)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ReadLimits = ReadLimits {
+        max_bulk_bytes: 1024,
+        max_array_len: 16,
+        max_line_bytes: 64,
+    };
+
+    fn command(args: &[&[u8]]) -> Vec<u8> {
+        encode(RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg))))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn decoder_waits_for_whole_frames_and_slices_the_buffer() {
+        let mut wire = command(&[b"SET", b"key", b"value"]);
+        wire.extend(command(&[b"GET", b"key"]));
+        let mut buf = BytesMut::new();
+        for (i, byte) in wire.iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            if i + 1 < wire.len() / 2 {
+                assert!(decode(&mut buf, LIMITS).expect("decode").is_none());
+            }
+        }
+
+        let first = decode(&mut buf, LIMITS).expect("decode").expect("frame");
+        let RespValue::Array(items) = &first else {
+            panic!("expected an array, got {first:?}");
+        };
+        let [RespValue::Bulk(Some(set)), RespValue::Bulk(Some(key)), _] = items.as_slice() else {
+            panic!("expected three bulk strings, got {items:?}");
+        };
+        assert_eq!(&set[..], b"SET");
+        // Arguments share the frame's buffer instead of owning copies.
+        assert_eq!(key.as_ptr(), set[set.len()..].as_ptr().wrapping_add(6));
+        assert_eq!(
+            frame_to_args(decode(&mut buf, LIMITS).expect("decode").expect("frame")),
+            Ok(vec![b"GET".to_vec(), b"key".to_vec()])
+        );
+        assert!(buf.is_empty());
+        assert!(decode(&mut buf, LIMITS).expect("decode").is_none());
+    }
+
+    #[test]
+    fn decoder_enforces_limits_and_framing() {
+        let rejects = |wire: &[u8]| decode(&mut BytesMut::from(wire), LIMITS).is_err();
+        assert!(rejects(b"*17\r\n"), "array too long");
+        assert!(rejects(b"*1\r\n$1025\r\n"), "bulk too long");
+        assert!(rejects(&[b'+'; 80]), "line too long");
+        assert!(rejects(b"*1\r\n$3\r\nGETxx"), "bulk without CRLF");
+        assert!(rejects(b"*1\r\n*1\r\n"), "nested array");
+        assert!(rejects(b"$3\nGET\r\n"), "bare LF");
+        assert!(rejects(b"%1\r\n"));
+
+        let mut null = BytesMut::from(&b"*2\r\n$-1\r\n:7\r\n"[..]);
+        let frame = decode(&mut null, LIMITS).expect("decode").expect("frame");
+        assert_eq!(
+            encode(frame),
+            encode(RespValue::Array(vec![
+                RespValue::Bulk(None),
+                RespValue::Integer(7)
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn frame_reader_reads_pipelined_frames_and_reports_truncation() {
+        let mut wire = command(&[b"PING"]);
+        wire.extend(command(&[b"ECHO", &[b'x'; 100_000]]));
+        wire.extend_from_slice(b"*1\r\n$4\r\nPI");
+        let limits = ReadLimits {
+            max_bulk_bytes: 1 << 20,
+            ..LIMITS
+        };
+        let mut reader = FrameReader::new(&wire[..], limits);
+
+        let ping = reader.read_frame().await.expect("read").expect("frame");
+        assert_eq!(frame_to_args(ping), Ok(vec![b"PING".to_vec()]));
+        let echo =
+            frame_to_args(reader.read_frame().await.expect("read").expect("frame")).expect("args");
+        assert_eq!(echo[1].len(), 100_000);
+        assert!(reader.read_frame().await.is_err(), "torn final frame");
+
+        let mut empty = FrameReader::new(&b""[..], limits);
+        assert!(empty.read_frame().await.expect("read").is_none());
+    }
+
+    /// Decoder throughput against the byte-at-a-time reader it replaced:
+    /// `cargo test --release decode_throughput -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn decode_throughput() {
+        let limits = ReadLimits {
+            max_bulk_bytes: 1 << 20,
+            max_array_len: 1024,
+            max_line_bytes: 4096,
+        };
+        let frames = 200_000;
+        let mut wire = Vec::new();
+        for i in 0..frames {
+            wire.extend(command(&[
+                b"SET",
+                format!("key:{i}").as_bytes(),
+                &[b'v'; 64],
+            ]));
+        }
+        let mb = wire.len() as f64 / (1024.0 * 1024.0);
+
+        let started = std::time::Instant::now();
+        let mut reader = FrameReader::new(&wire[..], limits);
+        let mut decoded = 0;
+        while reader.read_frame().await.expect("read").is_some() {
+            decoded += 1;
+        }
+        let buffered = started.elapsed();
+        assert_eq!(decoded, frames);
+
+        let started = std::time::Instant::now();
+        let mut reader = tokio::io::BufReader::new(&wire[..]);
+        let mut decoded = 0;
+        while read_frame_with_limits(&mut reader, limits)
+            .await
+            .expect("read")
+            .is_some()
+        {
+            decoded += 1;
+        }
+        let streamed = started.elapsed();
+        assert_eq!(decoded, frames);
+
+        for (name, elapsed) in [("FrameReader", buffered), ("read_frame", streamed)] {
+            println!(
+                "{name}: {frames} frames, {mb:.1} MiB in {elapsed:?} ({:.0} frames/s, {:.0} MiB/s)",
+                frames as f64 / elapsed.as_secs_f64(),
+                mb / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
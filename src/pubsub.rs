@@ -0,0 +1,359 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+
+use crate::protocol::{FrameReader, RespValue, encode, frame_to_args};
+
+/// Messages a subscriber may fall behind by before it is disconnected.
+const SUBSCRIBER_QUEUE: usize = 4096;
+
+/// Channel messaging for `SUBSCRIBE`/`PUBLISH`: what Sentinel uses to announce
+/// itself on `__sentinel__:hello`. Channels only, no patterns, and messages
+/// stay on the node they were published to.
+#[derive(Clone)]
+pub struct PubSub {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    tx: broadcast::Sender<Message>,
+    /// Subscribers per channel, for PUBLISH's reply.
+    subscribers: Mutex<HashMap<Vec<u8>, usize>>,
+}
+
+#[derive(Clone)]
+struct Message {
+    channel: Bytes,
+    payload: Bytes,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                tx: broadcast::channel(SUBSCRIBER_QUEUE).0,
+                subscribers: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Returns the number of clients that received the message.
+    pub fn publish(&self, channel: &[u8], payload: &[u8]) -> usize {
+        let receivers = self.counts().get(channel).copied().unwrap_or(0);
+        if receivers > 0 {
+            let _ = self.inner.tx.send(Message {
+                channel: Bytes::copy_from_slice(channel),
+                payload: Bytes::copy_from_slice(payload),
+            });
+        }
+        receivers
+    }
+
+    fn counts(&self) -> std::sync::MutexGuard<'_, HashMap<Vec<u8>, usize>> {
+        self.inner.subscribers.lock().expect("pubsub lock")
+    }
+}
+
+/// A connection's channels; dropping it unsubscribes from all of them.
+struct Subscription {
+    hub: PubSub,
+    channels: BTreeSet<Vec<u8>>,
+}
+
+impl Subscription {
+    fn subscribe(&mut self, channel: Vec<u8>) -> RespValue {
+        if self.channels.insert(channel.clone()) {
+            *self.hub.counts().entry(channel.clone()).or_default() += 1;
+        }
+        self.confirmation("subscribe", Some(channel))
+    }
+
+    fn unsubscribe(&mut self, channel: Vec<u8>) -> RespValue {
+        if self.channels.remove(&channel) {
+            release(&mut self.hub.counts(), &channel);
+        }
+        self.confirmation("unsubscribe", Some(channel))
+    }
+
+    fn confirmation(&self, kind: &str, channel: Option<Vec<u8>>) -> RespValue {
+        RespValue::Array(vec![
+            RespValue::Bulk(Some(Bytes::copy_from_slice(kind.as_bytes()))),
+            RespValue::Bulk(channel.map(Bytes::from)),
+            RespValue::Integer(self.channels.len() as i64),
+        ])
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut counts = self.hub.counts();
+        for channel in &self.channels {
+            release(&mut counts, channel);
+        }
+    }
+}
+
+/// Messages on a fixed set of channels, for listeners other than RESP
+/// connections. Dropping it unsubscribes.
+pub struct Subscriber {
+    rx: broadcast::Receiver<Message>,
+    subscription: Subscription,
+}
+
+impl PubSub {
+    pub fn subscriber(&self, channels: Vec<Vec<u8>>) -> Subscriber {
+        // As in serve_subscriber: receive before counting as a subscriber.
+        let rx = self.inner.tx.subscribe();
+        let mut subscription = Subscription {
+            hub: self.clone(),
+            channels: BTreeSet::new(),
+        };
+        for channel in channels {
+            subscription.subscribe(channel);
+        }
+        Subscriber { rx, subscription }
+    }
+}
+
+impl Subscriber {
+    /// The next `(channel, payload)`, or `None` once the hub is gone. Falling
+    /// too far behind is an error, as it is for a connection.
+    pub async fn recv(&mut self) -> Result<Option<(Bytes, Bytes)>, String> {
+        loop {
+            match self.rx.recv().await {
+                Ok(message) => {
+                    if self.subscription.channels.contains(&*message.channel) {
+                        return Ok(Some((message.channel, message.payload)));
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    return Err("subscriber fell too far behind its channels".to_string());
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(None),
+            }
+        }
+    }
+}
+
+fn release(counts: &mut HashMap<Vec<u8>, usize>, channel: &[u8]) {
+    if let Some(count) = counts.get_mut(channel) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(channel);
+        }
+    }
+}
+
+/// Runs a connection in subscriber mode, starting with the SUBSCRIBE or
+/// UNSUBSCRIBE that entered it. Returns `true` when the client went away or
+/// sent QUIT, and `false` once it is subscribed to nothing and back to
+/// issuing normal commands.
+pub async fn serve_subscriber<R, W>(
+    hub: &PubSub,
+    first: Vec<Vec<u8>>,
+    reader: &mut FrameReader<R>,
+    writer: &mut W,
+) -> Result<bool, Box<dyn std::error::Error>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Receiving before confirming means no message published after the
+    // confirmation is missed.
+    let mut rx = hub.inner.tx.subscribe();
+    let mut subscription = Subscription {
+        hub: hub.clone(),
+        channels: BTreeSet::new(),
+    };
+    let mut command = Some(first);
+    loop {
+        if let Some(args) = command.take()
+            && let Some(closed) = subscription.handle(args, writer).await?
+        {
+            return Ok(closed);
+        }
+        tokio::select! {
+            message = rx.recv() => match message {
+                Ok(message) => {
+                    if subscription.channels.contains(&*message.channel) {
+                        let push = RespValue::Array(vec![
+                            RespValue::Bulk(Some(Bytes::from_static(b"message"))),
+                            RespValue::Bulk(Some(message.channel.clone())),
+                            RespValue::Bulk(Some(message.payload.clone())),
+                        ]);
+                        writer.write_all(&encode(push)).await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    return Err("subscriber fell too far behind its channels".into());
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(true),
+            },
+            // Cancel safe: a frame half read when a message wins the race
+            // stays buffered in the reader. The error is flattened to a
+            // string so the select's output stays Send.
+            frame = async { reader.read_frame().await.map_err(|e| e.to_string()) } => {
+                let Some(frame) = frame? else {
+                    return Ok(true);
+                };
+                command = Some(frame_to_args(frame)?);
+            }
+        }
+    }
+}
+
+impl Subscription {
+    /// Answers one command; `Some` ends subscriber mode, with `true` when the
+    /// connection should close.
+    async fn handle<W>(
+        &mut self,
+        args: Vec<Vec<u8>>,
+        writer: &mut W,
+    ) -> Result<Option<bool>, Box<dyn std::error::Error>>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let Some(name) = args.first() else {
+            return Ok(None);
+        };
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+        match name.as_str() {
+            "SUBSCRIBE" if args.len() > 1 => {
+                for channel in args.into_iter().skip(1) {
+                    let reply = self.subscribe(channel);
+                    writer.write_all(&encode(reply)).await?;
+                }
+            }
+            "UNSUBSCRIBE" => {
+                let channels: Vec<Vec<u8>> = if args.len() > 1 {
+                    args.into_iter().skip(1).collect()
+                } else {
+                    self.channels.iter().cloned().collect()
+                };
+                if channels.is_empty() {
+                    let reply = self.confirmation("unsubscribe", None);
+                    writer.write_all(&encode(reply)).await?;
+                }
+                for channel in channels {
+                    let reply = self.unsubscribe(channel);
+                    writer.write_all(&encode(reply)).await?;
+                }
+                if self.channels.is_empty() {
+                    return Ok(Some(false));
+                }
+            }
+            "PING" => {
+                let payload = args.get(1).cloned().unwrap_or_default();
+                let reply = RespValue::Array(vec![
+                    RespValue::Bulk(Some(Bytes::from_static(b"pong"))),
+                    RespValue::Bulk(Some(payload.into())),
+                ]);
+                writer.write_all(&encode(reply)).await?;
+            }
+            "QUIT" => {
+                writer
+                    .write_all(&encode(RespValue::Simple("OK".to_string())))
+                    .await?;
+                return Ok(Some(true));
+            }
+            _ => {
+                let reply = RespValue::Error(format!(
+                    "ERR Can't execute '{}': only SUBSCRIBE / UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                    name.to_lowercase()
+                ));
+                writer.write_all(&encode(reply)).await?;
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ReadLimits, read_frame};
+    use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+    fn command(args: &[&str]) -> Vec<u8> {
+        encode(RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg.as_bytes()))))
+                .collect(),
+        ))
+    }
+
+    async fn next<R: AsyncBufRead + AsyncRead + Unpin>(reader: &mut R) -> Vec<u8> {
+        encode(read_frame(reader).await.expect("read").expect("frame"))
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_messages_until_they_unsubscribe() {
+        let hub = PubSub::new();
+        assert_eq!(hub.publish(b"__sentinel__:hello", b"early"), 0);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let (server_read, mut server_write) = tokio::io::split(server);
+        let limits = ReadLimits {
+            max_bulk_bytes: 1024,
+            max_array_len: 16,
+            max_line_bytes: 1024,
+        };
+        let subscriber = {
+            let hub = hub.clone();
+            tokio::spawn(async move {
+                let mut reader = FrameReader::new(server_read, limits);
+                serve_subscriber(
+                    &hub,
+                    vec![b"SUBSCRIBE".to_vec(), b"__sentinel__:hello".to_vec()],
+                    &mut reader,
+                    &mut server_write,
+                )
+                .await
+                .expect("subscriber")
+            })
+        };
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut client_read = BufReader::new(client_read);
+
+        assert_eq!(
+            next(&mut client_read).await,
+            encode(RespValue::Array(vec![
+                RespValue::Bulk(Some(Bytes::from_static(b"subscribe"))),
+                RespValue::Bulk(Some(Bytes::from_static(b"__sentinel__:hello"))),
+                RespValue::Integer(1),
+            ]))
+        );
+        assert_eq!(hub.publish(b"__sentinel__:hello", b"hi"), 1);
+        assert_eq!(hub.publish(b"other", b"nobody"), 0);
+        assert_eq!(
+            next(&mut client_read).await,
+            command(&["message", "__sentinel__:hello", "hi"])
+        );
+
+        client_write
+            .write_all(&command(&["GET", "a"]))
+            .await
+            .expect("write");
+        let mut error = String::new();
+        client_read.read_line(&mut error).await.expect("read");
+        assert!(error.starts_with("-ERR Can't execute 'get'"), "{error}");
+
+        client_write
+            .write_all(&command(&["UNSUBSCRIBE"]))
+            .await
+            .expect("write");
+        assert_eq!(
+            next(&mut client_read).await,
+            encode(RespValue::Array(vec![
+                RespValue::Bulk(Some(Bytes::from_static(b"unsubscribe"))),
+                RespValue::Bulk(Some(Bytes::from_static(b"__sentinel__:hello"))),
+                RespValue::Integer(0),
+            ]))
+        );
+        assert!(!subscriber.await.expect("join"), "back to normal mode");
+        assert_eq!(hub.publish(b"__sentinel__:hello", b"late"), 0);
+    }
+}
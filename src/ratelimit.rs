@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub commands_per_sec: Option<u64>,
+    pub bytes_per_sec: Option<u64>,
+}
+
+/// Token buckets per user; each bucket holds at most one second worth of budget.
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimit>,
+    buckets: Mutex<HashMap<String, Buckets>>,
+}
+
+struct Buckets {
+    commands: f64,
+    bytes: f64,
+    refreshed_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, RateLimit>) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Charges one command of `bytes` request size to `user`, returning false when
+    /// either the command or the byte budget is exhausted.
+    pub fn try_acquire(&self, user: &str, bytes: usize) -> bool {
+        let Some(limit) = self.limits.get(user) else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.entry(user.to_string()).or_insert_with(|| Buckets {
+            commands: limit.commands_per_sec.unwrap_or(0) as f64,
+            bytes: limit.bytes_per_sec.unwrap_or(0) as f64,
+            refreshed_at: now,
+        });
+
+        let elapsed = now.duration_since(bucket.refreshed_at).as_secs_f64();
+        bucket.refreshed_at = now;
+        if let Some(rate) = limit.commands_per_sec {
+            bucket.commands = (bucket.commands + elapsed * rate as f64).min(rate as f64);
+            if bucket.commands < 1.0 {
+                return false;
+            }
+        }
+        if let Some(rate) = limit.bytes_per_sec {
+            bucket.bytes = (bucket.bytes + elapsed * rate as f64).min(rate as f64);
+            if bucket.bytes <= 0.0 {
+                return false;
+            }
+        }
+
+        if limit.commands_per_sec.is_some() {
+            bucket.commands -= 1.0;
+        }
+        if limit.bytes_per_sec.is_some() {
+            // Oversized requests may push the byte bucket into debt, which later
+            // commands pay back before they are admitted again.
+            bucket.bytes -= bytes as f64;
+        }
+        true
+    }
+}
@@ -0,0 +1,626 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::atomic_file::AtomicFile;
+use crate::checksum::{Crc64Writer, crc64};
+use crate::encoding::ValueType;
+
+/// Highest RDB version written by the Redis releases fedis has been checked against.
+const MAX_RDB_VERSION: u32 = 12;
+/// Version written by exports; Redis 7.0 and later load it.
+const EXPORT_RDB_VERSION: &[u8] = b"0011";
+
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+const MODULE_OPCODE_EOF: usize = 0;
+const MODULE_OPCODE_SINT: usize = 1;
+const MODULE_OPCODE_UINT: usize = 2;
+const MODULE_OPCODE_FLOAT: usize = 3;
+const MODULE_OPCODE_DOUBLE: usize = 4;
+const MODULE_OPCODE_STRING: usize = 5;
+
+/// RedisJSON's type, `ReJSON-RL` at encoding version 3, which saves a
+/// document as its serialized text. fedis writes JSON keys as it does, so
+/// Redis with RedisJSON loads them.
+const REJSON_MODULE_ID: u64 = module_id(b"ReJSON-RL", 3);
+/// fedis' own type for time series, whose one string field is the series as
+/// the keyspace holds it. RedisTimeSeries saves compressed chunks instead, so
+/// Redis cannot load these; they exist for replicas, `DUMP` and migration.
+const FEDIS_TIMESERIES_MODULE_ID: u64 = module_id(b"fedis-tsd", 1);
+
+/// A key as `(key, kind, value, expires_at_ms)`; a JSON document's value is
+/// its text and a time series' its samples.
+pub type RdbEntry = (Vec<u8>, ValueType, Vec<u8>, Option<u64>);
+
+/// Keys read from a Redis `dump.rdb`.
+pub struct RdbDump {
+    /// String keys, JSON documents and time series of database 0.
+    pub entries: Vec<RdbEntry>,
+    /// Keys left out because fedis has no matching type or they live in another database.
+    pub skipped: usize,
+}
+
+pub fn read_rdb(path: &Path) -> Result<RdbDump, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path)?;
+    parse_rdb(&bytes).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Writes the keyspace as an RDB v11 file that Redis can load, replacing `path` atomically;
+/// time series need fedis to load them.
+/// Entries are streamed to disk; the iterator is walked once more up front for the
+/// counts in the RESIZEDB hint.
+pub fn write_rdb<'a, I>(path: &Path, entries: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: Iterator<Item = (&'a [u8], ValueType, &'a [u8], Option<u64>)> + Clone,
+{
+    let mut file = AtomicFile::create(path, "rdb.tmp")?;
+    write_rdb_to(&mut file, entries)?;
+    file.commit()?;
+    Ok(())
+}
+
+pub fn write_rdb_to<'a, W, I>(out: W, entries: I) -> std::io::Result<W>
+where
+    W: Write,
+    I: Iterator<Item = (&'a [u8], ValueType, &'a [u8], Option<u64>)> + Clone,
+{
+    let mut out = Crc64Writer::new(out);
+    out.write_all(b"REDIS")?;
+    out.write_all(EXPORT_RDB_VERSION)?;
+    for (field, value) in [
+        ("redis-ver", "7.0.0"),
+        ("redis-bits", "64"),
+        ("fedis-ver", env!("CARGO_PKG_VERSION")),
+    ] {
+        out.write_all(&[OPCODE_AUX])?;
+        write_string(&mut out, field.as_bytes())?;
+        write_string(&mut out, value.as_bytes())?;
+    }
+    let (keys, expiring) = entries.clone().fold((0, 0), |(keys, expiring), e| {
+        (keys + 1, expiring + e.3.is_some() as usize)
+    });
+    out.write_all(&[OPCODE_SELECTDB])?;
+    write_length(&mut out, 0)?;
+    out.write_all(&[OPCODE_RESIZEDB])?;
+    write_length(&mut out, keys)?;
+    write_length(&mut out, expiring)?;
+    for (key, kind, value, expires_at) in entries {
+        if let Some(expires_at) = expires_at {
+            out.write_all(&[OPCODE_EXPIRETIME_MS])?;
+            out.write_all(&expires_at.to_le_bytes())?;
+        }
+        out.write_all(&[value_type(kind)])?;
+        write_string(&mut out, key)?;
+        write_value(&mut out, kind, value)?;
+    }
+    out.write_all(&[OPCODE_EOF])?;
+    let checksum = out.crc();
+    let mut out = out.into_inner();
+    out.write_all(&checksum.to_le_bytes())?;
+    Ok(out)
+}
+
+/// A value serialized as `DUMP` does: the RDB encoding of the value, the RDB
+/// version and a CRC64 of both, so Redis and fedis can `RESTORE` it.
+pub fn dump_payload(kind: ValueType, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![value_type(kind)];
+    write_value(&mut out, kind, value).expect("writing to a Vec cannot fail");
+    let version: u16 = std::str::from_utf8(EXPORT_RDB_VERSION)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .expect("export version is numeric");
+    out.extend_from_slice(&version.to_le_bytes());
+    let checksum = crc64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// The value in a `DUMP` payload. Only strings, JSON documents and time
+/// series are accepted, as fedis stores nothing else.
+pub fn parse_dump_payload(payload: &[u8]) -> Result<(ValueType, Vec<u8>), String> {
+    let invalid = || "DUMP payload version or checksum are wrong".to_string();
+    let body_len = payload.len().checked_sub(10).ok_or_else(invalid)?;
+    let (body, footer) = payload.split_at(body_len);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let checksum = u64::from_le_bytes(footer[2..].try_into().expect("8-byte footer"));
+    if u32::from(version) > MAX_RDB_VERSION
+        || (checksum != 0 && checksum != crc64(&payload[..body_len + 2]))
+    {
+        return Err(invalid());
+    }
+    let mut reader = Reader {
+        bytes: body,
+        pos: 0,
+    };
+    let value = match reader.byte()? {
+        TYPE_STRING => (ValueType::String, reader.string()?),
+        TYPE_MODULE_2 => {
+            let kind = match reader.length()? as u64 {
+                REJSON_MODULE_ID => ValueType::Json,
+                FEDIS_TIMESERIES_MODULE_ID => ValueType::TimeSeries,
+                _ => {
+                    return Err(
+                        "only string, JSON and time series values can be restored".to_string()
+                    );
+                }
+            };
+            let value = reader.module_value(true)?.ok_or("Bad data format")?;
+            (kind, value)
+        }
+        _ => return Err("only string, JSON and time series values can be restored".to_string()),
+    };
+    if reader.pos != body.len() {
+        return Err("Bad data format".to_string());
+    }
+    Ok(value)
+}
+
+fn value_type(kind: ValueType) -> u8 {
+    match kind {
+        ValueType::Json | ValueType::TimeSeries => TYPE_MODULE_2,
+        _ => TYPE_STRING,
+    }
+}
+
+/// A value after its type byte: a string as is, a JSON document as the one
+/// string field RedisJSON saves, a time series likewise under fedis' type.
+fn write_value(out: &mut impl Write, kind: ValueType, value: &[u8]) -> std::io::Result<()> {
+    match kind {
+        ValueType::Json | ValueType::TimeSeries => {
+            let id = match kind {
+                ValueType::Json => REJSON_MODULE_ID,
+                _ => FEDIS_TIMESERIES_MODULE_ID,
+            };
+            write_length(out, id as usize)?;
+            write_length(out, MODULE_OPCODE_STRING)?;
+            write_string(out, value)?;
+            write_length(out, MODULE_OPCODE_EOF)
+        }
+        _ => write_string(out, value),
+    }
+}
+
+/// Module type IDs pack a 9-character name, 6 bits per character, above a
+/// 10-bit encoding version.
+const fn module_id(name: &[u8; 9], encver: u64) -> u64 {
+    const CHARSET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut id = 0;
+    let mut i = 0;
+    while i < name.len() {
+        let mut pos = 0;
+        while CHARSET[pos] != name[i] {
+            pos += 1;
+        }
+        id = (id << 6) | pos as u64;
+        i += 1;
+    }
+    (id << 10) | encver
+}
+
+fn write_length(out: &mut impl Write, len: usize) -> std::io::Result<()> {
+    if len < 1 << 6 {
+        out.write_all(&[len as u8])
+    } else if len < 1 << 14 {
+        out.write_all(&(0x4000 | len as u16).to_be_bytes())
+    } else if let Ok(len) = u32::try_from(len) {
+        out.write_all(&[0x80])?;
+        out.write_all(&len.to_be_bytes())
+    } else {
+        out.write_all(&[0x81])?;
+        out.write_all(&(len as u64).to_be_bytes())
+    }
+}
+
+fn write_string(out: &mut impl Write, value: &[u8]) -> std::io::Result<()> {
+    write_length(out, value.len())?;
+    out.write_all(value)
+}
+
+pub fn parse_rdb(bytes: &[u8]) -> Result<RdbDump, String> {
+    if bytes.len() < 9 || &bytes[..5] != b"REDIS" {
+        return Err("not an RDB file".to_string());
+    }
+    let version: u32 = std::str::from_utf8(&bytes[5..9])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or("invalid RDB version")?;
+    if version == 0 || version > MAX_RDB_VERSION {
+        return Err(format!("unsupported RDB version {}", version));
+    }
+
+    let mut reader = Reader { bytes, pos: 9 };
+    let mut dump = RdbDump {
+        entries: Vec::new(),
+        skipped: 0,
+    };
+    let mut db = 0;
+    let mut expires_at = None;
+    loop {
+        let opcode = reader.byte()?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => db = reader.length()?,
+            OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                expires_at = Some(u64::from_le_bytes(reader.array()?));
+            }
+            OPCODE_EXPIRETIME => {
+                expires_at = Some(u32::from_le_bytes(reader.array()?) as u64 * 1000);
+            }
+            OPCODE_IDLE => {
+                reader.length()?;
+            }
+            OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            OPCODE_FUNCTION2 => {
+                reader.string()?;
+            }
+            OPCODE_MODULE_AUX => return Err("module data is not supported".to_string()),
+            value_type => {
+                let key = reader.string()?;
+                if value_type == TYPE_STRING {
+                    let value = reader.string()?;
+                    if db == 0 {
+                        dump.entries
+                            .push((key, ValueType::String, value, expires_at));
+                    } else {
+                        dump.skipped += 1;
+                    }
+                } else if value_type == TYPE_MODULE_2 {
+                    let kind = match reader.length()? as u64 {
+                        REJSON_MODULE_ID => Some(ValueType::Json),
+                        FEDIS_TIMESERIES_MODULE_ID => Some(ValueType::TimeSeries),
+                        _ => None,
+                    };
+                    match (kind, reader.module_value(kind.is_some())?) {
+                        (Some(kind), Some(value)) if db == 0 => {
+                            dump.entries.push((key, kind, value, expires_at))
+                        }
+                        _ => dump.skipped += 1,
+                    }
+                } else {
+                    reader.skip_value(value_type)?;
+                    dump.skipped += 1;
+                }
+                expires_at = None;
+            }
+        }
+    }
+
+    if version >= 5 {
+        let end = reader.pos;
+        let checksum = u64::from_le_bytes(reader.array()?);
+        // A zero checksum means the writer had `rdbchecksum no`.
+        if checksum != 0 && checksum != crc64(&bytes[..end]) {
+            return Err("RDB checksum mismatch".to_string());
+        }
+    }
+    Ok(dump)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+enum Length {
+    Len(usize),
+    /// A special string encoding (integer or LZF) instead of a length.
+    Encoded(u8),
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("truncated RDB file")?;
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("slice has length N"))
+    }
+
+    fn raw_length(&mut self) -> Result<Length, String> {
+        let first = self.byte()?;
+        let len = match first >> 6 {
+            0 => (first & 0x3F) as u64,
+            1 => (((first & 0x3F) as u64) << 8) | self.byte()? as u64,
+            2 if first == 0x80 => u32::from_be_bytes(self.array()?) as u64,
+            2 if first == 0x81 => u64::from_be_bytes(self.array()?),
+            2 => return Err(format!("invalid RDB length prefix {:#x}", first)),
+            _ => return Ok(Length::Encoded(first & 0x3F)),
+        };
+        usize::try_from(len)
+            .map(Length::Len)
+            .map_err(|_| "RDB length out of range".to_string())
+    }
+
+    fn length(&mut self) -> Result<usize, String> {
+        match self.raw_length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err("unexpected encoded RDB length".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        match self.raw_length()? {
+            Length::Len(len) => Ok(self.take(len)?.to_vec()),
+            Length::Encoded(0) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            Length::Encoded(1) => Ok(i16::from_le_bytes(self.array()?).to_string().into_bytes()),
+            Length::Encoded(2) => Ok(i32::from_le_bytes(self.array()?).to_string().into_bytes()),
+            Length::Encoded(3) => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Length::Encoded(other) => Err(format!("unknown RDB string encoding {}", other)),
+        }
+    }
+
+    /// The fields a module saved after its type ID, up to their end marker.
+    /// Returns the first string field when `keep` asks for it; other modules'
+    /// values are stepped over, as their fields say how long they are.
+    fn module_value(&mut self, keep: bool) -> Result<Option<Vec<u8>>, String> {
+        let mut kept = None;
+        loop {
+            match self.length()? {
+                MODULE_OPCODE_EOF => return Ok(kept),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                    self.length()?;
+                }
+                MODULE_OPCODE_FLOAT => {
+                    self.take(4)?;
+                }
+                MODULE_OPCODE_DOUBLE => {
+                    self.take(8)?;
+                }
+                MODULE_OPCODE_STRING => {
+                    let value = self.string()?;
+                    if keep && kept.is_none() {
+                        kept = Some(value);
+                    }
+                }
+                other => return Err(format!("unknown RDB module opcode {}", other)),
+            }
+        }
+    }
+
+    /// Steps over the value of a type fedis does not store yet.
+    fn skip_value(&mut self, value_type: u8) -> Result<(), String> {
+        match value_type {
+            TYPE_LIST | TYPE_SET | TYPE_LIST_QUICKLIST => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                }
+            }
+            TYPE_HASH => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.string()?;
+                }
+            }
+            TYPE_ZSET => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    // Scores are a length-prefixed ASCII double; 253..=255 are NaN/±inf.
+                    let len = self.byte()?;
+                    if len < 253 {
+                        self.take(len as usize)?;
+                    }
+                }
+            }
+            TYPE_ZSET_2 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.take(8)?;
+                }
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                for _ in 0..self.length()? {
+                    self.length()?;
+                    self.string()?;
+                }
+            }
+            TYPE_HASH_ZIPMAP | TYPE_LIST_ZIPLIST | TYPE_SET_INTSET | TYPE_ZSET_ZIPLIST
+            | TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK | TYPE_ZSET_LISTPACK | TYPE_SET_LISTPACK => {
+                self.string()?;
+            }
+            other => return Err(format!("unsupported RDB value type {}", other)),
+        }
+        Ok(())
+    }
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(len);
+    let mut idx = 0;
+    while idx < input.len() {
+        let ctrl = input[idx] as usize;
+        idx += 1;
+        if ctrl < 32 {
+            let literal = input
+                .get(idx..idx + ctrl + 1)
+                .ok_or("truncated LZF literal")?;
+            out.extend_from_slice(literal);
+            idx += ctrl + 1;
+            continue;
+        }
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += *input.get(idx).ok_or("truncated LZF back reference")? as usize;
+            idx += 1;
+        }
+        let low = *input.get(idx).ok_or("truncated LZF back reference")? as usize;
+        idx += 1;
+        let start = out
+            .len()
+            .checked_sub(((ctrl & 0x1F) << 8) + low + 1)
+            .ok_or("invalid LZF back reference")?;
+        for i in start..start + run + 2 {
+            out.push(out[i]);
+        }
+    }
+    if out.len() != len {
+        return Err("LZF string length mismatch".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_payloads_round_trip_and_read_redis_dumps() {
+        let payload = dump_payload(ValueType::String, b"hello");
+        assert_eq!(
+            parse_dump_payload(&payload),
+            Ok((ValueType::String, b"hello".to_vec()))
+        );
+        let doc = dump_payload(ValueType::Json, b"{\"a\":1}");
+        assert_eq!(
+            parse_dump_payload(&doc),
+            Ok((ValueType::Json, b"{\"a\":1}".to_vec()))
+        );
+
+        // `DUMP mykey` of the integer 10, from the Redis documentation.
+        let redis = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        assert_eq!(
+            parse_dump_payload(redis),
+            Ok((ValueType::String, b"10".to_vec()))
+        );
+
+        let mut corrupt = payload.clone();
+        corrupt[3] ^= 1;
+        assert!(parse_dump_payload(&corrupt).is_err());
+        assert!(parse_dump_payload(b"short").is_err());
+    }
+
+    #[test]
+    fn reads_string_keys_and_skips_other_types() {
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(&[OPCODE_AUX, 9]);
+        rdb.extend_from_slice(b"redis-ver");
+        rdb.extend_from_slice(&[5]);
+        rdb.extend_from_slice(b"7.2.4");
+        rdb.extend_from_slice(&[OPCODE_SELECTDB, 0, OPCODE_RESIZEDB, 4, 1]);
+        // Plain string.
+        rdb.extend_from_slice(&[TYPE_STRING, 1, b'a', 2, b'h', b'i']);
+        // Integer-encoded string with a millisecond expiry.
+        rdb.push(OPCODE_EXPIRETIME_MS);
+        rdb.extend_from_slice(&4_102_444_800_000_u64.to_le_bytes());
+        rdb.extend_from_slice(&[TYPE_STRING, 1, b'n', 0xC1]);
+        rdb.extend_from_slice(&(-300_i16).to_le_bytes());
+        // LZF-compressed "aaaaaaaaaa".
+        rdb.extend_from_slice(&[TYPE_STRING, 1, b'z', 0xC3, 5, 10, 0x00, b'a', 0xE0, 0, 0]);
+        // A set, which fedis does not store yet.
+        rdb.extend_from_slice(&[TYPE_SET, 1, b's', 2, 1, b'x', 1, b'y']);
+        rdb.extend_from_slice(&[OPCODE_SELECTDB, 1, TYPE_STRING, 1, b'o', 1, b'1']);
+        rdb.push(OPCODE_EOF);
+        let checksum = crc64(&rdb);
+        rdb.extend_from_slice(&checksum.to_le_bytes());
+
+        let dump = parse_rdb(&rdb).expect("parse rdb");
+        assert_eq!(
+            dump.entries,
+            vec![
+                (b"a".to_vec(), ValueType::String, b"hi".to_vec(), None),
+                (
+                    b"n".to_vec(),
+                    ValueType::String,
+                    b"-300".to_vec(),
+                    Some(4_102_444_800_000)
+                ),
+                (
+                    b"z".to_vec(),
+                    ValueType::String,
+                    b"aaaaaaaaaa".to_vec(),
+                    None
+                ),
+            ]
+        );
+        assert_eq!(dump.skipped, 2);
+
+        let last = rdb.len() - 1;
+        rdb[last] ^= 1;
+        assert_eq!(
+            parse_rdb(&rdb).err(),
+            Some("RDB checksum mismatch".to_string())
+        );
+    }
+
+    #[test]
+    fn exported_rdb_reads_back() {
+        let entries = vec![
+            (b"short".to_vec(), ValueType::String, b"v".to_vec(), None),
+            (
+                b"long".to_vec(),
+                ValueType::String,
+                vec![b'x'; 20_000],
+                Some(4_102_444_800_000),
+            ),
+            (b"mid".to_vec(), ValueType::String, vec![b'y'; 100], None),
+            (
+                b"doc".to_vec(),
+                ValueType::Json,
+                b"{\"a\":[1,2]}".to_vec(),
+                Some(4_102_444_800_000),
+            ),
+        ];
+        let rdb = write_rdb_to(
+            Vec::new(),
+            entries
+                .iter()
+                .map(|(k, t, v, e)| (k.as_slice(), *t, v.as_slice(), *e)),
+        )
+        .expect("encode rdb");
+        assert_eq!(&rdb[..9], b"REDIS0011");
+        let dump = parse_rdb(&rdb).expect("parse exported rdb");
+        assert_eq!(dump.entries, entries);
+        assert_eq!(dump.skipped, 0);
+    }
+}
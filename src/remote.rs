@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use url::Url;
+
+use crate::s3::{S3Credentials, S3Sink};
+
+/// Somewhere off the box to keep copies of snapshots. Methods block; callers
+/// run them on the blocking pool.
+pub trait SnapshotSink: Send + Sync {
+    /// Uploads the file at `path` as `name`, replacing any object of that name.
+    fn put(&self, name: &str, path: &Path) -> Result<(), String>;
+    fn get(&self, name: &str, dest: &Path) -> Result<(), String>;
+    /// Names of every stored object, in no particular order.
+    fn list(&self) -> Result<Vec<String>, String>;
+    fn delete(&self, name: &str) -> Result<(), String>;
+    /// Where the sink stores things, for logs.
+    fn describe(&self) -> String;
+}
+
+/// A directory, typically a network mount.
+pub struct LocalDirSink {
+    dir: PathBuf,
+}
+
+impl LocalDirSink {
+    pub fn new(dir: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        Ok(Self { dir })
+    }
+}
+
+impl SnapshotSink for LocalDirSink {
+    fn put(&self, name: &str, path: &Path) -> Result<(), String> {
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        std::fs::copy(path, &tmp)
+            .and_then(|_| std::fs::rename(&tmp, self.dir.join(name)))
+            .map_err(|e| format!("{}: {}", self.dir.display(), e))
+    }
+
+    fn get(&self, name: &str, dest: &Path) -> Result<(), String> {
+        std::fs::copy(self.dir.join(name), dest)
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", self.dir.display(), e))
+    }
+
+    fn list(&self) -> Result<Vec<String>, String> {
+        let entries =
+            std::fs::read_dir(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        Ok(entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect())
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        std::fs::remove_file(self.dir.join(name))
+            .map_err(|e| format!("{}: {}", self.dir.display(), e))
+    }
+
+    fn describe(&self) -> String {
+        self.dir.display().to_string()
+    }
+}
+
+/// Uploads snapshots under timestamped names, keeps the newest `keep`, and
+/// restores the newest one onto a fresh node.
+#[derive(Clone)]
+pub struct RemoteSnapshots {
+    sink: Arc<dyn SnapshotSink>,
+    keep: usize,
+}
+
+impl RemoteSnapshots {
+    pub fn new(sink: Arc<dyn SnapshotSink>, keep: usize) -> Self {
+        Self {
+            sink,
+            keep: keep.max(1),
+        }
+    }
+
+    /// Parses `s3://bucket/prefix`, `gs://bucket/prefix` (GCS XML API with HMAC
+    /// keys) or `file:///dir`.
+    pub fn parse(
+        target: &str,
+        keep: usize,
+        region: Option<String>,
+        endpoint: Option<String>,
+        credentials: Option<S3Credentials>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let url = Url::parse(target).map_err(|e| format!("invalid snapshot target: {}", e))?;
+        let sink: Arc<dyn SnapshotSink> = match url.scheme() {
+            "file" => Arc::new(LocalDirSink::new(
+                url.to_file_path()
+                    .map_err(|_| "file snapshot targets need an absolute path")?,
+            )?),
+            scheme @ ("s3" | "gs") => {
+                let bucket = url
+                    .host_str()
+                    .filter(|b| !b.is_empty())
+                    .ok_or("snapshot target has no bucket")?;
+                let credentials =
+                    credentials.ok_or("snapshot target needs object store credentials")?;
+                let (region, endpoint) = match scheme {
+                    "gs" => (
+                        region.unwrap_or_else(|| "auto".to_string()),
+                        Some(
+                            endpoint
+                                .unwrap_or_else(|| "https://storage.googleapis.com".to_string()),
+                        ),
+                    ),
+                    _ => (region.unwrap_or_else(|| "us-east-1".to_string()), endpoint),
+                };
+                Arc::new(S3Sink::new(
+                    bucket,
+                    url.path(),
+                    &region,
+                    endpoint.as_deref(),
+                    credentials,
+                )?)
+            }
+            other => return Err(format!("unsupported snapshot target scheme '{}'", other).into()),
+        };
+        Ok(Self::new(sink, keep))
+    }
+
+    pub fn describe(&self) -> String {
+        self.sink.describe()
+    }
+
+    /// Uploads `path` as the snapshot taken at `created_ms`, then deletes all but
+    /// the newest `keep`. Returns the uploaded name.
+    pub fn upload(&self, path: &Path, created_ms: u64) -> Result<String, String> {
+        // Zero-padded so that names sort by age.
+        let name = format!("fedis-{:020}.snapshot", created_ms);
+        self.sink.put(&name, path)?;
+        let stored = self.snapshots()?;
+        let excess = stored.len().saturating_sub(self.keep);
+        for old in &stored[..excess] {
+            self.sink.delete(old)?;
+        }
+        Ok(name)
+    }
+
+    /// Downloads the newest snapshot to `dest`. Returns its name, or `None` when
+    /// nothing has been uploaded yet.
+    pub fn restore_latest(&self, dest: &Path) -> Result<Option<String>, String> {
+        let Some(name) = self.snapshots()?.pop() else {
+            return Ok(None);
+        };
+        let tmp = dest.with_extension("snapshot.download");
+        self.sink.get(&name, &tmp)?;
+        std::fs::rename(&tmp, dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+        Ok(Some(name))
+    }
+
+    fn snapshots(&self) -> Result<Vec<String>, String> {
+        let mut names: Vec<String> = self
+            .sink
+            .list()?
+            .into_iter()
+            .filter(|name| name.starts_with("fedis-") && name.ends_with(".snapshot"))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_keep_the_newest_snapshots() {
+        let root = std::env::temp_dir().join(format!("fedis-remote-test-{}", std::process::id()));
+        let remote = RemoteSnapshots::parse(
+            &format!("file://{}", root.join("bucket").display()),
+            2,
+            None,
+            None,
+            None,
+        )
+        .expect("target");
+        let local = root.join("local.snapshot");
+        for (created, contents) in [(300, "c"), (1_000, "d"), (20, "a"), (100, "b")] {
+            std::fs::write(&local, contents).expect("write local");
+            remote.upload(&local, created).expect("upload");
+        }
+
+        let mut stored = remote.sink.list().expect("list");
+        stored.sort();
+        assert_eq!(
+            stored,
+            vec![
+                "fedis-00000000000000000300.snapshot".to_string(),
+                "fedis-00000000000000001000.snapshot".to_string(),
+            ]
+        );
+
+        let restored = root.join("restored.snapshot");
+        assert_eq!(
+            remote.restore_latest(&restored).expect("restore"),
+            Some("fedis-00000000000000001000.snapshot".to_string())
+        );
+        assert_eq!(std::fs::read(&restored).expect("read"), b"d");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,1590 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    ReadBuf,
+};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::{Mutex, broadcast};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::auth::generate_password;
+use crate::command::CommandExecutor;
+use crate::persistence::{LogRecord, encode_resp_record};
+use crate::protocol::{
+    FrameReader, ReadLimits, RespValue, encode, frame_to_args, read_frame_with_limits,
+};
+use crate::store::Store;
+
+/// Largest full-sync payload accepted from a master.
+const MAX_RDB_BYTES: usize = 16 * 1024 * 1024 * 1024;
+const STREAM_LIMITS: ReadLimits = ReadLimits {
+    max_bulk_bytes: 512 * 1024 * 1024,
+    max_array_len: 1024 * 1024,
+    max_line_bytes: 4096,
+};
+
+/// The master this node follows, from `FEDIS_REPLICAOF` or `REPLICAOF`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicaOf {
+    pub host: String,
+    pub port: u16,
+    /// `masteruser`/`masterauth`: credentials sent with AUTH before syncing.
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Parses `host port` (as in `replicaof`) or `host:port`.
+pub fn parse_replica_of(value: &str) -> Result<(String, u16), Box<dyn std::error::Error>> {
+    let value = value.trim();
+    let (host, port) = value
+        .split_once(char::is_whitespace)
+        .or_else(|| value.rsplit_once(':'))
+        .ok_or("FEDIS_REPLICAOF must be '<host> <port>'")?;
+    let port = port
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid master port '{}'", port.trim()))?;
+    Ok((host.trim().to_string(), port))
+}
+
+/// Where a replica is in the master's stream. Survives reconnects, so a dropped
+/// link resumes with a partial resync when the master still has the backlog.
+#[derive(Default)]
+pub struct ReplicaState {
+    link_up: AtomicBool,
+    /// Replication ID of the master's history that `offset` refers to.
+    replid: std::sync::Mutex<Option<String>>,
+    offset: AtomicU64,
+    /// When the master last sent anything, in ms.
+    last_io_ms: AtomicU64,
+    /// When the link last went down, or when following started, in ms.
+    down_since_ms: AtomicU64,
+}
+
+impl ReplicaState {
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::Relaxed)
+    }
+}
+
+/// Follows a Redis (or fedis) master: handshake, PSYNC, a full sync from the
+/// master's RDB, then the write stream applied through the executor.
+pub struct Replica {
+    master: ReplicaOf,
+    executor: Arc<CommandExecutor>,
+    store: Store,
+    /// Our own port, announced with `REPLCONF listening-port`.
+    listening_port: u16,
+    state: Arc<ReplicaState>,
+}
+
+impl Replica {
+    pub fn new(
+        master: ReplicaOf,
+        executor: Arc<CommandExecutor>,
+        store: Store,
+        listening_port: u16,
+    ) -> Self {
+        Self {
+            master,
+            executor,
+            store,
+            listening_port,
+            state: Arc::new(ReplicaState {
+                down_since_ms: AtomicU64::new(now_ms()),
+                ..ReplicaState::default()
+            }),
+        }
+    }
+
+    /// Keeps the link up for as long as the task runs, reconnecting after a
+    /// second whenever it drops.
+    pub async fn run(self) {
+        loop {
+            match self.follow().await {
+                Ok(()) => warn!(
+                    master = %self.master_addr(),
+                    "master closed the replication link"
+                ),
+                Err(e) => warn!(
+                    master = %self.master_addr(),
+                    error = %e,
+                    "replication link failed"
+                ),
+            }
+            self.state.link_up.store(false, Ordering::Relaxed);
+            self.state.down_since_ms.store(now_ms(), Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    fn master_addr(&self) -> String {
+        format!("{}:{}", self.master.host, self.master.port)
+    }
+
+    async fn follow(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let stream = TcpStream::connect((self.master.host.as_str(), self.master.port)).await?;
+        let (read, write) = stream.into_split();
+        let mut reader = Counted::new(BufReader::new(read));
+        let writer = Arc::new(Mutex::new(write));
+
+        self.handshake(&mut reader, &writer).await?;
+        let (replid, offset) = {
+            let known = self
+                .state
+                .replid
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            match known {
+                Some(replid) => (replid, (self.state.offset() + 1).to_string()),
+                None => ("?".to_string(), "-1".to_string()),
+            }
+        };
+        let reply = command(&mut reader, &writer, &["PSYNC", &replid, &offset]).await?;
+        let mut words = reply.split_whitespace();
+        match words.next() {
+            Some("FULLRESYNC") => {
+                let replid = words.next().ok_or("FULLRESYNC without a replication ID")?;
+                let offset = words
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("FULLRESYNC without an offset")?;
+                let rdb = read_rdb_payload(&mut reader).await?;
+                let dump = crate::rdb::parse_rdb(&rdb)?;
+                let loaded = self.store.replace_all(dump.entries).await?;
+                info!(
+                    master = %self.master_addr(),
+                    replid,
+                    offset,
+                    loaded,
+                    skipped = dump.skipped,
+                    "full resync from master"
+                );
+                if dump.skipped > 0 {
+                    warn!(
+                        skipped = dump.skipped,
+                        "master keys of unsupported types or non-zero databases were not loaded"
+                    );
+                }
+                *self.state.replid.lock().unwrap_or_else(|e| e.into_inner()) =
+                    Some(replid.to_string());
+                self.state.offset.store(offset, Ordering::Relaxed);
+            }
+            Some("CONTINUE") => {
+                if let Some(replid) = words.next() {
+                    *self.state.replid.lock().unwrap_or_else(|e| e.into_inner()) =
+                        Some(replid.to_string());
+                }
+                info!(
+                    master = %self.master_addr(),
+                    offset = self.state.offset(),
+                    "partial resync from master"
+                );
+            }
+            _ => return Err(format!("unexpected PSYNC reply '{}'", reply).into()),
+        }
+        self.state.link_up.store(true, Ordering::Relaxed);
+        self.state.last_io_ms.store(now_ms(), Ordering::Relaxed);
+
+        // Aborted with the link, including when REPLICAOF cancels this task.
+        let _ack = AbortOnDrop(tokio::spawn(send_acks(writer.clone(), self.state.clone())));
+        self.apply_stream(&mut reader, &writer).await
+    }
+
+    async fn handshake<R>(
+        &self,
+        reader: &mut R,
+        writer: &Mutex<OwnedWriteHalf>,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        R: AsyncBufRead + AsyncRead + Unpin,
+    {
+        authenticate(reader, writer, &self.master).await?;
+        command(reader, writer, &["PING"]).await?;
+        command(
+            reader,
+            writer,
+            &[
+                "REPLCONF",
+                "listening-port",
+                &self.listening_port.to_string(),
+            ],
+        )
+        .await?;
+        // Masters older than 4.0 reject capabilities; they still serve PSYNC.
+        let _ = command(reader, writer, &["REPLCONF", "capa", "psync2"]).await;
+        Ok(())
+    }
+
+    /// Applies commands until the master closes the link. Only database 0 is
+    /// replicated, like RDB imports.
+    async fn apply_stream<R>(
+        &self,
+        reader: &mut Counted<R>,
+        writer: &Mutex<OwnedWriteHalf>,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        R: AsyncBufRead + AsyncRead + Unpin,
+    {
+        let mut db = 0;
+        loop {
+            let start = reader.consumed;
+            let Some(frame) = read_frame_with_limits(reader, STREAM_LIMITS).await? else {
+                return Ok(());
+            };
+            self.state.last_io_ms.store(now_ms(), Ordering::Relaxed);
+            let args = frame_to_args(frame)?;
+            let Some(name) = args.first() else {
+                continue;
+            };
+            match name.to_ascii_uppercase().as_slice() {
+                b"SELECT" => {
+                    db = args
+                        .get(1)
+                        .and_then(|v| std::str::from_utf8(v).ok()?.parse().ok())
+                        .ok_or("master sent an invalid SELECT")?;
+                }
+                b"REPLCONF"
+                    if args
+                        .get(1)
+                        .is_some_and(|v| v.eq_ignore_ascii_case(b"GETACK")) =>
+                {
+                    // The offset acknowledged excludes the GETACK itself.
+                    let offset = self.state.offset();
+                    write_command(writer, &["REPLCONF", "ACK", &offset.to_string()]).await?;
+                }
+                // Keepalives and transaction markers; the commands inside a
+                // MULTI are applied one by one.
+                b"PING" | b"REPLCONF" | b"MULTI" | b"EXEC" => {}
+                b"FLUSHALL" => {
+                    self.store.flush(is_async_flush(&args)).await?;
+                }
+                b"FLUSHDB" if db == 0 => {
+                    self.store.flush(is_async_flush(&args)).await?;
+                }
+                _ if db == 0 => {
+                    if let RespValue::Error(e) = self.executor.apply_replicated(&args).await {
+                        warn!(
+                            command = %String::from_utf8_lossy(name),
+                            error = %e,
+                            "replicated command failed"
+                        );
+                    }
+                }
+                _ => {}
+            }
+            self.state
+                .offset
+                .fetch_add(reader.consumed - start, Ordering::Relaxed);
+        }
+    }
+}
+
+/// What INFO reports about the master this node follows.
+pub struct ReplicaStatus {
+    pub host: String,
+    pub port: u16,
+    pub link_up: bool,
+    pub offset: u64,
+    /// Seconds since the master last sent anything.
+    pub last_io_secs: u64,
+    /// Seconds the link has been down, while it is.
+    pub down_secs: Option<u64>,
+    /// `replica-priority`: Sentinel promotes lower values first and never 0.
+    pub priority: u32,
+}
+
+/// This node's side of replication, switched at runtime with `REPLICAOF`: a
+/// master (the default) or a replica following one master.
+pub struct ReplicationRole {
+    /// Replicas apply the stream through the executor that owns this role.
+    executor: Weak<CommandExecutor>,
+    store: Store,
+    listening_port: AtomicU16,
+    user: Option<String>,
+    password: Option<String>,
+    priority: u32,
+    following: std::sync::Mutex<Option<Following>>,
+    failover: std::sync::Mutex<FailoverState>,
+    failover_abort: AtomicBool,
+}
+
+/// What a `FAILOVER` was asked to do.
+pub struct FailoverTarget {
+    /// The replica to promote; the most caught-up one when unset.
+    pub to: Option<(String, u16)>,
+    /// How long to wait for the replica to catch up; forever when unset.
+    pub timeout: Option<Duration>,
+    /// Promote `to` when the timeout passes even if it has not caught up.
+    pub force: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailoverState {
+    None,
+    /// Writes are paused until the target acknowledges everything before them.
+    WaitingForSync,
+    /// The target is being promoted and this node demoted.
+    InProgress,
+}
+
+impl FailoverState {
+    /// As reported by `master_failover_state`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "no-failover",
+            Self::WaitingForSync => "waiting-for-sync",
+            Self::InProgress => "failover-in-progress",
+        }
+    }
+}
+
+struct Following {
+    master: ReplicaOf,
+    state: Arc<ReplicaState>,
+    task: JoinHandle<()>,
+}
+
+impl ReplicationRole {
+    pub fn new(
+        executor: Weak<CommandExecutor>,
+        store: Store,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        Self {
+            executor,
+            store,
+            listening_port: AtomicU16::new(6379),
+            user,
+            password,
+            priority: 100,
+            following: std::sync::Mutex::new(None),
+            failover: std::sync::Mutex::new(FailoverState::None),
+            failover_abort: AtomicBool::new(false),
+        }
+    }
+
+    /// `replica-priority`, reported to Sentinel.
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The port announced to masters, known once the listener is bound.
+    pub fn set_listening_port(&self, port: u16) {
+        self.listening_port.store(port, Ordering::Relaxed);
+    }
+
+    /// Starts following `host:port`, dropping the link to any previous master.
+    /// Returns false when that master is already being followed.
+    pub fn follow(&self, host: String, port: u16) -> bool {
+        let mut following = self.lock();
+        if following
+            .as_ref()
+            .is_some_and(|current| current.master.host == host && current.master.port == port)
+        {
+            return false;
+        }
+        let Some(executor) = self.executor.upgrade() else {
+            return false;
+        };
+        if let Some(previous) = following.take() {
+            previous.task.abort();
+        }
+        info!(master = %format!("{}:{}", host, port), "replicating from master");
+        let master = ReplicaOf {
+            host,
+            port,
+            user: self.user.clone(),
+            password: self.password.clone(),
+        };
+        let replica = Replica::new(
+            master.clone(),
+            executor,
+            self.store.clone(),
+            self.listening_port.load(Ordering::Relaxed),
+        );
+        *following = Some(Following {
+            master,
+            state: replica.state.clone(),
+            task: tokio::spawn(replica.run()),
+        });
+        true
+    }
+
+    /// `REPLICAOF NO ONE`: stops following and keeps the data as it is. Returns
+    /// false when this node was already a master.
+    pub fn promote(&self) -> bool {
+        let Some(previous) = self.lock().take() else {
+            return false;
+        };
+        previous.task.abort();
+        info!(
+            master = %format!("{}:{}", previous.master.host, previous.master.port),
+            "stopped replicating; now a master"
+        );
+        true
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// `None` while this node is a master.
+    pub fn status(&self) -> Option<ReplicaStatus> {
+        self.lock().as_ref().map(|following| ReplicaStatus {
+            host: following.master.host.clone(),
+            port: following.master.port,
+            link_up: following.state.link_up.load(Ordering::Relaxed),
+            offset: following.state.offset(),
+            last_io_secs: now_ms()
+                .saturating_sub(following.state.last_io_ms.load(Ordering::Relaxed))
+                / 1000,
+            down_secs: (!following.state.link_up.load(Ordering::Relaxed)).then(|| {
+                now_ms().saturating_sub(following.state.down_since_ms.load(Ordering::Relaxed))
+                    / 1000
+            }),
+            priority: self.priority,
+        })
+    }
+
+    pub fn failover_state(&self) -> FailoverState {
+        *self.failover.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Pauses client writes and hands the master role to a replica in the
+    /// background; errors are the reasons a failover cannot start.
+    pub fn start_failover(&self, target: FailoverTarget) -> Result<(), String> {
+        if self.lock().is_some() {
+            return Err("FAILOVER is not valid when server is a replica.".to_string());
+        }
+        let (Some(executor), Some(feed)) = (
+            self.executor.upgrade(),
+            self.store.replication_feed().cloned(),
+        ) else {
+            return Err("replication is not enabled".to_string());
+        };
+        let replicas = feed.replicas();
+        if replicas.is_empty() {
+            return Err("FAILOVER requires connected replicas.".to_string());
+        }
+        if let Some((host, port)) = &target.to
+            && !replicas
+                .iter()
+                .any(|replica| &replica.host == host && replica.port == *port)
+        {
+            return Err("FAILOVER target HOST and PORT is not a replica.".to_string());
+        }
+        {
+            let mut state = self.failover.lock().unwrap_or_else(|e| e.into_inner());
+            if *state != FailoverState::None {
+                return Err("FAILOVER already in progress.".to_string());
+            }
+            *state = FailoverState::WaitingForSync;
+        }
+        self.failover_abort.store(false, Ordering::Relaxed);
+        executor.pause_writes(true);
+        tokio::spawn(async move {
+            let role = executor
+                .replication_role()
+                .expect("failover runs on a node with a replication role");
+            if let Err(e) = role.run_failover(&feed, target).await {
+                warn!(error = %e, "failover abandoned; still the master");
+            }
+            *role.failover.lock().unwrap_or_else(|e| e.into_inner()) = FailoverState::None;
+            executor.pause_writes(false);
+        });
+        Ok(())
+    }
+
+    /// `FAILOVER ABORT`. Only a failover still waiting for its target can be
+    /// stopped; returns false when there is none.
+    pub fn abort_failover(&self) -> bool {
+        if self.failover_state() == FailoverState::None {
+            return false;
+        }
+        self.failover_abort.store(true, Ordering::Relaxed);
+        true
+    }
+
+    async fn run_failover(
+        &self,
+        feed: &ReplicationFeed,
+        target: FailoverTarget,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Writes are paused: everything up to here must reach the new master.
+        let synced_at = feed.offset();
+        feed.request_acks();
+        let deadline = target.timeout.map(|timeout| Instant::now() + timeout);
+        let chosen = loop {
+            if self.failover_abort.swap(false, Ordering::Relaxed) {
+                return Err("FAILOVER ABORT".into());
+            }
+            let mut replicas = feed.replicas().into_iter();
+            let candidate = match &target.to {
+                Some((host, port)) => {
+                    replicas.rfind(|replica| &replica.host == host && replica.port == *port)
+                }
+                None => replicas.max_by_key(|replica| replica.acked),
+            }
+            .ok_or("the target replica disconnected")?;
+            if candidate.acked >= synced_at {
+                break candidate;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                if target.force {
+                    break candidate;
+                }
+                return Err("timed out waiting for the target replica to catch up".into());
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        *self.failover.lock().unwrap_or_else(|e| e.into_inner()) = FailoverState::InProgress;
+        let addr = format!("{}:{}", chosen.host, chosen.port);
+        self.hand_over(&chosen.host, chosen.port)
+            .await
+            .map_err(|e| format!("promoting {}: {}", addr, e))?;
+        info!(new_master = %addr, offset = synced_at, "failover complete");
+        // A replica before writes resume, so held writes are refused rather
+        // than accepted by a node that is no longer the master.
+        self.follow(chosen.host, chosen.port);
+        Ok(())
+    }
+
+    /// Promotes the replica at `host:port` with `REPLICAOF NO ONE`, using the
+    /// same credentials this node would use to follow it.
+    async fn hand_over(&self, host: &str, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+        let stream = TcpStream::connect((host, port)).await?;
+        let (read, write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let writer = Mutex::new(write);
+        let master = ReplicaOf {
+            host: host.to_string(),
+            port,
+            user: self.user.clone(),
+            password: self.password.clone(),
+        };
+        authenticate(&mut reader, &writer, &master).await?;
+        command(&mut reader, &writer, &["REPLICAOF", "NO", "ONE"]).await?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Following>> {
+        self.following.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Acknowledges the processed offset every second, as Redis replicas do; the
+/// master uses it for `WAIT` and lag reporting.
+async fn send_acks(writer: Arc<Mutex<OwnedWriteHalf>>, state: Arc<ReplicaState>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        let offset = state.offset().to_string();
+        if write_command(&writer, &["REPLCONF", "ACK", &offset])
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn authenticate<R>(
+    reader: &mut R,
+    writer: &Mutex<OwnedWriteHalf>,
+    master: &ReplicaOf,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: AsyncBufRead + AsyncRead + Unpin,
+{
+    let Some(password) = &master.password else {
+        return Ok(());
+    };
+    let mut auth = vec!["AUTH"];
+    auth.extend(master.user.as_deref());
+    auth.push(password);
+    command(reader, writer, &auth)
+        .await
+        .map_err(|e| format!("{}:{} rejected AUTH: {}", master.host, master.port, e))?;
+    Ok(())
+}
+
+async fn write_command(
+    writer: &Mutex<OwnedWriteHalf>,
+    args: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let frame = RespValue::Array(
+        args.iter()
+            .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg.as_bytes()))))
+            .collect(),
+    );
+    writer.lock().await.write_all(&encode(frame)).await?;
+    Ok(())
+}
+
+/// Sends a handshake command and returns its status reply, or the error the
+/// master answered with.
+async fn command<R>(
+    reader: &mut R,
+    writer: &Mutex<OwnedWriteHalf>,
+    args: &[&str],
+) -> Result<String, Box<dyn std::error::Error>>
+where
+    R: AsyncBufRead + AsyncRead + Unpin,
+{
+    write_command(writer, args).await?;
+    let line = read_line(reader).await?;
+    match line.as_bytes().first() {
+        Some(b'+') => Ok(line[1..].to_string()),
+        Some(b'-') => Err(format!("{} failed: {}", args[0], &line[1..]).into()),
+        _ => Err(format!("unexpected reply to {}: '{}'", args[0], line).into()),
+    }
+}
+
+async fn read_line<R>(reader: &mut R) -> Result<String, Box<dyn std::error::Error>>
+where
+    R: AsyncBufRead + AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(4096)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.last() != Some(&b'\n') {
+        return Err("master closed the connection".into());
+    }
+    let line = String::from_utf8(line).map_err(|_| "master sent a non-UTF-8 reply")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Reads the `$<len>\r\n<rdb>` that follows FULLRESYNC. While the master is
+/// still producing the file it sends bare newlines to keep the link alive.
+async fn read_rdb_payload<R>(reader: &mut R) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+where
+    R: AsyncBufRead + AsyncRead + Unpin,
+{
+    let header = loop {
+        let line = read_line(reader).await?;
+        if !line.is_empty() {
+            break line;
+        }
+    };
+    let len: usize = header
+        .strip_prefix('$')
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| format!("unexpected full sync header '{}'", header))?;
+    if len > MAX_RDB_BYTES {
+        return Err(format!("full sync payload of {} bytes is too large", len).into());
+    }
+    let mut rdb = vec![0; len];
+    reader.read_exact(&mut rdb).await?;
+    Ok(rdb)
+}
+
+/// Counts the bytes consumed from a buffered reader: the replication offset
+/// advances by the size of every command applied.
+struct Counted<R> {
+    inner: R,
+    consumed: u64,
+}
+
+impl<R> Counted<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, consumed: 0 }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.consumed += (buf.filled().len() - before) as u64;
+        poll
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for Counted<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.consumed += amt as u64;
+        Pin::new(&mut this.inner).consume(amt);
+    }
+}
+
+/// Messages a replica link may fall behind by before it is dropped; the replica
+/// then reconnects and catches up from the backlog.
+const LINK_QUEUE: usize = 64 * 1024;
+
+/// The master half. Every write is propagated as the command Redis would send
+/// for it (the same records the AOF gets), numbered by byte offset and kept in a
+/// backlog so that a replica that briefly lost its link can resume.
+#[derive(Clone)]
+pub struct ReplicationFeed {
+    inner: Arc<FeedInner>,
+}
+
+/// A replica link's view of the write stream.
+type Link = broadcast::Receiver<Arc<[u8]>>;
+
+struct FeedInner {
+    /// Identifies this master's history; a new one is chosen on every start.
+    replid: String,
+    backlog_size: usize,
+    /// Off until the first replica syncs, so a standalone server pays nothing.
+    active: AtomicBool,
+    stream: std::sync::Mutex<Backlog>,
+    tx: broadcast::Sender<Arc<[u8]>>,
+    /// Replicas currently served by [`serve_replica`], by link.
+    links: std::sync::Mutex<BTreeMap<u64, LinkEntry>>,
+    next_link: AtomicU64,
+}
+
+struct LinkEntry {
+    host: String,
+    port: u16,
+    acked: Arc<Acked>,
+}
+
+/// A replica's latest `REPLCONF ACK`.
+#[derive(Default)]
+struct Acked {
+    offset: AtomicU64,
+    at_ms: AtomicU64,
+}
+
+impl Acked {
+    fn store(&self, offset: u64) {
+        self.offset.store(offset, Ordering::Relaxed);
+        self.at_ms.store(now_ms(), Ordering::Relaxed);
+    }
+}
+
+/// A replica this master is streaming to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectedReplica {
+    pub host: String,
+    /// The port it announced with `REPLCONF listening-port`.
+    pub port: u16,
+    /// The offset from its latest `REPLCONF ACK`.
+    pub acked: u64,
+    /// Seconds since that ACK.
+    pub lag: u64,
+}
+
+struct Backlog {
+    /// Bytes propagated since start: `master_repl_offset`.
+    offset: u64,
+    /// The last `backlog_size` of them.
+    bytes: VecDeque<u8>,
+}
+
+impl ReplicationFeed {
+    pub fn new(backlog_size: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            inner: Arc::new(FeedInner {
+                replid: generate_password(160)?,
+                backlog_size: backlog_size.max(1),
+                active: AtomicBool::new(false),
+                stream: std::sync::Mutex::new(Backlog {
+                    offset: 0,
+                    bytes: VecDeque::new(),
+                }),
+                tx: broadcast::channel(LINK_QUEUE).0,
+                links: std::sync::Mutex::new(BTreeMap::new()),
+                next_link: AtomicU64::new(1),
+            }),
+        })
+    }
+
+    pub fn replid(&self) -> &str {
+        &self.inner.replid
+    }
+
+    /// `master_repl_offset`: bytes propagated since start.
+    pub fn offset(&self) -> u64 {
+        self.lock().offset
+    }
+
+    /// Bytes held in the backlog, for `MEMORY STATS`.
+    pub fn backlog_len(&self) -> usize {
+        self.lock().bytes.len()
+    }
+
+    pub fn replicas(&self) -> Vec<ConnectedReplica> {
+        self.links()
+            .values()
+            .map(|link| ConnectedReplica {
+                host: link.host.clone(),
+                port: link.port,
+                acked: link.acked.offset.load(Ordering::Relaxed),
+                lag: now_ms().saturating_sub(link.acked.at_ms.load(Ordering::Relaxed)) / 1000,
+            })
+            .collect()
+    }
+
+    fn links(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, LinkEntry>> {
+        self.inner.links.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Asks replicas to acknowledge now rather than at their next one-second tick.
+    pub fn request_acks(&self) {
+        if self.inner.tx.receiver_count() > 0 {
+            self.append(&encode(RespValue::Array(
+                ["REPLCONF", "GETACK", "*"]
+                    .iter()
+                    .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg.as_bytes()))))
+                    .collect(),
+            )));
+        }
+    }
+
+    pub fn append_record(&self, record: &LogRecord) {
+        if self.inner.active.load(Ordering::SeqCst) {
+            self.append(&encode_resp_record(record.clone()));
+        }
+    }
+
+    /// A flush is not a log record: the AOF is rewritten instead.
+    pub fn flushall(&self) {
+        if self.inner.active.load(Ordering::SeqCst) {
+            self.append(&encode(RespValue::Array(vec![RespValue::Bulk(Some(
+                Bytes::from_static(b"FLUSHALL"),
+            ))])));
+        }
+    }
+
+    /// Sent every few seconds so replicas can tell an idle master from a dead link.
+    pub fn ping(&self) {
+        if self.inner.tx.receiver_count() > 0 {
+            self.append(&encode(RespValue::Array(vec![RespValue::Bulk(Some(
+                Bytes::from_static(b"PING"),
+            ))])));
+        }
+    }
+
+    fn append(&self, command: &[u8]) {
+        let mut stream = self.lock();
+        stream.offset += command.len() as u64;
+        stream.bytes.extend(command);
+        let excess = stream.bytes.len().saturating_sub(self.inner.backlog_size);
+        stream.bytes.drain(..excess);
+        // Sent under the lock so links see commands in offset order.
+        let _ = self.inner.tx.send(command.into());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Backlog> {
+        self.inner.stream.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts streaming at the current offset; everything before it has to come
+    /// from a full sync.
+    fn subscribe(&self) -> (u64, Link) {
+        self.inner.active.store(true, Ordering::SeqCst);
+        let stream = self.lock();
+        (stream.offset, self.inner.tx.subscribe())
+    }
+
+    /// For `PSYNC <replid> <offset>`: the backlog from `offset` on, when this is
+    /// the history the replica followed and the backlog still reaches back that far.
+    fn resume(&self, replid: &str, offset: i64) -> Option<(Vec<u8>, Link)> {
+        if replid != self.inner.replid || !self.inner.active.load(Ordering::SeqCst) {
+            return None;
+        }
+        let stream = self.lock();
+        let oldest = stream.offset - stream.bytes.len() as u64 + 1;
+        let offset = u64::try_from(offset).ok()?;
+        if offset < oldest || offset > stream.offset + 1 {
+            return None;
+        }
+        let missing = stream
+            .bytes
+            .range((offset - oldest) as usize..)
+            .copied()
+            .collect();
+        Some((missing, self.inner.tx.subscribe()))
+    }
+}
+
+/// A `PSYNC` a client sent; the connection is handed to [`serve_replica`].
+pub struct PsyncRequest {
+    pub replid: String,
+    pub offset: i64,
+    /// Where the replica can be reached, for INFO and FAILOVER.
+    pub host: String,
+    pub port: u16,
+    pub feed: ReplicationFeed,
+    pub store: Store,
+}
+
+/// Serves a replica on a connection that sent PSYNC: a partial resync from the
+/// backlog when possible, a full sync from an RDB image otherwise, then the
+/// write stream until either side goes away.
+pub async fn serve_replica<R, W>(
+    request: Box<PsyncRequest>,
+    reader: FrameReader<R>,
+    writer: W,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let PsyncRequest {
+        replid,
+        offset,
+        host,
+        port,
+        feed,
+        store,
+    } = *request;
+    let id = feed.inner.next_link.fetch_add(1, Ordering::Relaxed);
+    let acked = Arc::new(Acked::default());
+    feed.links().insert(
+        id,
+        LinkEntry {
+            host,
+            port,
+            acked: acked.clone(),
+        },
+    );
+    let result = stream_to_replica(&feed, &replid, offset, &store, acked, reader, writer).await;
+    feed.links().remove(&id);
+    result
+}
+
+async fn stream_to_replica<R, W>(
+    feed: &ReplicationFeed,
+    replid: &str,
+    offset: i64,
+    store: &Store,
+    acked: Arc<Acked>,
+    mut reader: FrameReader<R>,
+    mut writer: W,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let mut rx = match feed.resume(replid, offset) {
+        Some((missing, rx)) => {
+            writer
+                .write_all(format!("+CONTINUE {}\r\n", feed.replid()).as_bytes())
+                .await?;
+            writer.write_all(&missing).await?;
+            acked.store(offset.max(1) as u64 - 1);
+            rx
+        }
+        None => {
+            // Subscribing before freezing the keyspace means every write is in the
+            // image, the stream, or (harmlessly, as records are idempotent) both.
+            let (offset, rx) = feed.subscribe();
+            acked.store(offset);
+            writer
+                .write_all(format!("+FULLRESYNC {} {}\r\n", feed.replid(), offset).as_bytes())
+                .await?;
+            let rdb = store.rdb_image().await?;
+            writer
+                .write_all(format!("${}\r\n", rdb.len()).as_bytes())
+                .await?;
+            writer.write_all(&rdb).await?;
+            rx
+        }
+    };
+    writer.flush().await?;
+
+    // Replicas only ever send REPLCONF ACKs from here on; a closed read side
+    // means the replica is gone.
+    let mut acks = tokio::spawn(async move {
+        while let Ok(Some(frame)) = reader.read_frame().await {
+            if let Ok(args) = frame_to_args(frame)
+                && let [name, sub, offset] = args.as_slice()
+                && name.eq_ignore_ascii_case(b"REPLCONF")
+                && sub.eq_ignore_ascii_case(b"ACK")
+                && let Some(offset) = std::str::from_utf8(offset)
+                    .ok()
+                    .and_then(|offset| offset.parse().ok())
+            {
+                acked.store(offset);
+            }
+        }
+    });
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Ok(command) => writer.write_all(&command).await?,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    acks.abort();
+                    return Err("replica fell too far behind the write stream".into());
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = &mut acks => break,
+        }
+    }
+    acks.abort();
+    Ok(())
+}
+
+/// `FLUSHALL ASYNC` / `FLUSHDB ASYNC`; the master decides, not our own default.
+fn is_async_flush(args: &[Vec<u8>]) -> bool {
+    args.get(1)
+        .is_some_and(|mode| mode.eq_ignore_ascii_case(b"ASYNC"))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::auth::{Auth, SessionAuth};
+    use crate::command::SessionAction;
+    use crate::encoding::ValueType;
+    use crate::persistence::{Aof, AofFormat, AofFsync};
+    use crate::protocol::read_frame;
+    use crate::ratelimit::RateLimiter;
+    use crate::stats::ServerStats;
+    use crate::store::SetCondition;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    fn resp(args: &[&str]) -> Vec<u8> {
+        encode(RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg.as_bytes()))))
+                .collect(),
+        ))
+    }
+
+    fn executor(store: &Store) -> Arc<CommandExecutor> {
+        Arc::new(CommandExecutor::new(
+            Auth::new(HashMap::new(), "default".to_string(), None),
+            store.clone(),
+            Arc::new(ServerStats::new()),
+            "127.0.0.1:0".to_string(),
+            None,
+            RateLimiter::new(HashMap::new()),
+            AuditLog::open(None).expect("audit log"),
+        ))
+    }
+
+    async fn temp_store(name: &str) -> (Store, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "fedis-replication-{}-{}.aof",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let aof = Aof::open(&path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        (Store::new(aof, None).await.expect("new store"), path)
+    }
+
+    /// Serves `executor` the way the server does, handing PSYNC connections
+    /// over to the replication link.
+    async fn spawn_master(
+        executor: Arc<CommandExecutor>,
+    ) -> (std::net::SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let server = tokio::spawn(async move {
+            loop {
+                let (socket, peer) = listener.accept().await.expect("accept");
+                let executor = executor.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = socket.into_split();
+                    let mut read = FrameReader::new(read, STREAM_LIMITS);
+                    let mut session = SessionAuth {
+                        peer_addr: Some(peer.to_string()),
+                        ..SessionAuth::default()
+                    };
+                    loop {
+                        let Ok(Some(frame)) = read.read_frame().await else {
+                            return;
+                        };
+                        let args = frame_to_args(frame).expect("args");
+                        match executor.execute(args, &mut session).await {
+                            (_, SessionAction::Replicate(request)) => {
+                                let _ = serve_replica(request, read, write).await;
+                                return;
+                            }
+                            (resp, _) => write.write_all(&encode(resp)).await.expect("reply"),
+                        }
+                    }
+                });
+            }
+        });
+        (addr, server)
+    }
+
+    /// An executor that can switch roles, as the server builds it.
+    fn node(store: &Store) -> Arc<CommandExecutor> {
+        Arc::new_cyclic(|this| {
+            CommandExecutor::new(
+                Auth::new(HashMap::new(), "default".to_string(), None),
+                store.clone(),
+                Arc::new(ServerStats::new()),
+                "127.0.0.1:0".to_string(),
+                None,
+                RateLimiter::new(HashMap::new()),
+                AuditLog::open(None).expect("audit log"),
+            )
+            .with_replication_role(ReplicationRole::new(
+                this.clone(),
+                store.clone(),
+                None,
+                None,
+            ))
+        })
+    }
+
+    async fn run(executor: Arc<CommandExecutor>, args: Vec<String>) -> RespValue {
+        let args = args.into_iter().map(String::into_bytes).collect();
+        executor.execute(args, &mut SessionAuth::default()).await.0
+    }
+
+    async fn info(executor: Arc<CommandExecutor>) -> String {
+        match run(executor, vec!["INFO".into(), "replication".into()]).await {
+            RespValue::Bulk(Some(info)) => String::from_utf8(info.to_vec()).expect("utf8"),
+            other => panic!("unexpected INFO reply {:?}", other),
+        }
+    }
+
+    async fn eventually(executor: Arc<CommandExecutor>, line: &str) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !info(executor.clone()).await.lines().any(|l| l == line) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("INFO never reported {}", line));
+    }
+
+    #[test]
+    fn parses_master_addresses() {
+        assert_eq!(
+            parse_replica_of("redis.internal 6380").unwrap(),
+            ("redis.internal".to_string(), 6380)
+        );
+        assert_eq!(
+            parse_replica_of("10.0.0.5:6379").unwrap(),
+            ("10.0.0.5".to_string(), 6379)
+        );
+        assert!(parse_replica_of("10.0.0.5").is_err());
+    }
+
+    #[tokio::test]
+    async fn replica_loads_the_rdb_and_applies_the_stream() {
+        let (store, aof_path) = temp_store("redis-master").await;
+        let executor = executor(&store);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let master = listener.local_addr().expect("addr");
+        let replica = Replica::new(
+            ReplicaOf {
+                host: master.ip().to_string(),
+                port: master.port(),
+                user: None,
+                password: None,
+            },
+            executor,
+            store.clone(),
+            6380,
+        );
+        let state = replica.state.clone();
+        let task = tokio::spawn(replica.run());
+
+        let (socket, _) = listener.accept().await.expect("accept");
+        let (read, mut write) = socket.into_split();
+        let mut read = BufReader::new(read);
+        let mut handshake = Vec::new();
+        for reply in [
+            "+PONG\r\n",
+            "+OK\r\n",
+            "+OK\r\n",
+            "+FULLRESYNC abc123 100\r\n",
+        ] {
+            let frame = read_frame(&mut read).await.expect("read").expect("frame");
+            handshake.push(frame_to_args(frame).expect("args")[0].clone());
+            write.write_all(reply.as_bytes()).await.expect("reply");
+        }
+        assert_eq!(
+            handshake,
+            vec![
+                b"PING".to_vec(),
+                b"REPLCONF".to_vec(),
+                b"REPLCONF".to_vec(),
+                b"PSYNC".to_vec()
+            ]
+        );
+
+        let entries = [(b"a".as_slice(), ValueType::String, b"1".as_slice(), None)];
+        let rdb = crate::rdb::write_rdb_to(Vec::new(), entries.into_iter()).expect("rdb");
+        write.write_all(b"\n").await.expect("keepalive");
+        write
+            .write_all(format!("${}\r\n", rdb.len()).as_bytes())
+            .await
+            .expect("rdb header");
+        write.write_all(&rdb).await.expect("rdb");
+
+        let stream = [
+            resp(&["SELECT", "0"]),
+            resp(&["SET", "b", "2"]),
+            resp(&["SELECT", "1"]),
+            resp(&["SET", "c", "3"]),
+            resp(&["SELECT", "0"]),
+            resp(&["DEL", "a"]),
+            resp(&["PING"]),
+        ];
+        let applied: usize = stream.iter().map(Vec::len).sum();
+        for command in &stream {
+            write.write_all(command).await.expect("stream");
+        }
+        write
+            .write_all(&resp(&["REPLCONF", "GETACK", "*"]))
+            .await
+            .expect("getack");
+
+        let expected = resp(&["REPLCONF", "ACK", &(100 + applied).to_string()]);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let frame = read_frame(&mut read).await.expect("read").expect("frame");
+                if encode(frame) == expected {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("replica acknowledged the stream");
+
+        assert!(state.link_up.load(Ordering::Relaxed));
+        assert_eq!(store.get(b"a").await, Ok(None));
+        assert_eq!(store.get(b"b").await, Ok(Some(Bytes::from_static(b"2"))));
+        assert_eq!(store.get(b"c").await, Ok(None));
+
+        task.abort();
+        let _ = std::fs::remove_file(aof_path);
+    }
+
+    #[test]
+    fn backlog_resumes_only_within_its_window() {
+        let feed = ReplicationFeed::new(8).expect("feed");
+        assert!(feed.resume(feed.replid(), 1).is_none(), "no history yet");
+        let (start, _rx) = feed.subscribe();
+        assert_eq!(start, 0);
+        feed.append(b"0123456789");
+
+        let replid = feed.replid().to_string();
+        assert_eq!(feed.resume(&replid, 8).expect("in window").0, b"789");
+        assert_eq!(feed.resume(&replid, 11).expect("caught up").0, b"");
+        assert!(
+            feed.resume(&replid, 2).is_none(),
+            "trimmed from the backlog"
+        );
+        assert!(feed.resume(&replid, 12).is_none(), "ahead of the master");
+        assert!(feed.resume("other", 8).is_none(), "another history");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writes_to_a_key_stream_in_the_order_applied() {
+        const TASKS: i64 = 8;
+        const INCRS: i64 = 100;
+        let (store, path) = temp_store("ordering").await;
+        let store = store.with_replication_feed(Some(ReplicationFeed::new(1 << 20).expect("feed")));
+        let feed = store.replication_feed().expect("feed").clone();
+        let (_, _link) = feed.subscribe();
+
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..INCRS {
+                        assert!(store.incr_by(b"n", 1).await.is_ok());
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.expect("task");
+        }
+
+        // Each INCR streams as `SET n <value>`: 1, 2, 3... with none out of
+        // order, as the store applied them.
+        let (backlog, _) = feed.resume(feed.replid(), 1).expect("backlog");
+        let backlog = String::from_utf8(backlog).expect("utf8");
+        let parts: Vec<&str> = backlog.split("\r\n").collect();
+        let values: Vec<i64> = parts
+            .windows(5)
+            .filter(|window| window[0] == "SET" && window[2] == "n")
+            .map(|window| window[4].parse().expect("a number"))
+            .collect();
+        assert_eq!(values, (1..=TASKS * INCRS).collect::<Vec<_>>());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn fedis_replica_follows_a_fedis_master() {
+        let (master_store, master_path) = temp_store("master").await;
+        let master_store =
+            master_store.with_replication_feed(Some(ReplicationFeed::new(1024).expect("feed")));
+        let master_executor = executor(&master_store);
+        master_store
+            .set(b"a".to_vec(), b"1".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set");
+
+        let (master, server) = spawn_master(master_executor).await;
+
+        let (replica_store, replica_path) = temp_store("replica").await;
+        let replica = Replica::new(
+            ReplicaOf {
+                host: master.ip().to_string(),
+                port: master.port(),
+                user: None,
+                password: None,
+            },
+            executor(&replica_store),
+            replica_store.clone(),
+            6380,
+        );
+        let task = tokio::spawn(replica.run());
+
+        let wait_for = |key: &'static [u8], value: &'static [u8]| {
+            let store = replica_store.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while store.get(key).await.ok().flatten().as_deref() != Some(value) {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .expect("replica caught up");
+            }
+        };
+        wait_for(b"a", b"1").await;
+        master_store
+            .set(
+                b"b".to_vec(),
+                b"2".to_vec(),
+                Some(4_102_444_800_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set");
+        wait_for(b"b", b"2").await;
+        assert!(replica_store.ttl(b"b").await > 0);
+
+        task.abort();
+        server.abort();
+        let _ = std::fs::remove_file(master_path);
+        let _ = std::fs::remove_file(replica_path);
+    }
+
+    #[tokio::test]
+    async fn replicaof_switches_roles_at_runtime() {
+        let (master_store, master_path) = temp_store("role-master").await;
+        let master_store =
+            master_store.with_replication_feed(Some(ReplicationFeed::new(1024).expect("feed")));
+        master_store
+            .set(b"a".to_vec(), b"1".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set");
+        let master_executor = executor(&master_store);
+        let (master, server) = spawn_master(master_executor.clone()).await;
+
+        let (store, path) = temp_store("role-replica").await;
+        let node = node(&store);
+
+        assert!(
+            info(node.clone())
+                .await
+                .contains("role:master\nconnected_slaves:0")
+        );
+        let replicaof = vec![
+            "REPLICAOF".to_string(),
+            master.ip().to_string(),
+            master.port().to_string(),
+        ];
+        assert!(matches!(
+            run(node.clone(), replicaof.clone()).await,
+            RespValue::Simple(reply) if reply == "OK"
+        ));
+        eventually(node.clone(), "master_link_status:up").await;
+        eventually(master_executor.clone(), "connected_slaves:1").await;
+        let status = info(node.clone()).await;
+        assert!(status.contains("role:slave"));
+        assert!(status.contains(&format!("master_port:{}", master.port())));
+        assert!(status.contains("slave_priority:100"));
+        assert!(
+            info(master_executor.clone())
+                .await
+                .contains("slave0:ip=127.0.0.1,")
+        );
+        assert!(matches!(
+            run(node.clone(), vec!["ROLE".into()]).await,
+            RespValue::Array(role) if matches!(&role[0], RespValue::Bulk(Some(kind)) if &kind[..] == b"slave")
+        ));
+        assert_eq!(store.get(b"a").await, Ok(Some(Bytes::from_static(b"1"))));
+        assert!(matches!(
+            run(node.clone(), replicaof).await,
+            RespValue::Simple(reply) if reply == "OK Already connected to specified master"
+        ));
+        let set = vec!["SET".to_string(), "b".to_string(), "2".to_string()];
+        assert!(matches!(
+            run(node.clone(), set.clone()).await,
+            RespValue::Error(e) if e.starts_with("READONLY")
+        ));
+        assert!(matches!(
+            run(node.clone(), vec!["GET".into(), "a".into()]).await,
+            RespValue::Bulk(Some(value)) if &value[..] == b"1"
+        ));
+        let replica_read_only = |value: &str| {
+            vec![
+                "CONFIG".to_string(),
+                "SET".to_string(),
+                "replica-read-only".to_string(),
+                value.to_string(),
+            ]
+        };
+        let _ = run(node.clone(), replica_read_only("no")).await;
+        assert!(matches!(
+            run(node.clone(), set.clone()).await,
+            RespValue::Simple(reply) if reply == "OK"
+        ));
+        let _ = run(node.clone(), replica_read_only("yes")).await;
+
+        let promote = vec!["SLAVEOF".to_string(), "no".to_string(), "one".to_string()];
+        assert!(matches!(
+            run(node.clone(), promote).await,
+            RespValue::Simple(reply) if reply == "OK"
+        ));
+        assert!(info(node.clone()).await.contains("role:master"));
+        eventually(master_executor, "connected_slaves:0").await;
+        assert!(matches!(
+            run(node.clone(), set).await,
+            RespValue::Simple(reply) if reply == "OK"
+        ));
+        assert_eq!(store.get(b"a").await, Ok(Some(Bytes::from_static(b"1"))));
+
+        server.abort();
+        let _ = std::fs::remove_file(master_path);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn failover_hands_the_master_role_to_a_caught_up_replica() {
+        let (old_store, old_path) = temp_store("failover-old").await;
+        let old_store =
+            old_store.with_replication_feed(Some(ReplicationFeed::new(1024).expect("feed")));
+        let old = node(&old_store);
+        let (old_addr, old_server) = spawn_master(old.clone()).await;
+        let (new_store, new_path) = temp_store("failover-new").await;
+        let new_store =
+            new_store.with_replication_feed(Some(ReplicationFeed::new(1024).expect("feed")));
+        let new = node(&new_store);
+        let (new_addr, new_server) = spawn_master(new.clone()).await;
+        let role = new.replication_role().expect("role");
+        role.set_listening_port(new_addr.port());
+
+        let failover = |args: &[&str]| {
+            let old = old.clone();
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            async move { run(old, args).await }
+        };
+        assert!(matches!(
+            failover(&["FAILOVER"]).await,
+            RespValue::Error(e) if e == "ERR FAILOVER requires connected replicas."
+        ));
+
+        role.follow(old_addr.ip().to_string(), old_addr.port());
+        eventually(old.clone(), "connected_slaves:1").await;
+        let set = |key: &str| vec!["SET".to_string(), key.to_string(), "1".to_string()];
+        assert!(matches!(
+            run(old.clone(), set("a")).await,
+            RespValue::Simple(_)
+        ));
+        assert!(matches!(
+            failover(&["FAILOVER", "TO", "127.0.0.1", "1"]).await,
+            RespValue::Error(e) if e == "ERR FAILOVER target HOST and PORT is not a replica."
+        ));
+        assert!(matches!(
+            failover(&["FAILOVER", "FORCE"]).await,
+            RespValue::Error(e) if e.contains("requires both a timeout")
+        ));
+
+        let port = new_addr.port().to_string();
+        assert!(matches!(
+            failover(&["FAILOVER", "TO", "127.0.0.1", &port, "TIMEOUT", "5000"]).await,
+            RespValue::Simple(ok) if ok == "OK"
+        ));
+        // Held until the replica has taken over, then refused by the new replica.
+        assert!(matches!(
+            run(old.clone(), set("b")).await,
+            RespValue::Error(e) if e.starts_with("READONLY")
+        ));
+        assert!(info(new.clone()).await.contains("role:master"));
+        assert_eq!(
+            new_store.get(b"a").await,
+            Ok(Some(Bytes::from_static(b"1")))
+        );
+        eventually(old.clone(), &format!("master_port:{}", port)).await;
+        eventually(old.clone(), "master_link_status:up").await;
+
+        assert!(matches!(
+            run(new.clone(), set("c")).await,
+            RespValue::Simple(_)
+        ));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while old_store.get(b"c").await == Ok(None) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the old master follows the new one");
+        assert!(matches!(
+            failover(&["FAILOVER", "ABORT"]).await,
+            RespValue::Error(e) if e == "ERR No failover in progress."
+        ));
+
+        old.replication_role().expect("role").promote();
+        old_server.abort();
+        new_server.abort();
+        let _ = std::fs::remove_file(old_path);
+        let _ = std::fs::remove_file(new_path);
+    }
+}
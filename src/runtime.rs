@@ -0,0 +1,125 @@
+use tokio::runtime::{Builder, Runtime};
+
+/// Tokio's own cap on the blocking pool, kept as the default.
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuntimeFlavor {
+    /// A scheduler thread per worker, stealing work from each other.
+    MultiThread,
+    /// Everything on the main thread: the smallest footprint, for tiny
+    /// containers with a single CPU.
+    CurrentThread,
+}
+
+/// How the server's Tokio runtime is built, from `FEDIS_RUNTIME`,
+/// `FEDIS_WORKER_THREADS` and `FEDIS_MAX_BLOCKING_THREADS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// Scheduler threads; always 1 on the current-thread runtime.
+    pub worker_threads: usize,
+    /// Threads for `spawn_blocking` work such as snapshots and AOF rewrites.
+    pub max_blocking_threads: usize,
+}
+
+impl Default for RuntimeConfig {
+    /// What `#[tokio::main]` builds: one worker per available CPU.
+    fn default() -> Self {
+        Self {
+            flavor: RuntimeFlavor::MultiThread,
+            worker_threads: std::thread::available_parallelism().map_or(1, usize::from),
+            max_blocking_threads: DEFAULT_MAX_BLOCKING_THREADS,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    pub fn build(&self) -> std::io::Result<Runtime> {
+        let mut builder = match self.flavor {
+            RuntimeFlavor::MultiThread => {
+                let mut builder = Builder::new_multi_thread();
+                builder.worker_threads(self.worker_threads);
+                builder
+            }
+            RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+        };
+        builder
+            .max_blocking_threads(self.max_blocking_threads)
+            .thread_name("fedis-worker")
+            .enable_all()
+            .build()
+    }
+
+    pub fn flavor_name(&self) -> &'static str {
+        match self.flavor {
+            RuntimeFlavor::MultiThread => "multi_thread",
+            RuntimeFlavor::CurrentThread => "current_thread",
+        }
+    }
+}
+
+/// Builds the runtime settings from their raw values; unset ones keep Tokio's
+/// defaults.
+pub fn parse_runtime(
+    flavor: Option<&str>,
+    worker_threads: Option<&str>,
+    max_blocking_threads: Option<&str>,
+) -> Result<RuntimeConfig, Box<dyn std::error::Error>> {
+    let mut config = RuntimeConfig::default();
+    match flavor.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+        None | Some("multi_thread") | Some("multi-thread") => {}
+        Some("current_thread") | Some("current-thread") => {
+            config.flavor = RuntimeFlavor::CurrentThread;
+            config.worker_threads = 1;
+        }
+        _ => return Err("FEDIS_RUNTIME must be one of: multi_thread, current_thread".into()),
+    }
+    let count = |value: &str, name: &str| -> Result<usize, Box<dyn std::error::Error>> {
+        match value.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("{} must be a positive integer", name).into()),
+        }
+    };
+    if let Some(value) = worker_threads {
+        let workers = count(value, "FEDIS_WORKER_THREADS")?;
+        if config.flavor == RuntimeFlavor::MultiThread {
+            config.worker_threads = workers;
+        }
+    }
+    if let Some(value) = max_blocking_threads {
+        config.max_blocking_threads = count(value, "FEDIS_MAX_BLOCKING_THREADS")?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_runtime_settings() {
+        let config = parse_runtime(None, Some("3"), Some("16")).unwrap();
+        assert_eq!(config.flavor, RuntimeFlavor::MultiThread);
+        assert_eq!(
+            (config.worker_threads, config.max_blocking_threads),
+            (3, 16)
+        );
+
+        let config = parse_runtime(Some("current_thread"), Some("8"), None).unwrap();
+        assert_eq!(config.flavor, RuntimeFlavor::CurrentThread);
+        assert_eq!(config.worker_threads, 1);
+        assert_eq!(config.max_blocking_threads, DEFAULT_MAX_BLOCKING_THREADS);
+
+        assert!(parse_runtime(Some("green"), None, None).is_err());
+        assert!(parse_runtime(None, Some("0"), None).is_err());
+    }
+
+    #[test]
+    fn builds_a_current_thread_runtime() {
+        let config = parse_runtime(Some("current_thread"), None, Some("2")).unwrap();
+        let runtime = config.build().unwrap();
+        let flavor = runtime.block_on(async { tokio::runtime::Handle::current().runtime_flavor() });
+        assert_eq!(flavor, tokio::runtime::RuntimeFlavor::CurrentThread);
+    }
+}
@@ -0,0 +1,676 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use serde_json::Value as JsonValue;
+
+use crate::jsonpath::JsonPath;
+
+/// An `FT.CREATE` index: the JSON documents under `prefixes` (every key when
+/// there are none), filed by the fields of its schema.
+#[derive(Debug)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub prefixes: Vec<Vec<u8>>,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+pub struct Field {
+    /// What queries call it: the `AS` alias, else the path as written.
+    pub name: String,
+    pub path: JsonPath,
+    pub kind: FieldKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Whole strings, or the strings of a matched array, found exactly or by
+    /// prefix; folded to lower case unless `CASESENSITIVE`.
+    Tag {
+        case_sensitive: bool,
+    },
+    Numeric,
+}
+
+impl IndexDefinition {
+    /// `FT.CREATE`'s arguments after the index name:
+    /// `[ON JSON] [PREFIX n prefix ...] SCHEMA path [AS name] TAG [CASESENSITIVE]|NUMERIC ...`.
+    pub fn parse(name: &[u8], args: &[Vec<u8>]) -> Result<Self, String> {
+        let mut prefixes = Vec::new();
+        let mut idx = 0;
+        loop {
+            let Some(arg) = args.get(idx) else {
+                return Err(syntax_error());
+            };
+            match arg.to_ascii_uppercase().as_slice() {
+                b"ON" => match args
+                    .get(idx + 1)
+                    .map(|on| on.to_ascii_uppercase())
+                    .as_deref()
+                {
+                    Some(b"JSON") => idx += 2,
+                    Some(b"HASH") => {
+                        return Err("ERR only ON JSON indexes are supported".to_string());
+                    }
+                    _ => return Err(syntax_error()),
+                },
+                b"PREFIX" => {
+                    let count = args
+                        .get(idx + 1)
+                        .and_then(|count| std::str::from_utf8(count).ok()?.parse::<usize>().ok())
+                        .ok_or_else(syntax_error)?;
+                    let listed = args
+                        .get(idx + 2..idx + 2 + count)
+                        .ok_or_else(syntax_error)?;
+                    prefixes.extend(listed.iter().cloned());
+                    idx += 2 + count;
+                }
+                b"SCHEMA" => {
+                    idx += 1;
+                    break;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        let mut fields: Vec<Field> = Vec::new();
+        while idx < args.len() {
+            let path = JsonPath::parse(&args[idx]).map_err(|e| e.reply())?;
+            let mut field_name = String::from_utf8_lossy(&args[idx]).into_owned();
+            idx += 1;
+            if args
+                .get(idx)
+                .is_some_and(|arg| arg.eq_ignore_ascii_case(b"AS"))
+            {
+                let alias = args.get(idx + 1).ok_or_else(syntax_error)?;
+                field_name = String::from_utf8_lossy(alias).into_owned();
+                idx += 2;
+            }
+            let kind = match args
+                .get(idx)
+                .map(|kind| kind.to_ascii_uppercase())
+                .as_deref()
+            {
+                Some(b"TAG") => {
+                    idx += 1;
+                    let case_sensitive = args
+                        .get(idx)
+                        .is_some_and(|arg| arg.eq_ignore_ascii_case(b"CASESENSITIVE"));
+                    if case_sensitive {
+                        idx += 1;
+                    }
+                    FieldKind::Tag { case_sensitive }
+                }
+                Some(b"NUMERIC") => {
+                    idx += 1;
+                    FieldKind::Numeric
+                }
+                Some(b"TEXT") => {
+                    return Err(
+                        "ERR TEXT fields are not supported, index strings as TAG".to_string()
+                    );
+                }
+                _ => return Err(syntax_error()),
+            };
+            if fields.iter().any(|field| field.name == field_name) {
+                return Err(format!("ERR Duplicate field in schema - {}", field_name));
+            }
+            fields.push(Field {
+                name: field_name,
+                path,
+                kind,
+            });
+        }
+        if fields.is_empty() {
+            return Err("ERR Fields arguments are missing".to_string());
+        }
+        Ok(Self {
+            name: String::from_utf8_lossy(name).into_owned(),
+            prefixes,
+            fields,
+        })
+    }
+
+    pub fn covers(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+/// An `FT.SEARCH` query: `*` for every document, else space-separated
+/// `@tag:{a | b*}` and `@num:[min max]` terms that must all hold.
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    predicates: Vec<Predicate>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Predicate {
+    /// Index of the field in the schema.
+    field: usize,
+    condition: Condition,
+}
+
+#[derive(Debug, PartialEq)]
+enum Condition {
+    /// Any of the tags.
+    Tags(Vec<TagMatch>),
+    /// A range whose bounds `(` makes exclusive; `-inf` and `+inf` leave it open.
+    Range(Bound<f64>, Bound<f64>),
+}
+
+#[derive(Debug, PartialEq)]
+enum TagMatch {
+    Exact(String),
+    /// Written with a trailing `*`.
+    Prefix(String),
+}
+
+impl Query {
+    pub fn parse(definition: &IndexDefinition, text: &str) -> Result<Self, String> {
+        let mut predicates = Vec::new();
+        let mut rest = text.trim();
+        if rest == "*" {
+            rest = "";
+        }
+        while !rest.is_empty() {
+            let near = || format!("ERR Syntax error near `{}`", rest);
+            let (name, term) = rest
+                .strip_prefix('@')
+                .and_then(|term| term.split_once(':'))
+                .ok_or_else(near)?;
+            let field = definition
+                .fields
+                .iter()
+                .position(|field| field.name == name)
+                .ok_or_else(|| format!("ERR Unknown field `{}`", name))?;
+            let (condition, remaining) = match (definition.fields[field].kind, term.chars().next())
+            {
+                (FieldKind::Tag { case_sensitive }, Some('{')) => {
+                    let (body, remaining) = split_closing(&term[1..], '}').ok_or_else(near)?;
+                    let tags = split_unescaped(body, '|')
+                        .into_iter()
+                        .filter_map(|tag| parse_tag(tag, case_sensitive))
+                        .collect::<Vec<_>>();
+                    if tags.is_empty() {
+                        return Err(near());
+                    }
+                    (Condition::Tags(tags), remaining)
+                }
+                (FieldKind::Numeric, Some('[')) => {
+                    let (body, remaining) = split_closing(&term[1..], ']').ok_or_else(near)?;
+                    let bounds: Vec<&str> = body.split_whitespace().collect();
+                    let [min, max] = bounds[..] else {
+                        return Err(near());
+                    };
+                    let min = parse_bound(min).ok_or_else(near)?;
+                    let max = parse_bound(max).ok_or_else(near)?;
+                    (Condition::Range(min, max), remaining)
+                }
+                _ => return Err(near()),
+            };
+            predicates.push(Predicate { field, condition });
+            rest = remaining.trim_start();
+        }
+        Ok(Self { predicates })
+    }
+}
+
+fn syntax_error() -> String {
+    "ERR syntax error".to_string()
+}
+
+/// Where `separator` first appears in `text` without a `\` before it.
+fn find_unescaped(text: &str, separator: char) -> Option<usize> {
+    let mut escaped = false;
+    for (idx, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == separator => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// What comes before the closing `close` and what follows it.
+fn split_closing(text: &str, close: char) -> Option<(&str, &str)> {
+    let idx = find_unescaped(text, close)?;
+    Some((&text[..idx], &text[idx + close.len_utf8()..]))
+}
+
+fn split_unescaped(mut text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    while let Some(idx) = find_unescaped(text, separator) {
+        parts.push(&text[..idx]);
+        text = &text[idx + separator.len_utf8()..];
+    }
+    parts.push(text);
+    parts
+}
+
+/// One tag of a `{...}` term, unescaped; `None` when it is empty.
+fn parse_tag(raw: &str, case_sensitive: bool) -> Option<TagMatch> {
+    let mut tag = String::new();
+    let mut prefix = false;
+    let mut chars = raw.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => tag.extend(chars.next()),
+            '*' if chars.peek().is_none() => prefix = true,
+            c => tag.push(c),
+        }
+    }
+    if tag.is_empty() {
+        return None;
+    }
+    let tag = fold(&tag, case_sensitive);
+    Some(if prefix {
+        TagMatch::Prefix(tag)
+    } else {
+        TagMatch::Exact(tag)
+    })
+}
+
+fn parse_bound(raw: &str) -> Option<Bound<f64>> {
+    let (exclusive, number) = match raw.strip_prefix('(') {
+        Some(number) => (true, number),
+        None => (false, raw),
+    };
+    // `inf`, `+inf` and `-inf` parse as the infinities, which bound nothing.
+    let value = number.parse::<f64>().ok().filter(|value| !value.is_nan())?;
+    Some(if exclusive {
+        Bound::Excluded(value)
+    } else {
+        Bound::Included(value)
+    })
+}
+
+fn fold(tag: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        tag.to_string()
+    } else {
+        tag.to_lowercase()
+    }
+}
+
+/// A number with the total order `BTreeSet` needs; JSON has no NaN, and `-0`
+/// is filed as `0` so a range finds both.
+#[derive(Clone, Copy, Debug)]
+struct Number(f64);
+
+impl Number {
+    fn new(value: f64) -> Self {
+        Self(if value == 0.0 { 0.0 } else { value })
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A value a document is filed under.
+#[derive(Debug)]
+enum Filed {
+    Tag(String),
+    Number(Number),
+}
+
+enum FieldIndex {
+    Tags(BTreeMap<String, BTreeSet<Vec<u8>>>),
+    Numbers(BTreeSet<(Number, Vec<u8>)>),
+}
+
+/// One index's documents in one shard.
+pub struct ShardIndex {
+    definition: Arc<IndexDefinition>,
+    /// By field, in schema order.
+    fields: Vec<FieldIndex>,
+    /// Every document the index covers and the values it was filed under,
+    /// to unfile it when it changes or goes.
+    docs: HashMap<Vec<u8>, Vec<(usize, Filed)>>,
+}
+
+impl ShardIndex {
+    pub fn new(definition: Arc<IndexDefinition>) -> Self {
+        let fields = definition
+            .fields
+            .iter()
+            .map(|field| match field.kind {
+                FieldKind::Tag { .. } => FieldIndex::Tags(BTreeMap::new()),
+                FieldKind::Numeric => FieldIndex::Numbers(BTreeSet::new()),
+            })
+            .collect();
+        Self {
+            definition,
+            fields,
+            docs: HashMap::new(),
+        }
+    }
+
+    /// Files `key` under the values of `doc`, in place of what it was filed
+    /// under before; a string key (`None`) is only unfiled.
+    pub fn insert(&mut self, key: &[u8], doc: Option<&JsonValue>) {
+        self.remove(key);
+        let Some(doc) = doc.filter(|_| self.definition.covers(key)) else {
+            return;
+        };
+        let mut filed = Vec::new();
+        for (idx, field) in self.definition.fields.iter().enumerate() {
+            let values = field
+                .path
+                .select(doc)
+                .into_iter()
+                .flat_map(|value| match value {
+                    JsonValue::Array(items) => items.as_slice(),
+                    value => std::slice::from_ref(value),
+                });
+            for value in values {
+                match (field.kind, value, &mut self.fields[idx]) {
+                    (
+                        FieldKind::Tag { case_sensitive },
+                        JsonValue::String(tag),
+                        FieldIndex::Tags(tags),
+                    ) => {
+                        let tag = fold(tag, case_sensitive);
+                        tags.entry(tag.clone()).or_default().insert(key.to_vec());
+                        filed.push((idx, Filed::Tag(tag)));
+                    }
+                    (
+                        FieldKind::Numeric,
+                        JsonValue::Number(number),
+                        FieldIndex::Numbers(numbers),
+                    ) => {
+                        let Some(number) = number.as_f64().map(Number::new) else {
+                            continue;
+                        };
+                        numbers.insert((number, key.to_vec()));
+                        filed.push((idx, Filed::Number(number)));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.docs.insert(key.to_vec(), filed);
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        let Some(filed) = self.docs.remove(key) else {
+            return;
+        };
+        for (idx, value) in filed {
+            match (&mut self.fields[idx], value) {
+                (FieldIndex::Tags(tags), Filed::Tag(tag)) => {
+                    if let Some(keys) = tags.get_mut(&tag) {
+                        keys.remove(key);
+                        if keys.is_empty() {
+                            tags.remove(&tag);
+                        }
+                    }
+                }
+                (FieldIndex::Numbers(numbers), Filed::Number(number)) => {
+                    numbers.remove(&(number, key.to_vec()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The documents every predicate of `query` holds for.
+    fn matches(&self, query: &Query) -> Vec<&[u8]> {
+        let mut found: Option<BTreeSet<&[u8]>> = None;
+        for predicate in &query.predicates {
+            let keys = self.predicate_keys(predicate);
+            found = Some(match found {
+                None => keys,
+                Some(found) => found.intersection(&keys).copied().collect(),
+            });
+        }
+        match found {
+            Some(keys) => keys.into_iter().collect(),
+            None => self.docs.keys().map(Vec::as_slice).collect(),
+        }
+    }
+
+    fn predicate_keys(&self, predicate: &Predicate) -> BTreeSet<&[u8]> {
+        let mut keys = BTreeSet::new();
+        match (&predicate.condition, &self.fields[predicate.field]) {
+            (Condition::Tags(wanted), FieldIndex::Tags(tags)) => {
+                for tag in wanted {
+                    match tag {
+                        TagMatch::Exact(tag) => {
+                            keys.extend(tags.get(tag).into_iter().flatten().map(Vec::as_slice));
+                        }
+                        TagMatch::Prefix(prefix) => keys.extend(
+                            tags.range::<str, _>((
+                                Bound::Included(prefix.as_str()),
+                                Bound::Unbounded,
+                            ))
+                            .take_while(|(tag, _)| tag.starts_with(prefix.as_str()))
+                            .flat_map(|(_, found)| found.iter().map(Vec::as_slice)),
+                        ),
+                    }
+                }
+            }
+            (Condition::Range(min, max), FieldIndex::Numbers(numbers)) => {
+                let bound = |bound: &Bound<f64>, open: f64| match bound {
+                    Bound::Included(value) | Bound::Excluded(value) => *value,
+                    Bound::Unbounded => open,
+                };
+                let start = Number::new(bound(min, f64::NEG_INFINITY));
+                let end = bound(max, f64::INFINITY);
+                keys.extend(
+                    numbers
+                        .range((start, Vec::new())..)
+                        .take_while(|(number, _)| number.0 <= end)
+                        .filter(|(number, _)| (*min, *max).contains(&number.0))
+                        .map(|(_, key)| key.as_slice()),
+                );
+            }
+            _ => {}
+        }
+        keys
+    }
+}
+
+/// The search indexes of one shard, kept current by its writes.
+#[derive(Default)]
+pub struct ShardSearch {
+    indexes: Vec<ShardIndex>,
+}
+
+impl ShardSearch {
+    pub fn add(&mut self, index: ShardIndex) {
+        self.indexes.push(index);
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        let before = self.indexes.len();
+        self.indexes.retain(|index| index.definition.name != name);
+        self.indexes.len() != before
+    }
+
+    pub fn definition(&self, name: &str) -> Option<Arc<IndexDefinition>> {
+        self.indexes
+            .iter()
+            .find(|index| index.definition.name == name)
+            .map(|index| Arc::clone(&index.definition))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .map(|index| index.definition.name.clone())
+            .collect()
+    }
+
+    pub fn insert(&mut self, key: &[u8], doc: Option<&JsonValue>) {
+        for index in &mut self.indexes {
+            index.insert(key, doc);
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        for index in &mut self.indexes {
+            index.remove(key);
+        }
+    }
+
+    /// Empties every index but keeps the definitions; the old contents are
+    /// returned so they can be freed elsewhere.
+    pub fn take(&mut self) -> ShardSearch {
+        let empty = self
+            .indexes
+            .iter()
+            .map(|index| ShardIndex::new(Arc::clone(&index.definition)))
+            .collect();
+        ShardSearch {
+            indexes: std::mem::replace(&mut self.indexes, empty),
+        }
+    }
+
+    /// `None` when the shard has no index called `name`.
+    pub fn matches(&self, name: &str, query: &Query) -> Option<Vec<&[u8]>> {
+        self.indexes
+            .iter()
+            .find(|index| index.definition.name == name)
+            .map(|index| index.matches(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(args: &[&str]) -> Result<IndexDefinition, String> {
+        let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        IndexDefinition::parse(b"idx", &args)
+    }
+
+    fn items() -> IndexDefinition {
+        definition(&[
+            "ON", "JSON", "PREFIX", "1", "item:", "SCHEMA", "$.tags", "AS", "tag", "TAG",
+            "$.price", "AS", "price", "NUMERIC",
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn definitions_parse_prefixes_and_schema() {
+        let def = items();
+        assert_eq!(def.prefixes, vec![b"item:".to_vec()]);
+        assert_eq!(def.fields.len(), 2);
+        assert_eq!(
+            def.fields[0].kind,
+            FieldKind::Tag {
+                case_sensitive: false
+            }
+        );
+        assert!(def.covers(b"item:1") && !def.covers(b"user:1"));
+
+        assert!(definition(&["ON", "HASH", "SCHEMA", "a", "TAG"]).is_err());
+        assert!(definition(&["SCHEMA", "$.a", "TEXT"]).is_err());
+        assert!(definition(&["SCHEMA"]).is_err());
+        assert!(
+            definition(&[
+                "SCHEMA", "$.a", "AS", "a", "TAG", "$.b", "AS", "a", "NUMERIC"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn queries_parse_tags_and_ranges() {
+        let def = items();
+        assert_eq!(
+            Query::parse(&def, "*").unwrap(),
+            Query { predicates: vec![] }
+        );
+        let query = Query::parse(&def, "@tag:{Red | blu*} @price:[(10 +inf]").unwrap();
+        assert_eq!(
+            query.predicates,
+            vec![
+                Predicate {
+                    field: 0,
+                    condition: Condition::Tags(vec![
+                        TagMatch::Exact("red".to_string()),
+                        TagMatch::Prefix("blu".to_string()),
+                    ]),
+                },
+                Predicate {
+                    field: 1,
+                    condition: Condition::Range(
+                        Bound::Excluded(10.0),
+                        Bound::Included(f64::INFINITY)
+                    ),
+                },
+            ]
+        );
+        let escaped = Query::parse(&def, r"@tag:{a\ b\}}").unwrap();
+        assert_eq!(
+            escaped.predicates[0].condition,
+            Condition::Tags(vec![TagMatch::Exact("a b}".to_string())])
+        );
+        assert!(Query::parse(&def, "@nope:{a}").is_err());
+        assert!(Query::parse(&def, "@price:{a}").is_err());
+        assert!(Query::parse(&def, "@price:[1]").is_err());
+        assert!(Query::parse(&def, "hello").is_err());
+    }
+
+    fn found(index: &ShardIndex, text: &str) -> Vec<Vec<u8>> {
+        let mut keys = index.matches(&Query::parse(&index.definition, text).unwrap());
+        keys.sort();
+        keys.into_iter().map(<[u8]>::to_vec).collect()
+    }
+
+    #[test]
+    fn shard_index_files_and_unfiles_documents() {
+        let mut index = ShardIndex::new(Arc::new(items()));
+        let doc = |raw: &str| serde_json::from_str::<JsonValue>(raw).unwrap();
+        index.insert(b"item:1", Some(&doc(r#"{"tags":["Red","big"],"price":5}"#)));
+        index.insert(b"item:2", Some(&doc(r#"{"tags":"blue","price":15}"#)));
+        index.insert(b"item:3", Some(&doc(r#"{"price":-0.0}"#)));
+        index.insert(b"user:1", Some(&doc(r#"{"tags":"red"}"#)));
+
+        assert_eq!(found(&index, "*").len(), 3);
+        assert_eq!(found(&index, "@tag:{red}"), vec![b"item:1".to_vec()]);
+        assert_eq!(
+            found(&index, "@tag:{b*}"),
+            vec![b"item:1".to_vec(), b"item:2".to_vec()]
+        );
+        assert_eq!(found(&index, "@price:[0 0]"), vec![b"item:3".to_vec()]);
+        assert_eq!(found(&index, "@price:[(5 15]"), vec![b"item:2".to_vec()]);
+        assert_eq!(
+            found(&index, "@tag:{b*} @price:[-inf 10]"),
+            vec![b"item:1".to_vec()]
+        );
+
+        index.insert(b"item:1", Some(&doc(r#"{"tags":"green","price":50}"#)));
+        assert!(found(&index, "@tag:{red}").is_empty());
+        index.insert(b"item:2", None);
+        assert_eq!(
+            found(&index, "*"),
+            vec![b"item:1".to_vec(), b"item:3".to_vec()]
+        );
+        index.remove(b"item:1");
+        assert!(found(&index, "@price:[40 60]").is_empty());
+    }
+}
@@ -1,55 +1,273 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use tokio::io::{AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Semaphore;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpListener;
+use tokio::sync::{Semaphore, watch};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
 use tracing::{debug, info, warn};
 
+use crate::audit::AuditLog;
 use crate::auth::{Auth, SessionAuth};
+use crate::changefeed::{ChangeFeed, serve_follower};
+use crate::cluster::Cluster;
 use crate::command::{CommandExecutor, SessionAction};
 use crate::config::Config;
+use crate::grpc::serve_grpc;
+use crate::metrics::{MetricsSource, MetricsState, spawn_metrics_server};
+use crate::otel::{CommandSpan, Telemetry};
 use crate::persistence::Aof;
-use crate::protocol::{ReadLimits, RespValue, encode, frame_to_args, read_frame_with_limits};
+use crate::protocol::{FrameReader, ReadLimits, RespValue, encode, frame_to_args};
+use crate::pubsub::serve_subscriber;
+use crate::ratelimit::RateLimiter;
+use crate::replication::{ReplicationFeed, ReplicationRole, serve_replica};
+use crate::slowlog::SlowLog;
 use crate::stats::ServerStats;
+use crate::statsd::spawn_statsd;
 use crate::store::Store;
+use crate::tls::{TlsClientUser, build_acceptor, certificate_user_names};
+use crate::wasm::WasmRuntime;
+use crate::webhook::ExpiryWebhook;
+use crate::write_behind::WriteBehind;
+
+/// Longest `TRACEID` accepted; a W3C `traceparent` is 55 bytes.
+const MAX_TRACE_ID_BYTES: usize = 128;
+/// How long shutdown waits for the write-behind sink to catch up.
+const WRITE_BEHIND_DRAIN: Duration = Duration::from_secs(5);
 
 pub struct Server {
     config: Config,
     executor: Arc<CommandExecutor>,
     store: Store,
     stats: Arc<ServerStats>,
+    auth: Auth,
+    tls: Option<(TlsAcceptor, TlsClientUser)>,
     next_connection_id: Arc<AtomicU64>,
+    metrics: MetricsState,
 }
 
 impl Server {
     pub async fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
-        let aof = Aof::open(&config.aof_path, config.aof_fsync).await?;
-        let store = Store::new(aof, config.snapshot_path.clone()).await?;
-        let auth = Auth::new(config.users.clone(), config.default_user.clone());
+        let metrics = MetricsState::default();
+        if let Some(metrics_addr) = &config.metrics_addr {
+            let tls = config
+                .metrics_tls
+                .as_ref()
+                .map(build_acceptor)
+                .transpose()?;
+            spawn_metrics_server(
+                metrics_addr.clone(),
+                metrics.clone(),
+                config.metrics_access.clone(),
+                tls,
+            );
+        }
+        if let (Some(remote), Some(snapshot_path)) =
+            (&config.snapshot_remote, &config.snapshot_path)
+            && !snapshot_path.exists()
+            && !config.aof_path.exists()
+        {
+            // A fresh node: start from the newest off-box snapshot.
+            let (remote, dest) = (remote.clone(), snapshot_path.clone());
+            match tokio::task::spawn_blocking(move || remote.restore_latest(&dest)).await? {
+                Ok(Some(name)) => info!(
+                    target = %config.snapshot_remote.as_ref().map(|r| r.describe()).unwrap_or_default(),
+                    name,
+                    "restored snapshot from remote target"
+                ),
+                Ok(None) => info!("remote snapshot target is empty; starting empty"),
+                Err(e) => return Err(format!("restoring remote snapshot: {}", e).into()),
+            }
+        }
+        let aof = Aof::open(
+            &config.aof_path,
+            config.aof_fsync,
+            config.encryption.clone(),
+            config.aof_format,
+        )
+        .await?
+        .with_load_truncated(config.aof_load_truncated)
+        .with_timestamps(config.aof_timestamps)
+        .with_recover_to(config.recover_to_ts)
+        .with_compression(config.aof_compression, config.aof_compression_min_bytes);
+        let store = Store::open(
+            aof,
+            config.snapshot_path.clone(),
+            config.storage_engine.clone(),
+        )
+        .await?
+        .with_rdb_export_path(config.rdb_export_path.clone())
+        .with_export_dir(Some(config.export_dir.clone()))
+        .with_snapshot_compression(config.snapshot_compression)
+        .with_remote_snapshots(config.snapshot_remote.clone())
+        .with_lazyfree_threshold(config.lazyfree_threshold_bytes)
+        .with_replication_feed(Some(ReplicationFeed::new(config.repl_backlog_bytes)?))
+        .with_write_behind(config.write_behind.as_ref().map(WriteBehind::start))
+        .with_change_feed(config.non_redis_mode.then(ChangeFeed::new).transpose()?)
+        .with_expiry_webhook(
+            config
+                .expiry_webhook
+                .as_ref()
+                .filter(|_| config.non_redis_mode)
+                .map(ExpiryWebhook::start)
+                .transpose()?,
+        )
+        .with_soft_delete(
+            config
+                .soft_delete_retention
+                .filter(|_| config.non_redis_mode),
+        );
+        if let Some(path) = &config.rdb_import_path {
+            if store.dbsize().await == 0 {
+                let (imported, skipped) = store.import_rdb(path).await?;
+                info!(path = %path.display(), imported, skipped, "imported RDB dump");
+                if skipped > 0 {
+                    warn!(
+                        skipped,
+                        "RDB keys of unsupported types or non-zero databases were not imported"
+                    );
+                }
+            } else {
+                info!(path = %path.display(), "store already has data; skipping RDB import");
+            }
+        }
+        let auth = Auth::new(
+            config.users.clone(),
+            config.default_user.clone(),
+            config.acl_file.clone(),
+        )
+        .with_lockout(config.auth_lockout)
+        .with_kill_deleted_sessions(config.kill_deleted_user_sessions)
+        .with_token_verifier(config.jwt.clone().filter(|_| config.non_redis_mode));
         let stats = Arc::new(ServerStats::new());
-        let executor = Arc::new(CommandExecutor::new(
-            auth,
-            store.clone(),
-            stats.clone(),
-            config.listen_addr.clone(),
-            config.max_memory_bytes,
-        ));
+        let telemetry = config
+            .otel
+            .as_ref()
+            .map(|otel| Telemetry::start(otel, stats.clone(), store.clone()))
+            .transpose()?;
+        if let Some(statsd) = config.statsd.clone() {
+            spawn_statsd(statsd, stats.clone(), store.clone());
+        }
+        let audit = AuditLog::open(config.audit_log_path.as_deref())?;
+        let cluster = config
+            .cluster_announce
+            .clone()
+            .map(|myself| Cluster::new(myself, &config.cluster_nodes))
+            .transpose()?;
+        let wasm = WasmRuntime::new(config.wasm.clone())?;
+        wasm.load_saved()?;
+        let executor = Arc::new_cyclic(|executor| {
+            let executor = CommandExecutor::new(
+                auth.clone(),
+                store.clone(),
+                stats.clone(),
+                config.listen_addr.clone(),
+                config.max_memory_bytes,
+                RateLimiter::new(config.user_rate_limits.clone()),
+                audit,
+            )
+            .with_runtime(config.runtime)
+            .with_telemetry(telemetry)
+            .with_non_redis_mode(config.non_redis_mode)
+            .with_advertised_modules(config.advertise_modules)
+            .with_wasm(wasm)
+            .with_upstream(config.upstream.clone())
+            .with_slowlog(SlowLog::new(
+                config.slowlog_log_slower_than,
+                config.slowlog_max_len,
+            ))
+            .with_replication_role(
+                ReplicationRole::new(
+                    executor.clone(),
+                    store.clone(),
+                    config.master_user.clone(),
+                    config.master_auth.clone(),
+                )
+                .with_priority(config.replica_priority),
+            );
+            match cluster {
+                Some(cluster) => executor.with_cluster(cluster),
+                None => executor,
+            }
+        });
+        executor.set_read_only(config.read_only);
+        executor.set_replica_read_only(config.replica_read_only);
+        let tls = match &config.tls {
+            Some(settings) => Some((build_acceptor(settings)?, settings.client_user)),
+            None => None,
+        };
         Ok(Self {
             config,
             executor,
             store,
             stats,
+            auth,
+            tls,
             next_connection_id: Arc::new(AtomicU64::new(1)),
+            metrics,
         })
     }
 
-    pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Runs until Ctrl-C, then flushes the AOF.
+    pub async fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut handle = self.start().await?;
+        tokio::select! {
+            signal = tokio::signal::ctrl_c() => {
+                signal?;
+                info!("shutdown signal received");
+                handle.shutdown().await
+            }
+            // Only ends on its own when accepting fails.
+            accepted = &mut handle.accept => {
+                handle.finish().await;
+                accepted?.map_err(Into::into)
+            }
+        }
+    }
+
+    /// The executor commands run through; see `ServerHandle::executor`.
+    pub fn executor(&self) -> Arc<CommandExecutor> {
+        self.executor.clone()
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Binds the listener and serves clients on background tasks until the
+    /// returned handle is shut down.
+    pub async fn start(self) -> Result<ServerHandle, Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(&self.config.listen_addr).await?;
+        let local_addr = listener.local_addr()?;
+        let grpc = match &self.config.grpc_addr {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+        Ok(self.launch(Some((listener, local_addr)), grpc))
+    }
+
+    /// Like `start`, without binding a listener: clients connect through
+    /// `ServerHandle::connect` only, and no gRPC listener either.
+    pub fn start_in_process(self) -> ServerHandle {
+        self.launch(None, None)
+    }
+
+    fn launch(
+        self,
+        listener: Option<(TcpListener, SocketAddr)>,
+        grpc: Option<TcpListener>,
+    ) -> ServerHandle {
+        let local_addr = listener.as_ref().map(|(_, addr)| *addr);
+        let grpc_addr = grpc.as_ref().and_then(|grpc| grpc.local_addr().ok());
         info!(
-            listen_addr = %listener.local_addr()?,
+            listen_addr =
+                local_addr.map_or_else(|| "in-process".to_string(), |addr| addr.to_string()),
+            tls = self.tls.is_some(),
+            ip_filter = !self.config.ip_filter.is_empty(),
             non_redis_mode = self.config.non_redis_mode,
             debug_response_ids = self.config.debug_response_ids,
             "server started"
@@ -61,57 +279,151 @@ impl Server {
             );
         }
 
-        if let Some(metrics_addr) = &self.config.metrics_addr {
-            let stats = self.stats.clone();
-            let store = self.store.clone();
-            let addr = metrics_addr.clone();
-            tokio::spawn(async move {
-                if let Err(e) = run_metrics_server(addr, stats, store).await {
-                    warn!(error = %e, "metrics server failed");
+        if self.config.jwt.is_some() && !self.config.non_redis_mode {
+            warn!("FEDIS_JWT_* is set but FEDIS_NON_REDIS_MODE is off; token auth is disabled");
+        }
+
+        if self.config.expiry_webhook.is_some() && !self.config.non_redis_mode {
+            warn!(
+                "FEDIS_EXPIRY_WEBHOOK_URL is set but FEDIS_NON_REDIS_MODE is off; the webhook is disabled"
+            );
+        }
+
+        if self.config.soft_delete_retention.is_some() && !self.config.non_redis_mode {
+            warn!(
+                "FEDIS_SOFT_DELETE_RETENTION_SEC is set but FEDIS_NON_REDIS_MODE is off; soft delete is disabled"
+            );
+        }
+
+        if let Some(role) = self.executor.replication_role() {
+            if let Some(addr) = local_addr {
+                role.set_listening_port(addr.port());
+            }
+            if let Some((host, port)) = &self.config.replica_of {
+                role.follow(host.clone(), *port);
+            }
+        }
+
+        // The store has replayed its log and clients can connect: ready.
+        let _ = self.metrics.set(MetricsSource {
+            executor: self.executor.clone(),
+            stats: self.stats.clone(),
+            store: self.store.clone(),
+            auth: self.auth.clone(),
+            prefix: self.config.metrics_prefix.clone(),
+        });
+        let mut tasks = Vec::new();
+        if let Some(feed) = self.store.replication_feed() {
+            let feed = feed.clone();
+            tasks.push(tokio::spawn(async move {
+                // Redis' `repl-ping-replica-period`.
+                let mut ticker = tokio::time::interval(Duration::from_secs(10));
+                loop {
+                    ticker.tick().await;
+                    feed.ping();
                 }
-            });
+            }));
         }
-        let cleanup_store = self.store.clone();
-        tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(Duration::from_millis(500));
+
+        // Ten active expiration cycles a second, each allowed a quarter of its
+        // slot, like Redis' default `hz 10`.
+        let expire_store = self.store.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_millis(100));
             loop {
                 ticker.tick().await;
-                cleanup_store.cleanup_expired().await;
+                expire_store.expire_cycle(Duration::from_millis(25)).await;
             }
-        });
+        }));
 
         if let Some(interval_sec) = self.config.snapshot_interval_sec {
             let save_store = self.store.clone();
-            tokio::spawn(async move {
+            tasks.push(tokio::spawn(async move {
                 let mut ticker = tokio::time::interval(Duration::from_secs(interval_sec.max(1)));
                 loop {
                     ticker.tick().await;
-                    let _ = save_store.bgsave().await;
+                    if save_store.changes_since_last_save() > 0 {
+                        let _ = save_store.bgsave().await;
+                    }
                 }
-            });
+            }));
+        }
+
+        if !self.config.save_rules.is_empty() {
+            let save_store = self.store.clone();
+            let rules = self.config.save_rules.clone();
+            let started_epoch_sec = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            tasks.push(tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    ticker.tick().await;
+                    if save_store.save_due(&rules, started_epoch_sec) {
+                        let _ = save_store.bgsave().await;
+                    }
+                }
+            }));
         }
 
         let stats = self.stats.clone();
-        tokio::spawn(async move {
+        tasks.push(tokio::spawn(async move {
             let mut ticker = tokio::time::interval(Duration::from_secs(1));
             loop {
                 ticker.tick().await;
                 stats.tick_ops_per_sec();
             }
-        });
+        }));
 
-        let mut shutdown = std::pin::pin!(tokio::signal::ctrl_c());
+        if let Some(grpc) = grpc {
+            tasks.push(tokio::spawn(serve_grpc(grpc, self.executor.clone())));
+        }
+
+        let (stop, stop_rx) = watch::channel(false);
+        let executor = self.executor.clone();
+        let store = self.store.clone();
+        let stats = self.stats.clone();
+        let next_connection_id = self.next_connection_id.clone();
+        let client = ClientSettings::new(&self.config);
+        let accept =
+            tokio::spawn(self.accept_loop(listener.map(|(listener, _)| listener), stop_rx));
+        ServerHandle {
+            local_addr,
+            grpc_addr,
+            executor,
+            store,
+            stats,
+            next_connection_id,
+            client,
+            stop,
+            accept,
+            tasks,
+        }
+    }
+
+    async fn accept_loop(
+        self,
+        listener: Option<TcpListener>,
+        mut stop: watch::Receiver<bool>,
+    ) -> Result<(), String> {
+        let Some(listener) = listener else {
+            let _ = stop.wait_for(|stopped| *stopped).await;
+            return Ok(());
+        };
         let limit = Arc::new(Semaphore::new(self.config.max_connections.max(1)));
         loop {
             let accept_result = tokio::select! {
-                _ = &mut shutdown => {
-                    info!("shutdown signal received");
-                    break;
-                }
+                _ = stop.wait_for(|stopped| *stopped) => break,
                 accepted = listener.accept() => accepted,
             };
 
-            let (socket, peer_addr) = accept_result?;
+            let (socket, peer_addr) = accept_result.map_err(|e| e.to_string())?;
+            if !self.config.ip_filter.permits(peer_addr.ip()) {
+                self.stats.on_ip_rejected();
+                debug!(peer = %peer_addr, "connection rejected by IP filter");
+                continue;
+            }
             let Ok(permit) = limit.clone().try_acquire_owned() else {
                 warn!(peer = %peer_addr, "connection rejected: max connections reached");
                 let mut socket = socket;
@@ -123,181 +435,220 @@ impl Server {
             let executor = self.executor.clone();
             let stats = self.stats.clone();
             let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
-            let with_response_ids = self.config.non_redis_mode && self.config.debug_response_ids;
-            let max_request_bytes = self.config.max_request_bytes;
-            let idle_timeout = Duration::from_secs(self.config.idle_timeout_sec.max(1));
+            let client = ClientSettings::new(&self.config);
+            let tls = self.tls.clone();
             stats.on_connect();
+            let connected_at = Instant::now();
             info!(connection_id, peer = %peer_addr, "client connected");
             tokio::spawn(async move {
-                if let Err(e) = handle_client(
-                    socket,
-                    executor,
+                let mut session = SessionAuth {
                     connection_id,
-                    peer_addr,
-                    with_response_ids,
-                    max_request_bytes,
-                    idle_timeout,
-                )
-                .await
-                {
+                    peer_addr: Some(peer_addr.to_string()),
+                    ..SessionAuth::default()
+                };
+                let result = match tls {
+                    Some((acceptor, client_user)) => {
+                        match tokio::time::timeout(client.idle_timeout, acceptor.accept(socket))
+                            .await
+                        {
+                            Ok(Ok(stream)) => {
+                                let names = stream
+                                    .get_ref()
+                                    .1
+                                    .peer_certificates()
+                                    .and_then(|certs| certs.first())
+                                    .map(|cert| certificate_user_names(cert, client_user))
+                                    .unwrap_or_default();
+                                if !names.is_empty()
+                                    && !executor.authenticate_certificate(&names, &mut session)
+                                {
+                                    warn!(connection_id, peer = %peer_addr, names = ?names, "client certificate does not map to an enabled user");
+                                }
+                                handle_client(stream, executor, session, client).await
+                            }
+                            Ok(Err(e)) => Err(format!("TLS handshake failed: {}", e).into()),
+                            Err(_) => Err("TLS handshake timed out".into()),
+                        }
+                    }
+                    None => handle_client(socket, executor, session, client).await,
+                };
+                if let Err(e) = result {
                     warn!(connection_id, peer = %peer_addr, error = %e, "client loop failed");
                 }
-                stats.on_disconnect();
+                stats.on_disconnect(connected_at.elapsed());
                 drop(permit);
                 info!(connection_id, peer = %peer_addr, "client disconnected");
             });
         }
 
-        info!("server stopped");
         Ok(())
     }
 }
 
-async fn run_metrics_server(
-    metrics_addr: String,
-    stats: Arc<ServerStats>,
+/// A started server. Commands can run in-process through `executor`, with
+/// a `SessionAuth` per logical client, or over `connect`'s streams, as well
+/// as over the listener.
+pub struct ServerHandle {
+    local_addr: Option<SocketAddr>,
+    grpc_addr: Option<SocketAddr>,
+    executor: Arc<CommandExecutor>,
     store: Store,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind(&metrics_addr).await?;
-    info!(metrics_addr = %listener.local_addr()?, "metrics server started");
+    stats: Arc<ServerStats>,
+    next_connection_id: Arc<AtomicU64>,
+    client: ClientSettings,
+    stop: watch::Sender<bool>,
+    accept: JoinHandle<Result<(), String>>,
+    /// Expiry, snapshot and statistics ticks and the gRPC listener, stopped
+    /// with the server.
+    tasks: Vec<JoinHandle<()>>,
+}
 
-    loop {
-        let (mut socket, _) = listener.accept().await?;
-        let metrics = format_metrics(&stats, &store).await;
-        let body = metrics.into_bytes();
-        let header = format!(
-            "HTTP/1.1 200 OK\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
-            body.len()
-        );
-        socket.write_all(header.as_bytes()).await?;
-        socket.write_all(&body).await?;
-        socket.flush().await?;
+impl ServerHandle {
+    /// The address the listener bound, with the port picked for port 0;
+    /// `None` for a server started in-process.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The gRPC listener's address, when `FEDIS_GRPC_ADDR` is set.
+    pub fn grpc_addr(&self) -> Option<SocketAddr> {
+        self.grpc_addr
+    }
+
+    /// A new client connection over an in-memory stream: the other end is
+    /// served like a TCP client, RESP in and out, pipelining, `TRACEID` and
+    /// all. It does not count towards `FEDIS_MAX_CONNECTIONS`.
+    pub fn connect(&self) -> DuplexStream {
+        let (stream, server_end) = tokio::io::duplex(64 * 1024);
+        let executor = self.executor.clone();
+        let stats = self.stats.clone();
+        let client = self.client;
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        stats.on_connect();
+        let connected_at = Instant::now();
+        info!(connection_id, peer = "in-process", "client connected");
+        tokio::spawn(async move {
+            let session = SessionAuth {
+                connection_id,
+                ..SessionAuth::default()
+            };
+            if let Err(e) = handle_client(server_end, executor, session, client).await {
+                warn!(connection_id, peer = "in-process", error = %e, "client loop failed");
+            }
+            stats.on_disconnect(connected_at.elapsed());
+            info!(connection_id, peer = "in-process", "client disconnected");
+        });
+        stream
+    }
+
+    pub fn executor(&self) -> Arc<CommandExecutor> {
+        self.executor.clone()
     }
-}
 
-async fn format_metrics(stats: &ServerStats, store: &Store) -> String {
-    let store_metrics = store.metrics().await;
-    let persistence = store.persistence_metrics();
-    let command_stats = stats.command_stats_snapshot();
-
-    let mut out = String::new();
-    out.push_str(&format!(
-        "fedis_connected_clients {}\n",
-        stats.connected_clients()
-    ));
-    out.push_str(&format!(
-        "fedis_total_connections {}\n",
-        stats.total_connections()
-    ));
-    out.push_str(&format!(
-        "fedis_total_commands {}\n",
-        stats.total_commands()
-    ));
-    out.push_str(&format!(
-        "fedis_instantaneous_ops_per_sec {}\n",
-        stats.instantaneous_ops_per_sec()
-    ));
-    out.push_str(&format!(
-        "fedis_total_command_usec {}\n",
-        stats.total_command_usec()
-    ));
-    out.push_str(&format!("fedis_keys {}\n", store_metrics.keys));
-    out.push_str(&format!(
-        "fedis_expiring_keys {}\n",
-        store_metrics.expiring_keys
-    ));
-    out.push_str(&format!(
-        "fedis_memory_bytes {}\n",
-        store_metrics.approx_memory_bytes
-    ));
-    out.push_str(&format!(
-        "fedis_aof_rewrite_in_progress {}\n",
-        if persistence.rewrite_in_progress {
-            1
-        } else {
-            0
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Stops accepting clients and the background ticks, then flushes the
+    /// AOF. Connections already open are served until they close.
+    pub async fn shutdown(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.stop.send_replace(true);
+        let accepted = (&mut self.accept).await?;
+        self.finish().await;
+        accepted.map_err(Into::into)
+    }
+
+    async fn finish(self) {
+        for task in &self.tasks {
+            task.abort();
         }
-    ));
-    out.push_str(&format!(
-        "fedis_aof_rewrites {}\n",
-        persistence.rewrite_count
-    ));
-    out.push_str(&format!(
-        "fedis_aof_rewrite_failures {}\n",
-        persistence.rewrite_fail_count
-    ));
-    out.push_str(&format!(
-        "fedis_snapshot_in_progress {}\n",
-        if persistence.snapshot_in_progress {
-            1
-        } else {
-            0
+        let pending = self.store.drain_write_behind(WRITE_BEHIND_DRAIN).await;
+        if pending > 0 {
+            warn!(pending, "write-behind records not sent before shutdown");
         }
-    ));
-    out.push_str(&format!(
-        "fedis_snapshot_saves {}\n",
-        persistence.snapshot_count
-    ));
-    out.push_str(&format!(
-        "fedis_snapshot_failures {}\n",
-        persistence.snapshot_fail_count
-    ));
-    out.push_str(&format!(
-        "fedis_snapshot_last_save_epoch_sec {}\n",
-        persistence.last_snapshot_epoch_sec
-    ));
-
-    for (name, calls, usec) in command_stats {
-        out.push_str(&format!(
-            "fedis_command_calls{{command=\"{}\"}} {}\n",
-            name, calls
-        ));
-        out.push_str(&format!(
-            "fedis_command_usec{{command=\"{}\"}} {}\n",
-            name, usec
-        ));
+        self.executor.audit_log().drain().await;
+        match self.store.sync_aof().await {
+            Ok(()) => info!("AOF flushed"),
+            Err(e) => warn!(error = %e, "failed to flush AOF on shutdown"),
+        }
+        info!("server stopped");
     }
-    out
 }
 
-async fn handle_client(
-    socket: TcpStream,
-    executor: Arc<CommandExecutor>,
-    connection_id: u64,
-    peer_addr: std::net::SocketAddr,
+/// The configuration every client connection is served with.
+#[derive(Clone, Copy)]
+struct ClientSettings {
+    non_redis_mode: bool,
     with_response_ids: bool,
     max_request_bytes: usize,
     idle_timeout: Duration,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let (reader_half, writer_half) = socket.into_split();
-    let mut reader = BufReader::new(reader_half);
+}
+
+impl ClientSettings {
+    fn new(config: &Config) -> Self {
+        Self {
+            non_redis_mode: config.non_redis_mode,
+            with_response_ids: config.non_redis_mode && config.debug_response_ids,
+            max_request_bytes: config.max_request_bytes,
+            idle_timeout: Duration::from_secs(config.idle_timeout_sec.max(1)),
+        }
+    }
+}
+
+async fn handle_client<S>(
+    socket: S,
+    executor: Arc<CommandExecutor>,
+    mut session: SessionAuth,
+    settings: ClientSettings,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let ClientSettings {
+        non_redis_mode,
+        with_response_ids,
+        max_request_bytes,
+        idle_timeout,
+    } = settings;
+    let connection_id = session.connection_id;
+    let peer_addr = session.peer_addr.clone().unwrap_or_default();
+    let (reader_half, writer_half) = tokio::io::split(socket);
+    let mut reader = FrameReader::new(
+        reader_half,
+        ReadLimits {
+            max_bulk_bytes: max_request_bytes,
+            max_array_len: 4096,
+            max_line_bytes: 4096,
+        },
+    );
     let mut writer = writer_half;
-    let mut session = SessionAuth::default();
     let mut request_id = 0_u64;
-    let read_limits = ReadLimits {
-        max_bulk_bytes: max_request_bytes,
-        max_array_len: 4096,
-        max_line_bytes: 4096,
-    };
+    let registration = executor
+        .clients()
+        .register(connection_id, session.peer_addr.clone());
+    let mut registered_user = None;
 
     loop {
-        let frame = match tokio::time::timeout(
-            idle_timeout,
-            read_frame_with_limits(&mut reader, read_limits),
-        )
-        .await
-        {
-            Ok(Ok(Some(frame))) => frame,
-            Ok(Ok(None)) => break,
-            Ok(Err(e)) => return Err(e),
-            Err(_) => {
-                info!(connection_id, peer = %peer_addr, "client idle timeout");
+        let frame = tokio::select! {
+            _ = registration.killed() => {
+                info!(connection_id, peer = %peer_addr, "client killed");
                 break;
             }
+            read = tokio::time::timeout(idle_timeout, reader.read_frame()) => match read {
+                Ok(Ok(Some(frame))) => frame,
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => {
+                    info!(connection_id, peer = %peer_addr, "client idle timeout");
+                    break;
+                }
+            },
         };
 
-        let response = match frame_to_args(frame) {
-            Ok(args) => {
+        let response = match frame_to_args(frame)
+            .and_then(|args| split_trace_id(args, non_redis_mode))
+        {
+            Ok((trace_id, args)) => {
                 let request_bytes: usize = args.iter().map(|v| v.len()).sum();
                 if request_bytes > max_request_bytes {
                     warn!(
@@ -317,14 +668,40 @@ async fn handle_client(
                 let arg_count = args.len();
                 let started = Instant::now();
                 let (resp, action) = executor.execute(args, &mut session).await;
+                if session.user != registered_user {
+                    registered_user.clone_from(&session.user);
+                    registration.set_user(registered_user.as_deref());
+                }
                 let elapsed_usec = started.elapsed().as_micros() as u64;
                 let elapsed_ms = elapsed_usec / 1000;
                 executor.record_command_stats(&command, elapsed_usec);
+                executor.record_command_outcome(
+                    &command,
+                    &session,
+                    matches!(resp, RespValue::Error(_)),
+                    elapsed_usec,
+                );
+                if let Some(telemetry) = executor.telemetry() {
+                    telemetry.record_command(CommandSpan {
+                        command: &command,
+                        elapsed: started.elapsed(),
+                        connection_id,
+                        peer_addr: &peer_addr,
+                        user: session.user.as_deref(),
+                        trace_id: trace_id.as_deref(),
+                        error: match &resp {
+                            RespValue::Error(e) => Some(e.as_str()),
+                            _ => None,
+                        },
+                    });
+                }
                 let authed_user = session.user.as_deref().unwrap_or("-");
-                if matches!(resp, RespValue::Error(_)) {
+                if let RespValue::Error(message) = &resp {
+                    executor.record_error_reply(&command, message);
                     warn!(
                         connection_id,
                         request_id,
+                        trace_id = trace_id.as_deref(),
                         peer = %peer_addr,
                         user = authed_user,
                         command,
@@ -336,6 +713,7 @@ async fn handle_client(
                     debug!(
                         connection_id,
                         request_id,
+                        trace_id = trace_id.as_deref(),
                         peer = %peer_addr,
                         user = authed_user,
                         command,
@@ -344,24 +722,44 @@ async fn handle_client(
                         "command handled"
                     );
                 }
-                let payload = if with_response_ids {
-                    wrap_with_request_id(resp, request_id)
-                } else {
-                    resp
+                let request = match action {
+                    SessionAction::Replicate(request) => request,
+                    SessionAction::Follow(request) => {
+                        serve_follower(request, &mut reader, &mut writer).await?;
+                        break;
+                    }
+                    SessionAction::Subscribe(command) => {
+                        let closed =
+                            serve_subscriber(executor.pubsub(), command, &mut reader, &mut writer)
+                                .await?;
+                        if closed {
+                            break;
+                        }
+                        continue;
+                    }
+                    action => {
+                        let payload = if with_response_ids {
+                            wrap_with_request_id(resp, request_id, trace_id)
+                        } else {
+                            resp
+                        };
+                        let encoded = encode(payload);
+                        writer.write_all(&encoded).await?;
+                        if matches!(action, SessionAction::Close) {
+                            break;
+                        }
+                        continue;
+                    }
                 };
-                let encoded = encode(payload);
-                writer.write_all(&encoded).await?;
-                if matches!(action, SessionAction::Close) {
-                    break;
-                }
-                continue;
+                info!(connection_id, peer = %peer_addr, "replica connected");
+                return serve_replica(request, reader, writer).await;
             }
             Err(e) => {
                 request_id = request_id.saturating_add(1);
                 warn!(connection_id, peer = %peer_addr, error = %e, "invalid client frame");
                 let resp = RespValue::Error(e);
                 if with_response_ids {
-                    wrap_with_request_id(resp, request_id)
+                    wrap_with_request_id(resp, request_id, None)
                 } else {
                     resp
                 }
@@ -374,12 +772,51 @@ async fn handle_client(
     Ok(())
 }
 
-fn wrap_with_request_id(response: RespValue, request_id: u64) -> RespValue {
-    RespValue::Array(vec![
+/// `RID <request id> <reply>`, followed by the client's trace id when the
+/// command came with one.
+fn wrap_with_request_id(
+    response: RespValue,
+    request_id: u64,
+    trace_id: Option<String>,
+) -> RespValue {
+    let mut out = vec![
         RespValue::Simple("RID".to_string()),
-        RespValue::Bulk(Some(request_id.to_string().into_bytes())),
+        RespValue::Bulk(Some(request_id.to_string().into())),
         response,
-    ])
+    ];
+    if let Some(trace_id) = trace_id {
+        out.push(RespValue::Bulk(Some(trace_id.into())));
+    }
+    RespValue::Array(out)
+}
+
+/// In non_redis_mode a command may be prefixed with `TRACEID <id>`: the id
+/// is split off to be echoed after the reply and attached to the command's
+/// log lines and span.
+fn split_trace_id(
+    args: Vec<Vec<u8>>,
+    non_redis_mode: bool,
+) -> Result<(Option<String>, Vec<Vec<u8>>), String> {
+    if !non_redis_mode
+        || !args
+            .first()
+            .is_some_and(|name| name.eq_ignore_ascii_case(b"TRACEID"))
+    {
+        return Ok((None, args));
+    }
+    if args.len() < 3 {
+        return Err("ERR wrong number of arguments for 'traceid' command".to_string());
+    }
+    let id = &args[1];
+    if id.is_empty() || id.len() > MAX_TRACE_ID_BYTES || !id.iter().all(u8::is_ascii_graphic) {
+        return Err(format!(
+            "ERR trace id must be 1 to {} printable ASCII characters",
+            MAX_TRACE_ID_BYTES
+        ));
+    }
+    let id = String::from_utf8_lossy(id).into_owned();
+    let mut args = args;
+    Ok((Some(id), args.split_off(2)))
 }
 
 fn command_name(args: &[Vec<u8>]) -> String {
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Redis' defaults for `slowlog-log-slower-than` (microseconds) and `slowlog-max-len`.
+pub const DEFAULT_SLOWER_THAN_USEC: i64 = 10_000;
+pub const DEFAULT_MAX_LEN: usize = 128;
+
+/// Arguments past this many are folded into a "... (n more arguments)" marker.
+const MAX_ARGS: usize = 32;
+/// Arguments longer than this are cut, with a "... (n more bytes)" suffix.
+const MAX_ARG_BYTES: usize = 128;
+
+/// A command that ran for longer than the threshold, as `SLOWLOG GET` reports it.
+#[derive(Clone, Debug)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Unix time in seconds the command finished at.
+    pub timestamp: u64,
+    pub duration_usec: u64,
+    /// The command and its arguments, truncated so one huge request cannot
+    /// bloat the log.
+    pub args: Vec<Vec<u8>>,
+    pub client_addr: String,
+    pub client_name: String,
+    /// The ACL user the command ran as.
+    pub user: String,
+}
+
+/// Narrows `SLOWLOG GET` to the commands of one user or client.
+#[derive(Clone, Debug, Default)]
+pub struct SlowLogFilter {
+    pub user: Option<String>,
+    /// Matches the client's address or its name.
+    pub client: Option<String>,
+}
+
+impl SlowLogFilter {
+    fn matches(&self, entry: &SlowLogEntry) -> bool {
+        self.user.as_ref().is_none_or(|user| *user == entry.user)
+            && self
+                .client
+                .as_ref()
+                .is_none_or(|client| *client == entry.client_addr || *client == entry.client_name)
+    }
+}
+
+/// The newest slow commands, in a ring bounded by `slowlog-max-len`. Both
+/// settings change at runtime with `CONFIG SET`.
+pub struct SlowLog {
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+    next_id: AtomicU64,
+    /// Negative disables the log; zero logs every command.
+    slower_than_usec: AtomicI64,
+    max_len: AtomicUsize,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOWER_THAN_USEC, DEFAULT_MAX_LEN)
+    }
+}
+
+impl SlowLog {
+    pub fn new(slower_than_usec: i64, max_len: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(0),
+            slower_than_usec: AtomicI64::new(slower_than_usec),
+            max_len: AtomicUsize::new(max_len),
+        }
+    }
+
+    pub fn slower_than_usec(&self) -> i64 {
+        self.slower_than_usec.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slower_than_usec(&self, usec: i64) {
+        self.slower_than_usec.store(usec, Ordering::Relaxed);
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len.load(Ordering::Relaxed)
+    }
+
+    /// Shrinking the limit drops the oldest entries right away.
+    pub fn set_max_len(&self, max_len: usize) {
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.lock().truncate(max_len);
+    }
+
+    /// Whether a command that took `elapsed` goes in the log.
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        let threshold = self.slower_than_usec();
+        threshold >= 0 && elapsed.as_micros() as u64 >= threshold as u64 && self.max_len() > 0
+    }
+
+    /// Records the command when it ran for at least the threshold.
+    pub fn record(
+        &self,
+        args: &[Vec<u8>],
+        elapsed: Duration,
+        client_addr: &str,
+        client_name: &str,
+        user: &str,
+    ) {
+        if !self.is_slow(elapsed) {
+            return;
+        }
+        let max_len = self.max_len();
+        let duration_usec = elapsed.as_micros() as u64;
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration_usec,
+            args: truncate_args(args),
+            client_addr: client_addr.to_string(),
+            client_name: client_name.to_string(),
+            user: user.to_string(),
+        };
+        let mut entries = self.lock();
+        entries.push_front(entry);
+        entries.truncate(max_len);
+    }
+
+    /// The newest `count` entries that pass `filter`, newest first; all of
+    /// them for `None`.
+    pub fn entries(&self, count: Option<usize>, filter: &SlowLogFilter) -> Vec<SlowLogEntry> {
+        let entries = self.lock();
+        let count = count.unwrap_or(entries.len());
+        entries
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Entries ever logged, including those since dropped or reset.
+    pub fn total_logged(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<SlowLogEntry>> {
+        self.entries.lock().expect("slowlog lock")
+    }
+}
+
+fn truncate_args(args: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let kept = if args.len() > MAX_ARGS {
+        MAX_ARGS - 1
+    } else {
+        args.len()
+    };
+    let mut out: Vec<Vec<u8>> = args[..kept]
+        .iter()
+        .map(|arg| {
+            if arg.len() <= MAX_ARG_BYTES {
+                return arg.clone();
+            }
+            let mut cut = arg[..MAX_ARG_BYTES].to_vec();
+            cut.extend_from_slice(
+                format!("... ({} more bytes)", arg.len() - MAX_ARG_BYTES).as_bytes(),
+            );
+            cut
+        })
+        .collect();
+    if kept < args.len() {
+        out.push(format!("... ({} more arguments)", args.len() - kept).into_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_slow_commands_truncated() {
+        let log = SlowLog::new(1_000, 2);
+        let fast = Duration::from_micros(999);
+        let slow = Duration::from_millis(5);
+        log.record(
+            &[b"GET".to_vec(), b"a".to_vec()],
+            fast,
+            "1.2.3.4:5",
+            "",
+            "default",
+        );
+        assert_eq!(log.len(), 0);
+
+        let mut big = vec![b"MSET".to_vec()];
+        big.extend((0..40).map(|_| vec![b'x'; 200]));
+        log.record(&big, slow, "1.2.3.4:5", "worker", "loader");
+        log.record(
+            &[b"KEYS".to_vec(), b"*".to_vec()],
+            slow,
+            "1.2.3.4:5",
+            "",
+            "default",
+        );
+        log.record(
+            &[b"SCAN".to_vec(), b"0".to_vec()],
+            slow,
+            "1.2.3.4:5",
+            "",
+            "default",
+        );
+
+        let entries = log.entries(None, &SlowLogFilter::default());
+        assert_eq!(entries.len(), 2, "bounded by max-len");
+        assert_eq!(entries[0].args[0], b"SCAN");
+        assert_eq!(entries[0].id, 2);
+
+        log.set_max_len(3);
+        log.reset();
+        log.record(&big, slow, "1.2.3.4:5", "worker", "loader");
+        let entry = &log.entries(Some(1), &SlowLogFilter::default())[0];
+        assert_eq!(entry.args.len(), MAX_ARGS);
+        assert_eq!(entry.args[MAX_ARGS - 1], b"... (10 more arguments)");
+        assert_eq!(
+            entry.args[1].len(),
+            MAX_ARG_BYTES + "... (72 more bytes)".len()
+        );
+        assert_eq!(entry.client_name, "worker");
+        assert_eq!(entry.user, "loader");
+        assert_eq!(log.total_logged(), 4);
+
+        log.set_slower_than_usec(-1);
+        log.record(&big, slow, "1.2.3.4:5", "", "default");
+        assert_eq!(log.len(), 1, "a negative threshold disables the log");
+    }
+}
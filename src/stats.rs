@@ -1,46 +1,91 @@
 use std::collections::HashMap;
 use std::sync::Mutex;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+use crate::auth::generate_password;
+use crate::latency::LatencyHistogram;
 
 pub struct ServerStats {
     started_at: Instant,
+    /// Identifies this process in INFO; Sentinel notices restarts by it.
+    run_id: String,
     connected_clients: AtomicUsize,
     total_connections: AtomicU64,
+    ip_rejected_connections: AtomicU64,
     total_commands: AtomicU64,
     total_command_usec: AtomicU64,
     ops_window: AtomicU64,
     ops_per_sec: AtomicU64,
     command_calls: Mutex<HashMap<String, CommandTiming>>,
+    /// Calls and microseconds by command, user and whether the reply was an
+    /// error, for the labelled metrics.
+    command_outcomes: Mutex<HashMap<CommandOutcome, (u64, u64)>>,
+    /// Latency of every command together, in microseconds.
+    command_latency: Mutex<LatencyHistogram>,
+    /// How long closed connections stayed open, in milliseconds.
+    connection_durations: Mutex<LatencyHistogram>,
+    rate_limited: Mutex<HashMap<String, u64>>,
+    total_error_replies: AtomicU64,
+    /// Error replies by command and error prefix (`WRONGTYPE`, `NOPERM`, ...).
+    error_replies: Mutex<HashMap<(String, String), u64>>,
 }
 
-#[derive(Clone, Copy)]
+/// Distinct error prefixes tracked, as Redis caps `errorstats`; replies with
+/// new prefixes past it still count in `total_error_replies`.
+const MAX_ERROR_PREFIXES: usize = 128;
+
+/// Command, user and whether the reply was an error.
+type CommandOutcome = (String, String, bool);
+
+#[derive(Default)]
 struct CommandTiming {
     calls: u64,
     usec: u64,
+    latency: LatencyHistogram,
 }
 
 impl ServerStats {
     pub fn new() -> Self {
         Self {
             started_at: Instant::now(),
+            run_id: generate_password(160).expect("random run_id"),
             connected_clients: AtomicUsize::new(0),
             total_connections: AtomicU64::new(0),
+            ip_rejected_connections: AtomicU64::new(0),
             total_commands: AtomicU64::new(0),
             total_command_usec: AtomicU64::new(0),
             ops_window: AtomicU64::new(0),
             ops_per_sec: AtomicU64::new(0),
             command_calls: Mutex::new(HashMap::new()),
+            command_outcomes: Mutex::new(HashMap::new()),
+            command_latency: Mutex::new(LatencyHistogram::default()),
+            connection_durations: Mutex::new(LatencyHistogram::default()),
+            rate_limited: Mutex::new(HashMap::new()),
+            total_error_replies: AtomicU64::new(0),
+            error_replies: Mutex::new(HashMap::new()),
         }
     }
 
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
     pub fn on_connect(&self) {
         self.connected_clients.fetch_add(1, Ordering::Relaxed);
         self.total_connections.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn on_disconnect(&self) {
+    /// `connected_for` is how long the connection was open.
+    pub fn on_disconnect(&self, connected_for: Duration) {
         self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+        if let Ok(mut durations) = self.connection_durations.lock() {
+            durations.record(connected_for.as_millis() as u64);
+        }
+    }
+
+    pub fn on_ip_rejected(&self) {
+        self.ip_rejected_connections.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn record_command(&self, command: &str, elapsed_usec: u64) {
@@ -50,14 +95,48 @@ impl ServerStats {
         self.ops_window.fetch_add(1, Ordering::Relaxed);
         if let Ok(mut calls) = self.command_calls.lock() {
             let key = command.to_ascii_lowercase();
-            let entry = calls
-                .entry(key)
-                .or_insert(CommandTiming { calls: 0, usec: 0 });
+            let entry = calls.entry(key).or_default();
             entry.calls += 1;
             entry.usec = entry.usec.saturating_add(elapsed_usec);
+            entry.latency.record(elapsed_usec);
+        }
+        if let Ok(mut latency) = self.command_latency.lock() {
+            latency.record(elapsed_usec);
+        }
+    }
+
+    /// Counts a call by who made it and whether it failed; `record_command`
+    /// still does the unlabelled totals.
+    pub fn record_command_outcome(
+        &self,
+        command: &str,
+        user: &str,
+        error: bool,
+        elapsed_usec: u64,
+    ) {
+        if let Ok(mut outcomes) = self.command_outcomes.lock() {
+            let key = (command.to_ascii_lowercase(), user.to_string(), error);
+            let entry = outcomes.entry(key).or_default();
+            entry.0 += 1;
+            entry.1 = entry.1.saturating_add(elapsed_usec);
         }
     }
 
+    /// `(command, user, error, calls, usec)`, sorted.
+    pub fn command_outcome_snapshot(&self) -> Vec<(String, String, bool, u64, u64)> {
+        let Ok(outcomes) = self.command_outcomes.lock() else {
+            return Vec::new();
+        };
+        let mut out: Vec<(String, String, bool, u64, u64)> = outcomes
+            .iter()
+            .map(|((command, user, error), (calls, usec))| {
+                (command.clone(), user.clone(), *error, *calls, *usec)
+            })
+            .collect();
+        out.sort();
+        out
+    }
+
     pub fn uptime_secs(&self) -> u64 {
         self.started_at.elapsed().as_secs()
     }
@@ -70,6 +149,10 @@ impl ServerStats {
         self.total_connections.load(Ordering::Relaxed)
     }
 
+    pub fn ip_rejected_connections(&self) -> u64 {
+        self.ip_rejected_connections.load(Ordering::Relaxed)
+    }
+
     pub fn total_commands(&self) -> u64 {
         self.total_commands.load(Ordering::Relaxed)
     }
@@ -98,4 +181,104 @@ impl ServerStats {
         }
         Vec::new()
     }
+
+    /// Latency histograms of the commands that were called, by lower-case
+    /// name; only those named in `filter` when it is given.
+    pub fn command_latency_snapshot(
+        &self,
+        filter: Option<&[String]>,
+    ) -> Vec<(String, LatencyHistogram)> {
+        let Ok(calls) = self.command_calls.lock() else {
+            return Vec::new();
+        };
+        let mut out: Vec<(String, LatencyHistogram)> = calls
+            .iter()
+            .filter(|(name, _)| filter.is_none_or(|names| names.contains(name)))
+            .map(|(name, timing)| (name.clone(), timing.latency.clone()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    pub fn command_latency(&self) -> LatencyHistogram {
+        self.command_latency
+            .lock()
+            .map(|latency| latency.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn connection_durations(&self) -> LatencyHistogram {
+        self.connection_durations
+            .lock()
+            .map(|durations| durations.clone())
+            .unwrap_or_default()
+    }
+
+    /// Counts an error reply to `command` under the first word of `message`.
+    pub fn record_error_reply(&self, command: &str, message: &str) {
+        self.total_error_replies.fetch_add(1, Ordering::Relaxed);
+        let prefix = message.split(' ').next().unwrap_or_default();
+        let Ok(mut errors) = self.error_replies.lock() else {
+            return;
+        };
+        let key = (command.to_ascii_lowercase(), prefix.to_string());
+        if let Some(count) = errors.get_mut(&key) {
+            *count += 1;
+            return;
+        }
+        let known_prefix = errors.keys().any(|(_, p)| p == prefix);
+        if known_prefix || error_prefixes(&errors).len() < MAX_ERROR_PREFIXES {
+            errors.insert(key, 1);
+        }
+    }
+
+    pub fn total_error_replies(&self) -> u64 {
+        self.total_error_replies.load(Ordering::Relaxed)
+    }
+
+    /// Error replies by prefix, sorted by prefix.
+    pub fn error_stats_snapshot(&self) -> Vec<(String, u64)> {
+        let Ok(errors) = self.error_replies.lock() else {
+            return Vec::new();
+        };
+        let mut out: Vec<(String, u64)> = error_prefixes(&errors).into_iter().collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// Error replies by command and prefix, sorted.
+    pub fn command_error_snapshot(&self) -> Vec<(String, String, u64)> {
+        let Ok(errors) = self.error_replies.lock() else {
+            return Vec::new();
+        };
+        let mut out: Vec<(String, String, u64)> = errors
+            .iter()
+            .map(|((command, prefix), count)| (command.clone(), prefix.clone(), *count))
+            .collect();
+        out.sort();
+        out
+    }
+
+    pub fn record_rate_limited(&self, user: &str) {
+        if let Ok(mut counts) = self.rate_limited.lock() {
+            *counts.entry(user.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    pub fn rate_limited_snapshot(&self) -> Vec<(String, u64)> {
+        if let Ok(counts) = self.rate_limited.lock() {
+            let mut out: Vec<(String, u64)> = counts.iter().map(|(k, v)| (k.clone(), *v)).collect();
+            out.sort_by(|a, b| a.0.cmp(&b.0));
+            return out;
+        }
+        Vec::new()
+    }
+}
+
+fn error_prefixes(errors: &HashMap<(String, String), u64>) -> HashMap<String, u64> {
+    let mut out: HashMap<String, u64> = HashMap::new();
+    for ((_, prefix), count) in errors {
+        *out.entry(prefix.clone()).or_insert(0) += count;
+    }
+    out
 }
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::latency::LatencyHistogram;
+use crate::stats::ServerStats;
+use crate::store::Store;
+
+const DEFAULT_FLUSH_INTERVAL_MS: u64 = 10_000;
+/// Keeps a datagram inside a 1500-byte Ethernet MTU after IP and UDP
+/// headers, as the StatsD docs recommend.
+const MAX_PACKET_BYTES: usize = 1432;
+
+/// How tags are attached to each line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagFormat {
+    /// `name:1|c|#key:value`, as DogStatsD and Telegraf read them.
+    DogStatsd,
+    /// `name;key=value:1|c`, passed through by StatsD to Graphite's tagged
+    /// series.
+    Graphite,
+    /// No tags: the command goes into the metric name instead.
+    None,
+}
+
+/// Push export to a StatsD collector over UDP, for environments without a
+/// Prometheus scraper.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsdConfig {
+    /// `host:port` of the collector.
+    pub addr: String,
+    /// Put before every metric name, joined with a dot.
+    pub prefix: String,
+    /// Added to every line.
+    pub tags: Vec<(String, String)>,
+    pub tag_format: TagFormat,
+    pub flush_interval: Duration,
+}
+
+/// Builds the export settings from `setting`; `None` unless
+/// `FEDIS_STATSD_ADDR` is set.
+pub fn parse_statsd(
+    setting: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<StatsdConfig>, Box<dyn std::error::Error>> {
+    let Some(addr) = setting("FEDIS_STATSD_ADDR") else {
+        return Ok(None);
+    };
+    let addr = addr.trim().to_string();
+    if addr
+        .rsplit_once(':')
+        .is_none_or(|(host, port)| host.is_empty() || port.parse::<u16>().is_err())
+    {
+        return Err("FEDIS_STATSD_ADDR must be host:port".into());
+    }
+    let prefix = setting("FEDIS_STATSD_PREFIX").unwrap_or_else(|| "fedis".to_string());
+    let prefix = prefix.trim().trim_end_matches('.').to_string();
+    if prefix.is_empty() || !prefix.split('.').all(is_name_part) {
+        return Err(format!("invalid FEDIS_STATSD_PREFIX '{}'", prefix).into());
+    }
+    let tag_format = match setting("FEDIS_STATSD_TAG_FORMAT").as_deref().map(str::trim) {
+        None | Some("dogstatsd") => TagFormat::DogStatsd,
+        Some("graphite") => TagFormat::Graphite,
+        Some("none") => TagFormat::None,
+        Some(other) => {
+            return Err(format!(
+                "FEDIS_STATSD_TAG_FORMAT must be dogstatsd, graphite or none, not '{}'",
+                other
+            )
+            .into());
+        }
+    };
+    let tags = setting("FEDIS_STATSD_TAGS")
+        .as_deref()
+        .map(parse_tags)
+        .transpose()?
+        .unwrap_or_default();
+    if tag_format == TagFormat::None && !tags.is_empty() {
+        return Err("FEDIS_STATSD_TAGS needs a FEDIS_STATSD_TAG_FORMAT other than none".into());
+    }
+    let flush_interval = match setting("FEDIS_STATSD_FLUSH_INTERVAL_MS") {
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(ms) if ms > 0 => ms,
+            _ => return Err("FEDIS_STATSD_FLUSH_INTERVAL_MS must be a positive integer".into()),
+        },
+        None => DEFAULT_FLUSH_INTERVAL_MS,
+    };
+    Ok(Some(StatsdConfig {
+        addr,
+        prefix,
+        tags,
+        tag_format,
+        flush_interval: Duration::from_millis(flush_interval),
+    }))
+}
+
+/// `key:value` pairs separated by commas.
+fn parse_tags(raw: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once(':')
+                .ok_or_else(|| format!("StatsD tag '{}' is not key:value", pair))?;
+            let (key, value) = (key.trim(), value.trim());
+            if !is_name_part(key) || !is_name_part(value) {
+                return Err(format!("invalid StatsD tag '{}'", pair).into());
+            }
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn is_name_part(part: &str) -> bool {
+    !part.is_empty()
+        && part
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-'))
+}
+
+/// Spawns the flush task; call from within the runtime.
+pub fn spawn_statsd(config: StatsdConfig, stats: Arc<ServerStats>, store: Store) {
+    info!(
+        addr = %config.addr,
+        interval_ms = config.flush_interval.as_millis() as u64,
+        "StatsD export enabled"
+    );
+    tokio::spawn(async move {
+        let mut socket: Option<UdpSocket> = None;
+        let mut previous = Previous::default();
+        let mut ticker = tokio::time::interval(config.flush_interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let lines = previous.flush(&config, &stats, &store).await;
+            if socket.is_none() {
+                match connect(&config.addr).await {
+                    Ok(connected) => socket = Some(connected),
+                    Err(e) => {
+                        warn!(addr = %config.addr, error = %e, "StatsD collector unreachable");
+                        continue;
+                    }
+                }
+            }
+            let Some(socket) = &socket else { continue };
+            for packet in packets(&lines) {
+                // A collector that is down refuses datagrams; the next flush
+                // carries on with fresh deltas.
+                if let Err(e) = socket.send(packet.as_bytes()).await {
+                    debug!(error = %e, "StatsD send failed");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+async fn connect(addr: &str) -> std::io::Result<UdpSocket> {
+    let target = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::other("no address"))?;
+    let local = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// Totals at the last flush, so counters go out as increments.
+#[derive(Default)]
+struct Previous {
+    commands: u64,
+    errors: u64,
+    connections: u64,
+    latency: HashMap<String, LatencyHistogram>,
+}
+
+impl Previous {
+    async fn flush(
+        &mut self,
+        config: &StatsdConfig,
+        stats: &ServerStats,
+        store: &Store,
+    ) -> Vec<String> {
+        let store_metrics = store.metrics().await;
+        let mut lines = vec![
+            line(
+                config,
+                "clients.connected",
+                stats.connected_clients() as u64,
+                "g",
+                None,
+            ),
+            line(config, "keys", store_metrics.keys as u64, "g", None),
+            line(
+                config,
+                "keys.expiring",
+                store_metrics.expiring_keys as u64,
+                "g",
+                None,
+            ),
+            line(
+                config,
+                "memory.used",
+                store_metrics.approx_memory_bytes as u64,
+                "g",
+                None,
+            ),
+        ];
+        let total = stats.total_commands();
+        lines.push(line(
+            config,
+            "commands",
+            total.saturating_sub(self.commands),
+            "c",
+            None,
+        ));
+        self.commands = total;
+        let errors = stats.total_error_replies();
+        lines.push(line(
+            config,
+            "errors",
+            errors.saturating_sub(self.errors),
+            "c",
+            None,
+        ));
+        self.errors = errors;
+        let connections = stats.total_connections();
+        lines.push(line(
+            config,
+            "connections",
+            connections.saturating_sub(self.connections),
+            "c",
+            None,
+        ));
+        self.connections = connections;
+
+        for (command, hist) in stats.command_latency_snapshot(None) {
+            let interval = match self.latency.get(&command) {
+                Some(earlier) => hist.since(earlier),
+                None => hist.clone(),
+            };
+            if interval.count() > 0 {
+                let tag = Some(command.as_str());
+                lines.push(line(config, "command.calls", interval.count(), "c", tag));
+                let mean = interval.sum() / interval.count();
+                for (name, usec) in [
+                    ("command.latency.mean_usec", mean),
+                    ("command.latency.p50_usec", interval.percentile(50.0)),
+                    ("command.latency.p99_usec", interval.percentile(99.0)),
+                    ("command.latency.max_usec", interval.percentile(100.0)),
+                ] {
+                    lines.push(line(config, name, usec, "g", tag));
+                }
+            }
+            self.latency.insert(command, hist);
+        }
+        lines
+    }
+}
+
+/// One StatsD line; `command` is tagged, or folded into the name when tags
+/// are off.
+fn line(
+    config: &StatsdConfig,
+    name: &str,
+    value: u64,
+    kind: &str,
+    command: Option<&str>,
+) -> String {
+    let command = command.map(|c| {
+        c.chars()
+            .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+            .collect::<String>()
+    });
+    let mut tags: Vec<(&str, &str)> = config
+        .tags
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let mut metric = format!("{}.{}", config.prefix, name);
+    match (&command, config.tag_format) {
+        (Some(command), TagFormat::None) => {
+            let (group, leaf) = name.rsplit_once('.').unwrap_or(("", name));
+            metric = format!("{}.{}.{}.{}", config.prefix, group, command, leaf);
+        }
+        (Some(command), _) => tags.push(("command", command)),
+        (None, _) => {}
+    }
+    match config.tag_format {
+        TagFormat::DogStatsd if !tags.is_empty() => {
+            let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+            format!("{}:{}|{}|#{}", metric, value, kind, tags.join(","))
+        }
+        TagFormat::Graphite => {
+            for (key, value) in tags {
+                metric.push_str(&format!(";{}={}", key, value));
+            }
+            format!("{}:{}|{}", metric, value, kind)
+        }
+        _ => format!("{}:{}|{}", metric, value, kind),
+    }
+}
+
+/// Joins lines with newlines into datagrams of at most `MAX_PACKET_BYTES`.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_PACKET_BYTES {
+            out.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Result<Option<StatsdConfig>, String> {
+        let lookup = |key: &str| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        };
+        parse_statsd(&lookup).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn lines_follow_the_tag_format() {
+        assert_eq!(config(&[]), Ok(None));
+        assert!(config(&[("FEDIS_STATSD_ADDR", "collector")]).is_err());
+        assert!(
+            config(&[
+                ("FEDIS_STATSD_ADDR", "collector:8125"),
+                ("FEDIS_STATSD_TAGS", "env=prod"),
+            ])
+            .is_err()
+        );
+
+        let mut cfg = config(&[
+            ("FEDIS_STATSD_ADDR", "collector:8125"),
+            ("FEDIS_STATSD_PREFIX", "cache.fedis"),
+            ("FEDIS_STATSD_TAGS", "env:prod, region:eu-1"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(cfg.tag_format, TagFormat::DogStatsd);
+        assert_eq!(cfg.flush_interval, Duration::from_secs(10));
+        assert_eq!(
+            line(&cfg, "command.calls", 3, "c", Some("client|list")),
+            "cache.fedis.command.calls:3|c|#env:prod,region:eu-1,command:client_list"
+        );
+
+        cfg.tag_format = TagFormat::Graphite;
+        assert_eq!(
+            line(&cfg, "keys", 7, "g", None),
+            "cache.fedis.keys;env=prod;region=eu-1:7|g"
+        );
+
+        cfg.tag_format = TagFormat::None;
+        cfg.tags.clear();
+        assert_eq!(
+            line(&cfg, "command.latency.p99_usec", 120, "g", Some("get")),
+            "cache.fedis.command.latency.get.p99_usec:120|g"
+        );
+    }
+
+    #[test]
+    fn packets_stay_under_the_mtu() {
+        let lines: Vec<String> = (0..100)
+            .map(|n| format!("fedis.metric{:03}:1|c", n))
+            .collect();
+        let packets = packets(&lines);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= MAX_PACKET_BYTES));
+        assert_eq!(packets.join("\n").lines().count(), 100);
+    }
+}
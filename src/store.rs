@@ -1,38 +1,121 @@
-use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::io::{ErrorKind, Read};
+use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use bytes::Bytes;
 use serde_json::Value as JsonValue;
 use tokio::sync::{Mutex, RwLock};
 
+use crate::atomic_file::AtomicFile;
+use crate::backend::{
+    IndexedShard, MemoryCounter, MemoryUsage, ShardBackend, ShardMap, SledShard, StorageEngine,
+    ValueEntry,
+};
+use crate::changefeed::ChangeFeed;
+use crate::checksum::{Crc64Writer, crc64};
+use crate::compression::Compression;
+use crate::encoding::{ValueType, read_value_header, write_value_header};
+use crate::encryption::Keyring;
+use crate::export::{ExportFormat, write_export};
+use crate::jsonpath::JsonError;
+use crate::latency::LatencyHistogram;
+use crate::lazyfree::LazyFree;
 use crate::persistence::{Aof, LogRecord};
+use crate::remote::RemoteSnapshots;
+use crate::replication::ReplicationFeed;
+use crate::search::{IndexDefinition, Query};
+use crate::throttle::{Decision, Gcra};
+use crate::tier::{TierStats, TieredShard};
+use crate::timeseries::{self, Series, TsError};
+use crate::tombstone::{TombstoneMetrics, Tombstones};
+use crate::webhook::{ExpiryWebhook, ExpiryWebhookMetrics};
+use crate::write_behind::{WriteBehind, WriteBehindMetrics};
 
 const DEFAULT_SHARDS: usize = 32;
+/// Expired keys removed per shard lock acquisition during active expiration.
+const EXPIRE_BATCH: usize = 128;
+/// Set in a sled database once the snapshot and AOF have been imported into it.
+const SLED_IMPORTED_MARKER: &[u8] = b"fedis:imported";
+
+type Shard = RwLock<IndexedShard>;
+pub(crate) type SnapshotEntry = (Vec<u8>, ValueType, Vec<u8>, Option<u64>);
+pub(crate) type EntryRef<'a> = (&'a [u8], ValueType, &'a [u8], Option<u64>);
 
 #[derive(Clone)]
 pub struct Store {
-    shards: std::sync::Arc<Vec<RwLock<HashMap<Vec<u8>, ValueEntry>>>>,
+    shards: std::sync::Arc<Vec<Shard>>,
     shard_count: usize,
     op_lock: std::sync::Arc<Mutex<()>>,
     aof: Aof,
+    /// Set when the sled engine holds the keyspace; it replaces the AOF.
+    sled: Option<sled::Db>,
     rewrite_in_progress: std::sync::Arc<AtomicBool>,
     rewrite_count: std::sync::Arc<AtomicU64>,
     rewrite_fail_count: std::sync::Arc<AtomicU64>,
+    last_rewrite_ok: std::sync::Arc<AtomicBool>,
     last_rewrite_epoch_sec: std::sync::Arc<AtomicU64>,
     snapshot_path: Option<PathBuf>,
+    /// Redis-compatible RDB copy written alongside snapshots by `SAVE`/`BGSAVE`.
+    rdb_export_path: Option<PathBuf>,
+    /// Where `EXPORT` writes its files.
+    export_dir: Option<PathBuf>,
+    export_in_progress: std::sync::Arc<AtomicBool>,
+    snapshot_compression: Compression,
+    /// Off-box copies of every snapshot written to `snapshot_path`.
+    remote: Option<RemoteSnapshots>,
+    /// Replicas following this node receive every logged record.
+    replication: Option<ReplicationFeed>,
+    /// So does the external write-behind sink, when configured.
+    write_behind: Option<WriteBehind>,
+    /// Told about every key removed because it expired.
+    expiry_webhook: Option<ExpiryWebhook>,
+    /// Every change, expiries included, for `FOLLOW`.
+    changes: Option<ChangeFeed>,
+    /// Where `DEL` and `UNLINK` move keys to when soft delete is on.
+    tombstones: Option<std::sync::Arc<Tombstones>>,
     snapshot_in_progress: std::sync::Arc<AtomicBool>,
     snapshot_count: std::sync::Arc<AtomicU64>,
     snapshot_fail_count: std::sync::Arc<AtomicU64>,
+    last_snapshot_ok: std::sync::Arc<AtomicBool>,
     last_snapshot_epoch_sec: std::sync::Arc<AtomicU64>,
+    /// Writes since the last successful snapshot, for `save <seconds> <changes>` rules.
+    dirty: std::sync::Arc<AtomicU64>,
+    /// Shard the next active expiration cycle starts at, so a cycle that runs
+    /// out of time does not keep skipping the same shards.
+    expire_cursor: std::sync::Arc<AtomicUsize>,
+    /// Memory used by the keys of all shards, updated as they are written.
+    memory: std::sync::Arc<MemoryCounter>,
+    /// Set when the tiered engine spills values to disk; what it spilled does
+    /// not count as used memory.
+    tier: Option<std::sync::Arc<TierStats>>,
+    /// Frees big removed values and flushed shards off the command path.
+    lazy_free: LazyFree,
+}
+
+/// A Redis-style `save <seconds> <changes>` rule: snapshot once at least `changes`
+/// writes happened and `seconds` passed since the last save.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
 }
 
 pub struct StoreMetrics {
     pub keys: usize,
     pub expiring_keys: usize,
+    /// `dataset_bytes` plus `overhead_bytes`.
     pub approx_memory_bytes: usize,
+    /// Key and value bytes.
+    pub dataset_bytes: usize,
+    /// What holding the keys costs on top of their bytes: map entries and the
+    /// expiry index.
+    pub overhead_bytes: usize,
+    /// The expiry index's share of `overhead_bytes`.
+    pub expires_overhead_bytes: usize,
+    /// The largest `approx_memory_bytes` seen so far.
+    pub peak_memory_bytes: usize,
 }
 
 pub struct ScanResult {
@@ -45,19 +128,52 @@ pub struct PersistenceMetrics {
     pub rewrite_in_progress: bool,
     pub rewrite_count: u64,
     pub rewrite_fail_count: u64,
+    pub last_rewrite_ok: bool,
     pub last_rewrite_epoch_sec: u64,
     pub snapshot_in_progress: bool,
     pub snapshot_count: u64,
     pub snapshot_fail_count: u64,
+    pub last_snapshot_ok: bool,
     pub last_snapshot_epoch_sec: u64,
+    pub changes_since_last_save: u64,
+    pub aof_pending_writes: u64,
+    pub aof_last_fsync_age_ms: u64,
+    pub aof_fsync_latency: LatencyHistogram,
+    pub aof_last_write_ok: bool,
+    pub aof_base_size: u64,
+    pub aof_current_size: u64,
+    pub write_behind: Option<WriteBehindMetrics>,
 }
 
 pub enum IncrByError {
     NotInteger,
     OutOfRange,
+    WrongType,
     Internal,
 }
 
+/// A string command on a key that holds a JSON document.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WrongType;
+
+impl std::fmt::Display for WrongType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WRONGTYPE Operation against a key holding the wrong kind of value")
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+/// What `UNDELETE` did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Undelete {
+    Restored,
+    /// No tombstone for the key, or it ran out.
+    Missing,
+    /// The key was written again since; its tombstone stays.
+    Exists,
+}
+
 pub enum GetExMode {
     None,
     Ex(u64),
@@ -65,12 +181,6 @@ pub enum GetExMode {
     Persist,
 }
 
-#[derive(Clone)]
-struct ValueEntry {
-    value: Vec<u8>,
-    expires_at: Option<u64>,
-}
-
 pub enum SetCondition {
     None,
     Nx,
@@ -78,39 +188,326 @@ pub enum SetCondition {
 }
 
 impl Store {
+    /// A memory-engine store, as tests build it.
+    #[cfg(test)]
     pub async fn new(
         aof: Aof,
         snapshot_path: Option<PathBuf>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut shards = Vec::with_capacity(DEFAULT_SHARDS);
-        for _ in 0..DEFAULT_SHARDS {
-            shards.push(RwLock::new(HashMap::new()));
-        }
+        Self::open(aof, snapshot_path, StorageEngine::Memory).await
+    }
+
+    /// Opens the keyspace on `engine`. The memory engine is rebuilt from the
+    /// snapshot and AOF; sled imports them once, into an empty database, and
+    /// persists everything itself from then on.
+    pub async fn open(
+        aof: Aof,
+        snapshot_path: Option<PathBuf>,
+        engine: StorageEngine,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut shards: Vec<Shard> = Vec::with_capacity(DEFAULT_SHARDS);
+        let memory = std::sync::Arc::new(MemoryCounter::default());
+        let mut tier = None;
+        let sled = match &engine {
+            StorageEngine::Memory => {
+                for _ in 0..DEFAULT_SHARDS {
+                    shards.push(RwLock::new(IndexedShard::new(
+                        Box::new(ShardMap::new()),
+                        memory.clone(),
+                    )));
+                }
+                None
+            }
+            StorageEngine::Sled(path) => {
+                let db = sled::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                for idx in 0..DEFAULT_SHARDS {
+                    shards.push(RwLock::new(IndexedShard::new(
+                        Box::new(SledShard::open(&db, idx)?),
+                        memory.clone(),
+                    )));
+                }
+                Some(db)
+            }
+            StorageEngine::Tiered(config) => {
+                let stats = std::sync::Arc::new(TierStats::default());
+                for idx in 0..DEFAULT_SHARDS {
+                    shards.push(RwLock::new(IndexedShard::new(
+                        Box::new(TieredShard::open(
+                            config,
+                            idx,
+                            DEFAULT_SHARDS,
+                            stats.clone(),
+                        )?),
+                        memory.clone(),
+                    )));
+                }
+                tier = Some(stats);
+                None
+            }
+        };
 
         let store = Self {
             shards: std::sync::Arc::new(shards),
             shard_count: DEFAULT_SHARDS,
             op_lock: std::sync::Arc::new(Mutex::new(())),
             aof,
+            sled,
             rewrite_in_progress: std::sync::Arc::new(AtomicBool::new(false)),
             rewrite_count: std::sync::Arc::new(AtomicU64::new(0)),
             rewrite_fail_count: std::sync::Arc::new(AtomicU64::new(0)),
+            last_rewrite_ok: std::sync::Arc::new(AtomicBool::new(true)),
             last_rewrite_epoch_sec: std::sync::Arc::new(AtomicU64::new(0)),
             snapshot_path,
+            rdb_export_path: None,
+            export_dir: None,
+            export_in_progress: std::sync::Arc::new(AtomicBool::new(false)),
+            snapshot_compression: Compression::None,
+            remote: None,
+            replication: None,
+            write_behind: None,
+            expiry_webhook: None,
+            changes: None,
+            tombstones: None,
             snapshot_in_progress: std::sync::Arc::new(AtomicBool::new(false)),
             snapshot_count: std::sync::Arc::new(AtomicU64::new(0)),
             snapshot_fail_count: std::sync::Arc::new(AtomicU64::new(0)),
+            last_snapshot_ok: std::sync::Arc::new(AtomicBool::new(true)),
             last_snapshot_epoch_sec: std::sync::Arc::new(AtomicU64::new(0)),
+            dirty: std::sync::Arc::new(AtomicU64::new(0)),
+            expire_cursor: std::sync::Arc::new(AtomicUsize::new(0)),
+            memory,
+            tier,
+            lazy_free: LazyFree::new(),
         };
-        store.load_snapshot().await?;
+        if let Some(db) = &store.sled {
+            if !db.contains_key(SLED_IMPORTED_MARKER)? {
+                store.load_snapshot().await?;
+                store.replay().await?;
+                db.insert(SLED_IMPORTED_MARKER, &[])?;
+                db.flush_async().await?;
+            }
+            return Ok(store);
+        }
+        // The snapshot may hold changes made after the recovery target, so a
+        // point-in-time recovery rebuilds from the log alone and then replaces it.
+        let recovering = store.aof.recovering();
+        if !recovering {
+            store.load_snapshot().await?;
+        }
         store.replay().await?;
+        if store.aof.format_mismatch() {
+            // Switching encryption on or off: rewrite the log in the configured format
+            // before appending anything to it.
+            store.rewrite_aof().await?;
+        }
+        if recovering && store.snapshot_path.is_some() {
+            store.save_snapshot_now().await?;
+        }
         Ok(store)
     }
 
+    pub fn with_rdb_export_path(mut self, path: Option<PathBuf>) -> Self {
+        self.rdb_export_path = path;
+        self
+    }
+
+    pub fn with_export_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.export_dir = dir;
+        self
+    }
+
+    pub fn with_snapshot_compression(mut self, compression: Compression) -> Self {
+        self.snapshot_compression = compression;
+        self
+    }
+
+    pub fn with_remote_snapshots(mut self, remote: Option<RemoteSnapshots>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    pub fn with_replication_feed(mut self, feed: Option<ReplicationFeed>) -> Self {
+        self.replication = feed;
+        self
+    }
+
+    pub(crate) fn with_write_behind(mut self, sink: Option<WriteBehind>) -> Self {
+        self.write_behind = sink;
+        self
+    }
+
+    /// Waits up to `timeout` for the write-behind sink to take the records
+    /// queued for it; returns how many it has not.
+    pub(crate) async fn drain_write_behind(&self, timeout: Duration) -> u64 {
+        match &self.write_behind {
+            Some(sink) => sink.drain(timeout).await,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn with_expiry_webhook(mut self, webhook: Option<ExpiryWebhook>) -> Self {
+        self.expiry_webhook = webhook;
+        self
+    }
+
+    pub(crate) fn with_change_feed(mut self, feed: Option<ChangeFeed>) -> Self {
+        self.changes = feed;
+        self
+    }
+
+    pub(crate) fn change_feed(&self) -> Option<&ChangeFeed> {
+        self.changes.as_ref()
+    }
+
+    /// Keeps the keys `DEL` and `UNLINK` remove for `UNDELETE`, for
+    /// `retention`.
+    pub(crate) fn with_soft_delete(mut self, retention: Option<Duration>) -> Self {
+        self.tombstones =
+            retention.map(|retention| std::sync::Arc::new(Tombstones::new(retention)));
+        self
+    }
+
+    pub(crate) fn soft_delete(&self) -> bool {
+        self.tombstones.is_some()
+    }
+
+    pub(crate) fn tombstone_metrics(&self) -> Option<TombstoneMetrics> {
+        self.tombstones.as_deref().map(Tombstones::metrics)
+    }
+
+    pub(crate) fn expiry_webhook_metrics(&self) -> Option<ExpiryWebhookMetrics> {
+        self.expiry_webhook.as_ref().map(ExpiryWebhook::metrics)
+    }
+
+    /// Values of at least `bytes` removed by `UNLINK` or active expiration
+    /// are freed on the lazyfree thread.
+    pub fn with_lazyfree_threshold(mut self, bytes: usize) -> Self {
+        self.lazy_free = self.lazy_free.with_threshold(bytes);
+        self
+    }
+
+    pub fn lazy_free(&self) -> &LazyFree {
+        &self.lazy_free
+    }
+
+    pub fn replication_feed(&self) -> Option<&ReplicationFeed> {
+        self.replication.as_ref()
+    }
+
+    /// Logs `record` for a write applied under `held`, the write lock of
+    /// the key's shard. It goes into the replication and change feeds and
+    /// the write-behind queue before the lock is released, so all of them
+    /// see the writes to a key in the order they were applied.
+    async fn log(
+        &self,
+        held: impl Send,
+        record: LogRecord,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.sequence(std::slice::from_ref(&record));
+        drop(held);
+        if let Some(sink) = &self.write_behind {
+            sink.wait_for_room().await;
+        }
+        if self.sled.is_some() {
+            return Ok(());
+        }
+        self.aof.append(record).await
+    }
+
+    /// `log` for the records of one multi-key command, with the lock of
+    /// every shard they touch held: a single AOF write.
+    async fn log_batch(
+        &self,
+        held: impl Send,
+        records: Vec<LogRecord>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.sequence(&records);
+        drop(held);
+        if let Some(sink) = &self.write_behind {
+            sink.wait_for_room().await;
+        }
+        if self.sled.is_some() {
+            return Ok(());
+        }
+        self.aof.append_batch(records).await
+    }
+
+    /// Feeds `records` to replicas, followers and the write-behind sink;
+    /// called with the shard locks still held, as `log` does.
+    fn sequence(&self, records: &[LogRecord]) {
+        self.dirty
+            .fetch_add(records.len() as u64, Ordering::Relaxed);
+        if let Some(feed) = &self.replication {
+            for record in records {
+                feed.append_record(record);
+            }
+        }
+        if let Some(changes) = &self.changes {
+            for record in records {
+                changes.record(record);
+            }
+        }
+        if let Some(sink) = &self.write_behind {
+            for record in records {
+                sink.push(record);
+            }
+        }
+    }
+
+    pub fn changes_since_last_save(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    /// Whether any save rule is met. Until the first save, time counts from
+    /// `started_epoch_sec`, as Redis counts from server start.
+    pub fn save_due(&self, rules: &[SaveRule], started_epoch_sec: u64) -> bool {
+        let dirty = self.changes_since_last_save();
+        if dirty == 0 {
+            return false;
+        }
+        let last_save = self
+            .last_snapshot_epoch_sec
+            .load(Ordering::SeqCst)
+            .max(started_epoch_sec);
+        let elapsed = (now_ms() / 1000).saturating_sub(last_save);
+        rules
+            .iter()
+            .any(|rule| dirty >= rule.changes && elapsed >= rule.seconds)
+    }
+
     fn shard_idx(&self, key: &[u8]) -> usize {
         shard_index(key, self.shard_count)
     }
 
+    /// Drops `key`, found past its expiry by a command; active expiration
+    /// reports the keys it removes the same way.
+    fn remove_expired(&self, shard: &mut IndexedShard, key: &[u8]) {
+        shard.remove(key);
+        self.expired(key);
+    }
+
+    fn expired(&self, key: &[u8]) {
+        if let Some(webhook) = &self.expiry_webhook {
+            webhook.expired(key);
+        }
+        if let Some(changes) = &self.changes {
+            changes.expired(key);
+        }
+    }
+
+    /// Positions of `keys` grouped by shard, so a multi-key command locks each
+    /// shard it touches once. Positions stay in argument order within a shard.
+    fn group_by_shard<'a>(&self, keys: impl IntoIterator<Item = &'a [u8]>) -> Vec<Vec<usize>> {
+        let mut groups = vec![Vec::new(); self.shard_count];
+        for (pos, key) in keys.into_iter().enumerate() {
+            groups[self.shard_idx(key)].push(pos);
+        }
+        groups
+    }
+
     async fn load_snapshot(&self) -> Result<(), Box<dyn std::error::Error>> {
         let Some(path) = &self.snapshot_path else {
             return Ok(());
@@ -119,17 +516,15 @@ impl Store {
             return Ok(());
         }
 
-        let entries = read_snapshot(path)?;
+        let entries = read_snapshot(path, self.aof.keyring().as_deref())?;
         for shard in self.shards.iter() {
             shard.write().await.clear();
         }
-        for (key, value, expires_at) in entries {
+        for (key, kind, value, expires_at) in entries {
             if !is_expired(expires_at) {
+                let entry = ValueEntry::decode(kind, value.into(), expires_at)?;
                 let idx = self.shard_idx(&key);
-                self.shards[idx]
-                    .write()
-                    .await
-                    .insert(key, ValueEntry { value, expires_at });
+                self.shards[idx].write().await.insert(key, entry);
             }
         }
         Ok(())
@@ -141,15 +536,14 @@ impl Store {
             match record {
                 LogRecord::Set {
                     key,
+                    kind,
                     value,
                     expires_at,
                 } => {
                     if !is_expired(expires_at) {
+                        let entry = ValueEntry::decode(kind, value, expires_at)?;
                         let idx = self.shard_idx(&key);
-                        self.shards[idx]
-                            .write()
-                            .await
-                            .insert(key, ValueEntry { value, expires_at });
+                        self.shards[idx].write().await.insert(key, entry);
                     }
                 }
                 LogRecord::Del { key } => {
@@ -158,71 +552,148 @@ impl Store {
                 }
                 LogRecord::Expire { key, expires_at } => {
                     let idx = self.shard_idx(&key);
-                    if let Some(entry) = self.shards[idx].write().await.get_mut(&key) {
-                        entry.expires_at = Some(expires_at);
-                    }
+                    self.shards[idx]
+                        .write()
+                        .await
+                        .set_expiry(&key, Some(expires_at));
                 }
                 LogRecord::Persist { key } => {
                     let idx = self.shard_idx(&key);
-                    if let Some(entry) = self.shards[idx].write().await.get_mut(&key) {
-                        entry.expires_at = None;
-                    }
+                    self.shards[idx].write().await.set_expiry(&key, None);
+                }
+                LogRecord::TsAdd {
+                    key,
+                    timestamp,
+                    value,
+                } => {
+                    let idx = self.shard_idx(&key);
+                    let mut shard = self.shards[idx].write().await;
+                    // The write was checked when it was logged; a series that
+                    // expired meanwhile is gone and the sample with it.
+                    let _ = add_sample(&mut shard, &key, timestamp, value);
                 }
             }
         }
         Ok(())
     }
 
-    pub async fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+    /// A single read-lock lookup; the value is a shared handle, so nothing is
+    /// copied. A key past its expiry reads as missing and is left for active
+    /// expiration to remove rather than upgrading to the write lock here.
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Bytes>, WrongType> {
         let idx = self.shard_idx(key);
-        {
-            let shard = self.shards[idx].read().await;
-            if let Some(entry) = shard.get(key) {
-                if !is_expired(entry.expires_at) {
-                    return Some(entry.value.clone());
-                }
-            } else {
-                return None;
-            }
-        }
+        let shard = self.shards[idx].read().await;
+        let Some(entry) = shard.get(key).filter(|entry| !is_expired(entry.expires_at)) else {
+            return Ok(None);
+        };
+        string_value(&entry).map(Some)
+    }
 
-        let mut shard = self.shards[idx].write().await;
-        if let Some(entry) = shard.get(key) {
-            if is_expired(entry.expires_at) {
-                shard.remove(key);
-                return None;
+    /// `MGET`: one read lock per shard touched. Like `get`, expired keys read
+    /// as missing and are left to active expiration; so do JSON documents.
+    pub async fn mget(&self, keys: &[Vec<u8>]) -> Vec<Option<Bytes>> {
+        let mut values = vec![None; keys.len()];
+        let groups = self.group_by_shard(keys.iter().map(Vec::as_slice));
+        for (idx, positions) in groups.iter().enumerate() {
+            if positions.is_empty() {
+                continue;
+            }
+            let shard = self.shards[idx].read().await;
+            for &pos in positions {
+                values[pos] = shard
+                    .get(&keys[pos])
+                    .filter(|entry| {
+                        !is_expired(entry.expires_at) && entry.kind == ValueType::String
+                    })
+                    .map(|entry| entry.value.clone());
             }
-            return Some(entry.value.clone());
         }
-        None
+        values
     }
 
-    pub async fn getdel(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    pub async fn getdel(&self, key: &[u8]) -> Result<Option<Bytes>, Box<dyn std::error::Error>> {
         let idx = self.shard_idx(key);
         let mut shard = self.shards[idx].write().await;
         let value = if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 None
             } else {
-                let value = entry.value.clone();
+                let value = string_value(&entry)?;
                 shard.remove(key);
                 Some(value)
             }
         } else {
             None
         };
-        drop(shard);
-
         if value.is_some() {
-            self.aof
-                .append(LogRecord::Del { key: key.to_vec() })
+            self.log(shard, LogRecord::Del { key: key.to_vec() })
                 .await?;
         }
 
         Ok(value)
     }
 
+    /// A key's kind, value and absolute expiry in ms, for `DUMP` and
+    /// migrations. A JSON document's value is its text.
+    pub async fn get_with_expiry(&self, key: &[u8]) -> Option<(ValueType, Bytes, Option<u64>)> {
+        let idx = self.shard_idx(key);
+        let shard = self.shards[idx].read().await;
+        shard
+            .get(key)
+            .filter(|entry| !is_expired(entry.expires_at))
+            .map(|entry| (entry.kind(), entry.value.clone(), entry.expires_at))
+    }
+
+    /// Like `get_with_expiry`, with the key's version as well.
+    pub async fn get_with_version(
+        &self,
+        key: &[u8],
+    ) -> Option<(ValueType, Bytes, Option<u64>, u64)> {
+        let idx = self.shard_idx(key);
+        let shard = self.shards[idx].read().await;
+        shard
+            .get(key)
+            .filter(|entry| !is_expired(entry.expires_at))
+            .map(|entry| {
+                (
+                    entry.kind(),
+                    entry.value.clone(),
+                    entry.expires_at,
+                    entry.version,
+                )
+            })
+    }
+
+    /// The version of `key`, raised by every write to it; `None` when it is
+    /// missing.
+    pub async fn version(&self, key: &[u8]) -> Option<u64> {
+        let idx = self.shard_idx(key);
+        let shard = self.shards[idx].read().await;
+        shard
+            .get(key)
+            .filter(|entry| !is_expired(entry.expires_at))
+            .map(|entry| entry.version)
+    }
+
+    /// Deletes `key` only if it still holds `value`: a key written to while it
+    /// was being copied elsewhere stays, to be copied again.
+    pub async fn del_if_unchanged(
+        &self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        if shard.get(key).is_none_or(|entry| entry.value != *value) {
+            return Ok(false);
+        }
+        shard.remove(key);
+        self.log(shard, LogRecord::Del { key: key.to_vec() })
+            .await?;
+        Ok(true)
+    }
+
     pub async fn set(
         &self,
         key: Vec<u8>,
@@ -230,12 +701,27 @@ impl Store {
         expires_at: Option<u64>,
         condition: SetCondition,
     ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.set_typed(key, ValueType::String, value, expires_at, condition)
+            .await
+    }
+
+    /// `set` of a value of any kind the keyspace holds, as `RESTORE` writes
+    /// it; a JSON document is given as its text.
+    pub async fn set_typed(
+        &self,
+        key: Vec<u8>,
+        kind: ValueType,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+        condition: SetCondition,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let entry = ValueEntry::decode(kind, value.into(), expires_at)?;
         let idx = self.shard_idx(&key);
         let mut shard = self.shards[idx].write().await;
 
         let exists = if let Some(entry) = shard.get(&key) {
             if is_expired(entry.expires_at) {
-                shard.remove(&key);
+                self.remove_expired(&mut shard, &key);
                 false
             } else {
                 true
@@ -254,25 +740,66 @@ impl Store {
             return Ok(false);
         }
 
-        shard.insert(
-            key.clone(),
-            ValueEntry {
-                value: value.clone(),
-                expires_at,
-            },
-        );
-        drop(shard);
-
-        self.aof
-            .append(LogRecord::Set {
+        let value = entry.value.clone();
+        shard.insert(key.clone(), entry);
+        self.log(
+            shard,
+            LogRecord::Set {
                 key,
+                kind,
                 value,
                 expires_at,
-            })
-            .await?;
+            },
+        )
+        .await?;
         Ok(true)
     }
 
+    /// `MSET`: one write lock per shard touched and one AOF write for all
+    /// pairs. Later pairs win over earlier ones for the same key.
+    pub async fn mset(
+        &self,
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let pairs: Vec<(Vec<u8>, Bytes)> = pairs
+            .into_iter()
+            .map(|(key, value)| (key, Bytes::from(value)))
+            .collect();
+        let groups = self.group_by_shard(pairs.iter().map(|(key, _)| key.as_slice()));
+        let mut replaced = Vec::new();
+        // Locked in shard order, like every command that holds several.
+        let mut locked = Vec::new();
+        for (idx, positions) in groups.iter().enumerate() {
+            if positions.is_empty() {
+                continue;
+            }
+            let mut shard = self.shards[idx].write().await;
+            for &pos in positions {
+                let (key, value) = &pairs[pos];
+                replaced.extend(shard.insert(key.clone(), ValueEntry::string(value.clone(), None)));
+            }
+            locked.push(shard);
+        }
+
+        let logged = self
+            .log_batch(
+                locked,
+                pairs
+                    .into_iter()
+                    .map(|(key, value)| LogRecord::Set {
+                        key,
+                        kind: ValueType::String,
+                        value,
+                        expires_at: None,
+                    })
+                    .collect(),
+            )
+            .await;
+        // Overwritten values are freed here, with no shard locked.
+        drop(replaced);
+        logged
+    }
+
     pub async fn msetnx(
         &self,
         pairs: &[(Vec<u8>, Vec<u8>)],
@@ -284,51 +811,153 @@ impl Store {
             let mut shard = self.shards[idx].write().await;
             if let Some(entry) = shard.get(key) {
                 if is_expired(entry.expires_at) {
-                    shard.remove(key);
+                    self.remove_expired(&mut shard, key);
                 } else {
                     return Ok(false);
                 }
             }
         }
 
-        for (key, value) in pairs {
-            let idx = self.shard_idx(key);
-            self.shards[idx].write().await.insert(
-                key.clone(),
-                ValueEntry {
-                    value: value.clone(),
-                    expires_at: None,
-                },
-            );
+        let values: Vec<Bytes> = pairs
+            .iter()
+            .map(|(_, value)| Bytes::copy_from_slice(value))
+            .collect();
+        let mut locked = Vec::new();
+        for (idx, positions) in self
+            .group_by_shard(pairs.iter().map(|(key, _)| key.as_slice()))
+            .iter()
+            .enumerate()
+        {
+            if positions.is_empty() {
+                continue;
+            }
+            let mut shard = self.shards[idx].write().await;
+            for &pos in positions {
+                shard.insert(
+                    pairs[pos].0.clone(),
+                    ValueEntry::string(values[pos].clone(), None),
+                );
+            }
+            locked.push(shard);
         }
 
-        for (key, value) in pairs {
-            self.aof
-                .append(LogRecord::Set {
+        self.log_batch(
+            locked,
+            pairs
+                .iter()
+                .zip(values)
+                .map(|((key, _), value)| LogRecord::Set {
                     key: key.clone(),
-                    value: value.clone(),
+                    kind: ValueType::String,
+                    value,
                     expires_at: None,
                 })
-                .await?;
-        }
+                .collect(),
+        )
+        .await?;
 
         Ok(true)
     }
 
+    /// With soft delete on, the keys are kept as tombstones for `UNDELETE`.
     pub async fn del(&self, keys: &[Vec<u8>]) -> Result<i64, Box<dyn std::error::Error>> {
-        let mut removed = 0_i64;
-        for key in keys {
-            let idx = self.shard_idx(key);
-            if self.shards[idx].write().await.remove(key).is_some() {
-                removed += 1;
+        self.remove_keys(keys, false, true).await
+    }
+
+    /// `DEL` that hands big values to the lazyfree thread instead of freeing
+    /// them while the command runs.
+    pub async fn unlink(&self, keys: &[Vec<u8>]) -> Result<i64, Box<dyn std::error::Error>> {
+        self.remove_keys(keys, true, true).await
+    }
+
+    /// `DEL` that never keeps tombstones, for local copies of keys another
+    /// server holds.
+    pub(crate) async fn forget(&self, keys: &[Vec<u8>]) -> Result<i64, Box<dyn std::error::Error>> {
+        self.remove_keys(keys, false, false).await
+    }
+
+    async fn remove_keys(
+        &self,
+        keys: &[Vec<u8>],
+        lazy: bool,
+        bury: bool,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let tombstones = self.tombstones.as_deref().filter(|_| bury);
+        let mut deleted = Vec::new();
+        let mut removed = Vec::new();
+        let mut locked = Vec::new();
+        for (idx, positions) in self
+            .group_by_shard(keys.iter().map(Vec::as_slice))
+            .iter()
+            .enumerate()
+        {
+            if positions.is_empty() {
+                continue;
+            }
+            let mut shard = self.shards[idx].write().await;
+            for &pos in positions {
+                let Some(entry) = shard.remove(&keys[pos]) else {
+                    continue;
+                };
+                if is_expired(entry.expires_at) {
+                    // Already gone for clients: dropped as a lazy expiry, not
+                    // counted or logged as a deletion.
+                    self.expired(&keys[pos]);
+                    removed.push(entry);
+                    continue;
+                }
+                deleted.push(LogRecord::Del {
+                    key: keys[pos].clone(),
+                });
+                // Buried under the shard lock, so `UNDELETE` always finds the
+                // key in one place or the other.
+                match tombstones {
+                    Some(tombstones) => {
+                        removed.extend(tombstones.bury(&keys[pos], entry, now_ms()));
+                    }
+                    None => removed.push(entry),
+                }
             }
+            locked.push(shard);
         }
 
-        for key in keys {
-            self.aof.append(LogRecord::Del { key: key.clone() }).await?;
+        let count = deleted.len() as i64;
+        let logged = self.log_batch(locked, deleted).await;
+        // Freed with no shard locked, on the lazyfree thread if asked to.
+        if lazy {
+            for entry in removed {
+                self.lazy_free.value(entry.value);
+            }
         }
+        logged?;
+        Ok(count)
+    }
 
-        Ok(removed)
+    /// Brings back the key `DEL` or `UNLINK` removed last as `key`, with the
+    /// value and expiry it had, while its tombstone lasts.
+    pub async fn undelete(&self, key: &[u8]) -> Result<Undelete, Box<dyn std::error::Error>> {
+        let Some(tombstones) = &self.tombstones else {
+            return Ok(Undelete::Missing);
+        };
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        match shard.get(key).map(|entry| is_expired(entry.expires_at)) {
+            Some(true) => self.remove_expired(&mut shard, key),
+            Some(false) => return Ok(Undelete::Exists),
+            None => {}
+        }
+        let Some(entry) = tombstones.dig_up(key, now_ms()) else {
+            return Ok(Undelete::Missing);
+        };
+        let record = LogRecord::Set {
+            key: key.to_vec(),
+            kind: entry.kind(),
+            value: entry.value.clone(),
+            expires_at: entry.expires_at,
+        };
+        shard.insert(key.to_vec(), entry);
+        self.log(shard, record).await?;
+        Ok(Undelete::Restored)
     }
 
     pub async fn exists(&self, keys: &[Vec<u8>]) -> i64 {
@@ -349,7 +978,7 @@ impl Store {
             let mut shard = self.shards[idx].write().await;
             if let Some(entry) = shard.get(key) {
                 if is_expired(entry.expires_at) {
-                    shard.remove(key);
+                    self.remove_expired(&mut shard, key);
                 } else {
                     count += 1;
                 }
@@ -392,19 +1021,20 @@ impl Store {
     ) -> Result<bool, Box<dyn std::error::Error>> {
         let idx = self.shard_idx(key);
         let mut shard = self.shards[idx].write().await;
-        if let Some(entry) = shard.get_mut(key) {
-            if is_expired(entry.expires_at) {
-                shard.remove(key);
+        if let Some(current) = shard.get(key).map(|entry| entry.expires_at) {
+            if is_expired(current) {
+                self.remove_expired(&mut shard, key);
                 return Ok(false);
             }
-            entry.expires_at = Some(expires_at);
-            drop(shard);
-            self.aof
-                .append(LogRecord::Expire {
+            shard.set_expiry(key, Some(expires_at));
+            self.log(
+                shard,
+                LogRecord::Expire {
                     key: key.to_vec(),
                     expires_at,
-                })
-                .await?;
+                },
+            )
+            .await?;
             return Ok(true);
         }
         Ok(false)
@@ -413,18 +1043,16 @@ impl Store {
     pub async fn persist(&self, key: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
         let idx = self.shard_idx(key);
         let mut shard = self.shards[idx].write().await;
-        if let Some(entry) = shard.get_mut(key) {
-            if is_expired(entry.expires_at) {
-                shard.remove(key);
+        if let Some(current) = shard.get(key).map(|entry| entry.expires_at) {
+            if is_expired(current) {
+                self.remove_expired(&mut shard, key);
                 return Ok(false);
             }
-            if entry.expires_at.is_none() {
+            if current.is_none() {
                 return Ok(false);
             }
-            entry.expires_at = None;
-            drop(shard);
-            self.aof
-                .append(LogRecord::Persist { key: key.to_vec() })
+            shard.set_expiry(key, None);
+            self.log(shard, LogRecord::Persist { key: key.to_vec() })
                 .await?;
             return Ok(true);
         }
@@ -436,13 +1064,13 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 return -2;
             }
             if let Some(exp) = entry.expires_at {
                 let now = now_ms();
                 if exp <= now {
-                    shard.remove(key);
+                    self.remove_expired(&mut shard, key);
                     return -2;
                 }
                 return ((exp - now) / 1000) as i64;
@@ -457,13 +1085,13 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 return -2;
             }
             if let Some(exp) = entry.expires_at {
                 let now = now_ms();
                 if exp <= now {
-                    shard.remove(key);
+                    self.remove_expired(&mut shard, key);
                     return -2;
                 }
                 return (exp - now) as i64;
@@ -478,9 +1106,12 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         let (current, expires_at) = if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 (0_i64, None)
             } else {
+                if entry.kind != ValueType::String {
+                    return Err(IncrByError::WrongType);
+                }
                 let parsed = std::str::from_utf8(&entry.value)
                     .ok()
                     .and_then(|v| v.parse::<i64>().ok())
@@ -492,66 +1123,234 @@ impl Store {
         };
 
         let next = current.checked_add(amount).ok_or(IncrByError::OutOfRange)?;
-        let next_bytes = next.to_string().into_bytes();
+        let next_bytes = Bytes::from(next.to_string());
         shard.insert(
             key.to_vec(),
-            ValueEntry {
-                value: next_bytes.clone(),
-                expires_at,
-            },
+            ValueEntry::string(next_bytes.clone(), expires_at),
         );
-        drop(shard);
-
-        self.aof
-            .append(LogRecord::Set {
+        self.log(
+            shard,
+            LogRecord::Set {
                 key: key.to_vec(),
+                kind: ValueType::String,
                 value: next_bytes,
                 expires_at,
-            })
-            .await
-            .map_err(|_| IncrByError::Internal)?;
+            },
+        )
+        .await
+        .map_err(|_| IncrByError::Internal)?;
 
         Ok(next)
     }
 
-    pub async fn metrics(&self) -> StoreMetrics {
-        let mut expiring = 0_usize;
-        let mut memory = 0_usize;
-        let mut keys = 0_usize;
-
-        for shard in self.shards.iter() {
-            let map = shard.read().await;
-            keys += map.len();
-            for (key, entry) in map.iter() {
-                if entry.expires_at.is_some() {
-                    expiring += 1;
-                }
-                memory = memory
-                    .saturating_add(key.len())
-                    .saturating_add(entry.value.len())
-                    .saturating_add(std::mem::size_of::<ValueEntry>());
+    /// `THROTTLE`: charges `quantity` requests to the rate limit at `key`.
+    /// The key holds the limit's theoretical arrival time in microseconds, as
+    /// a string that expires once the limit is back to a full burst; a
+    /// refused request leaves it as it was.
+    pub async fn throttle(
+        &self,
+        key: &[u8],
+        gcra: &Gcra,
+        quantity: u64,
+        now_us: u64,
+    ) -> Result<Decision, IncrByError> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        let tat = match shard.get(key) {
+            Some(entry) if !is_expired(entry.expires_at) => {
+                if entry.kind != ValueType::String {
+                    return Err(IncrByError::WrongType);
+                }
+                let tat = std::str::from_utf8(&entry.value)
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .ok_or(IncrByError::NotInteger)?;
+                Some(tat)
+            }
+            _ => None,
+        };
+
+        let decision = gcra.apply(tat, now_us, quantity);
+        let Some(tat) = decision.tat else {
+            return Ok(decision);
+        };
+        let value = Bytes::from(tat.to_string());
+        let expires_at = Some(tat.div_ceil(1000));
+        shard.insert(key.to_vec(), ValueEntry::string(value.clone(), expires_at));
+        self.log(
+            shard,
+            LogRecord::Set {
+                key: key.to_vec(),
+                kind: ValueType::String,
+                value,
+                expires_at,
+            },
+        )
+        .await
+        .map_err(|_| IncrByError::Internal)?;
+        Ok(decision)
+    }
+
+    /// `LOCK`: takes the lease at `key` for `ttl_ms` unless a live key is
+    /// there and returns its fencing token: the time in microseconds, or one
+    /// above the token of the lease that expired there if that is higher. The
+    /// key holds the token as a string until the lease expires or is
+    /// released, so leases persist and replicate like `SET`, and the next
+    /// token follows the last one even after a restart or a clock step back.
+    pub async fn lock(
+        &self,
+        key: &[u8],
+        ttl_ms: u64,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        let mut last_token = 0;
+        match shard.get(key) {
+            Some(entry) if is_expired(entry.expires_at) => {
+                last_token = lease_token(&entry).unwrap_or(0);
+                self.remove_expired(&mut shard, key);
             }
+            Some(_) => return Ok(None),
+            None => {}
+        }
+        let token = last_token.saturating_add(1).max(now_us());
+        let value = Bytes::from(token.to_string());
+        let expires_at = Some(now_ms().saturating_add(ttl_ms));
+        shard.insert(key.to_vec(), ValueEntry::string(value.clone(), expires_at));
+        self.log(
+            shard,
+            LogRecord::Set {
+                key: key.to_vec(),
+                kind: ValueType::String,
+                value,
+                expires_at,
+            },
+        )
+        .await?;
+        Ok(Some(token))
+    }
+
+    /// `UNLOCK`: releases the lease at `key` if `token` still holds it.
+    pub async fn unlock(&self, key: &[u8], token: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        if !holds_lease(&shard, key, token) {
+            return Ok(false);
+        }
+        shard.remove(key);
+        self.log(shard, LogRecord::Del { key: key.to_vec() })
+            .await?;
+        Ok(true)
+    }
+
+    /// `EXTEND`: makes the lease at `key` last `ttl_ms` from now if `token`
+    /// still holds it.
+    pub async fn extend(
+        &self,
+        key: &[u8],
+        token: u64,
+        ttl_ms: u64,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        if !holds_lease(&shard, key, token) {
+            return Ok(false);
+        }
+        let expires_at = now_ms().saturating_add(ttl_ms);
+        shard.set_expiry(key, Some(expires_at));
+        self.log(
+            shard,
+            LogRecord::Expire {
+                key: key.to_vec(),
+                expires_at,
+            },
+        )
+        .await?;
+        Ok(true)
+    }
+
+    pub async fn metrics(&self) -> StoreMetrics {
+        let mut expiring = 0_usize;
+        let mut keys = 0_usize;
+        let mut usage = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            let map = shard.read().await;
+            keys += map.len();
+            expiring += map.volatile_len();
+            usage.push(map.memory_usage());
         }
+        let mut usage: MemoryUsage = usage.into_iter().sum();
+        usage.dataset = usage.dataset.saturating_sub(self.spilled_bytes());
 
         StoreMetrics {
             keys,
             expiring_keys: expiring,
-            approx_memory_bytes: memory,
+            approx_memory_bytes: usage.total(),
+            dataset_bytes: usage.dataset,
+            overhead_bytes: usage.overhead + usage.expires,
+            expires_overhead_bytes: usage.expires,
+            peak_memory_bytes: self.memory.peak().max(usage.total()),
         }
     }
 
-    pub async fn cleanup_expired(&self) {
-        let now = now_ms();
-        for shard in self.shards.iter() {
-            shard
-                .write()
-                .await
-                .retain(|_, v| v.expires_at.is_none_or(|exp| exp > now));
+    /// Memory used by the keyspace, without taking any shard lock: what
+    /// `maxmemory` is enforced against.
+    pub fn used_memory(&self) -> usize {
+        self.memory.used().saturating_sub(self.spilled_bytes())
+    }
+
+    /// The tiered engine's spill counters; `None` with the other engines.
+    pub fn tier_stats(&self) -> Option<&TierStats> {
+        self.tier.as_deref()
+    }
+
+    fn spilled_bytes(&self) -> usize {
+        self.tier.as_ref().map_or(0, |tier| tier.spilled_bytes())
+    }
+
+    /// One round of active expiration: each shard pops the keys whose expiry
+    /// has passed from its expiry index, in batches so the shard lock is not
+    /// held for long, until `budget` is spent. The next round resumes at the
+    /// shard this one stopped at. Keys nobody reads are reclaimed this way;
+    /// the rest also expire lazily on access. Returns how many keys were
+    /// removed.
+    pub async fn expire_cycle(&self, budget: Duration) -> usize {
+        let started = Instant::now();
+        if let Some(tombstones) = &self.tombstones {
+            for entry in tombstones.purge(now_ms()) {
+                self.lazy_free.value(entry.value);
+            }
+        }
+        let first = self.expire_cursor.load(Ordering::Relaxed);
+        let mut removed = 0;
+        let mut expired = Vec::with_capacity(EXPIRE_BATCH);
+        for offset in 0..self.shard_count {
+            let idx = (first + offset) % self.shard_count;
+            loop {
+                let mut shard = self.shards[idx].write().await;
+                let more = shard.expire_due(now_ms(), EXPIRE_BATCH, &mut expired);
+                removed += expired.len();
+                // Reported before the lock is released, so no later write to
+                // the key reaches the change feed ahead of its expiry.
+                for (key, value) in expired.drain(..) {
+                    self.expired(&key);
+                    self.lazy_free.value(value);
+                }
+                drop(shard);
+                if !more || started.elapsed() >= budget {
+                    break;
+                }
+            }
+            if started.elapsed() >= budget {
+                self.expire_cursor.store(idx, Ordering::Relaxed);
+                return removed;
+            }
         }
+        removed
     }
 
+    /// Like Redis' `DBSIZE`, counts keys that expired but were not reclaimed yet.
     pub async fn dbsize(&self) -> i64 {
-        self.cleanup_expired().await;
         let mut total = 0_i64;
         for shard in self.shards.iter() {
             total += shard.read().await.len() as i64;
@@ -564,10 +1363,10 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 return "none";
             }
-            return "string";
+            return type_name(entry.kind());
         }
         "none"
     }
@@ -577,14 +1376,10 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 return None;
             }
-            let bytes = key
-                .len()
-                .saturating_add(entry.value.len())
-                .saturating_add(std::mem::size_of::<ValueEntry>());
-            return Some(bytes as i64);
+            return Some(MemoryUsage::of(key, &entry).total() as i64);
         }
         None
     }
@@ -594,7 +1389,7 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 return None;
             }
             return Some("raw");
@@ -602,17 +1397,17 @@ impl Store {
         None
     }
 
-    pub async fn strlen(&self, key: &[u8]) -> i64 {
+    pub async fn strlen(&self, key: &[u8]) -> Result<i64, WrongType> {
         let idx = self.shard_idx(key);
         let mut shard = self.shards[idx].write().await;
         if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
-                return 0;
+                self.remove_expired(&mut shard, key);
+                return Ok(0);
             }
-            return entry.value.len() as i64;
+            return Ok(string_value(&entry)?.len() as i64);
         }
-        0
+        Ok(0)
     }
 
     pub async fn append(
@@ -624,10 +1419,10 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         let (mut value, expires_at) = if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 (Vec::new(), None)
             } else {
-                (entry.value.clone(), entry.expires_at)
+                (string_value(&entry)?.to_vec(), entry.expires_at)
             }
         } else {
             (Vec::new(), None)
@@ -635,39 +1430,35 @@ impl Store {
 
         value.extend_from_slice(suffix);
         let new_len = value.len() as i64;
-        shard.insert(
-            key.to_vec(),
-            ValueEntry {
-                value: value.clone(),
-                expires_at,
-            },
-        );
-        drop(shard);
-
-        self.aof
-            .append(LogRecord::Set {
+        let value = Bytes::from(value);
+        shard.insert(key.to_vec(), ValueEntry::string(value.clone(), expires_at));
+        self.log(
+            shard,
+            LogRecord::Set {
                 key: key.to_vec(),
+                kind: ValueType::String,
                 value,
                 expires_at,
-            })
-            .await?;
+            },
+        )
+        .await?;
 
         Ok(new_len)
     }
 
-    pub async fn getrange(&self, key: &[u8], start: i64, end: i64) -> Vec<u8> {
+    pub async fn getrange(&self, key: &[u8], start: i64, end: i64) -> Result<Bytes, WrongType> {
         let idx = self.shard_idx(key);
         let mut shard = self.shards[idx].write().await;
         let Some(entry) = shard.get(key) else {
-            return Vec::new();
+            return Ok(Bytes::new());
         };
 
         if is_expired(entry.expires_at) {
-            shard.remove(key);
-            return Vec::new();
+            self.remove_expired(&mut shard, key);
+            return Ok(Bytes::new());
         }
 
-        slice_range(&entry.value, start, end)
+        Ok(slice_range(&string_value(&entry)?, start, end))
     }
 
     pub async fn setrange(
@@ -680,10 +1471,10 @@ impl Store {
         let mut shard = self.shards[idx].write().await;
         let (mut current, expires_at) = if let Some(entry) = shard.get(key) {
             if is_expired(entry.expires_at) {
-                shard.remove(key);
+                self.remove_expired(&mut shard, key);
                 (Vec::new(), None)
             } else {
-                (entry.value.clone(), entry.expires_at)
+                (string_value(&entry)?.to_vec(), entry.expires_at)
             }
         } else {
             (Vec::new(), None)
@@ -697,23 +1488,22 @@ impl Store {
         }
         current[offset..offset + value.len()].copy_from_slice(value);
         let new_len = current.len() as i64;
+        let current = Bytes::from(current);
 
         shard.insert(
             key.to_vec(),
-            ValueEntry {
-                value: current.clone(),
-                expires_at,
-            },
+            ValueEntry::string(current.clone(), expires_at),
         );
-        drop(shard);
-
-        self.aof
-            .append(LogRecord::Set {
+        self.log(
+            shard,
+            LogRecord::Set {
                 key: key.to_vec(),
+                kind: ValueType::String,
                 value: current,
                 expires_at,
-            })
-            .await?;
+            },
+        )
+        .await?;
 
         Ok(new_len)
     }
@@ -722,36 +1512,32 @@ impl Store {
         &self,
         key: Vec<u8>,
         value: Vec<u8>,
-    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Bytes>, Box<dyn std::error::Error>> {
         let idx = self.shard_idx(&key);
         let mut shard = self.shards[idx].write().await;
         let previous = if let Some(entry) = shard.get(&key) {
             if is_expired(entry.expires_at) {
-                shard.remove(&key);
+                self.remove_expired(&mut shard, &key);
                 None
             } else {
-                Some(entry.value.clone())
+                Some(string_value(&entry)?)
             }
         } else {
             None
         };
 
-        shard.insert(
-            key.clone(),
-            ValueEntry {
-                value: value.clone(),
-                expires_at: None,
-            },
-        );
-        drop(shard);
-
-        self.aof
-            .append(LogRecord::Set {
+        let value = Bytes::from(value);
+        shard.insert(key.clone(), ValueEntry::string(value.clone(), None));
+        self.log(
+            shard,
+            LogRecord::Set {
                 key,
+                kind: ValueType::String,
                 value,
                 expires_at: None,
-            })
-            .await?;
+            },
+        )
+        .await?;
 
         Ok(previous)
     }
@@ -760,26 +1546,29 @@ impl Store {
         &self,
         key: &[u8],
         mode: GetExMode,
-    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Bytes>, Box<dyn std::error::Error>> {
         let idx = self.shard_idx(key);
         let mut shard = self.shards[idx].write().await;
-        let Some(entry) = shard.get_mut(key) else {
+        let Some((value, current)) = shard
+            .get(key)
+            .map(|entry| (string_value(&entry), entry.expires_at))
+        else {
             return Ok(None);
         };
 
-        if is_expired(entry.expires_at) {
-            shard.remove(key);
+        if is_expired(current) {
+            self.remove_expired(&mut shard, key);
             return Ok(None);
         }
+        let value = value?;
 
-        let value = entry.value.clone();
         let key_owned = key.to_vec();
         let mut log_record = None;
         match mode {
             GetExMode::None => {}
             GetExMode::Ex(seconds) => {
                 let expires_at = now_ms().saturating_add(seconds.saturating_mul(1000));
-                entry.expires_at = Some(expires_at);
+                shard.set_expiry(key, Some(expires_at));
                 log_record = Some(LogRecord::Expire {
                     key: key_owned,
                     expires_at,
@@ -787,45 +1576,47 @@ impl Store {
             }
             GetExMode::Px(milliseconds) => {
                 let expires_at = now_ms().saturating_add(milliseconds);
-                entry.expires_at = Some(expires_at);
+                shard.set_expiry(key, Some(expires_at));
                 log_record = Some(LogRecord::Expire {
                     key: key_owned,
                     expires_at,
                 });
             }
             GetExMode::Persist => {
-                entry.expires_at = None;
+                shard.set_expiry(key, None);
                 log_record = Some(LogRecord::Persist { key: key_owned });
             }
         }
-        drop(shard);
-
         if let Some(record) = log_record {
-            self.aof.append(record).await?;
+            self.log(shard, record).await?;
         }
 
         Ok(Some(value))
     }
 
     pub async fn keys(&self, pattern: &[u8]) -> Vec<Vec<u8>> {
-        self.cleanup_expired().await;
-
+        let now = now_ms();
         let mut out = Vec::new();
         for shard in self.shards.iter() {
-            let map = shard.read().await;
-            out.extend(map.keys().filter(|k| glob_match(pattern, k)).cloned());
+            shard.read().await.for_each(&mut |key, entry| {
+                if entry.expires_at.is_none_or(|at| at > now) && glob_match(pattern, key) {
+                    out.push(key.to_vec());
+                }
+            });
         }
         out.sort();
         out
     }
 
     pub async fn scan(&self, cursor: u64, pattern: &[u8], count: usize) -> ScanResult {
-        self.cleanup_expired().await;
-
+        let now = now_ms();
         let mut keys = Vec::new();
         for shard in self.shards.iter() {
-            let map = shard.read().await;
-            keys.extend(map.keys().filter(|k| glob_match(pattern, k)).cloned());
+            shard.read().await.for_each(&mut |key, entry| {
+                if entry.expires_at.is_none_or(|at| at > now) && glob_match(pattern, key) {
+                    keys.push(key.to_vec());
+                }
+            });
         }
         keys.sort();
 
@@ -858,7 +1649,9 @@ impl Store {
 
         let store = self.clone();
         tokio::spawn(async move {
-            if store.rewrite_aof().await.is_ok() {
+            let rewritten = store.rewrite_aof().await.is_ok();
+            store.last_rewrite_ok.store(rewritten, Ordering::SeqCst);
+            if rewritten {
                 store.rewrite_count.fetch_add(1, Ordering::SeqCst);
                 store
                     .last_rewrite_epoch_sec
@@ -872,52 +1665,238 @@ impl Store {
     }
 
     async fn rewrite_aof(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let snapshot = {
-            self.cleanup_expired().await;
-            let mut entries = Vec::new();
-            for shard in self.shards.iter() {
-                let map = shard.read().await;
-                entries.extend(
-                    map.iter()
-                        .map(|(key, entry)| (key.clone(), entry.value.clone(), entry.expires_at)),
-                );
+        if self.sled.is_some() {
+            // The AOF is not written to while sled holds the data.
+            return Ok(());
+        }
+        let rewrite = self.aof.begin_rewrite().await?;
+        let frozen = self.freeze().await;
+        let rewrite = tokio::task::spawn_blocking(move || {
+            let mut rewrite = rewrite;
+            rewrite
+                .write_snapshot(frozen_entries(&frozen))
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>(rewrite)
+        })
+        .await??;
+        rewrite.commit().await
+    }
+
+    /// The keyspace as an RDB image, for a replica's full sync. Built in memory
+    /// from a frozen view, like encrypted snapshots.
+    pub async fn rdb_image(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let frozen = self.freeze().await;
+        let image = tokio::task::spawn_blocking(move || {
+            crate::rdb::write_rdb_to(Vec::new(), frozen_entries(&frozen)).map_err(|e| e.to_string())
+        })
+        .await??;
+        Ok(image)
+    }
+
+    /// Writes the live keys matching `pattern` to `name` in the export
+    /// directory, from a frozen view so writes go on meanwhile. Returns the
+    /// number of keys written.
+    pub async fn export(
+        &self,
+        name: &str,
+        format: ExportFormat,
+        pattern: Option<Vec<u8>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let dir = self
+            .export_dir
+            .clone()
+            .ok_or("exports are not configured")?;
+        if self
+            .export_in_progress
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err("an export is already in progress".into());
+        }
+        let frozen = self.freeze().await;
+        let path = dir.join(name);
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&dir)?;
+            write_export(&path, frozen_entries(&frozen), format, pattern.as_deref())
+        })
+        .await;
+        self.export_in_progress.store(false, Ordering::SeqCst);
+        Ok(result??)
+    }
+
+    /// Clones every shard map. Each clone is O(1) and holds the shard's read lock
+    /// only for that long; the result is a consistent per-shard view.
+    async fn freeze(&self) -> Vec<ShardMap> {
+        let mut frozen = Vec::with_capacity(self.shard_count);
+        for shard in self.shards.iter() {
+            frozen.push(shard.read().await.freeze());
+        }
+        frozen
+    }
+
+    /// Loads string keys from a Redis `dump.rdb` and rewrites the AOF so they are
+    /// durable. Returns how many keys were imported and how many were skipped.
+    pub async fn import_rdb(
+        &self,
+        path: &Path,
+    ) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+        let dump = crate::rdb::read_rdb(path)?;
+        let _guard = self.op_lock.lock().await;
+        let imported = self.insert_live(dump.entries).await?;
+        self.rewrite_aof().await?;
+        Ok((imported, dump.skipped))
+    }
+
+    /// Replaces the whole keyspace with `entries`, as a replica does on a full
+    /// resync, and rewrites the AOF to match. Returns how many keys were loaded.
+    pub async fn replace_all(
+        &self,
+        entries: Vec<SnapshotEntry>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let _guard = self.op_lock.lock().await;
+        for shard in self.shards.iter() {
+            shard.write().await.clear();
+        }
+        let loaded = self.insert_live(entries).await?;
+        self.rewrite_aof().await?;
+        Ok(loaded)
+    }
+
+    /// `FLUSHALL`: removes every key, rewrites the AOF to the empty keyspace
+    /// and tells replicas to flush too. With `lazy` each shard is swapped for
+    /// an empty one and the old contents are freed on the lazyfree thread, so
+    /// the shard locks are held only for the swap.
+    pub async fn flush(&self, lazy: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let _guard = self.op_lock.lock().await;
+        // Every shard stays locked until replicas, followers and the sink
+        // are told, so no write lands between the flush and its place in
+        // the feeds.
+        let mut locked = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            let mut shard = shard.write().await;
+            if lazy {
+                self.lazy_free.free(shard.detach());
+            } else {
+                shard.clear();
             }
-            entries
-        };
+            locked.push(shard);
+        }
+        if let Some(tombstones) = &self.tombstones {
+            self.lazy_free.free(tombstones.clear());
+        }
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        if let Some(feed) = &self.replication {
+            feed.flushall();
+        }
+        if let Some(changes) = &self.changes {
+            changes.flushall();
+        }
+        if let Some(sink) = &self.write_behind {
+            sink.flushall();
+        }
+        drop(locked);
+        if let Some(sink) = &self.write_behind {
+            sink.wait_for_room().await;
+        }
+        self.rewrite_aof().await
+    }
+
+    async fn insert_live(
+        &self,
+        entries: Vec<SnapshotEntry>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut inserted = 0;
+        for (key, kind, value, expires_at) in entries {
+            if !is_expired(expires_at) {
+                let entry = ValueEntry::decode(kind, value.into(), expires_at)?;
+                let idx = self.shard_idx(&key);
+                self.shards[idx].write().await.insert(key, entry);
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
 
-        self.aof.rewrite_from_snapshot(snapshot).await
+    /// Writes out everything buffered for the AOF and fsyncs it.
+    pub async fn sync_aof(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(db) = &self.sled {
+            db.flush_async().await?;
+        }
+        self.aof.sync().await
     }
 
     pub fn persistence_metrics(&self) -> PersistenceMetrics {
         PersistenceMetrics {
-            aof_enabled: true,
+            aof_enabled: self.sled.is_none(),
             rewrite_in_progress: self.rewrite_in_progress.load(Ordering::SeqCst),
             rewrite_count: self.rewrite_count.load(Ordering::SeqCst),
             rewrite_fail_count: self.rewrite_fail_count.load(Ordering::SeqCst),
+            last_rewrite_ok: self.last_rewrite_ok.load(Ordering::SeqCst),
             last_rewrite_epoch_sec: self.last_rewrite_epoch_sec.load(Ordering::SeqCst),
             snapshot_in_progress: self.snapshot_in_progress.load(Ordering::SeqCst),
             snapshot_count: self.snapshot_count.load(Ordering::SeqCst),
             snapshot_fail_count: self.snapshot_fail_count.load(Ordering::SeqCst),
+            last_snapshot_ok: self.last_snapshot_ok.load(Ordering::SeqCst),
             last_snapshot_epoch_sec: self.last_snapshot_epoch_sec.load(Ordering::SeqCst),
+            changes_since_last_save: self.changes_since_last_save(),
+            aof_pending_writes: self.aof.pending_writes(),
+            aof_last_fsync_age_ms: self.aof.last_fsync_age_ms(),
+            aof_fsync_latency: self.aof.fsync_latency(),
+            aof_last_write_ok: self.aof.last_write_ok(),
+            aof_base_size: self.aof.base_size(),
+            aof_current_size: self.aof.current_size(),
+            write_behind: self.write_behind.as_ref().map(WriteBehind::metrics),
         }
     }
 
+    /// Writes the configured snapshot and RDB files. With sled, flushing the
+    /// database is the save and the files are optional extras.
     pub async fn save_snapshot_now(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let Some(path) = &self.snapshot_path else {
+        let writes_files = self.snapshot_path.is_some() || self.rdb_export_path.is_some();
+        if !writes_files && self.sled.is_none() {
             return Err("snapshot path is not configured".into());
-        };
+        }
 
-        self.cleanup_expired().await;
-        let mut entries = Vec::new();
-        for shard in self.shards.iter() {
-            let map = shard.read().await;
-            entries.extend(
-                map.iter()
-                    .map(|(k, v)| (k.clone(), v.value.clone(), v.expires_at)),
-            );
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        if let Some(db) = &self.sled {
+            db.flush_async().await?;
         }
+        let frozen = if writes_files {
+            self.freeze().await
+        } else {
+            Vec::new()
+        };
 
-        write_snapshot(path, entries)?;
+        // Serializing and writing happen off the runtime, against the frozen view;
+        // writes to the live shards go on meanwhile.
+        let rdb_export_path = self.rdb_export_path.clone();
+        let snapshot_path = self.snapshot_path.clone();
+        let keyring = self.aof.keyring();
+        let compression = self.snapshot_compression;
+        let remote = self.remote.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), String> {
+            if let Some(path) = &rdb_export_path {
+                crate::rdb::write_rdb(path, frozen_entries(&frozen)).map_err(|e| e.to_string())?;
+            }
+            if let Some(path) = &snapshot_path {
+                write_snapshot(
+                    path,
+                    frozen_entries(&frozen),
+                    keyring.as_deref(),
+                    compression,
+                )
+                .map_err(|e| e.to_string())?;
+                if let Some(remote) = &remote {
+                    remote.upload(path, now_ms()).map_err(|e| {
+                        format!("snapshot saved locally but the upload failed: {}", e)
+                    })?;
+                }
+            }
+            Ok(())
+        })
+        .await??;
+        self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+        self.last_snapshot_ok.store(true, Ordering::SeqCst);
         self.snapshot_count.fetch_add(1, Ordering::SeqCst);
         self.last_snapshot_epoch_sec
             .store(now_ms() / 1000, Ordering::SeqCst);
@@ -925,7 +1904,7 @@ impl Store {
     }
 
     pub async fn bgsave(&self) -> bool {
-        if self.snapshot_path.is_none() {
+        if self.snapshot_path.is_none() && self.rdb_export_path.is_none() && self.sled.is_none() {
             return false;
         }
 
@@ -941,76 +1920,334 @@ impl Store {
         tokio::spawn(async move {
             if store.save_snapshot_now().await.is_err() {
                 store.snapshot_fail_count.fetch_add(1, Ordering::SeqCst);
+                store.last_snapshot_ok.store(false, Ordering::SeqCst);
             }
             store.snapshot_in_progress.store(false, Ordering::SeqCst);
         });
         true
     }
 
-    pub async fn json_set_root(
+    /// The parsed JSON document at `key`, shared rather than copied;
+    /// `WrongType` when the key holds a string.
+    pub async fn json_get(
         &self,
-        key: Vec<u8>,
-        json: &[u8],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let parsed: JsonValue = serde_json::from_slice(json)?;
-        let encoded = serde_json::to_vec(&parsed)?;
-        let _ = self.set(key, encoded, None, SetCondition::None).await?;
-        Ok(())
+        key: &[u8],
+    ) -> Result<Option<std::sync::Arc<JsonValue>>, JsonError> {
+        let idx = self.shard_idx(key);
+        let shard = self.shards[idx].read().await;
+        match shard.get(key) {
+            Some(entry) if !is_expired(entry.expires_at) => {
+                entry.json.clone().map(Some).ok_or(JsonError::WrongType)
+            }
+            _ => Ok(None),
+        }
     }
 
-    pub async fn json_get_root(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let value = self.get(key).await?;
-        if serde_json::from_slice::<JsonValue>(&value).is_ok() {
-            return Some(value);
+    /// The compact text of the JSON document at `key`, as it was serialized
+    /// when the document was written.
+    pub async fn json_text(&self, key: &[u8]) -> Result<Option<Bytes>, JsonError> {
+        let idx = self.shard_idx(key);
+        let shard = self.shards[idx].read().await;
+        match shard.get(key) {
+            Some(entry) if !is_expired(entry.expires_at) => match entry.json {
+                Some(_) => Ok(Some(entry.value.clone())),
+                None => Err(JsonError::WrongType),
+            },
+            _ => Ok(None),
         }
-        None
     }
 
-    pub async fn json_del_root(&self, key: &[u8]) -> Result<i64, Box<dyn std::error::Error>> {
-        self.del(&[key.to_vec()]).await
+    /// Runs `edit` on the JSON document at `key` under the shard's write
+    /// lock, so concurrent edits of one document do not interleave. `edit`
+    /// sees `None` for a missing key and may set it to `None` to delete the
+    /// key; the document is written back only when it changed, keeping its
+    /// TTL.
+    pub async fn json_update<T>(
+        &self,
+        key: &[u8],
+        edit: impl FnOnce(&mut Option<JsonValue>) -> Result<T, JsonError>,
+    ) -> Result<T, JsonError> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        let (before, expires_at) = match shard.get(key) {
+            Some(entry) if !is_expired(entry.expires_at) => {
+                let doc = entry.json.clone().ok_or(JsonError::WrongType)?;
+                (Some(doc), entry.expires_at)
+            }
+            _ => (None, None),
+        };
+        let mut doc = before.as_deref().cloned();
+        let out = edit(&mut doc)?;
+        if doc.as_ref() == before.as_deref() {
+            return Ok(out);
+        }
+        let record = match doc {
+            Some(doc) => {
+                let entry = ValueEntry::json(doc, expires_at);
+                let value = entry.value.clone();
+                shard.insert(key.to_vec(), entry);
+                LogRecord::Set {
+                    key: key.to_vec(),
+                    kind: ValueType::Json,
+                    value,
+                    expires_at,
+                }
+            }
+            None => {
+                shard.remove(key);
+                LogRecord::Del { key: key.to_vec() }
+            }
+        };
+        self.log(shard, record)
+            .await
+            .map_err(|e| JsonError::Internal(e.to_string()))?;
+        Ok(out)
     }
 
-    pub async fn json_type_root(&self, key: &[u8]) -> Option<&'static str> {
-        let value = self.get(key).await?;
-        let parsed: JsonValue = serde_json::from_slice(&value).ok()?;
-        Some(match parsed {
-            JsonValue::Null => "null",
-            JsonValue::Bool(_) => "boolean",
-            JsonValue::Number(ref n) if n.is_i64() || n.is_u64() => "integer",
-            JsonValue::Number(_) => "number",
-            JsonValue::String(_) => "string",
-            JsonValue::Array(_) => "array",
-            JsonValue::Object(_) => "object",
-        })
+    /// `TS.CREATE`: an empty series keeping `retention` ms of samples.
+    pub async fn ts_create(&self, key: &[u8], retention: u64) -> Result<(), TsError> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        if shard
+            .get(key)
+            .is_some_and(|entry| !is_expired(entry.expires_at))
+        {
+            return Err(TsError::Exists);
+        }
+        let series = timeseries::new(retention);
+        shard.insert(key.to_vec(), ValueEntry::series(series.clone(), None));
+        self.log(
+            shard,
+            LogRecord::Set {
+                key: key.to_vec(),
+                kind: ValueType::TimeSeries,
+                value: series,
+                expires_at: None,
+            },
+        )
+        .await
+        .map_err(|e| TsError::Internal(e.to_string()))
     }
-}
-
-fn shard_index(key: &[u8], count: usize) -> usize {
-    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-    key.hash(&mut hasher);
-    (hasher.finish() as usize) % count
-}
-
-fn now_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis() as u64
-}
 
-fn is_expired(exp: Option<u64>) -> bool {
-    exp.is_some_and(|v| v <= now_ms())
-}
+    /// `TS.ADD`: adds a sample to the series at `key`, creating it with
+    /// `create`'s retention when it is missing and `create` is given. Only
+    /// the sample is logged for a series that existed.
+    pub async fn ts_add(
+        &self,
+        key: &[u8],
+        timestamp: u64,
+        value: f64,
+        create: Option<u64>,
+    ) -> Result<(), TsError> {
+        let idx = self.shard_idx(key);
+        let mut shard = self.shards[idx].write().await;
+        let record = match add_sample(&mut shard, key, timestamp, value) {
+            Ok(()) => LogRecord::TsAdd {
+                key: key.to_vec(),
+                timestamp,
+                value,
+            },
+            Err(TsError::NoKey) if create.is_some() => {
+                let retention = create.unwrap_or_default();
+                let series = timeseries::add(timeseries::new(retention), timestamp, value);
+                shard.insert(key.to_vec(), ValueEntry::series(series.clone(), None));
+                LogRecord::Set {
+                    key: key.to_vec(),
+                    kind: ValueType::TimeSeries,
+                    value: series,
+                    expires_at: None,
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        self.log(shard, record)
+            .await
+            .map_err(|e| TsError::Internal(e.to_string()))
+    }
 
-fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
-    let mut p = 0_usize;
-    let mut t = 0_usize;
-    let mut star_idx: Option<usize> = None;
-    let mut match_idx = 0_usize;
+    /// The bytes of the time series at `key`, to read with `Series`.
+    pub async fn ts_series(&self, key: &[u8]) -> Result<Bytes, TsError> {
+        let idx = self.shard_idx(key);
+        let shard = self.shards[idx].read().await;
+        match shard.get(key) {
+            Some(entry) if !is_expired(entry.expires_at) => match entry.kind {
+                ValueType::TimeSeries => Ok(entry.value.clone()),
+                _ => Err(TsError::WrongType),
+            },
+            _ => Err(TsError::NoKey),
+        }
+    }
 
-    while t < text.len() {
-        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
-            p += 1;
+    /// `FT.CREATE`: files the documents already stored, a shard at a time,
+    /// and keeps the index current from then on. `false` when the name is
+    /// taken. Definitions live in memory only; they are not logged.
+    pub async fn create_search_index(&self, definition: IndexDefinition) -> bool {
+        let _guard = self.op_lock.lock().await;
+        if self.shards[0]
+            .read()
+            .await
+            .search_index(&definition.name)
+            .is_some()
+        {
+            return false;
+        }
+        let definition = std::sync::Arc::new(definition);
+        for shard in self.shards.iter() {
+            shard
+                .write()
+                .await
+                .add_search_index(std::sync::Arc::clone(&definition));
+        }
+        true
+    }
+
+    /// `FT.DROPINDEX`; the documents stay.
+    pub async fn drop_search_index(&self, name: &str) -> bool {
+        let _guard = self.op_lock.lock().await;
+        let mut dropped = false;
+        for shard in self.shards.iter() {
+            dropped |= shard.write().await.drop_search_index(name);
+        }
+        dropped
+    }
+
+    pub async fn search_index(&self, name: &str) -> Option<std::sync::Arc<IndexDefinition>> {
+        self.shards[0].read().await.search_index(name)
+    }
+
+    pub async fn search_index_names(&self) -> Vec<String> {
+        self.shards[0].read().await.search_index_names()
+    }
+
+    /// `FT.SEARCH`: how many documents of index `name` match `query`, and
+    /// `count` of them from `offset` on in key order, with their JSON text.
+    pub async fn search(
+        &self,
+        name: &str,
+        query: &Query,
+        offset: usize,
+        count: usize,
+    ) -> (usize, Vec<(Vec<u8>, Bytes)>) {
+        let now = now_ms();
+        let mut found = Vec::new();
+        for shard in self.shards.iter() {
+            found.extend(shard.read().await.search(name, query, now));
+        }
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        let total = found.len();
+        let page = found.into_iter().skip(offset).take(count).collect();
+        (total, page)
+    }
+}
+
+fn shard_index(key: &[u8], count: usize) -> usize {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() as usize) % count
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// The live entries of a frozen keyspace; keys that expired but were not
+/// reclaimed yet are left out.
+fn frozen_entries(frozen: &[ShardMap]) -> impl Iterator<Item = EntryRef<'_>> + Clone {
+    let now = now_ms();
+    frozen
+        .iter()
+        .flat_map(|map| map.iter())
+        .filter(move |(_, entry)| entry.expires_at.is_none_or(|at| at > now))
+        .map(|(key, entry)| {
+            (
+                key.as_slice(),
+                entry.kind(),
+                &entry.value[..],
+                entry.expires_at,
+            )
+        })
+}
+
+/// The bytes of a string key; string commands cannot read other kinds.
+fn string_value(entry: &ValueEntry) -> Result<Bytes, WrongType> {
+    match entry.kind {
+        ValueType::String => Ok(entry.value.clone()),
+        _ => Err(WrongType),
+    }
+}
+
+/// Adds a sample to the live time series at `key`, in place.
+fn add_sample(
+    shard: &mut IndexedShard,
+    key: &[u8],
+    timestamp: u64,
+    value: f64,
+) -> Result<(), TsError> {
+    let expires_at = match shard.get(key) {
+        Some(entry) if !is_expired(entry.expires_at) => {
+            if entry.kind != ValueType::TimeSeries {
+                return Err(TsError::WrongType);
+            }
+            Series::new(&entry.value).check(timestamp)?;
+            entry.expires_at
+        }
+        _ => return Err(TsError::NoKey),
+    };
+    // Taken out of the shard so that its bytes are not shared and grow in place.
+    let entry = shard.remove(key).expect("checked above");
+    let series = timeseries::add(entry.value, timestamp, value);
+    shard.insert(key.to_vec(), ValueEntry::series(series, expires_at));
+    Ok(())
+}
+
+fn is_expired(exp: Option<u64>) -> bool {
+    exp.is_some_and(|v| v <= now_ms())
+}
+
+/// Whether `key` is a live lease taken with `token`.
+fn holds_lease(shard: &IndexedShard, key: &[u8], token: u64) -> bool {
+    shard
+        .get(key)
+        .is_some_and(|entry| !is_expired(entry.expires_at) && lease_token(&entry) == Some(token))
+}
+
+/// The fencing token a lease entry holds.
+fn lease_token(entry: &ValueEntry) -> Option<u64> {
+    if entry.kind != ValueType::String {
+        return None;
+    }
+    std::str::from_utf8(&entry.value).ok()?.parse().ok()
+}
+
+/// What `TYPE` replies for a key of `kind`: the names RedisJSON and
+/// RedisTimeSeries use for theirs.
+pub(crate) fn type_name(kind: ValueType) -> &'static str {
+    match kind {
+        ValueType::Json => "ReJSON-RL",
+        ValueType::TimeSeries => "TSDB-TYPE",
+        _ => "string",
+    }
+}
+
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let mut p = 0_usize;
+    let mut t = 0_usize;
+    let mut star_idx: Option<usize> = None;
+    let mut match_idx = 0_usize;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
             t += 1;
             continue;
         }
@@ -1039,9 +2276,10 @@ fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
     p == pattern.len()
 }
 
-fn slice_range(value: &[u8], start: i64, end: i64) -> Vec<u8> {
+/// `GETRANGE` of a value: a view into the same buffer, not a copy.
+fn slice_range(value: &Bytes, start: i64, end: i64) -> Bytes {
     if value.is_empty() {
-        return Vec::new();
+        return Bytes::new();
     }
 
     let len = value.len() as i64;
@@ -1052,46 +2290,129 @@ fn slice_range(value: &[u8], start: i64, end: i64) -> Vec<u8> {
         s = 0;
     }
     if e < 0 {
-        return Vec::new();
+        return Bytes::new();
     }
     if s >= len {
-        return Vec::new();
+        return Bytes::new();
     }
     if e >= len {
         e = len - 1;
     }
     if s > e {
-        return Vec::new();
+        return Bytes::new();
     }
 
-    value[s as usize..=e as usize].to_vec()
+    value.slice(s as usize..=e as usize)
 }
 
-const SNAP_MAGIC: &[u8] = b"FDSNP1";
-
-fn write_snapshot(
+/// `FDSNP` followed by the format version digit.
+pub(crate) const SNAP_MAGIC_PREFIX: &[u8] = b"FDSNP";
+/// v1: bare entries after the magic.
+const SNAP_MAGIC_V1: &[u8] = b"FDSNP1";
+/// v2: `created_at_ms u64 | entry_count u64 | entries | crc64 u64`, the checksum
+/// covering everything before it including the magic.
+const SNAP_MAGIC_V2: &[u8] = b"FDSNP2";
+/// v3: as v2 with a compression tag byte after the entry count; the entries that
+/// follow are compressed with it.
+const SNAP_MAGIC_V3: &[u8] = b"FDSNP3";
+/// v4: as v3, but compressed entries are a streaming frame (an LZ4 frame rather
+/// than a size-prefixed block) so the writer never holds the whole body.
+const SNAP_MAGIC_V4: &[u8] = b"FDSNP4";
+/// v5: as v4 with a `type | version` value header between each key and value.
+const SNAP_MAGIC: &[u8] = b"FDSNP5";
+const SNAP_HEADER_LEN_V2: usize = 6 + 8 + 8;
+const SNAP_HEADER_LEN: usize = SNAP_HEADER_LEN_V2 + 1;
+/// Encrypted snapshots seal a whole v2/v3 image after the magic as one block.
+const ENCRYPTED_SNAP_MAGIC: &[u8] = b"FDSNPE";
+
+/// Streams a snapshot to a temporary file and renames it into place.
+pub(crate) fn write_snapshot<'a, I>(
     path: &Path,
-    entries: Vec<(Vec<u8>, Vec<u8>, Option<u64>)>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut out = Vec::new();
-    out.extend_from_slice(SNAP_MAGIC);
-    for (key, value, expires_at) in entries {
-        out.extend_from_slice(&(key.len() as u32).to_be_bytes());
-        out.extend_from_slice(&key);
-        out.extend_from_slice(&(value.len() as u32).to_be_bytes());
-        out.extend_from_slice(&value);
-        let exp = expires_at.map(|v| v as i64).unwrap_or(-1);
-        out.extend_from_slice(&exp.to_be_bytes());
+    entries: I,
+    keyring: Option<&Keyring>,
+    compression: Compression,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: Iterator<Item = EntryRef<'a>> + Clone,
+{
+    let mut file = AtomicFile::create(path, "snapshot.tmp")?;
+    match keyring {
+        // The image is sealed as a single AEAD block, so encrypted snapshots are
+        // still assembled in memory.
+        Some(keyring) => {
+            let image = write_snapshot_image(Vec::new(), entries, compression)?;
+            file.write_all(ENCRYPTED_SNAP_MAGIC)?;
+            file.write_all(&keyring.seal(&image, ENCRYPTED_SNAP_MAGIC)?)?;
+        }
+        None => {
+            write_snapshot_image(&mut file, entries, compression)?;
+        }
     }
-    let tmp = path.with_extension("snapshot.tmp");
-    std::fs::write(&tmp, out)?;
-    std::fs::rename(tmp, path)?;
+    file.commit()?;
     Ok(())
 }
 
-fn read_snapshot(
+fn write_snapshot_image<'a, W, I>(
+    out: W,
+    entries: I,
+    compression: Compression,
+) -> Result<W, Box<dyn std::error::Error>>
+where
+    W: Write,
+    I: Iterator<Item = EntryRef<'a>> + Clone,
+{
+    let mut out = Crc64Writer::new(out);
+    out.write_all(SNAP_MAGIC)?;
+    out.write_all(&now_ms().to_be_bytes())?;
+    out.write_all(&(entries.clone().count() as u64).to_be_bytes())?;
+    out.write_all(&[compression.tag()])?;
+    let mut body = compression.writer(&mut out)?;
+    for (key, kind, value, expires_at) in entries {
+        body.write_all(&(key.len() as u32).to_be_bytes())?;
+        body.write_all(key)?;
+        write_value_header(&mut body, kind)?;
+        body.write_all(&(value.len() as u32).to_be_bytes())?;
+        body.write_all(value)?;
+        let exp = expires_at.map(|v| v as i64).unwrap_or(-1);
+        body.write_all(&exp.to_be_bytes())?;
+    }
+    body.finish()?;
+    let checksum = out.crc();
+    let mut out = out.into_inner();
+    out.write_all(&checksum.to_be_bytes())?;
+    Ok(out)
+}
+
+/// What `fedis --check-snapshot` reports about a snapshot.
+pub struct SnapshotCheck {
+    pub file_len: u64,
+    pub entries: usize,
+    pub expiring: usize,
+}
+
+/// Validates a snapshot without loading it. Errors carry the offset of the
+/// first problem.
+pub fn check_snapshot(
     path: &Path,
-) -> Result<Vec<(Vec<u8>, Vec<u8>, Option<u64>)>, Box<dyn std::error::Error>> {
+    keyring: Option<&Keyring>,
+) -> Result<SnapshotCheck, Box<dyn std::error::Error>> {
+    let file_len = std::fs::metadata(path)?.len();
+    let entries = read_snapshot(path, keyring)?;
+    Ok(SnapshotCheck {
+        file_len,
+        entries: entries.len(),
+        expiring: entries
+            .iter()
+            .filter(|(_, _, _, exp)| exp.is_some())
+            .count(),
+    })
+}
+
+/// Reads and fully validates a snapshot before any of it is loaded.
+pub(crate) fn read_snapshot(
+    path: &Path,
+    keyring: Option<&Keyring>,
+) -> Result<Vec<SnapshotEntry>, Box<dyn std::error::Error>> {
     let mut bytes = Vec::new();
     let mut file = std::fs::File::open(path)?;
     file.read_to_end(&mut bytes)?;
@@ -1099,67 +2420,158 @@ fn read_snapshot(
     if bytes.is_empty() {
         return Ok(Vec::new());
     }
-    if bytes.len() < SNAP_MAGIC.len() || &bytes[..SNAP_MAGIC.len()] != SNAP_MAGIC {
-        return Err("invalid snapshot magic header".into());
-    }
-
-    let mut idx = SNAP_MAGIC.len();
-    let mut out = Vec::new();
-    while idx < bytes.len() {
-        if idx + 4 > bytes.len() {
-            return Err(
-                std::io::Error::new(ErrorKind::InvalidData, "truncated snapshot key len").into(),
-            );
-        }
-        let klen = u32::from_be_bytes(bytes[idx..idx + 4].try_into()?) as usize;
-        idx += 4;
-        if idx + klen > bytes.len() {
-            return Err(
-                std::io::Error::new(ErrorKind::InvalidData, "truncated snapshot key").into(),
-            );
+    if bytes.starts_with(ENCRYPTED_SNAP_MAGIC) {
+        let keyring = keyring.ok_or("snapshot is encrypted but no encryption key is configured")?;
+        bytes = keyring.open(&bytes[ENCRYPTED_SNAP_MAGIC.len()..], ENCRYPTED_SNAP_MAGIC)?;
+        if !bytes.starts_with(SNAP_MAGIC_PREFIX) {
+            // Encrypted snapshots written before v2 sealed the bare v1 entries.
+            bytes.splice(..0, SNAP_MAGIC_V1.iter().copied());
         }
-        let key = bytes[idx..idx + klen].to_vec();
-        idx += klen;
+    }
 
-        if idx + 4 > bytes.len() {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "truncated snapshot value len",
-            )
-            .into());
-        }
-        let vlen = u32::from_be_bytes(bytes[idx..idx + 4].try_into()?) as usize;
-        idx += 4;
-        if idx + vlen > bytes.len() {
-            return Err(
-                std::io::Error::new(ErrorKind::InvalidData, "truncated snapshot value").into(),
-            );
-        }
-        let value = bytes[idx..idx + vlen].to_vec();
-        idx += vlen;
+    // `base` is where `body` starts in the file, for error offsets; compressed
+    // bodies report offsets into the decompressed entries instead.
+    let (body, base, expected_entries): (std::borrow::Cow<[u8]>, usize, _) =
+        match bytes.get(..SNAP_MAGIC.len()) {
+            Some(magic) if magic == SNAP_MAGIC_V1 => (
+                bytes[SNAP_MAGIC_V1.len()..].into(),
+                SNAP_MAGIC_V1.len(),
+                None,
+            ),
+            Some(magic)
+                if magic == SNAP_MAGIC_V2
+                    || magic == SNAP_MAGIC_V3
+                    || magic == SNAP_MAGIC_V4
+                    || magic == SNAP_MAGIC =>
+            {
+                let header_len = if magic == SNAP_MAGIC_V2 {
+                    SNAP_HEADER_LEN_V2
+                } else {
+                    SNAP_HEADER_LEN
+                };
+                if bytes.len() < header_len + 8 {
+                    return Err(snapshot_error(bytes.len(), "truncated snapshot header"));
+                }
+                let (image, trailer) = bytes.split_at(bytes.len() - 8);
+                let stored = u64::from_be_bytes(trailer.try_into()?);
+                if crc64(image) != stored {
+                    return Err(format!(
+                        "snapshot checksum mismatch at offset {}: the file is truncated or corrupt",
+                        image.len()
+                    )
+                    .into());
+                }
+                let count = u64::from_be_bytes(image[14..SNAP_HEADER_LEN_V2].try_into()?);
+                let compression = match header_len {
+                    SNAP_HEADER_LEN => Compression::from_tag(image[SNAP_HEADER_LEN_V2])?,
+                    _ => Compression::None,
+                };
+                let body = &image[header_len..];
+                match compression {
+                    Compression::None => (body.into(), header_len, Some(count)),
+                    compression if magic == SNAP_MAGIC_V3 => {
+                        (compression.decompress(body)?.into(), 0, Some(count))
+                    }
+                    compression => (compression.decompress_framed(body)?.into(), 0, Some(count)),
+                }
+            }
+            Some(magic) if magic.starts_with(SNAP_MAGIC_PREFIX) => {
+                return Err(format!(
+                    "unsupported snapshot format version '{}'",
+                    String::from_utf8_lossy(&magic[SNAP_MAGIC_PREFIX.len()..])
+                )
+                .into());
+            }
+            _ => return Err("invalid snapshot magic header".into()),
+        };
+    let body = body.as_ref();
+    let typed = bytes.starts_with(SNAP_MAGIC);
 
-        if idx + 8 > bytes.len() {
-            return Err(
-                std::io::Error::new(ErrorKind::InvalidData, "truncated snapshot expiry").into(),
-            );
+    let mut idx = 0;
+    let mut out = Vec::new();
+    while idx < body.len() {
+        let key = read_snapshot_bytes(body, &mut idx, base, "key")?;
+        let mut kind = ValueType::String;
+        if typed {
+            let Some(header) = body.get(idx..idx + 2) else {
+                return Err(snapshot_error(
+                    base + idx,
+                    "truncated snapshot value header",
+                ));
+            };
+            kind = read_value_header(header).map_err(|e| snapshot_error(base + idx, &e))?;
+            idx += 2;
         }
-        let exp = i64::from_be_bytes(bytes[idx..idx + 8].try_into()?);
+        let value = read_snapshot_bytes(body, &mut idx, base, "value")?;
+        let Some(exp) = body.get(idx..idx + 8) else {
+            return Err(snapshot_error(base + idx, "truncated snapshot expiry"));
+        };
+        let exp = i64::from_be_bytes(exp.try_into()?);
         idx += 8;
         let expires_at = if exp < 0 { None } else { Some(exp as u64) };
-        out.push((key, value, expires_at));
+        out.push((key, kind, value, expires_at));
     }
 
+    if let Some(expected) = expected_entries
+        && expected != out.len() as u64
+    {
+        return Err(format!(
+            "snapshot header declares {} entries but the file holds {}",
+            expected,
+            out.len()
+        )
+        .into());
+    }
     Ok(out)
 }
 
+fn read_snapshot_bytes(
+    body: &[u8],
+    idx: &mut usize,
+    base: usize,
+    what: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let Some(len) = body.get(*idx..*idx + 4) else {
+        return Err(snapshot_error(
+            base + *idx,
+            &format!("truncated snapshot {} len", what),
+        ));
+    };
+    let len = u32::from_be_bytes(len.try_into()?) as usize;
+    *idx += 4;
+    let Some(data) = body.get(*idx..*idx + len) else {
+        return Err(snapshot_error(
+            base + *idx,
+            &format!("truncated snapshot {}", what),
+        ));
+    };
+    *idx += len;
+    Ok(data.to_vec())
+}
+
+fn snapshot_error(offset: usize, message: &str) -> Box<dyn std::error::Error> {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("{} at offset {}", message, offset),
+    )
+    .into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::persistence::AofFsync;
+    use crate::persistence::{AofFormat, AofFsync};
+    use crate::tier::TierConfig;
     use std::sync::atomic::{AtomicU64, Ordering};
 
     static TEST_ID: AtomicU64 = AtomicU64::new(1);
 
+    fn borrowed(entries: &[SnapshotEntry]) -> impl Iterator<Item = EntryRef<'_>> + Clone {
+        entries
+            .iter()
+            .map(|(k, t, v, e)| (k.as_slice(), *t, v.as_slice(), *e))
+    }
+
     fn temp_paths() -> (PathBuf, PathBuf) {
         let id = TEST_ID.fetch_add(1, Ordering::Relaxed);
         let root =
@@ -1168,11 +2580,319 @@ mod tests {
         (root.join("test.aof"), root.join("test.snapshot"))
     }
 
+    #[tokio::test]
+    async fn memory_accounting_follows_every_write() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        assert_eq!(store.used_memory(), 0);
+
+        let _ = store
+            .set(b"k".to_vec(), vec![b'x'; 100], None, SetCondition::None)
+            .await
+            .expect("set");
+        let plain = store.used_memory();
+        assert_eq!(
+            plain,
+            store.memory_usage(b"k").await.expect("usage") as usize
+        );
+        assert!(store.expire(b"k", 60).await.expect("expire"));
+        let volatile = store.used_memory();
+        assert!(volatile > plain, "the expiry index is accounted for");
+        let _ = store
+            .set(b"k".to_vec(), vec![b'x'; 10], None, SetCondition::None)
+            .await
+            .expect("overwrite");
+        assert!(store.used_memory() < plain);
+        assert_eq!(store.metrics().await.peak_memory_bytes, volatile);
+
+        let _ = store.del(&[b"k".to_vec()]).await.expect("del");
+        assert_eq!(store.used_memory(), 0);
+        assert_eq!(store.metrics().await.approx_memory_bytes, 0);
+
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
+    #[tokio::test]
+    async fn lease_tokens_follow_the_expired_lease_even_when_the_clock_is_behind() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        // Left by a node whose clock ran an hour ahead.
+        let ahead = now_us() + 3_600_000_000;
+        let _ = store
+            .set(
+                b"lease".to_vec(),
+                ahead.to_string().into_bytes(),
+                Some(now_ms() + 10),
+                SetCondition::None,
+            )
+            .await
+            .expect("set");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            store.lock(b"lease", 60_000).await.expect("lock"),
+            Some(ahead + 1)
+        );
+        assert_eq!(store.lock(b"lease", 60_000).await.expect("lock"), None);
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
+    #[tokio::test]
+    async fn deleting_an_expired_key_is_not_counted_or_logged() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        for (key, expires_at) in [(b"gone", now_ms() + 10), (b"live", now_ms() + 60_000)] {
+            let _ = store
+                .set(
+                    key.to_vec(),
+                    b"v".to_vec(),
+                    Some(expires_at),
+                    SetCondition::None,
+                )
+                .await
+                .expect("set");
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            store
+                .del(&[b"gone".to_vec(), b"live".to_vec()])
+                .await
+                .expect("del"),
+            1
+        );
+        assert_eq!(store.dbsize().await, 0);
+        let dels: Vec<Vec<u8>> = store
+            .aof
+            .read_all()
+            .expect("read aof")
+            .into_iter()
+            .filter_map(|record| match record {
+                LogRecord::Del { key } => Some(key),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(dels, [b"live".to_vec()]);
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
+    #[tokio::test]
+    async fn unlink_and_async_flush_free_in_the_background() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None)
+            .await
+            .expect("new store")
+            .with_lazyfree_threshold(1024);
+
+        for (key, size) in [(b"big", 4096), (b"sml", 16)] {
+            let _ = store
+                .set(key.to_vec(), vec![b'x'; size], None, SetCondition::None)
+                .await
+                .expect("set");
+        }
+        assert_eq!(
+            store
+                .unlink(&[b"big".to_vec(), b"sml".to_vec()])
+                .await
+                .expect("unlink"),
+            2
+        );
+        for i in 0..100 {
+            let _ = store
+                .set(
+                    format!("k{i}").into_bytes(),
+                    b"v".to_vec(),
+                    None,
+                    SetCondition::None,
+                )
+                .await
+                .expect("set");
+        }
+        store.flush(true).await.expect("flush");
+        assert_eq!(store.dbsize().await, 0);
+        assert_eq!(store.used_memory(), 0);
+
+        // The big value and every shard, but not the small value.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while store.lazy_free().freed() < 1 + DEFAULT_SHARDS as u64 {
+            assert!(
+                Instant::now() < deadline,
+                "lazyfree thread did not catch up"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(store.lazy_free().freed(), 1 + DEFAULT_SHARDS as u64);
+        assert_eq!(store.lazy_free().pending(), 0);
+
+        // The flush rewrote the AOF, so the keys stay gone after a restart.
+        drop(store);
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let store = Store::new(aof, None).await.expect("reopen store");
+        assert_eq!(store.dbsize().await, 0);
+
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
+    #[tokio::test]
+    async fn multi_key_commands_batch_across_shards_and_replay() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+
+        let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = (0..200)
+            .map(|i| (format!("k{i}").into_bytes(), format!("v{i}").into_bytes()))
+            .collect();
+        pairs.push((b"k0".to_vec(), b"last".to_vec()));
+        let _ = store
+            .set(
+                b"k1".to_vec(),
+                b"old".to_vec(),
+                Some(now_ms() + 60_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set");
+        let _ = store
+            .set(b"gone".to_vec(), b"x".to_vec(), Some(1), SetCondition::None)
+            .await
+            .expect("set");
+        store.mset(pairs).await.expect("mset");
+        assert_eq!(store.ttl(b"k1").await, -1, "MSET clears the expiry");
+
+        let keys: Vec<Vec<u8>> = [&b"k0"[..], b"missing", b"k199", b"gone", b"k1"]
+            .iter()
+            .map(|key| key.to_vec())
+            .collect();
+        let values = store.mget(&keys).await;
+        assert_eq!(
+            values,
+            vec![
+                Some(Bytes::from_static(b"last")),
+                None,
+                Some(Bytes::from_static(b"v199")),
+                None,
+                Some(Bytes::from_static(b"v1")),
+            ]
+        );
+        assert_eq!(
+            store.dbsize().await,
+            201,
+            "expired keys wait for active expiration"
+        );
+
+        let doomed: Vec<Vec<u8>> = (100..200).map(|i| format!("k{i}").into_bytes()).collect();
+        assert_eq!(store.del(&doomed).await.expect("del"), 100);
+
+        drop(store);
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let store = Store::new(aof, None).await.expect("reopen store");
+        assert_eq!(store.dbsize().await, 100, "the expired key is not replayed");
+        assert_eq!(
+            store.get(b"k0").await,
+            Ok(Some(Bytes::from_static(b"last")))
+        );
+        assert_eq!(store.get(b"k150").await, Ok(None));
+
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
+    #[tokio::test]
+    async fn reads_share_the_stored_value_buffer() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+
+        let _ = store
+            .set(
+                b"k".to_vec(),
+                b"0123456789".to_vec(),
+                None,
+                SetCondition::None,
+            )
+            .await
+            .expect("set");
+        let first = store.get(b"k").await.ok().flatten().expect("value");
+        let second = store.get(b"k").await.ok().flatten().expect("value");
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        let range = store.getrange(b"k", 2, 4).await.expect("string");
+        assert_eq!(&range[..], b"234");
+        assert_eq!(range.as_ptr(), first[2..].as_ptr());
+
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
+    #[tokio::test]
+    async fn active_expiration_pops_due_keys_from_the_expiry_index() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+
+        let past = now_ms() - 1;
+        for i in 0..500 {
+            let key = format!("gone:{i}").into_bytes();
+            let _ = store
+                .set(key, b"v".to_vec(), Some(past), SetCondition::None)
+                .await
+                .expect("set expired");
+        }
+        for i in 0..50 {
+            let _ = store
+                .set(
+                    format!("kept:{i}").into_bytes(),
+                    b"v".to_vec(),
+                    None,
+                    SetCondition::None,
+                )
+                .await
+                .expect("set");
+        }
+        let _ = store
+            .set(
+                b"later".to_vec(),
+                b"v".to_vec(),
+                Some(now_ms() + 60_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set later");
+        assert_eq!(store.dbsize().await, 551);
+        assert_eq!(store.keys(b"*").await.len(), 51, "expired keys are hidden");
+
+        assert_eq!(store.expire_cycle(Duration::from_secs(1)).await, 500);
+        assert_eq!(store.dbsize().await, 51);
+        assert_eq!(store.metrics().await.expiring_keys, 1);
+        assert!(store.persist(b"later").await.expect("persist"));
+        assert_eq!(store.metrics().await.expiring_keys, 0);
+        assert_eq!(store.expire_cycle(Duration::from_secs(1)).await, 0);
+
+        let _ = std::fs::remove_file(&aof_path);
+    }
+
     #[tokio::test]
     async fn restart_recovers_from_aof_and_snapshot() {
         let (aof_path, snapshot_path) = temp_paths();
 
-        let aof = Aof::open(&aof_path, AofFsync::Always)
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
             .await
             .expect("open aof");
         let store = Store::new(aof, Some(snapshot_path.clone()))
@@ -1190,14 +2910,14 @@ mod tests {
             .expect("set v2");
         drop(store);
 
-        let aof = Aof::open(&aof_path, AofFsync::Always)
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
             .await
             .expect("reopen aof");
         let store = Store::new(aof, Some(snapshot_path.clone()))
             .await
             .expect("reopen store");
 
-        assert_eq!(store.get(b"k").await, Some(b"v2".to_vec()));
+        assert_eq!(store.get(b"k").await, Ok(Some(Bytes::from_static(b"v2"))));
 
         let _ = std::fs::remove_file(&aof_path);
         let _ = std::fs::remove_file(&snapshot_path);
@@ -1207,7 +2927,7 @@ mod tests {
     async fn restart_recovers_from_aof_without_snapshot() {
         let (aof_path, _) = temp_paths();
 
-        let aof = Aof::open(&aof_path, AofFsync::Always)
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
             .await
             .expect("open aof");
         let store = Store::new(aof, None).await.expect("new store");
@@ -1217,12 +2937,856 @@ mod tests {
             .expect("set key");
         drop(store);
 
-        let aof = Aof::open(&aof_path, AofFsync::Always)
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
             .await
             .expect("reopen aof");
         let store = Store::new(aof, None).await.expect("reopen store");
-        assert_eq!(store.get(b"a").await, Some(b"1".to_vec()));
+        assert_eq!(store.get(b"a").await, Ok(Some(Bytes::from_static(b"1"))));
 
         let _ = std::fs::remove_file(&aof_path);
     }
+
+    #[tokio::test]
+    async fn encrypted_persistence_round_trips_and_migrates_plain_aof() {
+        let (aof_path, snapshot_path) = temp_paths();
+        let keyring = std::sync::Arc::new(
+            Keyring::parse(
+                "7:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+                None,
+            )
+            .expect("keyring"),
+        );
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open plain aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        let _ = store
+            .set(
+                b"plain".to_vec(),
+                b"before".to_vec(),
+                None,
+                SetCondition::None,
+            )
+            .await
+            .expect("set plain");
+        drop(store);
+
+        let aof = Aof::open(
+            &aof_path,
+            AofFsync::Always,
+            Some(keyring.clone()),
+            AofFormat::Fedis,
+        )
+        .await
+        .expect("open encrypted aof");
+        let store = Store::new(aof, Some(snapshot_path.clone()))
+            .await
+            .expect("migrate store");
+        let _ = store
+            .set(
+                b"secret".to_vec(),
+                b"classified".to_vec(),
+                None,
+                SetCondition::None,
+            )
+            .await
+            .expect("set secret");
+        store.save_snapshot_now().await.expect("save snapshot");
+        drop(store);
+
+        for path in [&aof_path, &snapshot_path] {
+            let bytes = std::fs::read(path).expect("read persisted file");
+            assert!(!bytes.windows(10).any(|w| w == b"classified"));
+            assert!(!bytes.windows(6).any(|w| w == b"before"));
+        }
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, Some(keyring), AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let store = Store::new(aof, Some(snapshot_path.clone()))
+            .await
+            .expect("reopen store");
+        assert_eq!(
+            store.get(b"plain").await,
+            Ok(Some(Bytes::from_static(b"before")))
+        );
+        assert_eq!(
+            store.get(b"secret").await,
+            Ok(Some(Bytes::from_static(b"classified")))
+        );
+        drop(store);
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open without key");
+        assert!(Store::new(aof, None).await.is_err());
+
+        let _ = std::fs::remove_file(&aof_path);
+        let _ = std::fs::remove_file(&snapshot_path);
+    }
+
+    #[tokio::test]
+    async fn rdb_export_round_trips_through_import() {
+        let (aof_path, _) = temp_paths();
+        let rdb_path = aof_path.with_file_name("dump.rdb");
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None)
+            .await
+            .expect("new store")
+            .with_rdb_export_path(Some(rdb_path.clone()));
+        let _ = store
+            .set(
+                b"k".to_vec(),
+                b"v".to_vec(),
+                Some(now_ms() + 60_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set k");
+        store.save_snapshot_now().await.expect("export rdb");
+        drop(store);
+        let _ = std::fs::remove_file(&aof_path);
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open fresh aof");
+        let store = Store::new(aof, None).await.expect("fresh store");
+        assert_eq!(store.import_rdb(&rdb_path).await.expect("import"), (1, 0));
+        assert_eq!(store.get(b"k").await, Ok(Some(Bytes::from_static(b"v"))));
+        assert!(store.ttl(b"k").await > 0);
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn redis_format_aof_replays_and_converts_existing_log() {
+        let (aof_path, _) = temp_paths();
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open fedis aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        let _ = store
+            .set(b"old".to_vec(), b"1".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set old");
+        drop(store);
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Resp)
+            .await
+            .expect("open resp aof");
+        let store = Store::new(aof, None).await.expect("convert store");
+        let _ = store
+            .set(
+                b"k".to_vec(),
+                b"v".to_vec(),
+                Some(now_ms() + 60_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set k");
+        let _ = store.del(&[b"old".to_vec()]).await.expect("del old");
+        drop(store);
+
+        let contents = std::fs::read(&aof_path).expect("read aof");
+        assert!(contents.starts_with(b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n"));
+        assert!(contents.windows(4).any(|w| w == b"PXAT"));
+        assert!(contents.ends_with(b"*2\r\n$3\r\nDEL\r\n$3\r\nold\r\n"));
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Resp)
+            .await
+            .expect("reopen resp aof");
+        let store = Store::new(aof, None).await.expect("replay store");
+        assert_eq!(store.get(b"k").await, Ok(Some(Bytes::from_static(b"v"))));
+        assert_eq!(store.get(b"old").await, Ok(None));
+        assert!(store.ttl(b"k").await > 0);
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn truncated_aof_tail_is_recovered_only_when_allowed() {
+        let (aof_path, _) = temp_paths();
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        for key in [b"a", b"b"] {
+            let _ = store
+                .set(key.to_vec(), b"v".to_vec(), None, SetCondition::None)
+                .await
+                .expect("set");
+        }
+        drop(store);
+        let intact_len = std::fs::metadata(&aof_path).expect("aof metadata").len();
+
+        // A torn write: the size header made it to disk, most of the record did not.
+        let mut contents = std::fs::read(&aof_path).expect("read aof");
+        contents.extend_from_slice(&[0, 0, 0, 40, 1, 0]);
+        std::fs::write(&aof_path, &contents).expect("write torn aof");
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof")
+            .with_load_truncated(false);
+        let err = Store::new(aof, None)
+            .await
+            .err()
+            .expect("strict load fails");
+        assert!(err.to_string().contains("truncated at offset"));
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let store = Store::new(aof, None).await.expect("recovering load");
+        assert_eq!(store.get(b"b").await, Ok(Some(Bytes::from_static(b"v"))));
+        assert_eq!(
+            std::fs::metadata(&aof_path).expect("aof metadata").len(),
+            intact_len
+        );
+        drop(store);
+
+        // Damage to a record that is not the last one is never skipped.
+        let mut contents = std::fs::read(&aof_path).expect("read aof");
+        let key_offset = contents.iter().position(|b| *b == b'a').expect("first key");
+        contents[key_offset] = b'x';
+        std::fs::write(&aof_path, &contents).expect("write corrupt aof");
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let err = Store::new(aof, None)
+            .await
+            .err()
+            .expect("corrupt load fails");
+        assert!(err.to_string().contains("is corrupt"));
+        let report = Aof::check(&aof_path, None).expect("check aof");
+        assert_eq!(report.records, 0);
+        assert!(
+            report
+                .first_bad
+                .is_some_and(|(offset, _)| offset < key_offset)
+        );
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[test]
+    fn snapshot_v2_detects_truncation_and_reads_v1() {
+        let (_, snapshot_path) = temp_paths();
+        let entries = vec![
+            (b"a".to_vec(), ValueType::String, b"1".to_vec(), None),
+            (
+                b"b".to_vec(),
+                ValueType::Json,
+                br#"{"n":2}"#.to_vec(),
+                Some(4_102_444_800_000),
+            ),
+        ];
+        write_snapshot(&snapshot_path, borrowed(&entries), None, Compression::None)
+            .expect("write snapshot");
+        assert_eq!(
+            read_snapshot(&snapshot_path, None).expect("read snapshot"),
+            entries
+        );
+        let report = check_snapshot(&snapshot_path, None).expect("check snapshot");
+        assert_eq!((report.entries, report.expiring), (2, 1));
+
+        // A value type this build cannot hold is named, not misparsed.
+        let mut bytes = std::fs::read(&snapshot_path).expect("read bytes");
+        let header_at = SNAP_HEADER_LEN + 4 + 1;
+        assert_eq!(bytes[header_at], ValueType::String.tag());
+        bytes[header_at] = ValueType::Hash.tag();
+        let image_len = bytes.len() - 8;
+        let checksum = crc64(&bytes[..image_len]);
+        bytes[image_len..].copy_from_slice(&checksum.to_be_bytes());
+        std::fs::write(&snapshot_path, &bytes).expect("write hash entry");
+        let err = read_snapshot(&snapshot_path, None).expect_err("hash entry");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "hash values are not supported by this build at offset {}",
+                header_at
+            )
+        );
+        write_snapshot(&snapshot_path, borrowed(&entries), None, Compression::None)
+            .expect("write snapshot");
+
+        let bytes = std::fs::read(&snapshot_path).expect("read bytes");
+        std::fs::write(&snapshot_path, &bytes[..bytes.len() - 5]).expect("truncate");
+        let err = read_snapshot(&snapshot_path, None).expect_err("truncated snapshot");
+        assert!(err.to_string().contains("checksum mismatch at offset"));
+
+        let mut v1 = SNAP_MAGIC_V1.to_vec();
+        v1.extend_from_slice(&1_u32.to_be_bytes());
+        v1.push(b'k');
+        v1.extend_from_slice(&1_u32.to_be_bytes());
+        v1.push(b'v');
+        v1.extend_from_slice(&(-1_i64).to_be_bytes());
+        std::fs::write(&snapshot_path, &v1).expect("write v1");
+        assert_eq!(
+            read_snapshot(&snapshot_path, None).expect("read v1"),
+            vec![(b"k".to_vec(), ValueType::String, b"v".to_vec(), None)]
+        );
+        std::fs::write(&snapshot_path, &v1[..v1.len() - 3]).expect("truncate v1");
+        let err = read_snapshot(&snapshot_path, None).expect_err("truncated v1");
+        assert_eq!(err.to_string(), "truncated snapshot expiry at offset 16");
+
+        let _ = std::fs::remove_dir_all(snapshot_path.parent().expect("temp dir"));
+    }
+
+    #[test]
+    fn compressed_snapshots_record_their_algorithm() {
+        let (_, snapshot_path) = temp_paths();
+        let entries: Vec<SnapshotEntry> = (0..200)
+            .map(|i| {
+                (
+                    format!("doc:{}", i).into_bytes(),
+                    ValueType::String,
+                    br#"{"status":"active","tags":["a","b","c"]}"#.repeat(10),
+                    None,
+                )
+            })
+            .collect();
+
+        write_snapshot(&snapshot_path, borrowed(&entries), None, Compression::None)
+            .expect("write plain snapshot");
+        let plain_len = std::fs::metadata(&snapshot_path).expect("metadata").len();
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            write_snapshot(&snapshot_path, borrowed(&entries), None, compression)
+                .expect("write compressed snapshot");
+            assert!(std::fs::metadata(&snapshot_path).expect("metadata").len() < plain_len / 4);
+            assert_eq!(
+                read_snapshot(&snapshot_path, None).expect("read compressed snapshot"),
+                entries
+            );
+        }
+
+        // v3 stored LZ4 bodies as a size-prefixed block rather than a frame.
+        let mut body = Vec::new();
+        for (key, _, value, _) in &entries {
+            body.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            body.extend_from_slice(key);
+            body.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            body.extend_from_slice(value);
+            body.extend_from_slice(&(-1_i64).to_be_bytes());
+        }
+        let mut v3 = SNAP_MAGIC_V3.to_vec();
+        v3.extend_from_slice(&0_u64.to_be_bytes());
+        v3.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+        v3.push(Compression::Lz4.tag());
+        v3.extend_from_slice(&Compression::Lz4.compress(&body).expect("compress"));
+        let checksum = crc64(&v3);
+        v3.extend_from_slice(&checksum.to_be_bytes());
+        std::fs::write(&snapshot_path, &v3).expect("write v3");
+        assert_eq!(
+            read_snapshot(&snapshot_path, None).expect("read v3 snapshot"),
+            entries
+        );
+
+        // v4 entries carry no value header.
+        let mut v4 = SNAP_MAGIC_V4.to_vec();
+        v4.extend_from_slice(&0_u64.to_be_bytes());
+        v4.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+        v4.push(Compression::None.tag());
+        v4.extend_from_slice(&body);
+        let checksum = crc64(&v4);
+        v4.extend_from_slice(&checksum.to_be_bytes());
+        std::fs::write(&snapshot_path, &v4).expect("write v4");
+        assert_eq!(
+            read_snapshot(&snapshot_path, None).expect("read v4 snapshot"),
+            entries
+        );
+
+        let _ = std::fs::remove_dir_all(snapshot_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn large_aof_values_are_compressed_and_replayed() {
+        let (aof_path, _) = temp_paths();
+        let blob = br#"{"status":"active","tags":["a","b","c"]}"#.repeat(100);
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof")
+            .with_compression(Compression::Zstd, 256);
+        let store = Store::new(aof, None).await.expect("new store");
+        let _ = store
+            .set(b"doc".to_vec(), blob.clone(), None, SetCondition::None)
+            .await
+            .expect("set doc");
+        let _ = store
+            .set(b"small".to_vec(), b"v".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set small");
+        drop(store);
+        assert!(std::fs::metadata(&aof_path).expect("metadata").len() < blob.len() as u64 / 4);
+
+        // Replay does not depend on compression being configured.
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let store = Store::new(aof, None).await.expect("replay store");
+        assert_eq!(store.get(b"doc").await, Ok(Some(blob.into())));
+        assert_eq!(
+            store.get(b"small").await,
+            Ok(Some(Bytes::from_static(b"v")))
+        );
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn save_rules_follow_the_dirty_counter() {
+        let (aof_path, snapshot_path) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, Some(snapshot_path))
+            .await
+            .expect("new store");
+        let rules = [
+            SaveRule {
+                seconds: 0,
+                changes: 3,
+            },
+            SaveRule {
+                seconds: 3600,
+                changes: 1,
+            },
+        ];
+        let started = now_ms() / 1000;
+
+        assert!(!store.save_due(&rules, started));
+        for key in [b"a", b"b"] {
+            let _ = store
+                .set(key.to_vec(), b"v".to_vec(), None, SetCondition::None)
+                .await
+                .expect("set");
+        }
+        assert_eq!(store.changes_since_last_save(), 2);
+        assert!(!store.save_due(&rules, started));
+        assert!(store.save_due(&rules, started - 3600));
+
+        let _ = store.del(&[b"a".to_vec()]).await.expect("del");
+        assert!(store.save_due(&rules, started));
+        store.save_snapshot_now().await.expect("save");
+        assert_eq!(store.changes_since_last_save(), 0);
+        assert!(!store.save_due(&rules, 0));
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn always_fsync_commits_concurrent_writes_before_acking() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        let writers: Vec<_> = (0..64)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .set(
+                            format!("k{i}").into_bytes(),
+                            b"v".to_vec(),
+                            None,
+                            SetCondition::None,
+                        )
+                        .await
+                        .is_ok_and(|set| set)
+                })
+            })
+            .collect();
+        for writer in writers {
+            assert!(writer.await.expect("join"));
+        }
+
+        // Acknowledged means in the file already: no sync before reading it.
+        let metrics = store.persistence_metrics();
+        assert_eq!(metrics.aof_pending_writes, 0);
+        assert!(metrics.aof_last_write_ok);
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        assert_eq!(aof.read_all().expect("read aof").len(), 64);
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn buffered_aof_writes_are_drained_by_sync() {
+        let (aof_path, _) = temp_paths();
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        for i in 0..500 {
+            let _ = store
+                .set(
+                    format!("k{}", i).into_bytes(),
+                    b"v".to_vec(),
+                    None,
+                    SetCondition::None,
+                )
+                .await
+                .expect("set");
+        }
+        store.sync_aof().await.expect("sync aof");
+        let metrics = store.persistence_metrics();
+        assert_eq!(metrics.aof_pending_writes, 0);
+        assert!(metrics.aof_last_fsync_age_ms < 60_000);
+        assert!(metrics.aof_last_write_ok);
+        assert!(metrics.aof_current_size > metrics.aof_base_size);
+
+        store.bgrewriteaof().await;
+        while store.persistence_metrics().rewrite_in_progress {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let metrics = store.persistence_metrics();
+        assert!(metrics.last_rewrite_ok);
+        assert_eq!(metrics.aof_base_size, metrics.aof_current_size);
+        drop(store);
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        assert_eq!(aof.read_all().expect("read aof").len(), 500);
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn recovery_replays_up_to_the_target_timestamp() {
+        let (aof_path, snapshot_path) = temp_paths();
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof")
+            .with_timestamps(true);
+        let store = Store::new(aof, None).await.expect("new store");
+        let _ = store
+            .set(b"k".to_vec(), b"v".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set");
+        drop(store);
+        let contents = std::fs::read(&aof_path).expect("read aof");
+        // Magic, then the first record's length, then its op: a timestamp.
+        assert_eq!(contents[10], 6);
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("reopen aof");
+        let store = Store::new(aof, None).await.expect("annotated replay");
+        assert_eq!(store.get(b"k").await, Ok(Some(Bytes::from_static(b"v"))));
+        drop(store);
+
+        let mut log = b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n#TS:100\r\n".to_vec();
+        log.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n#TS:200\r\n");
+        log.extend_from_slice(
+            b"*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n*2\r\n$3\r\nDEL\r\n$1\r\na\r\n",
+        );
+        std::fs::write(&aof_path, &log).expect("write aof");
+        write_snapshot(
+            &snapshot_path,
+            [(b"late".as_slice(), ValueType::String, b"x".as_slice(), None)].into_iter(),
+            None,
+            Compression::None,
+        )
+        .expect("write snapshot");
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Resp)
+            .await
+            .expect("open aof")
+            .with_recover_to(Some(50));
+        let err = Store::new(aof, Some(snapshot_path.clone()))
+            .await
+            .err()
+            .expect("target before the log");
+        assert!(
+            err.to_string()
+                .contains("older than the oldest AOF timestamp")
+        );
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Resp)
+            .await
+            .expect("open aof")
+            .with_recover_to(Some(150));
+        let store = Store::new(aof, Some(snapshot_path.clone()))
+            .await
+            .expect("recover");
+        assert_eq!(store.get(b"a").await, Ok(Some(Bytes::from_static(b"1"))));
+        assert_eq!(store.get(b"b").await, Ok(None));
+        assert_eq!(store.get(b"late").await, Ok(None));
+        drop(store);
+        assert_eq!(
+            std::fs::read(aof_path.with_extension("aof.before-recovery")).expect("backup"),
+            log
+        );
+
+        // The recovered state is what a normal restart sees.
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Resp)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, Some(snapshot_path.clone()))
+            .await
+            .expect("restart");
+        assert_eq!(store.get(b"a").await, Ok(Some(Bytes::from_static(b"1"))));
+        assert_eq!(store.get(b"late").await, Ok(None));
+        drop(store);
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Resp)
+            .await
+            .expect("open aof")
+            .with_recover_to(Some(150));
+        let err = Store::new(aof, Some(snapshot_path))
+            .await
+            .err()
+            .expect("second recovery");
+        assert!(err.to_string().contains("already exists"));
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn frozen_view_ignores_later_writes() {
+        let (aof_path, _) = temp_paths();
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        let _ = store
+            .set(b"k".to_vec(), b"old".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set");
+        let frozen = store.freeze().await;
+        let _ = store
+            .set(b"k".to_vec(), b"new".to_vec(), None, SetCondition::None)
+            .await
+            .expect("overwrite");
+        let _ = store
+            .set(b"other".to_vec(), b"v".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set other");
+
+        assert_eq!(
+            frozen_entries(&frozen).collect::<Vec<_>>(),
+            vec![(b"k".as_slice(), ValueType::String, b"old".as_slice(), None)]
+        );
+        assert_eq!(store.get(b"k").await, Ok(Some(Bytes::from_static(b"new"))));
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn export_writes_matching_keys() {
+        let (aof_path, _) = temp_paths();
+        let export_dir = aof_path.parent().expect("temp dir").join("export");
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("new store");
+        assert!(
+            store
+                .export("keys.ndjson", ExportFormat::Ndjson, None)
+                .await
+                .is_err()
+        );
+        let store = store.with_export_dir(Some(export_dir.clone()));
+        for (key, value) in [(b"user:1", b"a"), (b"user:2", b"b"), (b"other1", b"c")] {
+            let _ = store
+                .set(key.to_vec(), value.to_vec(), None, SetCondition::None)
+                .await
+                .expect("set");
+        }
+
+        let written = store
+            .export("users.csv", ExportFormat::Csv, Some(b"user:*".to_vec()))
+            .await
+            .expect("export");
+        assert_eq!(written, 2);
+        let csv = std::fs::read_to_string(export_dir.join("users.csv")).expect("read");
+        let mut rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.remove(0), crate::export::CSV_HEADER);
+        rows.sort();
+        assert_eq!(rows, ["user:1,string,,a", "user:2,string,,b"]);
+
+        let written = store
+            .export("all.ndjson", ExportFormat::Ndjson, None)
+            .await
+            .expect("export");
+        assert_eq!(written, 3);
+        let ndjson = std::fs::read_to_string(export_dir.join("all.ndjson")).expect("read");
+        let mut entries: Vec<SnapshotEntry> = ndjson
+            .lines()
+            .map(|line| crate::convert::parse_ndjson(line).expect("parse"))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries[0],
+            (b"other1".to_vec(), ValueType::String, b"c".to_vec(), None)
+        );
+
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn tiered_engine_spills_large_and_cold_values_and_reads_them_back() {
+        let (aof_path, _) = temp_paths();
+        let tier = TierConfig {
+            path: aof_path.with_file_name("test.tier"),
+            value_threshold: 1024,
+            hot_bytes: 64 * DEFAULT_SHARDS,
+        };
+
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::open(aof, None, StorageEngine::Tiered(tier.clone()))
+            .await
+            .expect("tiered store");
+        let big = vec![b'b'; 4096];
+        let _ = store
+            .set(b"big".to_vec(), big.clone(), None, SetCondition::None)
+            .await
+            .expect("set big");
+        let stats = store.tier_stats().expect("tier stats");
+        assert_eq!(stats.spilled_keys(), 1);
+        assert_eq!(stats.spilled_bytes(), 4096);
+        assert!(
+            store.used_memory() < 4096,
+            "spilled values are not resident"
+        );
+
+        for i in 0..200 {
+            let _ = store
+                .set(
+                    format!("k{i}").into_bytes(),
+                    vec![b'v'; 32],
+                    None,
+                    SetCondition::None,
+                )
+                .await
+                .expect("set small");
+        }
+        let spilled = stats.spilled_keys();
+        assert!(spilled > 1, "small values past the hot budget are spilled");
+        for i in 0..200 {
+            assert_eq!(
+                store.get(format!("k{i}").as_bytes()).await,
+                Ok(Some(Bytes::from(vec![b'v'; 32])))
+            );
+        }
+        assert_eq!(store.get(b"big").await, Ok(Some(Bytes::from(big.clone()))));
+        assert!(stats.disk_reads() >= spilled as u64);
+
+        let _ = store.del(&[b"big".to_vec()]).await.expect("del big");
+        assert_eq!(stats.spilled_keys(), spilled - 1);
+        store.sync_aof().await.expect("flush");
+        drop(store);
+
+        // The segments are scratch space: the AOF rebuilds the keyspace.
+        let aof = Aof::open(&aof_path, AofFsync::No, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::open(aof, None, StorageEngine::Tiered(tier))
+            .await
+            .expect("reopen tiered store");
+        assert_eq!(store.dbsize().await, 200);
+        assert_eq!(store.get(b"big").await, Ok(None));
+        assert_eq!(
+            store.get(b"k7").await,
+            Ok(Some(Bytes::from(vec![b'v'; 32])))
+        );
+
+        store.flush(false).await.expect("flush");
+        let stats = store.tier_stats().expect("tier stats");
+        assert_eq!((stats.spilled_keys(), stats.disk_bytes()), (0, 0));
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
+
+    #[tokio::test]
+    async fn sled_engine_imports_the_aof_once_and_persists_itself() {
+        let (aof_path, _) = temp_paths();
+        let sled_path = aof_path.with_file_name("test.sled");
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::new(aof, None).await.expect("memory store");
+        let _ = store
+            .set(b"old".to_vec(), b"1".to_vec(), None, SetCondition::None)
+            .await
+            .expect("set old");
+        drop(store);
+        let aof_len = std::fs::metadata(&aof_path).expect("aof metadata").len();
+
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::open(aof, None, StorageEngine::Sled(sled_path.clone()))
+            .await
+            .expect("sled store");
+        assert_eq!(store.get(b"old").await, Ok(Some(Bytes::from_static(b"1"))));
+        let _ = store
+            .set(
+                b"k".to_vec(),
+                b"v".to_vec(),
+                Some(now_ms() + 60_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set k");
+        assert!(store.persist(b"k").await.expect("persist"));
+        let _ = store.del(&[b"old".to_vec()]).await.expect("del old");
+        store
+            .set_typed(
+                b"doc".to_vec(),
+                ValueType::Json,
+                br#"{"n":1}"#.to_vec(),
+                Some(now_ms() + 60_000),
+                SetCondition::None,
+            )
+            .await
+            .expect("set doc");
+        assert_eq!(store.dbsize().await, 2);
+        let version = store.version(b"k").await.expect("version");
+        store.sync_aof().await.expect("flush");
+        drop(store);
+        assert_eq!(
+            std::fs::metadata(&aof_path).expect("aof metadata").len(),
+            aof_len
+        );
+
+        // Reopening does not import the AOF again, which would bring back `old`.
+        let aof = Aof::open(&aof_path, AofFsync::Always, None, AofFormat::Fedis)
+            .await
+            .expect("open aof");
+        let store = Store::open(aof, None, StorageEngine::Sled(sled_path))
+            .await
+            .expect("reopen sled store");
+        assert_eq!(store.get(b"old").await, Ok(None));
+        assert_eq!(store.get(b"k").await, Ok(Some(Bytes::from_static(b"v"))));
+        assert_eq!(store.ttl(b"k").await, -1);
+        // Versions are stored with the entries and keep rising after a restart.
+        assert_eq!(store.version(b"k").await, Some(version));
+        assert!(store.expire(b"k", 60).await.expect("expire"));
+        assert!(store.version(b"k").await > Some(version));
+        assert_eq!(store.key_type(b"doc").await, "ReJSON-RL");
+        assert!(store.ttl(b"doc").await > 0);
+        store.del(&[b"doc".to_vec()]).await.expect("del doc");
+        assert_eq!(store.keys(b"*").await, vec![b"k".to_vec()]);
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(aof_path.parent().expect("temp dir"));
+    }
 }
@@ -0,0 +1,297 @@
+//! A harness for tests against a real server without TCP or a subprocess:
+//! [`TestServer`] starts one in-process over a temporary data directory and
+//! hands out [`TestClient`]s, each an in-memory connection that speaks RESP
+//! exactly as a socket would.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use fedis::testing::TestServer;
+//!
+//! let server = TestServer::start().await;
+//! let mut client = server.client();
+//! client.assert_ok(&["SET", "greeting", "hello"]).await;
+//! client.assert_bulk(&["GET", "greeting"], Some("hello")).await;
+//! client.assert_error(&["INCR", "greeting"], "ERR value is not an integer").await;
+//! server.shutdown().await;
+//! # }
+//! ```
+//!
+//! The helpers panic on anything unexpected, so they read like `assert!`.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use crate::config::{Config, ConfigBuilder};
+use crate::protocol::{RespValue, encode};
+use crate::server::{Server, ServerHandle};
+
+/// How long `read_reply` waits before failing the test.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tells apart the data directories of servers started in the same process.
+static NEXT_SERVER: AtomicU64 = AtomicU64::new(0);
+
+/// A server started in-process, without a listener. Its data directory is
+/// removed when it is dropped.
+pub struct TestServer {
+    handle: Option<ServerHandle>,
+    data_dir: PathBuf,
+}
+
+impl TestServer {
+    /// A server with the default configuration.
+    pub async fn start() -> Self {
+        Self::with_config(Config::builder()).await
+    }
+
+    /// A server configured by `builder`, whose data path is replaced with a
+    /// fresh temporary directory.
+    pub async fn with_config(builder: ConfigBuilder) -> Self {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let data_dir = std::env::temp_dir().join(format!(
+            "fedis-test-{}-{}-{}",
+            std::process::id(),
+            NEXT_SERVER.fetch_add(1, Ordering::Relaxed),
+            stamp
+        ));
+        let config = builder
+            .data_path(&data_dir)
+            .build()
+            .expect("invalid test server configuration");
+        let server = Server::new(config)
+            .await
+            .expect("failed to open test server");
+        Self {
+            handle: Some(server.start_in_process()),
+            data_dir,
+        }
+    }
+
+    /// The running server, for its executor and store.
+    pub fn handle(&self) -> &ServerHandle {
+        self.handle.as_ref().expect("test server is running")
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// A new connection, with a session of its own.
+    pub fn client(&self) -> TestClient {
+        TestClient::new(self.handle().connect())
+    }
+
+    /// Stops the server and flushes its AOF; the directory stays until the
+    /// `TestServer` is dropped.
+    pub async fn shutdown(mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle
+                .shutdown()
+                .await
+                .expect("test server shutdown failed");
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// One client connection. Commands go out as RESP arrays of bulk strings
+/// and replies are parsed back into `RespValue`s, RESP3 maps included.
+pub struct TestClient {
+    stream: DuplexStream,
+    buf: Vec<u8>,
+}
+
+impl TestClient {
+    /// Wraps a stream from `ServerHandle::connect`.
+    pub fn new(stream: DuplexStream) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sends one command and waits for its reply.
+    pub async fn call(&mut self, args: &[&str]) -> RespValue {
+        self.send(args).await;
+        self.read_reply().await
+    }
+
+    /// `call` for arguments that are not UTF-8.
+    pub async fn call_bytes(&mut self, args: &[&[u8]]) -> RespValue {
+        self.send_bytes(args).await;
+        self.read_reply().await
+    }
+
+    /// Sends a command without waiting, to pipeline several before reading
+    /// their replies in order.
+    pub async fn send(&mut self, args: &[&str]) {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.send_bytes(&args).await;
+    }
+
+    pub async fn send_bytes(&mut self, args: &[&[u8]]) {
+        let command = RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg))))
+                .collect(),
+        );
+        self.send_raw(&encode(command)).await;
+    }
+
+    /// Writes `bytes` as they are, e.g. a malformed or partial frame.
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream
+            .write_all(bytes)
+            .await
+            .expect("test server connection closed");
+    }
+
+    /// The next reply; panics when none arrives in time, the connection
+    /// closes first or the reply is not valid RESP.
+    pub async fn read_reply(&mut self) -> RespValue {
+        loop {
+            match parse_reply(&self.buf, 0) {
+                Ok(Some((reply, end))) => {
+                    self.buf.drain(..end);
+                    return reply;
+                }
+                Ok(None) => {}
+                Err(e) => panic!("malformed reply from test server: {}", e),
+            }
+            let mut chunk = [0_u8; 4096];
+            let read = tokio::time::timeout(REPLY_TIMEOUT, self.stream.read(&mut chunk))
+                .await
+                .expect("no reply from test server")
+                .expect("test server connection failed");
+            if read == 0 {
+                panic!("test server closed the connection");
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Whether the server has closed this connection, e.g. after `QUIT`.
+    pub async fn is_closed(&mut self) -> bool {
+        let mut chunk = [0_u8; 1];
+        match tokio::time::timeout(REPLY_TIMEOUT, self.stream.read(&mut chunk)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => true,
+            Ok(Ok(_)) => {
+                self.buf.push(chunk[0]);
+                false
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub async fn assert_ok(&mut self, args: &[&str]) {
+        match self.call(args).await {
+            RespValue::Simple(status) if status == "OK" => {}
+            other => panic!("{:?}: expected OK, got {:?}", args, other),
+        }
+    }
+
+    pub async fn assert_int(&mut self, args: &[&str], expected: i64) {
+        match self.call(args).await {
+            RespValue::Integer(n) if n == expected => {}
+            other => panic!("{:?}: expected {}, got {:?}", args, expected, other),
+        }
+    }
+
+    /// `None` expects a nil reply.
+    pub async fn assert_bulk(&mut self, args: &[&str], expected: Option<&str>) {
+        let reply = self.call(args).await;
+        let matched = match (&reply, expected) {
+            (RespValue::Bulk(Some(value)), Some(expected)) => value == expected.as_bytes(),
+            (RespValue::Bulk(None), None) => true,
+            _ => false,
+        };
+        assert!(
+            matched,
+            "{:?}: expected {:?}, got {:?}",
+            args, expected, reply
+        );
+    }
+
+    /// Expects an error reply starting with `prefix`.
+    pub async fn assert_error(&mut self, args: &[&str], prefix: &str) {
+        match self.call(args).await {
+            RespValue::Error(message) if message.starts_with(prefix) => {}
+            other => panic!(
+                "{:?}: expected an error '{}...', got {:?}",
+                args, prefix, other
+            ),
+        }
+    }
+}
+
+/// The reply starting at `pos` and the position after it, or `None` while
+/// it is incomplete. Unlike the server's request parser this accepts every
+/// type the server sends, nested to any depth.
+fn parse_reply(buf: &[u8], pos: usize) -> Result<Option<(RespValue, usize)>, String> {
+    let Some(&kind) = buf.get(pos) else {
+        return Ok(None);
+    };
+    let Some(newline) = buf[pos..].windows(2).position(|w| w == b"\r\n") else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(&buf[pos + 1..pos + newline]).into_owned();
+    let mut next = pos + newline + 2;
+    let length = || {
+        line.parse::<i64>()
+            .map_err(|_| format!("invalid length '{}'", line))
+    };
+    let reply = match kind {
+        b'+' => RespValue::Simple(line.clone()),
+        b'-' => RespValue::Error(line.clone()),
+        b':' => RespValue::Integer(length()?),
+        b'$' => match usize::try_from(length()?) {
+            Err(_) => RespValue::Bulk(None),
+            Ok(len) => {
+                let Some(value) = buf.get(next..next + len + 2) else {
+                    return Ok(None);
+                };
+                if !value.ends_with(b"\r\n") {
+                    return Err("invalid bulk string ending".to_string());
+                }
+                next += len + 2;
+                RespValue::Bulk(Some(Bytes::copy_from_slice(&value[..len])))
+            }
+        },
+        b'*' | b'%' => {
+            let count = usize::try_from(length()?).map_err(|_| "negative length".to_string())?;
+            let elements = if kind == b'%' { count * 2 } else { count };
+            let mut items = Vec::with_capacity(elements);
+            for _ in 0..elements {
+                let Some((item, after)) = parse_reply(buf, next)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                next = after;
+            }
+            if kind == b'%' {
+                let mut items = items.into_iter();
+                let mut entries = Vec::with_capacity(count);
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    entries.push((key, value));
+                }
+                RespValue::Map(entries)
+            } else {
+                RespValue::Array(items)
+            }
+        }
+        other => return Err(format!("unknown reply type '{}'", other as char)),
+    };
+    Ok(Some((reply, next)))
+}
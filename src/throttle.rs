@@ -0,0 +1,125 @@
+/// A generic cell rate limit, as redis-cell's `CL.THROTTLE` applies it: the
+/// state is one theoretical arrival time (TAT) per key, in microseconds since
+/// the epoch, and a request is let through when it would not push the TAT
+/// further than the burst tolerance ahead of now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gcra {
+    /// Microseconds one request uses up: the period over its count.
+    emission: u64,
+    /// How far ahead of now the TAT may run: `max_burst + 1` emissions.
+    tolerance: u64,
+    limit: u64,
+}
+
+/// The outcome of one `THROTTLE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub limited: bool,
+    /// `max_burst + 1`, the most requests allowed at once.
+    pub limit: u64,
+    pub remaining: u64,
+    /// When a refused request could pass, in microseconds from now; `None`
+    /// when it was allowed, or when it asked for more than the burst.
+    pub retry_after: Option<u64>,
+    /// When the key is back to a full burst, in microseconds from now.
+    pub reset_after: u64,
+    /// The TAT to store when the request was allowed.
+    pub tat: Option<u64>,
+}
+
+impl Gcra {
+    /// `count` requests per `period_secs`, with bursts of `max_burst` more.
+    /// `None` unless `count` and the period are positive.
+    pub fn new(max_burst: u64, count: u64, period_secs: u64) -> Option<Self> {
+        let period = period_secs.checked_mul(1_000_000)?;
+        if count == 0 || period == 0 {
+            return None;
+        }
+        let emission = (period / count).max(1);
+        let limit = max_burst.checked_add(1)?;
+        Some(Self {
+            emission,
+            tolerance: emission.checked_mul(limit)?,
+            limit,
+        })
+    }
+
+    /// Charges `quantity` requests at `now` to a key whose stored TAT is `tat`.
+    pub fn apply(&self, tat: Option<u64>, now: u64, quantity: u64) -> Decision {
+        let tat = tat.unwrap_or(now).max(now);
+        let increment = self.emission.saturating_mul(quantity);
+        let new_tat = tat.saturating_add(increment);
+        let allow_at = new_tat.saturating_sub(self.tolerance);
+
+        let (limited, retry_after, stored) = if now < allow_at {
+            let retry = (increment <= self.tolerance).then_some(allow_at - now);
+            (true, retry, tat)
+        } else {
+            (false, None, new_tat)
+        };
+        let reset_after = stored - now;
+        Decision {
+            limited,
+            limit: self.limit,
+            remaining: self.tolerance.saturating_sub(reset_after) / self.emission,
+            retry_after,
+            reset_after,
+            tat: (!limited).then_some(stored),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1_000_000;
+
+    #[test]
+    fn bursts_then_refills_at_the_rate() {
+        // 1 per second with 2 more in a burst: three at once, then one a second.
+        let gcra = Gcra::new(2, 1, 1).expect("valid rate");
+        let mut tat = None;
+        let at = |now: u64, tat: &mut Option<u64>| {
+            let decision = gcra.apply(*tat, now, 1);
+            *tat = decision.tat.or(*tat);
+            decision
+        };
+        for remaining in [2, 1, 0] {
+            let decision = at(0, &mut tat);
+            assert!(!decision.limited);
+            assert_eq!((decision.limit, decision.remaining), (3, remaining));
+        }
+        let refused = at(0, &mut tat);
+        assert!(refused.limited);
+        assert_eq!(refused.retry_after, Some(SEC));
+        assert_eq!(refused.reset_after, 3 * SEC);
+
+        assert!(!at(SEC, &mut tat).limited);
+        assert!(at(SEC, &mut tat).limited);
+        let later = at(10 * SEC, &mut tat);
+        assert!(!later.limited);
+        assert_eq!(later.remaining, 2);
+    }
+
+    #[test]
+    fn quantity_past_the_burst_never_passes() {
+        let gcra = Gcra::new(4, 10, 1).expect("valid rate");
+        let decision = gcra.apply(None, 0, 6);
+        assert!(decision.limited);
+        assert_eq!(decision.retry_after, None);
+        assert_eq!(decision.remaining, 5);
+
+        let decision = gcra.apply(None, 0, 5);
+        assert!(!decision.limited);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.reset_after, SEC / 2);
+    }
+
+    #[test]
+    fn rejects_empty_rates() {
+        assert_eq!(Gcra::new(1, 0, 1), None);
+        assert_eq!(Gcra::new(1, 1, 0), None);
+        assert_eq!(Gcra::new(u64::MAX, 1, 1), None);
+    }
+}
@@ -0,0 +1,376 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use serde_json::Value as JsonValue;
+
+use crate::backend::{Garbage, ShardBackend, ShardMap, ValueEntry};
+use crate::encoding::ValueType;
+
+/// Values at least this large are spilled when written, unless configured.
+pub const DEFAULT_VALUE_THRESHOLD: usize = 16 * 1024;
+/// In-memory value budget of all shards, unless configured.
+pub const DEFAULT_HOT_BYTES: usize = 256 * 1024 * 1024;
+/// Segment garbage below this is never worth a compaction.
+const COMPACT_MIN_DEAD_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Where the tiered engine spills values and when.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TierConfig {
+    /// Directory holding one segment file per shard.
+    pub path: PathBuf,
+    /// Values at least this large go to disk as soon as they are written.
+    pub value_threshold: usize,
+    /// Value bytes all shards together keep in memory; past it, the least
+    /// recently used values are spilled.
+    pub hot_bytes: usize,
+}
+
+/// Counters the tiered shards share, for `INFO` and `maxmemory`.
+#[derive(Default)]
+pub struct TierStats {
+    spilled_keys: AtomicUsize,
+    spilled_bytes: AtomicUsize,
+    disk_bytes: AtomicU64,
+    disk_reads: AtomicU64,
+}
+
+impl TierStats {
+    /// Keys whose value is on disk.
+    pub fn spilled_keys(&self) -> usize {
+        self.spilled_keys.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the values on disk; memory accounting leaves them out.
+    pub fn spilled_bytes(&self) -> usize {
+        self.spilled_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Size of the segment files, garbage included.
+    pub fn disk_bytes(&self) -> u64 {
+        self.disk_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Values read back from disk.
+    pub fn disk_reads(&self) -> u64 {
+        self.disk_reads.load(Ordering::Relaxed)
+    }
+}
+
+/// A shard that keeps every key, expiry and access time in memory but only
+/// some values: large ones and, once the shard's share of the hot budget is
+/// used up, the least recently used ones live in an append-only segment file
+/// and are read back on access. The AOF and snapshots stay the source of
+/// truth, so the segment is scratch space, emptied when the shard opens.
+/// Segment I/O failures abort, like sled's.
+pub struct TieredShard {
+    entries: HashMap<Vec<u8>, Slot>,
+    segment: Segment,
+    /// Bytes of the values this shard keeps in memory.
+    hot_bytes: usize,
+    budget: usize,
+    threshold: usize,
+    /// Ticks on every access; a slot remembers the tick it was last used at.
+    clock: AtomicU64,
+    stats: Arc<TierStats>,
+}
+
+struct Slot {
+    value: Tier,
+    /// JSON documents and time series stay hot; only strings are spilled.
+    kind: ValueType,
+    json: Option<Arc<JsonValue>>,
+    expires_at: Option<u64>,
+    version: u64,
+    last_access: AtomicU64,
+}
+
+enum Tier {
+    Hot(Bytes),
+    Cold { offset: u64, len: usize },
+}
+
+struct Segment {
+    path: PathBuf,
+    file: File,
+    end: u64,
+    /// Bytes of values that were overwritten or removed since they were appended.
+    dead: u64,
+}
+
+impl Segment {
+    fn create(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self {
+            path,
+            file,
+            end: 0,
+            dead: 0,
+        })
+    }
+
+    fn append(&mut self, value: &[u8]) -> u64 {
+        let offset = self.end;
+        self.file
+            .write_all_at(value, offset)
+            .expect("tier segment write failed");
+        self.end += value.len() as u64;
+        offset
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Bytes {
+        let mut buf = vec![0; len];
+        self.file
+            .read_exact_at(&mut buf, offset)
+            .expect("tier segment read failed");
+        buf.into()
+    }
+
+    fn truncate(&mut self) {
+        self.file.set_len(0).expect("tier segment truncate failed");
+        self.end = 0;
+        self.dead = 0;
+    }
+}
+
+impl TieredShard {
+    pub fn open(
+        config: &TierConfig,
+        idx: usize,
+        shards: usize,
+        stats: Arc<TierStats>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&config.path)
+            .map_err(|e| format!("{}: {}", config.path.display(), e))?;
+        let segment = Segment::create(segment_path(&config.path, idx))?;
+        Ok(Self {
+            entries: HashMap::new(),
+            segment,
+            hot_bytes: 0,
+            budget: config.hot_bytes / shards.max(1),
+            threshold: config.value_threshold,
+            clock: AtomicU64::new(0),
+            stats,
+        })
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn load(&self, slot: &Slot) -> Bytes {
+        match &slot.value {
+            Tier::Hot(value) => value.clone(),
+            Tier::Cold { offset, len } => {
+                self.stats.disk_reads.fetch_add(1, Ordering::Relaxed);
+                self.segment.read(*offset, *len)
+            }
+        }
+    }
+
+    fn entry(&self, slot: &Slot) -> ValueEntry {
+        ValueEntry {
+            value: self.load(slot),
+            kind: slot.kind,
+            json: slot.json.clone(),
+            expires_at: slot.expires_at,
+            version: slot.version,
+        }
+    }
+
+    fn spill(&mut self, value: &[u8]) -> Tier {
+        let offset = self.segment.append(value);
+        self.stats
+            .disk_bytes
+            .fetch_add(value.len() as u64, Ordering::Relaxed);
+        self.stats.spilled_keys.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .spilled_bytes
+            .fetch_add(value.len(), Ordering::Relaxed);
+        Tier::Cold {
+            offset,
+            len: value.len(),
+        }
+    }
+
+    /// Drops the bookkeeping of a value leaving the shard.
+    fn forget(&mut self, tier: &Tier) {
+        match tier {
+            Tier::Hot(value) => self.hot_bytes -= value.len(),
+            Tier::Cold { len, .. } => {
+                self.segment.dead += *len as u64;
+                self.stats.spilled_keys.fetch_sub(1, Ordering::Relaxed);
+                self.stats.spilled_bytes.fetch_sub(*len, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Spills the least recently used hot values until the shard is a quarter
+    /// below its budget, so one pass over the keys pays for many inserts.
+    fn evict(&mut self) {
+        if self.hot_bytes <= self.budget {
+            return;
+        }
+        let target = self.budget - self.budget / 4;
+        let mut candidates: Vec<(u64, Vec<u8>)> = self
+            .entries
+            .iter()
+            .filter(|(_, slot)| {
+                matches!(slot.value, Tier::Hot(_)) && slot.kind == ValueType::String
+            })
+            .map(|(key, slot)| (slot.last_access.load(Ordering::Relaxed), key.clone()))
+            .collect();
+        candidates.sort_unstable();
+        for (_, key) in candidates {
+            if self.hot_bytes <= target {
+                break;
+            }
+            let Some(Tier::Hot(value)) = self.entries.get(&key).map(|slot| &slot.value) else {
+                continue;
+            };
+            let value = value.clone();
+            let cold = self.spill(&value);
+            self.hot_bytes -= value.len();
+            if let Some(slot) = self.entries.get_mut(&key) {
+                slot.value = cold;
+            }
+        }
+    }
+
+    /// Rewrites the segment without its garbage once garbage outweighs the
+    /// live values in it.
+    fn compact(&mut self) {
+        let live = self.segment.end - self.segment.dead;
+        if self.segment.dead < COMPACT_MIN_DEAD_BYTES || self.segment.dead < live {
+            return;
+        }
+        let tmp_path = self.segment.path.with_extension("compact");
+        let mut tmp = Segment::create(tmp_path.clone()).expect("tier segment compaction failed");
+        for slot in self.entries.values_mut() {
+            if let Tier::Cold { offset, len } = slot.value {
+                let value = self.segment.read(offset, len);
+                slot.value = Tier::Cold {
+                    offset: tmp.append(&value),
+                    len,
+                };
+            }
+        }
+        tmp.file.flush().expect("tier segment compaction failed");
+        std::fs::rename(&tmp_path, &self.segment.path).expect("tier segment compaction failed");
+        self.stats
+            .disk_bytes
+            .fetch_sub(self.segment.end - tmp.end, Ordering::Relaxed);
+        tmp.path = self.segment.path.clone();
+        self.segment = tmp;
+    }
+
+    fn reset(&mut self) {
+        self.stats
+            .disk_bytes
+            .fetch_sub(self.segment.end, Ordering::Relaxed);
+        for slot in self.entries.values() {
+            if let Tier::Cold { len, .. } = slot.value {
+                self.stats.spilled_keys.fetch_sub(1, Ordering::Relaxed);
+                self.stats.spilled_bytes.fetch_sub(len, Ordering::Relaxed);
+            }
+        }
+        self.segment.truncate();
+        self.hot_bytes = 0;
+    }
+}
+
+fn segment_path(dir: &Path, idx: usize) -> PathBuf {
+    dir.join(format!("shard-{}.seg", idx))
+}
+
+impl ShardBackend for TieredShard {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, ValueEntry>> {
+        let slot = self.entries.get(key)?;
+        slot.last_access.store(self.tick(), Ordering::Relaxed);
+        Some(Cow::Owned(self.entry(slot)))
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: ValueEntry) -> Option<ValueEntry> {
+        let previous = self.entries.remove(&key).map(|slot| {
+            let entry = self.entry(&slot);
+            self.forget(&slot.value);
+            entry
+        });
+        let value = if entry.kind == ValueType::String && entry.value.len() >= self.threshold {
+            self.spill(&entry.value)
+        } else {
+            self.hot_bytes += entry.value.len();
+            Tier::Hot(entry.value)
+        };
+        let slot = Slot {
+            value,
+            kind: entry.kind,
+            json: entry.json,
+            expires_at: entry.expires_at,
+            version: entry.version,
+            last_access: AtomicU64::new(self.tick()),
+        };
+        self.entries.insert(key, slot);
+        self.evict();
+        self.compact();
+        previous
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<ValueEntry> {
+        let slot = self.entries.remove(key)?;
+        let entry = self.entry(&slot);
+        self.forget(&slot.value);
+        self.compact();
+        Some(entry)
+    }
+
+    fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>, version: u64) -> bool {
+        match self.entries.get_mut(key) {
+            Some(slot) => {
+                slot.expires_at = expires_at;
+                slot.version = version;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn clear(&mut self) {
+        self.reset();
+        self.entries.clear();
+    }
+
+    fn detach(&mut self) -> Garbage {
+        self.reset();
+        Box::new(std::mem::take(&mut self.entries))
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&[u8], &ValueEntry)) {
+        for (key, slot) in &self.entries {
+            visit(key, &self.entry(slot));
+        }
+    }
+
+    fn freeze(&self) -> ShardMap {
+        self.entries
+            .iter()
+            .map(|(key, slot)| (key.clone(), self.entry(slot)))
+            .collect()
+    }
+}
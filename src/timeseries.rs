@@ -0,0 +1,305 @@
+use bytes::{Bytes, BytesMut};
+
+/// A time series is kept as its value bytes: the retention in milliseconds,
+/// then `(timestamp, value)` samples ascending by timestamp, all
+/// little-endian. New samples usually come last, so adding one appends 16
+/// bytes to a buffer nothing else holds, and a range is two binary searches.
+const HEADER: usize = 8;
+const SAMPLE: usize = 16;
+
+/// A failed time series command, as the reply it turns into.
+#[derive(Debug, PartialEq)]
+pub enum TsError {
+    /// The key holds a value that is not a time series.
+    WrongType,
+    /// The key does not exist and the command does not create it.
+    NoKey,
+    Exists,
+    /// A sample at that timestamp exists; like RedisTimeSeries' default
+    /// `BLOCK` policy, the first one is kept.
+    Duplicate,
+    /// Older than the retention window behind the newest sample.
+    TooOld,
+    /// Writing the change to the log failed.
+    Internal(String),
+}
+
+impl TsError {
+    pub fn reply(&self) -> String {
+        match self {
+            TsError::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            }
+            TsError::NoKey => "ERR TSDB: the key does not exist".to_string(),
+            TsError::Exists => "ERR TSDB: key already exists".to_string(),
+            TsError::Duplicate => "ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode".to_string(),
+            TsError::TooOld => "ERR TSDB: Timestamp is older than retention".to_string(),
+            TsError::Internal(e) => format!("ERR internal: {}", e),
+        }
+    }
+}
+
+/// An empty series keeping `retention` ms of samples behind its newest one;
+/// 0 keeps them all.
+pub fn new(retention: u64) -> Bytes {
+    Bytes::copy_from_slice(&retention.to_le_bytes())
+}
+
+/// Checks the layout of a series loaded from disk or sent by a peer.
+pub fn validate(value: &[u8]) -> Result<(), String> {
+    if value.len() < HEADER || !(value.len() - HEADER).is_multiple_of(SAMPLE) {
+        return Err("invalid time series: truncated".to_string());
+    }
+    let series = Series::new(value);
+    if (1..series.len()).any(|idx| series.sample(idx - 1).0 >= series.sample(idx).0) {
+        return Err("invalid time series: samples out of order".to_string());
+    }
+    Ok(())
+}
+
+/// Adds a sample `Series::check` allowed. The bytes are extended in place
+/// when nothing else shares them and copied otherwise; samples past the
+/// retention are dropped once they make up half of the series, and skipped
+/// by reads until then.
+pub fn add(value: Bytes, timestamp: u64, sample: f64) -> Bytes {
+    let mut bytes = BytesMut::from(value);
+    let end = bytes.len();
+    let at = HEADER + Series::new(&bytes).position(timestamp) * SAMPLE;
+    bytes.extend_from_slice(&timestamp.to_le_bytes());
+    bytes.extend_from_slice(&sample.to_le_bytes());
+    if at < end {
+        bytes.copy_within(at..end, at + SAMPLE);
+        bytes[at..at + 8].copy_from_slice(&timestamp.to_le_bytes());
+        bytes[at + 8..at + SAMPLE].copy_from_slice(&sample.to_le_bytes());
+    }
+
+    let series = Series::new(&bytes);
+    let expired = series.position(series.cutoff());
+    if expired > 0 && expired * 2 >= series.len() {
+        let mut kept = BytesMut::with_capacity(bytes.len() - expired * SAMPLE);
+        kept.extend_from_slice(&bytes[..HEADER]);
+        kept.extend_from_slice(&bytes[HEADER + expired * SAMPLE..]);
+        bytes = kept;
+    }
+    bytes.freeze()
+}
+
+/// A read-only view of a series' bytes.
+#[derive(Clone, Copy)]
+pub struct Series<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Series<'a> {
+    /// `bytes` must hold a series, as the keyspace's do.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn retention(&self) -> u64 {
+        u64::from_le_bytes(self.bytes[..HEADER].try_into().expect("series header"))
+    }
+
+    /// The newest sample.
+    pub fn last(&self) -> Option<(u64, f64)> {
+        self.len().checked_sub(1).map(|idx| self.sample(idx))
+    }
+
+    /// Whether a sample at `timestamp` may be added.
+    pub fn check(&self, timestamp: u64) -> Result<(), TsError> {
+        let Some((newest, _)) = self.last() else {
+            return Ok(());
+        };
+        if self.retention() > 0 && timestamp < newest.saturating_sub(self.retention()) {
+            return Err(TsError::TooOld);
+        }
+        let idx = self.position(timestamp);
+        if idx < self.len() && self.sample(idx).0 == timestamp {
+            return Err(TsError::Duplicate);
+        }
+        Ok(())
+    }
+
+    /// The samples from `from` to `to`, both included, oldest first.
+    pub fn range(&self, from: u64, to: u64) -> impl Iterator<Item = (u64, f64)> + 'a {
+        let series = *self;
+        let start = self.position(from.max(self.cutoff()));
+        let end = to
+            .checked_add(1)
+            .map_or(self.len(), |after| self.position(after));
+        (start..end.max(start)).map(move |idx| series.sample(idx))
+    }
+
+    /// Samples stored, including those past the retention not dropped yet.
+    fn len(&self) -> usize {
+        (self.bytes.len() - HEADER) / SAMPLE
+    }
+
+    fn sample(&self, idx: usize) -> (u64, f64) {
+        let at = HEADER + idx * SAMPLE;
+        let timestamp = u64::from_le_bytes(self.bytes[at..at + 8].try_into().expect("8 bytes"));
+        let value =
+            f64::from_le_bytes(self.bytes[at + 8..at + SAMPLE].try_into().expect("8 bytes"));
+        (timestamp, value)
+    }
+
+    /// How many samples are older than `timestamp`.
+    fn position(&self, timestamp: u64) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.sample(mid).0 < timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    /// Samples before this timestamp are past the retention.
+    fn cutoff(&self) -> u64 {
+        match self.last() {
+            Some((newest, _)) if self.retention() > 0 => newest.saturating_sub(self.retention()),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    Avg,
+    Min,
+    Max,
+    Sum,
+}
+
+impl Aggregation {
+    pub fn parse(name: &[u8]) -> Option<Self> {
+        match name.to_ascii_lowercase().as_slice() {
+            b"avg" => Some(Self::Avg),
+            b"min" => Some(Self::Min),
+            b"max" => Some(Self::Max),
+            b"sum" => Some(Self::Sum),
+            _ => None,
+        }
+    }
+}
+
+/// Folds `samples`, oldest first, into buckets of `bucket` ms aligned to the
+/// epoch, each stamped with the time it starts at. Empty buckets are left out.
+pub fn aggregate(
+    samples: impl Iterator<Item = (u64, f64)>,
+    aggregation: Aggregation,
+    bucket: u64,
+) -> Vec<(u64, f64)> {
+    let finish = |(start, total, count): (u64, f64, usize)| match aggregation {
+        Aggregation::Avg => (start, total / count as f64),
+        _ => (start, total),
+    };
+    let mut out = Vec::new();
+    let mut current: Option<(u64, f64, usize)> = None;
+    for (timestamp, value) in samples {
+        let start = timestamp - timestamp % bucket;
+        match &mut current {
+            Some((at, total, count)) if *at == start => {
+                *total = match aggregation {
+                    Aggregation::Min => total.min(value),
+                    Aggregation::Max => total.max(value),
+                    Aggregation::Avg | Aggregation::Sum => *total + value,
+                };
+                *count += 1;
+            }
+            _ => {
+                out.extend(current.take().map(finish));
+                current = Some((start, value, 1));
+            }
+        }
+    }
+    out.extend(current.map(finish));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(retention: u64, samples: &[(u64, f64)]) -> Bytes {
+        samples
+            .iter()
+            .fold(new(retention), |value, (timestamp, sample)| {
+                add(value, *timestamp, *sample)
+            })
+    }
+
+    #[test]
+    fn samples_stay_ordered_and_unique() {
+        let value = series(0, &[(10, 1.0), (30, 3.0), (20, 2.0), (40, 4.0)]);
+        validate(&value).expect("valid series");
+        let view = Series::new(&value);
+        assert_eq!(
+            view.range(0, u64::MAX).collect::<Vec<_>>(),
+            vec![(10, 1.0), (20, 2.0), (30, 3.0), (40, 4.0)]
+        );
+        assert_eq!(
+            view.range(15, 30).collect::<Vec<_>>(),
+            vec![(20, 2.0), (30, 3.0)]
+        );
+        assert_eq!(view.range(41, 50).count(), 0);
+        assert_eq!(view.last(), Some((40, 4.0)));
+        assert_eq!(view.check(20), Err(TsError::Duplicate));
+        assert_eq!(view.check(25), Ok(()));
+
+        assert!(validate(&value[..value.len() - 1]).is_err());
+        let mut swapped = value.to_vec();
+        swapped[8..24].copy_from_slice(&value[24..40]);
+        assert!(validate(&swapped).is_err());
+    }
+
+    #[test]
+    fn appends_reuse_an_unshared_buffer() {
+        // With room to spare, so that growing cannot move the buffer.
+        let mut spare = BytesMut::with_capacity(256);
+        spare.extend_from_slice(&series(0, &[(1, 1.0)]));
+        let value = spare.freeze();
+        let before = value.as_ptr();
+        let value = add(value, 2, 2.0);
+        assert_eq!(value.as_ptr(), before);
+
+        let shared = value.clone();
+        let grown = add(value, 3, 3.0);
+        assert_eq!(Series::new(&shared).range(0, 10).count(), 2);
+        assert_eq!(Series::new(&grown).range(0, 10).count(), 3);
+    }
+
+    #[test]
+    fn retention_hides_then_drops_old_samples() {
+        let value = series(100, &[(0, 0.0), (50, 1.0), (120, 2.0)]);
+        let view = Series::new(&value);
+        assert_eq!(
+            view.range(0, 200).collect::<Vec<_>>(),
+            vec![(50, 1.0), (120, 2.0)]
+        );
+        assert_eq!(view.check(10), Err(TsError::TooOld));
+        assert_eq!(value.len(), HEADER + 3 * SAMPLE);
+
+        let value = add(value, 300, 3.0);
+        assert_eq!(value.len(), HEADER + SAMPLE);
+        assert_eq!(Series::new(&value).retention(), 100);
+    }
+
+    #[test]
+    fn buckets_aggregate_from_the_epoch() {
+        let samples = [(1, 1.0), (9, 3.0), (10, 10.0), (25, 4.0), (29, 8.0)];
+        let run = |aggregation| aggregate(samples.iter().copied(), aggregation, 10);
+        assert_eq!(run(Aggregation::Avg), vec![(0, 2.0), (10, 10.0), (20, 6.0)]);
+        assert_eq!(run(Aggregation::Min), vec![(0, 1.0), (10, 10.0), (20, 4.0)]);
+        assert_eq!(run(Aggregation::Max), vec![(0, 3.0), (10, 10.0), (20, 8.0)]);
+        assert_eq!(
+            run(Aggregation::Sum),
+            vec![(0, 4.0), (10, 10.0), (20, 12.0)]
+        );
+        assert_eq!(Aggregation::parse(b"AVG"), Some(Aggregation::Avg));
+        assert_eq!(Aggregation::parse(b"twa"), None);
+    }
+}
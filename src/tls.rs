@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use x509_parser::extensions::GeneralName;
+
+/// Mirrors Redis' `tls-auth-clients`: whether a client certificate is requested
+/// and whether the handshake fails without one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    No,
+    Optional,
+    Yes,
+}
+
+/// Which certificate field names the ACL user for certificate-based login.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsClientUser {
+    Off,
+    Cn,
+    San,
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsSettings {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub ca_cert_file: Option<PathBuf>,
+    pub auth_clients: TlsAuthClients,
+    pub client_user: TlsClientUser,
+}
+
+pub fn parse_auth_clients(raw: &str) -> Result<TlsAuthClients, Box<dyn std::error::Error>> {
+    match raw.to_ascii_lowercase().as_str() {
+        "no" => Ok(TlsAuthClients::No),
+        "optional" => Ok(TlsAuthClients::Optional),
+        "yes" => Ok(TlsAuthClients::Yes),
+        _ => Err("FEDIS_TLS_AUTH_CLIENTS must be one of: no, optional, yes".into()),
+    }
+}
+
+pub fn parse_client_user(raw: &str) -> Result<TlsClientUser, Box<dyn std::error::Error>> {
+    match raw.to_ascii_lowercase().as_str() {
+        "off" => Ok(TlsClientUser::Off),
+        "cn" => Ok(TlsClientUser::Cn),
+        "san" => Ok(TlsClientUser::San),
+        _ => Err("FEDIS_TLS_AUTH_CLIENTS_USER must be one of: off, cn, san".into()),
+    }
+}
+
+pub fn build_acceptor(settings: &TlsSettings) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let certs = load_certs(&settings.cert_file)?;
+    let key = PrivateKeyDer::from_pem_file(&settings.key_file)
+        .map_err(|e| format!("{}: {}", settings.key_file.display(), e))?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match settings.auth_clients {
+        TlsAuthClients::No => builder.with_no_client_auth(),
+        mode => {
+            let ca_file = settings
+                .ca_cert_file
+                .as_ref()
+                .ok_or("FEDIS_TLS_CA_CERT_FILE is required when client certificates are enabled")?;
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_file)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if mode == TlsAuthClients::Optional {
+                verifier.allow_unauthenticated().build()?
+            } else {
+                verifier.build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+
+    let config = builder.with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A connector for reaching this server from the same box, as `fedis ping`
+/// does: it trusts exactly the configured certificate, whatever names it
+/// carries, and presents it as the client certificate when clients need one.
+pub fn build_self_connector(
+    settings: &TlsSettings,
+) -> Result<TlsConnector, Box<dyn std::error::Error>> {
+    let certs = load_certs(&settings.cert_file)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = PinnedCertificate {
+        cert: certs[0].clone(),
+        provider: provider.clone(),
+    };
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let config = match settings.auth_clients {
+        TlsAuthClients::No => builder.with_no_client_auth(),
+        _ => {
+            let key = PrivateKeyDer::from_pem_file(&settings.key_file)
+                .map_err(|e| format!("{}: {}", settings.key_file.display(), e))?;
+            builder.with_client_auth_cert(certs, key)?
+        }
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Accepts one certificate and nothing else; signatures are still checked.
+#[derive(Debug)]
+struct PinnedCertificate {
+    cert: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Box<dyn std::error::Error>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()).into());
+    }
+    Ok(certs)
+}
+
+/// Candidate ACL user names carried by a verified client certificate, in the
+/// order they should be tried.
+pub fn certificate_user_names(cert: &[u8], mode: TlsClientUser) -> Vec<String> {
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert) else {
+        return Vec::new();
+    };
+    match mode {
+        TlsClientUser::Off => Vec::new(),
+        TlsClientUser::Cn => cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .map(ToString::to_string)
+            .collect(),
+        TlsClientUser::San => {
+            let Ok(Some(san)) = cert.subject_alternative_name() else {
+                return Vec::new();
+            };
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(v) | GeneralName::RFC822Name(v) | GeneralName::URI(v) => {
+                        Some(v.to_string())
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_cert() -> CertificateDer<'static> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls/client.pem");
+        load_certs(&path).expect("client cert").remove(0)
+    }
+
+    #[test]
+    fn certificate_user_names_follow_configured_field() {
+        let cert = client_cert();
+        assert_eq!(
+            certificate_user_names(&cert, TlsClientUser::Cn),
+            vec!["alice".to_string()]
+        );
+        assert_eq!(
+            certificate_user_names(&cert, TlsClientUser::San),
+            vec!["svc-billing".to_string()]
+        );
+        assert!(certificate_user_names(&cert, TlsClientUser::Off).is_empty());
+    }
+}
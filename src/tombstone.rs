@@ -0,0 +1,148 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::backend::{Garbage, ValueEntry};
+
+/// Keys `DEL` and `UNLINK` removed, kept for `UNDELETE` until the retention
+/// from `FEDIS_SOFT_DELETE_RETENTION_SEC` passed: soft delete in
+/// non_redis_mode. Only this node's memory holds them, so a restart or a
+/// failover loses them.
+pub(crate) struct Tombstones {
+    retention_ms: u64,
+    inner: std::sync::Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    buried: HashMap<Vec<u8>, Tombstone>,
+    /// `(deleted_at, key)` in deletion order, which one retention for all
+    /// makes purge order too. A key deleted again leaves its older element
+    /// behind, told apart by `deleted_at`.
+    order: VecDeque<(u64, Vec<u8>)>,
+    bytes: usize,
+}
+
+struct Tombstone {
+    entry: ValueEntry,
+    deleted_at: u64,
+}
+
+/// For `INFO memory`.
+pub struct TombstoneMetrics {
+    pub keys: usize,
+    /// Key and value bytes.
+    pub bytes: usize,
+}
+
+impl Tombstones {
+    pub(crate) fn new(retention: Duration) -> Self {
+        Self {
+            retention_ms: retention.as_millis() as u64,
+            inner: std::sync::Mutex::new(Inner::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("tombstone lock")
+    }
+
+    /// Keeps `entry`, deleted at `now`; returns the tombstone it replaces,
+    /// from an earlier delete of the same key.
+    pub(crate) fn bury(&self, key: &[u8], entry: ValueEntry, now: u64) -> Option<ValueEntry> {
+        let mut inner = self.lock();
+        inner.bytes += key.len() + entry.value.len();
+        inner.order.push_back((now, key.to_vec()));
+        let previous = inner.buried.insert(
+            key.to_vec(),
+            Tombstone {
+                entry,
+                deleted_at: now,
+            },
+        )?;
+        inner.bytes -= key.len() + previous.entry.value.len();
+        Some(previous.entry)
+    }
+
+    /// Takes the entry last deleted as `key` back out; `None` when there is
+    /// none, or its retention or its own expiry passed by `now`.
+    pub(crate) fn dig_up(&self, key: &[u8], now: u64) -> Option<ValueEntry> {
+        let mut inner = self.lock();
+        let tombstone = inner.buried.remove(key)?;
+        inner.bytes -= key.len() + tombstone.entry.value.len();
+        let live = tombstone.deleted_at + self.retention_ms > now
+            && tombstone.entry.expires_at.is_none_or(|at| at > now);
+        live.then_some(tombstone.entry)
+    }
+
+    /// Drops the tombstones whose retention passed by `now` and returns
+    /// them, to be freed elsewhere.
+    pub(crate) fn purge(&self, now: u64) -> Vec<ValueEntry> {
+        let mut inner = self.lock();
+        let mut purged = Vec::new();
+        while let Some((deleted_at, _)) = inner.order.front() {
+            if deleted_at + self.retention_ms > now {
+                break;
+            }
+            let (deleted_at, key) = inner.order.pop_front().expect("front exists");
+            if inner
+                .buried
+                .get(&key)
+                .is_some_and(|tombstone| tombstone.deleted_at == deleted_at)
+            {
+                let tombstone = inner.buried.remove(&key).expect("tombstone exists");
+                inner.bytes -= key.len() + tombstone.entry.value.len();
+                purged.push(tombstone.entry);
+            }
+        }
+        purged
+    }
+
+    /// Empties the area for `FLUSHALL` and returns what it held.
+    pub(crate) fn clear(&self) -> Garbage {
+        Box::new(std::mem::take(&mut *self.lock()).buried)
+    }
+
+    pub(crate) fn metrics(&self) -> TombstoneMetrics {
+        let inner = self.lock();
+        TombstoneMetrics {
+            keys: inner.buried.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn entry(value: &'static [u8], expires_at: Option<u64>) -> ValueEntry {
+        ValueEntry::string(Bytes::from_static(value), expires_at)
+    }
+
+    #[test]
+    fn tombstones_last_until_their_retention_or_expiry() {
+        let tombstones = Tombstones::new(Duration::from_secs(10));
+        assert!(tombstones.bury(b"a", entry(b"1", None), 1_000).is_none());
+        let replaced = tombstones.bury(b"a", entry(b"2", None), 5_000);
+        assert_eq!(
+            replaced.map(|entry| entry.value),
+            Some(Bytes::from_static(b"1"))
+        );
+        tombstones.bury(b"b", entry(b"3", Some(8_000)), 5_000);
+        assert_eq!(tombstones.metrics().bytes, 4);
+
+        // The first delete of `a` is due, the second is not.
+        assert!(tombstones.purge(11_000).is_empty());
+        assert!(tombstones.dig_up(b"b", 9_000).is_none());
+        let a = tombstones.dig_up(b"a", 9_000).expect("a is kept");
+        assert_eq!(a.value, Bytes::from_static(b"2"));
+        assert!(tombstones.dig_up(b"a", 9_000).is_none());
+
+        tombstones.bury(b"c", entry(b"4", None), 20_000);
+        assert_eq!(tombstones.purge(29_999).len(), 0);
+        assert_eq!(tombstones.purge(30_000).len(), 1);
+        let metrics = tombstones.metrics();
+        assert_eq!((metrics.keys, metrics.bytes), (0, 0));
+    }
+}
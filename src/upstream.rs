@@ -0,0 +1,189 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::cli::{Connection, Reply};
+use crate::import::{Source, parse_source};
+use crate::protocol::RespValue;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2_000;
+/// Connections kept open between commands; more are opened under load and
+/// closed afterwards.
+const MAX_IDLE: usize = 16;
+
+/// A Redis fedis sits in front of, during a gradual migration: a `GET` of a
+/// key fedis does not have reads through to it, and writes go to it first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpstreamConfig {
+    pub(crate) source: Source,
+    /// Keep values read through for this long, or for what is left of their
+    /// TTL upstream if that is shorter; `None` reads through every time.
+    pub cache_ttl: Option<Duration>,
+    /// For each call, connecting included.
+    pub timeout: Duration,
+}
+
+/// Builds the proxy settings from `setting`; `None` unless
+/// `FEDIS_UPSTREAM_URL` is set.
+pub fn parse_upstream(
+    setting: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<UpstreamConfig>, Box<dyn std::error::Error>> {
+    let Some(url) = setting("FEDIS_UPSTREAM_URL") else {
+        return Ok(None);
+    };
+    let source = parse_source(url.trim()).map_err(|e| format!("FEDIS_UPSTREAM_URL: {}", e))?;
+    let millis = |name: &str| -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        setting(name)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} must be a number of milliseconds", name).into())
+            })
+            .transpose()
+    };
+    let cache_ttl = millis("FEDIS_UPSTREAM_CACHE_TTL_MS")?
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
+    let timeout = match millis("FEDIS_UPSTREAM_TIMEOUT_MS")? {
+        Some(0) => return Err("FEDIS_UPSTREAM_TIMEOUT_MS must be positive".into()),
+        Some(ms) => ms,
+        None => DEFAULT_TIMEOUT_MS,
+    };
+    Ok(Some(UpstreamConfig {
+        source,
+        cache_ttl,
+        timeout: Duration::from_millis(timeout),
+    }))
+}
+
+/// The connections to the upstream, shared by every client.
+pub(crate) struct Upstream {
+    config: UpstreamConfig,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Upstream {
+    pub(crate) fn new(config: UpstreamConfig) -> Self {
+        Self {
+            config,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn cache_ttl(&self) -> Option<Duration> {
+        self.config.cache_ttl
+    }
+
+    pub(crate) async fn call(&self, args: &[Vec<u8>]) -> Result<Reply, String> {
+        let mut replies = self.pipeline(&[args.to_vec()]).await?;
+        replies.pop().ok_or_else(|| "no reply".to_string())
+    }
+
+    /// Sends the commands on one connection and reads their replies in
+    /// order. A connection that failed or timed out is dropped rather than
+    /// reused.
+    pub(crate) async fn pipeline(&self, commands: &[Vec<Vec<u8>>]) -> Result<Vec<Reply>, String> {
+        let pooled = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let exchange = async {
+            let mut connection = match pooled {
+                Some(connection) => connection,
+                None => self
+                    .config
+                    .source
+                    .connect()
+                    .await
+                    .map_err(|e| e.to_string())?,
+            };
+            let replies = connection
+                .pipeline(commands)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>((connection, replies))
+        };
+        let result = match tokio::time::timeout(self.config.timeout, exchange).await {
+            Ok(result) => result,
+            Err(_) => Err("timed out".to_string()),
+        };
+        match result {
+            Ok((connection, replies)) => {
+                let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+                if idle.len() < MAX_IDLE {
+                    idle.push(connection);
+                }
+                Ok(replies)
+            }
+            Err(e) => {
+                warn!(upstream = %self.config.source.addr, error = %e, "upstream call failed");
+                Err(format!("upstream {}: {}", self.config.source.addr, e))
+            }
+        }
+    }
+}
+
+/// An upstream reply as fedis sends it on: RESP3-only types become their
+/// closest RESP2 form.
+pub(crate) fn into_resp(reply: Reply) -> RespValue {
+    match reply {
+        Reply::Simple(s) => RespValue::Simple(s),
+        Reply::Error(e) => RespValue::Error(e),
+        Reply::Integer(n) => RespValue::Integer(n),
+        Reply::Boolean(b) => RespValue::Integer(i64::from(b)),
+        Reply::Double(s) | Reply::BigNumber(s) | Reply::Verbatim(s) => {
+            RespValue::Bulk(Some(s.into_bytes().into()))
+        }
+        Reply::Bulk(bytes) => RespValue::Bulk(Some(bytes.into())),
+        Reply::Null => RespValue::Bulk(None),
+        Reply::Array(items) | Reply::Set(items) | Reply::Push(items) => {
+            RespValue::Array(items.into_iter().map(into_resp).collect())
+        }
+        Reply::Map(pairs) => RespValue::Map(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (into_resp(key), into_resp(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parses_upstream_settings() {
+        let parse = |pairs: &[(&str, &str)]| {
+            let map: HashMap<String, String> = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            parse_upstream(&|name| map.get(name).cloned())
+        };
+        assert_eq!(parse(&[]).expect("parse"), None);
+        let upstream = parse(&[("FEDIS_UPSTREAM_URL", "redis://:pw@cache:6380/2")])
+            .expect("parse")
+            .expect("upstream");
+        assert_eq!(upstream.source.addr, "cache:6380");
+        assert_eq!(upstream.source.password.as_deref(), Some("pw"));
+        assert_eq!(upstream.source.db, 2);
+        assert_eq!(upstream.cache_ttl, None);
+        assert_eq!(upstream.timeout, Duration::from_secs(2));
+        let upstream = parse(&[
+            ("FEDIS_UPSTREAM_URL", "redis://cache"),
+            ("FEDIS_UPSTREAM_CACHE_TTL_MS", "30000"),
+        ])
+        .expect("parse")
+        .expect("upstream");
+        assert_eq!(upstream.cache_ttl, Some(Duration::from_secs(30)));
+        assert!(parse(&[("FEDIS_UPSTREAM_URL", "http://cache")]).is_err());
+        assert!(
+            parse(&[
+                ("FEDIS_UPSTREAM_URL", "redis://cache"),
+                ("FEDIS_UPSTREAM_TIMEOUT_MS", "0"),
+            ])
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,569 @@
+//! Server-side scripting in WebAssembly: modules loaded with `WASM.LOAD`
+//! export command handlers that `WASM.CALL` runs in a fresh sandbox per call,
+//! metered with fuel and capped in memory.
+//!
+//! A module exports its `memory`, an `alloc(len: i32) -> i32` the host uses
+//! to hand it bytes, and handlers of type `(keys_ptr, keys_len, args_ptr,
+//! args_len: i32) -> i64`. Keys and arguments arrive as lists of strings,
+//! each a little-endian `u32` length followed by its bytes. A handler returns
+//! `ptr << 32 | len` for a bulk string reply, or -1 for nil. Its only view of
+//! the server is the `fedis` import module:
+//!
+//! - `get(key_ptr, key_len) -> i64`: the value, copied in through `alloc`
+//!   and returned like a reply, or -1 when the key does not exist;
+//! - `set(key_ptr, key_len, value_ptr, value_len)`: `SET` without options;
+//! - `del(key_ptr, key_len) -> i32`: 1 when the key existed;
+//! - `time_ms() -> i64`: the server's clock;
+//! - `error(ptr, len)`: makes the call reply with this error.
+//!
+//! Only the keys the call declared may be touched, so ACL key rules and
+//! cluster routing see every key a script uses; `set` and `del` fail in
+//! `WASM.CALL_RO`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::info;
+use wasmtime::{
+    AsContextMut, Caller, Engine, Extern, ExternType, InstancePre, Linker, Memory, Module,
+    Store as WasmStore, StoreLimits, StoreLimitsBuilder, Trap, TypedFunc, ValType, format_err,
+};
+
+use crate::atomic_file::AtomicFile;
+use crate::store::{SetCondition, Store};
+
+const DEFAULT_FUEL: u64 = 10_000_000;
+const DEFAULT_MAX_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+/// Fuel burnt between yields to the runtime, so a long script does not hold
+/// a worker thread for its whole run.
+const YIELD_INTERVAL_FUEL: u64 = 100_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WasmSettings {
+    /// Where loaded modules are kept to be loaded again on startup; `None`
+    /// keeps them in memory only.
+    pub path: Option<PathBuf>,
+    /// Fuel each call starts with, about one unit per instruction.
+    pub fuel: u64,
+    /// Most linear memory one call may grow to.
+    pub max_memory_bytes: usize,
+}
+
+impl Default for WasmSettings {
+    fn default() -> Self {
+        Self {
+            path: None,
+            fuel: DEFAULT_FUEL,
+            max_memory_bytes: DEFAULT_MAX_MEMORY_BYTES,
+        }
+    }
+}
+
+/// Builds the settings from `setting`; modules are kept under
+/// `<data path>/wasm` unless `FEDIS_WASM_PATH` says otherwise.
+pub fn parse_wasm(
+    setting: &dyn Fn(&str) -> Option<String>,
+    data_path: &Path,
+) -> Result<WasmSettings, Box<dyn std::error::Error>> {
+    let positive = |name: &str, default: u64| -> Result<u64, Box<dyn std::error::Error>> {
+        match setting(name) {
+            Some(value) => match value.trim().parse::<u64>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(format!("{} must be a positive integer", name).into()),
+            },
+            None => Ok(default),
+        }
+    };
+    Ok(WasmSettings {
+        path: Some(
+            setting("FEDIS_WASM_PATH")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| data_path.join("wasm")),
+        ),
+        fuel: positive("FEDIS_WASM_FUEL", DEFAULT_FUEL)?,
+        max_memory_bytes: positive(
+            "FEDIS_WASM_MAX_MEMORY_BYTES",
+            DEFAULT_MAX_MEMORY_BYTES as u64,
+        )? as usize,
+    })
+}
+
+/// What a call's sandbox can reach.
+struct HostState {
+    store: Store,
+    keys: Vec<Vec<u8>>,
+    read_only: bool,
+    limits: StoreLimits,
+    /// The error reply, once `error` was called or a host call failed.
+    error: Option<String>,
+}
+
+impl HostState {
+    fn fail(&mut self, reply: String) -> wasmtime::Error {
+        let error = format_err!("{}", reply);
+        self.error = Some(reply);
+        error
+    }
+}
+
+struct LoadedModule {
+    pre: InstancePre<HostState>,
+    functions: Vec<String>,
+}
+
+/// The `alloc` and `memory` exports bytes are copied in through.
+struct Exports {
+    alloc: TypedFunc<i32, i32>,
+    memory: Memory,
+}
+
+/// One call of a handler.
+pub struct WasmCall<'a> {
+    pub module: &'a str,
+    pub function: &'a str,
+    pub keys: &'a [Vec<u8>],
+    pub args: &'a [Vec<u8>],
+    pub read_only: bool,
+}
+
+pub struct WasmRuntime {
+    engine: Engine,
+    linker: Linker<HostState>,
+    settings: WasmSettings,
+    modules: RwLock<BTreeMap<String, Arc<LoadedModule>>>,
+}
+
+impl WasmRuntime {
+    pub fn new(settings: WasmSettings) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        let mut linker = Linker::new(&engine);
+        define_host_api(&mut linker).map_err(|e| e.to_string())?;
+        Ok(Self {
+            engine,
+            linker,
+            settings,
+            modules: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    /// Loads the modules kept under the configured path, returning how many.
+    pub fn load_saved(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let Some(dir) = &self.settings.path else {
+            return Ok(0);
+        };
+        if !dir.exists() {
+            return Ok(0);
+        }
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "wasm") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let bytes = std::fs::read(&path)?;
+            let module = self
+                .compile(&bytes)
+                .map_err(|e| format!("WASM module {}: {}", path.display(), e))?;
+            self.modules
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(name.to_string(), Arc::new(module));
+            loaded += 1;
+        }
+        if loaded > 0 {
+            info!(path = %dir.display(), modules = loaded, "loaded WASM modules");
+        }
+        Ok(loaded)
+    }
+
+    /// Compiles `bytes`, binary or text format, and adds it as `name`,
+    /// keeping a copy on disk when a path is configured. Replies with the
+    /// error to send back when the module is unusable.
+    pub async fn load(&self, name: &str, bytes: Vec<u8>, replace: bool) -> Result<(), String> {
+        if !valid_name(name) {
+            return Err(
+                "ERR WASM module names are 1 to 64 letters, digits, '_' or '-'".to_string(),
+            );
+        }
+        if !replace && self.has_module(name) {
+            return Err(format!("ERR WASM module '{}' already exists", name));
+        }
+        let engine = self.engine.clone();
+        let linker = self.linker.clone();
+        let path = self.settings.path.clone();
+        let file_name = format!("{}.wasm", name);
+        let module = tokio::task::spawn_blocking(move || {
+            let module = compile(&engine, &linker, &bytes)?;
+            if let Some(dir) = path {
+                save(&dir.join(file_name), &bytes)
+                    .map_err(|e| format!("ERR failed to save WASM module: {}", e))?;
+            }
+            Ok::<_, String>(module)
+        })
+        .await
+        .map_err(|e| format!("ERR {}", e))??;
+        let mut modules = self.modules.write().unwrap_or_else(|e| e.into_inner());
+        if !replace && modules.contains_key(name) {
+            return Err(format!("ERR WASM module '{}' already exists", name));
+        }
+        modules.insert(name.to_string(), Arc::new(module));
+        Ok(())
+    }
+
+    /// Removes `name`; false when no such module is loaded.
+    pub fn unload(&self, name: &str) -> std::io::Result<bool> {
+        let removed = self
+            .modules
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(name)
+            .is_some();
+        if removed && let Some(dir) = &self.settings.path {
+            match std::fs::remove_file(dir.join(format!("{}.wasm", name))) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Every module with its handlers, by name.
+    pub fn list(&self) -> Vec<(String, Vec<String>)> {
+        self.modules
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(name, module)| (name.clone(), module.functions.clone()))
+            .collect()
+    }
+
+    fn has_module(&self, name: &str) -> bool {
+        self.modules
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(name)
+    }
+
+    fn compile(&self, bytes: &[u8]) -> Result<LoadedModule, String> {
+        compile(&self.engine, &self.linker, bytes)
+    }
+
+    /// Runs a handler in a new instance. `Ok(None)` is a nil reply and
+    /// `Err` the error reply.
+    pub async fn call(&self, store: &Store, call: WasmCall<'_>) -> Result<Option<Vec<u8>>, String> {
+        let module = self
+            .modules
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(call.module)
+            .cloned()
+            .ok_or_else(|| format!("ERR no such WASM module '{}'", call.module))?;
+        if !module.functions.iter().any(|f| f == call.function) {
+            return Err(format!(
+                "ERR WASM module '{}' has no function '{}'",
+                call.module, call.function
+            ));
+        }
+
+        let mut wasm = WasmStore::new(
+            &self.engine,
+            HostState {
+                store: store.clone(),
+                keys: call.keys.to_vec(),
+                read_only: call.read_only,
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.settings.max_memory_bytes)
+                    .instances(1)
+                    .build(),
+                error: None,
+            },
+        );
+        wasm.limiter(|state| &mut state.limits);
+        let result = async {
+            wasm.set_fuel(self.settings.fuel)?;
+            wasm.fuel_async_yield_interval(Some(YIELD_INTERVAL_FUEL))?;
+            let instance = module.pre.instantiate_async(&mut wasm).await?;
+            let exports = Exports {
+                alloc: instance.get_typed_func(&mut wasm, "alloc")?,
+                memory: instance
+                    .get_memory(&mut wasm, "memory")
+                    .ok_or_else(|| format_err!("no memory export"))?,
+            };
+            let handler =
+                instance.get_typed_func::<(i32, i32, i32, i32), i64>(&mut wasm, call.function)?;
+            let (keys_ptr, keys_len) = copy_list_in(&mut wasm, &exports, call.keys).await?;
+            let (args_ptr, args_len) = copy_list_in(&mut wasm, &exports, call.args).await?;
+            let packed = handler
+                .call_async(&mut wasm, (keys_ptr, keys_len, args_ptr, args_len))
+                .await?;
+            if packed < 0 {
+                return Ok(None);
+            }
+            read_out(&wasm, exports.memory, packed).map(Some)
+        }
+        .await;
+        if let Some(error) = wasm.data_mut().error.take() {
+            return Err(error);
+        }
+        result.map_err(|e: wasmtime::Error| match e.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => "ERR WASM script ran out of fuel".to_string(),
+            _ => format!("ERR WASM script failed: {}", e),
+        })
+    }
+}
+
+fn compile(
+    engine: &Engine,
+    linker: &Linker<HostState>,
+    bytes: &[u8],
+) -> Result<LoadedModule, String> {
+    let module =
+        Module::new(engine, bytes).map_err(|e| format!("ERR invalid WASM module: {}", e))?;
+    let mut has_memory = false;
+    let mut has_alloc = false;
+    let mut functions = Vec::new();
+    for export in module.exports() {
+        match export.ty() {
+            ExternType::Memory(_) if export.name() == "memory" => has_memory = true,
+            ExternType::Func(ty) if export.name() == "alloc" => {
+                has_alloc = signature_is(&ty, &[ValType::I32], &[ValType::I32]);
+            }
+            ExternType::Func(ty) => {
+                let i32s = [ValType::I32, ValType::I32, ValType::I32, ValType::I32];
+                if signature_is(&ty, &i32s, &[ValType::I64]) {
+                    functions.push(export.name().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    if !has_memory || !has_alloc {
+        return Err("ERR WASM module must export 'memory' and 'alloc(i32) -> i32'".to_string());
+    }
+    if functions.is_empty() {
+        return Err("ERR WASM module exports no handler functions".to_string());
+    }
+    let pre = linker
+        .instantiate_pre(&module)
+        .map_err(|e| format!("ERR invalid WASM module: {}", e))?;
+    Ok(LoadedModule { pre, functions })
+}
+
+fn signature_is(ty: &wasmtime::FuncType, params: &[ValType], results: &[ValType]) -> bool {
+    let same = |a: ValType, b: &ValType| {
+        matches!(
+            (a, b),
+            (ValType::I32, ValType::I32) | (ValType::I64, ValType::I64)
+        )
+    };
+    ty.params().len() == params.len()
+        && ty.params().zip(params).all(|(a, b)| same(a, b))
+        && ty.results().len() == results.len()
+        && ty.results().zip(results).all(|(a, b)| same(a, b))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+fn save(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = AtomicFile::create(path, "wasm.tmp")?;
+    file.write_all(bytes)?;
+    file.commit()
+}
+
+fn define_host_api(linker: &mut Linker<HostState>) -> wasmtime::Result<()> {
+    linker.func_wrap_async(
+        "fedis",
+        "get",
+        |mut caller: Caller<'_, HostState>, (ptr, len): (i32, i32)| {
+            Box::new(async move {
+                let key = declared_key(&mut caller, ptr, len)?;
+                let store = caller.data().store.clone();
+                let value = match store.get(&key).await {
+                    Ok(Some(value)) => value,
+                    Ok(None) => return Ok(-1),
+                    Err(e) => return Err(caller.data_mut().fail(e.to_string())),
+                };
+                let exports = exports_of(&mut caller)?;
+                let ptr = copy_in(&mut caller, &exports, &value).await?;
+                Ok(pack(ptr, value.len()))
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        "fedis",
+        "set",
+        |mut caller: Caller<'_, HostState>,
+         (ptr, len, value_ptr, value_len): (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let key = writable_key(&mut caller, ptr, len)?;
+                let value = guest_bytes(&mut caller, value_ptr, value_len)?;
+                let store = caller.data().store.clone();
+                let stored = store
+                    .set(key, value, None, SetCondition::None)
+                    .await
+                    .map_err(|e| e.to_string());
+                stored
+                    .map(|_| ())
+                    .map_err(|e| caller.data_mut().fail(format!("ERR {}", e)))
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        "fedis",
+        "del",
+        |mut caller: Caller<'_, HostState>, (ptr, len): (i32, i32)| {
+            Box::new(async move {
+                let key = writable_key(&mut caller, ptr, len)?;
+                let store = caller.data().store.clone();
+                let removed = store.del(&[key]).await.map_err(|e| e.to_string());
+                removed
+                    .map(|n| n as i32)
+                    .map_err(|e| caller.data_mut().fail(format!("ERR {}", e)))
+            })
+        },
+    )?;
+    linker.func_wrap("fedis", "time_ms", || -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64
+    })?;
+    linker.func_wrap(
+        "fedis",
+        "error",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let message = guest_bytes(&mut caller, ptr, len)?;
+            let message = String::from_utf8_lossy(&message).replace(['\r', '\n'], " ");
+            let reply = if message.starts_with(|c: char| c.is_ascii_uppercase()) {
+                message
+            } else {
+                format!("ERR {}", message)
+            };
+            caller.data_mut().error = Some(reply);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// The key at `ptr`, which the call must have declared.
+fn declared_key(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<Vec<u8>> {
+    let key = guest_bytes(caller, ptr, len)?;
+    if !caller.data().keys.contains(&key) {
+        let reply = format!(
+            "ERR WASM script accessed undeclared key '{}'",
+            String::from_utf8_lossy(&key)
+        );
+        return Err(caller.data_mut().fail(reply));
+    }
+    Ok(key)
+}
+
+fn writable_key(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<Vec<u8>> {
+    if caller.data().read_only {
+        let reply = "ERR WASM script tried to write in WASM.CALL_RO".to_string();
+        return Err(caller.data_mut().fail(reply));
+    }
+    declared_key(caller, ptr, len)
+}
+
+fn exports_of(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Exports> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| format_err!("no memory export"))?;
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| format_err!("no alloc export"))?
+        .typed(&*caller)?;
+    Ok(Exports { alloc, memory })
+}
+
+fn guest_bytes(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<Vec<u8>> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| format_err!("no memory export"))?;
+    let start = ptr as u32 as usize;
+    memory
+        .data(&*caller)
+        .get(start..start + len as u32 as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| format_err!("pointer out of bounds"))
+}
+
+/// Copies `bytes` into memory `alloc` returned, answering its address.
+async fn copy_in(
+    mut ctx: impl AsContextMut<Data = HostState>,
+    exports: &Exports,
+    bytes: &[u8],
+) -> wasmtime::Result<i32> {
+    let len = i32::try_from(bytes.len())?;
+    let ptr = exports.alloc.call_async(&mut ctx, len).await?;
+    exports.memory.write(&mut ctx, ptr as u32 as usize, bytes)?;
+    Ok(ptr)
+}
+
+/// Copies `items` in as a list of length-prefixed strings.
+async fn copy_list_in(
+    ctx: impl AsContextMut<Data = HostState>,
+    exports: &Exports,
+    items: &[Vec<u8>],
+) -> wasmtime::Result<(i32, i32)> {
+    if items.is_empty() {
+        return Ok((0, 0));
+    }
+    let mut list = Vec::with_capacity(items.iter().map(|item| item.len() + 4).sum());
+    for item in items {
+        list.extend_from_slice(&u32::try_from(item.len())?.to_le_bytes());
+        list.extend_from_slice(item);
+    }
+    let ptr = copy_in(ctx, exports, &list).await?;
+    Ok((ptr, list.len() as i32))
+}
+
+/// The bytes at a packed `ptr << 32 | len`.
+fn read_out(wasm: &WasmStore<HostState>, memory: Memory, packed: i64) -> wasmtime::Result<Vec<u8>> {
+    let start = (packed >> 32) as u32 as usize;
+    let len = packed as u32 as usize;
+    memory
+        .data(wasm)
+        .get(start..start + len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| format_err!("reply out of bounds"))
+}
+
+fn pack(ptr: i32, len: usize) -> i64 {
+    ((ptr as u32 as i64) << 32) | len as i64
+}
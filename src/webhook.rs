@@ -0,0 +1,295 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::warn;
+use url::Url;
+
+use crate::export::text;
+use crate::otel::{client_tls, post_json};
+use crate::store::glob_match;
+
+const DEFAULT_BATCH: usize = 100;
+/// Events waiting to be posted, at most; later ones are dropped and counted.
+const QUEUE: usize = 10_000;
+/// A batch that is not full is posted once it is this old.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(5);
+const FIRST_RETRY: Duration = Duration::from_millis(100);
+const MAX_RETRY: Duration = Duration::from_secs(30);
+
+/// Posts the keys that expire to an HTTP endpoint, from
+/// `FEDIS_EXPIRY_WEBHOOK_URL`: keyspace notifications for consumers that
+/// cannot hold a subscription open.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpiryWebhookConfig {
+    pub url: Url,
+    /// Only keys matching this glob are posted; all of them when `None`.
+    pub pattern: Option<String>,
+    /// Events per request, at most.
+    pub batch: usize,
+}
+
+/// Builds the webhook settings from `setting`; `None` unless
+/// `FEDIS_EXPIRY_WEBHOOK_URL` is set.
+pub fn parse_expiry_webhook(
+    setting: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<ExpiryWebhookConfig>, Box<dyn std::error::Error>> {
+    let Some(url) = setting("FEDIS_EXPIRY_WEBHOOK_URL") else {
+        return Ok(None);
+    };
+    let url = Url::parse(url.trim())
+        .map_err(|e| format!("FEDIS_EXPIRY_WEBHOOK_URL is not a URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err("FEDIS_EXPIRY_WEBHOOK_URL must be an http:// or https:// URL".into());
+    }
+    let pattern = setting("FEDIS_EXPIRY_WEBHOOK_MATCH")
+        .map(|pattern| pattern.trim().to_string())
+        .filter(|pattern| !pattern.is_empty() && pattern != "*");
+    let batch = match setting("FEDIS_EXPIRY_WEBHOOK_BATCH") {
+        Some(value) => match value.trim().parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err("FEDIS_EXPIRY_WEBHOOK_BATCH must be a positive number".into()),
+        },
+        None => DEFAULT_BATCH,
+    };
+    Ok(Some(ExpiryWebhookConfig {
+        url,
+        pattern,
+        batch,
+    }))
+}
+
+struct ExpiredKey {
+    key: Vec<u8>,
+    at_ms: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Events taken off the queue and not yet accepted by the endpoint.
+    in_flight: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    /// Failed requests, each followed by a retry.
+    failures: AtomicU64,
+    last_failed: AtomicBool,
+}
+
+/// For `INFO stats`.
+pub struct ExpiryWebhookMetrics {
+    pub pending: u64,
+    pub sent: u64,
+    pub dropped: u64,
+    pub failures: u64,
+    pub last_ok: bool,
+}
+
+/// The queue in front of the endpoint; the store reports every key it
+/// removes because it expired, lazily or in the active cycle.
+#[derive(Clone)]
+pub(crate) struct ExpiryWebhook {
+    tx: mpsc::Sender<ExpiredKey>,
+    pattern: Option<Arc<[u8]>>,
+    counters: Arc<Counters>,
+}
+
+impl ExpiryWebhook {
+    /// Spawns the task that posts the queue, in order, retrying each batch
+    /// until the endpoint answers 2xx.
+    pub(crate) fn start(config: &ExpiryWebhookConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let tls = client_tls()?;
+        let (tx, rx) = mpsc::channel(QUEUE);
+        let counters = Arc::new(Counters::default());
+        tokio::spawn(post_queue(
+            rx,
+            config.url.clone(),
+            tls,
+            config.batch,
+            counters.clone(),
+        ));
+        Ok(Self {
+            tx,
+            pattern: config.pattern.as_deref().map(|p| Arc::from(p.as_bytes())),
+            counters,
+        })
+    }
+
+    /// Queues `key` without waiting: expiry runs under a shard lock, so a
+    /// slow endpoint drops events rather than stalling the keyspace.
+    pub(crate) fn expired(&self, key: &[u8]) {
+        if self
+            .pattern
+            .as_ref()
+            .is_some_and(|pattern| !glob_match(pattern, key))
+        {
+            return;
+        }
+        let event = ExpiredKey {
+            key: key.to_vec(),
+            at_ms: now_ms(),
+        };
+        if self.tx.try_send(event).is_err()
+            && self.counters.dropped.fetch_add(1, Ordering::Relaxed) == 0
+        {
+            warn!("expiry webhook queue is full: dropping events, see INFO stats");
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> ExpiryWebhookMetrics {
+        let queued = self.tx.max_capacity() - self.tx.capacity();
+        ExpiryWebhookMetrics {
+            pending: queued as u64 + self.counters.in_flight.load(Ordering::Relaxed),
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+            last_ok: !self.counters.last_failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// `{"events":[{"event":"expired","key":"...","at_ms":...}]}`.
+fn body(batch: &[ExpiredKey]) -> Vec<u8> {
+    let events: Vec<_> = batch
+        .iter()
+        .map(|event| json!({ "event": "expired", "key": text(&event.key), "at_ms": event.at_ms }))
+        .collect();
+    json!({ "events": events }).to_string().into_bytes()
+}
+
+/// Posts once a batch is full or the oldest queued event waited
+/// `FLUSH_INTERVAL`.
+async fn post_queue(
+    mut rx: mpsc::Receiver<ExpiredKey>,
+    url: Url,
+    tls: Arc<rustls::ClientConfig>,
+    batch_size: usize,
+    counters: Arc<Counters>,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    while rx.recv_many(&mut batch, batch_size).await > 0 {
+        let deadline = tokio::time::Instant::now() + FLUSH_INTERVAL;
+        while batch.len() < batch_size {
+            let more = batch_size - batch.len();
+            match tokio::time::timeout_at(deadline, rx.recv_many(&mut batch, more)).await {
+                Ok(n) if n > 0 => {}
+                _ => break,
+            }
+        }
+        counters
+            .in_flight
+            .store(batch.len() as u64, Ordering::Relaxed);
+        let payload = Arc::new(body(&batch));
+        let mut retry = FIRST_RETRY;
+        loop {
+            let (url, tls, payload) = (url.clone(), tls.clone(), payload.clone());
+            let result =
+                tokio::task::spawn_blocking(move || post_json(&tls, &url, &[], &payload, TIMEOUT))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()));
+            let Err(e) = result else {
+                break;
+            };
+            counters.failures.fetch_add(1, Ordering::Relaxed);
+            counters.last_failed.store(true, Ordering::Relaxed);
+            warn!(error = %e, events = batch.len(), retry_ms = retry.as_millis() as u64, "expiry webhook failed");
+            tokio::time::sleep(retry).await;
+            retry = (retry * 2).min(MAX_RETRY);
+        }
+        counters.last_failed.store(false, Ordering::Relaxed);
+        counters
+            .sent
+            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+        counters.in_flight.store(0, Ordering::Relaxed);
+        batch.clear();
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<Option<ExpiryWebhookConfig>, String> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        parse_expiry_webhook(&|name| map.get(name).cloned()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_webhook_settings() {
+        assert_eq!(parse(&[]), Ok(None));
+        let config = parse(&[
+            (
+                "FEDIS_EXPIRY_WEBHOOK_URL",
+                "https://hooks.example.com/expired?token=t",
+            ),
+            ("FEDIS_EXPIRY_WEBHOOK_MATCH", "session:*"),
+            ("FEDIS_EXPIRY_WEBHOOK_BATCH", "20"),
+        ])
+        .expect("parse")
+        .expect("config");
+        assert_eq!(
+            config.url.as_str(),
+            "https://hooks.example.com/expired?token=t"
+        );
+        assert_eq!(config.pattern.as_deref(), Some("session:*"));
+        assert_eq!(config.batch, 20);
+
+        let config = parse(&[
+            ("FEDIS_EXPIRY_WEBHOOK_URL", "http://localhost:8080/"),
+            ("FEDIS_EXPIRY_WEBHOOK_MATCH", "*"),
+        ])
+        .expect("parse")
+        .expect("config");
+        assert_eq!((config.pattern, config.batch), (None, DEFAULT_BATCH));
+
+        for url in ["ftp://example.com/", "not a url"] {
+            assert!(
+                parse(&[("FEDIS_EXPIRY_WEBHOOK_URL", url)]).is_err(),
+                "{}",
+                url
+            );
+        }
+        assert!(
+            parse(&[
+                ("FEDIS_EXPIRY_WEBHOOK_URL", "http://localhost/"),
+                ("FEDIS_EXPIRY_WEBHOOK_BATCH", "0"),
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn events_are_posted_as_json() {
+        let batch = [
+            ExpiredKey {
+                key: b"session:1".to_vec(),
+                at_ms: 1000,
+            },
+            ExpiredKey {
+                key: b"\xffbin".to_vec(),
+                at_ms: 1001,
+            },
+        ];
+        let value: serde_json::Value = serde_json::from_slice(&body(&batch)).expect("json");
+        assert_eq!(
+            value,
+            json!({ "events": [
+                { "event": "expired", "key": "session:1", "at_ms": 1000 },
+                { "event": "expired", "key": "\\xffbin", "at_ms": 1001 },
+            ]})
+        );
+    }
+}
@@ -0,0 +1,815 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use tokio::sync::{Notify, mpsc};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::encoding::ValueType;
+use crate::export::series_json;
+use crate::kafka::{KafkaProducer, KafkaRecord, ProduceError};
+use crate::otel::percent_decode;
+use crate::persistence::LogRecord;
+use crate::postgres::{PgConnection, PgError, PgTarget, bytea_literal, quote_literal};
+
+const DEFAULT_QUEUE: usize = 100_000;
+const DEFAULT_BATCH: usize = 500;
+const DEFAULT_TABLE: &str = "fedis_writes";
+const FIRST_RETRY: Duration = Duration::from_millis(100);
+const MAX_RETRY: Duration = Duration::from_secs(30);
+
+/// Where committed writes are streamed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SinkTarget {
+    /// Rows appended to a table, created if missing.
+    Postgres { target: PgTarget, table: String },
+    /// Records appended to one partition of a topic, keyed by the key.
+    Kafka {
+        bootstrap: Vec<String>,
+        topic: String,
+        partition: i32,
+    },
+}
+
+/// What a full queue does to the writes behind it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnFull {
+    /// Writes wait for room, so the server slows to the sink's pace.
+    Block,
+    /// Records that do not fit are dropped and counted.
+    Drop,
+}
+
+/// Streams every committed write to a system of record that trails behind
+/// fedis, from `FEDIS_WRITE_BEHIND_URL`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteBehindConfig {
+    pub(crate) target: SinkTarget,
+    /// Records waiting to be sent, at most.
+    pub queue: usize,
+    /// Records per insert or produce request, at most.
+    pub batch: usize,
+    pub on_full: OnFull,
+}
+
+/// Builds the sink settings from `setting`; `None` unless
+/// `FEDIS_WRITE_BEHIND_URL` is set.
+pub fn parse_write_behind(
+    setting: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<WriteBehindConfig>, Box<dyn std::error::Error>> {
+    let Some(url) = setting("FEDIS_WRITE_BEHIND_URL") else {
+        return Ok(None);
+    };
+    let url = url.trim();
+    let target = if url.starts_with("kafka://") {
+        parse_kafka(url)?
+    } else {
+        parse_postgres(url)?
+    };
+    let count = |name: &str, default: usize| -> Result<usize, Box<dyn std::error::Error>> {
+        match setting(name) {
+            Some(value) => match value.trim().parse::<usize>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(format!("{} must be a positive number", name).into()),
+            },
+            None => Ok(default),
+        }
+    };
+    let on_full = match setting("FEDIS_WRITE_BEHIND_ON_FULL")
+        .as_deref()
+        .map(str::trim)
+    {
+        None | Some("block") => OnFull::Block,
+        Some("drop") => OnFull::Drop,
+        Some(other) => {
+            return Err(format!(
+                "FEDIS_WRITE_BEHIND_ON_FULL must be block or drop, not '{}'",
+                other
+            )
+            .into());
+        }
+    };
+    Ok(Some(WriteBehindConfig {
+        target,
+        queue: count("FEDIS_WRITE_BEHIND_QUEUE", DEFAULT_QUEUE)?,
+        batch: count("FEDIS_WRITE_BEHIND_BATCH", DEFAULT_BATCH)?,
+        on_full,
+    }))
+}
+
+/// `postgres://[user[:password]@]host[:port][/database][?table=name]`.
+fn parse_postgres(input: &str) -> Result<SinkTarget, Box<dyn std::error::Error>> {
+    let url = Url::parse(input).map_err(|e| format!("invalid FEDIS_WRITE_BEHIND_URL: {}", e))?;
+    if !matches!(url.scheme(), "postgres" | "postgresql") {
+        return Err("FEDIS_WRITE_BEHIND_URL must be a postgres:// or kafka:// URL".into());
+    }
+    let host = url
+        .host_str()
+        .ok_or("FEDIS_WRITE_BEHIND_URL requires a host")?;
+    let user = match percent_decode(url.username()) {
+        user if user.is_empty() => "postgres".to_string(),
+        user => user,
+    };
+    let database = match percent_decode(url.path().trim_matches('/')) {
+        database if database.is_empty() => user.clone(),
+        database => database,
+    };
+    let table = url
+        .query_pairs()
+        .find(|(name, _)| name == "table")
+        .map(|(_, table)| table.into_owned())
+        .unwrap_or_else(|| DEFAULT_TABLE.to_string());
+    let identifier = |part: &str| {
+        part.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if !table.split('.').all(identifier) || table.split('.').count() > 2 {
+        return Err(format!("invalid write-behind table name '{}'", table).into());
+    }
+    Ok(SinkTarget::Postgres {
+        target: PgTarget {
+            addr: format!("{}:{}", host, url.port().unwrap_or(5432)),
+            user,
+            password: url.password().map(percent_decode),
+            database,
+        },
+        table,
+    })
+}
+
+/// `kafka://host:port[,host:port...]/topic[?partition=n]`.
+fn parse_kafka(input: &str) -> Result<SinkTarget, Box<dyn std::error::Error>> {
+    let rest = input.strip_prefix("kafka://").unwrap_or(input);
+    let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (hosts, topic) = rest
+        .split_once('/')
+        .filter(|(_, topic)| !topic.is_empty())
+        .ok_or("a kafka:// FEDIS_WRITE_BEHIND_URL must name a topic: kafka://host:9092/topic")?;
+    let bootstrap: Vec<String> = hosts
+        .split(',')
+        .filter(|host| !host.is_empty())
+        .map(|host| {
+            if host.contains(':') {
+                host.to_string()
+            } else {
+                format!("{}:9092", host)
+            }
+        })
+        .collect();
+    if bootstrap.is_empty() {
+        return Err("a kafka:// FEDIS_WRITE_BEHIND_URL needs at least one broker".into());
+    }
+    let topic_ok = topic.len() <= 249
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !topic_ok {
+        return Err(format!("invalid Kafka topic '{}'", topic).into());
+    }
+    let mut partition = 0;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some(("partition", value)) => {
+                partition = value
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|p| *p >= 0)
+                    .ok_or("the Kafka partition must be a non-negative number")?;
+            }
+            _ => return Err(format!("unknown kafka:// URL option '{}'", pair).into()),
+        }
+    }
+    Ok(SinkTarget::Kafka {
+        bootstrap,
+        topic: topic.to_string(),
+        partition,
+    })
+}
+
+/// One committed write as the sink receives it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct WriteRecord {
+    /// Numbers the writes in the order they were committed, from 1 at each
+    /// start of the server.
+    pub(crate) seq: u64,
+    /// When it was committed, in ms since the epoch.
+    pub(crate) at_ms: u64,
+    /// `set`, `del`, `expire`, `persist`, `ts.add` or `flushall`.
+    pub(crate) op: &'static str,
+    pub(crate) key: Vec<u8>,
+    /// Set by `set`: the type name as `EXPORT` writes it.
+    pub(crate) kind: Option<&'static str>,
+    /// The value written by `set`, with a time series as its samples in
+    /// JSON; `[timestamp, value]` for `ts.add`.
+    pub(crate) value: Option<Vec<u8>>,
+    /// The expiry in ms since the epoch, after `set` and `expire`.
+    pub(crate) expires_at: Option<u64>,
+}
+
+impl WriteRecord {
+    fn new(record: &LogRecord, at_ms: u64) -> Self {
+        let bare = |op, key: &[u8]| Self {
+            seq: 0,
+            at_ms,
+            op,
+            key: key.to_vec(),
+            kind: None,
+            value: None,
+            expires_at: None,
+        };
+        match record {
+            LogRecord::Set {
+                key,
+                kind,
+                value,
+                expires_at,
+            } => Self {
+                kind: Some(kind.name()),
+                value: Some(match kind {
+                    ValueType::TimeSeries => series_json(value).into_bytes(),
+                    _ => value.to_vec(),
+                }),
+                expires_at: *expires_at,
+                ..bare("set", key)
+            },
+            LogRecord::Del { key } => bare("del", key),
+            LogRecord::Expire { key, expires_at } => Self {
+                expires_at: Some(*expires_at),
+                ..bare("expire", key)
+            },
+            LogRecord::Persist { key } => bare("persist", key),
+            LogRecord::TsAdd {
+                key,
+                timestamp,
+                value,
+            } => Self {
+                value: Some(json!([timestamp, value]).to_string().into_bytes()),
+                ..bare("ts.add", key)
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Records waiting in the queue.
+    queued: AtomicU64,
+    /// Woken when the sender takes records off the queue.
+    room: Notify,
+    /// Records taken off the queue and not yet accepted by the sink.
+    in_flight: AtomicU64,
+    sent: AtomicU64,
+    /// Records dropped for a full queue or refused by the sink.
+    dropped: AtomicU64,
+    /// Failed attempts, each followed by a retry unless the sink refused
+    /// the records.
+    failures: AtomicU64,
+    last_failed: AtomicBool,
+}
+
+/// For `INFO persistence`.
+pub struct WriteBehindMetrics {
+    pub pending: u64,
+    pub sent: u64,
+    pub dropped: u64,
+    pub failures: u64,
+    pub last_ok: bool,
+}
+
+/// The queue in front of the sink; the store pushes every record it logs
+/// while it still holds the lock the write was applied under.
+#[derive(Clone)]
+pub(crate) struct WriteBehind {
+    tx: mpsc::UnboundedSender<WriteRecord>,
+    /// The last `seq` handed out, held while a record is numbered and
+    /// queued so the queue is in `seq` order.
+    seq: Arc<Mutex<u64>>,
+    capacity: u64,
+    on_full: OnFull,
+    counters: Arc<Counters>,
+}
+
+impl WriteBehind {
+    /// Spawns the task that sends the queue to the sink, in order, retrying
+    /// each batch until the sink takes it. A batch whose acknowledgement was
+    /// lost is sent again, so the sink sees every write at least once; a
+    /// record the sink refuses as such, which it would refuse again, is
+    /// dropped.
+    pub(crate) fn start(config: &WriteBehindConfig) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let counters = Arc::new(Counters::default());
+        let sink = match &config.target {
+            SinkTarget::Postgres { target, table } => Sink::Postgres {
+                target: target.clone(),
+                table: table.clone(),
+                connection: None,
+            },
+            SinkTarget::Kafka {
+                bootstrap,
+                topic,
+                partition,
+            } => Sink::Kafka(KafkaProducer::new(
+                bootstrap.clone(),
+                topic.clone(),
+                *partition,
+            )),
+        };
+        tokio::spawn(send_queue(rx, sink, config.batch, counters.clone()));
+        Self {
+            tx,
+            seq: Arc::new(Mutex::new(0)),
+            capacity: config.queue as u64,
+            on_full: config.on_full,
+            counters,
+        }
+    }
+
+    /// Queues `record` without waiting, numbered and stamped as committed
+    /// now. The store calls it with the key's shard still locked, so the
+    /// writes to a key reach the sink in the order they were applied.
+    pub(crate) fn push(&self, record: &LogRecord) {
+        self.enqueue(|| WriteRecord::new(record, now_ms()));
+    }
+
+    /// A flush is not a log record; the sink gets a `flushall` with no key.
+    pub(crate) fn flushall(&self) {
+        self.enqueue(|| WriteRecord {
+            seq: 0,
+            at_ms: now_ms(),
+            op: "flushall",
+            key: Vec::new(),
+            kind: None,
+            value: None,
+            expires_at: None,
+        });
+    }
+
+    fn enqueue(&self, record: impl FnOnce() -> WriteRecord) {
+        if self.on_full == OnFull::Drop
+            && self.counters.queued.load(Ordering::Relaxed) >= self.capacity
+        {
+            if self.counters.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                warn!("write-behind queue is full: dropping writes, see INFO persistence");
+            }
+            return;
+        }
+        let mut seq = self.seq.lock().unwrap_or_else(|e| e.into_inner());
+        *seq += 1;
+        let mut record = record();
+        record.seq = *seq;
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        if self.tx.send(record).is_err() {
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Waits for the queue to drop below its size when full writes block;
+    /// the store calls it once the lock is released, so a slow sink slows
+    /// the writers down without holding up the shard.
+    pub(crate) async fn wait_for_room(&self) {
+        if self.on_full == OnFull::Drop {
+            return;
+        }
+        loop {
+            let room = self.counters.room.notified();
+            tokio::pin!(room);
+            room.as_mut().enable();
+            if self.counters.queued.load(Ordering::Relaxed) < self.capacity {
+                return;
+            }
+            room.await;
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> WriteBehindMetrics {
+        WriteBehindMetrics {
+            pending: self.counters.queued.load(Ordering::Relaxed)
+                + self.counters.in_flight.load(Ordering::Relaxed),
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+            last_ok: !self.counters.last_failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Waits up to `timeout` for the sink to take what is queued, at
+    /// shutdown; returns how many records are still pending.
+    pub(crate) async fn drain(&self, timeout: Duration) -> u64 {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pending = self.metrics().pending;
+            if pending == 0 || Instant::now() >= deadline {
+                return pending;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+enum Sink {
+    Postgres {
+        target: PgTarget,
+        table: String,
+        /// Dropped after a failure and opened again for the retry.
+        connection: Option<PgConnection>,
+    },
+    Kafka(KafkaProducer),
+}
+
+/// Why the sink did not take a batch.
+struct SinkError {
+    message: String,
+    /// The sink refused the records themselves, so a retry would fail
+    /// again.
+    permanent: bool,
+}
+
+impl From<String> for SinkError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            permanent: false,
+        }
+    }
+}
+
+impl From<PgError> for SinkError {
+    fn from(error: PgError) -> Self {
+        Self {
+            permanent: error.is_permanent(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<ProduceError> for SinkError {
+    fn from(error: ProduceError) -> Self {
+        Self {
+            permanent: error.is_permanent(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl Sink {
+    async fn write(&mut self, batch: &[WriteRecord]) -> Result<(), SinkError> {
+        match self {
+            Sink::Postgres {
+                target,
+                table,
+                connection,
+            } => {
+                let mut open = match connection.take() {
+                    Some(open) => open,
+                    None => {
+                        let mut open = PgConnection::connect(target).await?;
+                        open.execute(&create_table(table)).await?;
+                        open.execute(&add_seq_column(table)).await?;
+                        info!(addr = %target.addr, table = %table, "write-behind connected to PostgreSQL");
+                        open
+                    }
+                };
+                open.execute(&insert(table, batch)).await?;
+                *connection = Some(open);
+                Ok(())
+            }
+            Sink::Kafka(producer) => Ok(producer.send(&kafka_records(batch)).await?),
+        }
+    }
+}
+
+fn create_table(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (\
+         id bigserial PRIMARY KEY, \
+         seq bigint, \
+         at_ms bigint NOT NULL, \
+         op text NOT NULL, \
+         key bytea NOT NULL, \
+         type text, \
+         value bytea, \
+         expires_at_ms bigint)",
+        table
+    )
+}
+
+/// Tables created before records carried `seq` get the column.
+fn add_seq_column(table: &str) -> String {
+    format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS seq bigint", table)
+}
+
+/// One `INSERT` for the whole batch, so it lands in full or not at all.
+fn insert(table: &str, batch: &[WriteRecord]) -> String {
+    let rows: Vec<String> = batch
+        .iter()
+        .map(|record| {
+            format!(
+                "({}, {}, {}, {}, {}, {}, {})",
+                record.seq,
+                record.at_ms,
+                quote_literal(record.op),
+                bytea_literal(&record.key),
+                record.kind.map_or("NULL".to_string(), quote_literal),
+                record
+                    .value
+                    .as_deref()
+                    .map_or("NULL".to_string(), bytea_literal),
+                record
+                    .expires_at
+                    .map_or("NULL".to_string(), |at| at.to_string()),
+            )
+        })
+        .collect();
+    format!(
+        "INSERT INTO {} (seq, at_ms, op, key, type, value, expires_at_ms) VALUES {}",
+        table,
+        rows.join(", ")
+    )
+}
+
+/// The key as the record key and the value as the record value; the seq,
+/// op, type and expiry travel as headers. Only `del` and `flushall` go out as
+/// tombstones with no value, so log compaction keeps an `expire` or
+/// `persist`: theirs is the op and expiry in JSON.
+fn kafka_records(batch: &[WriteRecord]) -> Vec<KafkaRecord> {
+    batch
+        .iter()
+        .map(|record| {
+            let mut headers = vec![
+                ("seq", record.seq.to_string().into_bytes()),
+                ("op", record.op.as_bytes().to_vec()),
+            ];
+            if let Some(kind) = record.kind {
+                headers.push(("type", kind.as_bytes().to_vec()));
+            }
+            if let Some(at) = record.expires_at {
+                headers.push(("expires_at_ms", at.to_string().into_bytes()));
+            }
+            let value = match (record.op, &record.value) {
+                (_, Some(value)) => Some(value.clone()),
+                ("del" | "flushall", None) => None,
+                (op, None) => Some(
+                    json!({"op": op, "expires_at_ms": record.expires_at})
+                        .to_string()
+                        .into_bytes(),
+                ),
+            };
+            KafkaRecord {
+                key: record.key.clone(),
+                value,
+                headers,
+                timestamp_ms: record.at_ms as i64,
+            }
+        })
+        .collect()
+}
+
+async fn send_queue(
+    mut rx: mpsc::UnboundedReceiver<WriteRecord>,
+    mut sink: Sink,
+    batch_size: usize,
+    counters: Arc<Counters>,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    while rx.recv_many(&mut batch, batch_size).await > 0 {
+        counters
+            .in_flight
+            .store(batch.len() as u64, Ordering::Relaxed);
+        counters
+            .queued
+            .fetch_sub(batch.len() as u64, Ordering::Relaxed);
+        counters.room.notify_waiters();
+        let mut refused = Vec::new();
+        match deliver(&mut sink, &batch, &counters).await {
+            Ok(()) => {}
+            Err(e) if batch.len() == 1 => refused.push((batch[0].seq, e)),
+            // Sent one at a time, the records the sink does take still land
+            // in order; only the ones it refuses are dropped.
+            Err(_) => {
+                for record in batch.chunks(1) {
+                    if let Err(e) = deliver(&mut sink, record, &counters).await {
+                        refused.push((record[0].seq, e));
+                    }
+                }
+            }
+        }
+        for (seq, e) in refused {
+            counters.dropped.fetch_add(1, Ordering::Relaxed);
+            warn!(error = %e.message, seq, "write-behind sink refused a record, dropping it");
+        }
+        counters.in_flight.store(0, Ordering::Relaxed);
+        batch.clear();
+    }
+}
+
+/// Sends `records` until the sink takes them, backing off between
+/// attempts; fails only when the sink refuses them for good.
+async fn deliver(
+    sink: &mut Sink,
+    records: &[WriteRecord],
+    counters: &Counters,
+) -> Result<(), SinkError> {
+    let mut retry = FIRST_RETRY;
+    loop {
+        let Err(e) = sink.write(records).await else {
+            counters.last_failed.store(false, Ordering::Relaxed);
+            counters
+                .sent
+                .fetch_add(records.len() as u64, Ordering::Relaxed);
+            return Ok(());
+        };
+        counters.failures.fetch_add(1, Ordering::Relaxed);
+        counters.last_failed.store(true, Ordering::Relaxed);
+        if e.permanent {
+            return Err(e);
+        }
+        warn!(error = %e.message, records = records.len(), retry_ms = retry.as_millis() as u64, "write-behind batch failed");
+        tokio::time::sleep(retry).await;
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<Option<WriteBehindConfig>, String> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        parse_write_behind(&|name| map.get(name).cloned()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_sink_urls() {
+        assert_eq!(parse(&[]), Ok(None));
+        let config = parse(&[(
+            "FEDIS_WRITE_BEHIND_URL",
+            "postgres://app:p%40ss@db:5433/records?table=audit.writes",
+        )])
+        .expect("parse")
+        .expect("config");
+        assert_eq!(
+            config.target,
+            SinkTarget::Postgres {
+                target: PgTarget {
+                    addr: "db:5433".to_string(),
+                    user: "app".to_string(),
+                    password: Some("p@ss".to_string()),
+                    database: "records".to_string(),
+                },
+                table: "audit.writes".to_string(),
+            }
+        );
+        assert_eq!(
+            (config.queue, config.batch, config.on_full),
+            (DEFAULT_QUEUE, DEFAULT_BATCH, OnFull::Block)
+        );
+
+        let config = parse(&[
+            (
+                "FEDIS_WRITE_BEHIND_URL",
+                "kafka://k1,k2:9093/fedis.writes?partition=2",
+            ),
+            ("FEDIS_WRITE_BEHIND_BATCH", "50"),
+            ("FEDIS_WRITE_BEHIND_ON_FULL", "drop"),
+        ])
+        .expect("parse")
+        .expect("config");
+        assert_eq!(
+            config.target,
+            SinkTarget::Kafka {
+                bootstrap: vec!["k1:9092".to_string(), "k2:9093".to_string()],
+                topic: "fedis.writes".to_string(),
+                partition: 2,
+            }
+        );
+        assert_eq!((config.batch, config.on_full), (50, OnFull::Drop));
+
+        for url in [
+            "mysql://db/records",
+            "postgres://db/records?table=writes;drop",
+            "kafka://k1",
+            "kafka://k1/bad topic",
+            "kafka://k1/t?partition=-1",
+        ] {
+            assert!(
+                parse(&[("FEDIS_WRITE_BEHIND_URL", url)]).is_err(),
+                "{}",
+                url
+            );
+        }
+        assert!(
+            parse(&[
+                ("FEDIS_WRITE_BEHIND_URL", "kafka://k1/t"),
+                ("FEDIS_WRITE_BEHIND_QUEUE", "0"),
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn records_become_rows_and_kafka_records() {
+        let numbered = |seq, record| WriteRecord { seq, ..record };
+        let set = numbered(
+            1,
+            WriteRecord::new(
+                &LogRecord::Set {
+                    key: b"it's".to_vec(),
+                    kind: ValueType::String,
+                    value: Bytes::from_static(b"v"),
+                    expires_at: Some(5000),
+                },
+                1000,
+            ),
+        );
+        let sample = numbered(
+            2,
+            WriteRecord::new(
+                &LogRecord::TsAdd {
+                    key: b"temp".to_vec(),
+                    timestamp: 7,
+                    value: 21.5,
+                },
+                2000,
+            ),
+        );
+        assert_eq!(sample.value.as_deref(), Some(&b"[7,21.5]"[..]));
+        assert_eq!(
+            insert("fedis_writes", &[set.clone(), sample]),
+            "INSERT INTO fedis_writes (seq, at_ms, op, key, type, value, expires_at_ms) VALUES \
+             (1, 1000, 'set', '\\x69742773'::bytea, 'string', '\\x76'::bytea, 5000), \
+             (2, 2000, 'ts.add', '\\x74656d70'::bytea, NULL, '\\x5b372c32312e355d'::bytea, NULL)"
+        );
+        let del = numbered(
+            3,
+            WriteRecord::new(&LogRecord::Del { key: b"k".to_vec() }, 3000),
+        );
+        let expire = numbered(
+            4,
+            WriteRecord::new(
+                &LogRecord::Expire {
+                    key: b"k".to_vec(),
+                    expires_at: 9000,
+                },
+                4000,
+            ),
+        );
+        let persist = numbered(
+            5,
+            WriteRecord::new(&LogRecord::Persist { key: b"k".to_vec() }, 5000),
+        );
+        assert_eq!(
+            kafka_records(&[set, del, expire, persist]),
+            vec![
+                KafkaRecord {
+                    key: b"it's".to_vec(),
+                    value: Some(b"v".to_vec()),
+                    headers: vec![
+                        ("seq", b"1".to_vec()),
+                        ("op", b"set".to_vec()),
+                        ("type", b"string".to_vec()),
+                        ("expires_at_ms", b"5000".to_vec()),
+                    ],
+                    timestamp_ms: 1000,
+                },
+                KafkaRecord {
+                    key: b"k".to_vec(),
+                    value: None,
+                    headers: vec![("seq", b"3".to_vec()), ("op", b"del".to_vec())],
+                    timestamp_ms: 3000,
+                },
+                KafkaRecord {
+                    key: b"k".to_vec(),
+                    value: Some(br#"{"expires_at_ms":9000,"op":"expire"}"#.to_vec()),
+                    headers: vec![
+                        ("seq", b"4".to_vec()),
+                        ("op", b"expire".to_vec()),
+                        ("expires_at_ms", b"9000".to_vec()),
+                    ],
+                    timestamp_ms: 4000,
+                },
+                KafkaRecord {
+                    key: b"k".to_vec(),
+                    value: Some(br#"{"expires_at_ms":null,"op":"persist"}"#.to_vec()),
+                    headers: vec![("seq", b"5".to_vec()), ("op", b"persist".to_vec())],
+                    timestamp_ms: 5000,
+                },
+            ]
+        );
+    }
+}
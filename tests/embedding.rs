@@ -0,0 +1,70 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fedis::{Config, RespValue, Server, SessionAuth};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn args(cmd: &[&str]) -> Vec<Vec<u8>> {
+    cmd.iter().map(|v| v.as_bytes().to_vec()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn embedded_server_serves_in_process_and_over_tcp() {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir =
+        std::env::temp_dir().join(format!("fedis-embed-{}-{}", std::process::id(), stamp));
+    let config = || {
+        Config::builder()
+            .listen_addr("127.0.0.1:0")
+            .data_path(&data_dir)
+            .set("FEDIS_AOF_FSYNC", "always")
+            .build()
+            .expect("config")
+    };
+
+    let handle = Server::new(config())
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start");
+    let addr = handle.local_addr().expect("listening");
+    assert_ne!(addr.port(), 0);
+    let executor = handle.executor();
+    let mut session = SessionAuth::default();
+    let (reply, _) = executor
+        .execute(args(&["SET", "greeting", "hello"]), &mut session)
+        .await;
+    assert!(matches!(reply, RespValue::Simple(ok) if ok == "OK"));
+
+    let mut client = TcpStream::connect(addr).await.expect("connect");
+    client
+        .write_all(b"*2\r\n$3\r\nGET\r\n$8\r\ngreeting\r\n")
+        .await
+        .expect("write");
+    let mut reply = [0_u8; 11];
+    client.read_exact(&mut reply).await.expect("read");
+    assert_eq!(&reply, b"$5\r\nhello\r\n");
+    drop(client);
+
+    handle.shutdown().await.expect("shutdown");
+    assert!(TcpStream::connect(addr).await.is_err());
+
+    // The AOF was flushed, so a new server over the same directory has the key.
+    let server = Server::new(config()).await.expect("reopen");
+    assert_eq!(
+        server
+            .store()
+            .get(b"greeting")
+            .await
+            .ok()
+            .flatten()
+            .as_deref(),
+        Some(&b"hello"[..])
+    );
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fedis::{Config, RespValue, Server, SessionAuth};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+fn args(cmd: &[&str]) -> Vec<Vec<u8>> {
+    cmd.iter().map(|v| v.as_bytes().to_vec()).collect()
+}
+
+/// Answers the first request with 503 and the rest with 200, passing each
+/// request line and body on.
+async fn endpoint(listener: TcpListener, requests: mpsc::UnboundedSender<(String, Value)>) {
+    let mut first = true;
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            return;
+        };
+        let mut received = Vec::new();
+        let mut buf = [0_u8; 4096];
+        let (head, body) = loop {
+            let n = socket.read(&mut buf).await.expect("read");
+            assert!(n > 0, "request cut short");
+            received.extend_from_slice(&buf[..n]);
+            let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&received[..end]).to_string();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .expect("content-length")
+                .parse()
+                .expect("length");
+            if received.len() >= end + 4 + length {
+                break (head, received[end + 4..end + 4 + length].to_vec());
+            }
+        };
+        let status = if first {
+            "503 Service Unavailable"
+        } else {
+            "200 OK"
+        };
+        first = false;
+        socket
+            .write_all(format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status).as_bytes())
+            .await
+            .expect("write");
+        let line = head.lines().next().unwrap_or_default().to_string();
+        let _ = requests.send((line, serde_json::from_slice(&body).expect("json body")));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expired_keys_are_posted_to_the_webhook() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let url = format!(
+        "http://{}/hooks/expired?token=t",
+        listener.local_addr().expect("addr")
+    );
+    let (tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(endpoint(listener, tx));
+
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir = std::env::temp_dir().join(format!(
+        "fedis-expiry-webhook-{}-{}",
+        std::process::id(),
+        stamp
+    ));
+    let config = Config::builder()
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .non_redis_mode(true)
+        .set("FEDIS_EXPIRY_WEBHOOK_URL", url)
+        .set("FEDIS_EXPIRY_WEBHOOK_MATCH", "session:*")
+        .set("FEDIS_EXPIRY_WEBHOOK_BATCH", "2")
+        .build()
+        .expect("config");
+    let handle = Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start");
+    let executor = handle.executor();
+    let mut session = SessionAuth::default();
+    for cmd in [
+        ["SET", "session:1", "a", "PX", "20"],
+        ["SET", "cart:1", "b", "PX", "20"],
+        ["SET", "session:2", "c", "PX", "20"],
+    ] {
+        executor.execute(args(&cmd), &mut session).await;
+    }
+    // Whichever of the read and the active cycle removes a key reports it.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(matches!(
+        executor
+            .execute(args(&["GET", "session:2"]), &mut session)
+            .await
+            .0,
+        RespValue::Bulk(None)
+    ));
+
+    let mut keys = Vec::new();
+    let mut attempts = 0;
+    while keys.len() < 2 {
+        let (line, body) = tokio::time::timeout(Duration::from_secs(10), requests.recv())
+            .await
+            .expect("webhook posted")
+            .expect("endpoint running");
+        assert_eq!(line, "POST /hooks/expired?token=t HTTP/1.1");
+        attempts += 1;
+        // The first attempt was refused, so its events come again.
+        if attempts == 1 {
+            continue;
+        }
+        for event in body["events"].as_array().expect("events") {
+            assert_eq!(event["event"], "expired");
+            assert!(event["at_ms"].as_u64().is_some());
+            keys.push(event["key"].as_str().expect("key").to_string());
+        }
+    }
+    keys.sort();
+    assert_eq!(keys, ["session:1", "session:2"]);
+
+    // The counters move once the reply is read.
+    let mut info = String::new();
+    for _ in 0..100 {
+        info = executor.info_text("stats").await.expect("stats");
+        if info.contains("expiry_webhook_sent:2") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(info.contains("expiry_webhook_sent:2"), "{}", info);
+    assert!(info.contains("expiry_webhook_failures:1"), "{}", info);
+    handle.shutdown().await.expect("shutdown");
+}
@@ -0,0 +1,167 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fedis::protocol::{encode, read_frame};
+use fedis::{Config, RespValue, Server, SessionAuth};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+fn args(cmd: &[&str]) -> Vec<Vec<u8>> {
+    cmd.iter().map(|v| v.as_bytes().to_vec()).collect()
+}
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Self {
+        let (reader, writer) = TcpStream::connect(addr)
+            .await
+            .expect("connect")
+            .into_split();
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, cmd: &[&str]) {
+        let frame = RespValue::Array(
+            cmd.iter()
+                .map(|arg| RespValue::Bulk(Some(Bytes::copy_from_slice(arg.as_bytes()))))
+                .collect(),
+        );
+        self.writer.write_all(&encode(frame)).await.expect("write");
+    }
+
+    async fn next(&mut self) -> RespValue {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            // read_frame parses what clients send, which has no errors.
+            if self.reader.fill_buf().await.expect("read").first() == Some(&b'-') {
+                let mut line = String::new();
+                self.reader.read_line(&mut line).await.expect("read");
+                return RespValue::Error(line[1..].trim_end().to_string());
+            }
+            read_frame(&mut self.reader)
+                .await
+                .expect("read")
+                .expect("frame")
+        })
+        .await
+        .expect("reply in time")
+    }
+
+    /// `(token, op, key, ttl)` of the next push; `None` for a null TTL.
+    async fn change(&mut self) -> (String, String, String, Option<i64>) {
+        let RespValue::Array(items) = self.next().await else {
+            panic!("expected a push");
+        };
+        let text = |item: &RespValue| match item {
+            RespValue::Bulk(Some(bytes)) => String::from_utf8_lossy(bytes).to_string(),
+            other => panic!("expected a bulk string, got {:?}", other),
+        };
+        assert_eq!(text(&items[0]), "change");
+        let ttl = match items[4] {
+            RespValue::Integer(ttl) => Some(ttl),
+            RespValue::Bulk(None) => None,
+            ref other => panic!("expected a TTL, got {:?}", other),
+        };
+        (text(&items[1]), text(&items[2]), text(&items[3]), ttl)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn followers_receive_changes_and_resume_from_tokens() {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir =
+        std::env::temp_dir().join(format!("fedis-follow-{}-{}", std::process::id(), stamp));
+    let config = Config::builder()
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .non_redis_mode(true)
+        .build()
+        .expect("config");
+    let handle = Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start");
+    let addr = handle.local_addr().expect("listening");
+    let executor = handle.executor();
+    let mut session = SessionAuth::default();
+
+    let mut follower = Client::connect(addr).await;
+    follower.send(&["FOLLOW", "user:*"]).await;
+    let RespValue::Array(confirmation) = follower.next().await else {
+        panic!("expected the confirmation");
+    };
+    assert!(matches!(&confirmation[0], RespValue::Bulk(Some(v)) if v == "follow"));
+    assert!(matches!(&confirmation[1], RespValue::Bulk(Some(v)) if v == "user:*"));
+
+    for cmd in [
+        &["SET", "user:1", "a", "EX", "100"][..],
+        &["SET", "other", "b"],
+        // `user:9` is missing, so its delete is no change.
+        &["DEL", "user:1", "user:9"],
+        &["SET", "user:2", "c", "PX", "30"],
+    ] {
+        executor.execute(args(cmd), &mut session).await;
+    }
+    let (token, op, key, ttl) = follower.change().await;
+    assert_eq!((op.as_str(), key.as_str()), ("set", "user:1"));
+    assert!(
+        ttl.is_some_and(|ttl| ttl > 99_000 && ttl <= 100_000),
+        "{:?}",
+        ttl
+    );
+    let deleted = follower.change().await;
+    assert_eq!((&deleted.1[..], &deleted.2[..]), ("del", "user:1"));
+    assert_eq!(deleted.3, Some(-2));
+    assert_eq!(follower.change().await.2, "user:2");
+    // Removed by the active expiration cycle.
+    let expired = follower.change().await;
+    assert_eq!(
+        (expired.1.as_str(), expired.2.as_str(), expired.3),
+        ("expired", "user:2", Some(-2))
+    );
+
+    follower.send(&["PING"]).await;
+    assert!(matches!(follower.next().await, RespValue::Array(pong) if pong.len() == 2));
+    follower.send(&["GET", "user:1"]).await;
+    assert!(matches!(follower.next().await, RespValue::Error(e) if e.contains("only PING / QUIT")));
+
+    // A new connection picks up after the first change.
+    let mut resumed = Client::connect(addr).await;
+    resumed.send(&["FOLLOW", "user:*", "FROM", &token]).await;
+    assert!(matches!(resumed.next().await, RespValue::Array(_)));
+    let replayed = [
+        resumed.change().await,
+        resumed.change().await,
+        resumed.change().await,
+    ];
+    assert_eq!(
+        replayed.map(|(_, op, key, _)| format!("{} {}", op, key)),
+        ["del user:1", "set user:2", "expired user:2"]
+    );
+
+    // A token of another feed is refused and the connection stays usable.
+    let mut stale = Client::connect(addr).await;
+    stale
+        .send(&["FOLLOW", "*", "FROM", "0123456789abcdef-1"])
+        .await;
+    assert!(matches!(stale.next().await, RespValue::Error(e) if e.contains("resume token")));
+    stale.send(&["PING"]).await;
+    assert!(matches!(stale.next().await, RespValue::Simple(pong) if pong == "PONG"));
+
+    follower.send(&["QUIT"]).await;
+    assert!(matches!(follower.next().await, RespValue::Simple(ok) if ok == "OK"));
+    handle.shutdown().await.expect("shutdown");
+}
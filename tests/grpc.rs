@@ -0,0 +1,180 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fedis::grpc::proto::fedis_client::FedisClient;
+use fedis::grpc::proto::{
+    DelRequest, ExpireRequest, GetRequest, ScanRequest, SetRequest, SubscribeRequest,
+};
+use fedis::{Config, RespValue, Server, SessionAuth};
+use tonic::Code;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grpc_calls_map_onto_commands() {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir =
+        std::env::temp_dir().join(format!("fedis-grpc-{}-{}", std::process::id(), stamp));
+    let config = Config::builder()
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .set("FEDIS_GRPC_ADDR", "127.0.0.1:0")
+        .build()
+        .expect("config");
+    let handle = Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start");
+    let addr = handle.grpc_addr().expect("gRPC listening");
+    let mut client = FedisClient::connect(format!("http://{}", addr))
+        .await
+        .expect("connect");
+
+    let set = |key: &str, value: &str| SetRequest {
+        key: key.into(),
+        value: value.into(),
+        ..SetRequest::default()
+    };
+    assert!(
+        client
+            .set(set("a", "1"))
+            .await
+            .expect("set")
+            .into_inner()
+            .stored
+    );
+    let nx = SetRequest {
+        nx: true,
+        ..set("a", "2")
+    };
+    assert!(!client.set(nx).await.expect("set nx").into_inner().stored);
+    client.set(set("b", "2")).await.expect("set");
+    let got = client
+        .get(GetRequest { key: "a".into() })
+        .await
+        .expect("get")
+        .into_inner();
+    assert_eq!(got.value.as_deref(), Some(&b"1"[..]));
+    let missing = client
+        .get(GetRequest {
+            key: "missing".into(),
+        })
+        .await
+        .expect("get")
+        .into_inner();
+    assert_eq!(missing.value, None);
+
+    let expire = ExpireRequest {
+        key: "b".into(),
+        ttl_ms: 60_000,
+    };
+    assert!(
+        client
+            .expire(expire)
+            .await
+            .expect("expire")
+            .into_inner()
+            .updated
+    );
+
+    let scan = ScanRequest {
+        cursor: 0,
+        pattern: "a*".to_string(),
+        count: 100,
+    };
+    let scanned = client.scan(scan).await.expect("scan").into_inner();
+    assert_eq!(scanned.cursor, 0);
+    assert_eq!(scanned.keys, vec![b"a".to_vec()]);
+
+    let del = DelRequest {
+        keys: vec!["a".into(), "b".into(), "missing".into()],
+    };
+    assert_eq!(client.del(del).await.expect("del").into_inner().deleted, 2);
+
+    // Error replies come back as statuses.
+    let executor = handle.executor();
+    let mut session = SessionAuth::default();
+    let json = ["JSON.SET", "doc", "$", "{}"].map(|arg| arg.as_bytes().to_vec());
+    executor.execute(json.to_vec(), &mut session).await;
+    let wrongtype = client
+        .get(GetRequest { key: "doc".into() })
+        .await
+        .expect_err("WRONGTYPE");
+    assert_eq!(wrongtype.code(), Code::FailedPrecondition);
+
+    let mut stream = client
+        .subscribe(SubscribeRequest {
+            channels: vec!["news".into()],
+        })
+        .await
+        .expect("subscribe")
+        .into_inner();
+    let publish = ["PUBLISH", "news", "hello"].map(|arg| arg.as_bytes().to_vec());
+    let (reply, _) = executor.execute(publish.to_vec(), &mut session).await;
+    assert!(matches!(reply, RespValue::Integer(1)));
+    let message = tokio::time::timeout(Duration::from_secs(10), stream.message())
+        .await
+        .expect("message in time")
+        .expect("stream open")
+        .expect("a message");
+    assert_eq!(message.channel, b"news");
+    assert_eq!(message.payload, b"hello");
+    drop(stream);
+
+    handle.shutdown().await.expect("shutdown");
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grpc_calls_authenticate_with_metadata() {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir =
+        std::env::temp_dir().join(format!("fedis-grpc-auth-{}-{}", std::process::id(), stamp));
+    let config = Config::builder()
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .password("secret")
+        .set("FEDIS_GRPC_ADDR", "127.0.0.1:0")
+        .build()
+        .expect("config");
+    let handle = Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start");
+    let addr = handle.grpc_addr().expect("gRPC listening");
+    let mut client = FedisClient::connect(format!("http://{}", addr))
+        .await
+        .expect("connect");
+
+    let denied = client
+        .get(GetRequest { key: "a".into() })
+        .await
+        .expect_err("NOAUTH");
+    assert_eq!(denied.code(), Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(GetRequest { key: "a".into() });
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer wrong".parse().expect("metadata"));
+    let wrong = client.get(request).await.expect_err("WRONGPASS");
+    assert_eq!(wrong.code(), Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(GetRequest { key: "a".into() });
+    // "default:secret"
+    request.metadata_mut().insert(
+        "authorization",
+        "Basic ZGVmYXVsdDpzZWNyZXQ=".parse().expect("metadata"),
+    );
+    let got = client.get(request).await.expect("get").into_inner();
+    assert_eq!(got.value, None);
+
+    handle.shutdown().await.expect("shutdown");
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
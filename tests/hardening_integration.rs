@@ -32,9 +32,8 @@ fn start_server(extra_env: &[(&str, &str)]) -> RunningServer {
     let data_dir = std::env::temp_dir().join(format!("fedis-it-{}-{}", std::process::id(), stamp));
     std::fs::create_dir_all(&data_dir).expect("create temp data dir");
 
-    let mut cmd = Command::new("cargo");
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_fedis"));
     cmd.current_dir(env!("CARGO_MANIFEST_DIR"));
-    cmd.arg("run").arg("--quiet");
     cmd.env("FEDIS_HOST", "127.0.0.1")
         .env("FEDIS_PORT", port.to_string())
         .env("FEDIS_DATA_PATH", &data_dir)
@@ -46,7 +45,7 @@ fn start_server(extra_env: &[(&str, &str)]) -> RunningServer {
         cmd.env(k, v);
     }
 
-    let child = cmd.spawn().expect("spawn fedis server");
+    let mut child = cmd.spawn().expect("spawn fedis server");
 
     for _ in 0..120 {
         if TcpStream::connect(("127.0.0.1", port)).is_ok() {
@@ -59,6 +58,8 @@ fn start_server(extra_env: &[(&str, &str)]) -> RunningServer {
         thread::sleep(Duration::from_millis(50));
     }
 
+    let _ = child.kill();
+    let _ = child.wait();
     panic!("server did not become ready");
 }
 
@@ -146,9 +147,8 @@ fn idle_timeout_closes_inactive_connection() {
 
     thread::sleep(Duration::from_secs(2));
 
-    match client.write_all(ping_frame()) {
-        Err(_) => return,
-        Ok(()) => {}
+    if client.write_all(ping_frame()).is_err() {
+        return;
     }
 
     let mut buf = [0_u8; 64];
@@ -161,3 +161,52 @@ fn idle_timeout_closes_inactive_connection() {
         Err(_) => {}
     }
 }
+
+#[test]
+fn password_file_is_used_for_auth() {
+    let _lock = test_lock();
+    let secret = std::env::temp_dir().join(format!("fedis-it-secret-{}", std::process::id()));
+    std::fs::write(&secret, "from-file\n").expect("write secret file");
+    let secret_path = secret.to_string_lossy().to_string();
+    let server = start_server(&[("FEDIS_PASSWORD_FILE", secret_path.as_str())]);
+
+    let mut client = TcpStream::connect(("127.0.0.1", server.port)).expect("connect client");
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .expect("set read timeout");
+    client
+        .write_all(b"*2\r\n$4\r\nAUTH\r\n$9\r\nfrom-file\r\n")
+        .expect("write auth");
+    let mut buf = [0_u8; 64];
+    let n = client.read(&mut buf).expect("read auth response");
+    assert_eq!(&buf[..n], b"+OK\r\n");
+
+    let _ = std::fs::remove_file(secret);
+}
+
+#[test]
+fn traceid_prefix_is_echoed_with_the_request_id() {
+    let _lock = test_lock();
+    let server = start_server(&[
+        ("FEDIS_NON_REDIS_MODE", "true"),
+        ("FEDIS_DEBUG_RESPONSE_ID", "true"),
+    ]);
+
+    let mut client = TcpStream::connect(("127.0.0.1", server.port)).expect("connect client");
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .expect("set read timeout");
+    client
+        .write_all(b"*3\r\n$7\r\nTRACEID\r\n$7\r\nreq-abc\r\n$4\r\nPING\r\n")
+        .expect("write traced ping");
+    let mut buf = [0_u8; 128];
+    let n = client.read(&mut buf).expect("read traced response");
+    assert_eq!(
+        &buf[..n],
+        b"*4\r\n+RID\r\n$1\r\n1\r\n+PONG\r\n$7\r\nreq-abc\r\n"
+    );
+
+    client.write_all(ping_frame()).expect("write ping");
+    let n = client.read(&mut buf).expect("read plain response");
+    assert_eq!(&buf[..n], b"*3\r\n+RID\r\n$1\r\n2\r\n+PONG\r\n");
+}
@@ -0,0 +1,140 @@
+use fedis::Config;
+use fedis::RespValue;
+use fedis::testing::TestServer;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_share_the_keyspace_but_not_sessions() {
+    let server = TestServer::start().await;
+    assert!(server.handle().local_addr().is_none());
+    let mut first = server.client();
+    let mut second = server.client();
+
+    first.assert_ok(&["SET", "counter", "41"]).await;
+    second.assert_int(&["INCR", "counter"], 42).await;
+    first.assert_bulk(&["GET", "counter"], Some("42")).await;
+    second.assert_bulk(&["GET", "missing"], None).await;
+    first
+        .assert_ok(&["JSON.SET", "doc", "$", r#"{"a":1}"#])
+        .await;
+    second.assert_error(&["INCR", "doc"], "WRONGTYPE").await;
+
+    first.assert_ok(&["CLIENT", "SETNAME", "first"]).await;
+    first
+        .assert_bulk(&["CLIENT", "GETNAME"], Some("first"))
+        .await;
+    second.assert_bulk(&["CLIENT", "GETNAME"], None).await;
+
+    let reply = first.call_bytes(&[b"SET", b"bin", b"\x00\xff"]).await;
+    assert!(matches!(reply, RespValue::Simple(ok) if ok == "OK"));
+    let reply = first.call_bytes(&[b"GET", b"bin"]).await;
+    assert!(matches!(reply, RespValue::Bulk(Some(value)) if value[..] == b"\x00\xff"[..]));
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_kill_closes_the_connections_it_matches() {
+    let server = TestServer::start().await;
+    let mut admin = server.client();
+    let mut victim = server.client();
+    let mut bystander = server.client();
+
+    let RespValue::Integer(id) = victim.call(&["CLIENT", "ID"]).await else {
+        panic!("CLIENT ID is not an integer");
+    };
+    admin
+        .assert_int(&["CLIENT", "KILL", "ID", &id.to_string()], 1)
+        .await;
+    assert!(victim.is_closed().await);
+    bystander.assert_bulk(&["GET", "missing"], None).await;
+
+    admin
+        .assert_int(&["CLIENT", "KILL", "USER", "default"], 1)
+        .await;
+    assert!(bystander.is_closed().await);
+    admin
+        .assert_int(&["CLIENT", "KILL", "ID", "424242"], 0)
+        .await;
+    admin
+        .assert_error(&["CLIENT", "KILL", "ID", "1", "SKIPME"], "ERR syntax error")
+        .await;
+    admin
+        .assert_error(&["CLIENT", "KILL", "10.0.0.1:1"], "ERR No such client")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pipelined_and_nested_replies_arrive_in_order() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    client.send(&["MSET", "a", "1", "b", "2"]).await;
+    client.send(&["MGET", "a", "b", "c"]).await;
+    client.send(&["NOSUCHCOMMAND"]).await;
+    assert!(matches!(client.read_reply().await, RespValue::Simple(ok) if ok == "OK"));
+    match client.read_reply().await {
+        RespValue::Array(items) => assert_eq!(items.len(), 3),
+        other => panic!("expected an array, got {:?}", other),
+    }
+    assert!(
+        matches!(client.read_reply().await, RespValue::Error(e) if e.contains("unknown command"))
+    );
+
+    match client.call(&["HELLO", "3"]).await {
+        RespValue::Map(entries) => assert!(
+            entries
+                .iter()
+                .any(|(key, _)| matches!(key, RespValue::Bulk(Some(k)) if k[..] == b"proto"[..]))
+        ),
+        other => panic!("expected a map, got {:?}", other),
+    }
+
+    client.send_raw(b"*1\r\n$4\r\nPI").await;
+    client.send_raw(b"NG\r\n").await;
+    assert!(matches!(client.read_reply().await, RespValue::Simple(pong) if pong == "PONG"));
+
+    client.assert_ok(&["QUIT"]).await;
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn configured_servers_apply_their_settings() {
+    let server = TestServer::with_config(
+        Config::builder()
+            .password("secret")
+            .non_redis_mode(true)
+            .set("FEDIS_DEBUG_RESPONSE_ID", "1"),
+    )
+    .await;
+    let mut client = server.client();
+
+    match client.call(&["TRACEID", "req-1", "GET", "key"]).await {
+        RespValue::Array(items) => {
+            assert!(matches!(&items[2], RespValue::Error(e) if e.starts_with("NOAUTH")));
+            assert!(matches!(&items[3], RespValue::Bulk(Some(id)) if id[..] == b"req-1"[..]));
+        }
+        other => panic!("expected a wrapped reply, got {:?}", other),
+    }
+    match client.call(&["AUTH", "secret"]).await {
+        RespValue::Array(items) => {
+            assert!(matches!(&items[2], RespValue::Simple(ok) if ok == "OK"));
+        }
+        other => panic!("expected a wrapped reply, got {:?}", other),
+    }
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_flushes_the_aof_into_the_data_dir() {
+    let server = TestServer::with_config(Config::builder().set("FEDIS_AOF_FSYNC", "always")).await;
+    let data_dir = server.data_dir().to_path_buf();
+    server.client().assert_ok(&["SET", "kept", "yes"]).await;
+    let store = server.handle().store().clone();
+    server.shutdown().await;
+    assert_eq!(
+        store.get(b"kept").await.ok().flatten().as_deref(),
+        Some(&b"yes"[..])
+    );
+    // Dropping the server removed its directory.
+    assert!(!data_dir.exists());
+}
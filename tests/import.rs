@@ -0,0 +1,127 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fedis::import::Import;
+use fedis::{Config, RespValue, Server, ServerHandle, SessionAuth};
+
+fn args(cmd: &[&str]) -> Vec<Vec<u8>> {
+    cmd.iter().map(|v| v.as_bytes().to_vec()).collect()
+}
+
+async fn start(name: &str, stamp: u128) -> ServerHandle {
+    let data_dir = std::env::temp_dir().join(format!(
+        "fedis-import-{}-{}-{}",
+        name,
+        std::process::id(),
+        stamp
+    ));
+    let config = Config::builder()
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .build()
+        .expect("config");
+    Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start")
+}
+
+async fn call(handle: &ServerHandle, cmd: &[&str]) -> RespValue {
+    let mut session = SessionAuth::default();
+    handle.executor().execute(args(cmd), &mut session).await.0
+}
+
+#[test]
+fn import_copies_keys_with_ttls() {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("runtime");
+    let (source, target) = runtime.block_on(async {
+        let source = start("source", stamp).await;
+        for idx in 0..250 {
+            let key = format!("user:{}", idx);
+            call(&source, &["SET", &key, "value"]).await;
+        }
+        call(&source, &["SET", "session", "abc", "PX", "600000"]).await;
+        call(&source, &["JSON.SET", "doc", "$", r#"{"a":[1,2]}"#]).await;
+        let target = start("target", stamp).await;
+        call(&target, &["SET", "user:0", "kept"]).await;
+        (source, target)
+    });
+    let source_url = format!("redis://{}", source.local_addr().expect("listening"));
+    let target_addr = target.local_addr().expect("listening").to_string();
+    let resume = std::env::temp_dir().join(format!("fedis-import-resume-{}.json", stamp));
+
+    let import = Import::from_args(&[
+        "import".to_string(),
+        source_url.clone(),
+        "--addr".to_string(),
+        target_addr.clone(),
+        "--batch".to_string(),
+        "50".to_string(),
+        "--no-replace".to_string(),
+        "--resume".to_string(),
+        resume.display().to_string(),
+    ])
+    .expect("parse")
+    .expect("import");
+    import.run(&runtime).expect("import");
+    assert!(!resume.exists());
+
+    runtime.block_on(async {
+        assert!(matches!(
+            call(&target, &["DBSIZE"]).await,
+            RespValue::Integer(252)
+        ));
+        assert!(matches!(
+            call(&target, &["GET", "user:0"]).await,
+            RespValue::Bulk(Some(value)) if value.as_ref() == b"kept"
+        ));
+        assert!(matches!(
+            call(&target, &["GET", "user:249"]).await,
+            RespValue::Bulk(Some(value)) if value.as_ref() == b"value"
+        ));
+        assert!(matches!(
+            call(&target, &["PTTL", "session"]).await,
+            RespValue::Integer(ttl) if ttl > 500_000
+        ));
+        assert!(matches!(
+            call(&target, &["JSON.GET", "doc"]).await,
+            RespValue::Bulk(Some(doc)) if doc.as_ref() == br#"{"a":[1,2]}"#
+        ));
+        call(&target, &["FLUSHALL"]).await;
+    });
+
+    // GET and SET copy strings only.
+    let import = Import::from_args(&[
+        "import".to_string(),
+        source_url,
+        "--addr".to_string(),
+        target_addr,
+        "--mode".to_string(),
+        "get".to_string(),
+        "--match".to_string(),
+        "*o*".to_string(),
+    ])
+    .expect("parse")
+    .expect("import");
+    import.run(&runtime).expect("import");
+    runtime.block_on(async {
+        assert!(matches!(
+            call(&target, &["DBSIZE"]).await,
+            RespValue::Integer(1)
+        ));
+        assert!(matches!(
+            call(&target, &["PTTL", "session"]).await,
+            RespValue::Integer(ttl) if ttl > 500_000
+        ));
+        source.shutdown().await.expect("shutdown");
+        target.shutdown().await.expect("shutdown");
+    });
+}
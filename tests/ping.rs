@@ -0,0 +1,94 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fedis::ping::Ping;
+use fedis::{Config, ConfigBuilder, Server};
+
+fn ping(args: &[&str], config: ConfigBuilder) -> i32 {
+    let args: Vec<String> = std::iter::once("ping")
+        .chain(args.iter().copied())
+        .map(str::to_string)
+        .collect();
+    let ping = Ping::from_args(&args).expect("parse").expect("ping");
+    // Off the test's runtime: `run` builds its own.
+    std::thread::spawn(move || ping.run(&config.build().expect("config")))
+        .join()
+        .expect("ping thread")
+}
+
+async fn check(name: &str, server: ConfigBuilder, client: ConfigBuilder) {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir = std::env::temp_dir().join(format!(
+        "fedis-ping-{}-{}-{}",
+        name,
+        std::process::id(),
+        stamp
+    ));
+    let handle = Server::new(
+        server
+            .listen_addr("127.0.0.1:0")
+            .data_path(&data_dir)
+            .password("secret")
+            .build()
+            .expect("config"),
+    )
+    .await
+    .expect("server")
+    .start()
+    .await
+    .expect("start");
+    let addr = handle.local_addr().expect("listening").to_string();
+    let client = client.data_path(&data_dir);
+
+    assert_eq!(
+        ping(&[], client.clone().listen_addr(&addr).password("secret")),
+        0
+    );
+    assert_eq!(
+        ping(&["--addr", &addr, "--password", "secret"], client.clone()),
+        0
+    );
+    assert_eq!(
+        ping(&[], client.clone().listen_addr(&addr).password("wrong")),
+        1
+    );
+
+    handle.shutdown().await.expect("shutdown");
+    assert_eq!(
+        ping(
+            &["--timeout", "1"],
+            client.listen_addr(&addr).password("secret")
+        ),
+        1
+    );
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping_reports_whether_the_server_answers() {
+    check("plain", Config::builder(), Config::builder()).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping_trusts_the_configured_certificate() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+    let tls = || {
+        Config::builder()
+            .set(
+                "FEDIS_TLS_CERT_FILE",
+                fixtures.join("server.pem").display().to_string(),
+            )
+            .set(
+                "FEDIS_TLS_KEY_FILE",
+                fixtures.join("server.key").display().to_string(),
+            )
+            .set(
+                "FEDIS_TLS_CA_CERT_FILE",
+                fixtures.join("ca.pem").display().to_string(),
+            )
+    };
+    check("tls", tls(), tls()).await;
+}
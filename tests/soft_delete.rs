@@ -0,0 +1,92 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fedis::{CommandExecutor, Config, RespValue, Server, SessionAuth};
+
+async fn run(executor: &CommandExecutor, session: &mut SessionAuth, cmd: &[&str]) -> RespValue {
+    let args = cmd.iter().map(|v| v.as_bytes().to_vec()).collect();
+    executor.execute(args, session).await.0
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deleted_keys_can_be_restored_until_their_tombstone_goes() {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir = std::env::temp_dir().join(format!(
+        "fedis-soft-delete-{}-{}",
+        std::process::id(),
+        stamp
+    ));
+    let config = Config::builder()
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .non_redis_mode(true)
+        .set("FEDIS_SOFT_DELETE_RETENTION_SEC", "60")
+        .build()
+        .expect("config");
+    let handle = Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start");
+    let executor = handle.executor();
+    let mut session = SessionAuth::default();
+
+    run(&executor, &mut session, &["SET", "a", "1", "EX", "100"]).await;
+    run(&executor, &mut session, &["SET", "b", "2"]).await;
+    assert!(matches!(
+        run(&executor, &mut session, &["DEL", "a", "b", "missing"]).await,
+        RespValue::Integer(2)
+    ));
+    let info = executor.info_text("memory").await.expect("memory");
+    assert!(info.contains("tombstone_keys:2"), "{}", info);
+
+    assert!(matches!(
+        run(&executor, &mut session, &["UNDELETE", "a"]).await,
+        RespValue::Integer(1)
+    ));
+    assert!(matches!(
+        run(&executor, &mut session, &["GET", "a"]).await,
+        RespValue::Bulk(Some(v)) if v == "1"
+    ));
+    assert!(matches!(
+        run(&executor, &mut session, &["TTL", "a"]).await,
+        RespValue::Integer(ttl) if ttl > 90
+    ));
+    // Restoring a key uses its tombstone up.
+    let info = executor.info_text("memory").await.expect("memory");
+    assert!(info.contains("tombstone_keys:1"), "{}", info);
+    assert!(matches!(
+        run(&executor, &mut session, &["UNDELETE", "missing"]).await,
+        RespValue::Integer(0)
+    ));
+
+    // A key written again is not overwritten, and a second delete replaces
+    // the tombstone.
+    run(&executor, &mut session, &["SET", "b", "3"]).await;
+    assert!(matches!(
+        run(&executor, &mut session, &["UNDELETE", "b"]).await,
+        RespValue::Error(e) if e.contains("exists again")
+    ));
+    run(&executor, &mut session, &["UNLINK", "b"]).await;
+    run(&executor, &mut session, &["UNDELETE", "b"]).await;
+    assert!(matches!(
+        run(&executor, &mut session, &["GET", "b"]).await,
+        RespValue::Bulk(Some(v)) if v == "3"
+    ));
+
+    // FLUSHALL empties the tombstones too.
+    run(&executor, &mut session, &["DEL", "b"]).await;
+    run(&executor, &mut session, &["FLUSHALL"]).await;
+    assert!(matches!(
+        run(&executor, &mut session, &["UNDELETE", "b"]).await,
+        RespValue::Integer(0)
+    ));
+    let info = executor.info_text("memory").await.expect("memory");
+    assert!(info.contains("tombstone_keys:0"), "{}", info);
+
+    handle.shutdown().await.expect("shutdown");
+    let _ = std::fs::remove_dir_all(data_dir);
+}
@@ -0,0 +1,124 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fedis::{Config, ConfigBuilder, RespValue, Server, ServerHandle, SessionAuth};
+
+fn args(cmd: &[&str]) -> Vec<Vec<u8>> {
+    cmd.iter().map(|v| v.as_bytes().to_vec()).collect()
+}
+
+async fn start(name: &str, config: ConfigBuilder) -> ServerHandle {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let data_dir = std::env::temp_dir().join(format!(
+        "fedis-upstream-{}-{}-{}",
+        name,
+        std::process::id(),
+        stamp
+    ));
+    let config = config
+        .listen_addr("127.0.0.1:0")
+        .data_path(&data_dir)
+        .build()
+        .expect("config");
+    Server::new(config)
+        .await
+        .expect("server")
+        .start()
+        .await
+        .expect("start")
+}
+
+/// Both servers take the same password.
+async fn call(handle: &ServerHandle, cmd: &[&str]) -> RespValue {
+    let mut session = SessionAuth::default();
+    handle
+        .executor()
+        .execute(args(&["AUTH", "secret"]), &mut session)
+        .await;
+    handle.executor().execute(args(cmd), &mut session).await.0
+}
+
+fn is_bulk(reply: RespValue, value: &str) -> bool {
+    matches!(reply, RespValue::Bulk(Some(v)) if v == value.as_bytes())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_and_writes_go_through_to_the_upstream() {
+    let upstream = start("origin", Config::builder().password("secret")).await;
+    let url = format!(
+        "redis://:secret@{}",
+        upstream.local_addr().expect("listening")
+    );
+    let proxy = start(
+        "proxy",
+        Config::builder()
+            .password("secret")
+            .set("FEDIS_UPSTREAM_URL", &url)
+            .set("FEDIS_UPSTREAM_CACHE_TTL_MS", "60000"),
+    )
+    .await;
+
+    call(&upstream, &["SET", "greeting", "hello"]).await;
+    assert!(is_bulk(call(&proxy, &["GET", "greeting"]).await, "hello"));
+    assert!(matches!(
+        call(&proxy, &["GET", "missing"]).await,
+        RespValue::Bulk(None)
+    ));
+    // Cached: the proxy keeps answering with what it read.
+    call(&upstream, &["SET", "greeting", "changed"]).await;
+    assert!(is_bulk(call(&proxy, &["GET", "greeting"]).await, "hello"));
+    assert!(matches!(
+        call(&proxy, &["PTTL", "greeting"]).await,
+        RespValue::Integer(ttl) if ttl > 0 && ttl <= 60_000
+    ));
+
+    // A write goes upstream and drops the cached copy.
+    assert!(
+        matches!(call(&proxy, &["SET", "greeting", "bonjour"]).await, RespValue::Simple(ok) if ok == "OK")
+    );
+    assert!(is_bulk(
+        call(&upstream, &["GET", "greeting"]).await,
+        "bonjour"
+    ));
+    assert!(matches!(
+        call(&proxy, &["EXISTS", "greeting"]).await,
+        RespValue::Integer(0)
+    ));
+    assert!(is_bulk(call(&proxy, &["GET", "greeting"]).await, "bonjour"));
+
+    call(&upstream, &["SET", "count", "41"]).await;
+    assert!(matches!(
+        call(&proxy, &["INCR", "count"]).await,
+        RespValue::Integer(42)
+    ));
+    assert!(is_bulk(call(&upstream, &["GET", "count"]).await, "42"));
+    assert!(matches!(
+        call(&proxy, &["INCR", "greeting"]).await,
+        RespValue::Error(e) if e.starts_with("ERR value is not an integer")
+    ));
+
+    upstream.shutdown().await.expect("shutdown");
+    // Nothing listens there now: cached values are still served, misses and
+    // writes fail.
+    let stranded = start(
+        "stranded",
+        Config::builder()
+            .password("secret")
+            .set("FEDIS_UPSTREAM_URL", url)
+            .set("FEDIS_UPSTREAM_CACHE_TTL_MS", "60000"),
+    )
+    .await;
+    assert!(is_bulk(call(&proxy, &["GET", "greeting"]).await, "bonjour"));
+    assert!(matches!(
+        call(&stranded, &["GET", "greeting"]).await,
+        RespValue::Error(e) if e.starts_with("ERR upstream")
+    ));
+    assert!(matches!(
+        call(&stranded, &["SET", "greeting", "1"]).await,
+        RespValue::Error(e) if e.starts_with("ERR upstream")
+    ));
+    stranded.shutdown().await.expect("shutdown");
+    proxy.shutdown().await.expect("shutdown");
+}